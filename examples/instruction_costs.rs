@@ -0,0 +1,150 @@
+// Measures how long the interpreter takes to execute each class of instruction on this host and
+// prints a table sorted from most to least expensive.
+//
+//     cargo run --release --example instruction_costs [ticks per class]
+
+use std::env;
+use std::time::{Duration, Instant};
+use user_mode_riscv::prelude::*;
+
+const CODE_SIZE: usize = 4096;
+const DATA_ADDRESS: usize = 0x2000;
+const MEMORY_SIZE: usize = 0x3000;
+const RUNS: usize = 3;
+
+// a3 is the destination for everything, a1/a2 and fa1/fa2 are the sources
+const RD: u32 = 13;
+const RS1: u32 = 11;
+const RS2: u32 = 12;
+const SP: u32 = 2;
+
+fn r(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn r4(rs3: u32, fmt: u32, rs2: u32, rs1: u32, rd: u32, opcode: u32) -> u32 {
+    rs3 << 27 | fmt << 25 | rs2 << 20 | rs1 << 15 | rd << 7 | opcode
+}
+
+fn i(imm: i32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    ((imm as u32) & 0xfff) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn s(imm: i32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5) & 0x7f) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | (imm & 0x1f) << 7 | opcode
+}
+
+fn b(imm: i32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 12) & 1) << 31 | ((imm >> 5) & 0x3f) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 |
+        ((imm >> 1) & 0xf) << 8 | ((imm >> 11) & 1) << 7 | 0x63
+}
+
+fn j(imm: i32, rd: u32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 20) & 1) << 31 | ((imm >> 1) & 0x3ff) << 21 | ((imm >> 11) & 1) << 20 | ((imm >> 12) & 0xff) << 12 | rd << 7 | 0x6f
+}
+
+// two copies of a compressed instruction packed into one word
+fn c(halfword: u32) -> u32 {
+    halfword << 16 | halfword
+}
+
+fn cases() -> Vec<(&'static str, &'static str, u32)> {
+    vec![
+        ("baseline", "nop", i(0, 0, 0, 0, 0x13)),
+        ("integer", "add", r(0, RS2, RS1, 0, RD, 0x33)),
+        ("integer", "addi", i(5, RS1, 0, RD, 0x13)),
+        ("integer", "addw", r(0, RS2, RS1, 0, RD, 0x3b)),
+        ("integer", "slli", i(3, RS1, 1, RD, 0x13)),
+        ("integer", "sltu", r(0, RS2, RS1, 3, RD, 0x33)),
+        ("integer", "lui", 0x12345 << 12 | RD << 7 | 0x37),
+        ("integer", "auipc", 0x12345 << 12 | RD << 7 | 0x17),
+        ("multiply", "mul", r(1, RS2, RS1, 0, RD, 0x33)),
+        ("multiply", "mulh", r(1, RS2, RS1, 1, RD, 0x33)),
+        ("multiply", "div", r(1, RS2, RS1, 4, RD, 0x33)),
+        ("multiply", "remu", r(1, RS2, RS1, 7, RD, 0x33)),
+        ("memory", "lb", i(0, SP, 0, RD, 0x03)),
+        ("memory", "ld", i(0, SP, 3, RD, 0x03)),
+        ("memory", "sb", s(0, RS2, SP, 0, 0x23)),
+        ("memory", "sd", s(0, RS2, SP, 3, 0x23)),
+        ("memory", "fence", 0x0ff0000f),
+        ("control", "beq (not taken)", b(4, RS2, RS1, 0)),
+        ("control", "bne (taken)", b(4, RS2, RS1, 1)),
+        ("control", "jal", j(4, 0)),
+        ("atomic", "amoadd.d", r(0b0000000, RS2, SP, 3, RD, 0x2f)),
+        ("atomic", "amoswap.w", r(0b0000100, RS2, SP, 2, RD, 0x2f)),
+        ("atomic", "lr.d", r(0b0001000, 0, SP, 3, RD, 0x2f)),
+        ("atomic", "sc.d", r(0b0001100, RS2, SP, 3, RD, 0x2f)),
+        ("float", "fadd.s", r(0b0000000, RS2, RS1, 0, RD, 0x53)),
+        ("float", "fmul.s", r(0b0001000, RS2, RS1, 0, RD, 0x53)),
+        ("float", "fdiv.s", r(0b0001100, RS2, RS1, 0, RD, 0x53)),
+        ("double", "fadd.d", r(0b0000001, RS2, RS1, 0, RD, 0x53)),
+        ("double", "fmul.d", r(0b0001001, RS2, RS1, 0, RD, 0x53)),
+        ("double", "fdiv.d", r(0b0001101, RS2, RS1, 0, RD, 0x53)),
+        ("double", "fsqrt.d", r(0b0101101, 0, RS1, 0, RD, 0x53)),
+        ("double", "fmadd.d", r4(RS1, 1, RS2, RS1, RD, 0x43)),
+        ("double", "fcvt.l.d", r(0b1100001, 2, RS1, 1, RD, 0x53)),
+        ("double", "fmv.x.d", r(0b1110001, 0, RS1, 0, RD, 0x53)),
+        ("double", "fld", i(0, SP, 3, RD, 0x07)),
+        ("system", "csrr fflags", i(1, 0, 2, RD, 0x73)),
+        ("compressed", "c.add", c(0x9002 | RD << 7 | RS1 << 2)),
+        ("compressed", "c.addi", c(RD << 7 | 1 << 2 | 1)),
+        ("compressed", "c.ld", c(0x6000 | 1 << 7 | 2 << 2)) // c.ld a0, 0(s1)
+    ]
+}
+
+fn setup(word: u32) -> (Cpu, Vec<u8>) {
+    let mut memory = vec![0u8; MEMORY_SIZE];
+    for offset in (0..CODE_SIZE).step_by(4) {
+        memory.write_u32(offset, word).expect("code fits");
+    }
+    memory.write_u32(CODE_SIZE, j(-(CODE_SIZE as i32), 0)).expect("code fits");
+
+    let mut cpu = Cpu::builder().pc(0).stack_pointer(DATA_ADDRESS).build();
+    cpu.set_register(Register::A1, 3);
+    cpu.set_register(Register::A2, 7);
+    cpu.set_register(Register::S1, DATA_ADDRESS as i64);
+    cpu.set_fp_register(FpRegister::FA1, 1.5);
+    cpu.set_fp_register(FpRegister::FA2, 2.5);
+    // single precision operands are NaN boxed, the same way FLW leaves them
+    if word & 0x7f == 0x53 && (word >> 25) & 3 == 0 {
+        cpu.set_f32(RS1 as usize, 1.5);
+        cpu.set_f32(RS2 as usize, 2.5);
+    }
+
+    (cpu, memory)
+}
+
+fn measure(word: u32, ticks: u64) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let (mut cpu, mut memory) = setup(word);
+        let start = Instant::now();
+        for _ in 0..ticks {
+            cpu.tick(&mut memory).expect("benchmark instruction trapped");
+        }
+        best = best.min(start.elapsed());
+    }
+
+    best
+}
+
+fn main() {
+    let ticks: u64 = env::args().nth(1).map(|arg| arg.parse().expect("ticks per class must be a number")).unwrap_or(2_000_000);
+
+    // warm up caches and clocks so whichever class runs first is not penalised
+    measure(cases()[0].2, ticks);
+
+    let mut results: Vec<(&str, &str, f64)> = cases().into_iter()
+        .map(|(class, name, word)| (class, name, measure(word, ticks).as_nanos() as f64 / ticks as f64))
+        .collect();
+    let baseline = results[0].2;
+    results.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+    println!("{:<12} {:<18} {:>10} {:>8}", "class", "instruction", "ns/instr", "x nop");
+    for (class, name, ns) in results {
+        println!("{:<12} {:<18} {:>10.2} {:>8.2}", class, name, ns, ns / baseline);
+    }
+}
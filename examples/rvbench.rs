@@ -0,0 +1,299 @@
+// A standard set of guest workloads for comparing emulator performance across hosts and engines.
+// Each benchmark is run once per engine to get emulated MIPS, then once more with every memory
+// access recorded so that fetch, decode and data memory traffic can be replayed and timed on
+// their own. Whatever is left of the interpreter's time is put down to execute.
+//
+//     cargo run --release --example rvbench [benchmark...]
+//
+// The kernels are small hand assembled loops, mandelbrot is a real compiled program.
+
+use std::cell::RefCell;
+use std::env;
+use std::time::{Duration, Instant};
+use user_mode_riscv::loader::elf::ElfLoader;
+use user_mode_riscv::prelude::*;
+
+const MEMORY_SIZE: usize = 256 * 1024;
+const DATA_ADDRESS: usize = 0x1000;
+const DATA_SIZE: usize = 0x1000;
+const FUEL: u64 = 2_000_000_000;
+
+const MANDELBROT: &[u8] = include_bytes!("../test/mandelbrot");
+
+type Setup = fn() -> (Cpu, Vec<u8>);
+
+fn r(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn i(imm: i32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    ((imm as u32) & 0xfff) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn s(imm: i32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5) & 0x7f) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | (imm & 0x1f) << 7 | opcode
+}
+
+fn b(imm: i32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 12) & 1) << 31 | ((imm >> 5) & 0x3f) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 |
+        ((imm >> 1) & 0xf) << 8 | ((imm >> 11) & 1) << 7 | 0x63
+}
+
+fn lui(imm: u32, rd: u32) -> u32 {
+    imm << 12 | rd << 7 | 0x37
+}
+
+const ECALL: u32 = 0x73;
+
+// running total over a buffer, written back as it goes, repeated s1 times
+fn sum_kernel() -> Vec<u32> {
+    vec![
+        lui(1, 10),                 // lui a0, 1
+        i(1024, 0, 0, 11, 0x13),    // li a1, 1024
+        i(0, 0, 0, 12, 0x13),       // li a2, 0
+        i(0, 10, 2, 5, 0x03),       // loop: lw t0, 0(a0)
+        r(0, 5, 12, 0, 12, 0x33),   // add a2, a2, t0
+        s(0, 12, 10, 2, 0x23),      // sw a2, 0(a0)
+        i(4, 10, 0, 10, 0x13),      // addi a0, a0, 4
+        i(-1, 11, 0, 11, 0x13),     // addi a1, a1, -1
+        b(-20, 0, 11, 1),           // bnez a1, loop
+        i(-1, 9, 0, 9, 0x13),       // addi s1, s1, -1
+        b(-40, 0, 9, 1),            // bnez s1, 0
+        ECALL
+    ]
+}
+
+// bitwise CRC-32 of the buffer, repeated s1 times
+fn crc32_kernel() -> Vec<u32> {
+    vec![
+        lui(1, 10),                 // lui a0, 1
+        i(1024, 0, 0, 11, 0x13),    // li a1, 1024
+        i(-1, 0, 0, 12, 0x13),      // li a2, -1
+        lui(0xedb88, 7),            // lui t2, 0xedb88
+        i(0x320, 7, 0, 7, 0x13),    // addi t2, t2, 0x320
+        i(0, 10, 4, 5, 0x03),       // byte: lbu t0, 0(a0)
+        r(0, 5, 12, 4, 12, 0x33),   // xor a2, a2, t0
+        i(8, 0, 0, 6, 0x13),        // li t1, 8
+        i(1, 12, 7, 28, 0x13),      // bit: andi t3, a2, 1
+        i(1, 12, 5, 12, 0x1b),      // srliw a2, a2, 1
+        b(8, 0, 28, 0),             // beqz t3, skip
+        r(0, 7, 12, 4, 12, 0x33),   // xor a2, a2, t2
+        i(-1, 6, 0, 6, 0x13),       // skip: addi t1, t1, -1
+        b(-20, 0, 6, 1),            // bnez t1, bit
+        i(1, 10, 0, 10, 0x13),      // addi a0, a0, 1
+        i(-1, 11, 0, 11, 0x13),     // addi a1, a1, -1
+        b(-44, 0, 11, 1),           // bnez a1, byte
+        i(-1, 9, 0, 9, 0x13),       // addi s1, s1, -1
+        b(-72, 0, 9, 1),            // bnez s1, 0
+        ECALL
+    ]
+}
+
+// write(2) is swallowed, any other ecall ends the run
+const ECALL_HANDLER: Instruction = Instruction {
+    name: "ECALL",
+    operation: |cpu, _memory, _word, _address| {
+        match cpu.get_register(Register::A7) {
+            64 => {
+                cpu.set_register(Register::A0, cpu.get_register(Register::A2));
+                Ok(())
+            },
+            _ => Err(Trap { trap_type: TrapType::Stop, value: cpu.get_register(Register::A0) as u64 })
+        }
+    }
+};
+
+fn kernel(code: Vec<u32>, repeat: i64) -> (Cpu, Vec<u8>) {
+    let mut memory = vec![0u8; MEMORY_SIZE];
+    for (index, word) in code.iter().enumerate() {
+        memory.write_u32(index * 4, *word).expect("code fits");
+    }
+    let mut seed = 0x2545f491u32;
+    for byte in &mut memory[DATA_ADDRESS..DATA_ADDRESS + DATA_SIZE] {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        *byte = seed as u8;
+    }
+
+    let mut cpu = Cpu::builder().ecall_fallback(ECALL_HANDLER).build();
+    cpu.set_register(Register::S1, repeat);
+    (cpu, memory)
+}
+
+fn mandelbrot() -> (Cpu, Vec<u8>) {
+    let mut memory = vec![0u8; MEMORY_SIZE];
+    let mut cpu = Cpu::builder().ecall_fallback(ECALL_HANDLER).build();
+    ElfLoader::new().base(0).stack_pointer(MEMORY_SIZE - 16)
+        .load_into(MANDELBROT, &mut cpu, &mut memory)
+        .expect("mandelbrot loads");
+    (cpu, memory)
+}
+
+fn benchmarks() -> Vec<(&'static str, Setup)> {
+    vec![
+        ("sum", || kernel(sum_kernel(), 2_000)),
+        ("crc32", || kernel(crc32_kernel(), 100)),
+        ("mandelbrot", mandelbrot)
+    ]
+}
+
+fn run(setup: Setup, engine: Engine, predecode: bool) -> (u64, Duration) {
+    let (mut cpu, mut memory) = setup();
+    cpu.set_engine(engine);
+    if predecode {
+        // every benchmark's code is below its data
+        cpu.predecode(&memory, 0..DATA_ADDRESS);
+    }
+
+    let mut instructions = 0;
+    let start = Instant::now();
+    while instructions < FUEL {
+        match cpu.tick(&mut memory) {
+            Ok(_) => instructions += 1,
+            Err(trap) if trap.trap_type == TrapType::Stop => break,
+            Err(trap) => panic!("benchmark trapped at {:#x}: {:?}", cpu.get_pc(), trap)
+        }
+    }
+
+    (instructions, start.elapsed())
+}
+
+#[derive(Clone, Copy)]
+enum Access {
+    Read(usize, u8),
+    Write(usize, u8)
+}
+
+// Vec<u8> memory that notes every access it serves
+struct Recording {
+    memory: Vec<u8>,
+    // reads only get &self
+    accesses: RefCell<Vec<Access>>
+}
+
+impl Recording {
+    fn read(&self, address: usize, size: u8) -> Result<u64, Trap> {
+        let mut value = [0u8; 8];
+        let bytes = self.memory.get(address..address + size as usize).ok_or(Trap { trap_type: TrapType::LoadAccessFault, value: address as u64 })?;
+        value[..size as usize].copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    fn note(&self, access: Access) {
+        self.accesses.borrow_mut().push(access);
+    }
+}
+
+impl Memory for Recording {
+    fn read_i8(&self, address: usize) -> Result<i8, Trap> { self.note(Access::Read(address, 1)); Ok(self.read(address, 1)? as i8) }
+    fn read_u8(&self, address: usize) -> Result<u8, Trap> { self.note(Access::Read(address, 1)); Ok(self.read(address, 1)? as u8) }
+    fn read_i16(&self, address: usize) -> Result<i16, Trap> { self.note(Access::Read(address, 2)); Ok(self.read(address, 2)? as i16) }
+    fn read_u16(&self, address: usize) -> Result<u16, Trap> { self.note(Access::Read(address, 2)); Ok(self.read(address, 2)? as u16) }
+    fn read_i32(&self, address: usize) -> Result<i32, Trap> { self.note(Access::Read(address, 4)); Ok(self.read(address, 4)? as i32) }
+    fn read_u32(&self, address: usize) -> Result<u32, Trap> { self.note(Access::Read(address, 4)); Ok(self.read(address, 4)? as u32) }
+    fn read_i64(&self, address: usize) -> Result<i64, Trap> { self.note(Access::Read(address, 8)); Ok(self.read(address, 8)? as i64) }
+    fn read_u64(&self, address: usize) -> Result<u64, Trap> { self.note(Access::Read(address, 8)); self.read(address, 8) }
+    fn write_u8(&mut self, address: usize, value: u8) -> Result<(), Trap> { self.note(Access::Write(address, 1)); self.memory.write_u8(address, value) }
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), Trap> { self.note(Access::Write(address, 2)); self.memory.write_u16(address, value) }
+    fn write_u32(&mut self, address: usize, value: u32) -> Result<(), Trap> { self.note(Access::Write(address, 4)); self.memory.write_u32(address, value) }
+    fn write_u64(&mut self, address: usize, value: u64) -> Result<(), Trap> { self.note(Access::Write(address, 8)); self.memory.write_u64(address, value) }
+}
+
+struct Breakdown {
+    fetch: Duration,
+    decode: Duration,
+    memory: Duration
+}
+
+// Replays one interpreter run phase by phase. The first read of each tick at the pc is the fetch,
+// everything else is data traffic.
+fn breakdown(setup: Setup) -> Breakdown {
+    let (mut cpu, memory) = setup();
+    let mut recording = Recording { memory, accesses: RefCell::new(Vec::new()) };
+    let mut pcs = Vec::new();
+    let mut data = Vec::new();
+    loop {
+        let pc = cpu.get_pc();
+        let before = recording.accesses.borrow().len();
+        let result = cpu.tick(&mut recording);
+        pcs.push(pc);
+        let mut fetched = false;
+        for access in &recording.accesses.borrow()[before..] {
+            match access {
+                Access::Read(address, _) if *address == pc && !fetched => fetched = true,
+                _ => data.push(*access)
+            }
+        }
+        match result {
+            Ok(_) if pcs.len() < FUEL as usize => {},
+            _ => break
+        }
+    }
+
+    let code = recording.memory;
+    let start = Instant::now();
+    let mut words = Vec::with_capacity(pcs.len());
+    for pc in &pcs {
+        cpu.update_pc(*pc);
+        words.push(cpu.fetch(&code).unwrap_or(0));
+    }
+    let fetch = start.elapsed();
+
+    let start = Instant::now();
+    let decoded = words.iter().filter(|w| Cpu::decode(**w).is_some()).count();
+    let decode = start.elapsed();
+    assert!(decoded > 0);
+
+    let (_, mut scratch) = setup();
+    let start = Instant::now();
+    for access in &data {
+        let _ = match access {
+            Access::Read(address, 1) => scratch.read_u8(*address).map(|_| ()),
+            Access::Read(address, 2) => scratch.read_u16(*address).map(|_| ()),
+            Access::Read(address, 4) => scratch.read_u32(*address).map(|_| ()),
+            Access::Read(address, _) => scratch.read_u64(*address).map(|_| ()),
+            Access::Write(address, 1) => scratch.write_u8(*address, 0),
+            Access::Write(address, 2) => scratch.write_u16(*address, 0),
+            Access::Write(address, 4) => scratch.write_u32(*address, 0),
+            Access::Write(address, _) => scratch.write_u64(*address, 0)
+        };
+    }
+    let memory = start.elapsed();
+
+    Breakdown { fetch, decode, memory }
+}
+
+fn percent(part: Duration, total: Duration) -> f64 {
+    100.0 * part.as_secs_f64() / total.as_secs_f64()
+}
+
+fn main() {
+    let selected: Vec<String> = env::args().skip(1).collect();
+    let engines = [("interpreter", Engine::Interpreter, false), ("block", Engine::Block, false), ("cached", Engine::Cached, false),
+                   ("predecoded", Engine::Cached, true)];
+
+    println!("{:<12} {:<12} {:>14} {:>10} {:>9}   {:>6} {:>6} {:>6} {:>7}",
+             "benchmark", "engine", "instructions", "time", "MIPS", "fetch", "decode", "memory", "execute");
+    for (name, setup) in benchmarks() {
+        if !selected.is_empty() && !selected.iter().any(|s| s == name) {
+            continue;
+        }
+
+        let parts = breakdown(setup);
+        for (engine_name, engine, predecode) in engines {
+            let (instructions, time) = run(setup, engine, predecode);
+            let mips = instructions as f64 / time.as_secs_f64() / 1e6;
+            print!("{:<12} {:<12} {:>14} {:>10.2?} {:>9.2}", name, engine_name, instructions, time, mips);
+            if engine == Engine::Interpreter {
+                let execute = time.saturating_sub(parts.fetch + parts.decode + parts.memory);
+                println!("   {:>5.1}% {:>5.1}% {:>5.1}% {:>6.1}%", percent(parts.fetch, time), percent(parts.decode, time),
+                         percent(parts.memory, time), percent(execute, time));
+            } else {
+                println!();
+            }
+        }
+    }
+}
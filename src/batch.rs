@@ -0,0 +1,187 @@
+use crate::cpu::{Cpu, StopReason, Trap};
+use crate::memory::Memory;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct BatchConfig {
+    pub threads: usize,
+    pub fuel: u64,
+    pub collect_coverage: bool
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            fuel: 1_000_000_000,
+            collect_coverage: false
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BatchStatus {
+    Exited(u64),
+    Trapped { pc: usize, trap: Trap },
+    OutOfFuel,
+    // anything else that ended the run, running out of gas or a stop request
+    Stopped(StopReason),
+    LoadFailed(String)
+}
+
+#[derive(Debug)]
+pub struct BatchResult {
+    pub path: PathBuf,
+    pub status: BatchStatus,
+    pub instructions: u64,
+    pub duration: Duration,
+    pub coverage: HashSet<usize>
+}
+
+impl BatchResult {
+    pub fn passed(&self) -> bool {
+        matches!(self.status, BatchStatus::Exited(0))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub results: Vec<BatchResult>
+}
+
+impl BatchReport {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|r| r.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    pub fn total_instructions(&self) -> u64 {
+        self.results.iter().map(|r| r.instructions).sum()
+    }
+
+    // pcs covered by any of the binaries, those several of them reach counting once
+    pub fn total_coverage(&self) -> usize {
+        self.results.iter().flat_map(|r| r.coverage.iter()).collect::<HashSet<_>>().len()
+    }
+}
+
+impl Display for BatchReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            let status = match &result.status {
+                BatchStatus::Exited(0) => "ok".to_string(),
+                BatchStatus::Exited(code) => format!("exit {:#x}", code),
+                BatchStatus::Trapped { pc, trap } => format!("trap {:?} ({:#x}) at pc {:#x}", trap.trap_type, trap.value, pc),
+                BatchStatus::OutOfFuel => "out of fuel".to_string(),
+                BatchStatus::Stopped(stop) => format!("stopped {:?}", stop),
+                BatchStatus::LoadFailed(e) => format!("load failed: {}", e)
+            };
+            writeln!(f, "{}: {} ({} instructions, {} pcs, {:?})", result.path.display(), status,
+                     result.instructions, result.coverage.len(), result.duration)?;
+        }
+        writeln!(f, "{} passed, {} failed, {} instructions", self.passed(), self.failed(), self.total_instructions())
+    }
+}
+
+// run every regular file in a directory, sorted by name so reports are stable between runs
+pub fn run_directory<F, M>(directory: &Path, config: &BatchConfig, setup: F) -> io::Result<BatchReport>
+    where F: Fn(&[u8]) -> Result<(Cpu, M), String> + Sync,
+          M: Memory {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    Ok(run_files(&paths, config, setup))
+}
+
+pub fn run_files<F, M>(paths: &[PathBuf], config: &BatchConfig, setup: F) -> BatchReport
+    where F: Fn(&[u8]) -> Result<(Cpu, M), String> + Sync,
+          M: Memory {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<BatchResult>>> = Mutex::new(paths.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..config.threads.max(1) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    if index >= paths.len() {
+                        break;
+                    }
+
+                    let result = run_one(&paths[index], config, &setup);
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+    });
+
+    BatchReport {
+        results: results.into_inner().unwrap().into_iter().flatten().collect()
+    }
+}
+
+fn run_one<F, M>(path: &Path, config: &BatchConfig, setup: &F) -> BatchResult
+    where F: Fn(&[u8]) -> Result<(Cpu, M), String>,
+          M: Memory {
+    let start = Instant::now();
+    let mut result = BatchResult {
+        path: path.to_path_buf(),
+        status: BatchStatus::OutOfFuel,
+        instructions: 0,
+        duration: Duration::default(),
+        coverage: HashSet::new()
+    };
+
+    let loaded = fs::read(path).map_err(|e| e.to_string()).and_then(|bytes| setup(&bytes));
+    let (mut cpu, mut memory) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            result.status = BatchStatus::LoadFailed(e);
+            result.duration = start.elapsed();
+            return result;
+        }
+    };
+
+    // collecting coverage needs every pc, so those runs go an instruction at a time
+    let slice = match config.collect_coverage {
+        true => 1,
+        false => config.fuel
+    };
+    result.status = loop {
+        let pc = cpu.get_pc();
+        let (consumed, stop) = cpu.run_with_fuel(&mut memory, slice.min(config.fuel - result.instructions));
+        result.instructions += consumed;
+        if config.collect_coverage && consumed != 0 {
+            result.coverage.insert(pc);
+        }
+
+        match stop {
+            StopReason::FuelExhausted if result.instructions < config.fuel => {},
+            StopReason::FuelExhausted => break BatchStatus::OutOfFuel,
+            StopReason::Exited(code) => break BatchStatus::Exited(code as u64),
+            StopReason::Trap { pc, trap } => break BatchStatus::Trapped { pc, trap },
+            stop => break BatchStatus::Stopped(stop)
+        }
+    };
+
+    result.duration = start.elapsed();
+    result
+}
@@ -0,0 +1,226 @@
+use crate::cpu::{Cpu, CpuState, Reservation, Xlen};
+use crate::memory::{CowMemory, Page, PAGE_SIZE};
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"RVCP";
+// the same whatever the build, which writes zeros for the parts of the hart it does not have
+const VERSION: u8 = 2;
+const ZERO_PAGE: u8 = 0;
+const DATA_PAGE: u8 = 1;
+
+// A paused hart and only the pages of its memory that differ from a base image, usually the
+// one the guest was loaded into. Pages still shared with the base are found without reading
+// them and the ones kept are shared rather than copied, so taking one costs little more than the
+// number of pages written since the base. Restoring needs the same base.
+//
+// The format written is the magic and version, the hart state, then the memory size and the
+// changed pages, all little endian. The hart state always has the top halves of the f registers
+// and the vector registers, empty without a vector unit. Pages that read as zero are stored
+// without their bytes.
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    state: CpuState,
+    size: usize,
+    pages: Vec<(usize, Option<Arc<Page>>)>
+}
+
+impl Checkpoint {
+    pub fn capture(cpu: &Cpu, memory: &CowMemory, base: &CowMemory) -> Self {
+        Checkpoint {
+            state: cpu.save_state(),
+            size: memory.size(),
+            pages: memory.delta(base)
+        }
+    }
+
+    // puts the hart back and returns its memory, a copy on write clone of base with the changes on top
+    pub fn restore(&self, cpu: &mut Cpu, base: &CowMemory) -> io::Result<CowMemory> {
+        if self.size != base.size() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "checkpoint was taken against a different base"));
+        }
+        cpu.load_state(&self.state);
+        Ok(CowMemory::with_delta(base, self.size, &self.pages))
+    }
+
+    pub fn state(&self) -> &CpuState {
+        &self.state
+    }
+
+    pub fn changed_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let state = &self.state;
+        let mut output = [&MAGIC[..], &[VERSION]].concat();
+        output.extend((state.pc as u64).to_le_bytes());
+        output.extend(state.x.iter().flat_map(|x| x.to_le_bytes()));
+        output.extend(state.f.iter().flat_map(|f| f.to_le_bytes()));
+        #[cfg(feature = "q")]
+        output.extend(state.f_high.iter().flat_map(|f| f.to_le_bytes()));
+        #[cfg(not(feature = "q"))]
+        output.extend([0; 32 * 8]);
+        #[cfg(feature = "v")]
+        {
+            output.extend((state.v.len() as u32).to_le_bytes());
+            output.extend(&state.v);
+        }
+        #[cfg(not(feature = "v"))]
+        output.extend(0u32.to_le_bytes());
+        output.push(match state.xlen {
+            Xlen::Bit32 => 32,
+            Xlen::Bit64 => 64
+        });
+        output.extend((state.csr.len() as u32).to_le_bytes());
+        for (address, value) in &state.csr {
+            output.extend(address.to_le_bytes());
+            output.extend(value.to_le_bytes());
+        }
+        match state.reservation {
+            Some(reservation) => {
+                output.push(1);
+                output.extend(reservation.address.to_le_bytes());
+                output.extend(reservation.value.to_le_bytes());
+            },
+            None => output.push(0)
+        }
+
+        output.extend((self.size as u64).to_le_bytes());
+        output.extend((self.pages.len() as u64).to_le_bytes());
+        for (index, page) in &self.pages {
+            output.extend((*index as u64).to_le_bytes());
+            match page.as_deref().filter(|page| page.iter().any(|byte| *byte != 0)) {
+                Some(page) => {
+                    output.push(DATA_PAGE);
+                    output.extend(page);
+                },
+                None => output.push(ZERO_PAGE)
+            }
+        }
+        writer.write_all(&output)
+    }
+
+    pub fn read(reader: &mut dyn Read) -> io::Result<Self> {
+        if bytes::<4>(reader)? != *MAGIC || bytes::<1>(reader)? != [VERSION] {
+            return Err(invalid("not a checkpoint this version can read"));
+        }
+        let pc = u64::from_le_bytes(bytes(reader)?) as usize;
+        let mut x = [0; 32];
+        for x in x.iter_mut() {
+            *x = i64::from_le_bytes(bytes(reader)?);
+        }
+        let mut f = [0; 32];
+        for f in f.iter_mut() {
+            *f = u64::from_le_bytes(bytes(reader)?);
+        }
+        // only a build with quads has anywhere to put these
+        let mut f_high = [0; 32];
+        for f in f_high.iter_mut() {
+            *f = u64::from_le_bytes(bytes(reader)?);
+        }
+        #[cfg(not(feature = "q"))]
+        let _ = f_high;
+        // none, or 32 registers of a VLEN the hart could have been built with
+        let length = u32::from_le_bytes(bytes(reader)?) as usize;
+        if length != 0 && (!cfg!(feature = "v") || !length.is_power_of_two() || !(256..=262144).contains(&length)) {
+            return Err(invalid("unsupported vector length"));
+        }
+        let mut v = vec![0; length];
+        reader.read_exact(&mut v)?;
+        #[cfg(not(feature = "v"))]
+        let _ = v;
+        let xlen = match bytes::<1>(reader)? {
+            [32] => Xlen::Bit32,
+            [64] => Xlen::Bit64,
+            _ => return Err(invalid("unknown xlen"))
+        };
+        let mut csr = BTreeMap::new();
+        for _ in 0..u32::from_le_bytes(bytes(reader)?) {
+            csr.insert(u16::from_le_bytes(bytes(reader)?), u64::from_le_bytes(bytes(reader)?));
+        }
+        let reservation = match bytes::<1>(reader)? {
+            [0] => None,
+            _ => Some(Reservation {
+                address: u64::from_le_bytes(bytes(reader)?),
+                value: u64::from_le_bytes(bytes(reader)?)
+            })
+        };
+
+        let size = usize::try_from(u64::from_le_bytes(bytes(reader)?)).map_err(|_| invalid("memory too large"))?;
+        let mut pages = Vec::new();
+        for _ in 0..u64::from_le_bytes(bytes(reader)?) {
+            let index = u64::from_le_bytes(bytes(reader)?);
+            let index = match usize::try_from(index) {
+                Ok(index) if index < size.div_ceil(PAGE_SIZE) => index,
+                _ => return Err(invalid("page outside the memory"))
+            };
+            let page = match bytes::<1>(reader)? {
+                [ZERO_PAGE] => None,
+                [DATA_PAGE] => Some(Arc::new(bytes::<PAGE_SIZE>(reader)?)),
+                _ => return Err(invalid("unknown page kind"))
+            };
+            pages.push((index, page));
+        }
+
+        Ok(Checkpoint {
+            state: CpuState { pc, x, f, #[cfg(feature = "q")] f_high, #[cfg(feature = "v")] v, xlen, csr, reservation },
+            size,
+            pages
+        })
+    }
+}
+
+fn bytes<const N: usize>(reader: &mut dyn Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+    use crate::memory::Memory;
+
+    #[test]
+    fn checkpoints_only_keep_what_changed() {
+        // addi a0, a0, 1; lui a5, 1; sw a0, 0(a5); fcvt.d.l fa0, a0; j 0
+        let words = [0x00150513u32, 0x000017b7, 0x00a7a023, 0xd2250553, 0xff1ff06f];
+        let bytes = program(&words);
+        let base = CowMemory::from_bytes(&[bytes, vec![0; 0x3000]].concat());
+        let (mut cpu, mut memory) = Cpu::new().fork(&base);
+        cpu.run_with_fuel(&mut memory, 12);
+
+        let checkpoint = Checkpoint::capture(&cpu, &memory, &base);
+        assert_eq!(1, checkpoint.changed_pages());
+        let mut saved = Vec::new();
+        checkpoint.write(&mut saved).unwrap();
+        assert!(saved.len() < 2 * PAGE_SIZE);
+
+        let mut resumed = Cpu::new();
+        let mut restored = Checkpoint::read(&mut saved.as_slice()).unwrap().restore(&mut resumed, &base).unwrap();
+        assert_eq!(cpu.save_state(), resumed.save_state());
+        cpu.run_with_fuel(&mut memory, 10);
+        resumed.run_with_fuel(&mut restored, 10);
+        assert_eq!(cpu.save_state(), resumed.save_state());
+        assert_eq!(memory.read_u32(0x1000).unwrap(), restored.read_u32(0x1000).unwrap());
+        assert!(Checkpoint::read(&mut &saved[1..]).is_err());
+        assert!(checkpoint.restore(&mut resumed, &CowMemory::new(PAGE_SIZE)).is_err());
+
+        // the size and the page count come just before the one page, which starts with its index
+        let at = saved.len() - PAGE_SIZE - 1 - 8 - 8 - 8;
+        let mut huge = saved.clone();
+        huge[at..at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Checkpoint::read(&mut huge.as_slice()).unwrap().restore(&mut resumed, &base).is_err());
+        let mut outside = saved.clone();
+        outside[at + 16..at + 24].copy_from_slice(&0x1000u64.to_le_bytes());
+        assert_eq!(io::ErrorKind::InvalidData, Checkpoint::read(&mut outside.as_slice()).unwrap_err().kind());
+    }
+}
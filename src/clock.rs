@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// how fast the time CSR counts when a clock is driving it, the 10MHz most boards use
+pub const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+// Where the guest's idea of time comes from, for the time CSR and the time system calls. Both
+// are given the number of instructions the hart has executed, which is all a deterministic
+// clock needs to produce the same answers on every run.
+pub trait Clock: Send + Sync {
+    // since the Unix epoch, for CLOCK_REALTIME and gettimeofday
+    fn realtime(&self, ticks: u64) -> Duration;
+    // since the hart started, for CLOCK_MONOTONIC and the time CSR
+    fn monotonic(&self, ticks: u64) -> Duration;
+}
+
+// Time that advances by a fixed step per instruction executed, so it only depends on the guest
+// and never on how fast or loaded the host happens to be.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstructionClock {
+    // the realtime clock reading when the hart starts
+    start: Duration,
+    nanoseconds_per_tick: u64
+}
+
+impl Default for InstructionClock {
+    // 2024-01-01 and one instruction a nanosecond
+    fn default() -> Self {
+        InstructionClock {
+            start: Duration::from_secs(1_704_067_200),
+            nanoseconds_per_tick: 1
+        }
+    }
+}
+
+impl InstructionClock {
+    pub fn new() -> Self {
+        InstructionClock::default()
+    }
+
+    // seconds since the Unix epoch
+    pub fn start(mut self, seconds: u64) -> Self {
+        self.start = Duration::from_secs(seconds);
+        self
+    }
+
+    pub fn nanoseconds_per_tick(mut self, nanoseconds: u64) -> Self {
+        self.nanoseconds_per_tick = nanoseconds;
+        self
+    }
+}
+
+impl Clock for InstructionClock {
+    fn realtime(&self, ticks: u64) -> Duration {
+        self.start + self.monotonic(ticks)
+    }
+
+    fn monotonic(&self, ticks: u64) -> Duration {
+        Duration::from_nanos(ticks.saturating_mul(self.nanoseconds_per_tick))
+    }
+}
+
+// The host's wall clock, with the monotonic clock counting from when this was created.
+#[derive(Clone, Copy, Debug)]
+pub struct HostClock {
+    started: Instant
+}
+
+impl Default for HostClock {
+    fn default() -> Self {
+        HostClock { started: Instant::now() }
+    }
+}
+
+impl HostClock {
+    pub fn new() -> Self {
+        HostClock::default()
+    }
+}
+
+impl Clock for HostClock {
+    fn realtime(&self, _ticks: u64) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+
+    fn monotonic(&self, _ticks: u64) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn instruction_clock_only_depends_on_ticks() {
+        let clock = InstructionClock::new().start(1000).nanoseconds_per_tick(250);
+        assert_eq!(Duration::from_micros(1), clock.monotonic(4));
+        assert_eq!(Duration::new(1000, 1000), clock.realtime(4));
+        assert_eq!(clock.realtime(123), clock.realtime(123));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::cpu::{Cpu, Register, Trap, TrapType};
+use crate::cpu::instruction::Instruction;
+use crate::loader::LoadError;
+use crate::loader::elf::ElfLoader;
+use crate::memory::Memory;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+const SYS_READ: i64 = 63;
+const SYS_WRITE: i64 = 64;
+const SYS_EXIT: i64 = 93;
+const SYS_EXIT_GROUP: i64 = 94;
+const ENOSYS: i64 = 38;
+const EBADF: i64 = 9;
+
+// Everything both builds are given. The same config always produces the same run, there is no
+// host state involved, so any difference in the observations comes from the guests themselves.
+#[derive(Clone, Debug)]
+pub struct CompareConfig {
+    pub memory_size: usize,
+    // where the lowest segment is placed, None loads at the linked addresses
+    pub base: Option<usize>,
+    pub fuel: u64,
+    // served to read(0, ...)
+    pub stdin: Vec<u8>,
+    // seeds or arguments put in registers before entry
+    pub registers: Vec<(Register, i64)>,
+    // guest memory to capture once the run has finished
+    pub regions: Vec<Range<usize>>
+}
+
+impl Default for CompareConfig {
+    fn default() -> Self {
+        CompareConfig {
+            memory_size: 1024 * 1024,
+            base: Some(0),
+            fuel: 1_000_000_000,
+            stdin: Vec::new(),
+            registers: Vec::new(),
+            regions: Vec::new()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    Exited(u64),
+    Trapped { pc: usize, trap_type: TrapType },
+    OutOfFuel
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Observation {
+    pub exit: Exit,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    // syscall numbers in the order they were made, arguments are left out as pointers are
+    // expected to move between builds
+    pub syscalls: Vec<i64>,
+    // one entry per CompareConfig::regions, None if the region could not be read
+    pub regions: Vec<Option<Vec<u8>>>,
+    // not compared, a different compiler is expected to change it
+    pub instructions: u64
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Difference {
+    Exit(Exit, Exit),
+    // offset of the first byte that differs, or the length of the shorter stream
+    Stdout(usize),
+    Stderr(usize),
+    Syscall { index: usize, a: Option<i64>, b: Option<i64> },
+    Region { range: Range<usize>, offset: Option<usize> }
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Exit(a, b) => write!(f, "exit differs: {:?} vs {:?}", a, b),
+            Difference::Stdout(offset) => write!(f, "stdout differs from byte {}", offset),
+            Difference::Stderr(offset) => write!(f, "stderr differs from byte {}", offset),
+            Difference::Syscall { index, a, b } => write!(f, "syscall {} differs: {:?} vs {:?}", index, a, b),
+            Difference::Region { range, offset: Some(offset) } => write!(f, "region {:#x}..{:#x} differs at {:#x}", range.start, range.end, range.start + offset),
+            Difference::Region { range, offset: None } => write!(f, "region {:#x}..{:#x} could not be read", range.start, range.end)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    pub a: Observation,
+    pub b: Observation,
+    pub differences: Vec<Difference>
+}
+
+impl Comparison {
+    pub fn is_identical(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_identical() {
+            return writeln!(f, "identical ({} vs {} instructions)", self.a.instructions, self.b.instructions);
+        }
+        for difference in &self.differences {
+            writeln!(f, "{}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+// Loads and runs one build. Syscalls surface as traps and are served here: reads from the
+// configured stdin, writes to stdout and stderr, and exit. Anything else fails with ENOSYS.
+pub fn observe(elf: &[u8], config: &CompareConfig) -> Result<Observation, LoadError> {
+    let mut memory = vec![0u8; config.memory_size];
+    let mut cpu = Cpu::new();
+    let mut loader = ElfLoader::new().stack_pointer(config.memory_size & !0xf);
+    if let Some(base) = config.base {
+        loader = loader.base(base);
+    }
+    loader.load_into(elf, &mut cpu, &mut memory)?;
+
+    cpu.set_ecall_fallback(Some(Instruction {
+        name: "ECALL",
+        operation: |_cpu, _memory, _word, _address| Err(Trap { trap_type: TrapType::EnvironmentCallFromUMode, value: 0 })
+    }));
+    for (register, value) in &config.registers {
+        cpu.set_register(*register, *value);
+    }
+
+    let mut observation = Observation {
+        exit: Exit::OutOfFuel,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        syscalls: Vec::new(),
+        regions: Vec::new(),
+        instructions: 0
+    };
+    let mut stdin = config.stdin.as_slice();
+
+    while observation.instructions < config.fuel {
+        let pc = cpu.get_pc();
+        match cpu.tick(&mut memory) {
+            Ok(_) => observation.instructions += 1,
+            Err(trap) if trap.trap_type == TrapType::EnvironmentCallFromUMode => {
+                observation.instructions += 1;
+                let number = cpu.get_register(Register::A7);
+                observation.syscalls.push(number);
+                if let Some(code) = syscall(&mut cpu, &mut memory, number, &mut stdin, &mut observation) {
+                    observation.exit = Exit::Exited(code);
+                    break;
+                }
+            },
+            Err(trap) => {
+                observation.exit = match trap.trap_type {
+                    TrapType::Stop => Exit::Exited(trap.value),
+                    trap_type => Exit::Trapped { pc, trap_type }
+                };
+                break;
+            }
+        }
+    }
+
+    observation.regions = config.regions.iter()
+        .map(|range| range.clone().map(|a| memory.read_u8(a).ok()).collect())
+        .collect();
+    Ok(observation)
+}
+
+// Some(code) when the guest asked to exit
+fn syscall(cpu: &mut Cpu, memory: &mut dyn Memory, number: i64, stdin: &mut &[u8], observation: &mut Observation) -> Option<u64> {
+    let fd = cpu.get_register(Register::A0);
+    let buffer = cpu.get_register(Register::A1) as usize;
+    let length = cpu.get_register(Register::A2) as usize;
+
+    let result = match number {
+        SYS_EXIT | SYS_EXIT_GROUP => return Some(fd as u64),
+        SYS_READ if fd == 0 => {
+            let count = length.min(stdin.len());
+            let copied = stdin[..count].iter().enumerate().try_for_each(|(i, byte)| memory.write_u8(buffer + i, *byte));
+            *stdin = &stdin[count..];
+            match copied {
+                Ok(_) => count as i64,
+                Err(_) => -EBADF
+            }
+        },
+        SYS_WRITE if fd == 1 || fd == 2 => {
+            let bytes: Result<Vec<u8>, Trap> = (buffer..buffer + length).map(|a| memory.read_u8(a)).collect();
+            match bytes {
+                Ok(bytes) => {
+                    let target = if fd == 1 { &mut observation.stdout } else { &mut observation.stderr };
+                    target.extend_from_slice(&bytes);
+                    length as i64
+                },
+                Err(_) => -EBADF
+            }
+        },
+        SYS_READ | SYS_WRITE => -EBADF,
+        _ => -ENOSYS
+    };
+
+    cpu.set_register(Register::A0, result);
+    None
+}
+
+// Runs both builds with the same config and lists every observable way in which they differ.
+pub fn compare(a: &[u8], b: &[u8], config: &CompareConfig) -> Result<Comparison, LoadError> {
+    let a = observe(a, config)?;
+    let b = observe(b, config)?;
+
+    let mut differences = Vec::new();
+    if a.exit != b.exit {
+        differences.push(Difference::Exit(a.exit, b.exit));
+    }
+    if let Some(offset) = first_difference(&a.stdout, &b.stdout) {
+        differences.push(Difference::Stdout(offset));
+    }
+    if let Some(offset) = first_difference(&a.stderr, &b.stderr) {
+        differences.push(Difference::Stderr(offset));
+    }
+    if let Some(index) = first_difference(&a.syscalls, &b.syscalls) {
+        differences.push(Difference::Syscall { index, a: a.syscalls.get(index).copied(), b: b.syscalls.get(index).copied() });
+    }
+    for (range, (x, y)) in config.regions.iter().zip(a.regions.iter().zip(&b.regions)) {
+        let offset = match (x, y) {
+            (Some(x), Some(y)) => first_difference(x, y),
+            _ => None
+        };
+        if offset.is_some() || x.is_none() || y.is_none() {
+            differences.push(Difference::Region { range: range.clone(), offset });
+        }
+    }
+
+    Ok(Comparison { a, b, differences })
+}
+
+fn first_difference<T: PartialEq>(a: &[T], b: &[T]) -> Option<usize> {
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(index) => Some(index),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None
+    }
+}
+
+#[cfg(all(test, feature = "m", feature = "a", feature = "d", feature = "c"))]
+mod test {
+    use super::*;
+
+    const MANDELBROT: &[u8] = include_bytes!("../test/mandelbrot");
+    const MANDELBROT_DEBUG: &[u8] = include_bytes!("../test/mandelbrot-debug");
+    const ADD_TEST: &[u8] = include_bytes!("../test/rv64ui-p-add");
+
+    #[test]
+    fn debug_and_release_builds_behave_the_same() {
+        let comparison = compare(MANDELBROT, MANDELBROT_DEBUG, &CompareConfig::default()).expect("load");
+
+        assert!(comparison.is_identical(), "{}", comparison);
+        assert!(!comparison.a.stdout.is_empty());
+        assert_ne!(comparison.a.instructions, comparison.b.instructions);
+    }
+
+    #[test]
+    fn reports_what_differs() {
+        let comparison = compare(MANDELBROT, ADD_TEST, &CompareConfig::default()).expect("load");
+
+        assert!(comparison.differences.contains(&Difference::Stdout(0)));
+        assert!(matches!(comparison.differences.iter().find(|d| matches!(d, Difference::Syscall { .. })),
+            Some(Difference::Syscall { index: 0, a: Some(SYS_WRITE), b: Some(SYS_EXIT) })));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::cpu::{Cpu, Trap, TrapType};
+use crate::memory::Memory;
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const ET_CORE: u16 = 4;
+const EM_RISCV: u16 = 243;
+// double precision float ABI
+const EF_RISCV_FLOAT_ABI_DOUBLE: u32 = 0x4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_RWX: u32 = 7;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRFPREG: u32 = 2;
+const PAGE_SIZE: usize = 4096;
+const CSR_FCSR_ADDRESS: u16 = 0x003;
+
+// struct elf_prstatus on riscv64, the general registers start at PR_REG with the pc in place of x0
+const PRSTATUS_SIZE: usize = 376;
+const PR_CURSIG: usize = 12;
+const PR_REG: usize = 112;
+const PR_FPVALID: usize = 368;
+// f0 to f31 followed by fcsr, padded out to 8 bytes
+const FPREGSET_SIZE: usize = 264;
+
+// The signal a Linux kernel would have killed the process with for a trap, it is what gdb
+// reports as the reason the program stopped.
+pub fn signal(trap: &Trap) -> u16 {
+    match trap.trap_type {
+        TrapType::IllegalInstruction => 4,
+        TrapType::Breakpoint => 5,
+        TrapType::InstructionAddressMisaligned | TrapType::LoadAddressMisaligned | TrapType::StoreAddressMisaligned => 7,
+        TrapType::InstructionAccessFault | TrapType::LoadAccessFault | TrapType::StoreAccessFault |
+        TrapType::InstructionPageFault | TrapType::LoadPageFault | TrapType::StorePageFault => 11,
+        // an ecall nothing would serve, as a seccomp kill reports it
+        TrapType::EnvironmentCallFromUMode => 31,
+        // SIGABRT for anything that does not have a natural equivalent
+        _ => 6
+    }
+}
+
+// Writes an ELF core file for the hart and the given regions of guest memory, which gdb will
+// open alongside the executable. Bytes that fault are left out, so a region with holes in it
+// becomes several segments.
+pub fn write(writer: &mut dyn Write, cpu: &Cpu, memory: &dyn Memory, regions: &[Range<usize>], trap: &Trap) -> io::Result<()> {
+    let segments: Vec<(usize, Vec<u8>)> = regions.iter().flat_map(|region| readable_runs(memory, region.clone())).collect();
+    let notes = [note(NT_PRSTATUS, &prstatus(cpu, trap)), note(NT_PRFPREG, &fpregset(cpu))].concat();
+
+    let header_count = segments.len() + 1;
+    let notes_offset = ELF_HEADER_SIZE + header_count * PROGRAM_HEADER_SIZE;
+    let mut offset = align(notes_offset + notes.len(), PAGE_SIZE);
+    let mut headers = program_header(PT_NOTE, 0, notes_offset, 0, notes.len(), 4);
+    let mut offsets = Vec::new();
+    for (address, bytes) in &segments {
+        headers.extend(program_header(PT_LOAD, PF_RWX, offset, *address, bytes.len(), PAGE_SIZE));
+        offsets.push(offset);
+        offset = align(offset + bytes.len(), PAGE_SIZE);
+    }
+
+    let mut output = elf_header(header_count);
+    output.extend(headers);
+    output.extend(notes);
+    for ((_, bytes), offset) in segments.iter().zip(offsets) {
+        output.resize(offset, 0);
+        output.extend(bytes);
+    }
+    writer.write_all(&output)
+}
+
+fn align(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+fn readable_runs(memory: &dyn Memory, region: Range<usize>) -> Vec<(usize, Vec<u8>)> {
+    let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut previous_readable = false;
+    for address in region {
+        match memory.read_u8(address) {
+            Ok(byte) if previous_readable => runs.last_mut().unwrap().1.push(byte),
+            Ok(byte) => runs.push((address, vec![byte])),
+            Err(_) => {}
+        }
+        previous_readable = runs.last().is_some_and(|(start, bytes)| start + bytes.len() == address + 1);
+    }
+    runs
+}
+
+fn elf_header(program_header_count: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(ELF_HEADER_SIZE);
+    // 64 bit, little endian, version 1, System V ABI
+    header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    header.resize(16, 0);
+    header.extend_from_slice(&ET_CORE.to_le_bytes());
+    header.extend_from_slice(&EM_RISCV.to_le_bytes());
+    header.extend_from_slice(&1u32.to_le_bytes());
+    // no entry point and no section headers
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes());
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header.extend_from_slice(&EF_RISCV_FLOAT_ABI_DOUBLE.to_le_bytes());
+    header.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&(program_header_count as u16).to_le_bytes());
+    header.extend_from_slice(&[0; 6]);
+    header
+}
+
+fn program_header(kind: u32, flags: u32, offset: usize, address: usize, size: usize, alignment: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(PROGRAM_HEADER_SIZE);
+    header.extend_from_slice(&kind.to_le_bytes());
+    header.extend_from_slice(&flags.to_le_bytes());
+    for value in [offset, address, address, size, size, alignment] {
+        header.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+    header
+}
+
+fn note(kind: u32, description: &[u8]) -> Vec<u8> {
+    let mut note = Vec::new();
+    note.extend_from_slice(&5u32.to_le_bytes());
+    note.extend_from_slice(&(description.len() as u32).to_le_bytes());
+    note.extend_from_slice(&kind.to_le_bytes());
+    // the name and the description are each padded to 4 bytes
+    note.extend_from_slice(b"CORE\0\0\0\0");
+    note.extend_from_slice(description);
+    note.resize(align(note.len(), 4), 0);
+    note
+}
+
+fn prstatus(cpu: &Cpu, trap: &Trap) -> Vec<u8> {
+    let mut status = vec![0u8; PRSTATUS_SIZE];
+    let signal = signal(trap);
+    status[0..4].copy_from_slice(&(signal as i32).to_le_bytes());
+    status[PR_CURSIG..PR_CURSIG + 2].copy_from_slice(&signal.to_le_bytes());
+    // a single thread, pid 1
+    status[32..36].copy_from_slice(&1i32.to_le_bytes());
+
+    let registers = std::iter::once(cpu.get_pc() as u64).chain(cpu.registers()[1..].iter().map(|x| *x as u64));
+    for (i, value) in registers.enumerate() {
+        status[PR_REG + i * 8..PR_REG + i * 8 + 8].copy_from_slice(&value.to_le_bytes());
+    }
+    status[PR_FPVALID..PR_FPVALID + 4].copy_from_slice(&1i32.to_le_bytes());
+    status
+}
+
+fn fpregset(cpu: &Cpu) -> Vec<u8> {
+    let mut registers: Vec<u8> = cpu.fp_registers().iter().flat_map(|f| f.to_bits().to_le_bytes()).collect();
+    registers.extend_from_slice(&(cpu.read_csr(CSR_FCSR_ADDRESS) as u32).to_le_bytes());
+    registers.resize(FPREGSET_SIZE, 0);
+    registers
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::{FpRegister, Register};
+    use crate::memory::Memory;
+
+    fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn core_holds_registers_and_readable_memory() {
+        let mut memory = vec![0u8; 0x100];
+        memory.write_u64(0xf0, 0x1122334455667788).unwrap();
+        let mut cpu = Cpu::builder().pc(0x40).build();
+        cpu.set_register(Register::A0, 42);
+        cpu.set_fp_register(FpRegister::FA0, 1.5);
+        let trap = Trap { trap_type: TrapType::LoadAccessFault, value: 0x200 };
+
+        let mut core = Vec::new();
+        // the second region is beyond the end of memory and leaves no segment behind
+        cpu.write_core_dump(&mut core, &memory, &[0xe0..0x100, 0x100..0x180], &trap).unwrap();
+
+        assert_eq!(b"\x7fELF", &core[0..4]);
+        assert_eq!(ET_CORE, u16::from_le_bytes([core[16], core[17]]));
+        assert_eq!(EM_RISCV, u16::from_le_bytes([core[18], core[19]]));
+        assert_eq!(2, u16::from_le_bytes([core[56], core[57]]));
+
+        let note = &core[ELF_HEADER_SIZE..];
+        let notes = &core[u64_at(note, 8) as usize..];
+        assert_eq!(PRSTATUS_SIZE as u32, u32::from_le_bytes(notes[4..8].try_into().unwrap()));
+        assert_eq!(b"CORE\0", &notes[12..17]);
+        let status = &notes[20..];
+        assert_eq!(11, status[PR_CURSIG]);
+        assert_eq!(0x40, u64_at(status, PR_REG));
+        assert_eq!(42, u64_at(status, PR_REG + 10 * 8));
+        let fp = &notes[20 + PRSTATUS_SIZE + 20..];
+        assert_eq!(1.5f64.to_bits(), u64_at(fp, 10 * 8));
+
+        let load = &core[ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE..];
+        assert_eq!(PT_LOAD, u32::from_le_bytes(load[0..4].try_into().unwrap()));
+        assert_eq!(0xe0, u64_at(load, 16));
+        assert_eq!(0x20, u64_at(load, 32));
+        assert_eq!(0x1122334455667788, u64_at(&core, u64_at(load, 8) as usize + 0x10));
+    }
+}
@@ -1,25 +1,167 @@
+use decode_table::DecodeTable;
+use engine::{BlockCache, DecodeCache, Watched, may_store};
 use instruction::Instruction;
+use predecode::Op;
+#[cfg(feature = "a")]
 use rv64ua::*;
+#[cfg(feature = "d")]
 use rv64ud::*;
+#[cfg(feature = "f")]
 use rv64uf::*;
+#[cfg(feature = "q")]
+use rv64uq::*;
 use rv64ui::*;
+#[cfg(feature = "m")]
 use rv64um::*;
+#[cfg(feature = "zba")]
+use rv64uzba::*;
+#[cfg(any(feature = "zbb", feature = "zbkb"))]
+use rv64uzbb::*;
+#[cfg(any(feature = "zbc", feature = "zbkc"))]
+use rv64uzbc::*;
+#[cfg(feature = "zbkb")]
+use rv64uzbkb::*;
+#[cfg(feature = "zbkx")]
+use rv64uzbkx::*;
+#[cfg(any(feature = "zknd", feature = "zkne"))]
+use rv64uzkn::*;
+#[cfg(feature = "zknh")]
+use rv64uzknh::*;
+#[cfg(feature = "zksed")]
+use rv64uzksed::*;
+#[cfg(feature = "zksh")]
+use rv64uzksh::*;
+#[cfg(feature = "zbs")]
+use rv64uzbs::*;
+#[cfg(feature = "p")]
+use rv64up::*;
+#[cfg(feature = "v")]
+use rv64uv::*;
+#[cfg(feature = "zawrs")]
+use rv64uzawrs::*;
+#[cfg(feature = "zfa")]
+use rv64uzfa::*;
+#[cfg(feature = "zicond")]
+use rv64uzicond::*;
+#[cfg(feature = "zimop")]
+use rv64uzimop::*;
+use std::cell::RefCell;
 use std::fmt::{Debug, Formatter};
 use std::fmt;
-use crate::memory::Memory;
-
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+use std::sync::Arc;
+use crate::clock::{Clock, TIMEBASE_FREQUENCY};
+use crate::coredump;
+use crate::dump::MemoryDump;
+use crate::memory::{CowMemory, Memory};
+use crate::trace::{TraceEvent, TraceFilter, Tracer, TracerSlot};
+use fflags::{FflagsProvenance, FFLAG_DZ, FFLAG_NV, FFLAG_NX};
+use step::Recording;
+
+pub mod branches;
+pub mod cache_model;
+mod call_stack;
+pub mod coverage;
+pub mod csr_hooks;
+pub mod decoders;
+mod decode_table;
+mod ecall;
+mod engine;
+pub mod extensions;
+pub mod fflags;
+pub mod gas;
+pub mod host;
+pub mod hpm;
 pub mod instruction;
+pub mod pc_history;
+mod predecode;
+pub mod profile;
+pub mod replay;
+mod reverse;
 mod rv64ui;
+#[cfg(feature = "m")]
 mod rv64um;
+#[cfg(feature = "a")]
 mod rv64ua;
+#[cfg(feature = "f")]
 mod rv64uf;
+#[cfg(feature = "d")]
 mod rv64ud;
+#[cfg(feature = "q")]
+mod quad;
+#[cfg(feature = "q")]
+mod rv64uq;
+#[cfg(feature = "p")]
+mod rv64up;
+#[cfg(feature = "v")]
+mod rv64uv;
+#[cfg(feature = "v")]
+mod rv64uvf;
+#[cfg(feature = "zba")]
+mod rv64uzba;
+#[cfg(any(feature = "zbb", feature = "zbkb"))]
+mod rv64uzbb;
+#[cfg(any(feature = "zbc", feature = "zbkc"))]
+mod rv64uzbc;
+#[cfg(feature = "zbkb")]
+mod rv64uzbkb;
+#[cfg(feature = "zbkx")]
+mod rv64uzbkx;
+#[cfg(any(feature = "zknd", feature = "zkne"))]
+mod rv64uzkn;
+#[cfg(feature = "zknh")]
+mod rv64uzknh;
+#[cfg(feature = "zksed")]
+mod rv64uzksed;
+#[cfg(feature = "zksh")]
+mod rv64uzksh;
+#[cfg(feature = "zbs")]
+mod rv64uzbs;
+#[cfg(feature = "zawrs")]
+mod rv64uzawrs;
+#[cfg(feature = "zfa")]
+mod rv64uzfa;
+#[cfg(feature = "zicond")]
+mod rv64uzicond;
+#[cfg(feature = "zimop")]
+mod rv64uzimop;
+pub mod run;
+pub mod state;
+pub mod step;
+pub mod timing;
+#[cfg(feature = "v")]
+pub mod vector;
+mod zfinx;
+
+pub use branches::{Bimodal, BranchCount, BranchStats, Gshare, Predictor, PredictorStats};
+pub use cache_model::{CacheConfig, CacheModel, HitStats};
+pub use coverage::Coverage;
+pub use csr_hooks::{CsrHandler, CsrHooks};
+pub use decoders::{CustomDecoder, CustomDecoders};
+pub use ecall::EcallHandlers;
+pub use extensions::Extensions;
+pub use gas::GasTable;
+pub use host::{HostArg, HostArgs, HostFn, HostReturn};
+pub use reverse::Diverged;
+pub use replay::{Effects, Event, Input, ReplayLog, ReplayStatus};
+pub use pc_history::PcRecord;
+pub use profile::Profile;
+pub use run::{RunConfig, StopHandle, StopReason, WakeHandle, Watchpoint};
+pub use state::{CpuState, Reservation};
+pub use step::{AccessKind, MemoryAccess, StepInfo, WrittenRegister};
+pub use timing::{ClassCosts, CostModel};
+pub use engine::{CacheStats, Caches, Engine};
 
 const CSR_CAPACITY: usize = 4096;
 const _CSR_USTATUS_ADDRESS: u16 = 0x000;
 const CSR_FFLAGS_ADDRESS: u16 = 0x001;
 const CSR_FRM_ADDRESS: u16 = 0x002;
 const CSR_FCSR_ADDRESS: u16 = 0x003;
+// the fixed point saturation flag, which V and P share
+#[cfg(any(feature = "v", feature = "p"))]
+const CSR_VXSAT_ADDRESS: u16 = 0x009;
 const _CSR_UIE_ADDRESS: u16 = 0x004;
 const _CSR_UTVEC_ADDRESS: u16 = 0x005;
 const _CSR_USCRATCH_ADDRESS: u16 = 0x040;
@@ -31,20 +173,20 @@ const CSR_SSTATUS_ADDRESS: u16 = 0x100;
 const _CSR_SEDELEG_ADDRESS: u16 = 0x102;
 const _SR_SIDELEG_ADDRESS: u16 = 0x103;
 const CSR_SIE_ADDRESS: u16 = 0x104;
-const _CSR_STVEC_ADDRESS: u16 = 0x105;
+const CSR_STVEC_ADDRESS: u16 = 0x105;
 const _CSR_SSCRATCH_ADDRESS: u16 = 0x140;
 const _CSR_SEPC_ADDRESS: u16 = 0x141;
 const _CSR_SCAUSE_ADDRESS: u16 = 0x142;
 const _CSR_STVAL_ADDRESS: u16 = 0x143;
 const CSR_SIP_ADDRESS: u16 = 0x144;
-const _CSR_SATP_ADDRESS: u16 = 0x180;
+const CSR_SATP_ADDRESS: u16 = 0x180;
 const CSR_MSTATUS_ADDRESS: u16 = 0x300;
-const _CSR_MISA_ADDRESS: u16 = 0x301;
-const _CSR_MEDELEG_ADDRESS: u16 = 0x302;
+const CSR_MISA_ADDRESS: u16 = 0x301;
+const CSR_MEDELEG_ADDRESS: u16 = 0x302;
 const CSR_MIDELEG_ADDRESS: u16 = 0x303;
 const CSR_MIE_ADDRESS: u16 = 0x304;
 
-const _CSR_MTVEC_ADDRESS: u16 = 0x305;
+const CSR_MTVEC_ADDRESS: u16 = 0x305;
 const _CSR_MSCRATCH_ADDRESS: u16 = 0x340;
 const CSR_MEPC_ADDRESS: u16 = 0x341;
 const _CSR_MCAUSE_ADDRESS: u16 = 0x342;
@@ -52,26 +194,89 @@ const _CSR_MTVAL_ADDRESS: u16 = 0x343;
 const CSR_MIP_ADDRESS: u16 = 0x344;
 const _CSR_PMPCFG0_ADDRESS: u16 = 0x3a0;
 const _CSR_PMPADDR0_ADDRESS: u16 = 0x3b0;
-const _CSR_MCYCLE_ADDRESS: u16 = 0xb00;
-const _CSR_CYCLE_ADDRESS: u16 = 0xc00;
+const CSR_MCYCLE_ADDRESS: u16 = 0xb00;
+const CSR_MINSTRET_ADDRESS: u16 = 0xb02;
+const CSR_MCYCLEH_ADDRESS: u16 = 0xb80;
+const CSR_MINSTRETH_ADDRESS: u16 = 0xb82;
+const CSR_CYCLE_ADDRESS: u16 = 0xc00;
 const CSR_TIME_ADDRESS: u16 = 0xc01;
-const _CSR_INSERT_ADDRESS: u16 = 0xc02;
+const CSR_INSTRET_ADDRESS: u16 = 0xc02;
+const CSR_CYCLEH_ADDRESS: u16 = 0xc80;
+const CSR_TIMEH_ADDRESS: u16 = 0xc81;
+const CSR_INSTRETH_ADDRESS: u16 = 0xc82;
 const _CSR_MHARTID_ADDRESS: u16 = 0xf14;
+const CSR_MCOUNTEREN_ADDRESS: u16 = 0x306;
+const CSR_SCOUNTEREN_ADDRESS: u16 = 0x106;
+
+// the encodings RV64 added: the word sized ops, doubleword loads and stores and AMOs, shift
+// amounts past 31, conversions and moves between 64 bit integers and FP registers and the 64 bit
+// forms of AES and SHA-512
+fn rv64_only(word: u32) -> bool {
+    let funct3 = (word >> 12) & 7;
+    match word & 0x7f {
+        0b0011011 | 0b0111011 => true,
+        0b0000011 => matches!(funct3, 0b011 | 0b110),
+        0b0100011 => funct3 == 0b011,
+        // amocas.d is the double width one in RV32
+        0b0101111 => funct3 == 0b100 || (funct3 == 0b011 && word >> 27 != 0b00101),
+        0b0010011 => (matches!(funct3, 0b001 | 0b101) && word & (1 << 25) != 0) ||
+            (funct3 == 0b001 && (word >> 25 == 0b0011000 || (word >> 25 == 0b0001000 && (word >> 22) & 7 == 1))),
+        0b0110011 => funct3 == 0 && matches!(word >> 25, 0b0011001 | 0b0011011 | 0b0011101 | 0b0011111 | 0b0111111),
+        0b1010011 => match word >> 25 {
+            0b1100000 | 0b1100001 | 0b1100011 | 0b1101000 | 0b1101001 | 0b1101011 => matches!((word >> 20) & 0x1f, 2 | 3),
+            // fclass.d and fmvh.x.d share their funct7 with fmv.x.d
+            0b1110001 => funct3 == 0 && (word >> 20) & 0x1f == 0,
+            // fli.d shares its funct7 with fmv.d.x
+            0b1111001 => (word >> 20) & 0x1f == 0,
+            _ => false
+        },
+        _ => false
+    }
+}
 
-#[derive(Clone, Debug)]
+// the encodings RV64 gave to something else or has in another form, rev8, zip, unzip and
+// zext.h, which with Zbkb is pack and on RV64 too, and the moves of a double to and from a pair
+// of x registers
+fn rv32_only(word: u32) -> bool {
+    match word & 0x7f {
+        0b0010011 => (word >> 20 == 0x698 && (word >> 12) & 7 == 0b101) || (word >> 20 == 0x08f && matches!((word >> 12) & 7, 0b001 | 0b101)),
+        0b0110011 => !cfg!(feature = "zbkb") && word >> 20 == 0x080 && (word >> 12) & 7 == 0b100,
+        0b1010011 => (word >> 20 == 0xe21 || word >> 25 == 0b1011001) && (word >> 12) & 7 == 0,
+        _ => false
+    }
+}
+
+// the CSRs the hart models, csr instructions trap on any other no handler serves
+fn is_implemented(address: u16, xlen: Xlen) -> bool {
+    match address {
+        // the upper halves of the counters only exist for RV32
+        0xb80 | 0xb82..=0xb9f | 0xc80..=0xc9f => xlen == Xlen::Bit32,
+        _ => matches!(address,
+            0x001..=0x003 | 0x008..=0x00a | 0x00f |
+            0x100 | 0x104..=0x106 | 0x140..=0x144 | 0x180 |
+            0x300..=0x306 | 0x323..=0x33f | 0x340..=0x344 | 0x3a0..=0x3ef |
+            0xb00 | 0xb02..=0xb1f |
+            0xc00..=0xc1f | 0xc20..=0xc22 |
+            0xf11..=0xf14)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Xlen {
     Bit32,
+    #[default]
     Bit64
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Trap {
     pub trap_type: TrapType,
     pub value: u64 // Trap type specific value
 }
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TrapType {
     InstructionAddressMisaligned,
     InstructionAccessFault,
@@ -96,7 +301,18 @@ pub enum TrapType {
     UserExternalInterrupt,
     SupervisorExternalInterrupt,
     MachineExternalInterrupt,
-    Stop
+    Stop,
+    // the instruction was not executed as there was not enough gas left for it, see set_gas
+    OutOfGas,
+    // a run that only comes back with traps was stopped through the hart's StopHandle
+    StopRequested
+}
+
+impl Trap {
+    // what an ecall handler returns to end the run with the guest's exit status
+    pub fn exit(status: i64) -> Self {
+        Trap { trap_type: TrapType::Stop, value: status as u64 }
+    }
 }
 
 /*
@@ -117,6 +333,7 @@ x28-31	    t3-6	    temporary registers	Caller
 
  */
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Register {
     ZERO = 0,
     RA = 1,
@@ -152,6 +369,7 @@ pub enum Register {
     T6 = 31
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum FpRegister {
     FT0 = 0,
     FT1 = 1,
@@ -187,15 +405,88 @@ pub enum FpRegister {
     FT11 = 31
 }
 
+// Lets guests keep tags in the high bits of pointers, in the spirit of the pointer masking
+// extensions. Accesses see the address with the ignored bits replaced by a copy of the highest bit
+// kept, or by zeroes. Registers are left alone, so tags survive being passed around. The spec
+// never masks instruction fetches, fetch_bits is there for guests that tag code pointers too.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PointerMasking {
+    // number of high bits ignored by loads, stores and AMOs
+    pub data_bits: u32,
+    pub fetch_bits: u32,
+    pub sign_extend: bool
+}
+
+impl PointerMasking {
+    fn apply(&self, bits: u32, address: usize) -> usize {
+        match (bits, self.sign_extend) {
+            (0, _) => address,
+            (_, true) => (((address as i64) << bits) >> bits) as usize,
+            (_, false) => (address << bits) >> bits
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Cpu {
-    pub pc: usize,
-    pub x: [i64; 32],
-    pub f: [f64; 32],
+    pub(crate) pc: usize,
+    pub(crate) x: [i64; 32],
+    pub(crate) f: [f64; 32],
+    // the top halves of the f registers, which only quads use, narrower values leave them all ones
+    #[cfg(feature = "q")]
+    pub(crate) f_high: [u64; 32],
+    // the vector registers one after another, VLEN / 8 bytes each, with vl and vtype kept in csr
+    #[cfg(feature = "v")]
+    pub(crate) v: Vec<u8>,
     xlen: Xlen,
-    pub csr: [u64; CSR_CAPACITY],
+    pub(crate) csr: [u64; CSR_CAPACITY],
     reservation: u64, // @TODO: Should support multiple address reservations
     is_reservation_set: bool,
-    ecall_handler: Option<Instruction>
+    reservation_value: u64,
+    ecall_handlers: EcallHandlers,
+    csr_hooks: CsrHooks,
+    decoders: CustomDecoders,
+    // runs in place of the ebreak itself, which otherwise only makes semihosting calls
+    ebreak_handler: Option<Instruction>,
+    fflags_provenance: Option<FflagsProvenance>,
+    tracer: TracerSlot,
+    trace_filter: Option<TraceFilter>,
+    pointer_masking: PointerMasking,
+    engine: Engine,
+    blocks: BlockCache,
+    decoded: DecodeCache,
+    // drives the time CSR when set, otherwise it simply counts instructions
+    clock: Option<Arc<dyn Clock>>,
+    // shared by clones, so one stop reaches every hart of a process
+    stop: StopHandle,
+    // where and when the last run stopped at a breakpoint, which the next one steps over
+    breakpoint_stop: Option<(usize, u64)>,
+    // whether a run stopped for the request from stop, which stands until the hart runs again
+    stopped_for_request: bool,
+    // what WRS parks the hart on, it never parks without one
+    wake: Option<WakeHandle>,
+    // whether PAUSE yields the host thread, for guests spinning while other harts do the work
+    yield_on_pause: bool,
+    // whether the F and D instructions work on the x registers, as Zfinx and Zdinx have them
+    zfinx: bool,
+    replay: replay::Mode,
+    // checkpoints for going back in time, when enabled
+    history: reverse::History,
+    pc_history: Option<pc_history::PcHistory>,
+    call_stack: Option<call_stack::CallStack>,
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+    branch_stats: Option<BranchStats>,
+    predictors: branches::Predictors,
+    cache_model: Option<CacheModel>,
+    cost_model: Option<Arc<dyn CostModel>>,
+    gas: Option<gas::Gas>,
+    // whether any mhpmevent is set, to skip looking at them all for every instruction
+    counting_events: bool,
+    extensions: Extensions,
+    // whether anything watches, filters, meters or masks what the hart runs, or it runs F in the
+    // x registers, an instruction retires without any of them when none of that is so
+    observed: bool
 }
 
 impl Debug for Cpu {
@@ -210,40 +501,320 @@ impl Debug for Cpu {
 }
 
 impl Cpu {
+    pub fn builder() -> CpuBuilder {
+        CpuBuilder::new()
+    }
+
     pub fn new() -> Self {
-        Cpu {
+        #[cfg_attr(not(feature = "v"), allow(unused_mut))]
+        let mut cpu = Cpu {
             pc: 0,
             x: [0; 32],
             f: [0.0; 32],
+            #[cfg(feature = "q")]
+            f_high: [0; 32],
+            #[cfg(feature = "v")]
+            v: Vec::new(),
             xlen: Xlen::Bit64,
             csr: [0; CSR_CAPACITY],
             reservation: 0,
             is_reservation_set: false,
-            ecall_handler: None
-        }
+            reservation_value: 0,
+            ecall_handlers: EcallHandlers::new(),
+            csr_hooks: CsrHooks::new(),
+            decoders: CustomDecoders::new(),
+            ebreak_handler: None,
+            fflags_provenance: None,
+            tracer: TracerSlot::default(),
+            trace_filter: None,
+            pointer_masking: PointerMasking::default(),
+            engine: Engine::Interpreter,
+            blocks: BlockCache::default(),
+            decoded: DecodeCache::default(),
+            clock: None,
+            stop: StopHandle::default(),
+            breakpoint_stop: None,
+            stopped_for_request: false,
+            wake: None,
+            yield_on_pause: false,
+            zfinx: false,
+            replay: replay::Mode::Off,
+            history: reverse::History::default(),
+            pc_history: None,
+            call_stack: None,
+            profile: None,
+            coverage: None,
+            branch_stats: None,
+            predictors: branches::Predictors::default(),
+            cache_model: None,
+            cost_model: None,
+            gas: None,
+            counting_events: false,
+            extensions: Extensions::all(),
+            observed: false
+        };
+        #[cfg(feature = "v")]
+        cpu.set_vlen(vector::DEFAULT_VLEN);
+        cpu
+    }
+
+    // duplicates the hart along with a copy-on-write clone of its memory, the two instances can
+    // then run independently without paying for a full copy of the guest image
+    pub fn fork(&self, memory: &CowMemory) -> (Cpu, CowMemory) {
+        (self.clone(), memory.clone_cow())
     }
 
     pub fn fetch(&mut self, memory: &dyn Memory) -> Result<u32, Trap> {
-        let result = memory.read_u32(self.pc)?;
-        match result & 3 {
-            3 => {
-                self.pc = self.pc + 4;
-                Ok(result)
+        let (word, length) = Cpu::fetch_word(memory, self.pc, self.xlen)?;
+        self.pc += length;
+        Ok(word)
+    }
+
+    // the uncompressed instruction at address along with how many bytes it occupies
+    #[cfg_attr(not(feature = "c"), allow(unused_variables))]
+    fn fetch_word(memory: &dyn Memory, address: usize, xlen: Xlen) -> Result<(u32, usize), Trap> {
+        let result = memory.read_u32(address).map_err(|trap| match trap.trap_type {
+            TrapType::LoadAccessFault => Trap {
+                trap_type: TrapType::InstructionAccessFault,
+                value: trap.value
             },
-            _ => {
-                self.pc = self.pc + 2;
+            _ => trap
+        })?;
+        match result & 3 {
+            3 => Ok((result, 4)),
+            #[cfg(feature = "c")]
+            _ => Ok((Cpu::uncompress_for(result & 0xffff, xlen), 2)),
+            #[cfg(not(feature = "c"))]
+            _ => Err(Trap { trap_type: TrapType::IllegalInstruction, value: (result & 0xffff) as u64 })
+        }
+    }
 
-                Ok(Cpu::uncompress(result & 0xffff))
-            }
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn pointer_masking(&self) -> PointerMasking {
+        self.pointer_masking
+    }
+
+    pub fn set_pointer_masking(&mut self, masking: PointerMasking) {
+        self.pointer_masking = masking;
+        self.update_observed();
+    }
+
+    // the address a load, store or AMO actually touches
+    pub(crate) fn data_address(&self, address: i64) -> usize {
+        self.pointer_masking.apply(self.pointer_masking.data_bits, self.wrap_address(address as usize))
+    }
+
+    // an RV32 hart only has 4GiB to address, anything past the end wraps around to the start
+    fn wrap_address(&self, address: usize) -> usize {
+        match self.xlen {
+            Xlen::Bit32 => address & 0xffff_ffff,
+            Xlen::Bit64 => address
+        }
+    }
+
+    // takes effect from the next tick, any decoded code is thrown away so the new engine starts
+    // from what is in memory right now
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+        self.invalidate_code_cache();
+    }
+
+    // must be called if guest code is changed from the host, guests do it with FENCE.I
+    pub fn invalidate_code_cache(&mut self) {
+        self.blocks.invalidate();
+        self.decoded.invalidate();
+    }
+
+    // like invalidate_code_cache, keeping what was decoded from outside range
+    pub fn invalidate_code(&mut self, range: Range<usize>) {
+        if !range.is_empty() {
+            self.blocks.invalidate_range(range.start, range.end - 1);
+            self.decoded.invalidate_range(range.start, range.end - 1);
         }
     }
 
+    // Runs write, which changes guest memory from the host, and throws away whatever code was
+    // decoded from the bytes it wrote.
+    pub(crate) fn host_writes<T>(&mut self, memory: &mut dyn Memory, write: impl FnOnce(&mut Cpu, &mut dyn Memory) -> T) -> T {
+        let mut watched = Watched { inner: memory, written: None };
+        let result = write(self, &mut watched);
+        if let Some((low, high)) = watched.written {
+            self.invalidate_code(low..high.saturating_add(1));
+        }
+        result
+    }
+
+    // Decodes the code in range ahead of time for Engine::Cached, which then runs it without
+    // going back to memory until the guest stores over it or executes FENCE.I. The decoded pages
+    // take far more memory than the code does. Returns how many instructions were decoded.
+    pub fn predecode(&mut self, memory: &dyn Memory, range: Range<usize>) -> usize {
+        self.decoded.fill(range, memory, self.xlen)
+    }
+
+    pub fn cached_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    // drops everything the hart has derived from guest memory, for hosts that patch code or
+    // remap memory behind the guest's back and want one call that is always enough
+    pub fn invalidate_all_caches(&mut self) {
+        self.invalidate_code_cache();
+    }
+
+    pub fn cache_stats(&self) -> Caches {
+        Caches {
+            blocks: self.blocks.stats(),
+            decoded: self.decoded.stats()
+        }
+    }
+
+    pub fn reset_cache_stats(&mut self) {
+        self.blocks.reset_stats();
+        self.decoded.reset_stats();
+    }
+
+    pub fn clock(&self) -> Option<&Arc<dyn Clock>> {
+        self.clock.as_ref()
+    }
+
+    pub fn set_clock(&mut self, clock: Option<Arc<dyn Clock>>) {
+        self.clock = clock;
+    }
+
+    // instructions executed so far, what clocks measure time in
+    pub fn ticks(&self) -> u64 {
+        self.csr[CSR_TIME_ADDRESS as usize]
+    }
+
     pub fn update_pc(&mut self, new_pc: usize) {
         self.pc = new_pc;
     }
 
-    pub fn set_ecall_handler(&mut self, handler: Option<Instruction>) {
-        self.ecall_handler = handler;
+    // handles every ecall whose number has no handler registered for it
+    pub fn set_ecall_fallback(&mut self, handler: Option<Instruction>) {
+        self.ecall_handlers.set_fallback(handler);
+    }
+
+    // handles ecalls with number in a7, in place of the fallback
+    pub fn register_ecall_handler(&mut self, number: i64, handler: Instruction) -> Option<Instruction> {
+        self.ecall_handlers.register(number, handler)
+    }
+
+    // for debug monitors and panic channels the guest triggers itself, unlike a debugger's
+    // breakpoints it runs whenever the guest executes an ebreak, semihosting calls included
+    pub fn set_ebreak_handler(&mut self, handler: Option<Instruction>) {
+        self.ebreak_handler = handler;
+    }
+
+    // Binds function to ecalls with number in a7, its arguments taken from the integer and
+    // floating point argument registers in order and its result put in a0 or fa0, e.g.
+    // cpu.bind_host_fn(1000, |(a, b): (u64, f64)| Ok(a as f64 * b))
+    pub fn bind_host_fn<A, R, F>(&mut self, number: i64, function: F)
+    where
+        A: HostArgs,
+        R: HostReturn,
+        F: FnMut(A) -> Result<R, Trap> + Send + 'static
+    {
+        self.ecall_handlers.bind(number, HostFn::new(function));
+    }
+
+    pub fn ecall_handlers(&self) -> &EcallHandlers {
+        &self.ecall_handlers
+    }
+
+    pub fn ecall_handlers_mut(&mut self) -> &mut EcallHandlers {
+        &mut self.ecall_handlers
+    }
+
+    // Serves the guest's accesses to the CSR at address with handler, in place of the hart's
+    // own implementation if it has one. read_csr and write_csr from the host go straight to the
+    // hart either way, and guest writes to read only CSRs trap before reaching the handler.
+    pub fn register_csr(&mut self, address: u16, handler: impl CsrHandler + 'static) {
+        self.csr_hooks.register(address, handler);
+    }
+
+    // serves the guest's accesses to every CSR the hart does not implement and no handler is
+    // registered for, such as custom and vendor CSRs
+    pub fn set_csr_fallback(&mut self, handler: Option<Box<dyn CsrHandler>>) {
+        self.csr_hooks.set_fallback(handler);
+    }
+
+    pub fn extensions(&self) -> Extensions {
+        self.extensions
+    }
+
+    // the extensions whose instructions the hart executes, the rest raise illegal instruction
+    // traps, and what misa reports
+    pub fn set_extensions(&mut self, extensions: Extensions) {
+        self.extensions = extensions;
+        self.update_observed();
+    }
+
+    // Decodes the 32 bit words of the major opcode with decoder when the hart has no instruction
+    // for them, see CustomDecoders. Returns the decoder the opcode had before, if any.
+    pub fn register_decoder(&mut self, opcode: u32, decoder: CustomDecoder) -> Option<CustomDecoder> {
+        self.decoders.register(opcode, decoder)
+    }
+
+    pub fn decoders(&self) -> &CustomDecoders {
+        &self.decoders
+    }
+
+    pub fn decoders_mut(&mut self) -> &mut CustomDecoders {
+        &mut self.decoders
+    }
+
+    pub fn csr_hooks(&self) -> &CsrHooks {
+        &self.csr_hooks
+    }
+
+    pub fn csr_hooks_mut(&mut self) -> &mut CsrHooks {
+        &mut self.csr_hooks
+    }
+
+    // start (or stop) recording which pcs raise which fflags bits
+    pub fn track_fflags_provenance(&mut self, enabled: bool) {
+        self.fflags_provenance = match enabled {
+            true => Some(self.fflags_provenance.take().unwrap_or_default()),
+            false => None
+        };
+        self.update_observed();
+    }
+
+    pub fn fflags_provenance(&self) -> Option<&FflagsProvenance> {
+        self.fflags_provenance.as_ref()
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) {
+        self.tracer = TracerSlot(tracer);
+        self.update_observed();
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        let tracer = self.tracer.0.take();
+        self.update_observed();
+        tracer
+    }
+
+    // instructions at pcs the filter rejects retire without the tracer hearing about them
+    pub fn set_trace_filter(&mut self, filter: Option<TraceFilter>) {
+        self.trace_filter = filter;
+    }
+
+    // hexdump of guest memory with pc and sp marked where they fall inside the range
+    pub fn dump_memory(&self, memory: &dyn Memory, range: Range<usize>) -> MemoryDump {
+        let mut dump = MemoryDump::new(memory, range);
+        dump.annotate(self.pc, "pc");
+        dump.annotate(self.x[Register::SP as usize] as usize, "sp");
+        dump
+    }
+
+    // an ELF core file gdb can load for post-mortem debugging of a trap nothing handled
+    pub fn write_core_dump(&self, writer: &mut dyn Write, memory: &dyn Memory, regions: &[Range<usize>], trap: &Trap) -> io::Result<()> {
+        coredump::write(writer, self, memory, regions, trap)
     }
 
     pub fn get_pc(&self) -> usize {
@@ -262,21 +833,345 @@ impl Cpu {
         self.x[Register::SP as usize] = stack_pointer as i64;
     }
 
+    pub fn registers(&self) -> &[i64; 32] {
+        &self.x
+    }
+
+    pub fn get_fp_register(&self, register: FpRegister) -> f64 {
+        self.f[register as usize]
+    }
+
+    pub fn set_fp_register(&mut self, register: FpRegister, value: f64) {
+        self.f[register as usize] = value;
+    }
+
+    pub fn fp_registers(&self) -> &[f64; 32] {
+        &self.f
+    }
+
+    pub fn get_xlen(&self) -> Xlen {
+        self.xlen
+    }
+
+    // compressed instructions decode differently for each width, so decoded code is thrown away
+    pub fn set_xlen(&mut self, xlen: Xlen) {
+        self.xlen = xlen;
+        self.invalidate_code_cache();
+    }
+
+    // A handle another thread can use to make run return StopReason::StopRequested before its
+    // next instruction, without killing the process the guest lives in.
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    pub fn wake_handle(&self) -> Option<&WakeHandle> {
+        self.wake.as_ref()
+    }
+
+    pub fn set_wake_handle(&mut self, wake: Option<WakeHandle>) {
+        self.wake = wake;
+    }
+
+    pub fn set_yield_on_pause(&mut self, yield_on_pause: bool) {
+        self.yield_on_pause = yield_on_pause;
+    }
+
+    pub fn zfinx(&self) -> bool {
+        self.zfinx
+    }
+
+    // F and D still have to be among the extensions for their instructions to run, but misa
+    // leaves them out as there are no f registers
+    pub fn set_zfinx(&mut self, zfinx: bool) {
+        self.zfinx = zfinx;
+        self.update_observed();
+    }
+
     pub fn tick(&mut self, memory: &mut dyn Memory) -> Result<(), Trap> {
+        if !self.observed {
+            self.pc = self.wrap_address(self.pc);
+            return self.retire::<false>(memory, None).map(|_| ());
+        }
+        self.advance(memory, None).map(|_| ())
+    }
+
+    // has to be called whenever anything advance or retire only looks at for the sake of
+    // something other than running the instruction might have changed
+    pub(crate) fn update_observed(&mut self) {
+        self.observed = self.tracer.0.is_some() || self.cache_model.is_some() || self.cost_model.is_some() || self.gas.is_some() ||
+            self.pc_history.is_some() || self.call_stack.is_some() || self.profile.is_some() || self.coverage.is_some() ||
+            self.branch_stats.is_some() || self.fflags_provenance.is_some() || self.counting_events ||
+            self.pointer_masking.fetch_bits != 0 || self.extensions != Extensions::all() || self.zfinx;
+    }
+
+    // Executes the next instruction and returns its word and name, noting the loads and stores
+    // it makes in accesses when given.
+    fn advance(&mut self, memory: &mut dyn Memory, accesses: Option<&RefCell<Vec<MemoryAccess>>>) -> Result<(u32, &'static str), Trap> {
+        if !self.observed {
+            self.pc = self.wrap_address(self.pc);
+            return self.retire::<false>(memory, accesses).map(|(word, name, _)| (word, name));
+        }
+        // a tagged pc is cleaned up before use so that code only ever runs from one address
+        self.pc = self.pointer_masking.apply(self.pointer_masking.fetch_bits, self.wrap_address(self.pc));
+        let instruction_address = self.pc;
+        let traced = match &self.tracer.0 {
+            Some(tracer) => self.trace_filter.as_ref().is_none_or(|f| f.matches(instruction_address)).then(|| tracer.wants_effects()),
+            None => None
+        };
+        let modelled = self.cache_model.is_some();
+        if traced.is_none() && !modelled && self.cost_model.is_none() {
+            return self.retire::<true>(memory, accesses).map(|(word, name, _)| (word, name));
+        }
+
+        // the instruction is watched as if stepped when what it did is wanted
+        let effects = traced == Some(true) || modelled;
+        let before = (traced == Some(true)).then_some((self.x, self.f));
+        let own = RefCell::new(Vec::new());
+        let accesses = accesses.or(effects.then_some(&own));
+        let seen = accesses.map_or(0, |accesses| accesses.borrow().len());
+        // read before the instruction can write over itself
+        let encoding = match traced {
+            Some(_) => memory.read_u32(instruction_address).map_or(0, |bits| if bits & 3 == 3 { bits } else { bits & 0xffff }),
+            None => 0
+        };
+        let result = self.retire::<true>(memory, accesses);
+        let (written, accessed) = match (&result, accesses) {
+            (Ok(_), Some(accesses)) if effects => {
+                let written = before.map_or(Vec::new(), |(x, f)| self.written_registers(&x, &f));
+                (written, accesses.borrow()[seen..].to_vec())
+            },
+            _ => (Vec::new(), Vec::new())
+        };
+
+        let mut misses = 0;
+        if let Some(model) = &mut self.cache_model {
+            let before = model.misses();
+            model.fetch(instruction_address);
+            for access in &accessed {
+                model.access(access.address, access.size);
+            }
+            misses = model.misses() - before;
+        }
+        if let (Ok((word, _, fallthrough)), Some(costs)) = (&result, &self.cost_model) {
+            let cycles = costs.cycles(*word, *fallthrough != self.pc, misses);
+            self.csr[CSR_MCYCLE_ADDRESS as usize] = self.csr[CSR_MCYCLE_ADDRESS as usize].wrapping_add(cycles);
+        }
+        if let (Some(tracer), Some(effects)) = (&mut self.tracer.0, traced) {
+            match &result {
+                Ok((word, name, _)) => {
+                    tracer.on_retire(&TraceEvent { pc: instruction_address, word: *word, encoding, name });
+                    for register in &written {
+                        tracer.on_register_write(instruction_address, register);
+                    }
+                    for access in accessed.iter().filter(|_| effects) {
+                        tracer.on_memory_access(instruction_address, access);
+                    }
+                },
+                Err(trap) => tracer.on_trap(instruction_address, trap)
+            }
+        }
+        result.map(|(word, name, _)| (word, name))
+    }
+
+    // Executes the next instruction like advance, returning where it would have fallen through
+    // to as well. OBSERVED is whether anything watches the hart, a hart that nobody watches runs
+    // the same instruction without paying for any of them.
+    #[inline(always)]
+    fn retire<const OBSERVED: bool>(&mut self, memory: &mut dyn Memory, accesses: Option<&RefCell<Vec<MemoryAccess>>>) -> Result<(u32, &'static str, usize), Trap> {
+        let instruction_address = self.pc;
+        let mut decoded = None;
+        let (word, instruction, op, custom) = match self.next_instruction::<OBSERVED>(memory, &mut decoded) {
+            Ok(next) => next,
+            Err(trap) => {
+                self.started(instruction_address);
+                return Err(trap);
+            }
+        };
+
+        match OBSERVED {
+            true => self.begin_observed(instruction.name, instruction_address)?,
+            false => self.csr[CSR_TIME_ADDRESS as usize] = self.csr[CSR_TIME_ADDRESS as usize].wrapping_add(1)
+        }
+
+        let fallthrough = self.pc;
+        let result = match (op, self.engine) {
+            // none of them touch memory
+            (Some(op), _) => {
+                self.perform(op, instruction_address);
+                Ok(())
+            },
+            // whatever the instruction stores to can no longer be trusted to hold the code
+            // that was decoded there, there is no telling what a custom one might store to
+            (None, Engine::Cached) if custom || may_store(word) => self.execute_watched(instruction, memory, accesses, word, instruction_address),
+            _ if OBSERVED || accesses.is_some() => self.execute_recording(instruction, memory, accesses, word, instruction_address),
+            _ => self.operate(instruction, memory, word, instruction_address)
+        };
+        self.x[0] = 0; // make sure x0 is still zero!
+        if result.is_ok() {
+            self.csr[CSR_MINSTRET_ADDRESS as usize] = self.csr[CSR_MINSTRET_ADDRESS as usize].wrapping_add(1);
+            match OBSERVED {
+                true => self.observe_retired(word, instruction.name, instruction_address, fallthrough),
+                false => self.csr[CSR_MCYCLE_ADDRESS as usize] = self.csr[CSR_MCYCLE_ADDRESS as usize].wrapping_add(1)
+            }
+        }
+        result.map(|_| (word, instruction.name, fallthrough))
+    }
+
+    // The instruction at pc, its word and the op a caching engine resolved it to, with pc moved
+    // past it and whether a custom decoder, which leaves what it decodes in custom, supplied it.
+    // An instruction from an extension that has been turned off is as good as undecodable, and
+    // the custom decoders get a go at any full sized word that is.
+    #[inline(always)]
+    fn next_instruction<'a, const OBSERVED: bool>(&mut self, memory: &dyn Memory, custom: &'a mut Option<Instruction>) -> Result<(u32, &'a Instruction, Option<Op>, bool), Trap> {
         let instruction_address = self.pc;
+        let cached = match self.engine {
+            Engine::Interpreter => None,
+            _ => self.cached_entry(memory).map(|entry| (entry.word, entry.instruction, entry.op, entry.length))
+        };
+        let (word, instruction, op) = match cached {
+            Some((word, instruction, op, length)) => {
+                self.pc += length as usize;
+                (word, Some(instruction), op)
+            },
+            None => {
+                let word = self.fetch(memory)?;
+                (word, Cpu::decode_for(word, self.xlen), None)
+            }
+        };
+        let compressed = self.pc - instruction_address == 2;
+        match instruction.filter(|_| !OBSERVED || self.extensions.allows(word, compressed)) {
+            Some(instruction) => Ok((word, instruction, op, false)),
+            None => match (!compressed).then(|| self.decoders.decode(word, self.xlen)).flatten() {
+                Some(instruction) => Ok((word, custom.insert(instruction), None, true)),
+                None => Err(Trap { trap_type: TrapType::IllegalInstruction, value: word as u64 })
+            }
+        }
+    }
+
+    // an instruction is paid for before anything else happens, so one there is no gas for has
+    // not happened at all
+    #[inline(never)]
+    fn begin_observed(&mut self, name: &'static str, address: usize) -> Result<(), Trap> {
+        if let Some(gas) = &mut self.gas {
+            let cost = gas.table.cost(name);
+            if gas.remaining < cost {
+                self.pc = address;
+                return Err(Trap { trap_type: TrapType::OutOfGas, value: cost });
+            }
+            gas.remaining -= cost;
+        }
+        self.started(address);
+        Ok(())
+    }
+
+    // whatever the instruction stores to can no longer be trusted to hold the code that was
+    // decoded there
+    #[inline(never)]
+    fn execute_watched(&mut self, instruction: &Instruction, memory: &mut dyn Memory, accesses: Option<&RefCell<Vec<MemoryAccess>>>, word: u32, address: usize) -> Result<(), Trap> {
+        let mut watched = Watched { inner: memory, written: None };
+        let result = self.execute_recording(instruction, &mut watched, accesses, word, address);
+        if let Some((low, high)) = watched.written {
+            self.decoded.invalidate_range(low, high);
+        }
+        result
+    }
+
+    // read where the cache keeps it, as copying an entry out costs more than running most of them
+    #[inline(always)]
+    fn cached_entry(&mut self, memory: &dyn Memory) -> Option<&engine::Entry> {
+        match self.engine {
+            Engine::Block => self.blocks.next(self.pc, memory, self.xlen),
+            Engine::Cached => self.decoded.next(self.pc, memory, self.xlen),
+            Engine::Interpreter => None
+        }
+    }
+
+    // tells whatever watches the hart about an instruction that has just retired
+    #[inline(never)]
+    fn observe_retired(&mut self, word: u32, name: &'static str, address: usize, fallthrough: usize) {
+        let taken = fallthrough != self.pc;
+        // without a cost model every instruction takes a cycle
+        if self.cost_model.is_none() {
+            self.csr[CSR_MCYCLE_ADDRESS as usize] = self.csr[CSR_MCYCLE_ADDRESS as usize].wrapping_add(1);
+        }
+        if self.counting_events {
+            self.count_events(word, taken);
+        }
+        if let Some(history) = &mut self.pc_history {
+            history.moved(fallthrough, self.pc);
+        }
+        if let Some(stack) = &mut self.call_stack {
+            stack.observe(word, fallthrough, self.pc);
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.count(word, name, fallthrough - address == 2, taken);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            // branches end a block whether or not they are taken
+            coverage.retired(address, fallthrough, taken || matches!(word & 0x7f, 0b1100011 | 0b1101111 | 0b1100111));
+        }
+        if word & 0x7f == 0b1100011 {
+            self.record_branch(address, taken);
+        }
+    }
+
+    fn started(&mut self, pc: usize) {
         self.csr[CSR_TIME_ADDRESS as usize] = self.csr[CSR_TIME_ADDRESS as usize].wrapping_add(1);
+        if let Some(history) = &mut self.pc_history {
+            history.push(pc);
+        }
+    }
 
-        let word = self.fetch(memory)?;
-        if let Some(instruction) = Cpu::decode(word) {
-            let result = (instruction.operation)(self, memory, word, instruction_address);
-            self.x[0] = 0; // make sure x0 is still zero!
+    fn execute(&mut self, instruction: &Instruction, memory: &mut dyn Memory, word: u32, address: usize) -> Result<(), Trap> {
+        match self.fflags_provenance.is_some() && Cpu::is_fp_computational(word) {
+            true => self.execute_tracking_fflags(instruction, memory, word, address),
+            false => self.operate(instruction, memory, word, address)
+        }
+    }
 
-            result
-        } else {
-            Err(Trap { trap_type: TrapType::IllegalInstruction, value: word as u64 })
+    fn execute_recording(&mut self, instruction: &Instruction, memory: &mut dyn Memory, accesses: Option<&RefCell<Vec<MemoryAccess>>>, word: u32, address: usize) -> Result<(), Trap> {
+        match accesses {
+            Some(accesses) => self.execute(instruction, &mut Recording { inner: memory, accesses }, word, address),
+            None => self.execute(instruction, memory, word, address)
         }
     }
 
+    #[inline(always)]
+    fn operate(&mut self, instruction: &Instruction, memory: &mut dyn Memory, word: u32, address: usize) -> Result<(), Trap> {
+        if self.zfinx && matches!(extensions::required(word), Some('F' | 'D' | 'Q')) {
+            return self.execute_in_x(instruction, memory, word, address);
+        }
+        let result = (instruction.operation)(self, memory, word, address);
+        #[cfg(feature = "q")]
+        if result.is_ok() {
+            self.box_narrower(word);
+        }
+        result
+    }
+
+    fn is_fp_computational(word: u32) -> bool {
+        matches!(word & 0x7f, 0b1010011 | 0b1000011 | 0b1000111 | 0b1001011 | 0b1001111)
+    }
+
+    // run the instruction with the accrued flags cleared so that we can see exactly which bits it
+    // raised, even ones that were already set by an earlier instruction
+    fn execute_tracking_fflags(&mut self, instruction: &Instruction, memory: &mut dyn Memory, word: u32, address: usize) -> Result<(), Trap> {
+        let before = self.read_fflags() & 0x1f;
+        self.write_fflags(0);
+        let result = self.operate(instruction, memory, word, address);
+        let raised = self.read_fflags() & 0x1f;
+        self.write_fflags(before | raised);
+
+        if raised != 0 {
+            if let Some(provenance) = &mut self.fflags_provenance {
+                provenance.record(address, raised);
+            }
+        }
+
+        result
+    }
+
     pub fn get_f32(&mut self, reg: usize) -> f32 {
         // only consider the bottom 32 bits of the register
         f32::from_bits(self.f[reg].to_bits() as u32)
@@ -288,7 +1183,71 @@ impl Cpu {
         self.f[reg] = f64::from_bits(0xffffffff00000000 | f.to_bits() as u64);
     }
 
+    #[cfg(feature = "q")]
+    pub fn get_f128(&self, reg: usize) -> u128 {
+        (self.f_high[reg] as u128) << 64 | self.f[reg].to_bits() as u128
+    }
+
+    #[cfg(feature = "q")]
+    pub fn set_f128(&mut self, reg: usize, bits: u128) {
+        self.f[reg] = f64::from_bits(bits as u64);
+        self.f_high[reg] = (bits >> 64) as u64;
+    }
+
+    // NaN boxes what an F or D instruction wrote to an f register, which it only did to the low half
+    #[cfg(feature = "q")]
+    fn box_narrower(&mut self, word: u32) {
+        let narrower = match word & 0x7f {
+            0b0000111 => (word >> 12) & 7 != 0b100,
+            0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => (word >> 25) & 3 != 3,
+            // leaving out those with an x register as rd
+            0b1010011 => (word >> 25) & 3 != 3 && !matches!(word >> 27, 0b10100 | 0b11000 | 0b11100),
+            // vfmv.f.s
+            0b1010111 => (word >> 12) & 7 == 0b001 && word >> 26 == 0b010000,
+            _ => false
+        };
+        if narrower {
+            self.f_high[((word >> 7) & 0x1f) as usize] = u64::MAX;
+        }
+    }
+
+    // the rounding mode in the instruction, with dynamic meaning the one in frm
+    #[cfg(any(feature = "zfa", feature = "q"))]
+    pub(crate) fn rounding_mode(&self, word: u32) -> Result<u64, Trap> {
+        match (word >> 12) & 7 {
+            7 => Ok((self.csr[CSR_FCSR_ADDRESS as usize] >> 5) & 7),
+            5 | 6 => Err(Trap { trap_type: TrapType::IllegalInstruction, value: word as u64 }),
+            rm => Ok(rm as u64)
+        }
+    }
+
+    // what word is to a hart of the given width, an RV32 one has none of the instructions that
+    // only make sense with 64 bit registers
+    #[inline(always)]
+    pub fn decode_for(word: u32, xlen: Xlen) -> Option<&'static Instruction> {
+        DecodeTable::get(xlen).decode(word)
+    }
+
+    // The decoding the tables are built from, and what they go back to for the few words that
+    // need more than opcode, funct3, funct7 and rs2 to tell what they are. A decoding that looks
+    // at rd or rs1 has to be one of those DecodeTable::build leaves to the match, its test fails
+    // otherwise.
+    pub(crate) fn decode_matching(word: u32, xlen: Xlen) -> Option<&'static Instruction> {
+        match xlen {
+            Xlen::Bit32 if rv64_only(word) => None,
+            Xlen::Bit64 if rv32_only(word) => None,
+            _ => Cpu::decode_either(word)
+        }
+    }
+
+    // decodes word as an RV64 hart would
     pub fn decode(word: u32) -> Option<&'static Instruction> {
+        Cpu::decode_for(word, Xlen::Bit64)
+    }
+
+    // the encodings of both widths, the few that mean something else in the other are left to
+    // decode_for to weed out
+    fn decode_either(word: u32) -> Option<&'static Instruction> {
         match word & 0x7f {
             0b0110111 => Some(&LUI),
 
@@ -337,6 +1296,45 @@ impl Cpu {
                 0b001 => match word >> 25 {
                     0b0000000 => Some(&SLLI),
                     0b0000001 => Some(&SLLI),
+                    // the unary ops give which one they are in the shift amount
+                    #[cfg(feature = "zbb")]
+                    0b0110000 => match (word >> 20) & 0x1f {
+                        0b00000 => Some(&CLZ),
+                        0b00001 => Some(&CTZ),
+                        0b00010 => Some(&CPOP),
+                        0b00100 => Some(&SEXT_B),
+                        0b00101 => Some(&SEXT_H),
+                        _ => None
+                    },
+                    #[cfg(feature = "zbkb")]
+                    0b0000100 if (word >> 20) & 0x1f == 0b01111 => Some(&ZIP),
+                    #[cfg(feature = "zksh")]
+                    0b0001000 if (word >> 20) & 0x1f == 0b01000 => Some(&SM3P0),
+                    #[cfg(feature = "zksh")]
+                    0b0001000 if (word >> 20) & 0x1f == 0b01001 => Some(&SM3P1),
+                    #[cfg(feature = "zknh")]
+                    0b0001000 => match (word >> 20) & 0x1f {
+                        0b00000 => Some(&SHA256SUM0),
+                        0b00001 => Some(&SHA256SUM1),
+                        0b00010 => Some(&SHA256SIG0),
+                        0b00011 => Some(&SHA256SIG1),
+                        0b00100 => Some(&SHA512SUM0),
+                        0b00101 => Some(&SHA512SUM1),
+                        0b00110 => Some(&SHA512SIG0),
+                        0b00111 => Some(&SHA512SIG1),
+                        _ => None
+                    },
+                    // the key schedule's round number is in the low bits of rs2
+                    #[cfg(any(feature = "zknd", feature = "zkne"))]
+                    0b0011000 if (word >> 24) & 1 == 1 => Some(&AES64KS1I),
+                    #[cfg(feature = "zknd")]
+                    0b0011000 if (word >> 20) & 0x1f == 0 => Some(&AES64IM),
+                    #[cfg(feature = "zbs")]
+                    0b0010100 | 0b0010101 => Some(&BSETI),
+                    #[cfg(feature = "zbs")]
+                    0b0100100 | 0b0100101 => Some(&BCLRI),
+                    #[cfg(feature = "zbs")]
+                    0b0110100 | 0b0110101 => Some(&BINVI),
                     _ => None
                 },
                 0b101 => match word >> 25 {
@@ -344,6 +1342,19 @@ impl Cpu {
                     0b0000001 => Some(&SRLI),
                     0b0100000 => Some(&SRAI),
                     0b0100001 => Some(&SRAI),
+                    #[cfg(any(feature = "zbb", feature = "zbkb"))]
+                    0b0110000 | 0b0110001 => Some(&RORI),
+                    #[cfg(feature = "zbb")]
+                    0b0010100 if (word >> 20) & 0x1f == 0b00111 => Some(&ORC_B),
+                    // RV32 and RV64 each have their own
+                    #[cfg(any(feature = "zbb", feature = "zbkb"))]
+                    0b0110100 | 0b0110101 if (word >> 20) & 0x1f == 0b11000 => Some(&REV8),
+                    #[cfg(feature = "zbkb")]
+                    0b0110100 if (word >> 20) & 0x1f == 0b00111 => Some(&BREV8),
+                    #[cfg(feature = "zbkb")]
+                    0b0000100 if (word >> 20) & 0x1f == 0b01111 => Some(&UNZIP),
+                    #[cfg(feature = "zbs")]
+                    0b0100100 | 0b0100101 => Some(&BEXTI),
                     _ => None
                 },
                 _ => None
@@ -352,44 +1363,121 @@ impl Cpu {
             0b0110011 => match (word >> 12) & 7 {
                 0b000 => match word >> 25 {
                     0b0000000 => Some(&ADD),
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&MUL),
                     0b0100000 => Some(&SUB),
+                    #[cfg(feature = "zkne")]
+                    0b0011001 => Some(&AES64ES),
+                    #[cfg(feature = "zkne")]
+                    0b0011011 => Some(&AES64ESM),
+                    #[cfg(feature = "zknd")]
+                    0b0011101 => Some(&AES64DS),
+                    #[cfg(feature = "zknd")]
+                    0b0011111 => Some(&AES64DSM),
+                    #[cfg(any(feature = "zknd", feature = "zkne"))]
+                    0b0111111 => Some(&AES64KS2),
+                    // the byte the SM4 ones take is in the top two bits
+                    #[cfg(feature = "zksed")]
+                    0b0011000 | 0b0111000 | 0b1011000 | 0b1111000 => Some(&SM4ED),
+                    #[cfg(feature = "zksed")]
+                    0b0011010 | 0b0111010 | 0b1011010 | 0b1111010 => Some(&SM4KS),
                     _ => None
                 },
                 0b001 => match word >> 25 {
                     0b0000000 => Some(&SLL),
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&MULH),
+                    #[cfg(any(feature = "zbb", feature = "zbkb"))]
+                    0b0110000 => Some(&ROL),
+                    #[cfg(feature = "zbs")]
+                    0b0010100 => Some(&BSET),
+                    #[cfg(feature = "zbs")]
+                    0b0100100 => Some(&BCLR),
+                    #[cfg(feature = "zbs")]
+                    0b0110100 => Some(&BINV),
+                    #[cfg(any(feature = "zbc", feature = "zbkc"))]
+                    0b0000101 => Some(&CLMUL),
                     _ => None
                 },
                 0b010 => match word >> 25 {
                     0b0000000 => Some(&SLT),
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&MULHSU),
+                    #[cfg(feature = "zba")]
+                    0b0010000 => Some(&SH1ADD),
+                    #[cfg(feature = "zbc")]
+                    0b0000101 => Some(&CLMULR),
+                    #[cfg(feature = "zbkx")]
+                    0b0010100 => Some(&XPERM4),
                     _ => None
                 },
                 0b011 => match word >> 25 {
                     0b0000000 => Some(&SLTU),
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&MULHU),
+                    #[cfg(any(feature = "zbc", feature = "zbkc"))]
+                    0b0000101 => Some(&CLMULH),
                     _ => None
                 },
                 0b100 => match word >> 25 {
                     0b0000000 => Some(&XOR),
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&DIV),
+                    #[cfg(feature = "zba")]
+                    0b0010000 => Some(&SH2ADD),
+                    #[cfg(any(feature = "zbb", feature = "zbkb"))]
+                    0b0100000 => Some(&XNOR),
+                    #[cfg(feature = "zbb")]
+                    0b0000101 => Some(&MIN),
+                    // RV32's zext.h, RV64 has it in the word sized ops. It is pack with rs2 as zero,
+                    // so with Zbkb pack covers it.
+                    #[cfg(all(feature = "zbb", not(feature = "zbkb")))]
+                    0b0000100 if (word >> 20) & 0x1f == 0 => Some(&ZEXT_H),
+                    #[cfg(feature = "zbkb")]
+                    0b0000100 => Some(&PACK),
+                    #[cfg(feature = "zbkx")]
+                    0b0010100 => Some(&XPERM8),
                     _ => None
                 ,}
                 0b111 => match word >> 25 {
                     0b0000000 => Some(&AND),
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&REMU),
+                    #[cfg(any(feature = "zbb", feature = "zbkb"))]
+                    0b0100000 => Some(&ANDN),
+                    #[cfg(feature = "zbkb")]
+                    0b0000100 => Some(&PACKH),
+                    #[cfg(feature = "zbb")]
+                    0b0000101 => Some(&MAXU),
+                    #[cfg(feature = "zicond")]
+                    0b0000111 => Some(&CZERO_NEZ),
                     _ => None
                 },
                 0b101 => match word >> 25 {
                     0b0000000 => Some(&SRL),
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&DIVU),
                     0b0100000 => Some(&SRA),
+                    #[cfg(feature = "zbb")]
+                    0b0000101 => Some(&MINU),
+                    #[cfg(any(feature = "zbb", feature = "zbkb"))]
+                    0b0110000 => Some(&ROR),
+                    #[cfg(feature = "zbs")]
+                    0b0100100 => Some(&BEXT),
+                    #[cfg(feature = "zicond")]
+                    0b0000111 => Some(&CZERO_EQZ),
                     _ => None
                 },
                 0b110 => match word >> 25 {
                     0b0000000 => Some(&OR),
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&REM),
+                    #[cfg(feature = "zba")]
+                    0b0010000 => Some(&SH3ADD),
+                    #[cfg(any(feature = "zbb", feature = "zbkb"))]
+                    0b0100000 => Some(&ORN),
+                    #[cfg(feature = "zbb")]
+                    0b0000101 => Some(&MAX),
                     _ => None
                 },
                 _ => None
@@ -399,11 +1487,23 @@ impl Cpu {
                 0b000 => Some(&ADDIW),
                 0b001 => match word >> 25 {
                     0b0000000 =>Some(&SLLIW),
+                    // the low bit of funct7 is the top bit of a 6 bit shift amount
+                    #[cfg(feature = "zba")]
+                    0b0000100 | 0b0000101 => Some(&SLLI_UW),
+                    #[cfg(feature = "zbb")]
+                    0b0110000 => match (word >> 20) & 0x1f {
+                        0b00000 => Some(&CLZW),
+                        0b00001 => Some(&CTZW),
+                        0b00010 => Some(&CPOPW),
+                        _ => None
+                    },
                     _ => None
                 },
                 0b101 => match word >> 25 {
                     0b0000000 => Some(&SRLIW),
                     0b0100000 => Some(&SRAIW),
+                    #[cfg(any(feature = "zbb", feature = "zbkb"))]
+                    0b0110000 => Some(&RORIW),
                     _ => None
                 },
                 _ => None
@@ -412,28 +1512,52 @@ impl Cpu {
             0b0111011 => match (word >> 12) & 7 {
                 0b000 => match word >> 25 {
                     0b0000000 => Some(&ADDW),
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&MULW),
                     0b0100000 => Some(&SUBW),
+                    #[cfg(feature = "zba")]
+                    0b0000100 => Some(&ADD_UW),
+                    _ => None
+                },
+                #[cfg(feature = "zba")]
+                0b010 => match word >> 25 {
+                    0b0010000 => Some(&SH1ADD_UW),
                     _ => None
                 },
                 0b001 => match word >> 25 {
                     0b0000000 => Some(&SLLW),
+                    #[cfg(any(feature = "zbb", feature = "zbkb"))]
+                    0b0110000 => Some(&ROLW),
                     _ => None
                 },
                 0b101 => match word >> 25 {
                     0b0000000 => Some(&SRLW),
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&DIVUW),
                     0b0100000 => Some(&SRAW),
+                    #[cfg(any(feature = "zbb", feature = "zbkb"))]
+                    0b0110000 => Some(&RORW),
                     _ => None
                 },
                 0b100 => match word >> 25 {
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&DIVW),
+                    #[cfg(feature = "zba")]
+                    0b0010000 => Some(&SH2ADD_UW),
+                    #[cfg(feature = "zbb")]
+                    0b0000100 if (word >> 20) & 0x1f == 0 => Some(&ZEXT_H),
+                    #[cfg(feature = "zbkb")]
+                    0b0000100 => Some(&PACKW),
                     _ => None
                 },
                 0b110 => match word >> 25 {
+                    #[cfg(feature = "m")]
                     0b0000001 => Some(&REMW),
+                    #[cfg(feature = "zba")]
+                    0b0010000 => Some(&SH3ADD_UW),
                     _ => None
                 },
+                #[cfg(feature = "m")]
                 0b111 => match word >> 25 {
                     0b0000001 => Some(&REMUW),
                     _ => None
@@ -442,30 +1566,48 @@ impl Cpu {
             },
 
             0b0000111 => match (word >> 12) & 7 {
+                #[cfg(feature = "f")]
                 0b010 => Some(&FLW),
+                #[cfg(feature = "d")]
                 0b011 => Some(&FLD),
+                #[cfg(feature = "q")]
+                0b100 => Some(&FLQ),
+                #[cfg(feature = "v")]
+                0b000 | 0b101 | 0b110 | 0b111 => decode_vector_load(word),
                 _ => None
             },
 
             0b0100111 => match (word >> 12) & 7 {
+                #[cfg(feature = "f")]
                 0b010 => Some(&FSW),
+                #[cfg(feature = "d")]
                 0b011 => Some(&FSD),
+                #[cfg(feature = "q")]
+                0b100 => Some(&FSQ),
+                #[cfg(feature = "v")]
+                0b000 | 0b101 | 0b110 | 0b111 => decode_vector_store(word),
                 _ => None
             },
 
+            #[cfg(feature = "f")]
             0b1010011 => match word >> 25 {
                 0b0000000 => Some(&FADD_S),
+                #[cfg(feature = "d")]
                 0b0000001 => Some(&FADD_D),
                 0b0000100 => Some(&FSUB_S),
+                #[cfg(feature = "d")]
                 0b0000101 => Some(&FSUB_D),
                 0b0001000 => Some(&FMUL_S),
+                #[cfg(feature = "d")]
                 0b0001001 => Some(&FMUL_D),
                 0b0001100 => Some(&FDIV_S),
+                #[cfg(feature = "d")]
                 0b0001101 => Some(&FDIV_D),
                 0b0101100 => match (word >> 20) & 31 {
                     0b00000 => Some(&FSQRT_S),
                     _ => None
                 },
+                #[cfg(feature = "d")]
                 0b0101101 => match (word >> 20) & 31 {
                     0b00000 => Some(&FSQRT_D),
                     _ => None
@@ -476,15 +1618,20 @@ impl Cpu {
                     0b010 => Some(&FSGNJX_S),
                     _ => None
                 },
+                #[cfg(feature = "d")]
                 0b0010001 => match (word >> 12) & 3 {
                     0b000 => Some(&FSGNJ_D),
                     0b001 => Some(&FSGNJN_D),
                     0b010 => Some(&FSGNJX_D),
                     _ => None
                 },
-                0b0010100 => match (word >> 12) & 3 {
+                0b0010100 => match (word >> 12) & 7 {
                     0b000 => Some(&FMIN_S),
                     0b001 => Some(&FMAX_S),
+                    #[cfg(feature = "zfa")]
+                    0b010 => Some(&FMINM_S),
+                    #[cfg(feature = "zfa")]
+                    0b011 => Some(&FMAXM_S),
                     _ => None
                 },
                 0b1100000 => match (word >> 20) & 31 {
@@ -501,10 +1648,14 @@ impl Cpu {
                     },
                     _ => None
                 },
-                0b1010000 => match (word >> 12) & 3 {
+                0b1010000 => match (word >> 12) & 7 {
                     0b010 => Some(&FEQ_S),
                     0b001 => Some(&FLT_S),
                     0b000 => Some(&FLE_S),
+                    #[cfg(feature = "zfa")]
+                    0b100 => Some(&FLEQ_S),
+                    #[cfg(feature = "zfa")]
+                    0b101 => Some(&FLTQ_S),
                     _ => None
                 },
                 0b1111000 => match (word >> 20) & 31 {
@@ -513,6 +1664,8 @@ impl Cpu {
                         0b001 => Some(&UNIMPLEMENTED), // FCLASS_S
                         _ => None
                     },
+                    #[cfg(feature = "zfa")]
+                    0b00001 if (word >> 12) & 7 == 0 => Some(&FLI_S),
                     _ => None
                 },
                 0b1101000 => match (word >> 20) & 31 {
@@ -523,35 +1676,61 @@ impl Cpu {
                     _ => None
                 },
                 0b0100000 => match (word >> 20) & 31 {
+                    #[cfg(feature = "d")]
                     0b00001 => Some(&FCVT_S_D),
+                    #[cfg(feature = "q")]
+                    0b00011 => Some(&FCVT_S_Q),
+                    #[cfg(feature = "zfa")]
+                    0b00100 => Some(&FROUND_S),
+                    #[cfg(feature = "zfa")]
+                    0b00101 => Some(&FROUNDNX_S),
                     _ => None
                 },
+                #[cfg(feature = "d")]
                 0b0100001 => match (word >> 20) & 31 {
                     0b00000 => Some(&FCVT_D_S),
+                    #[cfg(feature = "q")]
+                    0b00011 => Some(&FCVT_D_Q),
+                    #[cfg(feature = "zfa")]
+                    0b00100 => Some(&FROUND_D),
+                    #[cfg(feature = "zfa")]
+                    0b00101 => Some(&FROUNDNX_D),
                     _ => None
                 },
 
-                0b1010001 => match (word >> 12) & 3 {
+                #[cfg(feature = "d")]
+                0b1010001 => match (word >> 12) & 7 {
                     0b010 => Some(&FEQ_D),
                     0b001 => Some(&FLT_D),
                     0b000 => Some(&FLE_D),
+                    #[cfg(feature = "zfa")]
+                    0b100 => Some(&FLEQ_D),
+                    #[cfg(feature = "zfa")]
+                    0b101 => Some(&FLTQ_D),
                     _ => None
                 },
+                #[cfg(feature = "d")]
                 0b1110001 => match (word >> 20) & 31 {
                     0b00000 => match (word >> 12) & 3 {
                         0b000 => Some(&FMV_X_D),
                         0b001 => Some(&UNIMPLEMENTED), // FCLASS.D
                         _ => None
                     },
+                    #[cfg(feature = "zfa")]
+                    0b00001 if (word >> 12) & 7 == 0 => Some(&FMVH_X_D),
                     _ => None
                 },
+                #[cfg(feature = "d")]
                 0b1100001 => match (word >> 20) & 31 {
                     0b00000 => Some(&FCVT_W_D),
                     0b00001 => Some(&FCVT_WU_D),
                     0b00010 => Some(&FCVT_L_D),
                     0b00011 => Some(&FCVT_LU_D),
+                    #[cfg(feature = "zfa")]
+                    0b01000 if (word >> 12) & 7 == 0b001 => Some(&FCVTMOD_W_D),
                     _ => None
                 },
+                #[cfg(feature = "d")]
                 0b1101001 => match (word >> 20) & 31 {
                     0b00000 => Some(&FCVT_D_W),
                     0b00001 => Some(&FCVT_D_WU),
@@ -559,54 +1738,140 @@ impl Cpu {
                     0b00011 => Some(&FCVT_D_LU),
                     _ => None
                 },
+                #[cfg(feature = "d")]
                 0b1111001 => match (word >> 20) & 31 {
                     0b00000 => match (word >> 12) & 3 {
                         0b000 => Some(&FMV_D_X),
                         _ => None
                     },
+                    #[cfg(feature = "zfa")]
+                    0b00001 if (word >> 12) & 7 == 0 => Some(&FLI_D),
                     _ => None
                 },
+                #[cfg(all(feature = "d", feature = "zfa"))]
+                0b1011001 if (word >> 12) & 7 == 0 => Some(&FMVP_D_X),
 
-                0b0010101 => match (word >> 12) & 3 {
+                #[cfg(feature = "d")]
+                0b0010101 => match (word >> 12) & 7 {
                     0b000 => Some(&FMIN_D),
                     0b001 => Some(&FMAX_D),
+                    #[cfg(feature = "zfa")]
+                    0b010 => Some(&FMINM_D),
+                    #[cfg(feature = "zfa")]
+                    0b011 => Some(&FMAXM_D),
+                    _ => None
+                },
+
+                #[cfg(feature = "q")]
+                0b0000011 => Some(&FADD_Q),
+                #[cfg(feature = "q")]
+                0b0000111 => Some(&FSUB_Q),
+                #[cfg(feature = "q")]
+                0b0001011 => Some(&FMUL_Q),
+                #[cfg(feature = "q")]
+                0b0001111 => Some(&FDIV_Q),
+                #[cfg(feature = "q")]
+                0b0101111 => match (word >> 20) & 31 {
+                    0b00000 => Some(&FSQRT_Q),
                     _ => None
                 },
+                #[cfg(feature = "q")]
+                0b0010011 => match (word >> 12) & 7 {
+                    0b000 => Some(&FSGNJ_Q),
+                    0b001 => Some(&FSGNJN_Q),
+                    0b010 => Some(&FSGNJX_Q),
+                    _ => None
+                },
+                #[cfg(feature = "q")]
+                0b0010111 => match (word >> 12) & 7 {
+                    0b000 => Some(&FMIN_Q),
+                    0b001 => Some(&FMAX_Q),
+                    _ => None
+                },
+                #[cfg(feature = "q")]
+                0b0100011 => match (word >> 20) & 31 {
+                    0b00000 => Some(&FCVT_Q_S),
+                    0b00001 => Some(&FCVT_Q_D),
+                    _ => None
+                },
+                #[cfg(feature = "q")]
+                0b1010011 => match (word >> 12) & 7 {
+                    0b010 => Some(&FEQ_Q),
+                    0b001 => Some(&FLT_Q),
+                    0b000 => Some(&FLE_Q),
+                    _ => None
+                },
+                #[cfg(feature = "q")]
+                0b1100011 => match (word >> 20) & 31 {
+                    0b00000 => Some(&FCVT_W_Q),
+                    0b00001 => Some(&FCVT_WU_Q),
+                    0b00010 => Some(&FCVT_L_Q),
+                    0b00011 => Some(&FCVT_LU_Q),
+                    _ => None
+                },
+                #[cfg(feature = "q")]
+                0b1101011 => match (word >> 20) & 31 {
+                    0b00000 => Some(&FCVT_Q_W),
+                    0b00001 => Some(&FCVT_Q_WU),
+                    0b00010 => Some(&FCVT_Q_L),
+                    0b00011 => Some(&FCVT_Q_LU),
+                    _ => None
+                },
+                #[cfg(feature = "q")]
+                0b1110011 if (word >> 20) & 31 == 0 && (word >> 12) & 7 == 0b001 => Some(&FCLASS_Q),
 
                 _ => None
             },
 
+            #[cfg(feature = "f")]
             0b1000011 => match (word >> 25) & 3 {
                 0b00 => Some(&FMADD_S),
+                #[cfg(feature = "d")]
                 0b01 => Some(&FMADD_D),
+                #[cfg(feature = "q")]
+                0b11 => Some(&FMADD_Q),
                 _ => None
             },
 
 
+            #[cfg(feature = "f")]
             0b1000111 => match (word >> 25) & 3 {
                 0b00 => Some(&FMSUB_S),
+                #[cfg(feature = "d")]
                 0b01 => Some(&FMSUB_D),
+                #[cfg(feature = "q")]
+                0b11 => Some(&FMSUB_Q),
                 _ => None
             },
 
+            #[cfg(feature = "f")]
             0b1001011 => match (word >> 25) & 3 {
                 0b00 => Some(&FNMSUB_S),
+                #[cfg(feature = "d")]
                 0b01 => Some(&FNMSUB_D),
+                #[cfg(feature = "q")]
+                0b11 => Some(&FNMSUB_Q),
                 _ => None
             },
 
+            #[cfg(feature = "f")]
             0b1001111 => match (word >> 25) & 3 {
                 0b00 => Some(&FNMADD_S),
+                #[cfg(feature = "d")]
                 0b01 => Some(&FNMADD_D),
+                #[cfg(feature = "q")]
+                0b11 => Some(&FNMADD_Q),
                 _ => None
             },
 
             0b0001111 => match (word >> 12) & 7 {
+                0b000 if word == 0x0100000f => Some(&PAUSE),
                 0b000 => Some(&FENCE),
                 0b001 => Some(&FENCE_I),
                 _ => None
             },
 
+            #[cfg(feature = "a")]
             0b0101111 => match (word >> 12) & 7 {
                 0b010 => match word >> 27 {
                     0b00010 => match (word >> 20) & 0x1f {
@@ -623,6 +1888,8 @@ impl Cpu {
                     0b10100 => Some(&AMOMAX_W),
                     0b11000 => Some(&AMOMINU_W),
                     0b11100 => Some(&AMOMAXU_W),
+                    #[cfg(feature = "zacas")]
+                    0b00101 => Some(&AMOCAS_W),
                     _ => None
                 },
                 0b011 => match word >> 27 {
@@ -640,21 +1907,72 @@ impl Cpu {
                     0b10100 => Some(&AMOMAX_D),
                     0b11000 => Some(&AMOMINU_D),
                     0b11100 => Some(&AMOMAXU_D),
+                    #[cfg(feature = "zacas")]
+                    0b00101 => Some(&AMOCAS_D),
+                    _ => None
+                },
+                #[cfg(feature = "zabha")]
+                0b000 => match word >> 27 {
+                    0b00001 => Some(&AMOSWAP_B),
+                    0b00000 => Some(&AMOADD_B),
+                    0b00100 => Some(&AMOXOR_B),
+                    0b01100 => Some(&AMOAND_B),
+                    0b01000 => Some(&AMOOR_B),
+                    0b10000 => Some(&AMOMIN_B),
+                    0b10100 => Some(&AMOMAX_B),
+                    0b11000 => Some(&AMOMINU_B),
+                    0b11100 => Some(&AMOMAXU_B),
+                    #[cfg(feature = "zacas")]
+                    0b00101 => Some(&AMOCAS_B),
+                    _ => None
+                },
+                #[cfg(feature = "zabha")]
+                0b001 => match word >> 27 {
+                    0b00001 => Some(&AMOSWAP_H),
+                    0b00000 => Some(&AMOADD_H),
+                    0b00100 => Some(&AMOXOR_H),
+                    0b01100 => Some(&AMOAND_H),
+                    0b01000 => Some(&AMOOR_H),
+                    0b10000 => Some(&AMOMIN_H),
+                    0b10100 => Some(&AMOMAX_H),
+                    0b11000 => Some(&AMOMINU_H),
+                    0b11100 => Some(&AMOMAXU_H),
+                    #[cfg(feature = "zacas")]
+                    0b00101 => Some(&AMOCAS_H),
+                    _ => None
+                },
+                #[cfg(feature = "zacas")]
+                0b100 => match word >> 27 {
+                    0b00101 => Some(&AMOCAS_Q),
                     _ => None
                 },
                 _ => None
             },
 
+            #[cfg(feature = "v")]
+            0b1010111 => decode_vector(word),
+
+            #[cfg(feature = "p")]
+            0b1110111 => decode_packed(word),
+
             0b1110011 => match (word >> 12) & 7 {
                 0b000 => match word {
                     0b00000000000000000000000001110011 => Some(&ECALL),
                     0b00000000000100000000000001110011 => Some(&EBREAK),
                     0b00110000001000000000000001110011 => Some(&MRET),
+                    #[cfg(feature = "zawrs")]
+                    0b00000000110100000000000001110011 => Some(&WRS_NTO),
+                    #[cfg(feature = "zawrs")]
+                    0b00000001110100000000000001110011 => Some(&WRS_STO),
                     _ => None
                 },
                 0b001 => Some(&CSRRW),
                 0b010 => Some(&CSRRS),
                 0b011 => Some(&CSRRC),
+                #[cfg(feature = "zimop")]
+                0b100 if word & 0xb3c00000 == 0x81c00000 => Some(&MOP_R),
+                #[cfg(feature = "zimop")]
+                0b100 if word & 0xb2000000 == 0x82000000 => Some(&MOP_RR),
                 0b101 => Some(&CSRRWI),
                 0b110 => Some(&CSRRSI),
                 0b111 => Some(&CSRRCI),
@@ -665,7 +1983,14 @@ impl Cpu {
         }
     }
 
+    // expands a compressed instruction as an RV64 hart would
     pub fn uncompress(halfword: u32) -> u32 {
+        Cpu::uncompress_for(halfword, Xlen::Bit64)
+    }
+
+    // a few quadrants mean something else in RV32, C.JAL taking the place of C.ADDIW and the
+    // single precision loads and stores those of C.LD and C.SD
+    pub fn uncompress_for(halfword: u32, xlen: Xlen) -> u32 {
         let op = halfword & 0x3; // [1:0]
         let funct3 = (halfword >> 13) & 0x7; // [15:13]
 
@@ -709,8 +2034,18 @@ impl Cpu {
                             ((halfword << 1) & 0x40); // offset[6] <= [5]
                     return (offset << 20) | ((rs1 + 8) << 15) | (2 << 12) | ((rd + 8) << 7) | 0x3;
                 },
+                3 if xlen == Xlen::Bit32 => {
+                    // C.FLW
+                    // flw rd+8, offset(rs1+8)
+                    let rs1 = (halfword >> 7) & 0x7; // [9:7]
+                    let rd = (halfword >> 2) & 0x7; // [4:2]
+                    let offset =
+                        ((halfword >> 7) & 0x38) | // offset[5:3] <= [12:10]
+                            ((halfword >> 4) & 0x4) | // offset[2] <= [6]
+                            ((halfword << 1) & 0x40); // offset[6] <= [5]
+                    return (offset << 20) | ((rs1 + 8) << 15) | (2 << 12) | ((rd + 8) << 7) | 0x7;
+                },
                 3 => {
-                    // @TODO: Support C.FLW in 32-bit mode
                     // C.LD in 64-bit mode
                     // ld rd+8, offset(rs1+8)
                     let rs1 = (halfword >> 7) & 0x7; // [9:7]
@@ -748,8 +2083,20 @@ impl Cpu {
                     let imm4_0 = offset & 0x1f;
                     return (imm11_5 << 25) | ((rs2 + 8) << 20) | ((rs1 + 8) << 15) | (2 << 12) | (imm4_0 << 7) | 0x23;
                 },
+                7 if xlen == Xlen::Bit32 => {
+                    // C.FSW
+                    // fsw rs2+8, offset(rs1+8)
+                    let rs1 = (halfword >> 7) & 0x7; // [9:7]
+                    let rs2 = (halfword >> 2) & 0x7; // [4:2]
+                    let offset =
+                        ((halfword >> 7) & 0x38) | // offset[5:3] <= [12:10]
+                            ((halfword << 1) & 0x40) | // offset[6] <= [5]
+                            ((halfword >> 4) & 0x4); // offset[2] <= [6]
+                    let imm11_5 = (offset >> 5) & 0x7f;
+                    let imm4_0 = offset & 0x1f;
+                    return (imm11_5 << 25) | ((rs2 + 8) << 20) | ((rs1 + 8) << 15) | (2 << 12) | (imm4_0 << 7) | 0x27;
+                },
                 7 => {
-                    // @TODO: Support C.FSW in 32-bit mode
                     // C.SD
                     // sd rs2+8, offset(rs1+8)
                     let rs1 = (halfword >> 7) & 0x7; // [9:7]
@@ -773,20 +2120,16 @@ impl Cpu {
                         } | // imm[31:6] <= [12]
                             ((halfword >> 7) & 0x20) | // imm[5] <= [12]
                             ((halfword >> 2) & 0x1f); // imm[4:0] <= [6:2]
-                        if r == 0 && imm == 0 {
-                            // C.NOP
-                            // addi x0, x0, 0
-                            return 0x13;
-                        } else if r != 0 {
-                            // C.ADDI
-                            // addi r, r, imm
-                            return (imm << 20) | (r << 15) | (r << 7) | 0x13;
-                        }
-                        // @TODO: Support HINTs
-                        // r == 0 and imm != 0 is HINTs
+                        // C.ADDI
+                        // addi r, r, imm, which with r == 0 is C.NOP or a hint
+                        return (imm << 20) | (r << 15) | (r << 7) | 0x13;
+                    },
+                    1 if xlen == Xlen::Bit32 => {
+                        // C.JAL
+                        // jal x1, imm, laid out just as C.J is
+                        return Cpu::uncompress_for(halfword | 0x8000, xlen) | (1 << 7);
                     },
                     1 => {
-                        // @TODO: Support C.JAL in 32-bit mode
                         // C.ADDIW
                         // addiw r, r, imm
                         let r = (halfword >> 7) & 0x1f;
@@ -811,11 +2154,8 @@ impl Cpu {
                         } | // imm[31:6] <= [12]
                             ((halfword >> 7) & 0x20) | // imm[5] <= [12]
                             ((halfword >> 2) & 0x1f); // imm[4:0] <= [6:2]
-                        if r != 0 {
-                            return (imm << 20) | (r << 7) | 0x13;
-                        }
-                        // @TODO: Support HINTs
-                        // r == 0 is for HINTs
+                        // r == 0 is a hint, which writing x0 makes a no-op
+                        return (imm << 20) | (r << 7) | 0x13;
                     },
                     3 => {
                         let r = (halfword >> 7) & 0x1f; // [11:7]
@@ -836,9 +2176,9 @@ impl Cpu {
                             }
                             // imm == 0 is for reserved instruction
                         }
-                        if r != 0 && r != 2 {
+                        if r != 2 {
                             // C.LUI
-                            // lui r, nzimm
+                            // lui r, nzimm, r == 0 being a hint
                             let nzimm = match halfword & 0x1000 {
                                 0x1000 => 0xfffc0000,
                                 _ => 0
@@ -848,7 +2188,13 @@ impl Cpu {
                             if nzimm != 0 {
                                 return nzimm | (r << 7) | 0x37;
                             }
-                            // nzimm == 0 is for reserved instruction
+                            #[cfg(feature = "zimop")]
+                            if r % 2 == 1 && r < 16 && halfword & 0x1000 == 0 {
+                                // C.MOP.n
+                                // nop
+                                return 0x13;
+                            }
+                            // nzimm == 0 is otherwise reserved
                         }
                     },
                     4 => {
@@ -1048,8 +2394,17 @@ impl Cpu {
                         //}
                         // r == 0 is reseved instruction
                     },
+                    3 if xlen == Xlen::Bit32 => {
+                        // C.FLWSP
+                        // flw rd, offset(x2)
+                        let rd = (halfword >> 7) & 0x1f;
+                        let offset =
+                            ((halfword >> 7) & 0x20) | // offset[5] <= [12]
+                                ((halfword >> 2) & 0x1c) | // offset[4:2] <= [6:4]
+                                ((halfword << 4) & 0xc0); // offset[7:6] <= [3:2]
+                        return (offset << 20) | (2 << 15) | (2 << 12) | (rd << 7) | 0x7;
+                    },
                     3 => {
-                        // @TODO: Support C.FLWSP in 32-bit mode
                         // C.LDSP
                         // ld rd, offset(x2)
                         let rd = (halfword >> 7) & 0x1f;
@@ -1074,14 +2429,11 @@ impl Cpu {
                                     return (rs1 << 15) | 0x67;
                                 }
                                 // rs1 == 0 is reserved instruction
-                                if rs1 != 0 && rs2 != 0 {
+                                if rs2 != 0 {
                                     // C.MV
-                                    // add rs1, x0, rs2
-                                    // println!("C.MV RS1:{:x} RS2:{:x}", rs1, rs2);
+                                    // add rs1, x0, rs2, rs1 == 0 being a hint
                                     return (rs2 << 20) | (rs1 << 7) | 0x33;
                                 }
-                                // rs1 == 0 && rs2 != 0 is Hints
-                                // @TODO: Support Hints
                             },
                             1 => {
                                 if rs1 == 0 && rs2 == 0 {
@@ -1094,13 +2446,11 @@ impl Cpu {
                                     // jalr x1, 0(rs1)
                                     return (rs1 << 15) | (1 << 7) | 0x67;
                                 }
-                                if rs1 != 0 && rs2 != 0 {
+                                if rs2 != 0 {
                                     // C.ADD
-                                    // add rs1, rs1, rs2
+                                    // add rs1, rs1, rs2, rs1 == 0 being a hint
                                     return (rs2 << 20) | (rs1 << 15) | (rs1 << 7) | 0x33;
                                 }
-                                // rs1 == 0 && rs2 != 0 is Hists
-                                // @TODO: Supports Hinsts
                             },
                             _ => {} // Not happens
                         };
@@ -1128,8 +2478,18 @@ impl Cpu {
                         let imm4_0 = offset & 0x1f;
                         return (imm11_5 << 25) | (rs2 << 20) | (2 << 15) | (2 << 12) | (imm4_0 << 7) | 0x23;
                     },
+                    7 if xlen == Xlen::Bit32 => {
+                        // C.FSWSP
+                        // fsw rs2, offset(x2)
+                        let rs2 = (halfword >> 2) & 0x1f; // [6:2]
+                        let offset =
+                            ((halfword >> 7) & 0x3c) | // offset[5:2] <= [12:9]
+                                ((halfword >> 1) & 0xc0); // offset[7:6] <= [8:7]
+                        let imm11_5 = (offset >> 5) & 0x3f;
+                        let imm4_0 = offset & 0x1f;
+                        return (imm11_5 << 25) | (rs2 << 20) | (2 << 15) | (2 << 12) | (imm4_0 << 7) | 0x27;
+                    },
                     7 => {
-                        // @TODO: Support C.FSWSP in 32-bit mode
                         // C.SDSP
                         // sd rs, offset(x2)
                         let rs2 = (halfword >> 2) & 0x1f; // [6:2]
@@ -1148,6 +2508,7 @@ impl Cpu {
         0xffffffff // Return invalid value
     }
 
+    #[doc(hidden)]
     pub fn sign_extend(&self, value: i64) -> i64 {
         match self.xlen {
             Xlen::Bit32 => value as i32 as i64,
@@ -1155,6 +2516,7 @@ impl Cpu {
         }
     }
 
+    #[doc(hidden)]
     pub fn unsigned_data(&self, value: i64) -> u64 {
         match self.xlen {
             Xlen::Bit32 => (value & 0xffffffff) as u64,
@@ -1162,6 +2524,15 @@ impl Cpu {
         }
     }
 
+    // shifts by a register only use as many bits of it as it takes to shift out a whole register
+    pub(crate) fn shift_amount(&self, amount: i64) -> u32 {
+        match self.xlen {
+            Xlen::Bit32 => amount as u32 & 0x1f,
+            Xlen::Bit64 => amount as u32 & 0x3f
+        }
+    }
+
+    #[doc(hidden)]
     pub fn most_negative(&self) -> i64 {
         match self.xlen {
             Xlen::Bit32 => i32::MIN as i64,
@@ -1172,22 +2543,117 @@ impl Cpu {
     pub fn read_csr(&self, address: u16) -> u64 {
         match address {
             // @TODO: Mask should consider of 32-bit mode
-            CSR_FFLAGS_ADDRESS => self.read_fflags(),
+            CSR_FFLAGS_ADDRESS => self.read_fflags() & 0x1f,
             CSR_FRM_ADDRESS => (self.csr[CSR_FCSR_ADDRESS as usize] >> 5) & 0x7,
             CSR_SSTATUS_ADDRESS => self.csr[CSR_MSTATUS_ADDRESS as usize] & 0x80000003000de162,
             CSR_SIE_ADDRESS => self.csr[CSR_MIE_ADDRESS as usize] & 0x222,
             CSR_SIP_ADDRESS => self.csr[CSR_MIP_ADDRESS as usize] & 0x222,
-            CSR_FCSR_ADDRESS => self.csr[CSR_FCSR_ADDRESS as usize] & 0xff,
+            CSR_FCSR_ADDRESS => (self.csr[CSR_FCSR_ADDRESS as usize] & 0xe0) | (self.read_fflags() & 0x1f),
+            // the user mode counters are views of the machine mode ones, the h halves are for
+            // RV32 guests reading them 32 bits at a time
+            CSR_CYCLE_ADDRESS => self.csr[CSR_MCYCLE_ADDRESS as usize],
+            CSR_INSTRET_ADDRESS => self.csr[CSR_MINSTRET_ADDRESS as usize],
+            CSR_CYCLEH_ADDRESS | CSR_MCYCLEH_ADDRESS => self.csr[CSR_MCYCLE_ADDRESS as usize] >> 32,
+            CSR_INSTRETH_ADDRESS | CSR_MINSTRETH_ADDRESS => self.csr[CSR_MINSTRET_ADDRESS as usize] >> 32,
+            CSR_TIMEH_ADDRESS => self.read_csr(CSR_TIME_ADDRESS) >> 32,
+            #[cfg(feature = "v")]
+            vector::CSR_VCSR_ADDRESS => self.csr[vector::CSR_VXRM_ADDRESS as usize] << 1 | self.csr[CSR_VXSAT_ADDRESS as usize],
+            #[cfg(feature = "v")]
+            vector::CSR_VTYPE_ADDRESS => match vector::Vtype::parse(self.csr[address as usize]) {
+                Some(_) => self.csr[address as usize],
+                None => self.vill()
+            },
+            #[cfg(feature = "v")]
+            vector::CSR_VLENB_ADDRESS => self.vlenb() as u64,
+            CSR_MISA_ADDRESS => {
+                let mxl = match self.xlen {
+                    Xlen::Bit32 => 1 << 30,
+                    Xlen::Bit64 => 2 << 62
+                };
+                let extensions = match self.zfinx {
+                    true => self.extensions.without('F'),
+                    false => self.extensions
+                };
+                mxl | extensions.bits() as u64
+            },
+            // hpmcounter3 to 31 and the high halves of them and mhpmcounter3 to 31
+            0xc03..=0xc1f => self.csr[address as usize - 0x100],
+            0xc83..=0xc9f => self.csr[address as usize - 0x180] >> 32,
+            0xb83..=0xb9f => self.csr[address as usize - 0x80] >> 32,
+            CSR_TIME_ADDRESS => match &self.clock {
+                Some(clock) => (clock.monotonic(self.ticks()).as_nanos() * TIMEBASE_FREQUENCY as u128 / 1_000_000_000) as u64,
+                None => self.ticks()
+            },
             _ => self.csr[address as usize]
         }
     }
 
+    // what csr instructions read, the time CSR being an input to record or replay
+    pub(crate) fn read_csr_from_guest(&mut self, address: u16) -> Result<u64, Trap> {
+        if let Some(handler) = self.csr_hooks.handler(address, is_implemented(address, self.xlen)) {
+            return handler.lock().unwrap().read(self, address);
+        }
+        let value = self.read_csr(address);
+        Ok(match address {
+            CSR_TIME_ADDRESS | CSR_TIMEH_ADDRESS => self.replay_time(value),
+            _ => value
+        })
+    }
+
+    pub(crate) fn write_csr_from_guest(&mut self, address: u16, value: u64) -> Result<(), Trap> {
+        match self.csr_hooks.handler(address, is_implemented(address, self.xlen)) {
+            Some(handler) => handler.lock().unwrap().write(self, address, value),
+            None => {
+                self.write_csr(address, value);
+                Ok(())
+            }
+        }
+    }
+
     pub fn write_csr(&mut self, address: u16, value: u64) {
         match address {
             CSR_FFLAGS_ADDRESS => self.write_fflags(value),
-            CSR_FRM_ADDRESS => {
-                self.csr[CSR_FCSR_ADDRESS as usize] &= !0xe0;
-                self.csr[CSR_FCSR_ADDRESS as usize] |= (value << 5) & 0xe0;
+            // WARL fields keep their last legal value, or drop the bits that are reserved
+            CSR_FRM_ADDRESS => self.write_frm(value),
+            CSR_FCSR_ADDRESS => {
+                self.write_frm(value >> 5);
+                self.write_fflags(value);
+            },
+            #[cfg(feature = "v")]
+            vector::CSR_VSTART_ADDRESS => self.csr[address as usize] = value & (self.vlen() as u64 - 1),
+            #[cfg(any(feature = "v", feature = "p"))]
+            CSR_VXSAT_ADDRESS => self.csr[address as usize] = value & 1,
+            #[cfg(feature = "v")]
+            vector::CSR_VXRM_ADDRESS => self.csr[address as usize] = value & 3,
+            #[cfg(feature = "v")]
+            vector::CSR_VCSR_ADDRESS => {
+                self.csr[vector::CSR_VXRM_ADDRESS as usize] = (value >> 1) & 3;
+                self.csr[CSR_VXSAT_ADDRESS as usize] = value & 1;
+            },
+            #[cfg(feature = "v")]
+            vector::CSR_VLENB_ADDRESS => {},
+            CSR_STVEC_ADDRESS | CSR_MTVEC_ADDRESS => {
+                // only the direct and vectored modes exist
+                self.csr[address as usize] = value & !2;
+            },
+            CSR_SATP_ADDRESS => {
+                // only bare mode is supported, and a write of any other mode has no effect
+                let mode = match self.xlen {
+                    Xlen::Bit32 => value >> 31,
+                    Xlen::Bit64 => value >> 60
+                };
+                if mode == 0 {
+                    self.csr[address as usize] = value;
+                }
+            },
+            // the extensions are fixed once the hart is built, see set_extensions
+            CSR_MISA_ADDRESS => {},
+            CSR_MEDELEG_ADDRESS => {
+                // ecalls from machine mode cannot be delegated
+                self.csr[address as usize] = value & !(1 << 11);
+            },
+            CSR_SCOUNTEREN_ADDRESS | CSR_MCOUNTEREN_ADDRESS => {
+                self.csr[address as usize] = value & 0xffff_ffff;
             },
             CSR_SSTATUS_ADDRESS => {
                 self.csr[CSR_MSTATUS_ADDRESS as usize] &= !0x80000003000de162;
@@ -1204,25 +2670,67 @@ impl Cpu {
             CSR_MIDELEG_ADDRESS => {
                 self.csr[address as usize] = value & 0x666; // from qemu
             },
+            // read only, and a read through a clock would not give back the tick count anyway
+            CSR_TIME_ADDRESS => {},
+            // the user mode counters are read only, writes have to go to the machine mode ones
+            CSR_CYCLE_ADDRESS | CSR_INSTRET_ADDRESS | CSR_CYCLEH_ADDRESS | CSR_TIMEH_ADDRESS | CSR_INSTRETH_ADDRESS | 0xc03..=0xc1f | 0xc83..=0xc9f => {},
+            // mhpmevent3 to 31
+            0x323..=0x33f => {
+                self.csr[address as usize] = value;
+                self.update_event_counting();
+            },
+            CSR_MCYCLEH_ADDRESS | CSR_MINSTRETH_ADDRESS | 0xb83..=0xb9f => {
+                let counter = &mut self.csr[(address - 0x80) as usize];
+                *counter = (*counter & 0xffff_ffff) | (value << 32);
+            },
             _ => {
                 self.csr[address as usize] = value;
             }
         };
     }
 
+    // rounding modes 5 and 6 are reserved and 7 only means anything in an instruction
+    fn write_frm(&mut self, value: u64) {
+        let frm = value & 7;
+        if frm <= 4 {
+            self.csr[CSR_FCSR_ADDRESS as usize] &= !0xe0;
+            self.csr[CSR_FCSR_ADDRESS as usize] |= frm << 5;
+        }
+    }
+
+    // Traps as an illegal instruction when a csr instruction would access a CSR that does not
+    // exist, one neither the hart nor a handler serves, or write one that is read only, as those
+    // whose address starts 0b11 are. The instructions only count as writing when they have a
+    // source, so csrr of a read only CSR is fine.
+    pub(crate) fn check_csr(&self, address: u16, write: bool, word: u32) -> Result<(), Trap> {
+        let exists = match address {
+            CSR_FFLAGS_ADDRESS..=CSR_FCSR_ADDRESS if !self.extensions.contains('F') => false,
+            0x008 | 0x00a | 0x00f | 0xc20..=0xc22 if !self.extensions.contains('V') => false,
+            0x009 if !self.extensions.contains('V') && !self.extensions.contains('P') => false,
+            _ => is_implemented(address, self.xlen)
+        } || self.csr_hooks.serves(address);
+        match exists && !(write && address >> 10 == 3) {
+            true => Ok(()),
+            false => Err(Trap { trap_type: TrapType::IllegalInstruction, value: word as u64 })
+        }
+    }
+
     pub fn set_fcsr_nx(&mut self) {
-        let flags = self.read_fflags();
-        self.write_fflags(flags | 1);
+        self.raise_fflags(FFLAG_NX);
     }
 
     pub fn set_fcsr_dz(&mut self) {
-        let flags = self.read_fflags();
-        self.write_fflags(flags | 8);
+        self.raise_fflags(FFLAG_DZ);
     }
 
     pub fn set_fcsr_nv(&mut self) {
-        let flags = self.read_fflags();
-        self.write_fflags(flags | 16);
+        self.raise_fflags(FFLAG_NV);
+    }
+
+    // every explicitly raised exception flag goes through here
+    pub fn raise_fflags(&mut self, flags: u64) {
+        let current = self.read_fflags();
+        self.write_fflags(current | flags);
     }
 
     #[cfg(target_arch = "x86_64")]
@@ -1277,7 +2785,7 @@ impl Cpu {
             flags = flags | _MM_EXCEPT_INEXACT;
         }
         if value & 2 == 2 {
-            flags = flags | _MM_EXCEPT_OVERFLOW;
+            flags = flags | _MM_EXCEPT_UNDERFLOW;
         }
         if value & 4 == 4 {
             flags = flags | _MM_EXCEPT_OVERFLOW;
@@ -1299,8 +2807,163 @@ impl Cpu {
     }
 }
 
+pub struct CpuBuilder {
+    xlen: Xlen,
+    pc: usize,
+    stack_pointer: Option<usize>,
+    ecall_handlers: EcallHandlers,
+    ebreak_handler: Option<Instruction>,
+    decoders: CustomDecoders,
+    pointer_masking: PointerMasking,
+    engine: Engine,
+    clock: Option<Arc<dyn Clock>>,
+    pc_history: usize,
+    extensions: Extensions,
+    wake: Option<WakeHandle>,
+    yield_on_pause: bool,
+    zfinx: bool,
+    #[cfg(feature = "v")]
+    vlen: usize
+}
+
+impl Default for CpuBuilder {
+    fn default() -> Self {
+        CpuBuilder::new()
+    }
+}
+
+impl CpuBuilder {
+    pub fn new() -> Self {
+        CpuBuilder {
+            xlen: Xlen::Bit64,
+            pc: 0,
+            stack_pointer: None,
+            ecall_handlers: EcallHandlers::new(),
+            ebreak_handler: None,
+            decoders: CustomDecoders::new(),
+            pointer_masking: PointerMasking::default(),
+            engine: Engine::Interpreter,
+            clock: None,
+            pc_history: 0,
+            extensions: Extensions::all(),
+            wake: None,
+            yield_on_pause: false,
+            zfinx: false,
+            #[cfg(feature = "v")]
+            vlen: vector::DEFAULT_VLEN
+        }
+    }
+
+    pub fn xlen(mut self, xlen: Xlen) -> Self {
+        self.xlen = xlen;
+        self
+    }
+
+    pub fn pc(mut self, pc: usize) -> Self {
+        self.pc = pc;
+        self
+    }
+
+    pub fn stack_pointer(mut self, stack_pointer: usize) -> Self {
+        self.stack_pointer = Some(stack_pointer);
+        self
+    }
+
+    pub fn ecall_fallback(mut self, handler: Instruction) -> Self {
+        self.ecall_handlers.set_fallback(Some(handler));
+        self
+    }
+
+    pub fn ecall_handler(mut self, number: i64, handler: Instruction) -> Self {
+        self.ecall_handlers.register(number, handler);
+        self
+    }
+
+    pub fn ebreak_handler(mut self, handler: Instruction) -> Self {
+        self.ebreak_handler = Some(handler);
+        self
+    }
+
+    pub fn decoder(mut self, opcode: u32, decoder: CustomDecoder) -> Self {
+        self.decoders.register(opcode, decoder);
+        self
+    }
+
+    pub fn pointer_masking(mut self, masking: PointerMasking) -> Self {
+        self.pointer_masking = masking;
+        self
+    }
+
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    pub fn pc_history(mut self, capacity: usize) -> Self {
+        self.pc_history = capacity;
+        self
+    }
+
+    pub fn extensions(mut self, extensions: Extensions) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    pub fn wake_handle(mut self, wake: WakeHandle) -> Self {
+        self.wake = Some(wake);
+        self
+    }
+
+    pub fn yield_on_pause(mut self, yield_on_pause: bool) -> Self {
+        self.yield_on_pause = yield_on_pause;
+        self
+    }
+
+    pub fn zfinx(mut self, zfinx: bool) -> Self {
+        self.zfinx = zfinx;
+        self
+    }
+
+    // the width of each vector register in bits, a power of two from 64 to 65536
+    #[cfg(feature = "v")]
+    pub fn vlen(mut self, vlen: usize) -> Self {
+        self.vlen = vlen;
+        self
+    }
+
+    pub fn build(self) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.xlen = self.xlen;
+        cpu.update_pc(self.pc);
+        if let Some(stack_pointer) = self.stack_pointer {
+            cpu.update_stack_pointer(stack_pointer);
+        }
+        cpu.ecall_handlers = self.ecall_handlers;
+        cpu.set_ebreak_handler(self.ebreak_handler);
+        cpu.decoders = self.decoders;
+        cpu.set_pointer_masking(self.pointer_masking);
+        cpu.set_engine(self.engine);
+        cpu.set_clock(self.clock);
+        cpu.set_pc_history(self.pc_history);
+        cpu.set_extensions(self.extensions);
+        cpu.set_wake_handle(self.wake);
+        cpu.set_yield_on_pause(self.yield_on_pause);
+        cpu.set_zfinx(self.zfinx);
+        #[cfg(feature = "v")]
+        cpu.set_vlen(self.vlen);
+
+        cpu
+    }
+}
 
-pub const UNIMPLEMENTED: Instruction = Instruction {
+// stands in for fclass, which is not implemented yet
+#[cfg(feature = "f")]
+pub(crate) const UNIMPLEMENTED: Instruction = Instruction {
     name: "UNIMP",
     operation: |_cpu, _memory, word, _address| {
         Err(Trap{
@@ -1329,10 +2992,30 @@ const MRET: Instruction = Instruction {
 };
 
 #[cfg(test)]
-mod test_cpu {
+pub(crate) mod test_cpu {
     use super::*;
 
+    // words as the bytes of a program loaded at address 0
+    pub(crate) fn program(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    // A hart of xlen that started with a0 and a1 and has run each of words in turn. Only the
+    // extension tests use it, and a build can leave all of those out.
+    #[allow(dead_code)]
+    pub(crate) fn run(words: &[u32], xlen: Xlen, a0: i64, a1: i64) -> Cpu {
+        let mut memory = program(words);
+        let mut cpu = Cpu::builder().xlen(xlen).build();
+        cpu.x[10] = a0;
+        cpu.x[11] = a1;
+        for _ in 0..words.len() {
+            cpu.tick(&mut memory).unwrap();
+        }
+        cpu
+    }
+
     #[test]
+    #[cfg(feature = "c")]
     fn babys_first_instruction() {
         let mut cpu = Cpu::new();
         let mut instruction: Vec<u8> = vec![
@@ -1349,6 +3032,7 @@ mod test_cpu {
     }
 
     #[test]
+    #[cfg(feature = "c")]
     fn two_compressed_instruction() {
         let mut cpu = Cpu::new();
         let mut memory: Vec<u8> = vec![
@@ -1369,6 +3053,418 @@ mod test_cpu {
     }
 
     #[test]
+    #[cfg(feature = "c")]
+    fn hints_are_no_ops() {
+        // c.li zero, 5; c.add zero, a0; c.mv zero, a0; c.lui zero, 1; c.addi zero, 1
+        let mut memory: Vec<u8> = [0x4015u16, 0x902a, 0x802a, 0x6005, 0x0005].iter().flat_map(|h| h.to_le_bytes()).collect();
+        // pause; fence 0, rw; addi zero, zero, 1
+        memory.extend([0x0100000fu32, 0x0030000f, 0x00100013].iter().flat_map(|w| w.to_le_bytes()));
+        let mut cpu = Cpu::builder().yield_on_pause(true).build();
+        cpu.x[10] = 3;
+        for _ in 0..8 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!(22, cpu.get_pc());
+        assert_eq!(0, cpu.x[0]);
+        assert_eq!("PAUSE", Cpu::decode(0x0100000f).unwrap().name);
+    }
+
+    #[test]
+    fn counters_count_cycles_time_and_retired_instructions() {
+        // rdcycle a0; rdinstret a1; rdtime a2; then an illegal instruction; rdinstret a3; then
+        // rdinstreth a4, which RV64 does not have
+        let words = [0xc0002573u32, 0xc02025f3, 0xc0102673, 0, 0xc02026f3, 0xc8202773];
+        let mut memory: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let mut cpu = Cpu::new();
+        cpu.write_csr(0xb82, 1);
+
+        for _ in 0..3 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert!(cpu.tick(&mut memory).is_err());
+        cpu.update_pc(16);
+        cpu.tick(&mut memory).unwrap();
+        assert!(cpu.tick(&mut memory).is_err());
+        // time counts every instruction started, including the one reading it, and the traps
+        // did not retire
+        assert_eq!([0, 1 << 32 | 1, 3], [cpu.x[10], cpu.x[11], cpu.x[12]]);
+        assert_eq!(1 << 32 | 3, cpu.x[13]);
+        assert_eq!(6, cpu.ticks());
+        assert_eq!(1 << 32 | 4, cpu.read_csr(0xc02));
+        assert_eq!(4, cpu.read_csr(0xc00));
+    }
+
+    #[test]
+    #[cfg(feature = "f")]
+    fn csr_accesses_are_checked_and_warl_fields_keep_legal_values() {
+        // csrw cycle, a0; csrr a0, 0x7c0; csrwi frm, 3; csrwi frm, 5; csrr a1, fcsr
+        let words = [0xc0051073u32, 0x7c002573, 0x0021d073, 0x0022d073, 0x003025f3];
+        let mut memory: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let mut cpu = Cpu::new();
+
+        // writing a read only CSR and touching one that does not exist are both illegal
+        for (pc, word) in [(0, words[0]), (4, words[1])] {
+            cpu.update_pc(pc);
+            let trap = cpu.tick(&mut memory).unwrap_err();
+            assert_eq!((TrapType::IllegalInstruction, word as u64), (trap.trap_type, trap.value));
+        }
+        cpu.update_pc(8);
+        for _ in 0..3 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        // rounding mode 5 is reserved so frm keeps the 3 it had
+        assert_eq!(3, cpu.x[11] >> 5);
+
+        cpu.write_csr(CSR_SATP_ADDRESS, 8 << 60);
+        cpu.write_csr(CSR_MTVEC_ADDRESS, 0x1003);
+        assert_eq!([0, 0x1001], [cpu.read_csr(CSR_SATP_ADDRESS), cpu.read_csr(CSR_MTVEC_ADDRESS)]);
+    }
+
+    #[test]
+    fn upper_counter_halves_only_exist_in_rv32() {
+        // csrr a0, cycleh; csrr a0, instreth; csrr a0, mhpmcounter3h
+        let words = [0xc8002573u32, 0xc8202573, 0xb8302573];
+        let memory: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        for (pc, word) in words.iter().enumerate().map(|(i, word)| (i * 4, *word)) {
+            let mut cpu = Cpu::builder().xlen(Xlen::Bit32).build();
+            cpu.update_pc(pc);
+            assert!(cpu.tick(&mut memory.clone()).is_ok(), "{:#010x}", word);
+
+            let mut cpu = Cpu::new();
+            cpu.update_pc(pc);
+            let trap = cpu.tick(&mut memory.clone()).unwrap_err();
+            assert_eq!((TrapType::IllegalInstruction, word as u64), (trap.trap_type, trap.value));
+        }
+    }
+
+    #[test]
+    fn builder_configures_cpu() {
+        let cpu = Cpu::builder()
+            .xlen(Xlen::Bit32)
+            .pc(0x100)
+            .stack_pointer(0x8000)
+            .build();
+
+        assert_eq!(0x100, cpu.get_pc());
+        assert_eq!(0x8000, cpu.get_register(Register::SP));
+        assert_eq!(Xlen::Bit32, cpu.get_xlen());
+    }
+
+    #[test]
+    fn dump_memory_marks_pc_and_sp() {
+        let memory = vec![0u8; 0x40];
+        let cpu = Cpu::builder().pc(0x4).stack_pointer(0x38).build();
+
+        let dump = cpu.dump_memory(&memory, 0..0x40);
+        assert_eq!("pc", dump.rows[0].annotations[0].label);
+        assert_eq!(0x38, dump.rows[3].annotations[0].address);
+        assert!(dump.to_string().lines().nth(3).unwrap().ends_with("<sp+0x8>"));
+    }
+
+    // li a0, 0; li a1, 5; loop: addi a0, a0, 3; addi a1, a1, -1; bnez a1, loop; ecall
+    const COUNTING_LOOP: [u32; 6] = [0x00000513, 0x00500593, 0x00350513, 0xfff58593, 0xfe059ce3, 0x00000073];
+
+    #[test]
+    fn run_reports_how_it_ended() {
+        let mut memory = program(&COUNTING_LOOP);
+        memory.resize(0x20, 0);
+        let mut cpu = Cpu::builder().ecall_fallback(Instruction {
+            name: "ECALL",
+            operation: |cpu, _memory, _word, _address| Err(Trap::exit(cpu.get_register(Register::A0)))
+        }).build();
+
+        let config = RunConfig::new().fuel(10);
+        assert!(matches!(cpu.run(&mut memory, &config), StopReason::FuelExhausted));
+        cpu.stop_handle().stop();
+        assert!(matches!(cpu.run(&mut memory, &config), StopReason::StopRequested));
+        assert!(matches!(cpu.run(&mut memory, &config), StopReason::Exited(15)));
+
+        // the zeroed words after the ecall are not an instruction
+        match cpu.run(&mut memory, &config) {
+            StopReason::Trap { pc, trap } => {
+                assert_eq!(24, pc);
+                assert_eq!(TrapType::IllegalInstruction, trap.trap_type);
+            },
+            status => panic!("unexpected {:?}", status)
+        }
+    }
+
+    #[test]
+    fn ecalls_dispatch_on_a7() {
+        // ecalls numbered 1, 2 and 5
+        let mut memory = program(&[0x00100893, 0x00000073, 0x00200893, 0x00000073, 0x00500893, 0x00000073]);
+        let mut cpu = Cpu::builder()
+            .ecall_handler(1, Instruction { name: "ONE", operation: |cpu, _memory, _word, _address| { cpu.x[10] += 1; Ok(()) } })
+            .ecall_fallback(Instruction { name: "OTHER", operation: |cpu, _memory, _word, _address| { cpu.x[11] += 1; Ok(()) } })
+            .build();
+
+        for _ in 0..6 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!(1, cpu.get_register(Register::A0));
+        assert_eq!(2, cpu.get_register(Register::A1));
+
+        // without the fallback an ecall nothing handles does nothing
+        assert!(cpu.ecall_handlers_mut().unregister(1).is_some());
+        cpu.set_ecall_fallback(None);
+        cpu.update_pc(0);
+        for _ in 0..6 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!(1, cpu.get_register(Register::A0));
+        assert_eq!(2, cpu.get_register(Register::A1));
+    }
+
+    #[test]
+    fn host_functions_take_arguments_from_registers() {
+        // li a0, 6; li a1, -7; ecall 1000; ecall 1001
+        let mut memory = program(&[0x00600513, 0xff900593, 0x3e800893, 0x00000073, 0x3e900893, 0x00000073]);
+        let mut cpu = Cpu::new();
+        cpu.set_fp_register(FpRegister::FA0, 0.5);
+        cpu.bind_host_fn(1000, |(a, b, x): (u64, i32, f64)| Ok((a as i64 * b as i64) as f64 + x));
+        let mut calls = 0;
+        cpu.bind_host_fn(1001, move |(a,): (i64,)| {
+            calls += 1;
+            match calls {
+                1 => Err(Trap::exit(a)),
+                _ => Ok(())
+            }
+        });
+
+        for _ in 0..4 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!(-41.5, cpu.get_fp_register(FpRegister::FA0));
+        assert_eq!(6, cpu.get_register(Register::A0));
+
+        cpu.tick(&mut memory).unwrap();
+        let trap = cpu.tick(&mut memory).unwrap_err();
+        assert_eq!((TrapType::Stop, 6), (trap.trap_type, trap.value));
+    }
+
+    #[test]
+    fn ebreak_runs_its_handler() {
+        // li a0, 7; ebreak; ebreak
+        let mut memory = program(&[0x00700513, 0x00100073, 0x00100073]);
+        let mut cpu = Cpu::new();
+        cpu.tick(&mut memory).unwrap();
+        cpu.tick(&mut memory).unwrap();
+        cpu.set_ebreak_handler(Some(Instruction {
+            name: "EBREAK",
+            operation: |cpu, _memory, _word, address| Err(Trap { trap_type: TrapType::Breakpoint, value: cpu.x[10] as u64 + address as u64 })
+        }));
+        let trap = cpu.tick(&mut memory).unwrap_err();
+        assert_eq!(TrapType::Breakpoint, trap.trap_type);
+        assert_eq!(15, trap.value);
+    }
+
+    #[test]
+    fn engines_agree_across_switches() {
+        let mut memory = program(&COUNTING_LOOP);
+        let mut reference = Cpu::new();
+        let mut switching = Cpu::builder().engine(Engine::Block).build();
+
+        for step in 0..17 {
+            if step % 4 == 0 {
+                let other = match switching.engine() {
+                    Engine::Block => Engine::Cached,
+                    Engine::Cached => Engine::Interpreter,
+                    Engine::Interpreter => Engine::Block
+                };
+                switching.set_engine(other);
+            }
+            reference.tick(&mut memory).unwrap();
+            switching.tick(&mut memory).unwrap();
+            assert_eq!(reference.pc, switching.pc);
+            assert_eq!(reference.x, switching.x);
+        }
+        assert_eq!(15, switching.get_register(Register::A0));
+
+        switching.tick(&mut memory).unwrap();
+        assert_eq!(24, switching.pc);
+    }
+
+    #[test]
+    fn stale_blocks_are_dropped_on_invalidation() {
+        // addi a0, a0, 1; j -4
+        let mut memory = program(&[0x00150513, 0xffdff06f]);
+        let mut cpu = Cpu::builder().engine(Engine::Block).build();
+        cpu.tick(&mut memory).unwrap();
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(1, cpu.cached_blocks());
+
+        // addi a0, a0, 16 is not seen until the cache is invalidated
+        memory.write_u32(0, 0x01050513).unwrap();
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(2, cpu.get_register(Register::A0));
+        cpu.tick(&mut memory).unwrap();
+        cpu.invalidate_code_cache();
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(18, cpu.get_register(Register::A0));
+    }
+
+    #[test]
+    fn only_blocks_with_code_in_the_range_are_dropped() {
+        // addi a0, a0, 1; j 4; addi a1, a1, 1; j -12
+        let mut memory = program(&[0x00150513, 0x0040006f, 0x00158593, 0xff5ff06f]);
+        let mut cpu = Cpu::builder().engine(Engine::Block).build();
+        for _ in 0..4 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!(2, cpu.cached_blocks());
+        cpu.invalidate_code(0x10..0x100);
+        assert_eq!(2, cpu.cached_blocks());
+        cpu.invalidate_code(0xe..0xf);
+        assert_eq!(1, cpu.cached_blocks());
+        assert_eq!(1, cpu.cache_stats().blocks.invalidations);
+    }
+
+    #[test]
+    fn cache_stats_count_block_lookups() {
+        // addi a0, a0, 1; j -4
+        let mut memory = program(&[0x00150513, 0xffdff06f]);
+        let mut cpu = Cpu::builder().engine(Engine::Block).build();
+        for _ in 0..6 {
+            cpu.tick(&mut memory).unwrap();
+        }
+
+        let blocks = cpu.cache_stats().blocks;
+        assert_eq!(CacheStats { entries: 1, hits: 2, misses: 1, invalidations: 0 }, blocks);
+        assert_eq!(2.0 / 3.0, blocks.hit_rate());
+
+        cpu.invalidate_all_caches();
+        assert_eq!(0, cpu.cache_stats().blocks.entries);
+        assert_eq!(1, cpu.cache_stats().blocks.invalidations);
+        cpu.reset_cache_stats();
+        assert_eq!(Caches::default(), cpu.cache_stats());
+    }
+
+    #[test]
+    fn fence_i_invalidates_blocks() {
+        // addi a0, a0, 1; fence.i; j -8
+        let mut memory = program(&[0x00150513, 0x0000100f, 0xff9ff06f]);
+        let mut cpu = Cpu::builder().engine(Engine::Block).build();
+        cpu.tick(&mut memory).unwrap();
+        memory.write_u32(0, 0x01050513).unwrap();
+        for _ in 0..3 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!(17, cpu.get_register(Register::A0));
+    }
+
+    #[test]
+    fn decoded_instructions_are_reused_until_their_page_is_stored_to() {
+        // addi a0, a0, 1; j -4
+        let mut memory = program(&[0x00150513, 0xffdff06f]);
+        let mut cpu = Cpu::builder().engine(Engine::Cached).build();
+        for _ in 0..6 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!(CacheStats { entries: 2, hits: 4, misses: 2, invalidations: 0 }, cpu.cache_stats().decoded);
+
+        // changes from the host are not seen until the cache is invalidated
+        memory.write_u32(0, 0x01050513).unwrap();
+        cpu.tick(&mut memory).unwrap();
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(4, cpu.get_register(Register::A0));
+        cpu.invalidate_code_cache();
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(20, cpu.get_register(Register::A0));
+
+        // addi a0, a0, 1; sw a1, 0(zero); j -8 with a1 holding addi a0, a0, 16, which runs
+        // without a FENCE.I
+        let mut memory = program(&[0x00150513, 0x00b02023, 0xff9ff06f]);
+        let mut cpu = Cpu::builder().engine(Engine::Cached).build();
+        cpu.set_register(Register::A1, 0x01050513);
+        for _ in 0..4 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!(17, cpu.get_register(Register::A0));
+        assert_eq!(CacheStats { entries: 2, hits: 0, misses: 4, invalidations: 1 }, cpu.cache_stats().decoded);
+    }
+
+    #[test]
+    fn tagged_pointers_are_masked() {
+        // sw a1, 0(a0); lw a2, 0(a0)
+        let mut memory = program(&[0x00b52023, 0x00052603]);
+        memory.resize(0x200, 0);
+        let tagged = 0xab00_0000_0000_0100u64 as i64;
+
+        let mut untagged = Cpu::new();
+        untagged.set_register(Register::A0, tagged);
+        assert!(untagged.tick(&mut memory).is_err());
+
+        let masking = PointerMasking { data_bits: 8, fetch_bits: 16, sign_extend: false };
+        let mut cpu = Cpu::builder().pointer_masking(masking).pc(0x1234_0000_0000_0000).build();
+        cpu.set_register(Register::A0, tagged);
+        cpu.set_register(Register::A1, 0x55);
+        cpu.tick(&mut memory).unwrap();
+        cpu.tick(&mut memory).unwrap();
+
+        assert_eq!(0x55, memory[0x100]);
+        assert_eq!(0x55, cpu.get_register(Register::A2));
+        assert_eq!(tagged, cpu.get_register(Register::A0));
+        assert_eq!(8, cpu.get_pc());
+    }
+
+    #[test]
+    #[cfg(feature = "c")]
+    fn forked_cpu_runs_independently() {
+        let mut memory = CowMemory::from_bytes(&[
+            0x05, 0x05, // addi a0,a0,1
+            0x05, 0x05, // addi a0,a0,1
+            0x00, 0x00,
+            0x00, 0x00
+        ]);
+        let mut cpu = Cpu::new();
+        cpu.tick(&mut memory).expect("cpu failure");
+
+        let (mut child, mut child_memory) = cpu.fork(&memory);
+        child.tick(&mut child_memory).expect("cpu failure");
+        child_memory.write_u16(0, 0).expect("write");
+
+        assert_eq!(1, cpu.get_register(Register::A0));
+        assert_eq!(2, child.get_register(Register::A0));
+        assert_eq!(0x0505, memory.read_u16(0).expect("read"));
+    }
+
+    #[test]
+    #[cfg(feature = "f")]
+    fn fflags_provenance_records_raising_pc() {
+        let mut memory: Vec<u8> = vec![
+            0x13, 0x00, 0x00, 0x00, // nop
+            0x53, 0x80, 0x20, 0x18, // fdiv.s f0,f1,f2
+            0x53, 0x80, 0x20, 0x18, // fdiv.s f0,f1,f2
+            0x00, 0x00, 0x00, 0x00
+        ];
+        let mut cpu = Cpu::new();
+        cpu.set_f32(1, 1.0);
+        cpu.set_f32(2, 0.0);
+        cpu.track_fflags_provenance(true);
+
+        cpu.tick(&mut memory).expect("cpu failure");
+        cpu.tick(&mut memory).expect("cpu failure");
+        cpu.tick(&mut memory).expect("cpu failure");
+
+        let provenance = cpu.fflags_provenance().expect("tracking enabled");
+        assert!(provenance.site(0).is_none());
+        assert_eq!(1, provenance.site(4).expect("fdiv site").count(FFLAG_DZ));
+        assert_eq!(1, provenance.site(8).expect("fdiv site").count(FFLAG_DZ));
+        assert_eq!(FFLAG_DZ, cpu.read_csr(CSR_FFLAGS_ADDRESS) & FFLAG_DZ);
+    }
+
+    #[test]
+    fn each_fflags_bit_reads_back_as_itself() {
+        let mut cpu = Cpu::new();
+        for flag in [FFLAG_NX, fflags::FFLAG_UF, fflags::FFLAG_OF, FFLAG_DZ, FFLAG_NV] {
+            cpu.write_csr(CSR_FFLAGS_ADDRESS, flag);
+            assert_eq!(flag, cpu.read_csr(CSR_FFLAGS_ADDRESS));
+        }
+        cpu.write_csr(CSR_FFLAGS_ADDRESS, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "d")]
     fn decode_fld_compressed_instruction() {
         let opcode = Cpu::uncompress(0x3022);
         // println!("opcode = {:?}", opcode);
@@ -1389,4 +3485,56 @@ mod test_cpu {
             _ => panic!("invalid instruction")
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rv32_compressed_instructions_expand_differently() {
+        // c.jal 12, c.flw fa0, 4(a0), c.fsw fa1, 8(a1), c.flwsp fa2, 12(sp) and c.fswsp fa3, 16(sp)
+        let expanded = [0x2031, 0x6148, 0xe58c, 0x6632, 0xe836].map(|halfword| Cpu::uncompress_for(halfword, Xlen::Bit32));
+        assert_eq!([0x00c000ef, 0x00452507, 0x00b5a427, 0x00c12607, 0x00d12827], expanded);
+        // c.addiw a0, 1 in RV64
+        assert_eq!(0x0015051b, Cpu::uncompress(0x2505));
+    }
+
+    #[test]
+    #[cfg(all(feature = "a", feature = "d"))]
+    fn rv64_only_encodings_are_illegal_in_rv32() {
+        // addiw, ld, lwu, sd, amoadd.d, slli a0, a0, 32, fcvt.l.s, fcvt.d.lu and fmv.x.d
+        for word in [0x0015051b, 0x0005b503, 0x0005e503, 0x00a5b023, 0x00b6352f, 0x02051513, 0xc0257553, 0xd2357553, 0xe2050553] {
+            assert!(Cpu::decode_for(word, Xlen::Bit32).is_none(), "{:#x}", word);
+            assert!(Cpu::decode_for(word, Xlen::Bit64).is_some(), "{:#x}", word);
+        }
+        // fclass.d, fcvt.w.d and slli a0, a0, 31
+        for word in [0xe2051553, 0xc2057553, 0x01f51513] {
+            assert!(Cpu::decode_for(word, Xlen::Bit32).is_some(), "{:#x}", word);
+        }
+
+        let mut memory = 0x0015051bu32.to_le_bytes().to_vec();
+        let mut cpu = Cpu::builder().xlen(Xlen::Bit32).build();
+        let trap = cpu.tick(&mut memory).unwrap_err();
+        assert_eq!((TrapType::IllegalInstruction, 0x0015051b), (trap.trap_type, trap.value));
+    }
+
+    #[test]
+    #[cfg(feature = "c")]
+    fn rv32_wraps_shifts_addresses_and_jumps() {
+        // addi a0, zero, 1; addi a1, zero, 33; sll a2, a0, a1; addi a3, zero, -1; srl a4, a3, a1;
+        // c.jal 12; c.nop; then addi t0, zero, 0x31; jalr t1, 0(t0) at 0x20 and lw a5, 0x41(a3)
+        // at 0x30, which can only reach the word at 0x40 by wrapping around
+        let mut memory = vec![0u8; 0x44];
+        for (at, word) in [(0, 0x00100513u32), (4, 0x02100593), (8, 0x00b51633), (12, 0xfff00693), (16, 0x00b6d733),
+            (20, 0x00012031), (32, 0x03100293), (36, 0x00028367), (48, 0x0416a783), (64, 0x12345678)] {
+            memory[at..at + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        let mut cpu = Cpu::builder().xlen(Xlen::Bit32).build();
+
+        for _ in 0..9 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        // shifts only take the low 5 bits of the amount
+        assert_eq!([2, 0x7fffffff], [cpu.x[12], cpu.x[14]]);
+        // c.jal links, jalr drops bit 0 of the target
+        assert_eq!([0x16, 0x28], [cpu.x[1], cpu.x[6]]);
+        assert_eq!(0x12345678, cpu.x[15]);
+        assert_eq!(0x34, cpu.get_pc());
+    }
+}
@@ -0,0 +1,184 @@
+use crate::cpu::Cpu;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BranchCount {
+    pub taken: u64,
+    pub not_taken: u64
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PredictorStats {
+    pub name: String,
+    pub predictions: u64,
+    pub mispredictions: u64
+}
+
+impl PredictorStats {
+    pub fn misprediction_rate(&self) -> f64 {
+        match self.predictions {
+            0 => 0.0,
+            predictions => self.mispredictions as f64 / predictions as f64
+        }
+    }
+}
+
+// How every conditional branch went and how well each predictor added with add_predictor would
+// have done on them, in the order they were added.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BranchStats {
+    pub branches: BTreeMap<usize, BranchCount>,
+    pub predictors: Vec<PredictorStats>
+}
+
+// A branch predictor model, asked about every conditional branch before being told how it went.
+// Models only ever see the branches, the guest runs the same whatever they predict.
+pub trait Predictor: Send {
+    fn predict(&self, pc: usize) -> bool;
+    fn update(&mut self, pc: usize, taken: bool);
+}
+
+// Predictors are models that belong to one hart, so a cloned or forked Cpu starts out without
+// any. The counts they made so far stay with the BranchStats.
+#[derive(Default)]
+pub(crate) struct Predictors(Vec<Box<dyn Predictor>>);
+
+impl Clone for Predictors {
+    fn clone(&self) -> Self {
+        Predictors(Vec::new())
+    }
+}
+
+// A table of two bit saturating counters indexed by pc.
+pub struct Bimodal {
+    counters: Vec<u8>
+}
+
+impl Bimodal {
+    // entries is rounded up to a power of two
+    pub fn new(entries: usize) -> Self {
+        Bimodal { counters: vec![1; entries.max(1).next_power_of_two()] }
+    }
+
+    fn index(&self, key: usize) -> usize {
+        key & (self.counters.len() - 1)
+    }
+}
+
+impl Predictor for Bimodal {
+    fn predict(&self, pc: usize) -> bool {
+        self.counters[self.index(pc >> 1)] >= 2
+    }
+
+    fn update(&mut self, pc: usize, taken: bool) {
+        let index = self.index(pc >> 1);
+        train(&mut self.counters[index], taken);
+    }
+}
+
+// Two bit counters indexed by the pc xored with the outcomes of the last history_bits branches.
+pub struct Gshare {
+    counters: Vec<u8>,
+    history: usize,
+    history_mask: usize
+}
+
+impl Gshare {
+    // entries is rounded up to a power of two
+    pub fn new(entries: usize, history_bits: u32) -> Self {
+        Gshare { counters: vec![1; entries.max(1).next_power_of_two()], history: 0, history_mask: (1 << history_bits.min(usize::BITS - 1)) - 1 }
+    }
+
+    fn index(&self, pc: usize) -> usize {
+        ((pc >> 1) ^ self.history) & (self.counters.len() - 1)
+    }
+}
+
+impl Predictor for Gshare {
+    fn predict(&self, pc: usize) -> bool {
+        self.counters[self.index(pc)] >= 2
+    }
+
+    fn update(&mut self, pc: usize, taken: bool) {
+        let index = self.index(pc);
+        train(&mut self.counters[index], taken);
+        self.history = ((self.history << 1) | taken as usize) & self.history_mask;
+    }
+}
+
+fn train(counter: &mut u8, taken: bool) {
+    *counter = match taken {
+        true => (*counter + 1).min(3),
+        false => counter.saturating_sub(1)
+    };
+}
+
+impl Cpu {
+    // Starts counting how each conditional branch goes, or stops and throws the counts and any
+    // predictors away.
+    pub fn set_branch_stats(&mut self, enabled: bool) {
+        self.branch_stats = match enabled {
+            true => Some(self.branch_stats.take().unwrap_or_default()),
+            false => None
+        };
+        self.update_observed();
+        if !enabled {
+            self.predictors = Predictors::default();
+        }
+    }
+
+    // Has predictor predict every conditional branch from here on, counting branches too if they
+    // were not already.
+    pub fn add_predictor(&mut self, name: &str, predictor: Box<dyn Predictor>) {
+        self.set_branch_stats(true);
+        if let Some(stats) = &mut self.branch_stats {
+            stats.predictors.push(PredictorStats { name: name.to_string(), predictions: 0, mispredictions: 0 });
+        }
+        self.predictors.0.push(predictor);
+    }
+
+    pub fn branch_stats(&self) -> Option<&BranchStats> {
+        self.branch_stats.as_ref()
+    }
+
+    pub(crate) fn record_branch(&mut self, pc: usize, taken: bool) {
+        let Some(stats) = &mut self.branch_stats else {
+            return;
+        };
+        let count = stats.branches.entry(pc).or_default();
+        match taken {
+            true => count.taken += 1,
+            false => count.not_taken += 1
+        }
+        for (predictor, stats) in self.predictors.0.iter_mut().zip(stats.predictors.iter_mut()) {
+            stats.predictions += 1;
+            stats.mispredictions += (predictor.predict(pc) != taken) as u64;
+            predictor.update(pc, taken);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn branches_are_counted_and_predicted() {
+        // li a0, 3; loop: addi a0, a0, -1; bnez a0, loop
+        let words = [0x00300513u32, 0xfff50513, 0xfe051ee3];
+        let mut memory = program(&words);
+        let mut cpu = Cpu::new();
+        cpu.add_predictor("bimodal", Box::new(Bimodal::new(16)));
+        cpu.add_predictor("gshare", Box::new(Gshare::new(16, 4)));
+
+        while cpu.tick(&mut memory).is_ok() {}
+        let stats = cpu.branch_stats().unwrap();
+        assert_eq!(Some(&BranchCount { taken: 2, not_taken: 1 }), stats.branches.get(&8));
+        // weakly not taken to start with, wrong the first time and when the loop ends
+        assert_eq!((3, 2), (stats.predictors[0].predictions, stats.predictors[0].mispredictions));
+        assert_eq!("gshare", stats.predictors[1].name);
+        assert_eq!(3, stats.predictors[1].predictions);
+        assert!(cpu.clone().branch_stats().is_some());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::cpu::Cpu;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    // in bytes, all three are rounded up to powers of two
+    pub size: usize,
+    pub ways: usize,
+    pub line: usize
+}
+
+impl CacheConfig {
+    pub fn new(size: usize, ways: usize, line: usize) -> Self {
+        CacheConfig { size, ways, line }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HitStats {
+    pub hits: u64,
+    pub misses: u64
+}
+
+impl HitStats {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            accesses => self.hits as f64 / accesses as f64
+        }
+    }
+}
+
+// A set associative cache with least recently used replacement that only keeps tags.
+#[derive(Clone, Debug)]
+struct Cache {
+    // each set most recently used first
+    sets: Vec<Vec<usize>>,
+    ways: usize,
+    line_shift: u32,
+    stats: HitStats
+}
+
+impl Cache {
+    fn new(config: CacheConfig) -> Self {
+        let line = config.line.max(1).next_power_of_two();
+        let ways = config.ways.max(1).next_power_of_two();
+        let sets = (config.size.max(1).next_power_of_two() / (line * ways)).max(1);
+        Cache { sets: vec![Vec::with_capacity(ways); sets], ways, line_shift: line.trailing_zeros(), stats: HitStats::default() }
+    }
+
+    // every line the access touches counts once
+    fn access(&mut self, address: usize, size: usize) {
+        let count = self.sets.len();
+        for line in address >> self.line_shift..=(address + size.max(1) - 1) >> self.line_shift {
+            let set = &mut self.sets[line % count];
+            match set.iter().position(|tag| *tag == line) {
+                Some(way) => {
+                    set[..=way].rotate_right(1);
+                    self.stats.hits += 1;
+                },
+                None => {
+                    set.insert(0, line);
+                    set.truncate(self.ways);
+                    self.stats.misses += 1;
+                }
+            }
+        }
+    }
+}
+
+// Instruction and data caches fed with what the hart fetches, loads and stores, for seeing how
+// a guest would fare on hardware with caches like them. The caches are only counted, the guest
+// runs exactly the same with or without them.
+#[derive(Clone, Debug)]
+pub struct CacheModel {
+    instruction: Cache,
+    data: Cache
+}
+
+impl CacheModel {
+    pub fn new(instruction: CacheConfig, data: CacheConfig) -> Self {
+        CacheModel { instruction: Cache::new(instruction), data: Cache::new(data) }
+    }
+
+    pub fn instruction_stats(&self) -> HitStats {
+        self.instruction.stats
+    }
+
+    pub fn data_stats(&self) -> HitStats {
+        self.data.stats
+    }
+
+    pub(crate) fn misses(&self) -> u64 {
+        self.instruction.stats.misses + self.data.stats.misses
+    }
+
+    pub(crate) fn fetch(&mut self, pc: usize) {
+        self.instruction.access(pc, 2);
+    }
+
+    pub(crate) fn access(&mut self, address: usize, size: usize) {
+        self.data.access(address, size);
+    }
+}
+
+impl Cpu {
+    // Starts feeding model with what the hart fetches, loads and stores, None stops.
+    pub fn set_cache_model(&mut self, model: Option<CacheModel>) {
+        self.cache_model = model;
+        self.update_observed();
+    }
+
+    pub fn cache_model(&self) -> Option<&CacheModel> {
+        self.cache_model.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn hits_and_misses_are_counted() {
+        // li a0, 4; loop: lw a1, 0x100(zero); lw a2, 0x200(zero); addi a0, a0, -1; bnez a0, loop
+        let words = [0x00400513u32, 0x10002583, 0x20002603, 0xfff50513, 0xfe051ae3];
+        let mut memory = [program(&words), vec![0; 0x200]].concat();
+        let mut cpu = Cpu::new();
+        // 0x100 and 0x200 land in the same set of the direct mapped data cache and keep evicting
+        // each other, the two way one keeps both
+        cpu.set_cache_model(Some(CacheModel::new(CacheConfig::new(256, 1, 32), CacheConfig::new(256, 1, 32))));
+        let mut two_way = cpu.clone();
+        two_way.set_cache_model(Some(CacheModel::new(CacheConfig::new(256, 1, 32), CacheConfig::new(256, 2, 32))));
+
+        for _ in 0..17 {
+            cpu.tick(&mut memory).unwrap();
+            two_way.tick(&mut memory).unwrap();
+        }
+        let model = cpu.cache_model().unwrap();
+        assert_eq!(HitStats { hits: 16, misses: 1 }, model.instruction_stats());
+        assert_eq!(HitStats { hits: 0, misses: 8 }, model.data_stats());
+        assert_eq!(HitStats { hits: 6, misses: 2 }, two_way.cache_model().unwrap().data_stats());
+        assert_eq!(0.75, two_way.cache_model().unwrap().data_stats().hit_rate());
+    }
+}
@@ -0,0 +1,96 @@
+use crate::cpu::Cpu;
+
+// calls nested deeper than this lose their outermost frames, a guest that never returns from
+// what looks like a call cannot grow the stack without bound
+const MAX_DEPTH: usize = 4096;
+
+// The return addresses of the calls the guest is in, worked out from its jumps the way a return
+// address stack predicts them: a jump that links through ra or t0 is a call and one that jumps
+// through them without linking is a return.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CallStack {
+    return_addresses: Vec<usize>
+}
+
+fn is_link(register: u32) -> bool {
+    register == 1 || register == 5
+}
+
+impl CallStack {
+    // word has just executed and taken the hart to pc, it would have gone to fallthrough otherwise
+    pub(crate) fn observe(&mut self, word: u32, fallthrough: usize, pc: usize) {
+        let (rd, rs1) = ((word >> 7) & 0x1f, (word >> 15) & 0x1f);
+        match word & 0x7f {
+            // JAL
+            0x6f if is_link(rd) => self.call(fallthrough),
+            // JALR
+            0x67 => match (is_link(rd), is_link(rs1)) {
+                (true, false) => self.call(fallthrough),
+                (false, true) => self.ret(pc),
+                // a coroutine switch returns through one and links through the other
+                (true, true) if rd != rs1 => {
+                    self.ret(pc);
+                    self.call(fallthrough);
+                },
+                (true, true) => self.call(fallthrough),
+                (false, false) => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn call(&mut self, return_address: usize) {
+        if self.return_addresses.len() == MAX_DEPTH {
+            self.return_addresses.remove(0);
+        }
+        self.return_addresses.push(return_address);
+    }
+
+    // A return to somewhere further out, as longjmp does, unwinds every call in between. One to
+    // an address no call is waiting for is a tail jump the stack has no use for.
+    fn ret(&mut self, pc: usize) {
+        if let Some(depth) = self.return_addresses.iter().rposition(|address| *address == pc) {
+            self.return_addresses.truncate(depth);
+        }
+    }
+}
+
+impl Cpu {
+    // Tracks calls and returns from now on, for backtrace. Turning it off forgets the calls.
+    pub fn set_call_tracking(&mut self, enabled: bool) {
+        self.call_stack = enabled.then(|| self.call_stack.take().unwrap_or_default());
+        self.update_observed();
+    }
+
+    // the return addresses of the calls made since tracking started and not returned from yet,
+    // innermost first
+    pub fn backtrace(&self) -> Vec<usize> {
+        self.call_stack.as_ref().map_or(Vec::new(), |stack| stack.return_addresses.iter().rev().copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn backtraces_follow_calls_and_returns() {
+        // 0x00: call f; 0x04: call g
+        // 0x10 f: ret
+        // 0x20 g: jal t0, h
+        // 0x28 h: lw a0, 0x100(zero)
+        let words = [
+            0x010000efu32, 0x01c000ef, 0x00000013, 0x00000013, 0x00008067, 0x00000013,
+            0x00000013, 0x00000013, 0x008002ef, 0x00000013, 0x10002503
+        ];
+        let mut memory = program(&words);
+        let mut cpu = Cpu::new();
+        cpu.set_call_tracking(true);
+
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(vec![0x04], cpu.backtrace());
+        while cpu.tick(&mut memory).is_ok() {}
+        assert_eq!(vec![0x24, 0x08], cpu.backtrace());
+    }
+}
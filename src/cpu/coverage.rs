@@ -0,0 +1,117 @@
+use crate::cpu::Cpu;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+
+// The basic blocks a guest executed. A block runs from where execution arrived to the first
+// branch, jump or anything else that did not fall through, so jumping into the middle of a block
+// seen before gives a second, overlapping one.
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    // start to the most bytes executed from there
+    blocks: BTreeMap<usize, usize>,
+    // the block being executed, where it started and where it has got to
+    current: Option<(usize, usize)>
+}
+
+impl Coverage {
+    pub(crate) fn retired(&mut self, pc: usize, fallthrough: usize, ends: bool) {
+        let start = match self.current {
+            Some((start, end)) if end == pc => start,
+            _ => {
+                self.close();
+                pc
+            }
+        };
+        self.current = Some((start, fallthrough));
+        if ends {
+            self.close();
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some((start, end)) = self.current.take() {
+            let size = self.blocks.entry(start).or_insert(0);
+            *size = (*size).max(end - start);
+        }
+    }
+
+    // in address order, the one being executed included
+    pub fn blocks(&self) -> Vec<Range<usize>> {
+        let mut blocks = self.blocks.clone();
+        if let Some((start, end)) = self.current {
+            let size = blocks.entry(start).or_insert(0);
+            *size = (*size).max(end - start);
+        }
+        blocks.into_iter().map(|(start, size)| start..start + size).collect()
+    }
+
+    // Writes the blocks in the drcov format Lighthouse and other coverage tools read, against
+    // modules given as the path of each binary and where it was loaded. Blocks outside all of
+    // them are left out.
+    pub fn write_drcov(&self, writer: &mut dyn Write, modules: &[(&str, Range<usize>)]) -> io::Result<()> {
+        let mut table = Vec::new();
+        for block in self.blocks() {
+            if let Some((id, (_, range))) = modules.iter().enumerate().find(|(_, (_, range))| range.contains(&block.start)) {
+                table.extend(((block.start - range.start) as u32).to_le_bytes());
+                table.extend((block.len().min(u16::MAX as usize) as u16).to_le_bytes());
+                table.extend((id as u16).to_le_bytes());
+            }
+        }
+
+        let mut output = String::from("DRCOV VERSION: 2\nDRCOV FLAVOR: drcov\n");
+        output += &format!("Module Table: version 2, count {}\n", modules.len());
+        output += "Columns: id, base, end, entry, checksum, timestamp, path\n";
+        for (id, (path, range)) in modules.iter().enumerate() {
+            output += &format!("{:>3}, {:#018x}, {:#018x}, {:#018x}, 0x00000000, 0x00000000, {}\n", id, range.start, range.end, 0, path);
+        }
+        output += &format!("BB Table: {} bbs\n", table.len() / 8);
+        writer.write_all(output.as_bytes())?;
+        writer.write_all(&table)
+    }
+}
+
+impl Cpu {
+    // Starts noting which blocks the hart executes, or stops and throws them away. Noting them
+    // again after stopping starts from nothing.
+    pub fn set_coverage(&mut self, enabled: bool) {
+        self.coverage = match enabled {
+            true => Some(self.coverage.take().unwrap_or_default()),
+            false => None
+        };
+        self.update_observed();
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn blocks_are_exported_for_coverage_tools() {
+        // li a0, 2; loop: addi a0, a0, -1; bnez a0, loop; ebreak
+        let words = [0x00200513u32, 0xfff50513, 0xfe051ee3, 0x00100073];
+        let mut memory = program(&words);
+        let mut cpu = Cpu::new();
+        cpu.set_coverage(true);
+
+        while cpu.tick(&mut memory).is_ok() {}
+        let coverage = cpu.coverage().unwrap();
+        assert_eq!(vec![0..0xc, 4..0xc, 0xc..0x10], coverage.blocks());
+
+        let mut drcov = Vec::new();
+        coverage.write_drcov(&mut drcov, &[("/tmp/guest", 4..0x10)]).unwrap();
+        let header = b"DRCOV VERSION: 2\nDRCOV FLAVOR: drcov\nModule Table: version 2, count 1\n\
+            Columns: id, base, end, entry, checksum, timestamp, path\n  \
+            0, 0x0000000000000004, 0x0000000000000010, 0x0000000000000000, 0x00000000, 0x00000000, /tmp/guest\n\
+            BB Table: 2 bbs\n";
+        assert_eq!(&header[..], &drcov[..header.len()]);
+        assert_eq!(&[0, 0, 0, 0, 8, 0, 0, 0, 8, 0, 0, 0, 4, 0, 0, 0], &drcov[header.len()..]);
+    }
+}
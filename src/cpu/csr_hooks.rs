@@ -0,0 +1,131 @@
+use crate::cpu::{Cpu, Trap};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+// Serves guest accesses to a CSR in place of the hart, for vendor CSRs and the magic registers
+// test harnesses use. A register that is only ever read or only ever written need only
+// implement the one, the other reads as zero and ignores writes. Returning a trap stops the
+// instruction before it writes its destination register.
+pub trait CsrHandler: Send {
+    fn read(&mut self, _cpu: &mut Cpu, _address: u16) -> Result<u64, Trap> {
+        Ok(0)
+    }
+
+    fn write(&mut self, _cpu: &mut Cpu, _address: u16, _value: u64) -> Result<(), Trap> {
+        Ok(())
+    }
+}
+
+pub(crate) type SharedHandler = Arc<Mutex<dyn CsrHandler>>;
+
+// The handlers registered for particular CSRs and a fallback for the CSRs the hart does not
+// implement itself. Clones of a hart share the same handlers, and with them any state they keep.
+#[derive(Clone, Default)]
+pub struct CsrHooks {
+    handlers: BTreeMap<u16, SharedHandler>,
+    fallback: Option<SharedHandler>
+}
+
+impl CsrHooks {
+    pub fn new() -> Self {
+        CsrHooks::default()
+    }
+
+    // replaces whatever handled address before, the hart's own implementation included
+    pub fn register(&mut self, address: u16, handler: impl CsrHandler + 'static) {
+        self.handlers.insert(address, Arc::new(Mutex::new(handler)));
+    }
+
+    // gives address back to the hart, or to the fallback if the hart does not implement it
+    pub fn unregister(&mut self, address: u16) -> bool {
+        self.handlers.remove(&address).is_some()
+    }
+
+    pub fn set_fallback(&mut self, handler: Option<Box<dyn CsrHandler>>) {
+        self.fallback = handler.map(|handler| Arc::new(Mutex::new(handler)) as SharedHandler);
+    }
+
+    pub(crate) fn serves(&self, address: u16) -> bool {
+        self.fallback.is_some() || self.handlers.contains_key(&address)
+    }
+
+    // what serves a guest access to address, if not the hart
+    pub(crate) fn handler(&self, address: u16, implemented: bool) -> Option<SharedHandler> {
+        match self.handlers.get(&address) {
+            Some(handler) => Some(handler.clone()),
+            None if !implemented => self.fallback.clone(),
+            None => None
+        }
+    }
+}
+
+impl<H: CsrHandler + ?Sized> CsrHandler for Box<H> {
+    fn read(&mut self, cpu: &mut Cpu, address: u16) -> Result<u64, Trap> {
+        (**self).read(cpu, address)
+    }
+
+    fn write(&mut self, cpu: &mut Cpu, address: u16, value: u64) -> Result<(), Trap> {
+        (**self).write(cpu, address, value)
+    }
+}
+
+impl Debug for CsrHooks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CsrHooks")
+            .field("handlers", &self.handlers.keys().collect::<Vec<_>>())
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+    use crate::cpu::TrapType;
+
+    // a test harness register: writing it ends the run with the value written as the status
+    struct Exit;
+
+    impl CsrHandler for Exit {
+        fn write(&mut self, _cpu: &mut Cpu, _address: u16, value: u64) -> Result<(), Trap> {
+            Err(Trap { trap_type: TrapType::Stop, value })
+        }
+    }
+
+    // counts the reads of every CSR it serves
+    #[derive(Default)]
+    struct Reads(u64);
+
+    impl CsrHandler for Reads {
+        fn read(&mut self, _cpu: &mut Cpu, address: u16) -> Result<u64, Trap> {
+            self.0 += 1;
+            Ok(((address as u64) << 16) | self.0)
+        }
+    }
+
+    #[test]
+    fn guest_csr_accesses_reach_the_handlers() {
+        // csrr a0, 0x7c0; csrr a1, 0x7c1; csrr a2, 0x7c1; csrr a3, 0x8c0; csrwi 0x8c0, 3
+        let words = [0x7c002573u32, 0x7c1025f3, 0x7c102673, 0x8c0026f3, 0x8c01d073];
+        let mut memory = program(&words);
+        let mut cpu = Cpu::new();
+        cpu.set_csr_fallback(Some(Box::new(Reads::default())));
+        cpu.register_csr(0x8c0, Exit);
+
+        for _ in 0..4 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        let trap = cpu.tick(&mut memory).unwrap_err();
+        assert_eq!((TrapType::Stop, 3), (trap.trap_type, trap.value));
+        // the hart implements neither of the custom CSRs so the fallback serves both, and reading
+        // 0x8c0 is not taken for a write
+        assert_eq!([0x7c00001, 0x7c10002, 0x7c10003, 0], [cpu.x[10], cpu.x[11], cpu.x[12], cpu.x[13]]);
+
+        assert!(cpu.csr_hooks_mut().unregister(0x8c0));
+        cpu.update_pc(16);
+        cpu.tick(&mut memory).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+use crate::cpu::{Cpu, Xlen};
+use crate::cpu::instruction::Instruction;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// the values of rd and rs1 every key is tried with besides zero, each with a few values of rs2,
+// decoding that gives different answers for them is left to the match
+const PROBES: [(u32, u32); 2] = [(31, 31), (0b10101, 0b01010)];
+const PROBE_RS2: [usize; 3] = [0, 0b01010, 31];
+
+#[derive(Clone, Copy)]
+enum Slot {
+    Empty,
+    One(u16),
+    // an index into by_rs2, for keys like OP-IMM's unary ops that use rs2 to tell them apart
+    ByRs2(u16),
+    // whatever depends on more than rs2, the system instructions, pause and vector's vs1 forms
+    Match
+}
+
+// The decoding of every 32 bit word, precomputed from the match in Cpu::decode_matching so that
+// nearly every word decodes with two array indexes. Keys are bits 6:2 of the opcode, funct3 and
+// funct7, which with rs2 is all the match looks at outside the few places it is still asked.
+pub(crate) struct DecodeTable {
+    xlen: Xlen,
+    slots: Vec<Slot>,
+    instructions: Vec<&'static Instruction>,
+    by_rs2: Vec<[Option<u16>; 32]>
+}
+
+fn key(word: u32) -> usize {
+    (((word >> 2) & 0x1f) | ((word >> 12) & 7) << 5 | (word >> 25) << 8) as usize
+}
+
+fn same(a: Option<&'static Instruction>, b: Option<&'static Instruction>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => std::ptr::eq(a, b),
+        (None, None) => true,
+        _ => false
+    }
+}
+
+impl DecodeTable {
+    fn build(xlen: Xlen) -> DecodeTable {
+        let mut table = DecodeTable { xlen, slots: Vec::with_capacity(1 << 15), instructions: Vec::new(), by_rs2: Vec::new() };
+        let mut indexes = HashMap::new();
+        for key in 0..1u32 << 15 {
+            let base = (key & 0x1f) << 2 | 3 | ((key >> 5) & 7) << 12 | (key >> 8) << 25;
+            let decoded: [Option<&'static Instruction>; 32] = std::array::from_fn(|rs2| Cpu::decode_matching(base | (rs2 as u32) << 20, xlen));
+            // vector, ecall and its kind and fence with pause are known to look further, the probes
+            // are for anything else that does
+            let regular = !matches!((base & 0x7f, (base >> 12) & 7), (0b1010111, _) | (0b1110011, 0) | (0b0001111, 0)) &&
+                PROBES.iter().all(|(rd, rs1)| PROBE_RS2.iter().all(|&rs2| same(decoded[rs2], Cpu::decode_matching(base | (rs2 as u32) << 20 | rd << 7 | rs1 << 15, xlen))));
+            let slot = match decoded {
+                _ if !regular => Slot::Match,
+                [None, ..] if decoded.iter().all(Option::is_none) => Slot::Empty,
+                [first, ..] if decoded.iter().all(|other| same(first, *other)) => Slot::One(table.add(&mut indexes, first.unwrap())),
+                _ => {
+                    let rs2 = decoded.map(|instruction| instruction.map(|instruction| table.add(&mut indexes, instruction)));
+                    table.by_rs2.push(rs2);
+                    Slot::ByRs2(table.by_rs2.len() as u16 - 1)
+                }
+            };
+            table.slots.push(slot);
+        }
+        table
+    }
+
+    // the same instruction comes up for many keys, it is only kept once
+    fn add(&mut self, indexes: &mut HashMap<*const Instruction, u16>, instruction: &'static Instruction) -> u16 {
+        *indexes.entry(instruction as *const Instruction).or_insert_with(|| {
+            self.instructions.push(instruction);
+            self.instructions.len() as u16 - 1
+        })
+    }
+
+    #[inline]
+    pub(crate) fn get(xlen: Xlen) -> &'static DecodeTable {
+        static RV32: OnceLock<DecodeTable> = OnceLock::new();
+        static RV64: OnceLock<DecodeTable> = OnceLock::new();
+        match xlen {
+            Xlen::Bit32 => RV32.get_or_init(|| DecodeTable::build(Xlen::Bit32)),
+            Xlen::Bit64 => RV64.get_or_init(|| DecodeTable::build(Xlen::Bit64))
+        }
+    }
+
+    #[inline]
+    pub(crate) fn decode(&self, word: u32) -> Option<&'static Instruction> {
+        match self.slots.get(key(word)) {
+            Some(Slot::One(index)) if word & 3 == 3 => Some(self.instructions[*index as usize]),
+            _ => self.decode_rest(word)
+        }
+    }
+
+    // everything but the common case, kept out of line so that decode is cheap to inline
+    #[inline(never)]
+    fn decode_rest(&self, word: u32) -> Option<&'static Instruction> {
+        if word & 3 != 3 {
+            return None;
+        }
+        match self.slots[key(word)] {
+            Slot::Empty => None,
+            Slot::One(index) => Some(self.instructions[index as usize]),
+            Slot::ByRs2(index) => self.by_rs2[index as usize][((word >> 20) & 0x1f) as usize].map(|index| self.instructions[index as usize]),
+            Slot::Match => Cpu::decode_matching(word, self.xlen)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn the_tables_decode_as_the_match_does() {
+        // every key with every rs2 and rd and rs1 from a xorshift, then words from it alone
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u32
+        };
+        for xlen in [Xlen::Bit32, Xlen::Bit64] {
+            let table = DecodeTable::get(xlen);
+            for key in 0..1u32 << 15 {
+                for rs2 in 0..32 {
+                    let word = (key & 0x1f) << 2 | 3 | ((key >> 5) & 7) << 12 | (key >> 8) << 25 | rs2 << 20 | (random() & 0xf8f80);
+                    assert!(same(Cpu::decode_matching(word, xlen), table.decode(word)), "{:#010x}", word);
+                }
+            }
+            for _ in 0..100000 {
+                let word = random();
+                assert!(same(Cpu::decode_matching(word, xlen), table.decode(word)), "{:#010x}", word);
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::cpu::Xlen;
+use crate::cpu::instruction::Instruction;
+use std::collections::BTreeMap;
+
+// the major opcodes the base ISA leaves to vendors
+pub const CUSTOM_0: u32 = 0b0001011;
+pub const CUSTOM_1: u32 = 0b0101011;
+pub const CUSTOM_2: u32 = 0b1011011;
+pub const CUSTOM_3: u32 = 0b1111011;
+
+// Gives the instruction a 32 bit word of one major opcode is, if any.
+pub type CustomDecoder = fn(word: u32, xlen: Xlen) -> Option<Instruction>;
+
+// The decoders added to the hart's own, one for each major opcode that has one, for accelerator
+// and vendor instructions in the custom opcodes or in encodings a standard opcode leaves free.
+// They only see the words the hart has no instruction for, or has one from an extension turned
+// off with set_extensions, so they can add instructions but never replace one. A word none of
+// them decodes either is an illegal instruction, as always.
+#[derive(Clone, Debug, Default)]
+pub struct CustomDecoders {
+    decoders: BTreeMap<u32, CustomDecoder>
+}
+
+impl CustomDecoders {
+    pub fn new() -> Self {
+        CustomDecoders::default()
+    }
+
+    // returns the decoder opcode had before, if any
+    pub fn register(&mut self, opcode: u32, decoder: CustomDecoder) -> Option<CustomDecoder> {
+        assert!(opcode < 0x80 && opcode & 3 == 3, "{:#b} is not the major opcode of a 32 bit instruction", opcode);
+        self.decoders.insert(opcode, decoder)
+    }
+
+    pub fn unregister(&mut self, opcode: u32) -> Option<CustomDecoder> {
+        self.decoders.remove(&opcode)
+    }
+
+    pub fn decode(&self, word: u32, xlen: Xlen) -> Option<Instruction> {
+        self.decoders.get(&(word & 0x7f)).and_then(|decoder| decoder(word, xlen))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+    use crate::cpu::{instruction, Cpu, TrapType};
+
+    // a multiply accumulate in custom-0: rd += rs1 * rs2 with funct3 0 and funct7 1
+    const MAC: Instruction = Instruction {
+        name: "MAC",
+        operation: |cpu, _memory, word, _address| {
+            let f = instruction::parse_format_r(word);
+            cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rd].wrapping_add(cpu.x[f.rs1].wrapping_mul(cpu.x[f.rs2])));
+            Ok(())
+        }
+    };
+
+    fn accelerator(word: u32, _xlen: Xlen) -> Option<Instruction> {
+        match ((word >> 12) & 7, word >> 25) {
+            (0, 1) => Some(MAC),
+            _ => None
+        }
+    }
+
+    // takes add's funct3 and funct7 with the word sized opcode, which RV32 leaves free
+    fn free_encodings(word: u32, xlen: Xlen) -> Option<Instruction> {
+        match xlen {
+            Xlen::Bit32 => Some(MAC),
+            Xlen::Bit64 => panic!("{:#x} is addw on RV64", word)
+        }
+    }
+
+    #[test]
+    fn custom_decoders_fill_the_gaps_in_decoding() {
+        // mac a2, a0, a1 in custom-0 twice, then the same funct7 with funct3 1, which it leaves
+        // illegal
+        let words = [0x02b5060bu32, 0x02b5060b, 0x02b5160b];
+        let mut memory = program(&words);
+        let mut cpu = Cpu::builder().decoder(CUSTOM_0, accelerator).build();
+        cpu.x[10] = 6;
+        cpu.x[11] = 7;
+        cpu.x[12] = 1;
+        cpu.tick(&mut memory).unwrap();
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(85, cpu.x[12]);
+        let trap = cpu.tick(&mut memory).unwrap_err();
+        assert_eq!((TrapType::IllegalInstruction, 0x02b5160b), (trap.trap_type, trap.value));
+
+        // addw a2, a0, a1 goes to the hart's own decoding on RV64 and to the custom one on RV32
+        let mut memory = 0x00b5063bu32.to_le_bytes().to_vec();
+        let mut cpu = Cpu::new();
+        cpu.register_decoder(0b0111011, free_encodings);
+        cpu.x[10] = 6;
+        cpu.x[11] = 7;
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(13, cpu.x[12]);
+        let mut cpu = Cpu::builder().xlen(Xlen::Bit32).decoder(0b0111011, free_encodings).build();
+        cpu.x[10] = 6;
+        cpu.x[11] = 7;
+        cpu.x[12] = 1;
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(43, cpu.x[12]);
+
+        assert!(cpu.decoders_mut().unregister(0b0111011).is_some());
+        cpu.update_pc(0);
+        assert_eq!(TrapType::IllegalInstruction, cpu.tick(&mut memory).unwrap_err().trap_type);
+    }
+}
@@ -0,0 +1,54 @@
+use crate::cpu::host::HostFn;
+use crate::cpu::instruction::Instruction;
+use std::collections::BTreeMap;
+
+// What ecall dispatches to, a handler or host function for each system call number in a7 that
+// has one and a fallback for every other number, so independent syscall layers and an
+// application's own hypercalls can share a hart. An ecall nothing handles does nothing.
+#[derive(Clone, Debug, Default)]
+pub struct EcallHandlers {
+    handlers: BTreeMap<i64, Instruction>,
+    functions: BTreeMap<i64, HostFn>,
+    fallback: Option<Instruction>
+}
+
+impl EcallHandlers {
+    pub fn new() -> Self {
+        EcallHandlers::default()
+    }
+
+    // returns the handler number had before, if any, and replaces any host function bound to it
+    pub fn register(&mut self, number: i64, handler: Instruction) -> Option<Instruction> {
+        self.functions.remove(&number);
+        self.handlers.insert(number, handler)
+    }
+
+    // replaces any handler registered for number
+    pub fn bind(&mut self, number: i64, function: HostFn) {
+        self.handlers.remove(&number);
+        self.functions.insert(number, function);
+    }
+
+    // removes whatever handles number, leaving it to the fallback
+    pub fn unregister(&mut self, number: i64) -> Option<Instruction> {
+        self.functions.remove(&number);
+        self.handlers.remove(&number)
+    }
+
+    pub fn set_fallback(&mut self, handler: Option<Instruction>) {
+        self.fallback = handler;
+    }
+
+    pub fn fallback(&self) -> Option<Instruction> {
+        self.fallback
+    }
+
+    // the handler an ecall with number in a7 runs, when no host function is bound to it
+    pub fn get(&self, number: i64) -> Option<Instruction> {
+        self.handlers.get(&number).copied().or(self.fallback)
+    }
+
+    pub fn function(&self, number: i64) -> Option<HostFn> {
+        self.functions.get(&number).cloned()
+    }
+}
@@ -0,0 +1,431 @@
+use crate::cpu::{Cpu, Trap, Xlen};
+use crate::cpu::instruction::Instruction;
+use crate::cpu::predecode::{self, Op};
+use crate::memory::Memory;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+// longest run of straight line code kept in one block
+const MAX_BLOCK_LENGTH: usize = 64;
+
+// what the decoded instruction cache keeps together and throws away together
+const PAGE_SIZE: usize = 4096;
+
+// How the hart gets from a pc to the instruction to execute. The engines share every bit of
+// architectural state, so it is always safe to switch between them, even between two ticks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    // fetch and decode every instruction, always sees the current contents of memory
+    #[default]
+    Interpreter,
+    // decode straight line code once and replay it, guest code that modifies itself must
+    // execute FENCE.I before running the new code, as the spec requires
+    Block,
+    // fetch and decode the instruction at each pc once, keeping them a page at a time until the
+    // guest stores to the page or executes FENCE.I, so only code changed from the host needs
+    // Cpu::invalidate_code_cache. Cpu::predecode fills it ahead of time.
+    Cached
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Entry {
+    pub(crate) word: u32,
+    pub(crate) instruction: &'static Instruction,
+    // the instruction in a form that needs nothing more from the word, when it has one
+    pub(crate) op: Option<Op>,
+    // 2 or 4, kept small so that an entry is no bigger than three words
+    pub(crate) length: u8
+}
+
+impl Entry {
+    fn decode(address: usize, memory: &dyn Memory, xlen: Xlen) -> Option<Entry> {
+        let (word, length) = Cpu::fetch_word(memory, address, xlen).ok()?;
+        let instruction = Cpu::decode_for(word, xlen)?;
+        let op = predecode::resolve(word, instruction, xlen);
+        Some(Entry { word, instruction, op, length: length as u8 })
+    }
+}
+
+struct Block {
+    entries: Vec<Entry>
+}
+
+impl Block {
+    fn build(pc: usize, memory: &dyn Memory, xlen: Xlen) -> Block {
+        let mut entries = Vec::new();
+        let mut address = pc;
+        while entries.len() < MAX_BLOCK_LENGTH {
+            // stop short of anything that does not fetch or decode, the interpreter path will
+            // raise exactly the trap it always has when execution gets there
+            let entry = match Entry::decode(address, memory, xlen) {
+                Some(entry) => entry,
+                None => break
+            };
+
+            entries.push(entry);
+            address += entry.length as usize;
+            if ends_block(entry.word) {
+                break;
+            }
+        }
+
+        Block { entries }
+    }
+
+    // whether any of the code of the block starting at pc lies between low and high, both included
+    fn overlaps(&self, pc: usize, low: usize, high: usize) -> bool {
+        let end = pc + self.entries.iter().map(|entry| entry.length as usize).sum::<usize>();
+        pc <= high && low < end
+    }
+}
+
+fn ends_block(word: u32) -> bool {
+    // branches, jumps, system instructions and fences
+    matches!(word & 0x7f, 0b1100011 | 0b1101111 | 0b1100111 | 0b1110011 | 0b0001111)
+}
+
+// How well one of the host side caches is doing. A hit or miss is counted per lookup, so for
+// the block cache that is once per block entered rather than once per instruction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub invalidations: u64
+}
+
+impl CacheStats {
+    // 0 before there have been any lookups
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+// Every cache the hart keeps of guest code, all of them are emptied by Cpu::invalidate_all_caches
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Caches {
+    pub blocks: CacheStats,
+    // counted per instruction, its entries being instructions
+    pub decoded: CacheStats
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct BlockCache {
+    blocks: HashMap<usize, Arc<Block>>,
+    // the block being executed, the index of its next entry and the pc that entry lives at
+    cursor: Option<(Arc<Block>, usize, usize)>,
+    stats: CacheStats
+}
+
+impl BlockCache {
+    // None when there is nothing decodable at pc
+    #[inline(always)]
+    pub(crate) fn next(&mut self, pc: usize, memory: &dyn Memory, xlen: Xlen) -> Option<&Entry> {
+        match &self.cursor {
+            Some((block, index, next_pc)) if *next_pc == pc && *index < block.entries.len() => (),
+            _ => self.enter(pc, memory, xlen)?
+        }
+
+        let (block, index, next_pc) = self.cursor.as_mut()?;
+        let entry = &block.entries[*index];
+        *index += 1;
+        *next_pc += entry.length as usize;
+        Some(entry)
+    }
+
+    // points the cursor at the start of the block at pc, building it first when there is none
+    #[inline(never)]
+    fn enter(&mut self, pc: usize, memory: &dyn Memory, xlen: Xlen) -> Option<()> {
+        let block = match self.blocks.get(&pc) {
+            Some(block) => {
+                self.stats.hits += 1;
+                block.clone()
+            },
+            None => {
+                self.stats.misses += 1;
+                let block = Arc::new(Block::build(pc, memory, xlen));
+                if block.entries.is_empty() {
+                    self.cursor = None;
+                    return None;
+                }
+                self.blocks.insert(pc, block.clone());
+                block
+            }
+        };
+        self.cursor = Some((block, 0, pc));
+        Some(())
+    }
+
+    // throws away the blocks with code between low and high, both included
+    pub(crate) fn invalidate_range(&mut self, low: usize, high: usize) {
+        let before = self.blocks.len();
+        self.blocks.retain(|pc, block| !block.overlaps(*pc, low, high));
+        if self.blocks.len() != before {
+            self.stats.invalidations += 1;
+            self.cursor = None;
+        }
+    }
+
+    pub(crate) fn invalidate(&mut self) {
+        // only invalidations that actually threw something away are worth counting
+        if !self.blocks.is_empty() {
+            self.stats.invalidations += 1;
+        }
+        self.blocks.clear();
+        self.cursor = None;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats { entries: self.blocks.len(), ..self.stats }
+    }
+
+    // the cached blocks are kept, only the counters start again
+    pub(crate) fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+}
+
+#[derive(Clone)]
+struct Page {
+    // one for each halfword an instruction could start at
+    entries: Box<[Option<Entry>]>,
+    populated: bool
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct DecodeCache {
+    pages: Vec<Page>,
+    // where each page number's entries are in pages, pages being emptied rather than removed
+    index: HashMap<usize, usize>,
+    // the page of the last lookup, the next one nearly always being on it too
+    last: Option<(usize, usize)>,
+    stats: CacheStats
+}
+
+impl DecodeCache {
+    // None when there is nothing decodable at pc
+    #[inline(always)]
+    pub(crate) fn next(&mut self, pc: usize, memory: &dyn Memory, xlen: Xlen) -> Option<&Entry> {
+        let slot = (pc % PAGE_SIZE) / 2;
+        let index = match self.last {
+            Some((page, index)) if page == pc / PAGE_SIZE && pc & 1 == 0 && self.pages[index].entries[slot].is_some() => {
+                self.stats.hits += 1;
+                index
+            },
+            _ => self.look_up(pc, memory, xlen)?
+        };
+        self.pages[index].entries[slot].as_ref()
+    }
+
+    // where the page holding the instruction at pc is in pages, decoding the instruction first
+    // when it has not been already
+    #[inline(never)]
+    fn look_up(&mut self, pc: usize, memory: &dyn Memory, xlen: Xlen) -> Option<usize> {
+        if pc & 1 != 0 {
+            return None;
+        }
+        let page = pc / PAGE_SIZE;
+        let slot = (pc % PAGE_SIZE) / 2;
+        if let Some(&index) = self.index.get(&page) {
+            self.last = Some((page, index));
+            if self.pages[index].entries[slot].is_some() {
+                self.stats.hits += 1;
+                return Some(index);
+            }
+        }
+
+        self.stats.misses += 1;
+        let index = self.page(page);
+        self.last = Some((page, index));
+        let entry = Entry::decode(pc, memory, xlen)?;
+        self.store(index, slot, entry);
+        Some(index)
+    }
+
+    fn page(&mut self, page: usize) -> usize {
+        *self.index.entry(page).or_insert_with(|| {
+            self.pages.push(Page { entries: vec![None; PAGE_SIZE / 2].into_boxed_slice(), populated: false });
+            self.pages.len() - 1
+        })
+    }
+
+    fn store(&mut self, index: usize, slot: usize, entry: Entry) {
+        let page = &mut self.pages[index];
+        page.entries[slot] = Some(entry);
+        page.populated = true;
+    }
+
+    // decodes straight through range, which is taken to hold nothing but code, stepping over
+    // whatever does not decode a halfword at a time, and returns how many instructions it found
+    pub(crate) fn fill(&mut self, range: Range<usize>, memory: &dyn Memory, xlen: Xlen) -> usize {
+        let mut decoded = 0;
+        let mut address = range.start + (range.start & 1);
+        while address < range.end {
+            match Entry::decode(address, memory, xlen) {
+                Some(entry) => {
+                    let index = self.page(address / PAGE_SIZE);
+                    self.store(index, (address % PAGE_SIZE) / 2, entry);
+                    decoded += 1;
+                    address += entry.length as usize;
+                },
+                None => address += 2
+            }
+        }
+        decoded
+    }
+
+    // throws away the instructions on the pages from low to high, and the one before when an
+    // instruction there could run on into low
+    pub(crate) fn invalidate_range(&mut self, low: usize, high: usize) {
+        let pages = low.saturating_sub(2) / PAGE_SIZE..=high / PAGE_SIZE;
+        for (_, index) in self.index.iter().filter(|(page, _)| pages.contains(page)) {
+            let page = &mut self.pages[*index];
+            if page.populated {
+                page.entries.fill(None);
+                page.populated = false;
+                self.stats.invalidations += 1;
+            }
+        }
+    }
+
+    pub(crate) fn invalidate(&mut self) {
+        if self.pages.iter().any(|page| page.populated) {
+            self.stats.invalidations += 1;
+        }
+        self.pages.clear();
+        self.index.clear();
+        self.last = None;
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        let entries = self.pages.iter().map(|page| page.entries.iter().filter(|entry| entry.is_some()).count()).sum();
+        CacheStats { entries, ..self.stats }
+    }
+
+    pub(crate) fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+}
+
+// Whether the instruction in word could write to memory. Stores, AMOs, cache block zeroing and
+// the system instructions, whose ecalls and ebreaks hand memory to their handlers, could, nothing
+// else the hart decodes itself can.
+pub(crate) fn may_store(word: u32) -> bool {
+    matches!(word & 0x7f, 0b0100011 | 0b0100111 | 0b0101111 | 0b0001111 | 0b1110011)
+}
+
+// Passes every access through to the memory underneath, noting the lowest and highest address
+// stored to so that the decoded instructions there can be thrown away afterwards.
+pub(crate) struct Watched<'a> {
+    pub(crate) inner: &'a mut dyn Memory,
+    pub(crate) written: Option<(usize, usize)>
+}
+
+impl Watched<'_> {
+    fn note(&mut self, address: usize, size: usize) {
+        let (low, high) = self.written.unwrap_or((address, address));
+        self.written = Some((low.min(address), high.max(address + size - 1)));
+    }
+
+    fn exchanged<T>(&mut self, address: usize, size: usize, result: Result<Result<T, T>, Trap>) -> Result<Result<T, T>, Trap> {
+        if let Ok(Ok(_)) = result {
+            self.note(address, size);
+        }
+        result
+    }
+}
+
+impl Memory for Watched<'_> {
+    fn read_i8(&self, address: usize) -> Result<i8, Trap> {
+        self.inner.read_i8(address)
+    }
+
+    fn read_u8(&self, address: usize) -> Result<u8, Trap> {
+        self.inner.read_u8(address)
+    }
+
+    fn read_i16(&self, address: usize) -> Result<i16, Trap> {
+        self.inner.read_i16(address)
+    }
+
+    fn read_u16(&self, address: usize) -> Result<u16, Trap> {
+        self.inner.read_u16(address)
+    }
+
+    fn read_i32(&self, address: usize) -> Result<i32, Trap> {
+        self.inner.read_i32(address)
+    }
+
+    fn read_u32(&self, address: usize) -> Result<u32, Trap> {
+        self.inner.read_u32(address)
+    }
+
+    fn read_i64(&self, address: usize) -> Result<i64, Trap> {
+        self.inner.read_i64(address)
+    }
+
+    fn read_u64(&self, address: usize) -> Result<u64, Trap> {
+        self.inner.read_u64(address)
+    }
+
+    fn write_u8(&mut self, address: usize, value: u8) -> Result<(), Trap> {
+        self.inner.write_u8(address, value)?;
+        self.note(address, 1);
+        Ok(())
+    }
+
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), Trap> {
+        self.inner.write_u16(address, value)?;
+        self.note(address, 2);
+        Ok(())
+    }
+
+    fn write_u32(&mut self, address: usize, value: u32) -> Result<(), Trap> {
+        self.inner.write_u32(address, value)?;
+        self.note(address, 4);
+        Ok(())
+    }
+
+    fn write_u64(&mut self, address: usize, value: u64) -> Result<(), Trap> {
+        self.inner.write_u64(address, value)?;
+        self.note(address, 8);
+        Ok(())
+    }
+
+    fn fence(&mut self) {
+        self.inner.fence();
+    }
+
+    fn compare_exchange_u8(&mut self, address: usize, current: u8, new: u8) -> Result<Result<u8, u8>, Trap> {
+        let result = self.inner.compare_exchange_u8(address, current, new);
+        self.exchanged(address, 1, result)
+    }
+
+    fn compare_exchange_u16(&mut self, address: usize, current: u16, new: u16) -> Result<Result<u16, u16>, Trap> {
+        let result = self.inner.compare_exchange_u16(address, current, new);
+        self.exchanged(address, 2, result)
+    }
+
+    fn compare_exchange_u32(&mut self, address: usize, current: u32, new: u32) -> Result<Result<u32, u32>, Trap> {
+        let result = self.inner.compare_exchange_u32(address, current, new);
+        self.exchanged(address, 4, result)
+    }
+
+    fn compare_exchange_u64(&mut self, address: usize, current: u64, new: u64) -> Result<Result<u64, u64>, Trap> {
+        let result = self.inner.compare_exchange_u64(address, current, new);
+        self.exchanged(address, 8, result)
+    }
+
+    fn compare_exchange_u128(&mut self, address: usize, current: u128, new: u128) -> Result<Result<u128, u128>, Trap> {
+        let result = self.inner.compare_exchange_u128(address, current, new);
+        self.exchanged(address, 16, result)
+    }
+}
@@ -0,0 +1,133 @@
+// The standard extensions a hart implements, held as the letter bits misa gives them. Everything
+// the hart can execute is enabled by default, see the features in Cargo.toml for what it can. Turning an extension off makes its instructions
+// illegal, for checking that code built for a smaller profile really keeps to it, e.g.
+// Extensions::default().without('C').without('D')
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Extensions(u32);
+
+// the extensions the crate was built with, U standing for user mode rather than for instructions
+const SUPPORTED: [(char, bool); 10] = [('I', true), ('M', cfg!(feature = "m")), ('A', cfg!(feature = "a")), ('F', cfg!(feature = "f")),
+    ('D', cfg!(feature = "d")), ('Q', cfg!(feature = "q")), ('C', cfg!(feature = "c")), ('U', true), ('V', cfg!(feature = "v")),
+    ('P', cfg!(feature = "p"))];
+
+impl Default for Extensions {
+    fn default() -> Self {
+        SUPPORTED.iter().filter(|(_, built)| *built).fold(Extensions(0), |extensions, (letter, _)| Extensions(extensions.0 | bit(*letter)))
+    }
+}
+
+impl Extensions {
+    pub fn all() -> Self {
+        Extensions::default()
+    }
+
+    // Enables letter if the hart supports it, which it only does for the extensions whose cargo
+    // features it was built with. D needs F and Q needs D, so enabling them enables those as well.
+    pub fn with(mut self, letter: char) -> Self {
+        let letter = letter.to_ascii_uppercase();
+        if SUPPORTED.contains(&(letter, true)) {
+            self.0 |= bit(letter);
+            match letter {
+                'Q' => self.0 |= bit('D') | bit('F'),
+                'D' => self.0 |= bit('F'),
+                _ => {}
+            }
+        }
+        self
+    }
+
+    // Disables letter, other than I which every hart needs. Turning F off turns D and Q off too, and
+    // turning D off turns Q off.
+    pub fn without(mut self, letter: char) -> Self {
+        let letter = letter.to_ascii_uppercase();
+        if letter.is_ascii_uppercase() && letter != 'I' {
+            self.0 &= !bit(letter);
+        }
+        match letter {
+            'F' => self.0 &= !(bit('D') | bit('Q')),
+            'D' => self.0 &= !bit('Q'),
+            _ => {}
+        }
+        self
+    }
+
+    pub fn contains(&self, letter: char) -> bool {
+        letter.is_ascii_alphabetic() && self.0 & bit(letter.to_ascii_uppercase()) != 0
+    }
+
+    // the extensions field of misa, bit 0 for A through bit 25 for Z
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    // whether the instruction word, expanded if it was compressed, may execute
+    pub(crate) fn allows(&self, word: u32, compressed: bool) -> bool {
+        if compressed && !self.contains('C') {
+            return false;
+        }
+        match required(word) {
+            Some(letter) => self.contains(letter),
+            None => true
+        }
+    }
+}
+
+fn bit(letter: char) -> u32 {
+    1 << (letter as u32 - 'A' as u32)
+}
+
+// the extension an instruction belongs to, other than I
+pub(crate) fn required(word: u32) -> Option<char> {
+    let format = |fmt: u32| match fmt & 3 {
+        1 => 'D',
+        3 => 'Q',
+        _ => 'F'
+    };
+    match word & 0x7f {
+        0b0101111 => Some('A'),
+        0b0110011 | 0b0111011 if word >> 25 == 1 => Some('M'),
+        // loads and stores give the width in funct3, the ones that are not a float width are vector ones
+        0b0000111 | 0b0100111 => match (word >> 12) & 7 {
+            0 | 5..=7 => Some('V'),
+            3 => Some('D'),
+            4 => Some('Q'),
+            _ => Some('F')
+        },
+        // fmadd, fmsub, fnmsub and fnmadd
+        0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => Some(format(word >> 25)),
+        // conversions between formats need the wider one whichever way they go
+        0b1010011 if word >> 27 == 0b01000 => Some(format(((word >> 25) & 3).max((word >> 20) & 3))),
+        0b1010011 => Some(format(word >> 25)),
+        0b1010111 => Some('V'),
+        0b1110111 => Some('P'),
+        _ => None
+    }
+}
+
+#[cfg(all(test, feature = "f", feature = "m"))]
+mod test {
+    use super::*;
+    use crate::cpu::{Cpu, TrapType};
+
+    #[test]
+    fn disabled_extensions_are_illegal_and_left_out_of_misa() {
+        // addi a0, zero, 1; c.li a1, 3; fadd.d fa0, fa0, fa0; fadd.s fa0, fa0, fa0; mul a0, a0, a0
+        let mut memory: Vec<u8> = [0x00100513u32.to_le_bytes().to_vec(), 0x458du16.to_le_bytes().to_vec(),
+            0x02a57553u32.to_le_bytes().to_vec(), 0x00a57553u32.to_le_bytes().to_vec(), 0x02a50533u32.to_le_bytes().to_vec()].concat();
+        let mut cpu = Cpu::builder().extensions(Extensions::all().without('C').without('D')).build();
+        let (vector, packed) = ((cfg!(feature = "v") as u64) << 21, (cfg!(feature = "p") as u64) << 15);
+        assert_eq!(2 << 62 | vector | (1 << 20) | packed | (1 << 12) | (1 << 8) | (1 << 5) | 1, cpu.read_csr(0x301));
+
+        for (pc, legal) in [(0, true), (4, false), (6, false), (10, true), (14, true)] {
+            cpu.update_pc(pc);
+            match cpu.tick(&mut memory) {
+                Ok(_) => assert!(legal),
+                Err(trap) => assert_eq!((false, TrapType::IllegalInstruction), (legal, trap.trap_type))
+            }
+        }
+        assert_eq!(1, cpu.x[10]);
+
+        assert!(!Extensions::all().without('F').contains('D'));
+        assert!(Extensions::all().without('F').with('D').contains('F'));
+    }
+}
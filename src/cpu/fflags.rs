@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+pub const FFLAG_NX: u64 = 1;
+pub const FFLAG_UF: u64 = 2;
+pub const FFLAG_OF: u64 = 4;
+pub const FFLAG_DZ: u64 = 8;
+pub const FFLAG_NV: u64 = 16;
+
+const FLAG_NAMES: [&str; 5] = ["NX", "UF", "OF", "DZ", "NV"];
+
+// how many times each fflags bit was raised by the instruction at one pc
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FflagsSite {
+    pub counts: [u64; 5]
+}
+
+impl FflagsSite {
+    // how often flag, one of the FFLAG constants, was raised here, 0 for anything that is not
+    // exactly one of them
+    pub fn count(&self, flag: u64) -> u64 {
+        match flag.is_power_of_two() {
+            true => self.counts.get(flag.trailing_zeros() as usize).copied().unwrap_or(0),
+            false => 0
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FflagsProvenance {
+    sites: HashMap<usize, FflagsSite>
+}
+
+impl FflagsProvenance {
+    pub fn record(&mut self, pc: usize, raised: u64) {
+        let site = self.sites.entry(pc).or_default();
+        for (bit, count) in site.counts.iter_mut().enumerate() {
+            if raised & (1 << bit) != 0 {
+                *count += 1;
+            }
+        }
+    }
+
+    pub fn site(&self, pc: usize) -> Option<&FflagsSite> {
+        self.sites.get(&pc)
+    }
+
+    // all sites that raised at least one flag, ordered by pc
+    pub fn report(&self) -> Vec<(usize, FflagsSite)> {
+        let mut report: Vec<(usize, FflagsSite)> = self.sites.iter().map(|(pc, site)| (*pc, site.clone())).collect();
+        report.sort_by_key(|(pc, _)| *pc);
+        report
+    }
+}
+
+impl Display for FflagsProvenance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (pc, site) in self.report() {
+            write!(f, "{:#x}:", pc)?;
+            for (name, count) in FLAG_NAMES.iter().zip(site.counts.iter()) {
+                if *count > 0 {
+                    write!(f, " {}={}", name, count)?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_single_flags_are_counted() {
+        let mut provenance = FflagsProvenance::default();
+        provenance.record(0x100, FFLAG_NX | FFLAG_NV);
+        let site = provenance.site(0x100).unwrap();
+        assert_eq!(1, site.count(FFLAG_NX));
+        assert_eq!(1, site.count(FFLAG_NV));
+        assert_eq!(0, site.count(FFLAG_DZ));
+        assert_eq!(0, site.count(0));
+        assert_eq!(0, site.count(FFLAG_NX | FFLAG_NV));
+        assert_eq!(0, site.count(1 << 5));
+    }
+}
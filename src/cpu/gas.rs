@@ -0,0 +1,81 @@
+use crate::cpu::Cpu;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// What each instruction costs to run, by the name the decoder gives it (ADDI, DIV and so on).
+// Compressed instructions cost what the instructions they expand to do.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GasTable {
+    default: u64,
+    costs: HashMap<String, u64>
+}
+
+impl GasTable {
+    // default is what every instruction not given a cost of its own costs
+    pub fn new(default: u64) -> Self {
+        GasTable { default, costs: HashMap::new() }
+    }
+
+    pub fn cost_of(mut self, name: &str, cost: u64) -> Self {
+        self.costs.insert(name.to_string(), cost);
+        self
+    }
+
+    pub fn cost(&self, name: &str) -> u64 {
+        self.costs.get(name).copied().unwrap_or(self.default)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Gas {
+    pub(crate) table: Arc<GasTable>,
+    pub(crate) remaining: u64
+}
+
+impl Cpu {
+    // Makes every instruction pay what table says from the gas set with set_gas before it runs.
+    // One there is not enough left for traps with TrapType::OutOfGas, which run reports as
+    // StopReason::OutOfGas, without having done anything, so adding gas and carrying on runs it
+    // then. None stops metering.
+    pub fn set_gas_metering(&mut self, table: Option<GasTable>) {
+        let remaining = self.gas();
+        self.gas = table.map(|table| Gas { table: Arc::new(table), remaining });
+        self.update_observed();
+    }
+
+    pub fn set_gas(&mut self, gas: u64) {
+        if let Some(metered) = &mut self.gas {
+            metered.remaining = gas;
+        }
+    }
+
+    // what is left, 0 when gas is not being metered
+    pub fn gas(&self) -> u64 {
+        self.gas.as_ref().map_or(0, |gas| gas.remaining)
+    }
+}
+
+#[cfg(all(test, feature = "m"))]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+    use crate::cpu::{Register, RunConfig, StopReason};
+
+    #[test]
+    fn instructions_stop_when_gas_runs_out() {
+        // li a0, 7; li a1, 2; div a2, a0, a1; sw a2, 0x80(zero)
+        let words = [0x00700513u32, 0x00200593, 0x02b54633, 0x08c02023];
+        let mut memory = [program(&words), vec![0; 0x80]].concat();
+        let mut cpu = Cpu::new();
+        cpu.set_gas_metering(Some(GasTable::new(1).cost_of("DIV", 10)));
+        cpu.set_gas(11);
+
+        // the divide is not paid for, so it has neither written a2 nor counted as executed
+        assert!(matches!(cpu.run(&mut memory, &RunConfig::new()), StopReason::OutOfGas { pc: 8 }));
+        assert_eq!((9, 0, 2), (cpu.gas(), cpu.get_register(Register::A2), cpu.ticks()));
+
+        cpu.set_gas(11);
+        assert!(matches!(cpu.run(&mut memory, &RunConfig::new().fuel(2)), StopReason::FuelExhausted));
+        assert_eq!((0, 3, 3), (cpu.gas(), cpu.get_register(Register::A2), memory[0x80]));
+    }
+}
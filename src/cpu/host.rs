@@ -0,0 +1,153 @@
+use crate::cpu::{Cpu, Trap};
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+// integer arguments start in a0 and floating point ones in fa0
+const FIRST_ARGUMENT: usize = 10;
+
+// A value a host function takes from the next free argument register of its class.
+pub trait HostArg: Sized {
+    fn take(cpu: &mut Cpu, next: &mut NextArgument) -> Self;
+}
+
+// A value a host function hands back in a0, or fa0 for floating point.
+pub trait HostReturn {
+    fn store(self, cpu: &mut Cpu);
+}
+
+// The argument list of a host function, a tuple of up to eight HostArgs.
+pub trait HostArgs: Sized {
+    fn take(cpu: &mut Cpu) -> Self;
+}
+
+// how many integer and floating point argument registers have been taken so far, there are
+// never more than the eight of each class the ABI passes arguments in
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NextArgument {
+    integer: usize,
+    float: usize
+}
+
+impl NextArgument {
+    fn integer(&mut self) -> usize {
+        self.integer += 1;
+        FIRST_ARGUMENT + self.integer - 1
+    }
+
+    fn float(&mut self) -> usize {
+        self.float += 1;
+        FIRST_ARGUMENT + self.float - 1
+    }
+}
+
+macro_rules! integer {
+    ($($t:ty),*) => {$(
+        impl HostArg for $t {
+            fn take(cpu: &mut Cpu, next: &mut NextArgument) -> Self {
+                cpu.x[next.integer()] as $t
+            }
+        }
+
+        impl HostReturn for $t {
+            fn store(self, cpu: &mut Cpu) {
+                cpu.x[FIRST_ARGUMENT] = cpu.sign_extend(self as i64);
+            }
+        }
+    )*};
+}
+
+integer!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl HostArg for bool {
+    fn take(cpu: &mut Cpu, next: &mut NextArgument) -> Self {
+        cpu.x[next.integer()] != 0
+    }
+}
+
+impl HostReturn for bool {
+    fn store(self, cpu: &mut Cpu) {
+        cpu.x[FIRST_ARGUMENT] = self as i64;
+    }
+}
+
+impl HostArg for f64 {
+    fn take(cpu: &mut Cpu, next: &mut NextArgument) -> Self {
+        cpu.f[next.float()]
+    }
+}
+
+impl HostReturn for f64 {
+    fn store(self, cpu: &mut Cpu) {
+        cpu.f[FIRST_ARGUMENT] = self;
+    }
+}
+
+impl HostArg for f32 {
+    fn take(cpu: &mut Cpu, next: &mut NextArgument) -> Self {
+        cpu.get_f32(next.float())
+    }
+}
+
+impl HostReturn for f32 {
+    fn store(self, cpu: &mut Cpu) {
+        cpu.set_f32(FIRST_ARGUMENT, self);
+    }
+}
+
+// leaves a0 as it was
+impl HostReturn for () {
+    fn store(self, _cpu: &mut Cpu) {}
+}
+
+macro_rules! arguments {
+    ($($name:ident),*) => {
+        impl<$($name: HostArg),*> HostArgs for ($($name,)*) {
+            #[allow(unused_variables, unused_mut, clippy::unused_unit)]
+            fn take(cpu: &mut Cpu) -> Self {
+                let mut next = NextArgument::default();
+                ($($name::take(cpu, &mut next),)*)
+            }
+        }
+    };
+}
+
+arguments!();
+arguments!(A);
+arguments!(A, B);
+arguments!(A, B, C);
+arguments!(A, B, C, D);
+arguments!(A, B, C, D, E);
+arguments!(A, B, C, D, E, F);
+arguments!(A, B, C, D, E, F, G);
+arguments!(A, B, C, D, E, F, G, H);
+
+type Call = dyn FnMut(&mut Cpu) -> Result<(), Trap> + Send;
+
+// A host function bound to an ecall number. Clones of a hart share the same function, and with
+// it any state the function keeps.
+#[derive(Clone)]
+pub struct HostFn(Arc<Mutex<Call>>);
+
+impl HostFn {
+    pub fn new<A, R, F>(mut function: F) -> Self
+    where
+        A: HostArgs,
+        R: HostReturn,
+        F: FnMut(A) -> Result<R, Trap> + Send + 'static
+    {
+        HostFn(Arc::new(Mutex::new(move |cpu: &mut Cpu| {
+            function(A::take(cpu)).map(|result| result.store(cpu))
+        })))
+    }
+
+    pub fn call(&self, cpu: &mut Cpu) -> Result<(), Trap> {
+        (self.0.lock().unwrap())(cpu)
+    }
+}
+
+impl Debug for HostFn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("HostFn")
+    }
+}
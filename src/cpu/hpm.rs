@@ -0,0 +1,79 @@
+use crate::cpu::Cpu;
+
+// What the programmable counters can be set to count, by writing one of these to the
+// mhpmevent CSR of the counter. Anything else counts nothing.
+pub const EVENT_BRANCHES: u64 = 1;
+pub const EVENT_TAKEN_BRANCHES: u64 = 2;
+pub const EVENT_LOADS: u64 = 3;
+pub const EVENT_STORES: u64 = 4;
+pub const EVENT_FP_OPS: u64 = 5;
+pub const EVENT_JUMPS: u64 = 6;
+pub const EVENT_ATOMICS: u64 = 7;
+
+const MHPMCOUNTER3: usize = 0xb03;
+const MHPMEVENT3: usize = 0x323;
+const COUNTERS: usize = 29;
+
+impl Cpu {
+    // Counts the retired instruction word towards every counter set to an event it is, taken
+    // being whether it moved the pc somewhere other than the next instruction.
+    pub(crate) fn count_events(&mut self, word: u32, taken: bool) {
+        let opcode = word & 0x7f;
+        let happened = |event: u64| match event {
+            EVENT_BRANCHES => opcode == 0b1100011,
+            EVENT_TAKEN_BRANCHES => opcode == 0b1100011 && taken,
+            // atomics both load and store, bar the store conditional and load reserved
+            EVENT_LOADS => matches!(opcode, 0b0000011 | 0b0000111) || (opcode == 0b0101111 && word >> 27 != 0b00011),
+            EVENT_STORES => matches!(opcode, 0b0100011 | 0b0100111) || (opcode == 0b0101111 && word >> 27 != 0b00010),
+            EVENT_FP_OPS => matches!(opcode, 0b1010011 | 0b1000011 | 0b1000111 | 0b1001011 | 0b1001111),
+            EVENT_JUMPS => matches!(opcode, 0b1101111 | 0b1100111),
+            EVENT_ATOMICS => opcode == 0b0101111,
+            _ => false
+        };
+        for counter in 0..COUNTERS {
+            if happened(self.csr[MHPMEVENT3 + counter]) {
+                self.csr[MHPMCOUNTER3 + counter] = self.csr[MHPMCOUNTER3 + counter].wrapping_add(1);
+            }
+        }
+    }
+
+    // has to be called whenever the mhpmevent CSRs might have changed
+    pub(crate) fn update_event_counting(&mut self) {
+        self.counting_events = self.csr[MHPMEVENT3..MHPMEVENT3 + COUNTERS].iter().any(|event| *event != 0);
+        self.update_observed();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Register;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn programmed_counters_count_their_events() {
+        // li a0, 3; loop: lw a1, 0x80(zero); sw a1, 0x84(zero); addi a0, a0, -1; bnez a0, loop;
+        // csrr a2, hpmcounter3; csrr a3, hpmcounter4; csrr a4, hpmcounter5
+        let words = [0x00300513u32, 0x08002583, 0x08b02223, 0xfff50513, 0xfe051ae3, 0xc0302673, 0xc04026f3, 0xc0502773];
+        let mut memory = [program(&words), vec![0; 0x80]].concat();
+        let mut cpu = Cpu::new();
+        cpu.write_csr(0x323, EVENT_TAKEN_BRANCHES);
+        cpu.write_csr(0x324, EVENT_LOADS);
+        cpu.write_csr(0x325, EVENT_STORES);
+        cpu.write_csr(0xb85, 1);
+
+        for _ in 0..16 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!(2, cpu.get_register(Register::A2));
+        assert_eq!(3, cpu.get_register(Register::A3));
+        assert_eq!(1 << 32 | 3, cpu.get_register(Register::A4));
+        assert_eq!(1, cpu.read_csr(0xc85));
+
+        // the counters stop once nothing is programmed
+        cpu.write_csr(0x324, 0);
+        cpu.update_pc(4);
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(3, cpu.read_csr(0xb04));
+    }
+}
@@ -2,6 +2,7 @@ use crate::cpu::{Cpu, Memory, Trap};
 use std::fmt::{Debug, Formatter};
 use std::fmt;
 
+#[derive(Clone, Copy)]
 pub struct Instruction {
     pub name: &'static str,
     pub operation: fn(cpu: &mut Cpu, memory: &mut dyn Memory, word: u32, address: usize) -> Result<(), Trap>
@@ -0,0 +1,71 @@
+use crate::cpu::Cpu;
+use std::collections::VecDeque;
+
+// One instruction the hart started executing, faulting ones included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PcRecord {
+    pub pc: usize,
+    // where execution went next when it did not simply fall through, for taken branches, jumps
+    // and anything else that moved the pc
+    pub target: Option<usize>
+}
+
+// The last few pcs, kept for working out how a guest got to a fault after the fact at the cost
+// of a push per instruction rather than a tracer call.
+#[derive(Clone, Debug)]
+pub(crate) struct PcHistory {
+    records: VecDeque<PcRecord>,
+    capacity: usize
+}
+
+impl PcHistory {
+    pub(crate) fn push(&mut self, pc: usize) {
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(PcRecord { pc, target: None });
+    }
+
+    // the instruction pushed last was to fall through to fallthrough and went to pc instead
+    pub(crate) fn moved(&mut self, fallthrough: usize, pc: usize) {
+        if let (true, Some(record)) = (fallthrough != pc, self.records.back_mut()) {
+            record.target = Some(pc);
+        }
+    }
+}
+
+impl Cpu {
+    // Keeps the last capacity pcs executed from now on, 0 stops keeping them.
+    pub fn set_pc_history(&mut self, capacity: usize) {
+        self.pc_history = (capacity > 0).then(|| PcHistory { records: VecDeque::with_capacity(capacity), capacity });
+        self.update_observed();
+    }
+
+    // oldest first, so after a fault the last one is the pc of the instruction responsible
+    pub fn pc_history(&self) -> Vec<PcRecord> {
+        self.pc_history.as_ref().map_or(Vec::new(), |history| history.records.iter().copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn the_path_into_a_fault_is_kept() {
+        // li a0, 2; loop: addi a0, a0, -1; bnez a0, loop; jal 0x14; (gap) 0x14: lw a1, 0x100(zero)
+        let words = [0x00200513u32, 0xfff50513, 0xfe051ee3, 0x0080006f, 0x00000013, 0x10002583];
+        let mut memory = program(&words);
+        let mut cpu = Cpu::new();
+        cpu.set_pc_history(4);
+
+        while cpu.tick(&mut memory).is_ok() {}
+        assert_eq!(vec![
+            PcRecord { pc: 0x04, target: None },
+            PcRecord { pc: 0x08, target: None },
+            PcRecord { pc: 0x0c, target: Some(0x14) },
+            PcRecord { pc: 0x14, target: None }
+        ], cpu.pc_history());
+    }
+}
@@ -0,0 +1,238 @@
+use crate::cpu::{instruction, rv64ui, Cpu, Xlen};
+use crate::cpu::instruction::Instruction;
+
+// The common RV64I instructions with everything they take from the word already worked out, so
+// that running one is a register operation and nothing else. Anything not here runs through its
+// Instruction as always. There is one beside every instruction the caching engines hold, so it
+// is kept to a word, anything relative to the pc being an offset from the instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Op {
+    // shift amounts are masked to the register width they were resolved for
+    Immediate { alu: Alu, rd: u8, rs1: u8, imm: i32 },
+    Register { alu: Alu, rd: u8, rs1: u8, rs2: u8 },
+    Lui { rd: u8, value: i32 },
+    Auipc { rd: u8, offset: i32 },
+    Branch { condition: Condition, rs1: u8, rs2: u8, offset: i32 },
+    // jal, which links to wherever pc has moved on to
+    Jump { rd: u8, offset: i32 }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Alu {
+    Add,
+    AddWord,
+    Sub,
+    SubWord,
+    And,
+    Or,
+    Xor,
+    Less,
+    LessUnsigned,
+    ShiftLeft,
+    ShiftRight,
+    ShiftRightArithmetic
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    GreaterOrEqual,
+    LessUnsigned,
+    GreaterOrEqualUnsigned
+}
+
+// how an instruction with a fast path takes its operands from the word
+#[derive(Clone, Copy)]
+enum Form {
+    Immediate(Alu),
+    Shift(Alu),
+    Register(Alu),
+    Lui,
+    Auipc,
+    Branch(Condition),
+    Jump
+}
+
+// Keyed on the instruction itself rather than its name, the base instructions being statics so
+// that every decode of one hands back the same reference.
+static FAST_PATHS: [(&Instruction, Form); 28] = [
+    (&rv64ui::ADDI, Form::Immediate(Alu::Add)),
+    (&rv64ui::ADDIW, Form::Immediate(Alu::AddWord)),
+    (&rv64ui::ANDI, Form::Immediate(Alu::And)),
+    (&rv64ui::ORI, Form::Immediate(Alu::Or)),
+    (&rv64ui::XORI, Form::Immediate(Alu::Xor)),
+    (&rv64ui::SLTI, Form::Immediate(Alu::Less)),
+    (&rv64ui::SLTIU, Form::Immediate(Alu::LessUnsigned)),
+    (&rv64ui::SLLI, Form::Shift(Alu::ShiftLeft)),
+    (&rv64ui::SRLI, Form::Shift(Alu::ShiftRight)),
+    (&rv64ui::SRAI, Form::Shift(Alu::ShiftRightArithmetic)),
+    (&rv64ui::ADD, Form::Register(Alu::Add)),
+    (&rv64ui::ADDW, Form::Register(Alu::AddWord)),
+    (&rv64ui::SUB, Form::Register(Alu::Sub)),
+    (&rv64ui::SUBW, Form::Register(Alu::SubWord)),
+    (&rv64ui::AND, Form::Register(Alu::And)),
+    (&rv64ui::OR, Form::Register(Alu::Or)),
+    (&rv64ui::XOR, Form::Register(Alu::Xor)),
+    (&rv64ui::SLT, Form::Register(Alu::Less)),
+    (&rv64ui::SLTU, Form::Register(Alu::LessUnsigned)),
+    (&rv64ui::LUI, Form::Lui),
+    (&rv64ui::AUIPC, Form::Auipc),
+    (&rv64ui::BEQ, Form::Branch(Condition::Equal)),
+    (&rv64ui::BNE, Form::Branch(Condition::NotEqual)),
+    (&rv64ui::BLT, Form::Branch(Condition::Less)),
+    (&rv64ui::BGE, Form::Branch(Condition::GreaterOrEqual)),
+    (&rv64ui::BLTU, Form::Branch(Condition::LessUnsigned)),
+    (&rv64ui::BGEU, Form::Branch(Condition::GreaterOrEqualUnsigned)),
+    (&rv64ui::JAL, Form::Jump)
+];
+
+// word is the uncompressed form of an instruction
+pub(crate) fn resolve(word: u32, instruction: &Instruction, xlen: Xlen) -> Option<Op> {
+    let (_, form) = FAST_PATHS.iter().find(|(fast, _)| std::ptr::eq(*fast, instruction))?;
+    let i = instruction::parse_format_i(word);
+    let r = instruction::parse_format_r(word);
+    let (rd, rs1, rs2) = (r.rd as u8, r.rs1 as u8, r.rs2 as u8);
+    let shamt = (word >> 20) as i32 & match xlen {
+        Xlen::Bit32 => 0x1f,
+        Xlen::Bit64 => 0x3f
+    };
+    Some(match *form {
+        Form::Immediate(alu) => Op::Immediate { alu, rd, rs1, imm: i.imm as i32 },
+        Form::Shift(alu) => Op::Immediate { alu, rd, rs1, imm: shamt },
+        Form::Register(alu) => Op::Register { alu, rd, rs1, rs2 },
+        Form::Lui => Op::Lui { rd, value: instruction::parse_format_u(word).imm as i32 },
+        Form::Auipc => Op::Auipc { rd, offset: instruction::parse_format_u(word).imm as i32 },
+        Form::Branch(condition) => Op::Branch { condition, rs1, rs2, offset: instruction::parse_format_b(word).imm as i32 },
+        Form::Jump => Op::Jump { rd, offset: instruction::parse_format_j(word).imm as i32 }
+    })
+}
+
+impl Cpu {
+    #[inline(always)]
+    fn alu(&self, alu: Alu, a: i64, b: i64) -> i64 {
+        match alu {
+            Alu::Add => self.sign_extend(a.wrapping_add(b)),
+            Alu::AddWord => a.wrapping_add(b) as i32 as i64,
+            Alu::Sub => self.sign_extend(a.wrapping_sub(b)),
+            Alu::SubWord => a.wrapping_sub(b) as i32 as i64,
+            Alu::And => self.sign_extend(a & b),
+            Alu::Or => self.sign_extend(a | b),
+            Alu::Xor => self.sign_extend(a ^ b),
+            Alu::Less => (a < b) as i64,
+            Alu::LessUnsigned => (self.unsigned_data(a) < self.unsigned_data(b)) as i64,
+            Alu::ShiftLeft => self.sign_extend(a << b),
+            Alu::ShiftRight => self.sign_extend((self.unsigned_data(a) >> b) as i64),
+            Alu::ShiftRightArithmetic => self.sign_extend(a >> b)
+        }
+    }
+
+    // the op of the instruction at address, pc having already moved past it as it has for
+    // Instruction::operation
+    #[inline(always)]
+    pub(crate) fn perform(&mut self, op: Op, address: usize) {
+        match op {
+            Op::Immediate { alu, rd, rs1, imm } => self.x[rd as usize] = self.alu(alu, self.x[rs1 as usize], imm as i64),
+            Op::Register { alu, rd, rs1, rs2 } => self.x[rd as usize] = self.alu(alu, self.x[rs1 as usize], self.x[rs2 as usize]),
+            Op::Lui { rd, value } => self.x[rd as usize] = value as i64,
+            Op::Auipc { rd, offset } => self.x[rd as usize] = self.sign_extend(address.wrapping_add(offset as usize) as i64),
+            Op::Branch { condition, rs1, rs2, offset } => {
+                let (a, b) = (self.sign_extend(self.x[rs1 as usize]), self.sign_extend(self.x[rs2 as usize]));
+                let taken = match condition {
+                    Condition::Equal => a == b,
+                    Condition::NotEqual => a != b,
+                    Condition::Less => a < b,
+                    Condition::GreaterOrEqual => a >= b,
+                    Condition::LessUnsigned => self.unsigned_data(a) < self.unsigned_data(b),
+                    Condition::GreaterOrEqualUnsigned => self.unsigned_data(a) >= self.unsigned_data(b)
+                };
+                if taken {
+                    self.pc = address.wrapping_add(offset as usize);
+                }
+            },
+            Op::Jump { rd, offset } => {
+                self.x[rd as usize] = self.sign_extend(self.pc as i64);
+                self.pc = address.wrapping_add(offset as usize);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolved_instructions_do_what_their_words_do() {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut resolved = 0;
+        for xlen in [Xlen::Bit32, Xlen::Bit64] {
+            for _ in 0..200000 {
+                let memory = (random() as u32).to_le_bytes().to_vec();
+                let Ok((word, length)) = Cpu::fetch_word(&memory, 0, xlen) else { continue };
+                let Some(instruction) = Cpu::decode_for(word, xlen) else { continue };
+                let address = random() as usize & !1;
+                let Some(op) = resolve(word, instruction, xlen) else { continue };
+                resolved += 1;
+
+                let mut expected = Cpu::builder().xlen(xlen).build();
+                for register in 1..32 {
+                    // a few small values so that the comparisons come out both ways
+                    let value = match random() % 4 {
+                        0 => random() as i64 % 4,
+                        _ => random() as i64
+                    };
+                    expected.x[register] = expected.sign_extend(value);
+                }
+                expected.pc = address.wrapping_add(length);
+                let mut actual = expected.clone();
+                (instruction.operation)(&mut expected, &mut Vec::new(), word, address).unwrap();
+                actual.perform(op, address);
+                expected.x[0] = 0;
+                actual.x[0] = 0;
+                assert_eq!((expected.x, expected.pc), (actual.x, actual.pc), "{} {:#010x}", instruction.name, word);
+            }
+        }
+        assert!(resolved > 10000);
+    }
+
+    #[test]
+    fn every_fast_path_is_found_from_what_the_decoder_returns() {
+        // one encoding of each, with rd, rs1 and rs2 set so none of them is a hint or compressed
+        let words = [
+            0x00a58513, 0x00a5851b, 0x00a5f513, 0x00a5e513, 0x00a5c513, 0x00a5a513, 0x00a5b513,
+            0x00359513, 0x0035d513, 0x4035d513, 0x00c58533, 0x00c5853b, 0x40c58533, 0x40c5853b,
+            0x00c5f533, 0x00c5e533, 0x00c5c533, 0x00c5a533, 0x00c5b533, 0x12345537, 0x12345517,
+            0x00c58463, 0x00c59463, 0x00c5c463, 0x00c5d463, 0x00c5e463, 0x00c5f463, 0x0080056f
+        ];
+        for (word, (fast, _)) in words.into_iter().zip(FAST_PATHS.iter()) {
+            let instruction = Cpu::decode_for(word, Xlen::Bit64).unwrap();
+            assert_eq!(fast.name, instruction.name, "{:#010x}", word);
+            assert!(resolve(word, instruction, Xlen::Bit64).is_some(), "{}", fast.name);
+            if !fast.name.ends_with('W') {
+                assert!(resolve(word, Cpu::decode_for(word, Xlen::Bit32).unwrap(), Xlen::Bit32).is_some(), "{}", fast.name);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "c")]
+    fn compressed_forms_find_the_same_fast_paths() {
+        // c.addi a0, 1
+        let (word, _) = Cpu::fetch_word(&0x0505u32.to_le_bytes().to_vec(), 0, Xlen::Bit64).unwrap();
+        assert!(resolve(word, Cpu::decode_for(word, Xlen::Bit64).unwrap(), Xlen::Bit64).is_some());
+    }
+
+    #[test]
+    fn an_op_fits_beside_its_instruction() {
+        assert_eq!(std::mem::size_of::<Op>(), 8);
+        assert_eq!(std::mem::size_of::<Option<crate::cpu::engine::Entry>>(), 24);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::cpu::Cpu;
+use std::collections::BTreeMap;
+
+// What a guest spent its instructions on, counted as they retire so trapping instructions are
+// left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub instructions: u64,
+    // by the name the decoder gives them, ADDI and so on
+    pub mnemonics: BTreeMap<&'static str, u64>,
+    pub compressed: u64,
+    // atomics count as a load, a store or both, the way they touch memory
+    pub loads: u64,
+    pub stores: u64,
+    // conditional branches only, jumps are always taken
+    pub branches: u64,
+    pub taken_branches: u64
+}
+
+impl Profile {
+    pub fn uncompressed(&self) -> u64 {
+        self.instructions - self.compressed
+    }
+
+    pub(crate) fn count(&mut self, word: u32, name: &'static str, compressed: bool, moved: bool) {
+        self.instructions += 1;
+        *self.mnemonics.entry(name).or_insert(0) += 1;
+        self.compressed += compressed as u64;
+        let (load, store) = match word & 0x7f {
+            0b0000011 | 0b0000111 => (true, false),
+            0b0100011 | 0b0100111 => (false, true),
+            0b0101111 => match word >> 27 {
+                0b00010 => (true, false),
+                0b00011 => (false, true),
+                _ => (true, true)
+            },
+            _ => (false, false)
+        };
+        self.loads += load as u64;
+        self.stores += store as u64;
+        if word & 0x7f == 0b1100011 {
+            self.branches += 1;
+            self.taken_branches += moved as u64;
+        }
+    }
+}
+
+impl Cpu {
+    // Starts counting what the hart executes, or stops and throws the counts away. Counting again
+    // after stopping starts from zero.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = match enabled {
+            true => Some(self.profile.take().unwrap_or_default()),
+            false => None
+        };
+        self.update_observed();
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+}
+
+#[cfg(all(test, feature = "c"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn instructions_are_counted_by_kind() {
+        // li a0, 3; loop: c.addi a0, -1; sw a0, 0x80(zero); bnez a0, loop; lw a1, 0x80(zero)
+        let mut memory: Vec<u8> = [0x00300513u32.to_le_bytes().to_vec(), 0x157du16.to_le_bytes().to_vec(), 0x08a02023u32.to_le_bytes().to_vec(),
+            0xfe051de3u32.to_le_bytes().to_vec(), 0x08002583u32.to_le_bytes().to_vec(), vec![0; 0x80]].concat();
+        let mut cpu = Cpu::new();
+        cpu.set_profiling(true);
+
+        for _ in 0..11 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        let profile = cpu.profile().unwrap();
+        assert_eq!((11, 3, 8), (profile.instructions, profile.compressed, profile.uncompressed()));
+        assert_eq!((1, 3), (profile.loads, profile.stores));
+        assert_eq!((3, 2), (profile.branches, profile.taken_branches));
+        assert_eq!(Some(&3), profile.mnemonics.get("BNE"));
+        // compressed instructions go by the name of what they expand to
+        assert_eq!(Some(&4), profile.mnemonics.get("ADDI"));
+    }
+}
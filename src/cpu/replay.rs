@@ -0,0 +1,282 @@
+use crate::cpu::{Cpu, Trap};
+use crate::cpu::step::{AccessKind, MemoryAccess, Recording, WrittenRegister};
+use crate::memory::Memory;
+use std::cell::RefCell;
+
+// Everything the guest can see that does not follow from its own code and memory: what ecalls
+// hand back, which is also where clock and random bytes reach a guest, reads of the time CSR and
+// changes the host makes to the hart between instructions. Recording notes each of them down as
+// it happens and replaying hands the guest the same ones again, so a run can be reproduced
+// exactly without the host, its clock or its files.
+#[derive(Clone, Debug)]
+pub enum Input {
+    // what an ecall handler or host function left behind, and the trap it ended in if any
+    Ecall(Effects, Option<Trap>),
+    // a read of the time CSR
+    Time(u64),
+    // a change the host made through inject, such as delivering an interrupt or a signal
+    Injected(Effects)
+}
+
+// What a change from outside did to the hart, enough to make it again without its cause.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Effects {
+    pub pc: usize,
+    pub written_registers: Vec<WrittenRegister>,
+    // only the stores, in the order they were made
+    pub writes: Vec<MemoryAccess>
+}
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    // when the input arrived, see Cpu::ticks
+    pub ticks: u64,
+    pub input: Input
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReplayLog {
+    pub events: Vec<Event>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayStatus {
+    Off,
+    Recording,
+    Replaying { remaining: usize },
+    // The guest asked for something other than the next input in the log, at this tick. It has
+    // been running on live inputs since.
+    Diverged { ticks: u64 }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) enum Mode {
+    #[default]
+    Off,
+    Recording(ReplayLog),
+    // with record the log carries on being recorded into once the replay reaches its end
+    Replaying { log: ReplayLog, next: usize, record: bool },
+    Diverged { ticks: u64 }
+}
+
+impl Cpu {
+    // Starts a new log, leaving any earlier one behind. The hart and memory as they are now are
+    // where a replay has to start from, so a snapshot or fork goes with the log.
+    pub fn start_recording(&mut self) {
+        self.replay = Mode::Recording(ReplayLog::default());
+    }
+
+    // what was recorded since start_recording, if recording
+    pub fn stop_recording(&mut self) -> Option<ReplayLog> {
+        match std::mem::take(&mut self.replay) {
+            Mode::Recording(log) | Mode::Replaying { log, record: true, .. } => Some(log),
+            mode => {
+                self.replay = mode;
+                None
+            }
+        }
+    }
+
+    // Feeds the guest the inputs in log instead of live ones, until it runs past the end of the
+    // log or diverges from it.
+    pub fn start_replay(&mut self, log: ReplayLog) {
+        self.replay = Mode::Replaying { log, next: 0, record: false };
+    }
+
+    pub fn replay_status(&self) -> ReplayStatus {
+        match &self.replay {
+            Mode::Off => ReplayStatus::Off,
+            Mode::Recording(_) | Mode::Replaying { record: true, .. } => ReplayStatus::Recording,
+            Mode::Replaying { log, next, record: false } => ReplayStatus::Replaying { remaining: log.events.len() - next },
+            Mode::Diverged { ticks } => ReplayStatus::Diverged { ticks: *ticks }
+        }
+    }
+
+    // Changes the hart from outside between two instructions, the way a host delivers interrupts
+    // and signals. Recording notes what the change did, replaying makes the recorded change again
+    // instead of calling it.
+    pub fn inject<F: FnOnce(&mut Cpu, &mut dyn Memory)>(&mut self, memory: &mut dyn Memory, change: F) {
+        if let Some(Input::Injected(effects)) = self.replayed(|input| matches!(input, Input::Injected(_))) {
+            // the writes were all made once already, at the same addresses
+            let _ = self.apply(memory, &effects);
+            return;
+        }
+        let ((), effects) = self.capture(memory, change);
+        self.note(Input::Injected(effects));
+    }
+
+    // runs the handler for the ecall that has just been fetched, or what it did last time
+    pub(crate) fn ecall(&mut self, memory: &mut dyn Memory, word: u32, address: usize) -> Result<(), Trap> {
+        if let Some(Input::Ecall(effects, trap)) = self.replayed(|input| matches!(input, Input::Ecall(..))) {
+            self.apply(memory, &effects)?;
+            return trap.map_or(Ok(()), Err);
+        }
+        let (result, effects) = self.capture(memory, |cpu, memory| {
+            if let Some(function) = cpu.ecall_handlers.function(cpu.x[17]) {
+                function.call(cpu)
+            } else if let Some(handler) = cpu.ecall_handlers.get(cpu.x[17]) {
+                (handler.operation)(cpu, memory, word, address)
+            } else {
+                Ok(())
+            }
+        });
+        self.note(Input::Ecall(effects, result.clone().err()));
+        result
+    }
+
+    // Serves an ecall from outside tick, the way Linux::syscall does once the ecall has trapped
+    // out, recording what serve did or handing back what it did last time instead of calling it.
+    pub fn host_ecall<F: FnOnce(&mut Cpu, &mut dyn Memory) -> Result<(), Trap>>(&mut self, memory: &mut dyn Memory, serve: F) -> Result<(), Trap> {
+        if let Some(Input::Ecall(effects, trap)) = self.replayed(|input| matches!(input, Input::Ecall(..))) {
+            self.apply(memory, &effects)?;
+            return trap.map_or(Ok(()), Err);
+        }
+        let (result, effects) = self.capture(memory, serve);
+        self.note(Input::Ecall(effects, result.clone().err()));
+        result
+    }
+
+    // the time CSR as the guest reads it
+    pub(crate) fn replay_time(&mut self, value: u64) -> u64 {
+        if let Some(Input::Time(value)) = self.replayed(|input| matches!(input, Input::Time(_))) {
+            return value;
+        }
+        self.note(Input::Time(value));
+        value
+    }
+
+    // The next input in the log when it is of the kind wanted and arrived at this tick. Anything
+    // else means the guest has gone its own way and every input from now on is live.
+    fn replayed(&mut self, wanted: fn(&Input) -> bool) -> Option<Input> {
+        let ticks = self.ticks();
+        let Mode::Replaying { log, next, record } = &mut self.replay else {
+            return None;
+        };
+        let input = match log.events.get(*next) {
+            Some(event) if event.ticks == ticks && wanted(&event.input) => event.input.clone(),
+            Some(_) => {
+                self.replay = Mode::Diverged { ticks };
+                return None;
+            },
+            None => {
+                self.replay = match *record {
+                    true => Mode::Recording(std::mem::take(log)),
+                    false => Mode::Off
+                };
+                return None;
+            }
+        };
+        *next += 1;
+        Some(input)
+    }
+
+    // Replays the log from the input at next on, then records onto the end of it. For going over
+    // a stretch of a recording again, the inputs after it are kept for when the guest gets there.
+    pub(crate) fn rewind_log(&mut self, next: usize) {
+        let log = match std::mem::take(&mut self.replay) {
+            Mode::Recording(log) | Mode::Replaying { log, .. } => log,
+            _ => ReplayLog::default()
+        };
+        self.replay = Mode::Replaying { log, next, record: true };
+    }
+
+    // how many inputs of the log the guest has been handed so far
+    pub(crate) fn log_position(&self) -> usize {
+        match &self.replay {
+            Mode::Recording(log) => log.events.len(),
+            Mode::Replaying { next, .. } => *next,
+            _ => 0
+        }
+    }
+
+    fn note(&mut self, input: Input) {
+        let ticks = self.ticks();
+        if let Mode::Recording(log) = &mut self.replay {
+            log.events.push(Event { ticks, input });
+        }
+    }
+
+    fn capture<T, F: FnOnce(&mut Cpu, &mut dyn Memory) -> T>(&mut self, memory: &mut dyn Memory, change: F) -> (T, Effects) {
+        if !matches!(self.replay, Mode::Recording(_)) {
+            return (change(self, memory), Effects::default());
+        }
+        let (x, f) = (self.x, self.f);
+        let accesses = RefCell::new(Vec::new());
+        let result = change(self, &mut Recording { inner: memory, accesses: &accesses });
+        let effects = Effects {
+            pc: self.pc,
+            written_registers: self.written_registers(&x, &f),
+            writes: accesses.into_inner().into_iter().filter(|access| access.kind == AccessKind::Write).collect()
+        };
+        (result, effects)
+    }
+
+    fn apply(&mut self, memory: &mut dyn Memory, effects: &Effects) -> Result<(), Trap> {
+        for register in &effects.written_registers {
+            match *register {
+                WrittenRegister::X { index, value } => self.x[index] = value,
+                WrittenRegister::F { index, value } => self.f[index] = value
+            }
+        }
+        self.pc = effects.pc;
+        effects.writes.iter().try_for_each(|write| match write.size {
+            1 => memory.write_u8(write.address, write.value as u8),
+            2 => memory.write_u16(write.address, write.value as u16),
+            4 => memory.write_u32(write.address, write.value as u32),
+            _ => memory.write_u64(write.address, write.value)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::InstructionClock;
+    use crate::cpu::instruction::Instruction;
+    use crate::cpu::test_cpu::program;
+
+    // rdtime t0; li a7, 1; ecall; mv a1, a0; li a7, 2; ecall; lw a2, 0x80(zero); j .
+    const PROGRAM: [u32; 8] = [0xc01022f3, 0x00100893, 0x00000073, 0x00050593, 0x00200893, 0x00000073, 0x08002603, 0x0000006f];
+
+    fn load() -> Vec<u8> {
+        let mut memory = program(&PROGRAM);
+        memory.resize(0x100, 0);
+        memory
+    }
+
+    #[test]
+    fn replays_reproduce_the_recorded_inputs() {
+        let mut memory = load();
+        let mut cpu = Cpu::builder().clock(InstructionClock::new().nanoseconds_per_tick(1000)).ecall_handler(2, Instruction {
+            name: "ECALL",
+            operation: |cpu, memory, _word, _address| memory.write_u32(0x80, cpu.x[10] as u32 * 2)
+        }).build();
+        let mut calls = 100i64;
+        cpu.bind_host_fn(1, move |()| {
+            calls += 1;
+            Ok(calls)
+        });
+        cpu.start_recording();
+        cpu.inject(&mut memory, |cpu, _memory| cpu.x[13] = 5);
+        cpu.run_with_fuel(&mut memory, 8);
+        let log = cpu.stop_recording().unwrap();
+        assert_eq!([10, 101, 202, 5], [cpu.x[5], cpu.x[11], cpu.x[12], cpu.x[13]]);
+
+        // no clock, no handlers and a different injection, yet the same run
+        let mut memory = load();
+        let mut replay = Cpu::new();
+        replay.start_replay(log.clone());
+        replay.inject(&mut memory, |cpu, _memory| cpu.x[13] = 6);
+        replay.run_with_fuel(&mut memory, 8);
+        assert_eq!(cpu.x, replay.x);
+        assert_eq!(ReplayStatus::Replaying { remaining: 0 }, replay.replay_status());
+
+        // without the injection the rdtime in the first instruction gets the injection's input
+        let mut memory = load();
+        let mut diverged = Cpu::new();
+        diverged.start_replay(log);
+        diverged.run_with_fuel(&mut memory, 8);
+        assert_eq!(ReplayStatus::Diverged { ticks: 1 }, diverged.replay_status());
+        assert_eq!(1, diverged.x[5]);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::cpu::{Cpu, ReplayStatus, RunConfig, StopReason, CSR_FCSR_ADDRESS};
+use crate::memory::CowMemory;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+// The hart and its memory as they were at one tick, to go forward from again.
+#[derive(Clone)]
+struct Checkpoint {
+    cpu: Cpu,
+    memory: CowMemory,
+    // how many inputs the guest had been handed, see log_position
+    events: usize,
+    // as the guest read it then, the accrued flags being kept by the host rather than in cpu
+    fcsr: u64
+}
+
+// Going over a stretch again, the guest asked for an input at ticks that it had not asked for
+// there the first time. It carries on from there on live inputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Diverged {
+    pub ticks: u64
+}
+
+impl Display for Diverged {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "the guest went another way than it was recorded going at tick {}", self.ticks)
+    }
+}
+
+impl Error for Diverged {}
+
+struct Timeline {
+    interval: u64,
+    // in the order they were taken, which is also tick order
+    checkpoints: Vec<Checkpoint>
+}
+
+// Belongs to one hart, so a cloned or forked Cpu starts out without any history.
+#[derive(Default)]
+pub(crate) struct History(Option<Timeline>);
+
+impl Clone for History {
+    fn clone(&self) -> Self {
+        History(None)
+    }
+}
+
+impl Cpu {
+    // Makes going back in time possible from here on. run_checkpointed keeps a checkpoint every
+    // interval instructions and every input is recorded, so going forward again from a
+    // checkpoint sees exactly what the guest saw the first time. Memory has to be copy on write
+    // for checkpoints to be cheap enough to take often.
+    pub fn enable_reverse(&mut self, memory: &CowMemory, interval: u64) {
+        self.start_recording();
+        self.history = History(Some(Timeline { interval: interval.max(1), checkpoints: Vec::new() }));
+        self.checkpoint(memory);
+    }
+
+    pub fn disable_reverse(&mut self) {
+        self.history = History(None);
+        self.stop_recording();
+    }
+
+    // Runs like run, taking checkpoints as it goes when reverse execution is enabled. Coming
+    // back to a stretch that was gone back over runs it again from the same inputs.
+    pub fn run_checkpointed(&mut self, memory: &mut CowMemory, config: &RunConfig) -> StopReason {
+        let Some(interval) = self.history.0.as_ref().map(|timeline| timeline.interval) else {
+            return self.run(memory, config);
+        };
+        let mut fuel = config.fuel;
+        let mut step_over = true;
+        loop {
+            // one is due straight away when the hart has been run on without taking them
+            let due = self.checkpoint_ticks().last().map_or(0, |ticks| ticks + interval).saturating_sub(self.ticks());
+            let budget = fuel.map_or(due, |fuel| fuel.min(due));
+            let (consumed, stop) = self.run_counting(memory, &config.clone().fuel(budget), step_over);
+            step_over = false;
+            fuel = fuel.map(|fuel| fuel - consumed);
+            match stop {
+                StopReason::FuelExhausted if fuel != Some(0) => self.checkpoint(memory),
+                stop => return stop
+            }
+        }
+    }
+
+    // Goes back n instructions, or as far as reverse execution was enabled when that is nearer,
+    // and returns how many it went back.
+    pub fn reverse_step(&mut self, memory: &mut CowMemory, n: u64) -> Result<u64, Diverged> {
+        let now = self.ticks();
+        let target = now.saturating_sub(n).max(self.checkpoint_ticks().first().copied().unwrap_or(now));
+        self.travel_to(memory, target, None)?;
+        Ok(now - target)
+    }
+
+    // Goes back to the last time the hart was about to execute the instruction at pc. Without
+    // one since reverse execution was enabled the hart stays where it is and this is false.
+    pub fn reverse_continue_to(&mut self, memory: &mut CowMemory, pc: usize) -> Result<bool, Diverged> {
+        let now = self.ticks();
+        let mut end = now;
+        // each stretch between checkpoints is gone over again, the latest first
+        for start in self.checkpoint_ticks().into_iter().rev().filter(|ticks| *ticks < now) {
+            self.travel_to(memory, start, None)?;
+            if let Some(ticks) = self.travel_to(memory, end, Some(pc))? {
+                self.travel_to(memory, ticks, None)?;
+                return Ok(true);
+            }
+            end = start;
+        }
+        self.travel_to(memory, now, None)?;
+        Ok(false)
+    }
+
+    fn checkpoint_ticks(&self) -> Vec<u64> {
+        self.history.0.as_ref().map_or(Vec::new(), |timeline| timeline.checkpoints.iter().map(|c| c.cpu.ticks()).collect())
+    }
+
+    fn checkpoint(&mut self, memory: &CowMemory) {
+        let events = self.log_position();
+        let fcsr = self.read_csr(CSR_FCSR_ADDRESS);
+        // the log is only needed once, by the hart itself
+        let replay = std::mem::take(&mut self.replay);
+        let cpu = self.clone();
+        self.replay = replay;
+        if let History(Some(timeline)) = &mut self.history {
+            timeline.checkpoints.push(Checkpoint { cpu, memory: memory.clone_cow(), events, fcsr });
+        }
+    }
+
+    // Gets the hart to ticks, from the latest checkpoint not after it when that is in the past.
+    // Returns the last tick before ticks at which the hart was about to execute the instruction
+    // at watch.
+    fn travel_to(&mut self, memory: &mut CowMemory, ticks: u64, watch: Option<usize>) -> Result<Option<u64>, Diverged> {
+        if ticks < self.ticks() {
+            let checkpoint = self.history.0.as_ref().and_then(|timeline| timeline.checkpoints.iter().rev().find(|c| c.cpu.ticks() <= ticks));
+            let Some(checkpoint) = checkpoint.cloned() else {
+                return Ok(None);
+            };
+            self.restore(memory, &checkpoint);
+        }
+        // the tracer saw all of this the first time round
+        let tracer = self.take_tracer();
+        let mut seen = None;
+        while self.ticks() < ticks {
+            if watch == Some(self.pc) {
+                seen = Some(self.ticks());
+            }
+            // a trap comes out the same as it did before, whoever was running the hart saw to it then
+            let _ = self.tick(memory);
+        }
+        self.set_tracer(tracer);
+        match self.replay_status() {
+            ReplayStatus::Diverged { ticks } => Err(Diverged { ticks }),
+            _ => Ok(seen)
+        }
+    }
+
+    fn restore(&mut self, memory: &mut CowMemory, checkpoint: &Checkpoint) {
+        let from = &checkpoint.cpu;
+        self.pc = from.pc;
+        self.x = from.x;
+        self.f = from.f;
+        #[cfg(feature = "q")]
+        {
+            self.f_high = from.f_high;
+        }
+        #[cfg(feature = "v")]
+        self.v.clone_from(&from.v);
+        self.csr = from.csr;
+        self.write_csr(CSR_FCSR_ADDRESS, checkpoint.fcsr);
+        self.reservation = from.reservation;
+        self.is_reservation_set = from.is_reservation_set;
+        self.reservation_value = from.reservation_value;
+        self.fflags_provenance = from.fflags_provenance.clone();
+        *memory = checkpoint.memory.clone_cow();
+        self.update_event_counting();
+        self.update_observed();
+        self.invalidate_all_caches();
+        self.rewind_log(checkpoint.events);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Register;
+    use crate::cpu::test_cpu::program;
+    use crate::memory::Memory;
+
+    #[test]
+    fn reverse_execution_goes_back_through_recorded_inputs() {
+        // li a0, 0; li a7, 1; loop: ecall; sw a0, 0x80(zero); addi a1, a1, 1; j loop
+        let words = [0x00000513u32, 0x00100893, 0x00000073, 0x08a02023, 0x00158593, 0xff5ff06f];
+        let bytes = program(&words);
+        let mut memory = CowMemory::from_bytes(&[bytes, vec![0; 0x100]].concat());
+        let mut cpu = Cpu::new();
+        let mut calls = 100i64;
+        cpu.bind_host_fn(1, move |()| {
+            calls += 1;
+            Ok(calls)
+        });
+        cpu.enable_reverse(&memory, 4);
+
+        assert!(matches!(cpu.run_checkpointed(&mut memory, &RunConfig::new().fuel(30)), StopReason::FuelExhausted));
+        let (a0, a1) = (cpu.get_register(Register::A0), cpu.get_register(Register::A1));
+        assert_eq!((107, 7), (a0, a1));
+
+        assert_eq!(Ok(6), cpu.reverse_step(&mut memory, 6));
+        assert_eq!((106, 5, 106), (cpu.get_register(Register::A0), cpu.get_register(Register::A1), memory.read_u32(0x80).unwrap()));
+
+        // the host function is not called again, a live call would have given 108
+        cpu.run_checkpointed(&mut memory, &RunConfig::new().fuel(6));
+        assert_eq!((a0, a1), (cpu.get_register(Register::A0), cpu.get_register(Register::A1)));
+
+        // the store of the last call's result has not happened yet
+        assert_eq!(Ok(true), cpu.reverse_continue_to(&mut memory, 0x0c));
+        assert_eq!((0x0c, 107, 6, 106), (cpu.get_pc(), cpu.get_register(Register::A0), cpu.get_register(Register::A1), memory.read_u32(0x80).unwrap()));
+        assert_eq!(Ok(false), cpu.reverse_continue_to(&mut memory, 0x40));
+        assert_eq!(0x0c, cpu.get_pc());
+    }
+
+    #[test]
+    fn going_over_a_stretch_another_way_is_an_error() {
+        // li a0, 0; li a7, 1; loop: ecall; sw a0, 0x80(zero); addi a1, a1, 1; j loop
+        let words = [0x00000513u32, 0x00100893, 0x00000073, 0x08a02023, 0x00158593, 0xff5ff06f];
+        let bytes = program(&words);
+        let mut memory = CowMemory::from_bytes(&[bytes, vec![0; 0x100]].concat());
+        let mut cpu = Cpu::new();
+        cpu.bind_host_fn(1, |()| Ok(0i64));
+        cpu.enable_reverse(&memory, 4);
+        cpu.run_checkpointed(&mut memory, &RunConfig::new().fuel(30));
+
+        // as if something the recording missed had sent the guest back to the ecall
+        let History(Some(timeline)) = &mut cpu.history else { unreachable!() };
+        let checkpoint = timeline.checkpoints.iter_mut().find(|c| c.cpu.ticks() == 24).unwrap();
+        checkpoint.cpu.pc = 0x08;
+        assert_eq!(Err(Diverged { ticks: 25 }), cpu.reverse_step(&mut memory, 5));
+    }
+
+    #[test]
+    #[cfg(feature = "f")]
+    fn accrued_flags_come_back_with_the_checkpoint() {
+        // csrwi fflags, 0; csrwi fflags, 1; j 0
+        let words = [0x00105073u32, 0x0010d073, 0x0000006f];
+        let bytes = program(&words);
+        let mut memory = CowMemory::from_bytes(&[bytes, vec![0; 0x100]].concat());
+        let mut cpu = Cpu::new();
+        cpu.enable_reverse(&memory, 1);
+        cpu.run_checkpointed(&mut memory, &RunConfig::new().fuel(3));
+        assert_eq!(1, cpu.read_csr(0x001));
+
+        assert_eq!(Ok(2), cpu.reverse_step(&mut memory, 2));
+        assert_eq!(0, cpu.read_csr(0x001));
+    }
+}
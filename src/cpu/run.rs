@@ -0,0 +1,346 @@
+use crate::cpu::{Cpu, Trap, TrapType};
+use crate::cpu::step::{AccessKind, MemoryAccess};
+use crate::memory::Memory;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "zawrs")]
+use std::time::{Duration, Instant};
+
+// how often a hart parked by WRS looks for a stop request
+#[cfg(feature = "zawrs")]
+const STOP_POLL: Duration = Duration::from_millis(10);
+
+// When run should give control back, besides the guest exiting or trapping. With nothing set it
+// runs until one of those happens or a stop is requested.
+#[derive(Clone, Debug, Default)]
+pub struct RunConfig {
+    pub(crate) fuel: Option<u64>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: Vec<Watchpoint>
+}
+
+// a range of guest addresses the loads or the stores into stop the run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: Range<usize>,
+    pub kind: AccessKind
+}
+
+// Why run came back. Anything but a trap can be carried on from by calling run again.
+#[derive(Clone, Debug)]
+pub enum StopReason {
+    // pc holds a breakpoint, the instruction there has not run yet
+    Breakpoint { pc: usize },
+    // the instruction at pc made access to a watched address and has completed
+    Watchpoint { pc: usize, access: MemoryAccess },
+    // nothing handled the trap, pc is where the instruction responsible was fetched from
+    Trap { pc: usize, trap: Trap },
+    // the fuel given in the config has all been used
+    FuelExhausted,
+    // the instruction at pc costs more gas than is left and has not run
+    OutOfGas { pc: usize },
+    // a stop was requested through the StopHandle from stop_handle
+    StopRequested,
+    // the guest exited with this status
+    Exited(i64)
+}
+
+// Shared by the hart, its clones and anyone it was handed to, so one stop reaches every hart of
+// a process. The request stands until a hart that stopped for it is run again.
+#[derive(Clone, Debug, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stop_requested(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn clear(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+// Wakes the harts WRS.NTO and WRS.STO have parked, for whoever stores to the memory they may be
+// spinning on. A hart without one never parks, WRS completing straight away as the spec allows.
+#[derive(Clone, Debug, Default)]
+pub struct WakeHandle(Arc<(Mutex<u64>, Condvar)>);
+
+impl WakeHandle {
+    pub fn new() -> Self {
+        WakeHandle::default()
+    }
+
+    pub fn wake(&self) {
+        let (wakes, parked) = &*self.0;
+        *wakes.lock().unwrap() += 1;
+        parked.notify_all();
+    }
+
+    // taken before looking at memory, so that a store and wake in between is not missed
+    #[cfg(feature = "zawrs")]
+    pub(crate) fn wakes(&self) -> u64 {
+        *self.0.0.lock().unwrap()
+    }
+
+    // blocks until there has been a wake since seen, the deadline passes or a stop is requested
+    #[cfg(feature = "zawrs")]
+    pub(crate) fn park(&self, seen: u64, deadline: Option<Instant>, stop: &StopHandle) {
+        let (wakes, parked) = &*self.0;
+        let mut count = wakes.lock().unwrap();
+        while *count == seen && !stop.is_stop_requested() {
+            let now = Instant::now();
+            let slice = match deadline {
+                Some(deadline) if deadline <= now => return,
+                Some(deadline) => (deadline - now).min(STOP_POLL),
+                None => STOP_POLL
+            };
+            count = parked.wait_timeout(count, slice).unwrap().0;
+        }
+    }
+}
+
+impl RunConfig {
+    pub fn new() -> Self {
+        RunConfig::default()
+    }
+
+    // the most instructions to run
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    pub fn breakpoint(mut self, pc: usize) -> Self {
+        self.breakpoints.insert(pc);
+        self
+    }
+
+    pub fn watchpoint(mut self, range: Range<usize>, kind: AccessKind) -> Self {
+        self.watchpoints.push(Watchpoint { range, kind });
+        self
+    }
+
+    fn watched(&self, access: &MemoryAccess) -> bool {
+        self.watchpoints.iter().any(|watchpoint| watchpoint.kind == access.kind
+            && access.address < watchpoint.range.end
+            && access.address.saturating_add(access.size) > watchpoint.range.start)
+    }
+}
+
+impl Cpu {
+    // whether a stop has been requested, which the hart then carries on from with resume
+    pub(crate) fn poll_stop(&mut self) -> bool {
+        let requested = self.stop.is_stop_requested();
+        self.stopped_for_request |= requested;
+        requested
+    }
+
+    // withdraws the request the hart last stopped for, as running it again means carrying on
+    pub(crate) fn resume(&mut self) {
+        if std::mem::take(&mut self.stopped_for_request) {
+            self.stop.clear();
+        }
+    }
+
+    // Ticks until one of the reasons in StopReason comes up. Exiting is the TrapType::Stop trap
+    // made by Trap::exit. A breakpoint the last run stopped at is stepped over, so that calling
+    // run again carries on from one.
+    pub fn run(&mut self, memory: &mut dyn Memory, config: &RunConfig) -> StopReason {
+        self.run_counting(memory, config, true).1
+    }
+
+    // Runs at most fuel instructions and returns how many it took, which is fewer when the run
+    // stopped for any other reason. Calling it again carries on where it left off, so a host can
+    // share its time between many guests by handing each a slice in turn.
+    pub fn run_with_fuel(&mut self, memory: &mut dyn Memory, fuel: u64) -> (u64, StopReason) {
+        self.run_counting(memory, &RunConfig::new().fuel(fuel), true)
+    }
+
+    // Runs until pc is reached, stopping with a breakpoint there. Running to the instruction
+    // after a call is how a debugger steps over it.
+    pub fn run_until_pc(&mut self, memory: &mut dyn Memory, pc: usize, config: &RunConfig) -> StopReason {
+        self.run(memory, &config.clone().breakpoint(pc))
+    }
+
+    // Runs until the function just entered returns, stopping with a breakpoint at the address in
+    // ra. It has to be called before ra is reused for another call, usually on the first
+    // instruction of the function. Recursive calls that return to the same address are told
+    // apart by the stack pointer, which is still below where it was until the call itself returns.
+    pub fn run_until_return(&mut self, memory: &mut dyn Memory, config: &RunConfig) -> StopReason {
+        let (ra, sp) = (self.x[1] as usize, self.x[2]);
+        let mut config = config.clone().breakpoint(ra);
+        loop {
+            match self.run_counting(memory, &config, true) {
+                (consumed, StopReason::Breakpoint { pc }) if pc == ra && self.x[2] < sp => {
+                    config.fuel = config.fuel.map(|fuel| fuel - consumed);
+                },
+                (_, stop) => return stop
+            }
+        }
+    }
+
+    // An instruction that traps or hits a watchpoint counts as having run, one there was not
+    // enough gas for does not. A breakpoint where the run starts is only stepped over with
+    // step_over, and only when the last run stopped for it with nothing run since. Any other
+    // stop there, fuel running out included, leaves it to fire.
+    pub(crate) fn run_counting(&mut self, memory: &mut dyn Memory, config: &RunConfig, step_over: bool) -> (u64, StopReason) {
+        let step_over = step_over && self.breakpoint_stop.take() == Some((self.pc, self.ticks()));
+        self.resume();
+        let mut consumed = 0;
+        let stop = loop {
+            if config.fuel == Some(consumed) {
+                break StopReason::FuelExhausted;
+            }
+            if self.poll_stop() {
+                break StopReason::StopRequested;
+            }
+            let pc = self.pc;
+            if (consumed != 0 || !step_over) && config.breakpoints.contains(&pc) {
+                break StopReason::Breakpoint { pc };
+            }
+            consumed += 1;
+
+            // watching has to look at every access, which tick alone does not pay for
+            let result = match config.watchpoints.is_empty() {
+                true => self.tick(memory),
+                false => {
+                    let accesses = RefCell::new(Vec::new());
+                    let result = self.advance(memory, Some(&accesses)).map(|_| ());
+                    if let (Ok(_), Some(access)) = (&result, accesses.into_inner().into_iter().find(|a| config.watched(a))) {
+                        break StopReason::Watchpoint { pc, access };
+                    }
+                    result
+                }
+            };
+            match result {
+                Ok(_) => {},
+                Err(trap) if trap.trap_type == TrapType::Stop => break StopReason::Exited(trap.value as i64),
+                Err(trap) if trap.trap_type == TrapType::OutOfGas => {
+                    consumed -= 1;
+                    break StopReason::OutOfGas { pc };
+                },
+                Err(trap) => break StopReason::Trap { pc, trap }
+            }
+        };
+        if let StopReason::Breakpoint { pc } = stop {
+            self.breakpoint_stop = Some((pc, self.ticks()));
+        }
+        (consumed, stop)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn breakpoints_and_watchpoints_stop_the_run() {
+        // addi a0, zero, 0x40; sd a0, 8(a0); ld a1, 8(a0); addi a0, a0, 1
+        let words = [0x04000513u32, 0x00a53423, 0x00853583, 0x00150513];
+        let mut memory = program(&words);
+        memory.resize(0x80, 0);
+        let mut cpu = Cpu::new();
+
+        let config = RunConfig::new().breakpoint(0x0c).watchpoint(0x4c..0x50, AccessKind::Write).watchpoint(0x40..0x48, AccessKind::Read);
+        match cpu.run(&mut memory, &config) {
+            StopReason::Watchpoint { pc, access } => {
+                assert_eq!(4, pc);
+                assert_eq!((0x48, 8), (access.address, access.size));
+            },
+            reason => panic!("unexpected {:?}", reason)
+        }
+        // the load reads 0x48 to 0x50, which is only watched for writes
+        assert!(matches!(cpu.run(&mut memory, &config), StopReason::Breakpoint { pc: 0x0c }));
+        assert!(matches!(cpu.run(&mut memory, &config.clone().fuel(1)), StopReason::FuelExhausted));
+        assert_eq!(0x41, cpu.get_register(crate::cpu::Register::A0));
+    }
+
+    #[test]
+    fn fuel_running_out_at_a_breakpoint_leaves_it_to_fire() {
+        // addi a0, a0, 1 four times
+        let mut memory = program(&[0x00150513; 4]);
+        let mut cpu = Cpu::new();
+
+        let config = RunConfig::new().breakpoint(0x08);
+        assert!(matches!(cpu.run(&mut memory, &config.clone().fuel(2)), StopReason::FuelExhausted));
+        assert!(matches!(cpu.run(&mut memory, &config), StopReason::Breakpoint { pc: 0x08 }));
+        assert_eq!(2, cpu.x[10]);
+        // and having fired, the next run carries on past it
+        assert!(matches!(cpu.run(&mut memory, &config.fuel(2)), StopReason::FuelExhausted));
+        assert_eq!((4, 0x10), (cpu.x[10], cpu.get_pc()));
+    }
+
+    #[test]
+    fn fuel_runs_can_be_resumed() {
+        // li a0, 0; li a1, 5; loop: addi a0, a0, 3; addi a1, a1, -1; bnez a1, loop; ecall
+        let words = [0x00000513u32, 0x00500593, 0x00350513, 0xfff58593, 0xfe059ce3, 0x00000073];
+        let mut memory = program(&words);
+        let mut cpu = Cpu::builder().ecall_fallback(crate::cpu::instruction::Instruction {
+            name: "ECALL",
+            operation: |cpu, _memory, _word, _address| Err(Trap::exit(cpu.x[10]))
+        }).build();
+
+        let mut total = 0;
+        let stop = loop {
+            match cpu.run_with_fuel(&mut memory, 4) {
+                (consumed, StopReason::FuelExhausted) => total += consumed,
+                (consumed, stop) => break (total + consumed, stop)
+            }
+        };
+        // two instructions before the loop, three in each of its five turns and the ecall
+        assert_eq!(18, stop.0);
+        assert!(matches!(stop.1, StopReason::Exited(15)));
+    }
+
+    #[test]
+    fn run_until_return_finishes_recursive_calls() {
+        // li a0, 3; call f; li a1, 7; j .
+        // f: addi sp, sp, -16; sd ra, 0(sp); addi a0, a0, -1; beqz a0, 1f; call f
+        // 1: ld ra, 0(sp); addi sp, sp, 16; ret
+        let words = [
+            0x00300513u32, 0x00c000ef, 0x00700593, 0x0000006f,
+            0xff010113, 0x00113023, 0xfff50513, 0x00050463, 0xff1ff0ef, 0x00013083, 0x01010113, 0x00008067
+        ];
+        let mut memory = program(&words);
+        memory.resize(0x100, 0);
+        let mut cpu = Cpu::new();
+        cpu.x[2] = 0x100;
+        let config = RunConfig::new().fuel(100);
+
+        assert!(matches!(cpu.run_until_pc(&mut memory, 0x10, &config), StopReason::Breakpoint { pc: 0x10 }));
+        // into the first recursive call, which returns to 0x24 as the one it makes does
+        assert!(matches!(cpu.run_until_pc(&mut memory, 0x10, &config), StopReason::Breakpoint { pc: 0x10 }));
+        assert!(matches!(cpu.run_until_return(&mut memory, &config), StopReason::Breakpoint { pc: 0x24 }));
+        assert_eq!((0xf0, 0), (cpu.x[2], cpu.x[10]));
+        assert!(matches!(cpu.run_until_pc(&mut memory, 0x0c, &config), StopReason::Breakpoint { pc: 0x0c }));
+        assert_eq!(7, cpu.x[11]);
+    }
+
+    #[test]
+    fn another_thread_can_stop_the_run() {
+        // j . spins forever
+        let mut memory = 0x0000006fu32.to_le_bytes().to_vec();
+        let mut cpu = Cpu::new();
+        let mut other = cpu.clone();
+        let handle = cpu.stop_handle();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            handle.stop();
+        });
+
+        assert!(matches!(cpu.run(&mut memory, &RunConfig::new()), StopReason::StopRequested));
+        stopper.join().unwrap();
+        // every hart sharing the handle stops, until one that stopped is run again
+        assert!(matches!(other.run(&mut memory, &RunConfig::new()), StopReason::StopRequested));
+        assert!(matches!(cpu.run(&mut memory, &RunConfig::new().fuel(10)), StopReason::FuelExhausted));
+        assert!(!cpu.stop_handle().is_stop_requested());
+    }
+}
@@ -1,248 +1,448 @@
+use crate::cpu::{Cpu, Memory, Trap};
+#[cfg(feature = "zacas")]
+use crate::cpu::{TrapType, Xlen};
 use crate::cpu::instruction;
 use crate::cpu::instruction::Instruction;
 
-pub const AMOADD_D: Instruction = Instruction {
-    name: "AMOADD.D",
+// The read-modify-write is a compare and swap loop so that it stays atomic when the memory is
+// shared with other harts running on other threads.
+fn amo_d(cpu: &mut Cpu, memory: &mut dyn Memory, word: u32, op: fn(u64, u64) -> u64) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let address = cpu.data_address(cpu.x[f.rs1]);
+    let source = cpu.x[f.rs2] as u64;
+
+    let mut old = memory.read_u64(address)?;
+    while let Err(current) = memory.compare_exchange_u64(address, old, op(old, source))? {
+        old = current;
+    }
+    cpu.x[f.rd] = old as i64;
+    Ok(())
+}
+
+fn amo_w(cpu: &mut Cpu, memory: &mut dyn Memory, word: u32, op: fn(u32, u32) -> u32) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let address = cpu.data_address(cpu.x[f.rs1]);
+    let source = cpu.x[f.rs2] as u32;
+
+    let mut old = memory.read_u32(address)?;
+    while let Err(current) = memory.compare_exchange_u32(address, old, op(old, source))? {
+        old = current;
+    }
+    cpu.x[f.rd] = old as i32 as i64;
+    Ok(())
+}
+
+// the sub-word forms sign extend what they load just as amo_w does
+#[cfg(feature = "zabha")]
+fn amo_b(cpu: &mut Cpu, memory: &mut dyn Memory, word: u32, op: fn(u8, u8) -> u8) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let address = cpu.data_address(cpu.x[f.rs1]);
+    let source = cpu.x[f.rs2] as u8;
+
+    let mut old = memory.read_u8(address)?;
+    while let Err(current) = memory.compare_exchange_u8(address, old, op(old, source))? {
+        old = current;
+    }
+    cpu.x[f.rd] = old as i8 as i64;
+    Ok(())
+}
+
+#[cfg(feature = "zabha")]
+fn amo_h(cpu: &mut Cpu, memory: &mut dyn Memory, word: u32, op: fn(u16, u16) -> u16) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let address = cpu.data_address(cpu.x[f.rs1]);
+    let source = cpu.x[f.rs2] as u16;
+
+    let mut old = memory.read_u16(address)?;
+    while let Err(current) = memory.compare_exchange_u16(address, old, op(old, source))? {
+        old = current;
+    }
+    cpu.x[f.rd] = old as i16 as i64;
+    Ok(())
+}
+
+// The double width value in the even/odd register pair starting at register, low half first,
+// x0 standing for a pair of zeros. An odd register is reserved.
+#[cfg(feature = "zacas")]
+fn read_pair(cpu: &Cpu, register: usize, word: u32) -> Result<u128, Trap> {
+    let width = match cpu.xlen {
+        Xlen::Bit32 => 32,
+        Xlen::Bit64 => 64
+    };
+    match register {
+        0 => Ok(0),
+        _ if register % 2 == 1 => Err(Trap { trap_type: TrapType::IllegalInstruction, value: word as u64 }),
+        _ => Ok(cpu.unsigned_data(cpu.x[register]) as u128 | (cpu.unsigned_data(cpu.x[register + 1]) as u128) << width)
+    }
+}
+
+#[cfg(feature = "zacas")]
+fn write_pair(cpu: &mut Cpu, register: usize, value: u128) {
+    let width = match cpu.xlen {
+        Xlen::Bit32 => 32,
+        Xlen::Bit64 => 64
+    };
+    if register != 0 {
+        cpu.x[register] = cpu.sign_extend(value as i64);
+        cpu.x[register + 1] = cpu.sign_extend((value >> width) as i64);
+    }
+}
+
+// rd holds the value expected and gets back the one found, whether or not rs2 was stored
+#[cfg(feature = "zacas")]
+pub const AMOCAS_W: Instruction = Instruction {
+    name: "AMOCAS.W",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_r(word);
-
-        let tmp = memory.read_i64(cpu.x[f.rs1] as usize)?;
-        memory.write_u64(cpu.x[f.rs1] as usize, cpu.x[f.rs2].wrapping_add(tmp) as u64)?;
-        cpu.x[f.rd] = tmp;
+        let address = cpu.data_address(cpu.x[f.rs1]);
+        let old = memory.compare_exchange_u32(address, cpu.x[f.rd] as u32, cpu.x[f.rs2] as u32)?.unwrap_or_else(|old| old);
+        cpu.x[f.rd] = old as i32 as i64;
         Ok(())
     }
 };
 
-pub const AMOADD_W: Instruction = Instruction {
-    name: "AMOADD.W",
+#[cfg(all(feature = "zabha", feature = "zacas"))]
+pub const AMOCAS_B: Instruction = Instruction {
+    name: "AMOCAS.B",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_r(word);
-        let tmp = memory.read_i32(cpu.x[f.rs1] as usize)? as i64;
-        memory.write_u32(cpu.x[f.rs1] as usize, cpu.x[f.rs2].wrapping_add(tmp) as u32)?;
-        cpu.x[f.rd] = tmp;
+        let address = cpu.data_address(cpu.x[f.rs1]);
+        let old = memory.compare_exchange_u8(address, cpu.x[f.rd] as u8, cpu.x[f.rs2] as u8)?.unwrap_or_else(|old| old);
+        cpu.x[f.rd] = old as i8 as i64;
         Ok(())
     }
 };
 
-pub const AMOAND_D: Instruction = Instruction {
-    name: "AMOAND.D",
+#[cfg(all(feature = "zabha", feature = "zacas"))]
+pub const AMOCAS_H: Instruction = Instruction {
+    name: "AMOCAS.H",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_r(word);
-        let tmp = memory.read_i64(cpu.x[f.rs1] as usize)?;
-        memory.write_u64(cpu.x[f.rs1] as usize, (cpu.x[f.rs2] & tmp) as u64)?;
-        cpu.x[f.rd] = tmp;
+        let address = cpu.data_address(cpu.x[f.rs1]);
+        let old = memory.compare_exchange_u16(address, cpu.x[f.rd] as u16, cpu.x[f.rs2] as u16)?.unwrap_or_else(|old| old);
+        cpu.x[f.rd] = old as i16 as i64;
         Ok(())
     }
 };
 
-pub const AMOAND_W: Instruction = Instruction {
-    name: "AMOAND.W",
+// a register pair in RV32, as AMOCAS.Q is in RV64
+#[cfg(feature = "zacas")]
+pub const AMOCAS_D: Instruction = Instruction {
+    name: "AMOCAS.D",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_r(word);
-        let tmp = memory.read_i32(cpu.x[f.rs1] as usize)? as i64;
-        memory.write_u32(cpu.x[f.rs1] as usize, (cpu.x[f.rs2] & tmp) as u32)?;
-        cpu.x[f.rd] = tmp;
+        let address = cpu.data_address(cpu.x[f.rs1]);
+        match cpu.xlen {
+            Xlen::Bit32 => {
+                let (current, new) = (read_pair(cpu, f.rd, word)? as u64, read_pair(cpu, f.rs2, word)? as u64);
+                let old = memory.compare_exchange_u64(address, current, new)?.unwrap_or_else(|old| old);
+                write_pair(cpu, f.rd, old as u128);
+            },
+            Xlen::Bit64 => {
+                let old = memory.compare_exchange_u64(address, cpu.x[f.rd] as u64, cpu.x[f.rs2] as u64)?.unwrap_or_else(|old| old);
+                cpu.x[f.rd] = old as i64;
+            }
+        }
         Ok(())
     }
 };
 
-pub const AMOMAX_D: Instruction = Instruction {
-    name: "AMOMAX.D",
+#[cfg(feature = "zacas")]
+pub const AMOCAS_Q: Instruction = Instruction {
+    name: "AMOCAS.Q",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_r(word);
+        let address = cpu.data_address(cpu.x[f.rs1]);
+        let (current, new) = (read_pair(cpu, f.rd, word)?, read_pair(cpu, f.rs2, word)?);
+        let old = memory.compare_exchange_u128(address, current, new)?.unwrap_or_else(|old| old);
+        write_pair(cpu, f.rd, old);
+        Ok(())
+    }
+};
 
-        let tmp = memory.read_i64(cpu.x[f.rs1] as usize)?;
-        let max = match cpu.x[f.rs2] >=tmp {
-            true => cpu.x[f.rs2],
-            false => tmp as i64
-        };
-        memory.write_u64(cpu.x[f.rs1] as usize, max as u64)?;
-        cpu.x[f.rd] = tmp;
+#[cfg(feature = "zabha")]
+pub const AMOADD_B: Instruction = Instruction {
+    name: "AMOADD.B",
+    operation: |cpu, memory, word, _address| {
+        amo_b(cpu, memory, word, |old, source| old.wrapping_add(source))
+    }
+};
 
-        Ok(())
+#[cfg(feature = "zabha")]
+pub const AMOADD_H: Instruction = Instruction {
+    name: "AMOADD.H",
+    operation: |cpu, memory, word, _address| {
+        amo_h(cpu, memory, word, |old, source| old.wrapping_add(source))
+    }
+};
+
+pub const AMOADD_D: Instruction = Instruction {
+    name: "AMOADD.D",
+    operation: |cpu, memory, word, _address| {
+        amo_d(cpu, memory, word, |old, source| old.wrapping_add(source))
+    }
+};
+
+pub const AMOADD_W: Instruction = Instruction {
+    name: "AMOADD.W",
+    operation: |cpu, memory, word, _address| {
+        amo_w(cpu, memory, word, |old, source| old.wrapping_add(source))
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOAND_B: Instruction = Instruction {
+    name: "AMOAND.B",
+    operation: |cpu, memory, word, _address| {
+        amo_b(cpu, memory, word, |old, source| old & source)
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOAND_H: Instruction = Instruction {
+    name: "AMOAND.H",
+    operation: |cpu, memory, word, _address| {
+        amo_h(cpu, memory, word, |old, source| old & source)
+    }
+};
+
+pub const AMOAND_D: Instruction = Instruction {
+    name: "AMOAND.D",
+    operation: |cpu, memory, word, _address| {
+        amo_d(cpu, memory, word, |old, source| old & source)
+    }
+};
+
+pub const AMOAND_W: Instruction = Instruction {
+    name: "AMOAND.W",
+    operation: |cpu, memory, word, _address| {
+        amo_w(cpu, memory, word, |old, source| old & source)
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOMAX_B: Instruction = Instruction {
+    name: "AMOMAX.B",
+    operation: |cpu, memory, word, _address| {
+        amo_b(cpu, memory, word, |old, source| (old as i8).max(source as i8) as u8)
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOMAX_H: Instruction = Instruction {
+    name: "AMOMAX.H",
+    operation: |cpu, memory, word, _address| {
+        amo_h(cpu, memory, word, |old, source| (old as i16).max(source as i16) as u16)
+    }
+};
+
+pub const AMOMAX_D: Instruction = Instruction {
+    name: "AMOMAX.D",
+    operation: |cpu, memory, word, _address| {
+        amo_d(cpu, memory, word, |old, source| (old as i64).max(source as i64) as u64)
     }
 };
 
 pub const AMOMAX_W: Instruction = Instruction {
     name: "AMOMAX.W",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_i32(cpu.x[f.rs1] as usize)?;
-        let max = match (cpu.x[f.rs2] as i32) >=tmp {
-            true => cpu.x[f.rs2] as i32,
-            false => tmp as i32
-        };
-        memory.write_u32(cpu.x[f.rs1] as usize, max as u32)?;
-        cpu.x[f.rd] = tmp as i64;
-        Ok(())
+        amo_w(cpu, memory, word, |old, source| (old as i32).max(source as i32) as u32)
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOMAXU_B: Instruction = Instruction {
+    name: "AMOMAXU.B",
+    operation: |cpu, memory, word, _address| {
+        amo_b(cpu, memory, word, |old, source| old.max(source))
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOMAXU_H: Instruction = Instruction {
+    name: "AMOMAXU.H",
+    operation: |cpu, memory, word, _address| {
+        amo_h(cpu, memory, word, |old, source| old.max(source))
     }
 };
 
 pub const AMOMAXU_D: Instruction = Instruction {
     name: "AMOMAXU.D",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_u64(cpu.x[f.rs1] as usize)?;
-        let max = match (cpu.x[f.rs2] as u64) >=tmp {
-            true => cpu.x[f.rs2] as u64,
-            false => tmp as u64
-        };
-        memory.write_u64(cpu.x[f.rs1] as usize, max)?;
-        cpu.x[f.rd] = tmp as i64;
-        Ok(())
+        amo_d(cpu, memory, word, |old, source| old.max(source))
     }
 };
 
 pub const AMOMAXU_W: Instruction = Instruction {
     name: "AMOMAXU.W",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_u32(cpu.x[f.rs1] as usize)?;
-        let max = match (cpu.x[f.rs2] as u32) >= tmp {
-            true => cpu.x[f.rs2] as u32,
-            false => tmp as u32
-        };
-        memory.write_u32(cpu.x[f.rs1] as usize, max)?;
-        cpu.x[f.rd] = tmp as i32 as i64;
-        Ok(())
+        amo_w(cpu, memory, word, |old, source| old.max(source))
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOMIN_B: Instruction = Instruction {
+    name: "AMOMIN.B",
+    operation: |cpu, memory, word, _address| {
+        amo_b(cpu, memory, word, |old, source| (old as i8).min(source as i8) as u8)
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOMIN_H: Instruction = Instruction {
+    name: "AMOMIN.H",
+    operation: |cpu, memory, word, _address| {
+        amo_h(cpu, memory, word, |old, source| (old as i16).min(source as i16) as u16)
     }
 };
 
 pub const AMOMIN_D: Instruction = Instruction {
     name: "AMOMIN.D",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-
-        let tmp = memory.read_i64(cpu.x[f.rs1] as usize)?;
-        let min = match cpu.x[f.rs2] <=tmp {
-            true => cpu.x[f.rs2],
-            false => tmp as i64
-        };
-        memory.write_u64(cpu.x[f.rs1] as usize, min as u64)?;
-        cpu.x[f.rd] = tmp as i64;
-        Ok(())
+        amo_d(cpu, memory, word, |old, source| (old as i64).min(source as i64) as u64)
     }
 };
 
 pub const AMOMIN_W: Instruction = Instruction {
     name: "AMOMIN.W",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_i32(cpu.x[f.rs1] as usize)?;
-        let min = match (cpu.x[f.rs2] as i32) <= tmp {
-            true => cpu.x[f.rs2] as i32,
-            false => tmp as i32
-        };
-        memory.write_u32(cpu.x[f.rs1] as usize, min as u32)?;
-        cpu.x[f.rd] = tmp as i64;
-        Ok(())
+        amo_w(cpu, memory, word, |old, source| (old as i32).min(source as i32) as u32)
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOMINU_B: Instruction = Instruction {
+    name: "AMOMINU.B",
+    operation: |cpu, memory, word, _address| {
+        amo_b(cpu, memory, word, |old, source| old.min(source))
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOMINU_H: Instruction = Instruction {
+    name: "AMOMINU.H",
+    operation: |cpu, memory, word, _address| {
+        amo_h(cpu, memory, word, |old, source| old.min(source))
     }
 };
 
 pub const AMOMINU_D: Instruction = Instruction {
     name: "AMOMINU.D",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_u64(cpu.x[f.rs1] as usize)?;
-        let min = match (cpu.x[f.rs2] as u64) <= tmp {
-            true => cpu.x[f.rs2] as u64,
-            false => tmp as u64
-        };
-        memory.write_u64(cpu.x[f.rs1] as usize, min)?;
-        cpu.x[f.rd] = tmp as i64;
-        Ok(())
+        amo_d(cpu, memory, word, |old, source| old.min(source))
     }
 };
 
 pub const AMOMINU_W: Instruction = Instruction {
     name: "AMOMINU.W",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_u32(cpu.x[f.rs1] as usize)?;
-        let min = match (cpu.x[f.rs2] as u32) <= tmp {
-            true => cpu.x[f.rs2] as u32,
-            false => tmp as u32
-        };
-        memory.write_u32(cpu.x[f.rs1] as usize, min)?;
-        cpu.x[f.rd] = tmp as i32 as i64;
-        Ok(())
+        amo_w(cpu, memory, word, |old, source| old.min(source))
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOOR_B: Instruction = Instruction {
+    name: "AMOOR.B",
+    operation: |cpu, memory, word, _address| {
+        amo_b(cpu, memory, word, |old, source| old | source)
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOOR_H: Instruction = Instruction {
+    name: "AMOOR.H",
+    operation: |cpu, memory, word, _address| {
+        amo_h(cpu, memory, word, |old, source| old | source)
     }
 };
 
 pub const AMOOR_D: Instruction = Instruction {
     name: "AMOOR.D",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_u64(cpu.x[f.rs1] as usize)?;
-        memory.write_u64(cpu.x[f.rs1] as usize, (cpu.x[f.rs2] as u64) | tmp)?;
-        cpu.x[f.rd] = tmp as i64;
-        Ok(())
+        amo_d(cpu, memory, word, |old, source| old | source)
     }
 };
 
 pub const AMOOR_W: Instruction = Instruction {
     name: "AMOOR.W",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_u32(cpu.x[f.rs1] as usize)?;
-        memory.write_u32(cpu.x[f.rs1] as usize, (cpu.x[f.rs2] as u32) | tmp)?;
-        cpu.x[f.rd] = tmp as i32 as i64;
-        Ok(())
+        amo_w(cpu, memory, word, |old, source| old | source)
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOSWAP_B: Instruction = Instruction {
+    name: "AMOSWAP.B",
+    operation: |cpu, memory, word, _address| {
+        amo_b(cpu, memory, word, |_old, source| source)
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOSWAP_H: Instruction = Instruction {
+    name: "AMOSWAP.H",
+    operation: |cpu, memory, word, _address| {
+        amo_h(cpu, memory, word, |_old, source| source)
     }
 };
 
 pub const AMOSWAP_D: Instruction = Instruction {
     name: "AMOSWAP.D",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_u64(cpu.x[f.rs1] as usize)?;
-        memory.write_u64(cpu.x[f.rs1] as usize, cpu.x[f.rs2] as u64)?;
-        cpu.x[f.rd] = tmp as i64;
-        Ok(())
+        amo_d(cpu, memory, word, |_old, source| source)
     }
 };
 
 pub const AMOSWAP_W: Instruction = Instruction {
     name: "AMOSWAP.W",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_u32(cpu.x[f.rs1] as usize)?;
-        memory.write_u32(cpu.x[f.rs1] as usize, cpu.x[f.rs2] as u32)?;
-        cpu.x[f.rd] = tmp as i32 as i64;
-        Ok(())
+        amo_w(cpu, memory, word, |_old, source| source)
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOXOR_B: Instruction = Instruction {
+    name: "AMOXOR.B",
+    operation: |cpu, memory, word, _address| {
+        amo_b(cpu, memory, word, |old, source| old ^ source)
+    }
+};
+
+#[cfg(feature = "zabha")]
+pub const AMOXOR_H: Instruction = Instruction {
+    name: "AMOXOR.H",
+    operation: |cpu, memory, word, _address| {
+        amo_h(cpu, memory, word, |old, source| old ^ source)
     }
 };
 
 pub const AMOXOR_D: Instruction = Instruction {
     name: "AMOXOR.D",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_u64(cpu.x[f.rs1] as usize)?;
-        memory.write_u64(cpu.x[f.rs1] as usize, cpu.x[f.rs2] as u64 ^ tmp)?;
-        cpu.x[f.rd] = tmp as i64;
-        Ok(())
+        amo_d(cpu, memory, word, |old, source| old ^ source)
     }
 };
 
 pub const AMOXOR_W: Instruction = Instruction {
     name: "AMOXOR.W",
     operation: |cpu, memory, word, _address| {
-        let f = instruction::parse_format_r(word);
-        let tmp = memory.read_u32(cpu.x[f.rs1] as usize)?;
-        memory.write_u32(cpu.x[f.rs1] as usize, cpu.x[f.rs2] as u32 ^ tmp)?;
-        cpu.x[f.rd] = tmp as i32 as i64;
-        Ok(())
+        amo_w(cpu, memory, word, |old, source| old ^ source)
     }
 };
 
+// The reservation remembers the value that was loaded and SC only stores if memory still holds it.
+// Another hart writing the same value back in between goes unnoticed, which is fine for every
+// LR/SC sequence the spec guarantees to make forward progress.
 pub const LR_D: Instruction = Instruction {
     name: "LR.D",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_r(word);
-        // @TODO: Implement properly
-        cpu.x[f.rd] = memory.read_i64(cpu.x[f.rs1] as usize)?;
+        let address = cpu.data_address(cpu.x[f.rs1]);
+        let value = memory.read_u64(address)?;
+        cpu.x[f.rd] = value as i64;
         cpu.is_reservation_set = true;
-        cpu.reservation = cpu.x[f.rs1] as u64; // Is virtual address ok?
+        cpu.reservation = address as u64;
+        cpu.reservation_value = value;
         Ok(())
     }
 };
@@ -251,10 +451,12 @@ pub const LR_W: Instruction = Instruction {
     name: "LR.W",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_r(word);
-        // @TODO: Implement properly
-        cpu.x[f.rd] = memory.read_u32(cpu.x[f.rs1] as usize)? as i64;
+        let address = cpu.data_address(cpu.x[f.rs1]);
+        let value = memory.read_u32(address)?;
+        cpu.x[f.rd] = value as i32 as i64;
         cpu.is_reservation_set = true;
-        cpu.reservation = cpu.x[f.rs1] as u64; // Is virtual address ok?
+        cpu.reservation = address as u64;
+        cpu.reservation_value = value as u64;
         Ok(())
     }
 };
@@ -263,15 +465,11 @@ pub const SC_D: Instruction = Instruction {
     name: "SC.D",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_r(word);
-        // @TODO: Implement properly
-        cpu.x[f.rd] = match cpu.is_reservation_set && cpu.reservation == (cpu.x[f.rs1] as u64) {
-            true => {
-                memory.write_u64(cpu.x[f.rs1] as usize, cpu.x[f.rs2] as u64)?;
-                cpu.is_reservation_set = false;
-                0
-            },
-            false => 1
-        };
+        let address = cpu.data_address(cpu.x[f.rs1]);
+        let stored = cpu.is_reservation_set && cpu.reservation == address as u64 &&
+            memory.compare_exchange_u64(address, cpu.reservation_value, cpu.x[f.rs2] as u64)?.is_ok();
+        cpu.is_reservation_set = false;
+        cpu.x[f.rd] = if stored { 0 } else { 1 };
         Ok(())
     }
 };
@@ -280,15 +478,80 @@ pub const SC_W: Instruction = Instruction {
     name: "SC.W",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_r(word);
-        // @TODO: Implement properly
-        cpu.x[f.rd] = match cpu.is_reservation_set && cpu.reservation == (cpu.x[f.rs1] as u64) {
-            true => {
-                memory.write_u32(cpu.x[f.rs1] as usize, cpu.x[f.rs2] as u32)?;
-                cpu.is_reservation_set = false;
-                0
-            },
-            false => 1
-        };
+        let address = cpu.data_address(cpu.x[f.rs1]);
+        let stored = cpu.is_reservation_set && cpu.reservation == address as u64 &&
+            memory.compare_exchange_u32(address, cpu.reservation_value as u32, cpu.x[f.rs2] as u32)?.is_ok();
+        cpu.is_reservation_set = false;
+        cpu.x[f.rd] = if stored { 0 } else { 1 };
         Ok(())
     }
 };
+
+#[cfg(test)]
+mod test {
+    #[cfg(any(feature = "zacas", feature = "zabha"))]
+    use crate::cpu::Cpu;
+    #[cfg(any(feature = "zacas", feature = "zabha"))]
+    use crate::cpu::test_cpu::program;
+    #[cfg(feature = "zacas")]
+    use crate::cpu::{TrapType, Xlen};
+    #[cfg(any(feature = "zacas", feature = "zabha"))]
+    use crate::memory::Memory;
+
+    // amocas.w a0, a1, (a2); amocas.q a0, a2, (a4) twice and amocas.q a1, a2, (a4), rd being odd
+    #[cfg(feature = "zacas")]
+    const PROGRAM: [u32; 4] = [0x28b6252f, 0x28c7452f, 0x28c7452f, 0x28c745af];
+
+    #[test]
+    #[cfg(feature = "zabha")]
+    fn byte_and_halfword_atomics() {
+        // amoadd.b a0, a1, (a2); amoswap.b a4, zero, (a2); amomaxu.h a5, a1, (a3); amomax.h a6, a1, (a3)
+        let words = [0x00b6052fu32, 0x0806072f, 0xe0b697af, 0xa0b6982f];
+        let mut memory = [program(&words), vec![0; 0x10]].concat();
+        memory.write_u8(0x10, 0x7f).unwrap();
+        memory.write_u8(0x11, 0x55).unwrap();
+        memory.write_u16(0x12, 0x8000).unwrap();
+        let mut cpu = Cpu::new();
+        cpu.x[11..14].copy_from_slice(&[1, 0x10, 0x12]);
+
+        for _ in 0..words.len() {
+            cpu.tick(&mut memory).unwrap();
+        }
+        // the values loaded are sign extended, and the neighbouring bytes are left alone
+        assert_eq!([0x7f, -128, -32768, -32768], [cpu.x[10], cpu.x[14], cpu.x[15], cpu.x[16]]);
+        assert_eq!([0, 0x55], [memory.read_u8(0x10).unwrap(), memory.read_u8(0x11).unwrap()]);
+        assert_eq!(1, memory.read_u16(0x12).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "zacas")]
+    fn compare_and_swap() {
+        let mut memory = [program(&PROGRAM), vec![0; 0x30]].concat();
+        memory.write_u32(0x10, 5).unwrap();
+        memory.write_u64(0x20, 1).unwrap();
+        memory.write_u64(0x28, 2).unwrap();
+        let mut cpu = Cpu::new();
+        cpu.x[10..15].copy_from_slice(&[5, 7, 0x10, 0, 0x20]);
+
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!((7, 5), (memory.read_u32(0x10).unwrap(), cpu.x[10]));
+
+        // the pair a0, a1 matches what is there so a2, a3 is stored, then it no longer does
+        cpu.x[10..14].copy_from_slice(&[1, 2, 3, 4]);
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!([1, 2], cpu.x[10..12]);
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!([3, 4], cpu.x[10..12]);
+        assert_eq!((3, 4), (memory.read_u64(0x20).unwrap(), memory.read_u64(0x28).unwrap()));
+        assert_eq!(TrapType::IllegalInstruction, cpu.tick(&mut memory).unwrap_err().trap_type);
+
+        // amocas.d is the pair sized one in RV32, and amocas.q does not exist
+        let mut cpu = Cpu::builder().xlen(Xlen::Bit32).build();
+        memory[4..8].copy_from_slice(&0x28c7352fu32.to_le_bytes());
+        cpu.x[10..15].copy_from_slice(&[3, 0, -1, 9, 0x20]);
+        cpu.update_pc(4);
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!((0x9_ffff_ffff, [3, 0]), (memory.read_u64(0x20).unwrap(), [cpu.x[10], cpu.x[11]]));
+        assert!(cpu.tick(&mut memory).is_err());
+    }
+}
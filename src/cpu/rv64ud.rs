@@ -1,8 +1,8 @@
-use crate::cpu::instruction;
+use crate::cpu::{instruction, Cpu};
 use crate::cpu::instruction::Instruction;
+use std::cmp::Ordering;
 
 pub const CANONICAL_NAN: u64 = 0x7ff8000000000000;
-pub const SIGNALING_NAN: u64 = 0x7fff000000000000;
 
 
 pub const FADD_D: Instruction = Instruction {
@@ -235,7 +235,7 @@ pub const FSD: Instruction = Instruction {
     name: "FSD",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_s(word);
-        memory.write_u64(cpu.x[f.rs1].wrapping_add(f.imm) as usize, cpu.f[f.rs2].to_bits())
+        memory.write_u64(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)), cpu.f[f.rs2].to_bits())
     }
 };
 
@@ -243,7 +243,7 @@ pub const FLD: Instruction = Instruction {
     name: "FLD",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_i(word);
-        cpu.f[f.rd] = f64::from_bits(memory.read_u64(cpu.x[f.rs1].wrapping_add(f.imm) as usize)?);
+        cpu.f[f.rd] = f64::from_bits(memory.read_u64(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)))?);
         Ok(())
     }
 };
@@ -319,6 +319,25 @@ pub const FSGNJX_D: Instruction = Instruction {
     }
 };
 
+// FMIN and FMAX, the second taking the larger. NaNs never reach a comparison on the host, which
+// is free to use instructions that raise invalid for quiet NaNs too.
+fn min_max(cpu: &mut Cpu, v1: f64, v2: f64, max: bool) -> f64 {
+    let signaling = |v: f64| v.is_nan() && v.to_bits() & 0x0008_0000_0000_0000 == 0;
+    if signaling(v1) || signaling(v2) {
+        cpu.set_fcsr_nv();
+    }
+    match (v1.is_nan(), v2.is_nan()) {
+        (true, true) => f64::from_bits(CANONICAL_NAN),
+        (true, false) => v2,
+        (false, true) => v1,
+        // ordered by their bits, which puts -0.0 before +0.0
+        (false, false) => match (v1.total_cmp(&v2) == Ordering::Greater) == max {
+            true => v1,
+            false => v2
+        }
+    }
+}
+
 pub const FMIN_D: Instruction = Instruction {
     name: "FMIN.D",
     operation: |cpu, _memory, word, _address| {
@@ -326,33 +345,7 @@ pub const FMIN_D: Instruction = Instruction {
         let v1 = cpu.f[f.rs1];
         let v2 = cpu.f[f.rs2];
 
-        let mut result = match (v1.is_sign_positive(), v2.is_sign_positive()) {
-            (true, true) => match v1 < v2 {
-                true => v1,
-                false => v2
-            },
-            (true, false) => v2,
-            (false, true) => v1,
-            (false, false) => match v1 < v2 {
-                true => v1,
-                false => v2
-            }
-        };
-
-        if v1.is_nan() || v2.is_nan() {
-            if v1.is_nan() && v2.is_nan() {
-                if v1.to_bits() == SIGNALING_NAN || v2.to_bits() == SIGNALING_NAN {
-                    cpu.set_fcsr_nv();
-                }
-                result = f64::from_bits(CANONICAL_NAN);
-            } else if v1.is_nan() {
-                result = v2;
-            } else {
-                result = v1;
-            }
-        }
-
-        cpu.f[f.rd] = result;
+        cpu.f[f.rd] = min_max(cpu, v1, v2, false);
         Ok(())
     }
 };
@@ -365,33 +358,7 @@ pub const FMAX_D: Instruction = Instruction {
         let v1 = cpu.f[f.rs1];
         let v2 = cpu.f[f.rs2];
 
-        let mut result = match (v1.is_sign_positive(), v2.is_sign_positive()) {
-            (true, true) => match v1 > v2 {
-                true => v1,
-                false => v2
-            },
-            (true, false) => v1,
-            (false, true) => v2,
-            (false, false) => match v1 > v2 {
-                true => v1,
-                false => v2
-            }
-        };
-
-        if v1.is_nan() || v2.is_nan() {
-            if v1.is_nan() && v2.is_nan() {
-                if v1.to_bits() == SIGNALING_NAN || v2.to_bits() == SIGNALING_NAN {
-                    cpu.set_fcsr_nv();
-                }
-                result = f64::from_bits(CANONICAL_NAN);
-            } else if v1.is_nan() {
-                result = v2;
-            } else {
-                result = v1;
-            }
-        }
-
-        cpu.f[f.rd] = result;
+        cpu.f[f.rd] = min_max(cpu, v1, v2, true);
         Ok(())
     }
 };
@@ -477,4 +444,36 @@ pub const FCVT_LU_D: Instruction = Instruction {
         }
         Ok(())
     }
-};
\ No newline at end of file
+};
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::CSR_FFLAGS_ADDRESS;
+    use crate::cpu::fflags::FFLAG_NV;
+
+    #[test]
+    fn min_max_orders_zeros_and_sorts_out_nans() {
+        let mut cpu = Cpu::new();
+        let quiet = f64::from_bits(CANONICAL_NAN);
+        let signaling = f64::from_bits(0x7ff4_0000_0000_0000);
+        cpu.write_csr(CSR_FFLAGS_ADDRESS, 0);
+
+        // -0.0 is the smaller zero whichever side it is on
+        for (v1, v2) in [(-0.0, 0.0), (0.0, -0.0)] {
+            assert_eq!((-0.0f64).to_bits(), min_max(&mut cpu, v1, v2, false).to_bits());
+            assert_eq!(0.0f64.to_bits(), min_max(&mut cpu, v1, v2, true).to_bits());
+        }
+        // a quiet NaN gives way to the other operand without raising anything
+        assert_eq!(1.5, min_max(&mut cpu, quiet, 1.5, false));
+        assert_eq!(-1.5, min_max(&mut cpu, -1.5, quiet, true));
+        assert_eq!(CANONICAL_NAN, min_max(&mut cpu, quiet, quiet, true).to_bits());
+        assert_eq!(0, cpu.read_csr(CSR_FFLAGS_ADDRESS));
+
+        // a signaling one raises invalid, alone or beside another NaN
+        assert_eq!(2.0, min_max(&mut cpu, signaling, 2.0, true));
+        assert_eq!(FFLAG_NV, cpu.read_csr(CSR_FFLAGS_ADDRESS));
+        cpu.write_csr(CSR_FFLAGS_ADDRESS, 0);
+        assert_eq!(CANONICAL_NAN, min_max(&mut cpu, quiet, signaling, false).to_bits());
+        assert_eq!(FFLAG_NV, cpu.read_csr(CSR_FFLAGS_ADDRESS));
+    }
+}
@@ -1,7 +1,8 @@
-use crate::cpu::{instruction, Xlen};
+use crate::cpu::{instruction, Trap, TrapType, Xlen};
 use crate::cpu::instruction::Instruction;
+use crate::syscalls::semihosting;
 
-pub const ADD: Instruction = Instruction {
+pub static ADD: Instruction = Instruction {
     name: "ADD",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -10,7 +11,7 @@ pub const ADD: Instruction = Instruction {
     }
 };
 
-pub const ADDI: Instruction = Instruction {
+pub static ADDI: Instruction = Instruction {
     name: "ADDI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_i(word);
@@ -19,7 +20,7 @@ pub const ADDI: Instruction = Instruction {
     }
 };
 
-pub const ADDIW: Instruction = Instruction {
+pub static ADDIW: Instruction = Instruction {
     name: "ADDIW",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_i(word);
@@ -28,7 +29,7 @@ pub const ADDIW: Instruction = Instruction {
     }
 };
 
-pub const ADDW: Instruction = Instruction {
+pub static ADDW: Instruction = Instruction {
     name: "ADDW",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -37,7 +38,7 @@ pub const ADDW: Instruction = Instruction {
     }
 };
 
-pub const AND: Instruction = Instruction {
+pub static AND: Instruction = Instruction {
     name: "AND",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -46,7 +47,7 @@ pub const AND: Instruction = Instruction {
     }
 };
 
-pub const ANDI: Instruction = Instruction {
+pub static ANDI: Instruction = Instruction {
     name: "ANDI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_i(word);
@@ -55,7 +56,7 @@ pub const ANDI: Instruction = Instruction {
     }
 };
 
-pub const AUIPC: Instruction = Instruction {
+pub static AUIPC: Instruction = Instruction {
     name: "AUIPC",
     operation: |cpu, _memory, word, address| {
         let f = instruction::parse_format_u(word);
@@ -64,7 +65,7 @@ pub const AUIPC: Instruction = Instruction {
     }
 };
 
-pub const BEQ: Instruction = Instruction {
+pub static BEQ: Instruction = Instruction {
     name: "BEQ",
     operation: |cpu, _memory, word, address| {
         let f = instruction::parse_format_b(word);
@@ -75,7 +76,7 @@ pub const BEQ: Instruction = Instruction {
     }
 };
 
-pub const BGE: Instruction = Instruction {
+pub static BGE: Instruction = Instruction {
     name: "BGE",
     operation: |cpu, _memory, word, address| {
         let f = instruction::parse_format_b(word);
@@ -86,7 +87,7 @@ pub const BGE: Instruction = Instruction {
     }
 };
 
-pub const BGEU: Instruction = Instruction {
+pub static BGEU: Instruction = Instruction {
     name: "BGEU",
     operation: |cpu, _memory, word, address| {
         let f = instruction::parse_format_b(word);
@@ -97,7 +98,7 @@ pub const BGEU: Instruction = Instruction {
     }
 };
 
-pub const BLT: Instruction = Instruction {
+pub static BLT: Instruction = Instruction {
     name: "BLT",
     operation: |cpu, _memory, word, address| {
         let f = instruction::parse_format_b(word);
@@ -108,7 +109,7 @@ pub const BLT: Instruction = Instruction {
     }
 };
 
-pub const BLTU: Instruction = Instruction {
+pub static BLTU: Instruction = Instruction {
     name: "BLTU",
     operation: |cpu, _memory, word, address| {
         let f = instruction::parse_format_b(word);
@@ -119,7 +120,7 @@ pub const BLTU: Instruction = Instruction {
     }
 };
 
-pub const BNE: Instruction = Instruction {
+pub static BNE: Instruction = Instruction {
     name: "BNE",
     operation: |cpu, _memory, word, address| {
         let f = instruction::parse_format_b(word);
@@ -130,16 +131,19 @@ pub const BNE: Instruction = Instruction {
     }
 };
 
-pub const FENCE: Instruction = Instruction {
+// with no predecessor or no successor set there is nothing to order, those encodings are hints
+pub static FENCE: Instruction = Instruction {
     name: "FENCE",
-    operation: |_cpu, _memory, _word, _address| {
-        // Do nothing
+    operation: |_cpu, memory, word, _address| {
+        if (word >> 24) & 0xf != 0 && (word >> 20) & 0xf != 0 {
+            memory.fence();
+        }
         Ok(())
     }
 };
 
 
-pub const JAL: Instruction = Instruction {
+pub static JAL: Instruction = Instruction {
     name: "JAL",
     operation: |cpu, _memory, word, address| {
         let f = instruction::parse_format_j(word);
@@ -149,81 +153,81 @@ pub const JAL: Instruction = Instruction {
     }
 };
 
-pub const JALR: Instruction = Instruction {
+pub static JALR: Instruction = Instruction {
     name: "JALR",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_i(word);
         let tmp = cpu.sign_extend(cpu.pc as i64);
-        cpu.pc = (cpu.x[f.rs1] as u64).wrapping_add(f.imm as u64) as usize;
+        cpu.pc = ((cpu.x[f.rs1] as u64).wrapping_add(f.imm as u64) & !1) as usize;
         cpu.x[f.rd] = tmp;
         Ok(())
     }
 };
 
-pub const LB: Instruction = Instruction {
+pub static LB: Instruction = Instruction {
     name: "LB",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_i(word);
-        cpu.x[f.rd] = memory.read_i8(cpu.x[f.rs1].wrapping_add(f.imm) as usize)? as i64;
+        cpu.x[f.rd] = memory.read_i8(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)))? as i64;
         Ok(())
     }
 };
 
-pub const LBU: Instruction = Instruction {
+pub static LBU: Instruction = Instruction {
     name: "LBU",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_i(word);
-        cpu.x[f.rd] = memory.read_u8(cpu.x[f.rs1].wrapping_add(f.imm) as usize)? as i64;
+        cpu.x[f.rd] = memory.read_u8(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)))? as i64;
         Ok(())
     }
 };
 
-pub const LD: Instruction = Instruction {
+pub static LD: Instruction = Instruction {
     name: "LD",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_i(word);
-        cpu.x[f.rd] = memory.read_i64(cpu.x[f.rs1].wrapping_add(f.imm) as usize)?;
+        cpu.x[f.rd] = memory.read_i64(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)))?;
         Ok(())
     }
 };
 
-pub const LH: Instruction = Instruction {
+pub static LH: Instruction = Instruction {
     name: "LH",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_i(word);
-        cpu.x[f.rd] = memory.read_i16(cpu.x[f.rs1].wrapping_add(f.imm) as usize)? as i64;
+        cpu.x[f.rd] = memory.read_i16(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)))? as i64;
         Ok(())
     }
 };
 
-pub const LHU: Instruction = Instruction {
+pub static LHU: Instruction = Instruction {
     name: "LHU",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_i(word);
-        cpu.x[f.rd] = memory.read_u16(cpu.x[f.rs1].wrapping_add(f.imm) as usize)? as i64;
+        cpu.x[f.rd] = memory.read_u16(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)))? as i64;
         Ok(())
     }
 };
 
-pub const LW: Instruction = Instruction {
+pub static LW: Instruction = Instruction {
     name: "LW",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_i(word);
-        cpu.x[f.rd] = memory.read_i32(cpu.x[f.rs1].wrapping_add(f.imm) as usize)? as i64;
+        cpu.x[f.rd] = memory.read_i32(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)))? as i64;
         Ok(())
     }
 };
 
-pub const LWU: Instruction = Instruction {
+pub static LWU: Instruction = Instruction {
     name: "LWU",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_i(word);
-        cpu.x[f.rd] = memory.read_u32(cpu.x[f.rs1].wrapping_add(f.imm) as usize)? as i64;
+        cpu.x[f.rd] = memory.read_u32(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)))? as i64;
         Ok(())
     }
 };
 
-pub const LUI: Instruction = Instruction {
+pub static LUI: Instruction = Instruction {
     name: "LUI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_u(word);
@@ -232,7 +236,7 @@ pub const LUI: Instruction = Instruction {
     }
 };
 
-pub const OR: Instruction = Instruction {
+pub static OR: Instruction = Instruction {
     name: "OR",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -241,7 +245,7 @@ pub const OR: Instruction = Instruction {
     }
 };
 
-pub const ORI: Instruction = Instruction {
+pub static ORI: Instruction = Instruction {
     name: "ORI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_i(word);
@@ -250,40 +254,51 @@ pub const ORI: Instruction = Instruction {
     }
 };
 
-pub const SB: Instruction = Instruction {
+// the FENCE hint spin loops use, it only ever gives the host thread away when asked to
+pub static PAUSE: Instruction = Instruction {
+    name: "PAUSE",
+    operation: |cpu, _memory, _word, _address| {
+        if cpu.yield_on_pause {
+            std::thread::yield_now();
+        }
+        Ok(())
+    }
+};
+
+pub static SB: Instruction = Instruction {
     name: "SB",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_s(word);
-        memory.write_u8(cpu.x[f.rs1].wrapping_add(f.imm) as usize, cpu.x[f.rs2] as u8)
+        memory.write_u8(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)), cpu.x[f.rs2] as u8)
     }
 };
 
-pub const SD: Instruction = Instruction {
+pub static SD: Instruction = Instruction {
     name: "SD",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_s(word);
-        memory.write_u64(cpu.x[f.rs1].wrapping_add(f.imm) as usize, cpu.x[f.rs2] as u64)
+        memory.write_u64(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)), cpu.x[f.rs2] as u64)
     }
 };
 
-pub const SH: Instruction = Instruction {
+pub static SH: Instruction = Instruction {
     name: "SH",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_s(word);
-        memory.write_u16(cpu.x[f.rs1].wrapping_add(f.imm) as usize, cpu.x[f.rs2] as u16)
+        memory.write_u16(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)), cpu.x[f.rs2] as u16)
     }
 };
 
-pub const SLL: Instruction = Instruction {
+pub static SLL: Instruction = Instruction {
     name: "SLL",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
-        cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rs1].wrapping_shl(cpu.x[f.rs2] as u32));
+        cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rs1] << cpu.shift_amount(cpu.x[f.rs2]));
         Ok(())
     }
 };
 
-pub const SLLI: Instruction = Instruction {
+pub static SLLI: Instruction = Instruction {
     name: "SLLI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -297,7 +312,7 @@ pub const SLLI: Instruction = Instruction {
     }
 };
 
-pub const SLLIW: Instruction = Instruction {
+pub static SLLIW: Instruction = Instruction {
     name: "SLLIW",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -307,7 +322,7 @@ pub const SLLIW: Instruction = Instruction {
     }
 };
 
-pub const SLLW: Instruction = Instruction {
+pub static SLLW: Instruction = Instruction {
     name: "SLLW",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -316,7 +331,7 @@ pub const SLLW: Instruction = Instruction {
     }
 };
 
-pub const SLTI: Instruction = Instruction {
+pub static SLTI: Instruction = Instruction {
     name: "SLTI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_i(word);
@@ -328,7 +343,7 @@ pub const SLTI: Instruction = Instruction {
     }
 };
 
-pub const SLT: Instruction = Instruction {
+pub static SLT: Instruction = Instruction {
     name: "SLT",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -340,7 +355,7 @@ pub const SLT: Instruction = Instruction {
     }
 };
 
-pub const SLTIU: Instruction = Instruction {
+pub static SLTIU: Instruction = Instruction {
     name: "SLTIU",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_i(word);
@@ -352,7 +367,7 @@ pub const SLTIU: Instruction = Instruction {
     }
 };
 
-pub const SLTU: Instruction = Instruction {
+pub static SLTU: Instruction = Instruction {
     name: "SLTU",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -364,16 +379,16 @@ pub const SLTU: Instruction = Instruction {
     }
 };
 
-pub const SRA: Instruction = Instruction {
+pub static SRA: Instruction = Instruction {
     name: "SRA",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
-        cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rs1].wrapping_shr(cpu.x[f.rs2] as u32));
+        cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rs1] >> cpu.shift_amount(cpu.x[f.rs2]));
         Ok(())
     }
 };
 
-pub const SRAI: Instruction = Instruction {
+pub static SRAI: Instruction = Instruction {
     name: "SRAI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -387,7 +402,7 @@ pub const SRAI: Instruction = Instruction {
     }
 };
 
-pub const SRAIW: Instruction = Instruction {
+pub static SRAIW: Instruction = Instruction {
     name: "SRAIW",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -397,7 +412,7 @@ pub const SRAIW: Instruction = Instruction {
     }
 };
 
-pub const SRAW: Instruction = Instruction {
+pub static SRAW: Instruction = Instruction {
     name: "SRAW",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -407,16 +422,16 @@ pub const SRAW: Instruction = Instruction {
 };
 
 
-pub const SRL: Instruction = Instruction {
+pub static SRL: Instruction = Instruction {
     name: "SRL",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
-        cpu.x[f.rd] = cpu.sign_extend(cpu.unsigned_data(cpu.x[f.rs1]).wrapping_shr(cpu.x[f.rs2] as u32) as i64);
+        cpu.x[f.rd] = cpu.sign_extend((cpu.unsigned_data(cpu.x[f.rs1]) >> cpu.shift_amount(cpu.x[f.rs2])) as i64);
         Ok(())
     }
 };
 
-pub const SRLI: Instruction = Instruction {
+pub static SRLI: Instruction = Instruction {
     name: "SRLI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -430,7 +445,7 @@ pub const SRLI: Instruction = Instruction {
     }
 };
 
-pub const SRLIW: Instruction = Instruction {
+pub static SRLIW: Instruction = Instruction {
     name: "SRLIW",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -444,7 +459,7 @@ pub const SRLIW: Instruction = Instruction {
     }
 };
 
-pub const SRLW: Instruction = Instruction {
+pub static SRLW: Instruction = Instruction {
     name: "SRLW",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -453,7 +468,7 @@ pub const SRLW: Instruction = Instruction {
     }
 };
 
-pub const SUB: Instruction = Instruction {
+pub static SUB: Instruction = Instruction {
     name: "SUB",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -462,7 +477,7 @@ pub const SUB: Instruction = Instruction {
     }
 };
 
-pub const SUBW: Instruction = Instruction {
+pub static SUBW: Instruction = Instruction {
     name: "SUBW",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -471,15 +486,15 @@ pub const SUBW: Instruction = Instruction {
     }
 };
 
-pub const SW: Instruction = Instruction {
+pub static SW: Instruction = Instruction {
     name: "SW",
     operation: |cpu, memory, word, _address| {
         let f = instruction::parse_format_s(word);
-        memory.write_u32(cpu.x[f.rs1].wrapping_add(f.imm) as usize, cpu.x[f.rs2] as u32)
+        memory.write_u32(cpu.data_address(cpu.x[f.rs1].wrapping_add(f.imm)), cpu.x[f.rs2] as u32)
     }
 };
 
-pub const XOR: Instruction = Instruction {
+pub static XOR: Instruction = Instruction {
     name: "XOR",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_r(word);
@@ -488,7 +503,7 @@ pub const XOR: Instruction = Instruction {
     }
 };
 
-pub const XORI: Instruction = Instruction {
+pub static XORI: Instruction = Instruction {
     name: "XORI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_i(word);
@@ -497,99 +512,124 @@ pub const XORI: Instruction = Instruction {
     }
 };
 
-pub const CSRRC: Instruction = Instruction {
+// The csr instructions only read the CSR when they have a destination, csrrw and csrrwi, or write
+// it when they have a source, csrrs, csrrc and their immediate forms, so neither side of a
+// handler registered for it runs for nothing. Whether the instruction writes decides if a read
+// only CSR is legal, see check_csr, and a trapping write leaves rd as it was.
+pub static CSRRC: Instruction = Instruction {
     name: "CSRRC",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_csr(word);
-        let data = cpu.read_csr(f.csr) as i64;
-        let tmp = cpu.x[f.rs];
-        cpu.x[f.rd] = cpu.sign_extend(data);
-        cpu.write_csr(f.csr, (cpu.x[f.rd] & !tmp) as u64);
-
+        cpu.check_csr(f.csr, f.rs != 0, word)?;
+        let data = cpu.read_csr_from_guest(f.csr)?;
+        let data = cpu.sign_extend(data as i64);
+        if f.rs != 0 {
+            cpu.write_csr_from_guest(f.csr, (data & !cpu.x[f.rs]) as u64)?;
+        }
+        cpu.x[f.rd] = data;
         Ok(())
     }
 };
 
-pub const CSRRCI: Instruction = Instruction {
+pub static CSRRCI: Instruction = Instruction {
     name: "CSRRCI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_csr(word);
-        let data = cpu.read_csr(f.csr);
-        cpu.x[f.rd] = cpu.sign_extend(data as i64);
-        cpu.write_csr(f.csr, (cpu.x[f.rd] & !(f.rs as i64)) as u64);
+        cpu.check_csr(f.csr, f.rs != 0, word)?;
+        let data = cpu.read_csr_from_guest(f.csr)?;
+        let data = cpu.sign_extend(data as i64);
+        if f.rs != 0 {
+            cpu.write_csr_from_guest(f.csr, (data & !(f.rs as i64)) as u64)?;
+        }
+        cpu.x[f.rd] = data;
         Ok(())
     }
 };
 
-pub const CSRRS: Instruction = Instruction {
+pub static CSRRS: Instruction = Instruction {
     name: "CSRRS",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_csr(word);
-        let data = cpu.read_csr(f.csr);
-        let tmp = cpu.x[f.rs];
-        cpu.x[f.rd] = cpu.sign_extend(data as i64);
-        cpu.write_csr(f.csr, cpu.unsigned_data(cpu.x[f.rd] | tmp));
+        cpu.check_csr(f.csr, f.rs != 0, word)?;
+        let data = cpu.read_csr_from_guest(f.csr)?;
+        let data = cpu.sign_extend(data as i64);
+        if f.rs != 0 {
+            cpu.write_csr_from_guest(f.csr, cpu.unsigned_data(data | cpu.x[f.rs]))?;
+        }
+        cpu.x[f.rd] = data;
         Ok(())
     }
 };
 
-pub const CSRRSI: Instruction = Instruction {
+pub static CSRRSI: Instruction = Instruction {
     name: "CSRRSI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_csr(word);
-        let data = cpu.read_csr(f.csr);
-        cpu.x[f.rd] = cpu.sign_extend(data as i64);
-        cpu.write_csr(f.csr, cpu.unsigned_data(cpu.x[f.rd] | (f.rs as i64)));
+        cpu.check_csr(f.csr, f.rs != 0, word)?;
+        let data = cpu.read_csr_from_guest(f.csr)?;
+        let data = cpu.sign_extend(data as i64);
+        if f.rs != 0 {
+            cpu.write_csr_from_guest(f.csr, cpu.unsigned_data(data | (f.rs as i64)))?;
+        }
+        cpu.x[f.rd] = data;
         Ok(())
     }
 };
 
-pub const CSRRW: Instruction = Instruction {
+pub static CSRRW: Instruction = Instruction {
     name: "CSRRW",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_csr(word);
-        let data = cpu.read_csr(f.csr);
-        let tmp = cpu.x[f.rs];
-        cpu.x[f.rd] = cpu.sign_extend(data as i64);
-        cpu.write_csr(f.csr, cpu.unsigned_data(tmp));
+        cpu.check_csr(f.csr, true, word)?;
+        let data = match f.rd {
+            0 => 0,
+            _ => cpu.read_csr_from_guest(f.csr)?
+        };
+        let data = cpu.sign_extend(data as i64);
+        cpu.write_csr_from_guest(f.csr, cpu.unsigned_data(cpu.x[f.rs]))?;
+        cpu.x[f.rd] = data;
         Ok(())
     }
 };
 
-pub const CSRRWI: Instruction = Instruction {
+pub static CSRRWI: Instruction = Instruction {
     name: "CSRRWI",
     operation: |cpu, _memory, word, _address| {
         let f = instruction::parse_format_csr(word);
-        let data = cpu.read_csr(f.csr);
-        cpu.x[f.rd] = cpu.sign_extend(data as i64);
-        cpu.write_csr(f.csr, f.rs as u64);
+        cpu.check_csr(f.csr, true, word)?;
+        let data = match f.rd {
+            0 => 0,
+            _ => cpu.read_csr_from_guest(f.csr)?
+        };
+        let data = cpu.sign_extend(data as i64);
+        cpu.write_csr_from_guest(f.csr, f.rs as u64)?;
+        cpu.x[f.rd] = data;
         Ok(())
     }
 };
 
-pub const EBREAK: Instruction = Instruction {
+pub static EBREAK: Instruction = Instruction {
     name: "EBREAK",
-    operation: |_cpu, _memory, _word, _address| {
-        // TODO: implement debugger?
-        Ok(())
+    operation: |cpu, memory, word, address| {
+        // without a handler a semihosting call comes back out of tick for the host to serve and
+        // any other ebreak is ignored
+        match cpu.ebreak_handler {
+            Some(handler) => (handler.operation)(cpu, memory, word, address),
+            None if semihosting::is_call(memory, address) => Err(Trap { trap_type: TrapType::Breakpoint, value: address as u64 }),
+            None => Ok(())
+        }
     }
 };
 
-pub const ECALL: Instruction = Instruction {
+pub static ECALL: Instruction = Instruction {
     name: "ECALL",
-    operation: |cpu, memory, word, address| {
-        if let Some(handler) = &cpu.ecall_handler {
-            (handler.operation)(cpu, memory, word, address)
-        } else {
-            Ok(())
-        }
-    }
+    operation: |cpu, memory, word, address| cpu.ecall(memory, word, address)
 };
 
-pub const FENCE_I: Instruction = Instruction {
+pub static FENCE_I: Instruction = Instruction {
     name: "FENCE.I",
-    operation: |_cpu, _memory, _word, _address| {
-        // Do nothing
+    operation: |cpu, _memory, _word, _address| {
+        cpu.invalidate_code_cache();
         Ok(())
     }
 };
@@ -0,0 +1,618 @@
+use crate::cpu::{instruction, Cpu, Trap, Xlen, CSR_VXSAT_ADDRESS};
+use crate::cpu::instruction::Instruction;
+
+// The packed SIMD instructions of the draft P extension, which treat an x register as lanes of 8
+// or 16 bits, as many as XLEN holds. The saturating ones, the K forms, set the OV flag, which P
+// keeps in vxsat.
+
+// how the add and subtract instructions deal with a result that does not fit in a lane
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Wrapping,
+    // the R and UR forms, which halve the result
+    Halving,
+    UnsignedHalving,
+    // the K and UK forms
+    Saturating,
+    UnsignedSaturating
+}
+
+// Runs op on each lane of rs1 along with the same lane of rs2, or the other lane of its pair when
+// crossed, giving it whether the lane is the upper one of its pair and a flag to set when the
+// result saturated.
+fn lanes(cpu: &mut Cpu, word: u32, bits: usize, crossed: bool, mut op: impl FnMut(u64, u64, bool, &mut bool) -> u64) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let xlen = match cpu.xlen {
+        Xlen::Bit32 => 32,
+        Xlen::Bit64 => 64
+    };
+    let (a, b) = (cpu.x[f.rs1] as u64, cpu.x[f.rs2] as u64);
+    let mask = (1 << bits) - 1;
+    let mut result = 0;
+    let mut overflow = false;
+    for lane in 0..xlen / bits {
+        let other = if crossed { lane ^ 1 } else { lane };
+        let value = op((a >> (lane * bits)) & mask, (b >> (other * bits)) & mask, lane & 1 == 1, &mut overflow);
+        result |= (value & mask) << (lane * bits);
+    }
+    if overflow {
+        cpu.csr[CSR_VXSAT_ADDRESS as usize] |= 1;
+    }
+    cpu.x[f.rd] = cpu.sign_extend(result as i64);
+    Ok(())
+}
+
+// the shifts by the low bits of rs2, or by the immediate in its place
+fn shifts(cpu: &mut Cpu, word: u32, bits: usize, immediate: bool, op: impl Fn(u64, u32, &mut bool) -> u64) -> Result<(), Trap> {
+    let rs2 = ((word >> 20) & 0x1f) as usize;
+    let amount = match immediate {
+        true => rs2 as u32,
+        false => cpu.x[rs2] as u32
+    } & (bits as u32 - 1);
+    lanes(cpu, word, bits, false, |a, _, _, overflow| op(a, amount, overflow))
+}
+
+fn signed(value: u64, bits: usize) -> i64 {
+    ((value << (64 - bits)) as i64) >> (64 - bits)
+}
+
+fn saturate(value: i64, bits: usize, signed: bool, overflow: &mut bool) -> u64 {
+    let (min, max) = match signed {
+        true => (-(1 << (bits - 1)), (1 << (bits - 1)) - 1),
+        false => (0, (1 << bits) - 1)
+    };
+    if value < min || value > max {
+        *overflow = true;
+    }
+    value.clamp(min, max) as u64
+}
+
+// a plus b, or minus it when subtracting, worked out wide enough that nothing is lost
+fn add(kind: Kind, a: u64, b: u64, bits: usize, subtract: bool, overflow: &mut bool) -> u64 {
+    let (a, b) = match kind {
+        Kind::UnsignedHalving | Kind::UnsignedSaturating => (a as i64, b as i64),
+        _ => (signed(a, bits), signed(b, bits))
+    };
+    let sum = if subtract { a - b } else { a + b };
+    match kind {
+        Kind::Wrapping => sum as u64,
+        Kind::Halving | Kind::UnsignedHalving => (sum >> 1) as u64,
+        Kind::Saturating => saturate(sum, bits, true, overflow),
+        Kind::UnsignedSaturating => saturate(sum, bits, false, overflow)
+    }
+}
+
+// the .u forms round, adding in the last bit shifted out
+fn shift_right(a: u64, amount: u32, bits: usize, arithmetic: bool, rounding: bool) -> u64 {
+    let value = if arithmetic { signed(a, bits) } else { a as i64 };
+    match rounding && amount > 0 {
+        true => (((value >> (amount - 1)) + 1) >> 1) as u64,
+        false => (value >> amount) as u64
+    }
+}
+
+// Q15 or Q7 multiplication, where the one product that does not fit is -1 times -1
+fn multiply_fraction(a: u64, b: u64, bits: usize, overflow: &mut bool) -> u64 {
+    let min = 1 << (bits - 1);
+    if a == min && b == min {
+        *overflow = true;
+        return min - 1;
+    }
+    ((signed(a, bits) * signed(b, bits)) >> (bits - 1)) as u64
+}
+
+pub const ADD16: Instruction = Instruction {
+    name: "ADD16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, overflow| add(Kind::Wrapping, a, b, 16, false, overflow))
+};
+
+pub const SUB16: Instruction = Instruction {
+    name: "SUB16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, overflow| add(Kind::Wrapping, a, b, 16, true, overflow))
+};
+
+pub const RADD16: Instruction = Instruction {
+    name: "RADD16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, overflow| add(Kind::Halving, a, b, 16, false, overflow))
+};
+
+pub const RSUB16: Instruction = Instruction {
+    name: "RSUB16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, overflow| add(Kind::Halving, a, b, 16, true, overflow))
+};
+
+pub const URADD16: Instruction = Instruction {
+    name: "URADD16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, overflow| add(Kind::UnsignedHalving, a, b, 16, false, overflow))
+};
+
+pub const URSUB16: Instruction = Instruction {
+    name: "URSUB16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, overflow| add(Kind::UnsignedHalving, a, b, 16, true, overflow))
+};
+
+pub const KADD16: Instruction = Instruction {
+    name: "KADD16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, overflow| add(Kind::Saturating, a, b, 16, false, overflow))
+};
+
+pub const KSUB16: Instruction = Instruction {
+    name: "KSUB16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, overflow| add(Kind::Saturating, a, b, 16, true, overflow))
+};
+
+pub const UKADD16: Instruction = Instruction {
+    name: "UKADD16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, overflow| add(Kind::UnsignedSaturating, a, b, 16, false, overflow))
+};
+
+pub const UKSUB16: Instruction = Instruction {
+    name: "UKSUB16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, overflow| add(Kind::UnsignedSaturating, a, b, 16, true, overflow))
+};
+
+pub const ADD8: Instruction = Instruction {
+    name: "ADD8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, overflow| add(Kind::Wrapping, a, b, 8, false, overflow))
+};
+
+pub const SUB8: Instruction = Instruction {
+    name: "SUB8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, overflow| add(Kind::Wrapping, a, b, 8, true, overflow))
+};
+
+pub const RADD8: Instruction = Instruction {
+    name: "RADD8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, overflow| add(Kind::Halving, a, b, 8, false, overflow))
+};
+
+pub const RSUB8: Instruction = Instruction {
+    name: "RSUB8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, overflow| add(Kind::Halving, a, b, 8, true, overflow))
+};
+
+pub const URADD8: Instruction = Instruction {
+    name: "URADD8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, overflow| add(Kind::UnsignedHalving, a, b, 8, false, overflow))
+};
+
+pub const URSUB8: Instruction = Instruction {
+    name: "URSUB8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, overflow| add(Kind::UnsignedHalving, a, b, 8, true, overflow))
+};
+
+pub const KADD8: Instruction = Instruction {
+    name: "KADD8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, overflow| add(Kind::Saturating, a, b, 8, false, overflow))
+};
+
+pub const KSUB8: Instruction = Instruction {
+    name: "KSUB8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, overflow| add(Kind::Saturating, a, b, 8, true, overflow))
+};
+
+pub const UKADD8: Instruction = Instruction {
+    name: "UKADD8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, overflow| add(Kind::UnsignedSaturating, a, b, 8, false, overflow))
+};
+
+pub const UKSUB8: Instruction = Instruction {
+    name: "UKSUB8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, overflow| add(Kind::UnsignedSaturating, a, b, 8, true, overflow))
+};
+
+pub const CRAS16: Instruction = Instruction {
+    name: "CRAS16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, true, |a, b, high, overflow| add(Kind::Wrapping, a, b, 16, !high, overflow))
+};
+
+pub const CRSA16: Instruction = Instruction {
+    name: "CRSA16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, true, |a, b, high, overflow| add(Kind::Wrapping, a, b, 16, high, overflow))
+};
+
+pub const RCRAS16: Instruction = Instruction {
+    name: "RCRAS16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, true, |a, b, high, overflow| add(Kind::Halving, a, b, 16, !high, overflow))
+};
+
+pub const RCRSA16: Instruction = Instruction {
+    name: "RCRSA16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, true, |a, b, high, overflow| add(Kind::Halving, a, b, 16, high, overflow))
+};
+
+pub const URCRAS16: Instruction = Instruction {
+    name: "URCRAS16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, true, |a, b, high, overflow| add(Kind::UnsignedHalving, a, b, 16, !high, overflow))
+};
+
+pub const URCRSA16: Instruction = Instruction {
+    name: "URCRSA16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, true, |a, b, high, overflow| add(Kind::UnsignedHalving, a, b, 16, high, overflow))
+};
+
+pub const KCRAS16: Instruction = Instruction {
+    name: "KCRAS16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, true, |a, b, high, overflow| add(Kind::Saturating, a, b, 16, !high, overflow))
+};
+
+pub const KCRSA16: Instruction = Instruction {
+    name: "KCRSA16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, true, |a, b, high, overflow| add(Kind::Saturating, a, b, 16, high, overflow))
+};
+
+pub const UKCRAS16: Instruction = Instruction {
+    name: "UKCRAS16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, true, |a, b, high, overflow| add(Kind::UnsignedSaturating, a, b, 16, !high, overflow))
+};
+
+pub const UKCRSA16: Instruction = Instruction {
+    name: "UKCRSA16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, true, |a, b, high, overflow| add(Kind::UnsignedSaturating, a, b, 16, high, overflow))
+};
+
+pub const CMPEQ16: Instruction = Instruction {
+    name: "CMPEQ16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, _| if a == b { u64::MAX } else { 0 })
+};
+
+pub const SCMPLT16: Instruction = Instruction {
+    name: "SCMPLT16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, _| if signed(a, 16) < signed(b, 16) { u64::MAX } else { 0 })
+};
+
+pub const SCMPLE16: Instruction = Instruction {
+    name: "SCMPLE16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, _| if signed(a, 16) <= signed(b, 16) { u64::MAX } else { 0 })
+};
+
+pub const UCMPLT16: Instruction = Instruction {
+    name: "UCMPLT16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, _| if a < b { u64::MAX } else { 0 })
+};
+
+pub const UCMPLE16: Instruction = Instruction {
+    name: "UCMPLE16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, _| if a <= b { u64::MAX } else { 0 })
+};
+
+pub const SMIN16: Instruction = Instruction {
+    name: "SMIN16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, _| if signed(a, 16) < signed(b, 16) { a } else { b })
+};
+
+pub const SMAX16: Instruction = Instruction {
+    name: "SMAX16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, _| if signed(a, 16) > signed(b, 16) { a } else { b })
+};
+
+pub const UMIN16: Instruction = Instruction {
+    name: "UMIN16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, _| a.min(b))
+};
+
+pub const UMAX16: Instruction = Instruction {
+    name: "UMAX16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, _| a.max(b))
+};
+
+pub const SRA16: Instruction = Instruction {
+    name: "SRA16",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, false, |a, amount, _| shift_right(a, amount, 16, true, false))
+};
+
+pub const SRA16_U: Instruction = Instruction {
+    name: "SRA16.U",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, false, |a, amount, _| shift_right(a, amount, 16, true, true))
+};
+
+pub const SRL16: Instruction = Instruction {
+    name: "SRL16",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, false, |a, amount, _| shift_right(a, amount, 16, false, false))
+};
+
+pub const SRL16_U: Instruction = Instruction {
+    name: "SRL16.U",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, false, |a, amount, _| shift_right(a, amount, 16, false, true))
+};
+
+pub const SLL16: Instruction = Instruction {
+    name: "SLL16",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, false, |a, amount, _| a << amount)
+};
+
+pub const KSLL16: Instruction = Instruction {
+    name: "KSLL16",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, false, |a, amount, overflow| saturate(signed(a, 16) << amount, 16, true, overflow))
+};
+
+pub const SRAI16: Instruction = Instruction {
+    name: "SRAI16",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, true, |a, amount, _| shift_right(a, amount, 16, true, false))
+};
+
+pub const SRAI16_U: Instruction = Instruction {
+    name: "SRAI16.U",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, true, |a, amount, _| shift_right(a, amount, 16, true, true))
+};
+
+pub const SRLI16: Instruction = Instruction {
+    name: "SRLI16",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, true, |a, amount, _| shift_right(a, amount, 16, false, false))
+};
+
+pub const SRLI16_U: Instruction = Instruction {
+    name: "SRLI16.U",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, true, |a, amount, _| shift_right(a, amount, 16, false, true))
+};
+
+pub const SLLI16: Instruction = Instruction {
+    name: "SLLI16",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, true, |a, amount, _| a << amount)
+};
+
+pub const KSLLI16: Instruction = Instruction {
+    name: "KSLLI16",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 16, true, |a, amount, overflow| saturate(signed(a, 16) << amount, 16, true, overflow))
+};
+
+pub const KHM16: Instruction = Instruction {
+    name: "KHM16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, false, |a, b, _, overflow| multiply_fraction(a, b, 16, overflow))
+};
+
+pub const KHMX16: Instruction = Instruction {
+    name: "KHMX16",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 16, true, |a, b, _, overflow| multiply_fraction(a, b, 16, overflow))
+};
+
+pub const CMPEQ8: Instruction = Instruction {
+    name: "CMPEQ8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, _| if a == b { u64::MAX } else { 0 })
+};
+
+pub const SCMPLT8: Instruction = Instruction {
+    name: "SCMPLT8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, _| if signed(a, 8) < signed(b, 8) { u64::MAX } else { 0 })
+};
+
+pub const SCMPLE8: Instruction = Instruction {
+    name: "SCMPLE8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, _| if signed(a, 8) <= signed(b, 8) { u64::MAX } else { 0 })
+};
+
+pub const UCMPLT8: Instruction = Instruction {
+    name: "UCMPLT8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, _| if a < b { u64::MAX } else { 0 })
+};
+
+pub const UCMPLE8: Instruction = Instruction {
+    name: "UCMPLE8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, _| if a <= b { u64::MAX } else { 0 })
+};
+
+pub const SMIN8: Instruction = Instruction {
+    name: "SMIN8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, _| if signed(a, 8) < signed(b, 8) { a } else { b })
+};
+
+pub const SMAX8: Instruction = Instruction {
+    name: "SMAX8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, _| if signed(a, 8) > signed(b, 8) { a } else { b })
+};
+
+pub const UMIN8: Instruction = Instruction {
+    name: "UMIN8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, _| a.min(b))
+};
+
+pub const UMAX8: Instruction = Instruction {
+    name: "UMAX8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, _| a.max(b))
+};
+
+pub const SRA8: Instruction = Instruction {
+    name: "SRA8",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, false, |a, amount, _| shift_right(a, amount, 8, true, false))
+};
+
+pub const SRA8_U: Instruction = Instruction {
+    name: "SRA8.U",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, false, |a, amount, _| shift_right(a, amount, 8, true, true))
+};
+
+pub const SRL8: Instruction = Instruction {
+    name: "SRL8",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, false, |a, amount, _| shift_right(a, amount, 8, false, false))
+};
+
+pub const SRL8_U: Instruction = Instruction {
+    name: "SRL8.U",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, false, |a, amount, _| shift_right(a, amount, 8, false, true))
+};
+
+pub const SLL8: Instruction = Instruction {
+    name: "SLL8",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, false, |a, amount, _| a << amount)
+};
+
+pub const KSLL8: Instruction = Instruction {
+    name: "KSLL8",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, false, |a, amount, overflow| saturate(signed(a, 8) << amount, 8, true, overflow))
+};
+
+pub const SRAI8: Instruction = Instruction {
+    name: "SRAI8",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, true, |a, amount, _| shift_right(a, amount, 8, true, false))
+};
+
+pub const SRAI8_U: Instruction = Instruction {
+    name: "SRAI8.U",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, true, |a, amount, _| shift_right(a, amount, 8, true, true))
+};
+
+pub const SRLI8: Instruction = Instruction {
+    name: "SRLI8",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, true, |a, amount, _| shift_right(a, amount, 8, false, false))
+};
+
+pub const SRLI8_U: Instruction = Instruction {
+    name: "SRLI8.U",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, true, |a, amount, _| shift_right(a, amount, 8, false, true))
+};
+
+pub const SLLI8: Instruction = Instruction {
+    name: "SLLI8",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, true, |a, amount, _| a << amount)
+};
+
+pub const KSLLI8: Instruction = Instruction {
+    name: "KSLLI8",
+    operation: |cpu, _memory, word, _address| shifts(cpu, word, 8, true, |a, amount, overflow| saturate(signed(a, 8) << amount, 8, true, overflow))
+};
+
+pub const KHM8: Instruction = Instruction {
+    name: "KHM8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, false, |a, b, _, overflow| multiply_fraction(a, b, 8, overflow))
+};
+
+pub const KHMX8: Instruction = Instruction {
+    name: "KHMX8",
+    operation: |cpu, _memory, word, _address| lanes(cpu, word, 8, true, |a, b, _, overflow| multiply_fraction(a, b, 8, overflow))
+};
+
+// everything under the OP-P major opcode with funct3 000, which funct7 tells apart
+pub(crate) fn decode_packed(word: u32) -> Option<&'static Instruction> {
+    if (word >> 12) & 7 != 0 {
+        return None;
+    }
+    let rs2 = (word >> 20) & 0x1f;
+    match word >> 25 {
+        0b0000000 => Some(&RADD16),
+        0b0000001 => Some(&RSUB16),
+        0b0000010 => Some(&RCRAS16),
+        0b0000011 => Some(&RCRSA16),
+        0b0000100 => Some(&RADD8),
+        0b0000101 => Some(&RSUB8),
+        0b0000110 => Some(&SCMPLT16),
+        0b0000111 => Some(&SCMPLT8),
+        0b0001000 => Some(&KADD16),
+        0b0001001 => Some(&KSUB16),
+        0b0001010 => Some(&KCRAS16),
+        0b0001011 => Some(&KCRSA16),
+        0b0001100 => Some(&KADD8),
+        0b0001101 => Some(&KSUB8),
+        0b0001110 => Some(&SCMPLE16),
+        0b0001111 => Some(&SCMPLE8),
+        0b0010000 => Some(&URADD16),
+        0b0010001 => Some(&URSUB16),
+        0b0010010 => Some(&URCRAS16),
+        0b0010011 => Some(&URCRSA16),
+        0b0010100 => Some(&URADD8),
+        0b0010101 => Some(&URSUB8),
+        0b0010110 => Some(&UCMPLT16),
+        0b0010111 => Some(&UCMPLT8),
+        0b0011000 => Some(&UKADD16),
+        0b0011001 => Some(&UKSUB16),
+        0b0011010 => Some(&UKCRAS16),
+        0b0011011 => Some(&UKCRSA16),
+        0b0011100 => Some(&UKADD8),
+        0b0011101 => Some(&UKSUB8),
+        0b0011110 => Some(&UCMPLE16),
+        0b0011111 => Some(&UCMPLE8),
+        0b0100000 => Some(&ADD16),
+        0b0100001 => Some(&SUB16),
+        0b0100010 => Some(&CRAS16),
+        0b0100011 => Some(&CRSA16),
+        0b0100100 => Some(&ADD8),
+        0b0100101 => Some(&SUB8),
+        0b0100110 => Some(&CMPEQ16),
+        0b0100111 => Some(&CMPEQ8),
+        0b0101000 => Some(&SRA16),
+        0b0101001 => Some(&SRL16),
+        0b0101010 => Some(&SLL16),
+        0b0101100 => Some(&SRA8),
+        0b0101101 => Some(&SRL8),
+        0b0101110 => Some(&SLL8),
+        0b0110000 => Some(&SRA16_U),
+        0b0110001 => Some(&SRL16_U),
+        0b0110010 => Some(&KSLL16),
+        0b0110100 => Some(&SRA8_U),
+        0b0110101 => Some(&SRL8_U),
+        0b0110110 => Some(&KSLL8),
+        0b0111000 => Some(match rs2 >> 4 {
+            0 => &SRAI16,
+            _ => &SRAI16_U
+        }),
+        0b0111001 => Some(match rs2 >> 4 {
+            0 => &SRLI16,
+            _ => &SRLI16_U
+        }),
+        0b0111010 => Some(match rs2 >> 4 {
+            0 => &SLLI16,
+            _ => &KSLLI16
+        }),
+        0b0111100 => match rs2 >> 3 {
+            0b00 => Some(&SRAI8),
+            0b01 => Some(&SRAI8_U),
+            _ => None
+        },
+        0b0111101 => match rs2 >> 3 {
+            0b00 => Some(&SRLI8),
+            0b01 => Some(&SRLI8_U),
+            _ => None
+        },
+        0b0111110 => match rs2 >> 3 {
+            0b00 => Some(&SLLI8),
+            0b01 => Some(&KSLLI8),
+            _ => None
+        },
+        0b1000000 => Some(&SMIN16),
+        0b1000001 => Some(&SMAX16),
+        0b1000011 => Some(&KHM16),
+        0b1000100 => Some(&SMIN8),
+        0b1000101 => Some(&SMAX8),
+        0b1000111 => Some(&KHM8),
+        0b1001000 => Some(&UMIN16),
+        0b1001001 => Some(&UMAX16),
+        0b1001011 => Some(&KHMX16),
+        0b1001100 => Some(&UMIN8),
+        0b1001101 => Some(&UMAX8),
+        0b1001111 => Some(&KHMX8),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn packed_lanes() {
+        // add16 a0, a1, a2; kadd16 a3, a1, a2; uradd16 a4, a1, a2; cras16 a5, a1, a2; scmplt8 a6, a1, a2;
+        // srai16.u a7, a1, 4; khm16 t0, a1, a2
+        let program = [0x40c58577u32, 0x10c586f7, 0x20c58777, 0x44c587f7, 0x0ec58877, 0x714588f7, 0x86c582f7];
+        let mut memory = vec![0u8; 0x40];
+        for (i, word) in program.iter().enumerate() {
+            memory[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        let mut cpu = Cpu::new();
+        cpu.x[11] = 0x7fff_0001_ffff_8000;
+        cpu.x[12] = 0x0001_0001_0001_8000;
+        cpu.tick(&mut memory).unwrap();
+        // nothing saturated yet
+        assert_eq!(0, cpu.read_csr(0x009));
+        for _ in 1..program.len() {
+            cpu.tick(&mut memory).unwrap();
+        }
+
+        assert_eq!(0x8000_0002_0000_0000, cpu.x[10] as u64);
+        assert_eq!(0x7fff_0002_0000_8000, cpu.x[13] as u64);
+        assert_eq!(0x4000_0001_8000_8000, cpu.x[14] as u64);
+        assert_eq!(0x8000_0000_7fff_7fff, cpu.x[15] as u64);
+        assert_eq!(0x00ff_0000_ffff_0000, cpu.x[16] as u64);
+        assert_eq!(0x0800_0000_0000_f800, cpu.x[17] as u64);
+        assert_eq!(0x0000_0000_ffff_7fff, cpu.x[5] as u64);
+        assert_eq!(1, cpu.read_csr(0x009));
+    }
+}
@@ -0,0 +1,371 @@
+use crate::cpu::{instruction, quad, Cpu, Trap};
+use crate::cpu::instruction::Instruction;
+use crate::cpu::quad::{DOUBLE, QUAD, SINGLE, SIGN};
+use std::cmp::Ordering;
+
+// writes a quad result to rd, raising the flags that came with it
+fn set_result(cpu: &mut Cpu, rd: usize, (bits, flags): (u128, u64)) {
+    cpu.set_f128(rd, bits);
+    raise(cpu, flags);
+}
+
+fn raise(cpu: &mut Cpu, flags: u64) {
+    if flags != 0 {
+        cpu.raise_fflags(flags);
+    }
+}
+
+fn arithmetic(cpu: &mut Cpu, word: u32, operation: fn(u128, u128, u64) -> (u128, u64)) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let rm = cpu.rounding_mode(word)?;
+    let result = operation(cpu.get_f128(f.rs1), cpu.get_f128(f.rs2), rm);
+    set_result(cpu, f.rd, result);
+    Ok(())
+}
+
+fn fused(cpu: &mut Cpu, word: u32, negate_product: bool, negate_addend: bool) -> Result<(), Trap> {
+    let f = instruction::parse_format_r2(word);
+    let rm = cpu.rounding_mode(word)?;
+    let result = quad::fused_multiply_add(cpu.get_f128(f.rs1), cpu.get_f128(f.rs2), cpu.get_f128(f.rs3), negate_product, negate_addend, rm);
+    set_result(cpu, f.rd, result);
+    Ok(())
+}
+
+fn to_integer(cpu: &mut Cpu, word: u32, signed: bool, width: u32) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let (value, flags) = quad::to_integer(cpu.get_f128(f.rs1), cpu.rounding_mode(word)?, signed, width);
+    // the word sized results are sign extended whether they are signed or not
+    cpu.x[f.rd] = match width {
+        32 => value as i32 as i64,
+        _ => value
+    };
+    raise(cpu, flags);
+    Ok(())
+}
+
+fn from_integer(cpu: &mut Cpu, word: u32, value: fn(i64) -> i128) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let bits = quad::from_integer(value(cpu.x[f.rs1]));
+    cpu.set_f128(f.rd, bits);
+    Ok(())
+}
+
+// FLT and FLE are signaling comparisons, invalid for any NaN, FEQ only for signaling ones
+fn comparison(cpu: &mut Cpu, word: u32, signaling: bool, holds: fn(Ordering) -> bool) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let (a, b) = (cpu.get_f128(f.rs1), cpu.get_f128(f.rs2));
+    let invalid = match signaling {
+        true => quad::is_nan(a) || quad::is_nan(b),
+        false => quad::is_signaling(a) || quad::is_signaling(b)
+    };
+    if invalid {
+        cpu.set_fcsr_nv();
+    }
+    cpu.x[f.rd] = quad::compare(a, b).is_some_and(holds) as i64;
+    Ok(())
+}
+
+fn sign_injection(cpu: &mut Cpu, word: u32, sign: fn(u128, u128) -> u128) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let (a, b) = (cpu.get_f128(f.rs1), cpu.get_f128(f.rs2));
+    cpu.set_f128(f.rd, a & !SIGN | sign(a, b) & SIGN);
+    Ok(())
+}
+
+pub const FADD_Q: Instruction = Instruction {
+    name: "FADD.Q",
+    operation: |cpu, _memory, word, _address| {
+        arithmetic(cpu, word, quad::add)
+    }
+};
+
+pub const FCLASS_Q: Instruction = Instruction {
+    name: "FCLASS.Q",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = quad::classify(cpu.get_f128(f.rs1)) as i64;
+        Ok(())
+    }
+};
+
+pub const FCVT_D_Q: Instruction = Instruction {
+    name: "FCVT.D.Q",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (bits, flags) = quad::convert(cpu.get_f128(f.rs1), QUAD, DOUBLE, cpu.rounding_mode(word)?);
+        cpu.f[f.rd] = f64::from_bits(bits as u64);
+        raise(cpu, flags);
+        Ok(())
+    }
+};
+
+pub const FCVT_L_Q: Instruction = Instruction {
+    name: "FCVT.L.Q",
+    operation: |cpu, _memory, word, _address| {
+        to_integer(cpu, word, true, 64)
+    }
+};
+
+pub const FCVT_LU_Q: Instruction = Instruction {
+    name: "FCVT.LU.Q",
+    operation: |cpu, _memory, word, _address| {
+        to_integer(cpu, word, false, 64)
+    }
+};
+
+pub const FCVT_Q_D: Instruction = Instruction {
+    name: "FCVT.Q.D",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let result = quad::convert(cpu.f[f.rs1].to_bits() as u128, DOUBLE, QUAD, cpu.rounding_mode(word)?);
+        set_result(cpu, f.rd, result);
+        Ok(())
+    }
+};
+
+pub const FCVT_Q_L: Instruction = Instruction {
+    name: "FCVT.Q.L",
+    operation: |cpu, _memory, word, _address| {
+        from_integer(cpu, word, |x| x as i128)
+    }
+};
+
+pub const FCVT_Q_LU: Instruction = Instruction {
+    name: "FCVT.Q.LU",
+    operation: |cpu, _memory, word, _address| {
+        from_integer(cpu, word, |x| x as u64 as i128)
+    }
+};
+
+pub const FCVT_Q_S: Instruction = Instruction {
+    name: "FCVT.Q.S",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let single = cpu.get_f32(f.rs1).to_bits() as u128;
+        let result = quad::convert(single, SINGLE, QUAD, cpu.rounding_mode(word)?);
+        set_result(cpu, f.rd, result);
+        Ok(())
+    }
+};
+
+pub const FCVT_Q_W: Instruction = Instruction {
+    name: "FCVT.Q.W",
+    operation: |cpu, _memory, word, _address| {
+        from_integer(cpu, word, |x| x as i32 as i128)
+    }
+};
+
+pub const FCVT_Q_WU: Instruction = Instruction {
+    name: "FCVT.Q.WU",
+    operation: |cpu, _memory, word, _address| {
+        from_integer(cpu, word, |x| x as u32 as i128)
+    }
+};
+
+pub const FCVT_S_Q: Instruction = Instruction {
+    name: "FCVT.S.Q",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (bits, flags) = quad::convert(cpu.get_f128(f.rs1), QUAD, SINGLE, cpu.rounding_mode(word)?);
+        cpu.set_f32(f.rd, f32::from_bits(bits as u32));
+        raise(cpu, flags);
+        Ok(())
+    }
+};
+
+pub const FCVT_W_Q: Instruction = Instruction {
+    name: "FCVT.W.Q",
+    operation: |cpu, _memory, word, _address| {
+        to_integer(cpu, word, true, 32)
+    }
+};
+
+pub const FCVT_WU_Q: Instruction = Instruction {
+    name: "FCVT.WU.Q",
+    operation: |cpu, _memory, word, _address| {
+        to_integer(cpu, word, false, 32)
+    }
+};
+
+pub const FDIV_Q: Instruction = Instruction {
+    name: "FDIV.Q",
+    operation: |cpu, _memory, word, _address| {
+        arithmetic(cpu, word, quad::div)
+    }
+};
+
+pub const FEQ_Q: Instruction = Instruction {
+    name: "FEQ.Q",
+    operation: |cpu, _memory, word, _address| {
+        comparison(cpu, word, false, Ordering::is_eq)
+    }
+};
+
+pub const FLE_Q: Instruction = Instruction {
+    name: "FLE.Q",
+    operation: |cpu, _memory, word, _address| {
+        comparison(cpu, word, true, Ordering::is_le)
+    }
+};
+
+pub const FLQ: Instruction = Instruction {
+    name: "FLQ",
+    operation: |cpu, memory, word, _address| {
+        let f = instruction::parse_format_i(word);
+        let address = cpu.x[f.rs1].wrapping_add(f.imm);
+        let low = memory.read_u64(cpu.data_address(address))?;
+        let high = memory.read_u64(cpu.data_address(address.wrapping_add(8)))?;
+        cpu.set_f128(f.rd, (high as u128) << 64 | low as u128);
+        Ok(())
+    }
+};
+
+pub const FLT_Q: Instruction = Instruction {
+    name: "FLT.Q",
+    operation: |cpu, _memory, word, _address| {
+        comparison(cpu, word, true, Ordering::is_lt)
+    }
+};
+
+pub const FMADD_Q: Instruction = Instruction {
+    name: "FMADD.Q",
+    operation: |cpu, _memory, word, _address| {
+        fused(cpu, word, false, false)
+    }
+};
+
+pub const FMAX_Q: Instruction = Instruction {
+    name: "FMAX.Q",
+    operation: |cpu, _memory, word, _address| {
+        arithmetic(cpu, word, |a, b, _rm| quad::min_max(a, b, true))
+    }
+};
+
+pub const FMIN_Q: Instruction = Instruction {
+    name: "FMIN.Q",
+    operation: |cpu, _memory, word, _address| {
+        arithmetic(cpu, word, |a, b, _rm| quad::min_max(a, b, false))
+    }
+};
+
+pub const FMSUB_Q: Instruction = Instruction {
+    name: "FMSUB.Q",
+    operation: |cpu, _memory, word, _address| {
+        fused(cpu, word, false, true)
+    }
+};
+
+pub const FMUL_Q: Instruction = Instruction {
+    name: "FMUL.Q",
+    operation: |cpu, _memory, word, _address| {
+        arithmetic(cpu, word, quad::mul)
+    }
+};
+
+pub const FNMADD_Q: Instruction = Instruction {
+    name: "FNMADD.Q",
+    operation: |cpu, _memory, word, _address| {
+        fused(cpu, word, true, true)
+    }
+};
+
+pub const FNMSUB_Q: Instruction = Instruction {
+    name: "FNMSUB.Q",
+    operation: |cpu, _memory, word, _address| {
+        fused(cpu, word, true, false)
+    }
+};
+
+pub const FSGNJ_Q: Instruction = Instruction {
+    name: "FSGNJ.Q",
+    operation: |cpu, _memory, word, _address| {
+        sign_injection(cpu, word, |_a, b| b)
+    }
+};
+
+pub const FSGNJN_Q: Instruction = Instruction {
+    name: "FSGNJN.Q",
+    operation: |cpu, _memory, word, _address| {
+        sign_injection(cpu, word, |_a, b| !b)
+    }
+};
+
+pub const FSGNJX_Q: Instruction = Instruction {
+    name: "FSGNJX.Q",
+    operation: |cpu, _memory, word, _address| {
+        sign_injection(cpu, word, |a, b| a ^ b)
+    }
+};
+
+pub const FSQ: Instruction = Instruction {
+    name: "FSQ",
+    operation: |cpu, memory, word, _address| {
+        let f = instruction::parse_format_s(word);
+        let address = cpu.x[f.rs1].wrapping_add(f.imm);
+        let bits = cpu.get_f128(f.rs2);
+        memory.write_u64(cpu.data_address(address), bits as u64)?;
+        memory.write_u64(cpu.data_address(address.wrapping_add(8)), (bits >> 64) as u64)
+    }
+};
+
+pub const FSQRT_Q: Instruction = Instruction {
+    name: "FSQRT.Q",
+    operation: |cpu, _memory, word, _address| {
+        arithmetic(cpu, word, |a, _b, rm| quad::sqrt(a, rm))
+    }
+};
+
+pub const FSUB_Q: Instruction = Instruction {
+    name: "FSUB.Q",
+    operation: |cpu, _memory, word, _address| {
+        arithmetic(cpu, word, quad::sub)
+    }
+};
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::{Cpu, Xlen, CSR_FFLAGS_ADDRESS};
+    use crate::cpu::extensions::Extensions;
+    use crate::cpu::fflags::FFLAG_NX;
+
+    const DATA: usize = 0x100;
+
+    #[test]
+    fn quad_precision() {
+        // fcvt.q.l fa0, a1; fcvt.q.l fa1, a2; fdiv.q fa2, fa0, fa1; fsq fa2, 0(a0); flq fa3, 0(a0);
+        // fmsub.q fa4, fa3, fa1, fa0; fcvt.d.q fa5, fa3; flt.q a3, fa4, fa0; fclass.q a4, fa4;
+        // fcvt.w.q a5, fa1, rtz; fneg.q fa6, fa0; fadd.d fa7, fa5, fa5
+        let words = [0xd625f553u32, 0xd62675d3, 0x1eb57653, 0x00c54027, 0x00054687, 0x56b6f747, 0x4236f7d3, 0xa6a716d3,
+            0xe6071753, 0xc60597d3, 0x26a51853, 0x02f7f8d3];
+        let mut memory = vec![0u8; DATA + 16];
+        for (i, word) in words.iter().enumerate() {
+            memory[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        let mut cpu = Cpu::new();
+        cpu.x[10..13].copy_from_slice(&[DATA as i64, 1, 3]);
+        cpu.write_csr(CSR_FFLAGS_ADDRESS, 0);
+        for _ in 0..words.len() {
+            cpu.tick(&mut memory).unwrap();
+        }
+
+        let third = 0x3ffd5555555555555555555555555555u128;
+        assert_eq!([0x3fff << 112, 0x40008 << 108, third, third], [cpu.get_f128(10), cpu.get_f128(11), cpu.get_f128(12), cpu.get_f128(13)]);
+        assert_eq!(third.to_le_bytes(), memory[DATA..]);
+        // the error in rounding a third, exactly
+        assert_eq!(0xbf8d << 112, cpu.get_f128(14));
+        assert_eq!([1, 2, 3], cpu.x[13..16]);
+        assert_eq!(0xbfff << 112, cpu.get_f128(16));
+        // doubles written by a conversion or by D itself are NaN boxed
+        assert_eq!((u64::MAX as u128) << 64 | (1.0f64 / 3.0).to_bits() as u128, cpu.get_f128(15));
+        assert_eq!((u64::MAX as u128) << 64 | (2.0f64 / 3.0).to_bits() as u128, cpu.get_f128(17));
+        assert_eq!(FFLAG_NX, cpu.read_csr(CSR_FFLAGS_ADDRESS));
+
+        // fcvt.l.q is RV64 only, and without Q the quad instructions are illegal
+        assert!(Cpu::decode_for(0xc625f853, Xlen::Bit32).is_none());
+        assert!(Cpu::decode_for(0xc625f853, Xlen::Bit64).is_some());
+        assert_ne!(0, cpu.read_csr(0x301) & 1 << 16);
+        cpu.set_extensions(Extensions::all().without('D'));
+        cpu.update_pc(0);
+        assert!(cpu.tick(&mut memory).is_err());
+    }
+}
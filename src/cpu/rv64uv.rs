@@ -0,0 +1,1366 @@
+use crate::cpu::{Cpu, Memory, Trap};
+use crate::cpu::instruction;
+use crate::cpu::instruction::Instruction;
+use crate::cpu::rv64uvf::decode_vector_fp;
+use crate::cpu::vector::{canonical_nan, check_group, illegal, sign_extend, truncate, CSR_VL_ADDRESS, CSR_VSTART_ADDRESS, CSR_VTYPE_ADDRESS, Vtype};
+
+// The integer instructions of V. Most come as vector-vector, vector-scalar and vector-immediate
+// forms told apart by funct3, so one constant covers all the forms of an instruction and the
+// helpers pick its second operand. Tails and masked off elements are always left undisturbed.
+
+// where the second operand comes from, vs1 or a scalar every element shares
+pub(crate) enum Operand {
+    Vector(usize),
+    Scalar(u64)
+}
+
+impl Operand {
+    pub(crate) fn get(&self, cpu: &Cpu, index: usize, eew: usize) -> u64 {
+        match self {
+            Operand::Vector(vreg) => cpu.vector_element(*vreg, index, eew),
+            Operand::Scalar(value) => truncate(*value, eew)
+        }
+    }
+}
+
+pub(crate) fn operand(cpu: &Cpu, word: u32, sew: usize) -> Operand {
+    let rs1 = ((word >> 15) & 0x1f) as usize;
+    match (word >> 12) & 7 {
+        // shifts, slides and gathers take the immediate unsigned
+        0b011 => Operand::Scalar(match word >> 26 {
+            0b001100..=0b001111 | 0b100101.. => rs1 as u64,
+            _ => sign_extend(rs1 as u64, 5) as u64
+        }),
+        0b100 | 0b110 => Operand::Scalar(cpu.x[rs1] as u64),
+        // a single that is not NaN boxed reads as the canonical NaN
+        0b101 => Operand::Scalar(match cpu.f[rs1].to_bits() {
+            bits if sew == 32 && bits >> 32 != 0xffffffff => canonical_nan(32),
+            bits => bits
+        }),
+        // the unary ones use the vs1 field to say which operation they are
+        0b001 | 0b010 if matches!(word >> 26, 0b010010 | 0b010011) => Operand::Scalar(0),
+        _ => Operand::Vector(rs1)
+    }
+}
+
+// the widths of vd, vs2 and the second operand, as powers of two times SEW
+pub(crate) const SINGLE: [i32; 3] = [0, 0, 0];
+pub(crate) const WIDENING: [i32; 3] = [1, 0, 0];
+pub(crate) const WIDE: [i32; 3] = [1, 1, 0];
+pub(crate) const NARROWING: [i32; 3] = [0, 1, 0];
+
+// Runs op on each active element from vstart up to vl, giving it the elements of vs2, the second
+// operand and vd along with SEW, and writing what it returns to vd.
+pub(crate) fn elements(cpu: &mut Cpu, word: u32, widths: [i32; 3], mut op: impl FnMut(u64, u64, u64, usize) -> u64) -> Result<(), Trap> {
+    let (vtype, vl, vstart) = cpu.vector_config(word)?;
+    let f = instruction::parse_format_r(word);
+    let [vd_eew, vs2_eew, operand_eew] = widths.map(|width| vtype.sew << width);
+    let operand = operand(cpu, word, vtype.sew);
+    let mut groups = vec![(f.rd, vd_eew), (f.rs2, vs2_eew)];
+    if let Operand::Vector(vs1) = operand {
+        groups.push((vs1, operand_eew));
+    }
+    for (vreg, eew) in groups {
+        if eew > 64 {
+            return Err(illegal(word));
+        }
+        check_group(vreg, vtype.emul_log2(eew), word)?;
+    }
+
+    for i in vstart..vl {
+        if cpu.element_active(word, i) {
+            let b = operand.get(cpu, i, operand_eew);
+            let result = op(cpu.vector_element(f.rs2, i, vs2_eew), b, cpu.vector_element(f.rd, i, vd_eew), vtype.sew);
+            cpu.set_vector_element(f.rd, i, vd_eew, result);
+        }
+    }
+    cpu.set_vstart(0);
+    Ok(())
+}
+
+// vadc, vsbc and vmerge, which take a carry or a choice from v0 for every element rather than
+// using it as a mask
+pub(crate) fn carrying(cpu: &mut Cpu, word: u32, op: impl Fn(u64, u64, bool, usize) -> u64) -> Result<(), Trap> {
+    let (vtype, vl, vstart) = cpu.vector_config(word)?;
+    let f = instruction::parse_format_r(word);
+    let operand = operand(cpu, word, vtype.sew);
+    check_group(f.rd, vtype.lmul_log2, word)?;
+    check_group(f.rs2, vtype.lmul_log2, word)?;
+    if let Operand::Vector(vs1) = operand {
+        check_group(vs1, vtype.lmul_log2, word)?;
+    }
+
+    let carries = (word >> 25) & 1 == 0;
+    for i in vstart..vl {
+        let carry = carries && cpu.mask_bit(0, i);
+        let b = operand.get(cpu, i, vtype.sew);
+        let result = op(cpu.vector_element(f.rs2, i, vtype.sew), b, carry, vtype.sew);
+        cpu.set_vector_element(f.rd, i, vtype.sew, result);
+    }
+    cpu.set_vstart(0);
+    Ok(())
+}
+
+// The compares, vmadc and vmsbc, which write a bit of vd for each element. The carry out ones
+// work on every element, taking a carry in from v0 when vm is clear.
+pub(crate) fn mask_result(cpu: &mut Cpu, word: u32, carries: bool, mut op: impl FnMut(u64, u64, bool, usize) -> bool) -> Result<(), Trap> {
+    let (vtype, vl, vstart) = cpu.vector_config(word)?;
+    let f = instruction::parse_format_r(word);
+    let operand = operand(cpu, word, vtype.sew);
+    check_group(f.rs2, vtype.lmul_log2, word)?;
+    if let Operand::Vector(vs1) = operand {
+        check_group(vs1, vtype.lmul_log2, word)?;
+    }
+
+    let carry_in = carries && (word >> 25) & 1 == 0;
+    for i in vstart..vl {
+        if carries || cpu.element_active(word, i) {
+            let carry = carry_in && cpu.mask_bit(0, i);
+            let bit = op(cpu.vector_element(f.rs2, i, vtype.sew), operand.get(cpu, i, vtype.sew), carry, vtype.sew);
+            cpu.set_mask_bit(f.rd, i, bit);
+        }
+    }
+    cpu.set_vstart(0);
+    Ok(())
+}
+
+// The reductions fold the active elements of vs2 into the first element of vs1 and write the
+// result to the first element of vd, which is twice SEW for the widening ones. With vl at 0 vd
+// is left alone.
+pub(crate) fn reduce(cpu: &mut Cpu, word: u32, widening: bool, mut op: impl FnMut(u64, u64, usize) -> u64) -> Result<(), Trap> {
+    let (vtype, vl, vstart) = cpu.vector_config(word)?;
+    let f = instruction::parse_format_r(word);
+    let eew = vtype.sew << widening as usize;
+    if eew > 64 || vstart != 0 {
+        return Err(illegal(word));
+    }
+    check_group(f.rs2, vtype.lmul_log2, word)?;
+
+    if vl > 0 {
+        let mut accumulator = cpu.vector_element(f.rs1, 0, eew);
+        for i in 0..vl {
+            if cpu.element_active(word, i) {
+                accumulator = op(accumulator, cpu.vector_element(f.rs2, i, vtype.sew), vtype.sew);
+            }
+        }
+        cpu.set_vector_element(f.rd, 0, eew, accumulator);
+    }
+    Ok(())
+}
+
+// vzext and vsext, growing elements of vs2 by the factor in the vs1 field
+fn extend(cpu: &mut Cpu, word: u32, signed: bool) -> Result<(), Trap> {
+    let (vtype, vl, vstart) = cpu.vector_config(word)?;
+    let f = instruction::parse_format_r(word);
+    let eew = vtype.sew >> (4 - (f.rs1 >> 1));
+    if eew < 8 {
+        return Err(illegal(word));
+    }
+    check_group(f.rd, vtype.lmul_log2, word)?;
+    check_group(f.rs2, vtype.emul_log2(eew), word)?;
+
+    for i in vstart..vl {
+        if cpu.element_active(word, i) {
+            let value = cpu.vector_element(f.rs2, i, eew);
+            let value = match signed {
+                true => sign_extend(value, eew) as u64,
+                false => value
+            };
+            cpu.set_vector_element(f.rd, i, vtype.sew, value);
+        }
+    }
+    cpu.set_vstart(0);
+    Ok(())
+}
+
+// the mask logical instructions, which work on every bit up to vl whatever the mask says
+fn mask_logical(cpu: &mut Cpu, word: u32, op: impl Fn(bool, bool) -> bool) -> Result<(), Trap> {
+    let (_, vl, vstart) = cpu.vector_config(word)?;
+    let f = instruction::parse_format_r(word);
+    for i in vstart..vl {
+        let bit = op(cpu.mask_bit(f.rs2, i), cpu.mask_bit(f.rs1, i));
+        cpu.set_mask_bit(f.rd, i, bit);
+    }
+    cpu.set_vstart(0);
+    Ok(())
+}
+
+// The active bits of the mask in vs2 up to vl. Like the others that look at all of a mask at once
+// these can't start part way through.
+fn active_bits(cpu: &Cpu, word: u32) -> Result<Vec<(usize, bool)>, Trap> {
+    let (_, vl, vstart) = cpu.vector_config(word)?;
+    if vstart != 0 {
+        return Err(illegal(word));
+    }
+    let vs2 = ((word >> 20) & 0x1f) as usize;
+    Ok((0..vl).filter(|&i| cpu.element_active(word, i)).map(|i| (i, cpu.mask_bit(vs2, i))).collect())
+}
+
+// vmsbf.m, vmsif.m and vmsof.m, op being given whether the first set bit has gone by yet
+fn set_first(cpu: &mut Cpu, word: u32, op: impl Fn(bool, bool) -> bool) -> Result<(), Trap> {
+    let rd = ((word >> 7) & 0x1f) as usize;
+    let mut found = false;
+    for (i, bit) in active_bits(cpu, word)? {
+        cpu.set_mask_bit(rd, i, op(found, bit));
+        found |= bit;
+    }
+    Ok(())
+}
+
+pub(crate) fn signed(value: u64, sew: usize) -> i64 {
+    sign_extend(value, sew)
+}
+
+fn shift(amount: u64, bits: usize) -> u32 {
+    (amount & (bits as u64 - 1)) as u32
+}
+
+fn read(memory: &dyn Memory, address: usize, eew: usize) -> Result<u64, Trap> {
+    match eew {
+        8 => memory.read_u8(address).map(u64::from),
+        16 => memory.read_u16(address).map(u64::from),
+        32 => memory.read_u32(address).map(u64::from),
+        _ => memory.read_u64(address)
+    }
+}
+
+fn write(memory: &mut dyn Memory, address: usize, eew: usize, value: u64) -> Result<(), Trap> {
+    match eew {
+        8 => memory.write_u8(address, value as u8),
+        16 => memory.write_u16(address, value as u16),
+        32 => memory.write_u32(address, value as u32),
+        _ => memory.write_u64(address, value)
+    }
+}
+
+// how a load or store finds the address of each element after the first
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Addressing {
+    UnitStride,
+    // the byte stride is in rs2
+    Strided,
+    // the offsets are the elements of vs2, eew bits wide
+    Indexed
+}
+
+// Loads and stores of nf + 1 fields to an element, each field going to its own register group
+// one after the other, which is the plain load or store when there is just the one. The data is
+// eew bits wide except for the indexed ones, where eew is the width of the offsets and the data
+// is SEW wide. A trap leaves vstart at the element that caused it, so that the instruction carries
+// on from there once the trap is dealt with, except for a fault-only-first load past the first
+// element, which cuts vl short there instead.
+fn transfer(cpu: &mut Cpu, memory: &mut dyn Memory, word: u32, eew: usize, store: bool, addressing: Addressing, fault_only_first: bool) -> Result<(), Trap> {
+    let (vtype, vl, vstart) = cpu.vector_config(word)?;
+    let f = instruction::parse_format_r(word);
+    let fields = (word >> 29) as usize + 1;
+    let (data_eew, emul_log2) = match addressing {
+        Addressing::Indexed => {
+            check_group(f.rs2, vtype.emul_log2(eew), word)?;
+            (vtype.sew, vtype.lmul_log2)
+        },
+        _ => (eew, vtype.emul_log2(eew))
+    };
+    check_group(f.rd, emul_log2, word)?;
+    let registers = 1 << emul_log2.max(0);
+    if fields * registers > 8 || f.rd + fields * registers > 32 {
+        return Err(illegal(word));
+    }
+
+    let base = cpu.x[f.rs1];
+    let stride = match addressing {
+        Addressing::Strided => cpu.x[f.rs2],
+        _ => (fields * data_eew / 8) as i64
+    };
+    for i in vstart..vl {
+        if !cpu.element_active(word, i) {
+            continue;
+        }
+        let offset = match addressing {
+            Addressing::Indexed => cpu.vector_element(f.rs2, i, eew) as i64,
+            _ => stride.wrapping_mul(i as i64)
+        };
+        for field in 0..fields {
+            let address = cpu.data_address(base.wrapping_add(offset).wrapping_add((field * data_eew / 8) as i64));
+            let vreg = f.rd + field * registers;
+            let result = match store {
+                true => write(memory, address, data_eew, cpu.vector_element(vreg, i, data_eew)),
+                false => read(memory, address, data_eew).map(|value| cpu.set_vector_element(vreg, i, data_eew, value))
+            };
+            match result {
+                Err(_) if fault_only_first && i > 0 => {
+                    cpu.csr[CSR_VL_ADDRESS as usize] = i as u64;
+                    cpu.set_vstart(0);
+                    return Ok(());
+                },
+                Err(trap) => {
+                    cpu.set_vstart(i);
+                    return Err(trap);
+                },
+                Ok(()) => {}
+            }
+        }
+    }
+    cpu.set_vstart(0);
+    Ok(())
+}
+
+// vl<nf>re<eew>.v and vs<nf>r.v move nf + 1 whole registers whatever vtype and vl are
+fn whole_registers(cpu: &mut Cpu, memory: &mut dyn Memory, word: u32, eew: usize, store: bool) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let registers = (word >> 29) as usize + 1;
+    check_group(f.rd, registers.trailing_zeros() as i32, word)?;
+
+    let base = cpu.x[f.rs1];
+    let vstart = cpu.csr[CSR_VSTART_ADDRESS as usize] as usize;
+    for i in vstart..registers * cpu.vlen() / eew {
+        let address = cpu.data_address(base.wrapping_add((i * eew / 8) as i64));
+        let result = match store {
+            true => write(memory, address, eew, cpu.vector_element(f.rd, i, eew)),
+            false => read(memory, address, eew).map(|value| cpu.set_vector_element(f.rd, i, eew, value))
+        };
+        if let Err(trap) = result {
+            cpu.set_vstart(i);
+            return Err(trap);
+        }
+    }
+    cpu.set_vstart(0);
+    Ok(())
+}
+
+// vlm.v and vsm.v move the bytes of a mask, enough of them for vl bits
+fn mask_transfer(cpu: &mut Cpu, memory: &mut dyn Memory, word: u32, store: bool) -> Result<(), Trap> {
+    let (_, vl, vstart) = cpu.vector_config(word)?;
+    let f = instruction::parse_format_r(word);
+
+    let base = cpu.x[f.rs1];
+    for i in vstart..vl.div_ceil(8) {
+        let address = cpu.data_address(base.wrapping_add(i as i64));
+        let result = match store {
+            true => write(memory, address, 8, cpu.vector_element(f.rd, i, 8)),
+            false => read(memory, address, 8).map(|value| cpu.set_vector_element(f.rd, i, 8, value))
+        };
+        if let Err(trap) = result {
+            cpu.set_vstart(i);
+            return Err(trap);
+        }
+    }
+    cpu.set_vstart(0);
+    Ok(())
+}
+
+pub const VSETVLI: Instruction = Instruction {
+    name: "VSETVLI",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let avl = match f.rs1 {
+            0 => None,
+            rs1 => Some(cpu.unsigned_data(cpu.x[rs1]))
+        };
+        cpu.set_vector_config(f.rd, avl, ((word >> 20) & 0x7ff) as u64);
+        Ok(())
+    }
+};
+
+pub const VSETIVLI: Instruction = Instruction {
+    name: "VSETIVLI",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.set_vector_config(f.rd, Some(f.rs1 as u64), ((word >> 20) & 0x3ff) as u64);
+        Ok(())
+    }
+};
+
+pub const VSETVL: Instruction = Instruction {
+    name: "VSETVL",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let avl = match f.rs1 {
+            0 => None,
+            rs1 => Some(cpu.unsigned_data(cpu.x[rs1]))
+        };
+        let vtype = cpu.unsigned_data(cpu.x[f.rs2]);
+        cpu.set_vector_config(f.rd, avl, vtype);
+        Ok(())
+    }
+};
+
+pub const VLE8_V: Instruction = Instruction {
+    name: "VLE8.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 8, false, Addressing::UnitStride, false)
+};
+
+pub const VLE16_V: Instruction = Instruction {
+    name: "VLE16.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 16, false, Addressing::UnitStride, false)
+};
+
+pub const VLE32_V: Instruction = Instruction {
+    name: "VLE32.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 32, false, Addressing::UnitStride, false)
+};
+
+pub const VLE64_V: Instruction = Instruction {
+    name: "VLE64.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 64, false, Addressing::UnitStride, false)
+};
+
+pub const VLE8FF_V: Instruction = Instruction {
+    name: "VLE8FF.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 8, false, Addressing::UnitStride, true)
+};
+
+pub const VLE16FF_V: Instruction = Instruction {
+    name: "VLE16FF.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 16, false, Addressing::UnitStride, true)
+};
+
+pub const VLE32FF_V: Instruction = Instruction {
+    name: "VLE32FF.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 32, false, Addressing::UnitStride, true)
+};
+
+pub const VLE64FF_V: Instruction = Instruction {
+    name: "VLE64FF.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 64, false, Addressing::UnitStride, true)
+};
+
+pub const VLSE8_V: Instruction = Instruction {
+    name: "VLSE8.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 8, false, Addressing::Strided, false)
+};
+
+pub const VLSE16_V: Instruction = Instruction {
+    name: "VLSE16.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 16, false, Addressing::Strided, false)
+};
+
+pub const VLSE32_V: Instruction = Instruction {
+    name: "VLSE32.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 32, false, Addressing::Strided, false)
+};
+
+pub const VLSE64_V: Instruction = Instruction {
+    name: "VLSE64.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 64, false, Addressing::Strided, false)
+};
+
+pub const VLUXEI8_V: Instruction = Instruction {
+    name: "VLUXEI8.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 8, false, Addressing::Indexed, false)
+};
+
+pub const VLUXEI16_V: Instruction = Instruction {
+    name: "VLUXEI16.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 16, false, Addressing::Indexed, false)
+};
+
+pub const VLUXEI32_V: Instruction = Instruction {
+    name: "VLUXEI32.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 32, false, Addressing::Indexed, false)
+};
+
+pub const VLUXEI64_V: Instruction = Instruction {
+    name: "VLUXEI64.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 64, false, Addressing::Indexed, false)
+};
+
+pub const VLOXEI8_V: Instruction = Instruction {
+    name: "VLOXEI8.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 8, false, Addressing::Indexed, false)
+};
+
+pub const VLOXEI16_V: Instruction = Instruction {
+    name: "VLOXEI16.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 16, false, Addressing::Indexed, false)
+};
+
+pub const VLOXEI32_V: Instruction = Instruction {
+    name: "VLOXEI32.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 32, false, Addressing::Indexed, false)
+};
+
+pub const VLOXEI64_V: Instruction = Instruction {
+    name: "VLOXEI64.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 64, false, Addressing::Indexed, false)
+};
+
+pub const VLRE8_V: Instruction = Instruction {
+    name: "VLRE8.V",
+    operation: |cpu, memory, word, _address| whole_registers(cpu, memory, word, 8, false)
+};
+
+pub const VLRE16_V: Instruction = Instruction {
+    name: "VLRE16.V",
+    operation: |cpu, memory, word, _address| whole_registers(cpu, memory, word, 16, false)
+};
+
+pub const VLRE32_V: Instruction = Instruction {
+    name: "VLRE32.V",
+    operation: |cpu, memory, word, _address| whole_registers(cpu, memory, word, 32, false)
+};
+
+pub const VLRE64_V: Instruction = Instruction {
+    name: "VLRE64.V",
+    operation: |cpu, memory, word, _address| whole_registers(cpu, memory, word, 64, false)
+};
+
+pub const VSE8_V: Instruction = Instruction {
+    name: "VSE8.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 8, true, Addressing::UnitStride, false)
+};
+
+pub const VSE16_V: Instruction = Instruction {
+    name: "VSE16.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 16, true, Addressing::UnitStride, false)
+};
+
+pub const VSE32_V: Instruction = Instruction {
+    name: "VSE32.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 32, true, Addressing::UnitStride, false)
+};
+
+pub const VSE64_V: Instruction = Instruction {
+    name: "VSE64.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 64, true, Addressing::UnitStride, false)
+};
+
+pub const VSSE8_V: Instruction = Instruction {
+    name: "VSSE8.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 8, true, Addressing::Strided, false)
+};
+
+pub const VSSE16_V: Instruction = Instruction {
+    name: "VSSE16.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 16, true, Addressing::Strided, false)
+};
+
+pub const VSSE32_V: Instruction = Instruction {
+    name: "VSSE32.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 32, true, Addressing::Strided, false)
+};
+
+pub const VSSE64_V: Instruction = Instruction {
+    name: "VSSE64.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 64, true, Addressing::Strided, false)
+};
+
+pub const VSUXEI8_V: Instruction = Instruction {
+    name: "VSUXEI8.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 8, true, Addressing::Indexed, false)
+};
+
+pub const VSUXEI16_V: Instruction = Instruction {
+    name: "VSUXEI16.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 16, true, Addressing::Indexed, false)
+};
+
+pub const VSUXEI32_V: Instruction = Instruction {
+    name: "VSUXEI32.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 32, true, Addressing::Indexed, false)
+};
+
+pub const VSUXEI64_V: Instruction = Instruction {
+    name: "VSUXEI64.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 64, true, Addressing::Indexed, false)
+};
+
+pub const VSOXEI8_V: Instruction = Instruction {
+    name: "VSOXEI8.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 8, true, Addressing::Indexed, false)
+};
+
+pub const VSOXEI16_V: Instruction = Instruction {
+    name: "VSOXEI16.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 16, true, Addressing::Indexed, false)
+};
+
+pub const VSOXEI32_V: Instruction = Instruction {
+    name: "VSOXEI32.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 32, true, Addressing::Indexed, false)
+};
+
+pub const VSOXEI64_V: Instruction = Instruction {
+    name: "VSOXEI64.V",
+    operation: |cpu, memory, word, _address| transfer(cpu, memory, word, 64, true, Addressing::Indexed, false)
+};
+
+pub const VSR_V: Instruction = Instruction {
+    name: "VSR.V",
+    operation: |cpu, memory, word, _address| whole_registers(cpu, memory, word, 8, true)
+};
+
+pub const VLM_V: Instruction = Instruction {
+    name: "VLM.V",
+    operation: |cpu, memory, word, _address| mask_transfer(cpu, memory, word, false)
+};
+
+pub const VSM_V: Instruction = Instruction {
+    name: "VSM.V",
+    operation: |cpu, memory, word, _address| mask_transfer(cpu, memory, word, true)
+};
+
+pub const VADD: Instruction = Instruction {
+    name: "VADD",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, _| a.wrapping_add(b))
+};
+
+pub const VSUB: Instruction = Instruction {
+    name: "VSUB",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, _| a.wrapping_sub(b))
+};
+
+pub const VRSUB: Instruction = Instruction {
+    name: "VRSUB",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, _| b.wrapping_sub(a))
+};
+
+pub const VMINU: Instruction = Instruction {
+    name: "VMINU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, _| a.min(b))
+};
+
+pub const VMIN: Instruction = Instruction {
+    name: "VMIN",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, sew| signed(a, sew).min(signed(b, sew)) as u64)
+};
+
+pub const VMAXU: Instruction = Instruction {
+    name: "VMAXU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, _| a.max(b))
+};
+
+pub const VMAX: Instruction = Instruction {
+    name: "VMAX",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, sew| signed(a, sew).max(signed(b, sew)) as u64)
+};
+
+pub const VAND: Instruction = Instruction {
+    name: "VAND",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, _| a & b)
+};
+
+pub const VOR: Instruction = Instruction {
+    name: "VOR",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, _| a | b)
+};
+
+pub const VXOR: Instruction = Instruction {
+    name: "VXOR",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, _| a ^ b)
+};
+
+pub const VADC: Instruction = Instruction {
+    name: "VADC",
+    operation: |cpu, _memory, word, _address| carrying(cpu, word, |a, b, carry, _| a.wrapping_add(b).wrapping_add(carry as u64))
+};
+
+pub const VSBC: Instruction = Instruction {
+    name: "VSBC",
+    operation: |cpu, _memory, word, _address| carrying(cpu, word, |a, b, borrow, _| a.wrapping_sub(b).wrapping_sub(borrow as u64))
+};
+
+// vmv.v.v, vmv.v.x and vmv.v.i when vm is set
+pub const VMERGE: Instruction = Instruction {
+    name: "VMERGE",
+    operation: |cpu, _memory, word, _address| carrying(cpu, word, |a, b, first, _| if first || (word >> 25) & 1 == 1 { b } else { a })
+};
+
+pub const VMADC: Instruction = Instruction {
+    name: "VMADC",
+    operation: |cpu, _memory, word, _address| mask_result(cpu, word, true, |a, b, carry, sew| {
+        (a as u128 + b as u128 + carry as u128) >> sew != 0
+    })
+};
+
+pub const VMSBC: Instruction = Instruction {
+    name: "VMSBC",
+    operation: |cpu, _memory, word, _address| mask_result(cpu, word, true, |a, b, borrow, _| {
+        (a as u128) < b as u128 + borrow as u128
+    })
+};
+
+pub const VMSEQ: Instruction = Instruction {
+    name: "VMSEQ",
+    operation: |cpu, _memory, word, _address| mask_result(cpu, word, false, |a, b, _, _| a == b)
+};
+
+pub const VMSNE: Instruction = Instruction {
+    name: "VMSNE",
+    operation: |cpu, _memory, word, _address| mask_result(cpu, word, false, |a, b, _, _| a != b)
+};
+
+pub const VMSLTU: Instruction = Instruction {
+    name: "VMSLTU",
+    operation: |cpu, _memory, word, _address| mask_result(cpu, word, false, |a, b, _, _| a < b)
+};
+
+pub const VMSLT: Instruction = Instruction {
+    name: "VMSLT",
+    operation: |cpu, _memory, word, _address| mask_result(cpu, word, false, |a, b, _, sew| signed(a, sew) < signed(b, sew))
+};
+
+pub const VMSLEU: Instruction = Instruction {
+    name: "VMSLEU",
+    operation: |cpu, _memory, word, _address| mask_result(cpu, word, false, |a, b, _, _| a <= b)
+};
+
+pub const VMSLE: Instruction = Instruction {
+    name: "VMSLE",
+    operation: |cpu, _memory, word, _address| mask_result(cpu, word, false, |a, b, _, sew| signed(a, sew) <= signed(b, sew))
+};
+
+pub const VMSGTU: Instruction = Instruction {
+    name: "VMSGTU",
+    operation: |cpu, _memory, word, _address| mask_result(cpu, word, false, |a, b, _, _| a > b)
+};
+
+pub const VMSGT: Instruction = Instruction {
+    name: "VMSGT",
+    operation: |cpu, _memory, word, _address| mask_result(cpu, word, false, |a, b, _, sew| signed(a, sew) > signed(b, sew))
+};
+
+pub const VSLL: Instruction = Instruction {
+    name: "VSLL",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, sew| a << shift(b, sew))
+};
+
+pub const VSRL: Instruction = Instruction {
+    name: "VSRL",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, sew| a >> shift(b, sew))
+};
+
+pub const VSRA: Instruction = Instruction {
+    name: "VSRA",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, sew| (signed(a, sew) >> shift(b, sew)) as u64)
+};
+
+pub const VNSRL: Instruction = Instruction {
+    name: "VNSRL.W",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, NARROWING, |a, b, _, sew| a >> shift(b, sew * 2))
+};
+
+pub const VNSRA: Instruction = Instruction {
+    name: "VNSRA.W",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, NARROWING, |a, b, _, sew| (signed(a, sew * 2) >> shift(b, sew * 2)) as u64)
+};
+
+// vmv1r.v, vmv2r.v, vmv4r.v and vmv8r.v, which copy whole registers whatever vtype and vl are
+pub const VMVNR_V: Instruction = Instruction {
+    name: "VMVNR.V",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let count = f.rs1 + 1;
+        if !f.rd.is_multiple_of(count) || !f.rs2.is_multiple_of(count) {
+            return Err(illegal(word));
+        }
+        let sew = Vtype::parse(cpu.csr[CSR_VTYPE_ADDRESS as usize]).map_or(8, |vtype| vtype.sew);
+        let vlenb = cpu.vlenb();
+        let start = (cpu.csr[CSR_VSTART_ADDRESS as usize] as usize * sew / 8).min(count * vlenb);
+        cpu.v.copy_within(f.rs2 * vlenb + start..(f.rs2 + count) * vlenb, f.rd * vlenb + start);
+        cpu.set_vstart(0);
+        Ok(())
+    }
+};
+
+pub const VDIVU: Instruction = Instruction {
+    name: "VDIVU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, _| a.checked_div(b).unwrap_or(u64::MAX))
+};
+
+pub const VDIV: Instruction = Instruction {
+    name: "VDIV",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, sew| match signed(b, sew) {
+        0 => u64::MAX,
+        b => signed(a, sew).wrapping_div(b) as u64
+    })
+};
+
+pub const VREMU: Instruction = Instruction {
+    name: "VREMU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, _| a.checked_rem(b).unwrap_or(a))
+};
+
+pub const VREM: Instruction = Instruction {
+    name: "VREM",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, sew| match signed(b, sew) {
+        0 => a,
+        b => signed(a, sew).wrapping_rem(b) as u64
+    })
+};
+
+pub const VMULHU: Instruction = Instruction {
+    name: "VMULHU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, sew| ((a as u128 * b as u128) >> sew) as u64)
+};
+
+pub const VMUL: Instruction = Instruction {
+    name: "VMUL",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, _| a.wrapping_mul(b))
+};
+
+pub const VMULHSU: Instruction = Instruction {
+    name: "VMULHSU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, sew| ((signed(a, sew) as i128 * b as i128) >> sew) as u64)
+};
+
+pub const VMULH: Instruction = Instruction {
+    name: "VMULH",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, _, sew| ((signed(a, sew) as i128 * signed(b, sew) as i128) >> sew) as u64)
+};
+
+pub const VMADD: Instruction = Instruction {
+    name: "VMADD",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, d, _| b.wrapping_mul(d).wrapping_add(a))
+};
+
+pub const VNMSUB: Instruction = Instruction {
+    name: "VNMSUB",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, d, _| a.wrapping_sub(b.wrapping_mul(d)))
+};
+
+pub const VMACC: Instruction = Instruction {
+    name: "VMACC",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, d, _| b.wrapping_mul(a).wrapping_add(d))
+};
+
+pub const VNMSAC: Instruction = Instruction {
+    name: "VNMSAC",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, SINGLE, |a, b, d, _| d.wrapping_sub(b.wrapping_mul(a)))
+};
+
+pub const VWADDU: Instruction = Instruction {
+    name: "VWADDU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDENING, |a, b, _, _| a + b)
+};
+
+pub const VWADD: Instruction = Instruction {
+    name: "VWADD",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDENING, |a, b, _, sew| (signed(a, sew) + signed(b, sew)) as u64)
+};
+
+pub const VWSUBU: Instruction = Instruction {
+    name: "VWSUBU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDENING, |a, b, _, _| a.wrapping_sub(b))
+};
+
+pub const VWSUB: Instruction = Instruction {
+    name: "VWSUB",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDENING, |a, b, _, sew| (signed(a, sew) - signed(b, sew)) as u64)
+};
+
+pub const VWADDU_W: Instruction = Instruction {
+    name: "VWADDU.W",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDE, |a, b, _, _| a.wrapping_add(b))
+};
+
+pub const VWADD_W: Instruction = Instruction {
+    name: "VWADD.W",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDE, |a, b, _, sew| a.wrapping_add(signed(b, sew) as u64))
+};
+
+pub const VWSUBU_W: Instruction = Instruction {
+    name: "VWSUBU.W",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDE, |a, b, _, _| a.wrapping_sub(b))
+};
+
+pub const VWSUB_W: Instruction = Instruction {
+    name: "VWSUB.W",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDE, |a, b, _, sew| a.wrapping_sub(signed(b, sew) as u64))
+};
+
+pub const VWMULU: Instruction = Instruction {
+    name: "VWMULU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDENING, |a, b, _, _| a * b)
+};
+
+pub const VWMULSU: Instruction = Instruction {
+    name: "VWMULSU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDENING, |a, b, _, sew| signed(a, sew).wrapping_mul(b as i64) as u64)
+};
+
+pub const VWMUL: Instruction = Instruction {
+    name: "VWMUL",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDENING, |a, b, _, sew| (signed(a, sew) * signed(b, sew)) as u64)
+};
+
+pub const VWMACCU: Instruction = Instruction {
+    name: "VWMACCU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDENING, |a, b, d, _| d.wrapping_add(a * b))
+};
+
+pub const VWMACC: Instruction = Instruction {
+    name: "VWMACC",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDENING, |a, b, d, sew| d.wrapping_add((signed(a, sew) * signed(b, sew)) as u64))
+};
+
+pub const VWMACCUS: Instruction = Instruction {
+    name: "VWMACCUS",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDENING, |a, b, d, sew| d.wrapping_add(signed(a, sew).wrapping_mul(b as i64) as u64))
+};
+
+pub const VWMACCSU: Instruction = Instruction {
+    name: "VWMACCSU",
+    operation: |cpu, _memory, word, _address| elements(cpu, word, WIDENING, |a, b, d, sew| d.wrapping_add(signed(b, sew).wrapping_mul(a as i64) as u64))
+};
+
+pub const VREDSUM: Instruction = Instruction {
+    name: "VREDSUM.VS",
+    operation: |cpu, _memory, word, _address| reduce(cpu, word, false, |sum, e, _| sum.wrapping_add(e))
+};
+
+pub const VREDAND: Instruction = Instruction {
+    name: "VREDAND.VS",
+    operation: |cpu, _memory, word, _address| reduce(cpu, word, false, |all, e, _| all & e)
+};
+
+pub const VREDOR: Instruction = Instruction {
+    name: "VREDOR.VS",
+    operation: |cpu, _memory, word, _address| reduce(cpu, word, false, |any, e, _| any | e)
+};
+
+pub const VREDXOR: Instruction = Instruction {
+    name: "VREDXOR.VS",
+    operation: |cpu, _memory, word, _address| reduce(cpu, word, false, |parity, e, _| parity ^ e)
+};
+
+pub const VREDMINU: Instruction = Instruction {
+    name: "VREDMINU.VS",
+    operation: |cpu, _memory, word, _address| reduce(cpu, word, false, |least, e, _| least.min(e))
+};
+
+pub const VREDMIN: Instruction = Instruction {
+    name: "VREDMIN.VS",
+    operation: |cpu, _memory, word, _address| reduce(cpu, word, false, |least, e, sew| signed(least, sew).min(signed(e, sew)) as u64)
+};
+
+pub const VREDMAXU: Instruction = Instruction {
+    name: "VREDMAXU.VS",
+    operation: |cpu, _memory, word, _address| reduce(cpu, word, false, |most, e, _| most.max(e))
+};
+
+pub const VREDMAX: Instruction = Instruction {
+    name: "VREDMAX.VS",
+    operation: |cpu, _memory, word, _address| reduce(cpu, word, false, |most, e, sew| signed(most, sew).max(signed(e, sew)) as u64)
+};
+
+pub const VWREDSUMU: Instruction = Instruction {
+    name: "VWREDSUMU.VS",
+    operation: |cpu, _memory, word, _address| reduce(cpu, word, true, |sum, e, _| sum.wrapping_add(e))
+};
+
+pub const VWREDSUM: Instruction = Instruction {
+    name: "VWREDSUM.VS",
+    operation: |cpu, _memory, word, _address| reduce(cpu, word, true, |sum, e, sew| sum.wrapping_add(signed(e, sew) as u64))
+};
+
+pub const VZEXT: Instruction = Instruction {
+    name: "VZEXT",
+    operation: |cpu, _memory, word, _address| extend(cpu, word, false)
+};
+
+pub const VSEXT: Instruction = Instruction {
+    name: "VSEXT",
+    operation: |cpu, _memory, word, _address| extend(cpu, word, true)
+};
+
+pub const VMV_X_S: Instruction = Instruction {
+    name: "VMV.X.S",
+    operation: |cpu, _memory, word, _address| {
+        let (vtype, _, _) = cpu.vector_config(word)?;
+        let f = instruction::parse_format_r(word);
+        let value = signed(cpu.vector_element(f.rs2, 0, vtype.sew), vtype.sew);
+        cpu.x[f.rd] = cpu.sign_extend(value);
+        cpu.set_vstart(0);
+        Ok(())
+    }
+};
+
+pub const VMV_S_X: Instruction = Instruction {
+    name: "VMV.S.X",
+    operation: |cpu, _memory, word, _address| {
+        let (vtype, vl, vstart) = cpu.vector_config(word)?;
+        let f = instruction::parse_format_r(word);
+        if vstart < vl {
+            let value = cpu.x[f.rs1] as u64;
+            cpu.set_vector_element(f.rd, 0, vtype.sew, value);
+        }
+        cpu.set_vstart(0);
+        Ok(())
+    }
+};
+
+pub const VCPOP_M: Instruction = Instruction {
+    name: "VCPOP.M",
+    operation: |cpu, _memory, word, _address| {
+        let count = active_bits(cpu, word)?.iter().filter(|(_, bit)| *bit).count();
+        cpu.x[((word >> 7) & 0x1f) as usize] = count as i64;
+        Ok(())
+    }
+};
+
+pub const VFIRST_M: Instruction = Instruction {
+    name: "VFIRST.M",
+    operation: |cpu, _memory, word, _address| {
+        let first = active_bits(cpu, word)?.iter().find(|(_, bit)| *bit).map(|(i, _)| *i as i64);
+        cpu.x[((word >> 7) & 0x1f) as usize] = first.unwrap_or(-1);
+        Ok(())
+    }
+};
+
+pub const VMSBF_M: Instruction = Instruction {
+    name: "VMSBF.M",
+    operation: |cpu, _memory, word, _address| set_first(cpu, word, |found, bit| !found && !bit)
+};
+
+pub const VMSIF_M: Instruction = Instruction {
+    name: "VMSIF.M",
+    operation: |cpu, _memory, word, _address| set_first(cpu, word, |found, _| !found)
+};
+
+pub const VMSOF_M: Instruction = Instruction {
+    name: "VMSOF.M",
+    operation: |cpu, _memory, word, _address| set_first(cpu, word, |found, bit| !found && bit)
+};
+
+// each active element gets the number of set bits in vs2 before it
+pub const VIOTA_M: Instruction = Instruction {
+    name: "VIOTA.M",
+    operation: |cpu, _memory, word, _address| {
+        let (vtype, _, _) = cpu.vector_config(word)?;
+        let rd = ((word >> 7) & 0x1f) as usize;
+        check_group(rd, vtype.lmul_log2, word)?;
+        let mut count = 0;
+        for (i, bit) in active_bits(cpu, word)? {
+            cpu.set_vector_element(rd, i, vtype.sew, count);
+            count += bit as u64;
+        }
+        Ok(())
+    }
+};
+
+pub const VID_V: Instruction = Instruction {
+    name: "VID.V",
+    operation: |cpu, _memory, word, _address| {
+        let (vtype, vl, vstart) = cpu.vector_config(word)?;
+        let rd = ((word >> 7) & 0x1f) as usize;
+        check_group(rd, vtype.lmul_log2, word)?;
+        for i in vstart..vl {
+            if cpu.element_active(word, i) {
+                cpu.set_vector_element(rd, i, vtype.sew, i as u64);
+            }
+        }
+        cpu.set_vstart(0);
+        Ok(())
+    }
+};
+
+pub const VMANDN_MM: Instruction = Instruction {
+    name: "VMANDN.MM",
+    operation: |cpu, _memory, word, _address| mask_logical(cpu, word, |a, b| a && !b)
+};
+
+pub const VMAND_MM: Instruction = Instruction {
+    name: "VMAND.MM",
+    operation: |cpu, _memory, word, _address| mask_logical(cpu, word, |a, b| a && b)
+};
+
+pub const VMOR_MM: Instruction = Instruction {
+    name: "VMOR.MM",
+    operation: |cpu, _memory, word, _address| mask_logical(cpu, word, |a, b| a || b)
+};
+
+pub const VMXOR_MM: Instruction = Instruction {
+    name: "VMXOR.MM",
+    operation: |cpu, _memory, word, _address| mask_logical(cpu, word, |a, b| a != b)
+};
+
+pub const VMORN_MM: Instruction = Instruction {
+    name: "VMORN.MM",
+    operation: |cpu, _memory, word, _address| mask_logical(cpu, word, |a, b| a || !b)
+};
+
+pub const VMNAND_MM: Instruction = Instruction {
+    name: "VMNAND.MM",
+    operation: |cpu, _memory, word, _address| mask_logical(cpu, word, |a, b| !(a && b))
+};
+
+pub const VMNOR_MM: Instruction = Instruction {
+    name: "VMNOR.MM",
+    operation: |cpu, _memory, word, _address| mask_logical(cpu, word, |a, b| !(a || b))
+};
+
+pub const VMXNOR_MM: Instruction = Instruction {
+    name: "VMXNOR.MM",
+    operation: |cpu, _memory, word, _address| mask_logical(cpu, word, |a, b| a == b)
+};
+
+// everything under the OP-V major opcode
+pub(crate) fn decode_vector(word: u32) -> Option<&'static Instruction> {
+    let funct6 = word >> 26;
+    let unmasked = (word >> 25) & 1 == 1;
+    let vs1 = (word >> 15) & 0x1f;
+    let vs2 = (word >> 20) & 0x1f;
+    match (word >> 12) & 7 {
+        0b111 => match word >> 30 {
+            0b00 | 0b01 => Some(&VSETVLI),
+            0b11 => Some(&VSETIVLI),
+            _ if (word >> 25) & 0x1f == 0 => Some(&VSETVL),
+            _ => None
+        },
+        // OPIVV, OPIVI and OPIVX
+        funct3 @ (0b000 | 0b011 | 0b100) => {
+            let (vector, immediate) = (funct3 == 0b000, funct3 == 0b011);
+            match funct6 {
+                0b000000 => Some(&VADD),
+                0b000010 if !immediate => Some(&VSUB),
+                0b000011 if !vector => Some(&VRSUB),
+                0b000100 if !immediate => Some(&VMINU),
+                0b000101 if !immediate => Some(&VMIN),
+                0b000110 if !immediate => Some(&VMAXU),
+                0b000111 if !immediate => Some(&VMAX),
+                0b001001 => Some(&VAND),
+                0b001010 => Some(&VOR),
+                0b001011 => Some(&VXOR),
+                0b010000 if !unmasked => Some(&VADC),
+                0b010001 => Some(&VMADC),
+                0b010010 if !unmasked && !immediate => Some(&VSBC),
+                0b010011 if !immediate => Some(&VMSBC),
+                0b010111 if !unmasked || vs2 == 0 => Some(&VMERGE),
+                0b011000 => Some(&VMSEQ),
+                0b011001 => Some(&VMSNE),
+                0b011010 if !immediate => Some(&VMSLTU),
+                0b011011 if !immediate => Some(&VMSLT),
+                0b011100 => Some(&VMSLEU),
+                0b011101 => Some(&VMSLE),
+                0b011110 if !vector => Some(&VMSGTU),
+                0b011111 if !vector => Some(&VMSGT),
+                0b100101 => Some(&VSLL),
+                0b100111 if immediate && unmasked && matches!(vs1, 0 | 1 | 3 | 7) => Some(&VMVNR_V),
+                0b101000 => Some(&VSRL),
+                0b101001 => Some(&VSRA),
+                0b101100 => Some(&VNSRL),
+                0b101101 => Some(&VNSRA),
+                0b110000 if vector => Some(&VWREDSUMU),
+                0b110001 if vector => Some(&VWREDSUM),
+                _ => None
+            }
+        },
+        // OPMVV and OPMVX
+        funct3 @ (0b010 | 0b110) => {
+            let vector = funct3 == 0b010;
+            match funct6 {
+                0b000000 if vector => Some(&VREDSUM),
+                0b000001 if vector => Some(&VREDAND),
+                0b000010 if vector => Some(&VREDOR),
+                0b000011 if vector => Some(&VREDXOR),
+                0b000100 if vector => Some(&VREDMINU),
+                0b000101 if vector => Some(&VREDMIN),
+                0b000110 if vector => Some(&VREDMAXU),
+                0b000111 if vector => Some(&VREDMAX),
+                0b010000 if vector && unmasked && vs1 == 0 => Some(&VMV_X_S),
+                0b010000 if !vector && unmasked && vs2 == 0 => Some(&VMV_S_X),
+                0b010000 if vector && vs1 == 0b10000 => Some(&VCPOP_M),
+                0b010000 if vector && vs1 == 0b10001 => Some(&VFIRST_M),
+                0b010010 if vector => match vs1 {
+                    0b00010 | 0b00100 | 0b00110 => Some(&VZEXT),
+                    0b00011 | 0b00101 | 0b00111 => Some(&VSEXT),
+                    _ => None
+                },
+                0b010100 if vector => match vs1 {
+                    0b00001 => Some(&VMSBF_M),
+                    0b00010 => Some(&VMSOF_M),
+                    0b00011 => Some(&VMSIF_M),
+                    0b10000 => Some(&VIOTA_M),
+                    0b10001 if vs2 == 0 => Some(&VID_V),
+                    _ => None
+                },
+                0b011000 if vector && unmasked => Some(&VMANDN_MM),
+                0b011001 if vector && unmasked => Some(&VMAND_MM),
+                0b011010 if vector && unmasked => Some(&VMOR_MM),
+                0b011011 if vector && unmasked => Some(&VMXOR_MM),
+                0b011100 if vector && unmasked => Some(&VMORN_MM),
+                0b011101 if vector && unmasked => Some(&VMNAND_MM),
+                0b011110 if vector && unmasked => Some(&VMNOR_MM),
+                0b011111 if vector && unmasked => Some(&VMXNOR_MM),
+                0b100000 => Some(&VDIVU),
+                0b100001 => Some(&VDIV),
+                0b100010 => Some(&VREMU),
+                0b100011 => Some(&VREM),
+                0b100100 => Some(&VMULHU),
+                0b100101 => Some(&VMUL),
+                0b100110 => Some(&VMULHSU),
+                0b100111 => Some(&VMULH),
+                0b101001 => Some(&VMADD),
+                0b101011 => Some(&VNMSUB),
+                0b101101 => Some(&VMACC),
+                0b101111 => Some(&VNMSAC),
+                0b110000 => Some(&VWADDU),
+                0b110001 => Some(&VWADD),
+                0b110010 => Some(&VWSUBU),
+                0b110011 => Some(&VWSUB),
+                0b110100 => Some(&VWADDU_W),
+                0b110101 => Some(&VWADD_W),
+                0b110110 => Some(&VWSUBU_W),
+                0b110111 => Some(&VWSUB_W),
+                0b111000 => Some(&VWMULU),
+                0b111010 => Some(&VWMULSU),
+                0b111011 => Some(&VWMUL),
+                0b111100 => Some(&VWMACCU),
+                0b111101 => Some(&VWMACC),
+                0b111110 if !vector => Some(&VWMACCUS),
+                0b111111 => Some(&VWMACCSU),
+                _ => None
+            }
+        },
+        // OPFVV and OPFVF
+        _ => decode_vector_fp(word)
+    }
+}
+
+// The loads and stores under LOAD-FP and STORE-FP with a vector width in funct3, mop picking how
+// they address memory and, for the unit-stride ones, lumop or sumop in the rs2 field picking
+// which kind they are.
+pub(crate) fn decode_vector_load(word: u32) -> Option<&'static Instruction> {
+    let nf = word >> 29;
+    let unmasked = (word >> 25) & 1 == 1;
+    let eew = vector_width(word);
+    // mew is always clear, it is reserved for element widths past 64 bits
+    let instructions: &'static [Instruction; 4] = match ((word >> 26) & 7, (word >> 20) & 0x1f) {
+        (0b000, 0b00000) => &[VLE8_V, VLE16_V, VLE32_V, VLE64_V],
+        (0b000, 0b01000) if unmasked && matches!(nf, 0 | 1 | 3 | 7) => &[VLRE8_V, VLRE16_V, VLRE32_V, VLRE64_V],
+        (0b000, 0b01011) if unmasked && nf == 0 && eew == 0 => return Some(&VLM_V),
+        (0b000, 0b10000) => &[VLE8FF_V, VLE16FF_V, VLE32FF_V, VLE64FF_V],
+        (0b001, _) => &[VLUXEI8_V, VLUXEI16_V, VLUXEI32_V, VLUXEI64_V],
+        (0b010, _) => &[VLSE8_V, VLSE16_V, VLSE32_V, VLSE64_V],
+        (0b011, _) => &[VLOXEI8_V, VLOXEI16_V, VLOXEI32_V, VLOXEI64_V],
+        _ => return None
+    };
+    Some(&instructions[eew])
+}
+
+pub(crate) fn decode_vector_store(word: u32) -> Option<&'static Instruction> {
+    let nf = word >> 29;
+    let unmasked = (word >> 25) & 1 == 1;
+    let eew = vector_width(word);
+    let instructions: &'static [Instruction; 4] = match ((word >> 26) & 7, (word >> 20) & 0x1f) {
+        (0b000, 0b00000) => &[VSE8_V, VSE16_V, VSE32_V, VSE64_V],
+        (0b000, 0b01000) if unmasked && matches!(nf, 0 | 1 | 3 | 7) && eew == 0 => return Some(&VSR_V),
+        (0b000, 0b01011) if unmasked && nf == 0 && eew == 0 => return Some(&VSM_V),
+        (0b001, _) => &[VSUXEI8_V, VSUXEI16_V, VSUXEI32_V, VSUXEI64_V],
+        (0b010, _) => &[VSSE8_V, VSSE16_V, VSSE32_V, VSSE64_V],
+        (0b011, _) => &[VSOXEI8_V, VSOXEI16_V, VSOXEI32_V, VSOXEI64_V],
+        _ => return None
+    };
+    Some(&instructions[eew])
+}
+
+// the element width of a load or store, as log2 of its bytes
+fn vector_width(word: u32) -> usize {
+    match (word >> 12) & 7 {
+        0b000 => 0,
+        0b101 => 1,
+        0b110 => 2,
+        _ => 3
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::{Cpu, TrapType};
+
+    fn words(cpu: &Cpu, vreg: usize, count: usize) -> Vec<u32> {
+        (0..count).map(|i| cpu.vector_element(vreg, i, 32) as u32).collect()
+    }
+
+    #[test]
+    fn integer_vectors() {
+        // vsetvli t0, a0, e32, m1, ta, ma; vle32.v v1, (a1); vle32.v v2, (a2); vadd.vv v3, v1, v2;
+        // vmslt.vv v0, v1, v2; vrsub.vi v3, v1, 10, v0.t; vmul.vx v4, v1, a3; vwaddu.vv v6, v1, v2;
+        // vse32.v v3, (a4); vmv.x.s a5, v4; vwaddu.vv v5, v1, v2; vnsrl.wi v8, v6, 4; vzext.vf2 v9, v1;
+        // vsetvl t1, zero, a6; vadd.vv v1, v1, v1
+        let program = [0x0d0572d7u32, 0x0205e087, 0x02066107, 0x021101d7, 0x6e110057, 0x0c1531d7, 0x9616e257, 0xc2112357,
+            0x020761a7, 0x424027d7, 0xc21122d7, 0xb2623457, 0x4a1324d7, 0x81007357, 0x021080d7];
+        let mut memory = vec![0u8; 0x400];
+        for (i, word) in program.iter().enumerate() {
+            memory[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        for (i, value) in [1, -2, 3, i32::MIN, 5, 6, -7, 1].iter().enumerate() {
+            let address = 0x100 * (1 + i / 4) + i % 4 * 4;
+            memory[address..address + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        let mut cpu = Cpu::new();
+        cpu.x[10..15].copy_from_slice(&[10, 0x100, 0x200, 3, 0x300]);
+        for _ in 0..10 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        // VLEN is 128 bits, so four elements fit
+        assert_eq!(4, cpu.x[5]);
+        assert_eq!(0b1011, cpu.vector_element(0, 0, 8));
+        assert_eq!(vec![9, 12, -4i32 as u32, 0x8000000a], words(&cpu, 3, 4));
+        assert_eq!(&[9, 0, 0, 0, 12, 0, 0, 0, 0xfc, 0xff, 0xff, 0xff, 0x0a, 0, 0, 0x80], &memory[0x300..0x310]);
+        assert_eq!(vec![3, -6i32 as u32, 9, 0x80000000], words(&cpu, 4, 4));
+        assert_eq!([6, 0x1_0000_0004, 0xffff_fffc, 0x8000_0001], [0, 1, 2, 3].map(|i| cpu.vector_element(6, i, 64)));
+        assert_eq!(3, cpu.x[15]);
+
+        // a double width group has to start on an even register
+        assert_eq!(TrapType::IllegalInstruction, cpu.tick(&mut memory).unwrap_err().trap_type);
+        cpu.update_pc(44);
+        cpu.tick(&mut memory).unwrap();
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(vec![0, 0x1000_0000, 0x0fff_ffff, 0x0800_0000], words(&cpu, 8, 4));
+        assert_eq!(vec![1, 0, 0xfffe, 0xffff], words(&cpu, 9, 4));
+
+        // e64, m8 gives sixteen elements, and a reserved LMUL makes vtype illegal along with everything using it
+        cpu.x[16] = 0x1b;
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(16, cpu.x[6]);
+        cpu.x[16] = 4;
+        cpu.update_pc(52);
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!([0, 1 << 63], [cpu.x[6] as u64, cpu.read_csr(0xc21)]);
+        assert_eq!(TrapType::IllegalInstruction, cpu.tick(&mut memory).unwrap_err().trap_type);
+
+        assert_eq!(32, Cpu::builder().vlen(256).build().read_csr(0xc22));
+    }
+
+    #[test]
+    fn strided_indexed_segment_and_mask_memory() {
+        // vsetvli t0, a0, e8, m1, ta, ma; vle8ff.v v1, (a1); vmseq.vi v0, v1, 0; vfirst.m a2, v0; vcpop.m a3, v0;
+        // vmsif.m v2, v0; viota.m v4, v0; vid.v v5, v0.t; vlseg2e8.v v8, (a4); vlse32.v v12, (a4), a5;
+        // vluxei8.v v16, (a4), v4; vs1r.v v8, (a6); vsm.v v2, (a7); vmxor.mm v3, v0, v2
+        let program = [0x0c0572d7u32, 0x03058087, 0x62103057, 0x4208a657, 0x420826d7, 0x5201a157, 0x52082257, 0x5008a2d7,
+            0x22070407, 0x0af76607, 0x06470807, 0x02880427, 0x02b88127, 0x6e0121d7];
+        // the string at 0x100 runs into the end of memory
+        let mut memory = vec![0u8; 0x108];
+        for (i, word) in program.iter().enumerate() {
+            memory[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        for i in 0..0x40 {
+            memory[0x80 + i] = i as u8;
+        }
+        memory[0x100..0x108].copy_from_slice(b"ab\0c\0efg");
+        let mut cpu = Cpu::new();
+        cpu.x[10..18].copy_from_slice(&[16, 0x100, 0, 0, 0x80, 8, 0xc0, 0xe0]);
+        for _ in 0..program.len() {
+            cpu.tick(&mut memory).unwrap();
+        }
+
+        // the fault-only-first load stopped at the end of memory rather than trapping
+        assert_eq!([16, 8], [cpu.x[5] as u64, cpu.read_csr(0xc20)]);
+        assert_eq!([2, 2], [cpu.x[12], cpu.x[13]]);
+        assert_eq!(0b111, cpu.vector_element(2, 0, 8));
+        assert_eq!(vec![0, 0, 0, 1, 1, 2, 2, 2], (0..8).map(|i| cpu.vector_element(4, i, 8)).collect::<Vec<_>>());
+        assert_eq!(vec![0, 0, 2, 0, 4, 0, 0, 0], (0..8).map(|i| cpu.vector_element(5, i, 8)).collect::<Vec<_>>());
+        assert_eq!([0x0e0c0a0806040200, 0x0f0d0b0907050301], [cpu.vector_element(8, 0, 64), cpu.vector_element(9, 0, 64)]);
+        assert_eq!([0x03020100, 0x0b0a0908], [cpu.vector_element(12, 0, 32), cpu.vector_element(12, 1, 32)]);
+        assert_eq!(vec![0, 0, 0, 1, 1, 2, 2, 2], (0..8).map(|i| cpu.vector_element(16, i, 8)).collect::<Vec<_>>());
+        assert_eq!(&[0, 2, 4, 6, 8, 10, 12, 14], &memory[0xc0..0xc8]);
+        assert_eq!(0b111, memory[0xe0]);
+        assert_eq!(0b10011, cpu.vector_element(3, 0, 8));
+
+        // a fault on the first element still traps
+        cpu.x[11] = 0x200;
+        cpu.update_pc(4);
+        assert_eq!(TrapType::LoadAccessFault, cpu.tick(&mut memory).unwrap_err().trap_type);
+    }
+}
@@ -0,0 +1,794 @@
+use crate::cpu::{Cpu, Trap, CSR_FCSR_ADDRESS};
+use crate::cpu::fflags::{FFLAG_DZ, FFLAG_NV, FFLAG_NX};
+use crate::cpu::instruction;
+use crate::cpu::instruction::Instruction;
+use crate::cpu::rv64uv::{carrying, elements, mask_result, operand, reduce, NARROWING, SINGLE, WIDE, WIDENING};
+use crate::cpu::vector::{canonical_nan, illegal, sign_extend, truncate};
+
+// The floating point instructions of V. Elements are singles or doubles, which need F or D to be
+// enabled too. As with the scalar instructions the arithmetic is the host's, in round to nearest
+// even, with singles worked on as doubles and rounded once at the end, which gives the same
+// result for everything but the multiply-adds, so those get a single precision fma of their own.
+// frm only matters to conversions to integers.
+
+fn float(bits: u64, width: usize) -> f64 {
+    match width {
+        32 => f32::from_bits(bits as u32) as f64,
+        _ => f64::from_bits(bits)
+    }
+}
+
+// rounds value to a float width bits wide, with any NaN coming out canonical
+fn bits(value: f64, width: usize) -> u64 {
+    match (value.is_nan(), width) {
+        (true, _) => canonical_nan(width),
+        (false, 32) => (value as f32).to_bits() as u64,
+        (false, _) => value.to_bits()
+    }
+}
+
+// looking at the bits so that a signaling NaN does not raise anything on the host
+fn is_nan(bits: u64, width: usize) -> bool {
+    let (exponent, fraction) = match width {
+        32 => (0xff << 23, 0x7fffff),
+        _ => (0x7ff << 52, (1 << 52) - 1)
+    };
+    bits & exponent == exponent && bits & fraction != 0
+}
+
+fn is_signaling(bits: u64, width: usize) -> bool {
+    let quiet = match width {
+        32 => 1 << 22,
+        _ => 1 << 51
+    };
+    is_nan(bits, width) && bits & quiet == 0
+}
+
+// the precisions of the elements have to be enabled, F for singles and D for doubles
+fn check_width(cpu: &Cpu, width: usize, word: u32) -> Result<(), Trap> {
+    match width {
+        32 if cpu.extensions.contains('F') => Ok(()),
+        64 if cpu.extensions.contains('D') => Ok(()),
+        _ => Err(illegal(word))
+    }
+}
+
+fn check_widths(cpu: &Cpu, word: u32, widths: [i32; 3]) -> Result<(), Trap> {
+    let (vtype, _, _) = cpu.vector_config(word)?;
+    for width in widths {
+        check_width(cpu, vtype.sew << width, word)?;
+    }
+    Ok(())
+}
+
+fn arithmetic(cpu: &mut Cpu, word: u32, widths: [i32; 3], op: impl Fn(f64, f64) -> f64) -> Result<(), Trap> {
+    check_widths(cpu, word, widths)?;
+    elements(cpu, word, widths, |a, b, _, sew| {
+        let [vd, vs2, operand] = widths.map(|width| sew << width);
+        bits(op(float(a, vs2), float(b, operand)), vd)
+    })
+}
+
+// Division and square root work out invalid and divide by zero for themselves rather than leave
+// them to the host, as the optimizer may answer whether a square root is NaN without taking it.
+fn raising(cpu: &mut Cpu, word: u32, op: impl Fn(u64, u64, usize, &mut u64) -> f64) -> Result<(), Trap> {
+    check_widths(cpu, word, SINGLE)?;
+    let mut flags = 0;
+    elements(cpu, word, SINGLE, |a, b, _, sew| bits(op(a, b, sew, &mut flags), sew))?;
+    cpu.raise_fflags(flags);
+    Ok(())
+}
+
+fn divide(dividend: u64, divisor: u64, width: usize, flags: &mut u64) -> f64 {
+    let (x, y) = (float(dividend, width), float(divisor, width));
+    if is_signaling(dividend, width) || is_signaling(divisor, width) || (x == 0.0 && y == 0.0) || (x.is_infinite() && y.is_infinite()) {
+        *flags |= FFLAG_NV;
+    } else if y == 0.0 && x.is_finite() {
+        *flags |= FFLAG_DZ;
+    }
+    x / y
+}
+
+fn square_root(value: u64, width: usize, flags: &mut u64) -> f64 {
+    let x = float(value, width);
+    if is_signaling(value, width) || x < 0.0 {
+        *flags |= FFLAG_NV;
+    }
+    x.sqrt()
+}
+
+// The multiply-adds multiply vs1 or f[rs1] by vs2 and add vd, or by vd and add vs2 for the ones
+// that overwrite the multiplicand.
+fn fused(cpu: &mut Cpu, word: u32, widths: [i32; 3], negate_product: bool, negate_addend: bool, overwrite: bool) -> Result<(), Trap> {
+    check_widths(cpu, word, widths)?;
+    elements(cpu, word, widths, |a, b, d, sew| {
+        let [vd, vs2, operand] = widths.map(|width| sew << width);
+        let (multiplicand, addend) = match overwrite {
+            true => (float(d, vd), float(a, vs2)),
+            false => (float(a, vs2), float(d, vd))
+        };
+        let product = if negate_product { -float(b, operand) } else { float(b, operand) };
+        let addend = if negate_addend { -addend } else { addend };
+        match vd {
+            32 => bits((product as f32).mul_add(multiplicand as f32, addend as f32) as f64, 32),
+            _ => bits(product.mul_add(multiplicand, addend), 64)
+        }
+    })
+}
+
+// fmin and fmax as IEEE 754-2019 has them, a quiet NaN only loses to a number and -0 is less than +0
+fn pick(a: u64, b: u64, width: usize, max: bool, flags: &mut u64) -> u64 {
+    if is_signaling(a, width) || is_signaling(b, width) {
+        *flags |= FFLAG_NV;
+    }
+    match (is_nan(a, width), is_nan(b, width)) {
+        (true, true) => canonical_nan(width),
+        (true, false) => b,
+        (false, true) => a,
+        _ => {
+            let (x, y) = (float(a, width), float(b, width));
+            let first = match x == y {
+                true => (a >> (width - 1) == 1) != max,
+                false => (x < y) != max
+            };
+            if first { a } else { b }
+        }
+    }
+}
+
+fn min_max(cpu: &mut Cpu, word: u32, max: bool) -> Result<(), Trap> {
+    check_widths(cpu, word, SINGLE)?;
+    let mut flags = 0;
+    elements(cpu, word, SINGLE, |a, b, _, sew| pick(a, b, sew, max, &mut flags))?;
+    cpu.raise_fflags(flags);
+    Ok(())
+}
+
+// the ordering compares raise invalid for any NaN, equality only for a signaling one
+fn compare(cpu: &mut Cpu, word: u32, ordering: bool, op: impl Fn(f64, f64) -> bool) -> Result<(), Trap> {
+    check_widths(cpu, word, SINGLE)?;
+    let mut flags = 0;
+    mask_result(cpu, word, false, |a, b, _, sew| {
+        let nan = match ordering {
+            true => is_nan(a, sew) || is_nan(b, sew),
+            false => is_signaling(a, sew) || is_signaling(b, sew)
+        };
+        if nan {
+            flags |= FFLAG_NV;
+        }
+        op(float(a, sew), float(b, sew))
+    })?;
+    cpu.raise_fflags(flags);
+    Ok(())
+}
+
+fn sign_injection(cpu: &mut Cpu, word: u32, op: impl Fn(u64, u64) -> u64) -> Result<(), Trap> {
+    check_widths(cpu, word, SINGLE)?;
+    elements(cpu, word, SINGLE, |a, b, _, sew| {
+        let sign = 1 << (sew - 1);
+        (a & !sign) | (op(a, b) & sign)
+    })
+}
+
+fn sum(cpu: &mut Cpu, word: u32, widening: bool) -> Result<(), Trap> {
+    check_widths(cpu, word, if widening { WIDENING } else { SINGLE })?;
+    reduce(cpu, word, widening, |sum, e, sew| {
+        let width = sew << widening as usize;
+        bits(float(sum, width) + float(e, sew), width)
+    })
+}
+
+fn reduce_min_max(cpu: &mut Cpu, word: u32, max: bool) -> Result<(), Trap> {
+    check_widths(cpu, word, SINGLE)?;
+    let mut flags = 0;
+    reduce(cpu, word, false, |most, e, sew| pick(most, e, sew, max, &mut flags))?;
+    cpu.raise_fflags(flags);
+    Ok(())
+}
+
+fn classify(bits: u64, width: usize) -> u64 {
+    let fraction_bits = if width == 32 { 23 } else { 52 };
+    let exponent = (bits >> fraction_bits) & ((1 << (width - 1 - fraction_bits)) - 1);
+    let fraction = bits & ((1 << fraction_bits) - 1);
+    let negative = bits >> (width - 1) == 1;
+    let infinite = exponent == (1 << (width - 1 - fraction_bits)) - 1;
+    let class = match (exponent, fraction) {
+        _ if infinite && fraction != 0 => if is_signaling(bits, width) { 8 } else { 9 },
+        _ if infinite => if negative { 0 } else { 7 },
+        (0, 0) => if negative { 3 } else { 4 },
+        (0, _) => if negative { 2 } else { 5 },
+        _ => if negative { 1 } else { 6 }
+    };
+    1 << class
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Number {
+    Unsigned,
+    Signed,
+    Float
+}
+
+// what the conversions take rounding to mean when it is not a rounding mode
+const ROUND_TO_ODD: u64 = 8;
+
+// Saturates when value is out of range, or a NaN, raising invalid instead of inexact.
+fn to_integer(value: f64, rounding: u64, signed: bool, width: usize, flags: &mut u64) -> u64 {
+    let rounded = match rounding {
+        1 => value.trunc(),
+        2 => value.floor(),
+        3 => value.ceil(),
+        4 => value.round(),
+        _ => value.round_ties_even()
+    };
+    let limit = 2f64.powi(width as i32 - signed as i32);
+    let low = if signed { -limit } else { 0.0 };
+    if value.is_nan() || rounded >= limit || rounded < low {
+        *flags |= FFLAG_NV;
+        return match (value < 0.0, signed) {
+            (true, true) => 1 << (width - 1),
+            (true, false) => 0,
+            (false, true) => (1 << (width - 1)) - 1,
+            (false, false) => truncate(u64::MAX, width)
+        };
+    }
+    if rounded != value {
+        *flags |= FFLAG_NX;
+    }
+    match signed {
+        true => truncate(rounded as i64 as u64, width),
+        false => rounded as u64
+    }
+}
+
+fn from_integer(value: u64, signed: bool, from: usize, width: usize) -> u64 {
+    match (width, signed) {
+        (32, true) => (sign_extend(value, from) as f32).to_bits() as u64,
+        (32, false) => (value as f32).to_bits() as u64,
+        (_, true) => (sign_extend(value, from) as f64).to_bits(),
+        (_, false) => (value as f64).to_bits()
+    }
+}
+
+// Narrows to the nearest single toward zero, setting the lowest bit of it when that was not exact
+// so that rounding it again later comes out as though the double had been rounded directly.
+fn round_to_odd(value: f64) -> u64 {
+    let nearest = value as f32;
+    if value.is_nan() || nearest as f64 == value {
+        return bits(value, 32);
+    }
+    let mut toward_zero = nearest.to_bits();
+    if (nearest as f64).abs() > value.abs() {
+        toward_zero -= 1;
+    }
+    (toward_zero | 1) as u64
+}
+
+// The conversions, between integers and floats or floats of two widths, with rounding None for
+// the mode in frm. Any flags the host raises converting to an integer are replaced with the ones
+// RISC-V has for it.
+fn convert(cpu: &mut Cpu, word: u32, widths: [i32; 3], from: Number, to: Number, rounding: Option<u64>) -> Result<(), Trap> {
+    let (vtype, _, _) = cpu.vector_config(word)?;
+    let [output, input, _] = widths.map(|width| vtype.sew << width);
+    for (number, width) in [(from, input), (to, output)] {
+        match number {
+            Number::Float => check_width(cpu, width, word)?,
+            _ if width > 64 => return Err(illegal(word)),
+            _ => {}
+        }
+    }
+    let rounding = match rounding {
+        Some(rounding) => rounding,
+        None => match (cpu.csr[CSR_FCSR_ADDRESS as usize] >> 5) & 7 {
+            frm @ 0..=4 => frm,
+            _ => return Err(illegal(word))
+        }
+    };
+
+    let before = cpu.read_fflags() & 0x1f;
+    let mut flags = 0;
+    elements(cpu, word, widths, |a, _, _, sew| {
+        let [output, input, _] = widths.map(|width| sew << width);
+        match (from, to) {
+            (Number::Float, Number::Float) if rounding == ROUND_TO_ODD => round_to_odd(float(a, input)),
+            (Number::Float, Number::Float) => bits(float(a, input), output),
+            (Number::Float, integer) => to_integer(float(a, input), rounding, integer == Number::Signed, output, &mut flags),
+            (integer, _) => from_integer(a, integer == Number::Signed, input, output)
+        }
+    })?;
+    match to {
+        Number::Float => cpu.raise_fflags(flags),
+        _ => cpu.write_fflags(before | flags)
+    }
+    Ok(())
+}
+
+pub const VFADD: Instruction = Instruction {
+    name: "VFADD",
+    operation: |cpu, _memory, word, _address| arithmetic(cpu, word, SINGLE, |a, b| a + b)
+};
+
+pub const VFSUB: Instruction = Instruction {
+    name: "VFSUB",
+    operation: |cpu, _memory, word, _address| arithmetic(cpu, word, SINGLE, |a, b| a - b)
+};
+
+pub const VFRSUB: Instruction = Instruction {
+    name: "VFRSUB",
+    operation: |cpu, _memory, word, _address| arithmetic(cpu, word, SINGLE, |a, b| b - a)
+};
+
+pub const VFMUL: Instruction = Instruction {
+    name: "VFMUL",
+    operation: |cpu, _memory, word, _address| arithmetic(cpu, word, SINGLE, |a, b| a * b)
+};
+
+pub const VFDIV: Instruction = Instruction {
+    name: "VFDIV",
+    operation: |cpu, _memory, word, _address| raising(cpu, word, divide)
+};
+
+pub const VFRDIV: Instruction = Instruction {
+    name: "VFRDIV",
+    operation: |cpu, _memory, word, _address| raising(cpu, word, |a, b, sew, flags| divide(b, a, sew, flags))
+};
+
+pub const VFMIN: Instruction = Instruction {
+    name: "VFMIN",
+    operation: |cpu, _memory, word, _address| min_max(cpu, word, false)
+};
+
+pub const VFMAX: Instruction = Instruction {
+    name: "VFMAX",
+    operation: |cpu, _memory, word, _address| min_max(cpu, word, true)
+};
+
+pub const VFSGNJ: Instruction = Instruction {
+    name: "VFSGNJ",
+    operation: |cpu, _memory, word, _address| sign_injection(cpu, word, |_, b| b)
+};
+
+pub const VFSGNJN: Instruction = Instruction {
+    name: "VFSGNJN",
+    operation: |cpu, _memory, word, _address| sign_injection(cpu, word, |_, b| !b)
+};
+
+pub const VFSGNJX: Instruction = Instruction {
+    name: "VFSGNJX",
+    operation: |cpu, _memory, word, _address| sign_injection(cpu, word, |a, b| a ^ b)
+};
+
+// vfmv.v.f when vm is set
+pub const VFMERGE: Instruction = Instruction {
+    name: "VFMERGE",
+    operation: |cpu, _memory, word, _address| {
+        check_widths(cpu, word, SINGLE)?;
+        carrying(cpu, word, |a, b, carry, _| if carry || (word >> 25) & 1 == 1 { b } else { a })
+    }
+};
+
+pub const VMFEQ: Instruction = Instruction {
+    name: "VMFEQ",
+    operation: |cpu, _memory, word, _address| compare(cpu, word, false, |a, b| a == b)
+};
+
+pub const VMFNE: Instruction = Instruction {
+    name: "VMFNE",
+    operation: |cpu, _memory, word, _address| compare(cpu, word, false, |a, b| a != b)
+};
+
+pub const VMFLT: Instruction = Instruction {
+    name: "VMFLT",
+    operation: |cpu, _memory, word, _address| compare(cpu, word, true, |a, b| a < b)
+};
+
+pub const VMFLE: Instruction = Instruction {
+    name: "VMFLE",
+    operation: |cpu, _memory, word, _address| compare(cpu, word, true, |a, b| a <= b)
+};
+
+pub const VMFGT: Instruction = Instruction {
+    name: "VMFGT",
+    operation: |cpu, _memory, word, _address| compare(cpu, word, true, |a, b| a > b)
+};
+
+pub const VMFGE: Instruction = Instruction {
+    name: "VMFGE",
+    operation: |cpu, _memory, word, _address| compare(cpu, word, true, |a, b| a >= b)
+};
+
+pub const VFMACC: Instruction = Instruction {
+    name: "VFMACC",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, SINGLE, false, false, false)
+};
+
+pub const VFNMACC: Instruction = Instruction {
+    name: "VFNMACC",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, SINGLE, true, true, false)
+};
+
+pub const VFMSAC: Instruction = Instruction {
+    name: "VFMSAC",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, SINGLE, false, true, false)
+};
+
+pub const VFNMSAC: Instruction = Instruction {
+    name: "VFNMSAC",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, SINGLE, true, false, false)
+};
+
+pub const VFMADD: Instruction = Instruction {
+    name: "VFMADD",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, SINGLE, false, false, true)
+};
+
+pub const VFNMADD: Instruction = Instruction {
+    name: "VFNMADD",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, SINGLE, true, true, true)
+};
+
+pub const VFMSUB: Instruction = Instruction {
+    name: "VFMSUB",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, SINGLE, false, true, true)
+};
+
+pub const VFNMSUB: Instruction = Instruction {
+    name: "VFNMSUB",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, SINGLE, true, false, true)
+};
+
+pub const VFWADD: Instruction = Instruction {
+    name: "VFWADD",
+    operation: |cpu, _memory, word, _address| arithmetic(cpu, word, WIDENING, |a, b| a + b)
+};
+
+pub const VFWSUB: Instruction = Instruction {
+    name: "VFWSUB",
+    operation: |cpu, _memory, word, _address| arithmetic(cpu, word, WIDENING, |a, b| a - b)
+};
+
+pub const VFWADD_W: Instruction = Instruction {
+    name: "VFWADD.W",
+    operation: |cpu, _memory, word, _address| arithmetic(cpu, word, WIDE, |a, b| a + b)
+};
+
+pub const VFWSUB_W: Instruction = Instruction {
+    name: "VFWSUB.W",
+    operation: |cpu, _memory, word, _address| arithmetic(cpu, word, WIDE, |a, b| a - b)
+};
+
+pub const VFWMUL: Instruction = Instruction {
+    name: "VFWMUL",
+    operation: |cpu, _memory, word, _address| arithmetic(cpu, word, WIDENING, |a, b| a * b)
+};
+
+pub const VFWMACC: Instruction = Instruction {
+    name: "VFWMACC",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, WIDENING, false, false, false)
+};
+
+pub const VFWNMACC: Instruction = Instruction {
+    name: "VFWNMACC",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, WIDENING, true, true, false)
+};
+
+pub const VFWMSAC: Instruction = Instruction {
+    name: "VFWMSAC",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, WIDENING, false, true, false)
+};
+
+pub const VFWNMSAC: Instruction = Instruction {
+    name: "VFWNMSAC",
+    operation: |cpu, _memory, word, _address| fused(cpu, word, WIDENING, true, false, false)
+};
+
+// the unordered sums are added up in order as well
+pub const VFREDUSUM: Instruction = Instruction {
+    name: "VFREDUSUM.VS",
+    operation: |cpu, _memory, word, _address| sum(cpu, word, false)
+};
+
+pub const VFREDOSUM: Instruction = Instruction {
+    name: "VFREDOSUM.VS",
+    operation: |cpu, _memory, word, _address| sum(cpu, word, false)
+};
+
+pub const VFREDMIN: Instruction = Instruction {
+    name: "VFREDMIN.VS",
+    operation: |cpu, _memory, word, _address| reduce_min_max(cpu, word, false)
+};
+
+pub const VFREDMAX: Instruction = Instruction {
+    name: "VFREDMAX.VS",
+    operation: |cpu, _memory, word, _address| reduce_min_max(cpu, word, true)
+};
+
+pub const VFWREDUSUM: Instruction = Instruction {
+    name: "VFWREDUSUM.VS",
+    operation: |cpu, _memory, word, _address| sum(cpu, word, true)
+};
+
+pub const VFWREDOSUM: Instruction = Instruction {
+    name: "VFWREDOSUM.VS",
+    operation: |cpu, _memory, word, _address| sum(cpu, word, true)
+};
+
+pub const VFMV_F_S: Instruction = Instruction {
+    name: "VFMV.F.S",
+    operation: |cpu, _memory, word, _address| {
+        check_widths(cpu, word, SINGLE)?;
+        let (vtype, _, _) = cpu.vector_config(word)?;
+        let f = instruction::parse_format_r(word);
+        let value = cpu.vector_element(f.rs2, 0, vtype.sew);
+        cpu.f[f.rd] = f64::from_bits(match vtype.sew {
+            32 => 0xffffffff00000000 | value,
+            _ => value
+        });
+        cpu.set_vstart(0);
+        Ok(())
+    }
+};
+
+pub const VFMV_S_F: Instruction = Instruction {
+    name: "VFMV.S.F",
+    operation: |cpu, _memory, word, _address| {
+        check_widths(cpu, word, SINGLE)?;
+        let (vtype, vl, vstart) = cpu.vector_config(word)?;
+        let f = instruction::parse_format_r(word);
+        if vstart < vl {
+            let value = operand(cpu, word, vtype.sew).get(cpu, 0, vtype.sew);
+            cpu.set_vector_element(f.rd, 0, vtype.sew, value);
+        }
+        cpu.set_vstart(0);
+        Ok(())
+    }
+};
+
+pub const VFSQRT_V: Instruction = Instruction {
+    name: "VFSQRT.V",
+    operation: |cpu, _memory, word, _address| raising(cpu, word, |a, _, sew, flags| square_root(a, sew, flags))
+};
+
+pub const VFCLASS_V: Instruction = Instruction {
+    name: "VFCLASS.V",
+    operation: |cpu, _memory, word, _address| {
+        check_widths(cpu, word, SINGLE)?;
+        elements(cpu, word, SINGLE, |a, _, _, sew| classify(a, sew))
+    }
+};
+
+pub const VFCVT_XU_F_V: Instruction = Instruction {
+    name: "VFCVT.XU.F.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, SINGLE, Number::Float, Number::Unsigned, None)
+};
+
+pub const VFCVT_X_F_V: Instruction = Instruction {
+    name: "VFCVT.X.F.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, SINGLE, Number::Float, Number::Signed, None)
+};
+
+pub const VFCVT_F_XU_V: Instruction = Instruction {
+    name: "VFCVT.F.XU.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, SINGLE, Number::Unsigned, Number::Float, None)
+};
+
+pub const VFCVT_F_X_V: Instruction = Instruction {
+    name: "VFCVT.F.X.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, SINGLE, Number::Signed, Number::Float, None)
+};
+
+pub const VFCVT_RTZ_XU_F_V: Instruction = Instruction {
+    name: "VFCVT.RTZ.XU.F.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, SINGLE, Number::Float, Number::Unsigned, Some(1))
+};
+
+pub const VFCVT_RTZ_X_F_V: Instruction = Instruction {
+    name: "VFCVT.RTZ.X.F.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, SINGLE, Number::Float, Number::Signed, Some(1))
+};
+
+pub const VFWCVT_XU_F_V: Instruction = Instruction {
+    name: "VFWCVT.XU.F.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, WIDENING, Number::Float, Number::Unsigned, None)
+};
+
+pub const VFWCVT_X_F_V: Instruction = Instruction {
+    name: "VFWCVT.X.F.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, WIDENING, Number::Float, Number::Signed, None)
+};
+
+pub const VFWCVT_F_XU_V: Instruction = Instruction {
+    name: "VFWCVT.F.XU.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, WIDENING, Number::Unsigned, Number::Float, None)
+};
+
+pub const VFWCVT_F_X_V: Instruction = Instruction {
+    name: "VFWCVT.F.X.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, WIDENING, Number::Signed, Number::Float, None)
+};
+
+pub const VFWCVT_F_F_V: Instruction = Instruction {
+    name: "VFWCVT.F.F.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, WIDENING, Number::Float, Number::Float, None)
+};
+
+pub const VFWCVT_RTZ_XU_F_V: Instruction = Instruction {
+    name: "VFWCVT.RTZ.XU.F.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, WIDENING, Number::Float, Number::Unsigned, Some(1))
+};
+
+pub const VFWCVT_RTZ_X_F_V: Instruction = Instruction {
+    name: "VFWCVT.RTZ.X.F.V",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, WIDENING, Number::Float, Number::Signed, Some(1))
+};
+
+pub const VFNCVT_XU_F_W: Instruction = Instruction {
+    name: "VFNCVT.XU.F.W",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, NARROWING, Number::Float, Number::Unsigned, None)
+};
+
+pub const VFNCVT_X_F_W: Instruction = Instruction {
+    name: "VFNCVT.X.F.W",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, NARROWING, Number::Float, Number::Signed, None)
+};
+
+pub const VFNCVT_F_XU_W: Instruction = Instruction {
+    name: "VFNCVT.F.XU.W",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, NARROWING, Number::Unsigned, Number::Float, None)
+};
+
+pub const VFNCVT_F_X_W: Instruction = Instruction {
+    name: "VFNCVT.F.X.W",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, NARROWING, Number::Signed, Number::Float, None)
+};
+
+pub const VFNCVT_F_F_W: Instruction = Instruction {
+    name: "VFNCVT.F.F.W",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, NARROWING, Number::Float, Number::Float, None)
+};
+
+pub const VFNCVT_ROD_F_F_W: Instruction = Instruction {
+    name: "VFNCVT.ROD.F.F.W",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, NARROWING, Number::Float, Number::Float, Some(ROUND_TO_ODD))
+};
+
+pub const VFNCVT_RTZ_XU_F_W: Instruction = Instruction {
+    name: "VFNCVT.RTZ.XU.F.W",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, NARROWING, Number::Float, Number::Unsigned, Some(1))
+};
+
+pub const VFNCVT_RTZ_X_F_W: Instruction = Instruction {
+    name: "VFNCVT.RTZ.X.F.W",
+    operation: |cpu, _memory, word, _address| convert(cpu, word, NARROWING, Number::Float, Number::Signed, Some(1))
+};
+
+// OPFVV and OPFVF, told apart by funct3
+pub(crate) fn decode_vector_fp(word: u32) -> Option<&'static Instruction> {
+    let vector = (word >> 12) & 7 == 0b001;
+    let unmasked = (word >> 25) & 1 == 1;
+    let vs1 = (word >> 15) & 0x1f;
+    let vs2 = (word >> 20) & 0x1f;
+    match word >> 26 {
+        0b000000 => Some(&VFADD),
+        0b000001 if vector => Some(&VFREDUSUM),
+        0b000010 => Some(&VFSUB),
+        0b000011 if vector => Some(&VFREDOSUM),
+        0b000100 => Some(&VFMIN),
+        0b000101 if vector => Some(&VFREDMIN),
+        0b000110 => Some(&VFMAX),
+        0b000111 if vector => Some(&VFREDMAX),
+        0b001000 => Some(&VFSGNJ),
+        0b001001 => Some(&VFSGNJN),
+        0b001010 => Some(&VFSGNJX),
+        0b010000 if vector && unmasked && vs1 == 0 => Some(&VFMV_F_S),
+        0b010000 if !vector && unmasked && vs2 == 0 => Some(&VFMV_S_F),
+        0b010010 if vector => match vs1 {
+            0b00000 => Some(&VFCVT_XU_F_V),
+            0b00001 => Some(&VFCVT_X_F_V),
+            0b00010 => Some(&VFCVT_F_XU_V),
+            0b00011 => Some(&VFCVT_F_X_V),
+            0b00110 => Some(&VFCVT_RTZ_XU_F_V),
+            0b00111 => Some(&VFCVT_RTZ_X_F_V),
+            0b01000 => Some(&VFWCVT_XU_F_V),
+            0b01001 => Some(&VFWCVT_X_F_V),
+            0b01010 => Some(&VFWCVT_F_XU_V),
+            0b01011 => Some(&VFWCVT_F_X_V),
+            0b01100 => Some(&VFWCVT_F_F_V),
+            0b01110 => Some(&VFWCVT_RTZ_XU_F_V),
+            0b01111 => Some(&VFWCVT_RTZ_X_F_V),
+            0b10000 => Some(&VFNCVT_XU_F_W),
+            0b10001 => Some(&VFNCVT_X_F_W),
+            0b10010 => Some(&VFNCVT_F_XU_W),
+            0b10011 => Some(&VFNCVT_F_X_W),
+            0b10100 => Some(&VFNCVT_F_F_W),
+            0b10101 => Some(&VFNCVT_ROD_F_F_W),
+            0b10110 => Some(&VFNCVT_RTZ_XU_F_W),
+            0b10111 => Some(&VFNCVT_RTZ_X_F_W),
+            _ => None
+        },
+        0b010011 if vector => match vs1 {
+            0b00000 => Some(&VFSQRT_V),
+            0b10000 => Some(&VFCLASS_V),
+            _ => None
+        },
+        0b010111 if !vector && (!unmasked || vs2 == 0) => Some(&VFMERGE),
+        0b011000 => Some(&VMFEQ),
+        0b011001 => Some(&VMFLE),
+        0b011011 => Some(&VMFLT),
+        0b011100 => Some(&VMFNE),
+        0b011101 if !vector => Some(&VMFGT),
+        0b011111 if !vector => Some(&VMFGE),
+        0b100000 => Some(&VFDIV),
+        0b100001 if !vector => Some(&VFRDIV),
+        0b100100 => Some(&VFMUL),
+        0b100111 if !vector => Some(&VFRSUB),
+        0b101000 => Some(&VFMADD),
+        0b101001 => Some(&VFNMADD),
+        0b101010 => Some(&VFMSUB),
+        0b101011 => Some(&VFNMSUB),
+        0b101100 => Some(&VFMACC),
+        0b101101 => Some(&VFNMACC),
+        0b101110 => Some(&VFMSAC),
+        0b101111 => Some(&VFNMSAC),
+        0b110000 => Some(&VFWADD),
+        0b110001 if vector => Some(&VFWREDUSUM),
+        0b110010 => Some(&VFWSUB),
+        0b110011 if vector => Some(&VFWREDOSUM),
+        0b110100 => Some(&VFWADD_W),
+        0b110110 => Some(&VFWSUB_W),
+        0b111000 => Some(&VFWMUL),
+        0b111100 => Some(&VFWMACC),
+        0b111101 => Some(&VFWNMACC),
+        0b111110 => Some(&VFWMSAC),
+        0b111111 => Some(&VFWNMSAC),
+        _ => None
+    }
+}
+
+#[cfg(all(test, feature = "d"))]
+mod test {
+    use crate::cpu::{Cpu, TrapType};
+
+    fn singles(cpu: &Cpu, vreg: usize) -> Vec<u32> {
+        (0..4).map(|i| cpu.vector_element(vreg, i, 32) as u32).collect()
+    }
+
+    #[test]
+    fn floating_point_vectors() {
+        // vsetivli zero, 4, e32, m1, ta, ma; vfadd.vv v3, v1, v2; vfmacc.vf v4, fa0, v1; vfwadd.vv v6, v1, v2;
+        // vfredmax.vs v8, v1, v2; vfmv.f.s fa1, v3; vmflt.vf v12, v1, fa0; vfncvt.rtz.x.f.w v10, v6;
+        // vfclass.v v18, v1; vfmin.vv v19, v1, v2; vsetivli zero, 2, e64, m1, ta, ma; vfsqrt.v v15, v6;
+        // vsetivli zero, 4, e8, m1, ta, ma; vfadd.vv v3, v1, v2
+        let program = [0xcd027057u32, 0x021111d7, 0xb2155257, 0xc2111357, 0x1e111457, 0x423015d7, 0x6e155657, 0x4a6b9557,
+            0x4e181957, 0x121119d7, 0xcd817057, 0x4e6017d7, 0xcc027057, 0x021111d7];
+        let mut memory = vec![0u8; 0x100];
+        for (i, word) in program.iter().enumerate() {
+            memory[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        let mut cpu = Cpu::new();
+        // the last element of v1 is a signaling NaN
+        for (i, (a, b)) in [(1.5f32.to_bits(), 0.25f32), ((-2.0f32).to_bits(), 4.0), (3.0f32.to_bits(), -0.0), (0x7f800001, 1.0)].into_iter().enumerate() {
+            cpu.set_vector_element(1, i, 32, a as u64);
+            cpu.set_vector_element(2, i, 32, b.to_bits() as u64);
+        }
+        cpu.f[10] = f64::from_bits(0xffffffff00000000 | 2.0f32.to_bits() as u64);
+        for _ in 0..program.len() - 1 {
+            cpu.tick(&mut memory).unwrap();
+        }
+
+        assert_eq!(vec![1.75f32.to_bits(), 2.0f32.to_bits(), 3.0f32.to_bits(), 0x7fc00000], singles(&cpu, 3));
+        assert_eq!(vec![3.0f32.to_bits(), (-4.0f32).to_bits(), 6.0f32.to_bits(), 0x7fc00000], singles(&cpu, 4));
+        assert_eq!(3.0f32.to_bits() as u64, cpu.vector_element(8, 0, 32));
+        assert_eq!(0xffffffff00000000 | 1.75f32.to_bits() as u64, cpu.f[11].to_bits());
+        assert_eq!(0b0011, cpu.vector_element(12, 0, 8) & 0xf);
+        assert_eq!(vec![1, 2, 3, i32::MAX as u32], singles(&cpu, 10));
+        assert_eq!(vec![1 << 6, 1 << 1, 1 << 6, 1 << 8], singles(&cpu, 18));
+        assert_eq!(vec![0.25f32.to_bits(), (-2.0f32).to_bits(), (-0.0f32).to_bits(), 1.0f32.to_bits()], singles(&cpu, 19));
+        assert_eq!([1.75f64.sqrt().to_bits(), 2.0f64.sqrt().to_bits()], [0, 1].map(|i| cpu.vector_element(15, i, 64)));
+        // invalid for the signaling NaN
+        assert_eq!(0x10, cpu.read_csr(0x001) & 0x10);
+
+        // there are no byte wide floats
+        assert_eq!(TrapType::IllegalInstruction, cpu.tick(&mut memory).unwrap_err().trap_type);
+    }
+}
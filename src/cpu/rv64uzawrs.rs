@@ -0,0 +1,83 @@
+use crate::cpu::{Cpu, Memory, Trap};
+use crate::cpu::instruction::Instruction;
+use std::time::{Duration, Instant};
+
+// how long WRS.STO stays parked when nothing wakes it
+const SHORT_TIMEOUT: Duration = Duration::from_micros(100);
+
+// Parks the hart while the reservation an LR made is intact, until the WakeHandle is woken. Only
+// the low word of the reserved value is compared, as that is all LR.W loaded. Completing early is
+// always allowed, so with no reservation or no handle this does nothing at all.
+fn wait_on_reservation(cpu: &mut Cpu, memory: &mut dyn Memory, timeout: Option<Duration>) -> Result<(), Trap> {
+    let wake = match (&cpu.wake, cpu.is_reservation_set) {
+        (Some(wake), true) => wake.clone(),
+        _ => return Ok(())
+    };
+    let seen = wake.wakes();
+    if memory.read_u32(cpu.reservation as usize)? == cpu.reservation_value as u32 {
+        wake.park(seen, timeout.map(|timeout| Instant::now() + timeout), &cpu.stop);
+    }
+    Ok(())
+}
+
+pub const WRS_NTO: Instruction = Instruction {
+    name: "WRS.NTO",
+    operation: |cpu, memory, _word, _address| {
+        wait_on_reservation(cpu, memory, None)
+    }
+};
+
+pub const WRS_STO: Instruction = Instruction {
+    name: "WRS.STO",
+    operation: |cpu, memory, _word, _address| {
+        wait_on_reservation(cpu, memory, Some(SHORT_TIMEOUT))
+    }
+};
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::{Cpu, Register, WakeHandle};
+    use crate::memory::{Memory, SharedMemory};
+    use std::thread;
+    use std::time::Duration;
+
+    // lr.w a2, (a0); wrs.nto; wrs.sto
+    const PROGRAM: [u32; 3] = [0x1005262f, 0x00d00073, 0x01d00073];
+    const FLAG: usize = 0x100;
+
+    #[test]
+    fn wrs_parks_until_woken() {
+        let mut image = vec![0u8; 0x200];
+        for (i, word) in PROGRAM.iter().enumerate() {
+            image[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        let mut memory = SharedMemory::from_bytes(&image);
+        let wake = WakeHandle::new();
+
+        // without a handle both complete at once
+        let mut cpu = Cpu::new();
+        cpu.set_register(Register::A0, FLAG as i64);
+        for _ in 0..PROGRAM.len() {
+            cpu.tick(&mut memory).unwrap();
+        }
+
+        let mut other = memory.clone();
+        let waker = wake.clone();
+        let stored = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            other.write_u32(FLAG, 1).unwrap();
+            waker.wake();
+        });
+        let mut cpu = Cpu::builder().wake_handle(wake).build();
+        cpu.set_register(Register::A0, FLAG as i64);
+        cpu.tick(&mut memory).unwrap();
+        cpu.tick(&mut memory).unwrap();
+        // wrs.nto only came back once the store it was waiting for had been made
+        assert_eq!(1, memory.read_u32(FLAG).unwrap());
+        stored.join().unwrap();
+
+        // the reserved value has changed, so wrs.sto does not park either
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(12, cpu.get_pc());
+    }
+}
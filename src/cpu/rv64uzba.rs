@@ -0,0 +1,102 @@
+use crate::cpu::{instruction, Cpu};
+use crate::cpu::instruction::Instruction;
+
+// rd = (rs1 << shift) + rs2, the .uw forms taking only the low word of rs1 and zero extending it
+fn shift_add(cpu: &mut Cpu, word: u32, shift: u32, uw: bool) {
+    let f = instruction::parse_format_r(word);
+    let base = match uw {
+        true => cpu.x[f.rs1] as u32 as i64,
+        false => cpu.x[f.rs1]
+    };
+    cpu.x[f.rd] = cpu.sign_extend((base << shift).wrapping_add(cpu.x[f.rs2]));
+}
+
+pub const ADD_UW: Instruction = Instruction {
+    name: "ADD.UW",
+    operation: |cpu, _memory, word, _address| {
+        shift_add(cpu, word, 0, true);
+        Ok(())
+    }
+};
+
+pub const SH1ADD: Instruction = Instruction {
+    name: "SH1ADD",
+    operation: |cpu, _memory, word, _address| {
+        shift_add(cpu, word, 1, false);
+        Ok(())
+    }
+};
+
+pub const SH1ADD_UW: Instruction = Instruction {
+    name: "SH1ADD.UW",
+    operation: |cpu, _memory, word, _address| {
+        shift_add(cpu, word, 1, true);
+        Ok(())
+    }
+};
+
+pub const SH2ADD: Instruction = Instruction {
+    name: "SH2ADD",
+    operation: |cpu, _memory, word, _address| {
+        shift_add(cpu, word, 2, false);
+        Ok(())
+    }
+};
+
+pub const SH2ADD_UW: Instruction = Instruction {
+    name: "SH2ADD.UW",
+    operation: |cpu, _memory, word, _address| {
+        shift_add(cpu, word, 2, true);
+        Ok(())
+    }
+};
+
+pub const SH3ADD: Instruction = Instruction {
+    name: "SH3ADD",
+    operation: |cpu, _memory, word, _address| {
+        shift_add(cpu, word, 3, false);
+        Ok(())
+    }
+};
+
+pub const SH3ADD_UW: Instruction = Instruction {
+    name: "SH3ADD.UW",
+    operation: |cpu, _memory, word, _address| {
+        shift_add(cpu, word, 3, true);
+        Ok(())
+    }
+};
+
+pub const SLLI_UW: Instruction = Instruction {
+    name: "SLLI.UW",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let shamt = (word >> 20) & 0x3f;
+        cpu.x[f.rd] = ((cpu.x[f.rs1] as u32 as u64) << shamt) as i64;
+        Ok(())
+    }
+};
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test_cpu::{program, run};
+    use crate::cpu::{Cpu, Xlen};
+
+    #[test]
+    fn address_generation() {
+        // sh1add a2, a0, a1; sh2add a3, a0, a1; sh3add a4, a0, a1; add.uw a5, a0, a1;
+        // sh3add.uw a6, a0, a1; slli.uw a7, a0, 4
+        let words = [0x20b52633u32, 0x20b546b3, 0x20b56733, 0x08b507bb, 0x20b5683b, 0x0845189b];
+        let cpu = run(&words, Xlen::Bit64, -1, 0x1000);
+        assert_eq!([0xffe, 0xffc, 0xff8], [cpu.x[12], cpu.x[13], cpu.x[14]]);
+        // the .uw forms see rs1 as 0xffffffff
+        assert_eq!([0x1_0000_0fff, 0x8_0000_0ff8, 0xf_ffff_fff0], [cpu.x[15], cpu.x[16], cpu.x[17]]);
+
+        // only the word sized forms are missing from RV32
+        let cpu = run(&words[..1], Xlen::Bit32, 3, 0x1000);
+        assert_eq!(0x1006, cpu.x[12]);
+        let mut cpu = Cpu::builder().xlen(Xlen::Bit32).build();
+        cpu.update_pc(12);
+        assert!(cpu.tick(&mut program(&words)).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+#![cfg_attr(not(feature = "zbb"), allow(dead_code))]
+use crate::cpu::{instruction, Xlen};
+use crate::cpu::instruction::Instruction;
+
+pub const ANDN: Instruction = Instruction {
+    name: "ANDN",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.x[f.rs1] & !cpu.x[f.rs2];
+        Ok(())
+    }
+};
+
+pub const CLZ: Instruction = Instruction {
+    name: "CLZ",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = match cpu.xlen {
+            Xlen::Bit32 => (cpu.x[f.rs1] as u32).leading_zeros() as i64,
+            Xlen::Bit64 => cpu.x[f.rs1].leading_zeros() as i64
+        };
+        Ok(())
+    }
+};
+
+pub const CLZW: Instruction = Instruction {
+    name: "CLZW",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = (cpu.x[f.rs1] as u32).leading_zeros() as i64;
+        Ok(())
+    }
+};
+
+pub const CPOP: Instruction = Instruction {
+    name: "CPOP",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.unsigned_data(cpu.x[f.rs1]).count_ones() as i64;
+        Ok(())
+    }
+};
+
+pub const CPOPW: Instruction = Instruction {
+    name: "CPOPW",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = (cpu.x[f.rs1] as u32).count_ones() as i64;
+        Ok(())
+    }
+};
+
+pub const CTZ: Instruction = Instruction {
+    name: "CTZ",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = match cpu.xlen {
+            Xlen::Bit32 => (cpu.x[f.rs1] as u32).trailing_zeros() as i64,
+            Xlen::Bit64 => cpu.x[f.rs1].trailing_zeros() as i64
+        };
+        Ok(())
+    }
+};
+
+pub const CTZW: Instruction = Instruction {
+    name: "CTZW",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = (cpu.x[f.rs1] as u32).trailing_zeros() as i64;
+        Ok(())
+    }
+};
+
+pub const MAX: Instruction = Instruction {
+    name: "MAX",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.x[f.rs1].max(cpu.x[f.rs2]);
+        Ok(())
+    }
+};
+
+pub const MAXU: Instruction = Instruction {
+    name: "MAXU",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        // registers are kept sign extended in RV32, which leaves their unsigned order alone
+        cpu.x[f.rd] = (cpu.x[f.rs1] as u64).max(cpu.x[f.rs2] as u64) as i64;
+        Ok(())
+    }
+};
+
+pub const MIN: Instruction = Instruction {
+    name: "MIN",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.x[f.rs1].min(cpu.x[f.rs2]);
+        Ok(())
+    }
+};
+
+pub const MINU: Instruction = Instruction {
+    name: "MINU",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = (cpu.x[f.rs1] as u64).min(cpu.x[f.rs2] as u64) as i64;
+        Ok(())
+    }
+};
+
+pub const ORC_B: Instruction = Instruction {
+    name: "ORC.B",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let bytes = cpu.x[f.rs1].to_le_bytes().map(|byte| if byte == 0 { 0 } else { 0xff });
+        cpu.x[f.rd] = cpu.sign_extend(i64::from_le_bytes(bytes));
+        Ok(())
+    }
+};
+
+pub const ORN: Instruction = Instruction {
+    name: "ORN",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.x[f.rs1] | !cpu.x[f.rs2];
+        Ok(())
+    }
+};
+
+pub const REV8: Instruction = Instruction {
+    name: "REV8",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = match cpu.xlen {
+            Xlen::Bit32 => (cpu.x[f.rs1] as u32).swap_bytes() as i32 as i64,
+            Xlen::Bit64 => cpu.x[f.rs1].swap_bytes()
+        };
+        Ok(())
+    }
+};
+
+pub const ROL: Instruction = Instruction {
+    name: "ROL",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let shamt = cpu.shift_amount(cpu.x[f.rs2]);
+        cpu.x[f.rd] = match cpu.xlen {
+            Xlen::Bit32 => (cpu.x[f.rs1] as u32).rotate_left(shamt) as i32 as i64,
+            Xlen::Bit64 => cpu.x[f.rs1].rotate_left(shamt)
+        };
+        Ok(())
+    }
+};
+
+pub const ROLW: Instruction = Instruction {
+    name: "ROLW",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = (cpu.x[f.rs1] as u32).rotate_left(cpu.x[f.rs2] as u32 & 0x1f) as i32 as i64;
+        Ok(())
+    }
+};
+
+pub const ROR: Instruction = Instruction {
+    name: "ROR",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let shamt = cpu.shift_amount(cpu.x[f.rs2]);
+        cpu.x[f.rd] = match cpu.xlen {
+            Xlen::Bit32 => (cpu.x[f.rs1] as u32).rotate_right(shamt) as i32 as i64,
+            Xlen::Bit64 => cpu.x[f.rs1].rotate_right(shamt)
+        };
+        Ok(())
+    }
+};
+
+pub const RORI: Instruction = Instruction {
+    name: "RORI",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let shamt = cpu.shift_amount((word >> 20) as i64);
+        cpu.x[f.rd] = match cpu.xlen {
+            Xlen::Bit32 => (cpu.x[f.rs1] as u32).rotate_right(shamt) as i32 as i64,
+            Xlen::Bit64 => cpu.x[f.rs1].rotate_right(shamt)
+        };
+        Ok(())
+    }
+};
+
+pub const RORIW: Instruction = Instruction {
+    name: "RORIW",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = (cpu.x[f.rs1] as u32).rotate_right(f.rs2 as u32) as i32 as i64;
+        Ok(())
+    }
+};
+
+pub const RORW: Instruction = Instruction {
+    name: "RORW",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = (cpu.x[f.rs1] as u32).rotate_right(cpu.x[f.rs2] as u32 & 0x1f) as i32 as i64;
+        Ok(())
+    }
+};
+
+pub const SEXT_B: Instruction = Instruction {
+    name: "SEXT.B",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.x[f.rs1] as i8 as i64;
+        Ok(())
+    }
+};
+
+pub const SEXT_H: Instruction = Instruction {
+    name: "SEXT.H",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.x[f.rs1] as i16 as i64;
+        Ok(())
+    }
+};
+
+pub const XNOR: Instruction = Instruction {
+    name: "XNOR",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = !(cpu.x[f.rs1] ^ cpu.x[f.rs2]);
+        Ok(())
+    }
+};
+
+pub const ZEXT_H: Instruction = Instruction {
+    name: "ZEXT.H",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.x[f.rs1] as u16 as i64;
+        Ok(())
+    }
+};
+
+#[cfg(all(test, feature = "zbb"))]
+mod test {
+    use crate::cpu::test_cpu::run;
+    use crate::cpu::Xlen;
+
+    #[test]
+    fn bit_manipulation() {
+        // andn a2, a0, a1; orn a3, a0, a1; xnor a4, a0, a1; clz a5, a0; ctz a6, a0; cpop a7, a0;
+        // clzw t0, a0; minu t1, a0, a1; max t2, a0, a1; sext.b t3, a0; zext.h t4, a0;
+        // rori t5, a0, 8; orc.b t6, a0; rev8 s2, a0; rolw s3, a0, a1
+        let words = [0x40b57633, 0x40b566b3, 0x40b54733, 0x60051793, 0x60151813, 0x60251893, 0x6005129b, 0x0ab55333,
+            0x0ab563b3, 0x60451e13, 0x08054ebb, 0x60855f13, 0x28755f93, 0x6b855913, 0x60b519bb];
+        let cpu = run(&words, Xlen::Bit64, 0x0000_00f0_0000_8180, 4);
+        assert_eq!([0xf0_0000_8180 & !4, !4, !(0xf0_0000_8180 ^ 4)], [cpu.x[12], cpu.x[13], cpu.x[14]]);
+        assert_eq!([24, 7, 7, 16], [cpu.x[15], cpu.x[16], cpu.x[17], cpu.x[5]]);
+        assert_eq!([4, 0xf0_0000_8180, -128, 0x8180], [cpu.x[6], cpu.x[7], cpu.x[28], cpu.x[29]]);
+        assert_eq!([0x8000_0000_f000_0081u64 as i64, 0xff_0000_ffff], [cpu.x[30], cpu.x[31]]);
+        assert_eq!([0x8081_0000_f000_0000u64 as i64, 0x81800], [cpu.x[18], cpu.x[19]]);
+
+        // clz and ctz count within the register width, rev8 has its own encoding in RV32
+        let cpu = run(&[0x60051793, 0x60151813, 0x69855913, 0x60855f13], Xlen::Bit32, 0, 0);
+        assert_eq!([32, 32, 0, 0], [cpu.x[15], cpu.x[16], cpu.x[18], cpu.x[30]]);
+        let cpu = run(&[0x69855913, 0x60855f13], Xlen::Bit32, 0x1234_5678, 0);
+        assert_eq!([0x7856_3412, 0x7812_3456], [cpu.x[18], cpu.x[30]]);
+    }
+}
@@ -0,0 +1,62 @@
+#![cfg_attr(not(feature = "zbc"), allow(dead_code))]
+use crate::cpu::{instruction, Cpu, Xlen};
+use crate::cpu::instruction::Instruction;
+
+// the full double width carry-less product of rs1 and rs2, along with the width
+fn carryless_product(cpu: &Cpu, word: u32) -> (u128, u32) {
+    let f = instruction::parse_format_r(word);
+    let width = match cpu.xlen {
+        Xlen::Bit32 => 32,
+        Xlen::Bit64 => 64
+    };
+    let (a, b) = (cpu.unsigned_data(cpu.x[f.rs1]) as u128, cpu.unsigned_data(cpu.x[f.rs2]));
+    let product = (0..width).filter(|i| (b >> i) & 1 != 0).fold(0, |product, i| product ^ (a << i));
+    (product, width)
+}
+
+pub const CLMUL: Instruction = Instruction {
+    name: "CLMUL",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (product, _) = carryless_product(cpu, word);
+        cpu.x[f.rd] = cpu.sign_extend(product as i64);
+        Ok(())
+    }
+};
+
+pub const CLMULH: Instruction = Instruction {
+    name: "CLMULH",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (product, width) = carryless_product(cpu, word);
+        cpu.x[f.rd] = cpu.sign_extend((product >> width) as i64);
+        Ok(())
+    }
+};
+
+pub const CLMULR: Instruction = Instruction {
+    name: "CLMULR",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (product, width) = carryless_product(cpu, word);
+        cpu.x[f.rd] = cpu.sign_extend((product >> (width - 1)) as i64);
+        Ok(())
+    }
+};
+
+#[cfg(all(test, feature = "zbc"))]
+mod test {
+    use crate::cpu::test_cpu::run;
+    use crate::cpu::Xlen;
+
+    #[test]
+    fn carryless_multiplication() {
+        // clmul a2, a0, a1; clmulh a3, a0, a1; clmulr a4, a0, a1
+        let words = [0x0ab51633u32, 0x0ab536b3, 0x0ab52733];
+        let cpu = run(&words, Xlen::Bit64, 0x8000_0000_0000_0003u64 as i64, 0x8000_0000_0000_0005u64 as i64);
+        assert_eq!([0xf, 0x4000_0000_0000_0003, 0x8000_0000_0000_0006u64 as i64], [cpu.x[12], cpu.x[13], cpu.x[14]]);
+
+        let cpu = run(&words, Xlen::Bit32, 0x8000_0003u32 as i32 as i64, 0x8000_0005u32 as i32 as i64);
+        assert_eq!([0xf, 0x4000_0003, 0x8000_0006u32 as i32 as i64], [cpu.x[12], cpu.x[13], cpu.x[14]]);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::cpu::{instruction, Xlen};
+use crate::cpu::instruction::Instruction;
+
+// The Zbkb instructions Zbb does not have, the rest of it being shared with Zbb.
+
+pub const PACK: Instruction = Instruction {
+    name: "PACK",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let half = match cpu.xlen {
+            Xlen::Bit32 => 16,
+            Xlen::Bit64 => 32
+        };
+        let low = cpu.x[f.rs1] as u64 & ((1 << half) - 1);
+        cpu.x[f.rd] = cpu.sign_extend(((cpu.x[f.rs2] as u64) << half | low) as i64);
+        Ok(())
+    }
+};
+
+pub const PACKH: Instruction = Instruction {
+    name: "PACKH",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = (cpu.x[f.rs2] & 0xff) << 8 | (cpu.x[f.rs1] & 0xff);
+        Ok(())
+    }
+};
+
+pub const PACKW: Instruction = Instruction {
+    name: "PACKW",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = ((cpu.x[f.rs2] as u32) << 16 | (cpu.x[f.rs1] as u16 as u32)) as i32 as i64;
+        Ok(())
+    }
+};
+
+// reverses the bits of each byte, leaving the bytes where they are
+pub const BREV8: Instruction = Instruction {
+    name: "BREV8",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rs1].reverse_bits().swap_bytes());
+        Ok(())
+    }
+};
+
+// RV32 only, interleaving the low half of rs1 with the high half
+pub const ZIP: Instruction = Instruction {
+    name: "ZIP",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let value = cpu.x[f.rs1] as u32;
+        let zipped = (0..16).fold(0, |zipped, i| zipped | ((value >> i) & 1) << (2 * i) | ((value >> (i + 16)) & 1) << (2 * i + 1));
+        cpu.x[f.rd] = zipped as i32 as i64;
+        Ok(())
+    }
+};
+
+pub const UNZIP: Instruction = Instruction {
+    name: "UNZIP",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let value = cpu.x[f.rs1] as u32;
+        let unzipped = (0..16).fold(0, |unzipped, i| unzipped | ((value >> (2 * i)) & 1) << i | ((value >> (2 * i + 1)) & 1) << (i + 16));
+        cpu.x[f.rd] = unzipped as i32 as i64;
+        Ok(())
+    }
+};
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test_cpu::run;
+    use crate::cpu::Xlen;
+
+    #[test]
+    fn packing_and_bit_permutations() {
+        // pack a2, a0, a1; packh a3, a0, a1; packw a4, a0, a1; brev8 a5, a0
+        let cpu = run(&[0x08b54633, 0x08b576b3, 0x08b5473b, 0x68755793], Xlen::Bit64, 0x1234_5678_9abc_de01, 0x0fed_cba9_8765_4381);
+        assert_eq!([0x8765_4381_9abc_de01u64 as i64, 0x8101, 0x4381_de01, 0x482c_6a1e_593d_7b80], [cpu.x[12], cpu.x[13], cpu.x[14], cpu.x[15]]);
+
+        // pack a2, a0, a1; zip a3, a0; unzip a4, a0
+        let cpu = run(&[0x08b54633, 0x08f51693, 0x08f55713], Xlen::Bit32, 0xffff_0000u32 as i32 as i64, 0x8001);
+        assert_eq!([0x8001_0000u32 as i32 as i64, 0xaaaa_aaaau32 as i32 as i64, 0xff00_ff00u32 as i32 as i64], [cpu.x[12], cpu.x[13], cpu.x[14]]);
+    }
+}
@@ -0,0 +1,53 @@
+use crate::cpu::{instruction, Cpu, Xlen};
+use crate::cpu::instruction::Instruction;
+
+// Looks up each lane of rs2, bits wide, as an index into the lanes of rs1, with those past the end
+// of the register giving zero. These are the crossbar permutations of Zbkx.
+fn permute(cpu: &Cpu, word: u32, bits: u32) -> i64 {
+    let f = instruction::parse_format_r(word);
+    let width = match cpu.xlen {
+        Xlen::Bit32 => 32,
+        Xlen::Bit64 => 64
+    };
+    let (table, indices) = (cpu.x[f.rs1] as u64, cpu.x[f.rs2] as u64);
+    let mask = (1 << bits) - 1;
+    let result = (0..width / bits).fold(0, |result, lane| {
+        let index = (indices >> (lane * bits)) & mask;
+        match index < (width / bits) as u64 {
+            true => result | ((table >> (index as u32 * bits)) & mask) << (lane * bits),
+            false => result
+        }
+    });
+    cpu.sign_extend(result as i64)
+}
+
+pub const XPERM4: Instruction = Instruction {
+    name: "XPERM4",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = permute(cpu, word, 4);
+        Ok(())
+    }
+};
+
+pub const XPERM8: Instruction = Instruction {
+    name: "XPERM8",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = permute(cpu, word, 8);
+        Ok(())
+    }
+};
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test_cpu::run;
+    use crate::cpu::Xlen;
+
+    #[test]
+    fn crossbar_permutations() {
+        // xperm8 a2, a0, a1; xperm4 a3, a0, a1
+        let cpu = run(&[0x28b54633, 0x28b526b3], Xlen::Bit64, 0x0706_0504_0302_0100, 0x0008_0107_0203_0f00);
+        assert_eq!([0x0000_0107_0203_0000, 0x0004_0000_0100_0000], [cpu.x[12], cpu.x[13]]);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::cpu::{instruction, Cpu};
+use crate::cpu::instruction::Instruction;
+
+// the bit of rs1 the instruction is about, from rs2 or the shift amount field, modulo the width
+fn bit_index(cpu: &Cpu, word: u32, immediate: bool) -> u32 {
+    let f = instruction::parse_format_r(word);
+    match immediate {
+        true => cpu.shift_amount((word >> 20) as i64),
+        false => cpu.shift_amount(cpu.x[f.rs2])
+    }
+}
+
+pub const BCLR: Instruction = Instruction {
+    name: "BCLR",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rs1] & !(1 << bit_index(cpu, word, false)));
+        Ok(())
+    }
+};
+
+pub const BCLRI: Instruction = Instruction {
+    name: "BCLRI",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rs1] & !(1 << bit_index(cpu, word, true)));
+        Ok(())
+    }
+};
+
+pub const BEXT: Instruction = Instruction {
+    name: "BEXT",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = (cpu.x[f.rs1] >> bit_index(cpu, word, false)) & 1;
+        Ok(())
+    }
+};
+
+pub const BEXTI: Instruction = Instruction {
+    name: "BEXTI",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = (cpu.x[f.rs1] >> bit_index(cpu, word, true)) & 1;
+        Ok(())
+    }
+};
+
+pub const BINV: Instruction = Instruction {
+    name: "BINV",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rs1] ^ (1 << bit_index(cpu, word, false)));
+        Ok(())
+    }
+};
+
+pub const BINVI: Instruction = Instruction {
+    name: "BINVI",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rs1] ^ (1 << bit_index(cpu, word, true)));
+        Ok(())
+    }
+};
+
+pub const BSET: Instruction = Instruction {
+    name: "BSET",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rs1] | (1 << bit_index(cpu, word, false)));
+        Ok(())
+    }
+};
+
+pub const BSETI: Instruction = Instruction {
+    name: "BSETI",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = cpu.sign_extend(cpu.x[f.rs1] | (1 << bit_index(cpu, word, true)));
+        Ok(())
+    }
+};
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test_cpu::run;
+    use crate::cpu::Xlen;
+
+    #[test]
+    fn single_bit_operations() {
+        // bset a2, a0, a1; bclr a3, a0, a1; binv a4, a0, a1; bext a5, a0, a1; bseti a6, a0, 40;
+        // bclri a7, a0, 4; binvi t0, a0, 63; bexti t1, a0, 4
+        let words = [0x28b51633u32, 0x48b516b3, 0x68b51733, 0x48b557b3, 0x2a851813, 0x48451893, 0x6bf51293, 0x48455313];
+        let cpu = run(&words, Xlen::Bit64, 0x30, 36);
+        assert_eq!([0x10_0000_0030, 0x30, 0x10_0000_0030, 0], [cpu.x[12], cpu.x[13], cpu.x[14], cpu.x[15]]);
+        assert_eq!([0x100_0000_0030, 0x20, 0x8000_0000_0000_0030u64 as i64, 1], [cpu.x[16], cpu.x[17], cpu.x[5], cpu.x[6]]);
+
+        // the index wraps at 32 in RV32, making 36 bit 4
+        let cpu = run(&words[..4], Xlen::Bit32, 0x30, 36);
+        assert_eq!([0x30, 0x20, 0x20, 1], [cpu.x[12], cpu.x[13], cpu.x[14], cpu.x[15]]);
+    }
+}
@@ -0,0 +1,409 @@
+use crate::cpu::{instruction, Cpu, Trap};
+use crate::cpu::instruction::Instruction;
+use crate::cpu::rv64uf;
+#[cfg(feature = "d")]
+use crate::cpu::rv64ud;
+
+// what FLI loads for each value of rs1, entry 1 being the smallest normal number of the
+// precision and entry 31 its canonical NaN
+const FLI_VALUES: [f64; 32] = [-1.0, f64::MIN_POSITIVE, 1.52587890625e-05, 3.0517578125e-05, 0.00390625, 0.0078125, 0.0625,
+    0.125, 0.25, 0.3125, 0.375, 0.4375, 0.5, 0.625, 0.75, 0.875, 1.0, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0, 4.0, 8.0, 16.0, 128.0,
+    256.0, 32768.0, 65536.0, f64::INFINITY, f64::NAN];
+
+fn is_signaling_s(v: f32) -> bool {
+    v.is_nan() && v.to_bits() & 0x0040_0000 == 0
+}
+
+#[cfg(feature = "d")]
+fn is_signaling_d(v: f64) -> bool {
+    v.is_nan() && v.to_bits() & 0x0008_0000_0000_0000 == 0
+}
+
+// IEEE 754-2019 minimum and maximum, which unlike FMIN and FMAX return NaN when either input is
+// one. The callers deal with the NaNs, what is left here is -0.0 ordering before +0.0.
+fn minimum(v1: f64, v2: f64) -> f64 {
+    match v1 == v2 {
+        true if v1.is_sign_negative() => v1,
+        true => v2,
+        false => v1.min(v2)
+    }
+}
+
+fn maximum(v1: f64, v2: f64) -> f64 {
+    match v1 == v2 {
+        true if v1.is_sign_positive() => v1,
+        true => v2,
+        false => v1.max(v2)
+    }
+}
+
+// v rounded to an integer the way rm says, whatever the precision v started out in
+fn round_to_integer(v: f64, rm: u64) -> f64 {
+    match rm {
+        0 => v.round_ties_even(),
+        1 => v.trunc(),
+        2 => v.floor(),
+        3 => v.ceil(),
+        _ => v.round()
+    }
+}
+
+pub const FLI_S: Instruction = Instruction {
+    name: "FLI.S",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let value = match f.rs1 {
+            1 => f32::MIN_POSITIVE,
+            31 => f32::from_bits(rv64uf::CANONICAL_NAN),
+            index => FLI_VALUES[index] as f32
+        };
+        cpu.set_f32(f.rd, value);
+        Ok(())
+    }
+};
+
+pub const FMINM_S: Instruction = Instruction {
+    name: "FMINM.S",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (v1, v2) = (cpu.get_f32(f.rs1), cpu.get_f32(f.rs2));
+        if is_signaling_s(v1) || is_signaling_s(v2) {
+            cpu.set_fcsr_nv();
+        }
+        let result = match v1.is_nan() || v2.is_nan() {
+            true => f32::from_bits(rv64uf::CANONICAL_NAN),
+            false => minimum(v1 as f64, v2 as f64) as f32
+        };
+        cpu.set_f32(f.rd, result);
+        Ok(())
+    }
+};
+
+pub const FMAXM_S: Instruction = Instruction {
+    name: "FMAXM.S",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (v1, v2) = (cpu.get_f32(f.rs1), cpu.get_f32(f.rs2));
+        if is_signaling_s(v1) || is_signaling_s(v2) {
+            cpu.set_fcsr_nv();
+        }
+        let result = match v1.is_nan() || v2.is_nan() {
+            true => f32::from_bits(rv64uf::CANONICAL_NAN),
+            false => maximum(v1 as f64, v2 as f64) as f32
+        };
+        cpu.set_f32(f.rd, result);
+        Ok(())
+    }
+};
+
+// FROUNDNX is the same but for raising the inexact flag when rounding changed anything
+fn round_s(cpu: &mut Cpu, word: u32, inexact: bool) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let rm = cpu.rounding_mode(word)?;
+    let v = cpu.get_f32(f.rs1);
+    // the host may raise inexact along the way, which only FROUNDNX is meant to
+    let flags = cpu.read_fflags();
+    if is_signaling_s(v) {
+        cpu.set_fcsr_nv();
+    }
+    let result = match v.is_nan() {
+        true => f32::from_bits(rv64uf::CANONICAL_NAN),
+        false => round_to_integer(v as f64, rm) as f32
+    };
+    cpu.write_fflags(flags);
+    if inexact && !v.is_nan() && result != v {
+        cpu.set_fcsr_nx();
+    }
+    cpu.set_f32(f.rd, result);
+    Ok(())
+}
+
+pub const FROUND_S: Instruction = Instruction {
+    name: "FROUND.S",
+    operation: |cpu, _memory, word, _address| {
+        round_s(cpu, word, false)
+    }
+};
+
+pub const FROUNDNX_S: Instruction = Instruction {
+    name: "FROUNDNX.S",
+    operation: |cpu, _memory, word, _address| {
+        round_s(cpu, word, true)
+    }
+};
+
+// quiet comparisons, only a signaling NaN is invalid
+pub const FLEQ_S: Instruction = Instruction {
+    name: "FLEQ.S",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (v1, v2) = (cpu.get_f32(f.rs1), cpu.get_f32(f.rs2));
+        if is_signaling_s(v1) || is_signaling_s(v2) {
+            cpu.set_fcsr_nv();
+        }
+        cpu.x[f.rd] = (v1 <= v2) as i64;
+        Ok(())
+    }
+};
+
+pub const FLTQ_S: Instruction = Instruction {
+    name: "FLTQ.S",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (v1, v2) = (cpu.get_f32(f.rs1), cpu.get_f32(f.rs2));
+        if is_signaling_s(v1) || is_signaling_s(v2) {
+            cpu.set_fcsr_nv();
+        }
+        cpu.x[f.rd] = (v1 < v2) as i64;
+        Ok(())
+    }
+};
+
+#[cfg(feature = "d")]
+pub const FLI_D: Instruction = Instruction {
+    name: "FLI.D",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.f[f.rd] = match f.rs1 {
+            31 => f64::from_bits(rv64ud::CANONICAL_NAN),
+            index => FLI_VALUES[index]
+        };
+        Ok(())
+    }
+};
+
+#[cfg(feature = "d")]
+pub const FMINM_D: Instruction = Instruction {
+    name: "FMINM.D",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (v1, v2) = (cpu.f[f.rs1], cpu.f[f.rs2]);
+        if is_signaling_d(v1) || is_signaling_d(v2) {
+            cpu.set_fcsr_nv();
+        }
+        cpu.f[f.rd] = match v1.is_nan() || v2.is_nan() {
+            true => f64::from_bits(rv64ud::CANONICAL_NAN),
+            false => minimum(v1, v2)
+        };
+        Ok(())
+    }
+};
+
+#[cfg(feature = "d")]
+pub const FMAXM_D: Instruction = Instruction {
+    name: "FMAXM.D",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (v1, v2) = (cpu.f[f.rs1], cpu.f[f.rs2]);
+        if is_signaling_d(v1) || is_signaling_d(v2) {
+            cpu.set_fcsr_nv();
+        }
+        cpu.f[f.rd] = match v1.is_nan() || v2.is_nan() {
+            true => f64::from_bits(rv64ud::CANONICAL_NAN),
+            false => maximum(v1, v2)
+        };
+        Ok(())
+    }
+};
+
+#[cfg(feature = "d")]
+fn round_d(cpu: &mut Cpu, word: u32, inexact: bool) -> Result<(), Trap> {
+    let f = instruction::parse_format_r(word);
+    let rm = cpu.rounding_mode(word)?;
+    let v = cpu.f[f.rs1];
+    // the host may raise inexact along the way, which only FROUNDNX is meant to
+    let flags = cpu.read_fflags();
+    if is_signaling_d(v) {
+        cpu.set_fcsr_nv();
+    }
+    let result = match v.is_nan() {
+        true => f64::from_bits(rv64ud::CANONICAL_NAN),
+        false => round_to_integer(v, rm)
+    };
+    cpu.write_fflags(flags);
+    if inexact && !v.is_nan() && result != v {
+        cpu.set_fcsr_nx();
+    }
+    cpu.f[f.rd] = result;
+    Ok(())
+}
+
+#[cfg(feature = "d")]
+pub const FROUND_D: Instruction = Instruction {
+    name: "FROUND.D",
+    operation: |cpu, _memory, word, _address| {
+        round_d(cpu, word, false)
+    }
+};
+
+#[cfg(feature = "d")]
+pub const FROUNDNX_D: Instruction = Instruction {
+    name: "FROUNDNX.D",
+    operation: |cpu, _memory, word, _address| {
+        round_d(cpu, word, true)
+    }
+};
+
+#[cfg(feature = "d")]
+pub const FLEQ_D: Instruction = Instruction {
+    name: "FLEQ.D",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (v1, v2) = (cpu.f[f.rs1], cpu.f[f.rs2]);
+        if is_signaling_d(v1) || is_signaling_d(v2) {
+            cpu.set_fcsr_nv();
+        }
+        cpu.x[f.rd] = (v1 <= v2) as i64;
+        Ok(())
+    }
+};
+
+#[cfg(feature = "d")]
+pub const FLTQ_D: Instruction = Instruction {
+    name: "FLTQ.D",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (v1, v2) = (cpu.f[f.rs1], cpu.f[f.rs2]);
+        if is_signaling_d(v1) || is_signaling_d(v2) {
+            cpu.set_fcsr_nv();
+        }
+        cpu.x[f.rd] = (v1 < v2) as i64;
+        Ok(())
+    }
+};
+
+// Truncates towards zero and keeps the low 32 bits of the integer, however large it was, the way
+// JavaScript converts numbers. The flags are those FCVT.W.D would raise rounding towards zero.
+#[cfg(feature = "d")]
+pub const FCVTMOD_W_D: Instruction = Instruction {
+    name: "FCVTMOD.W.D",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let v = cpu.f[f.rs1];
+        if !v.is_finite() {
+            cpu.set_fcsr_nv();
+            cpu.x[f.rd] = 0;
+            return Ok(());
+        }
+
+        let bits = v.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64;
+        let mantissa = match exponent {
+            0 => bits & 0xf_ffff_ffff_ffff,
+            _ => (bits & 0xf_ffff_ffff_ffff) | (1 << 52)
+        };
+        // the value is mantissa * 2^shift
+        let shift = exponent.max(1) - 1075;
+        let magnitude = match shift {
+            ..=-64 => 0,
+            -63..=-1 => (mantissa >> -shift) as u32,
+            0..=31 => (mantissa << shift) as u32,
+            _ => 0
+        };
+        let result = match v.is_sign_negative() {
+            true => magnitude.wrapping_neg(),
+            false => magnitude
+        } as i32;
+
+        let truncated = v.trunc();
+        if truncated < i32::MIN as f64 || truncated > i32::MAX as f64 {
+            cpu.set_fcsr_nv();
+        } else if truncated != v {
+            cpu.set_fcsr_nx();
+        }
+        cpu.x[f.rd] = result as i64;
+        Ok(())
+    }
+};
+
+// the two halves of a double in RV32, where it does not fit in an x register
+#[cfg(feature = "d")]
+pub const FMVH_X_D: Instruction = Instruction {
+    name: "FMVH.X.D",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = (cpu.f[f.rs1].to_bits() >> 32) as i32 as i64;
+        Ok(())
+    }
+};
+
+#[cfg(feature = "d")]
+pub const FMVP_D_X: Instruction = Instruction {
+    name: "FMVP.D.X",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.f[f.rd] = f64::from_bits((cpu.x[f.rs2] as u32 as u64) << 32 | cpu.x[f.rs1] as u32 as u64);
+        Ok(())
+    }
+};
+
+#[cfg(all(test, feature = "d"))]
+mod test {
+    use crate::cpu::fflags::{FFLAG_NV, FFLAG_NX};
+    use crate::cpu::test_cpu::program;
+    use crate::cpu::{Cpu, Xlen, CSR_FFLAGS_ADDRESS, CSR_FRM_ADDRESS};
+
+    fn run(cpu: &mut Cpu, words: &[u32]) {
+        let mut memory = program(words);
+        cpu.update_pc(0);
+        for _ in 0..words.len() {
+            cpu.tick(&mut memory).unwrap();
+        }
+    }
+
+    fn fflags(cpu: &mut Cpu) -> u64 {
+        let flags = cpu.read_csr(CSR_FFLAGS_ADDRESS);
+        cpu.write_csr(CSR_FFLAGS_ADDRESS, 0);
+        flags
+    }
+
+    #[test]
+    fn additional_floating_point() {
+        let mut cpu = Cpu::new();
+        cpu.set_f32(13, f32::NAN);
+        cpu.f[15..17].copy_from_slice(&[-0.0, 0.0]);
+        cpu.f[0] = 2.5;
+        cpu.set_f32(2, -1.5);
+        cpu.f[3..6].copy_from_slice(&[4294967301.5, -3.7, f64::NAN]);
+        cpu.write_csr(CSR_FRM_ADDRESS, 3);
+        fflags(&mut cpu);
+
+        // fli.s fa0, 2.0; fli.d fa1, min; fminm.s fa2, fa0, fa3; fmaxm.d fa4, fa5, fa6
+        run(&mut cpu, &[0xf01a0553, 0xf21085d3, 0x28d52653, 0x2b07b753]);
+        assert_eq!((2.0, f64::MIN_POSITIVE), (cpu.get_f32(10), cpu.f[11]));
+        assert_eq!(0x7fc00000, cpu.get_f32(12).to_bits());
+        assert_eq!(0.0f64.to_bits(), cpu.f[14].to_bits());
+        assert_eq!(0, fflags(&mut cpu));
+
+        // fround.d fa7, ft0, rne; froundnx.s ft1, ft2 with frm being rup
+        run(&mut cpu, &[0x424008d3, 0x405170d3]);
+        assert_eq!((2.0, -1.0), (cpu.f[17], cpu.get_f32(1)));
+        assert_eq!(FFLAG_NX, fflags(&mut cpu));
+
+        // fcvtmod.w.d a0, ft3; fcvtmod.w.d a1, ft4, wrapping and then only inexact
+        run(&mut cpu, &[0xc2819553]);
+        assert_eq!((5, FFLAG_NV), (cpu.x[10], fflags(&mut cpu)));
+        run(&mut cpu, &[0xc28215d3]);
+        assert_eq!((-3, FFLAG_NX), (cpu.x[11], fflags(&mut cpu)));
+
+        // fltq.d a2, ft5, ft6 on a quiet NaN; fleq.s a3, fa0, fa0
+        run(&mut cpu, &[0xa262d653, 0xa0a546d3]);
+        assert_eq!([0, 1], cpu.x[12..14]);
+        assert_eq!(0, fflags(&mut cpu));
+    }
+
+    #[test]
+    fn rv32_moves_doubles_in_halves() {
+        // fmvh.x.d a4, fa4; fmvp.d.x fa5, a0, a1
+        let words = [0xe2170753, 0xb2b507d3];
+        let mut cpu = Cpu::builder().xlen(Xlen::Bit32).build();
+        cpu.f[14] = f64::from_bits(0x8123_4567_0000_0000);
+        cpu.x[10..12].copy_from_slice(&[0x89ab_cdef_u32 as i32 as i64, 0x0123_4567]);
+        run(&mut cpu, &words);
+        assert_eq!(0x8123_4567_u32 as i32 as i64, cpu.x[14]);
+        assert_eq!(0x0123_4567_89ab_cdef, cpu.f[15].to_bits());
+
+        assert!(Cpu::decode(words[0]).is_none());
+        assert!(Cpu::decode(words[1]).is_none());
+    }
+}
@@ -0,0 +1,44 @@
+use crate::cpu::instruction;
+use crate::cpu::instruction::Instruction;
+
+pub const CZERO_EQZ: Instruction = Instruction {
+    name: "CZERO.EQZ",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = match cpu.x[f.rs2] {
+            0 => 0,
+            _ => cpu.x[f.rs1]
+        };
+        Ok(())
+    }
+};
+
+pub const CZERO_NEZ: Instruction = Instruction {
+    name: "CZERO.NEZ",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = match cpu.x[f.rs2] {
+            0 => cpu.x[f.rs1],
+            _ => 0
+        };
+        Ok(())
+    }
+};
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test_cpu::run;
+    use crate::cpu::Xlen;
+
+    #[test]
+    fn conditional_zero() {
+        // czero.eqz a2, a0, a1; czero.nez a3, a0, a1
+        for condition in [0, -1] {
+            let cpu = run(&[0x0eb55633, 0x0eb576b3], Xlen::Bit64, 42, condition);
+            match condition {
+                0 => assert_eq!([0, 42], [cpu.x[12], cpu.x[13]]),
+                _ => assert_eq!([42, 0], [cpu.x[12], cpu.x[13]])
+            }
+        }
+    }
+}
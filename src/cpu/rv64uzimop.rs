@@ -0,0 +1,47 @@
+use crate::cpu::instruction;
+use crate::cpu::instruction::Instruction;
+
+// Nothing is redefined on top of any of the may-be-operations yet, so every one of them just
+// writes zero to rd. C.MOP expands to a plain NOP, it not writing anything.
+pub const MOP_R: Instruction = Instruction {
+    name: "MOP.R",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = 0;
+        Ok(())
+    }
+};
+
+pub const MOP_RR: Instruction = Instruction {
+    name: "MOP.RR",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = 0;
+        Ok(())
+    }
+};
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::Cpu;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn may_be_operations_write_zero() {
+        // mop.r.0 a0, a1; mop.rr.7 a2, a0, a1
+        let mut memory = program(&[0x81c5c573u32, 0xceb54673]);
+        // c.mop.1
+        memory.extend([0x81, 0x60, 0, 0]);
+        let mut cpu = Cpu::new();
+        cpu.x[1] = 9;
+        cpu.x[10..13].copy_from_slice(&[1, 2, 3]);
+        cpu.tick(&mut memory).unwrap();
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!([0, 2, 0], cpu.x[10..13]);
+        #[cfg(feature = "c")]
+        {
+            cpu.tick(&mut memory).unwrap();
+            assert_eq!((9, 10), (cpu.x[1], cpu.get_pc()));
+        }
+    }
+}
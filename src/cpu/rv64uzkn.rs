@@ -0,0 +1,195 @@
+#![cfg_attr(not(all(feature = "zknd", feature = "zkne")), allow(dead_code))]
+use crate::cpu::{instruction, Cpu, Trap, TrapType};
+use crate::cpu::instruction::Instruction;
+
+// The RV64 AES instructions of Zkne and Zknd. The state is the 128 bits of rs2 and rs1 with rs1
+// the low half, its columns being the four 32 bit words, and the rounds give back the low half.
+
+const fn gf_multiply(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1b } else { 0 };
+        b >>= 1;
+    }
+    product
+}
+
+// the inverse in GF(2^8) as a^254, followed by the affine transformation
+const fn sbox() -> [u8; 256] {
+    let mut sbox = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut inverse = 1;
+        let mut n = 0;
+        while n < 254 {
+            inverse = gf_multiply(inverse, i as u8);
+            n += 1;
+        }
+        if i == 0 {
+            inverse = 0;
+        }
+        sbox[i] = inverse ^ inverse.rotate_left(1) ^ inverse.rotate_left(2) ^ inverse.rotate_left(3) ^ inverse.rotate_left(4) ^ 0x63;
+        i += 1;
+    }
+    sbox
+}
+
+const fn inverse_sbox() -> [u8; 256] {
+    let mut inverse = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        inverse[SBOX[i] as usize] = i as u8;
+        i += 1;
+    }
+    inverse
+}
+
+const SBOX: [u8; 256] = sbox();
+const INVERSE_SBOX: [u8; 256] = inverse_sbox();
+
+// each byte of a column times the coefficients of the matrix, rotated for each row
+fn mix_column(column: u32, coefficients: [u8; 4]) -> u32 {
+    let bytes = column.to_le_bytes();
+    u32::from_le_bytes(std::array::from_fn(|row| (0..4).fold(0, |sum, i| sum ^ gf_multiply(coefficients[(i + 4 - row) % 4], bytes[i]))))
+}
+
+fn mix_columns(value: u64, inverse: bool) -> u64 {
+    let coefficients = match inverse {
+        true => [14, 11, 13, 9],
+        false => [2, 3, 1, 1]
+    };
+    (mix_column((value >> 32) as u32, coefficients) as u64) << 32 | mix_column(value as u32, coefficients) as u64
+}
+
+// ShiftRows and SubBytes, or their inverses, for the first two columns, mixing them too for the
+// middle rounds
+fn round(cpu: &Cpu, word: u32, inverse: bool, mix: bool) -> i64 {
+    let f = instruction::parse_format_r(word);
+    let state = [(cpu.x[f.rs1] as u64).to_le_bytes(), (cpu.x[f.rs2] as u64).to_le_bytes()].concat();
+    let bytes: [u8; 8] = std::array::from_fn(|i| {
+        let (column, row) = (i / 4, i % 4);
+        match inverse {
+            true => INVERSE_SBOX[state[4 * ((column + 4 - row) % 4) + row] as usize],
+            false => SBOX[state[4 * ((column + row) % 4) + row] as usize]
+        }
+    });
+    let value = u64::from_le_bytes(bytes);
+    match mix {
+        true => mix_columns(value, inverse) as i64,
+        false => value as i64
+    }
+}
+
+fn sub_word(value: u32) -> u32 {
+    u32::from_le_bytes(value.to_le_bytes().map(|byte| SBOX[byte as usize]))
+}
+
+pub const AES64ES: Instruction = Instruction {
+    name: "AES64ES",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = round(cpu, word, false, false);
+        Ok(())
+    }
+};
+
+pub const AES64ESM: Instruction = Instruction {
+    name: "AES64ESM",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = round(cpu, word, false, true);
+        Ok(())
+    }
+};
+
+pub const AES64DS: Instruction = Instruction {
+    name: "AES64DS",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = round(cpu, word, true, false);
+        Ok(())
+    }
+};
+
+pub const AES64DSM: Instruction = Instruction {
+    name: "AES64DSM",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = round(cpu, word, true, true);
+        Ok(())
+    }
+};
+
+// InvMixColumns alone, turning an encryption round key into one for the equivalent inverse cipher
+pub const AES64IM: Instruction = Instruction {
+    name: "AES64IM",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = mix_columns(cpu.x[f.rs1] as u64, true) as i64;
+        Ok(())
+    }
+};
+
+// the round number is in rs2's place, with 10 for the step of AES-256 that takes no round constant
+// and anything past it reserved
+pub const AES64KS1I: Instruction = Instruction {
+    name: "AES64KS1I",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let round = (word >> 20) & 0xf;
+        if round > 10 {
+            return Err(Trap { trap_type: TrapType::IllegalInstruction, value: word as u64 });
+        }
+        let high = (cpu.x[f.rs1] as u64 >> 32) as u32;
+        let value = match round {
+            10 => sub_word(high),
+            _ => sub_word(high.rotate_right(8)) ^ [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36][round as usize]
+        };
+        cpu.x[f.rd] = ((value as u64) << 32 | value as u64) as i64;
+        Ok(())
+    }
+};
+
+pub const AES64KS2: Instruction = Instruction {
+    name: "AES64KS2",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        let (rs1, rs2) = (cpu.x[f.rs1] as u64, cpu.x[f.rs2] as u64);
+        let low = (rs1 >> 32) as u32 ^ rs2 as u32;
+        let high = low ^ (rs2 >> 32) as u32;
+        cpu.x[f.rd] = ((high as u64) << 32 | low as u64) as i64;
+        Ok(())
+    }
+};
+
+#[cfg(all(test, feature = "zknd", feature = "zkne"))]
+mod test {
+    use crate::cpu::test_cpu::run;
+    use crate::cpu::{Cpu, TrapType, Xlen};
+
+    #[test]
+    fn aes_rounds_and_key_schedule() {
+        // the first round of the FIPS-197 example: aes64es a2, a0, a1; aes64esm a3, a0, a1;
+        // aes64im a6, a3
+        let cpu = run(&[0x32b50633, 0x36b506b3, 0x30069813], Xlen::Bit64, 0x2be2_f4a0_bee3_3d19, 0x0848_f8e9_2a8d_c69a);
+        assert_eq!([0xae52_b4e0_305d_bfd4u64 as i64, 0x9a19_cbe0_e581_6604u64 as i64, 0xae52_b4e0_305d_bfd4u64 as i64],
+            [cpu.x[12], cpu.x[13], cpu.x[16]]);
+
+        // and back: aes64ds a4, a0, a1; aes64dsm a5, a0, a1
+        let cpu = run(&[0x3ab50733, 0x3eb507b3], Xlen::Bit64, 0xae52_b4e0_305d_bfd4u64 as i64, 0xe598_271e_f111_41b8u64 as i64);
+        assert_eq!([0x2be2_f4a0_bee3_3d19, 0x6e61_9002_4d00_3c08], [cpu.x[14], cpu.x[15]]);
+
+        // the first words of the FIPS-197 key expansion: aes64ks1i a2, a1, 0; aes64ks2 a3, a2, a0
+        let cpu = run(&[0x31059613, 0x7ea606b3], Xlen::Bit64, 0xa6d2_ae28_1615_7e2bu64 as i64, 0x3c4f_cf09_8815_f7ab);
+        assert_eq!([0x01eb_848b_01eb_848b, 0xb12c_5488_17fe_faa0u64 as i64], [cpu.x[12], cpu.x[13]]);
+
+        // aes64ks1i a2, a1, 11
+        let mut memory = 0x31b59613u32.to_le_bytes().to_vec();
+        let trap = Cpu::new().tick(&mut memory).unwrap_err();
+        assert_eq!(TrapType::IllegalInstruction, trap.trap_type);
+        assert!(Cpu::decode_for(0x32b50633, Xlen::Bit32).is_none());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::cpu::instruction;
+use crate::cpu::instruction::Instruction;
+
+// The SHA-256 and SHA-512 sigma and sum functions of Zknh, the SHA-512 ones being the RV64 forms.
+
+fn sha256(value: i64, rotations: [u32; 2], last: u32, shift: bool) -> i64 {
+    let value = value as u32;
+    let last = match shift {
+        true => value >> last,
+        false => value.rotate_right(last)
+    };
+    (value.rotate_right(rotations[0]) ^ value.rotate_right(rotations[1]) ^ last) as i32 as i64
+}
+
+fn sha512(value: i64, rotations: [u32; 2], last: u32, shift: bool) -> i64 {
+    let value = value as u64;
+    let last = match shift {
+        true => value >> last,
+        false => value.rotate_right(last)
+    };
+    (value.rotate_right(rotations[0]) ^ value.rotate_right(rotations[1]) ^ last) as i64
+}
+
+pub const SHA256SIG0: Instruction = Instruction {
+    name: "SHA256SIG0",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = sha256(cpu.x[f.rs1], [7, 18], 3, true);
+        Ok(())
+    }
+};
+
+pub const SHA256SIG1: Instruction = Instruction {
+    name: "SHA256SIG1",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = sha256(cpu.x[f.rs1], [17, 19], 10, true);
+        Ok(())
+    }
+};
+
+pub const SHA256SUM0: Instruction = Instruction {
+    name: "SHA256SUM0",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = sha256(cpu.x[f.rs1], [2, 13], 22, false);
+        Ok(())
+    }
+};
+
+pub const SHA256SUM1: Instruction = Instruction {
+    name: "SHA256SUM1",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = sha256(cpu.x[f.rs1], [6, 11], 25, false);
+        Ok(())
+    }
+};
+
+pub const SHA512SIG0: Instruction = Instruction {
+    name: "SHA512SIG0",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = sha512(cpu.x[f.rs1], [1, 8], 7, true);
+        Ok(())
+    }
+};
+
+pub const SHA512SIG1: Instruction = Instruction {
+    name: "SHA512SIG1",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = sha512(cpu.x[f.rs1], [19, 61], 6, true);
+        Ok(())
+    }
+};
+
+pub const SHA512SUM0: Instruction = Instruction {
+    name: "SHA512SUM0",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = sha512(cpu.x[f.rs1], [28, 34], 39, false);
+        Ok(())
+    }
+};
+
+pub const SHA512SUM1: Instruction = Instruction {
+    name: "SHA512SUM1",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = sha512(cpu.x[f.rs1], [14, 18], 41, false);
+        Ok(())
+    }
+};
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test_cpu::run;
+    use crate::cpu::Xlen;
+
+    #[test]
+    fn sha_sigma_and_sum() {
+        // sha256sum0 a2, a0 through sha512sig1 s3, a0, each writing register 12 plus its number
+        let words = [0x10051613u32, 0x10151693, 0x10251713, 0x10351793, 0x10451813, 0x10551893, 0x10651913, 0x10751993];
+        let cpu = run(&words, Xlen::Bit64, 0x1234_5678_9abc_def0, 0);
+        assert_eq!([0x2250_2030, 0x4216_dcad, -0x3a21_3b34, -0x0b7f_0ec2, 0x7c57_a100_c7ec_1abb, 0x7031_1233_3475_5677,
+            0xf92c_77c6_c4f1_aa1bu64 as i64, 0x0a34_60db_bd43_17ac], cpu.x[12..20]);
+    }
+}
@@ -0,0 +1,73 @@
+use crate::cpu::instruction;
+use crate::cpu::instruction::Instruction;
+
+// The SM4 instructions of Zksed, each putting one byte of rs2 through the S-box and the linear
+// transformation and adding what that gives for its place in the word to rs1. The four of them
+// with bs from 0 to 3 make a round or a step of the key schedule.
+
+const SBOX: [u8; 256] = [
+    0xd6, 0x90, 0xe9, 0xfe, 0xcc, 0xe1, 0x3d, 0xb7, 0x16, 0xb6, 0x14, 0xc2, 0x28, 0xfb, 0x2c, 0x05,
+    0x2b, 0x67, 0x9a, 0x76, 0x2a, 0xbe, 0x04, 0xc3, 0xaa, 0x44, 0x13, 0x26, 0x49, 0x86, 0x06, 0x99,
+    0x9c, 0x42, 0x50, 0xf4, 0x91, 0xef, 0x98, 0x7a, 0x33, 0x54, 0x0b, 0x43, 0xed, 0xcf, 0xac, 0x62,
+    0xe4, 0xb3, 0x1c, 0xa9, 0xc9, 0x08, 0xe8, 0x95, 0x80, 0xdf, 0x94, 0xfa, 0x75, 0x8f, 0x3f, 0xa6,
+    0x47, 0x07, 0xa7, 0xfc, 0xf3, 0x73, 0x17, 0xba, 0x83, 0x59, 0x3c, 0x19, 0xe6, 0x85, 0x4f, 0xa8,
+    0x68, 0x6b, 0x81, 0xb2, 0x71, 0x64, 0xda, 0x8b, 0xf8, 0xeb, 0x0f, 0x4b, 0x70, 0x56, 0x9d, 0x35,
+    0x1e, 0x24, 0x0e, 0x5e, 0x63, 0x58, 0xd1, 0xa2, 0x25, 0x22, 0x7c, 0x3b, 0x01, 0x21, 0x78, 0x87,
+    0xd4, 0x00, 0x46, 0x57, 0x9f, 0xd3, 0x27, 0x52, 0x4c, 0x36, 0x02, 0xe7, 0xa0, 0xc4, 0xc8, 0x9e,
+    0xea, 0xbf, 0x8a, 0xd2, 0x40, 0xc7, 0x38, 0xb5, 0xa3, 0xf7, 0xf2, 0xce, 0xf9, 0x61, 0x15, 0xa1,
+    0xe0, 0xae, 0x5d, 0xa4, 0x9b, 0x34, 0x1a, 0x55, 0xad, 0x93, 0x32, 0x30, 0xf5, 0x8c, 0xb1, 0xe3,
+    0x1d, 0xf6, 0xe2, 0x2e, 0x82, 0x66, 0xca, 0x60, 0xc0, 0x29, 0x23, 0xab, 0x0d, 0x53, 0x4e, 0x6f,
+    0xd5, 0xdb, 0x37, 0x45, 0xde, 0xfd, 0x8e, 0x2f, 0x03, 0xff, 0x6a, 0x72, 0x6d, 0x6c, 0x5b, 0x51,
+    0x8d, 0x1b, 0xaf, 0x92, 0xbb, 0xdd, 0xbc, 0x7f, 0x11, 0xd9, 0x5c, 0x41, 0x1f, 0x10, 0x5a, 0xd8,
+    0x0a, 0xc1, 0x31, 0x88, 0xa5, 0xcd, 0x7b, 0xbd, 0x2d, 0x74, 0xd0, 0x12, 0xb8, 0xe5, 0xb4, 0xb0,
+    0x89, 0x69, 0x97, 0x4a, 0x0c, 0x96, 0x77, 0x7e, 0x65, 0xb9, 0xf1, 0x09, 0xc5, 0x6e, 0xc6, 0x84,
+    0x18, 0xf0, 0x7d, 0xec, 0x3a, 0xdc, 0x4d, 0x20, 0x79, 0xee, 0x5f, 0x3e, 0xd7, 0xcb, 0x39, 0x48
+];
+
+// SM4 has its words big endian, so the rotations of the transformation are done on the bytes
+// swapped around
+fn step(rs1: i64, rs2: i64, word: u32, rotations: &[u32]) -> i64 {
+    let bs = word >> 30;
+    let x = (SBOX[(rs2 as u32 >> (8 * bs)) as u8 as usize] as u32).swap_bytes();
+    let y = rotations.iter().fold(x, |y, rotation| y ^ x.rotate_left(*rotation)).swap_bytes();
+    (y.rotate_left(8 * bs) ^ rs1 as u32) as i32 as i64
+}
+
+pub const SM4ED: Instruction = Instruction {
+    name: "SM4ED",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = step(cpu.x[f.rs1], cpu.x[f.rs2], word, &[2, 10, 18, 24]);
+        Ok(())
+    }
+};
+
+pub const SM4KS: Instruction = Instruction {
+    name: "SM4KS",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = step(cpu.x[f.rs1], cpu.x[f.rs2], word, &[13, 23]);
+        Ok(())
+    }
+};
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::Cpu;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn sm4_rounds_take_a_byte_at_a_time() {
+        // sm4ed a0, a0, a1, 0 to 3; sm4ks a2, a2, a1, 0 to 3
+        let words = [0x30b50533u32, 0x70b50533, 0xb0b50533, 0xf0b50533, 0x34b60633, 0x74b60633, 0xb4b60633, 0xf4b60633];
+        let mut memory = program(&words);
+        let mut cpu = Cpu::new();
+        cpu.x[10] = 0x0123_4567;
+        cpu.x[11] = 0x9abc_def0;
+        cpu.x[12] = 0x89ab_cdef;
+        for _ in 0..words.len() {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!([0x25e4_444a, -0x6420_2c1d], [cpu.x[10], cpu.x[12]]);
+    }
+}
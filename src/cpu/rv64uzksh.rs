@@ -0,0 +1,40 @@
+use crate::cpu::instruction;
+use crate::cpu::instruction::Instruction;
+
+// The permutations of SM3's compression function and message expansion, from Zksh.
+
+fn permute(value: i64, rotations: [u32; 2]) -> i64 {
+    let value = value as u32;
+    (value ^ value.rotate_left(rotations[0]) ^ value.rotate_left(rotations[1])) as i32 as i64
+}
+
+pub const SM3P0: Instruction = Instruction {
+    name: "SM3P0",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = permute(cpu.x[f.rs1], [9, 17]);
+        Ok(())
+    }
+};
+
+pub const SM3P1: Instruction = Instruction {
+    name: "SM3P1",
+    operation: |cpu, _memory, word, _address| {
+        let f = instruction::parse_format_r(word);
+        cpu.x[f.rd] = permute(cpu.x[f.rs1], [15, 23]);
+        Ok(())
+    }
+};
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::test_cpu::run;
+    use crate::cpu::Xlen;
+
+    #[test]
+    fn sm3_permutations() {
+        // sm3p0 a3, a1; sm3p1 a4, a1
+        let cpu = run(&[0x10859693, 0x10959713], Xlen::Bit64, 0, 0x9abc_def0);
+        assert_eq!([0x5ee0_0abc, -0x7276_323f], [cpu.x[13], cpu.x[14]]);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::cpu::{Cpu, Xlen, CSR_CAPACITY, CSR_FCSR_ADDRESS};
+use std::collections::BTreeMap;
+
+// Everything about a paused hart that the guest can tell apart, which is all that has to be
+// kept to carry on later, in another process even. What the host set the hart up with, such as
+// its ecall handlers, clock and engine, is left to whoever restores it to set up again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+    pub pc: usize,
+    pub x: [i64; 32],
+    // the bits of each register, so NaN payloads survive formats that have no NaN
+    pub f: [u64; 32],
+    #[cfg(feature = "q")]
+    pub f_high: [u64; 32],
+    // the vector register file, which also gives VLEN
+    #[cfg(feature = "v")]
+    pub v: Vec<u8>,
+    pub xlen: Xlen,
+    // only the CSRs that are not zero
+    pub csr: BTreeMap<u16, u64>,
+    pub reservation: Option<Reservation>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reservation {
+    pub address: u64,
+    pub value: u64
+}
+
+impl Cpu {
+    pub fn save_state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            x: self.x,
+            f: self.f.map(f64::to_bits),
+            #[cfg(feature = "q")]
+            f_high: self.f_high,
+            #[cfg(feature = "v")]
+            v: self.v.clone(),
+            xlen: self.xlen,
+            // the accrued flags of fcsr are not kept with the rest of it
+            csr: self.csr.iter().enumerate().map(|(address, value)| match address as u16 {
+                CSR_FCSR_ADDRESS => (CSR_FCSR_ADDRESS, self.read_csr(CSR_FCSR_ADDRESS)),
+                address => (address, *value)
+            }).filter(|(_, value)| *value != 0).collect(),
+            reservation: self.is_reservation_set.then_some(Reservation { address: self.reservation, value: self.reservation_value })
+        }
+    }
+
+    // Puts the hart back the way it was when state was saved. Decoded code is thrown away, as
+    // the memory that goes with the state is usually restored alongside it.
+    pub fn load_state(&mut self, state: &CpuState) {
+        self.pc = state.pc;
+        self.x = state.x;
+        self.f = state.f.map(f64::from_bits);
+        #[cfg(feature = "q")]
+        {
+            self.f_high = state.f_high;
+        }
+        #[cfg(feature = "v")]
+        {
+            self.v = state.v.clone();
+        }
+        self.xlen = state.xlen;
+        self.csr = [0; CSR_CAPACITY];
+        for (address, value) in state.csr.iter().filter(|(address, _)| (**address as usize) < CSR_CAPACITY) {
+            self.csr[*address as usize] = *value;
+        }
+        self.write_csr(CSR_FCSR_ADDRESS, state.csr.get(&CSR_FCSR_ADDRESS).copied().unwrap_or(0));
+        self.is_reservation_set = state.reservation.is_some();
+        if let Some(reservation) = state.reservation {
+            self.reservation = reservation.address;
+            self.reservation_value = reservation.value;
+        }
+        self.update_event_counting();
+        self.invalidate_all_caches();
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+    use crate::memory::{CowMemory, Memory, SharedMemory};
+
+    #[test]
+    fn a_guest_can_be_saved_and_resumed_elsewhere() {
+        // addi a0, a0, 1; lui a5, 1; sw a0, 0(a5); fcvt.d.l fa0, a0; j 0
+        let words = [0x00150513u32, 0x000017b7, 0x00a7a023, 0xd2250553, 0xff1ff06f];
+        let bytes = program(&words);
+        let mut memory = CowMemory::from_bytes(&[bytes, vec![0; 0x2000]].concat());
+        let mut cpu = Cpu::new();
+        cpu.run_with_fuel(&mut memory, 12);
+        cpu.f[1] = f64::from_bits(0x7ff8_0000_dead_beef);
+        cpu.write_csr(0x003, 0x42);
+
+        // saved from copy on write memory, restored into shared memory
+        let saved = serde_json::to_string(&(cpu.save_state(), &memory)).unwrap();
+        let (state, mut restored): (CpuState, SharedMemory) = serde_json::from_str(&saved).unwrap();
+        let mut resumed = Cpu::new();
+        // the accrued flags are held by the host while the hart runs, loading puts them back
+        assert_eq!(Some(&0x42), state.csr.get(&0x003));
+        resumed.write_csr(0x001, 0x1f);
+        resumed.load_state(&state);
+        assert_eq!(0x42, resumed.read_csr(0x003));
+
+        assert_eq!(cpu.save_state(), resumed.save_state());
+        assert_eq!(0x7ff8_0000_dead_beef, resumed.f[1].to_bits());
+        assert_eq!(memory.read_u32(0x1000).unwrap(), restored.read_u32(0x1000).unwrap());
+        cpu.run_with_fuel(&mut memory, 10);
+        resumed.run_with_fuel(&mut restored, 10);
+        assert_eq!(cpu.save_state(), resumed.save_state());
+        assert_eq!(memory.read_u32(0x1000).unwrap(), restored.read_u32(0x1000).unwrap());
+    }
+}
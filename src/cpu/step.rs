@@ -0,0 +1,222 @@
+use crate::cpu::{Cpu, Trap};
+use crate::memory::Memory;
+use std::cell::RefCell;
+
+// What one instruction did, for debuggers and tracers that show the effect of each step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepInfo {
+    pub pc: usize,
+    // always the 32 bit form, compressed instructions are expanded before they get here
+    pub word: u32,
+    pub mnemonic: &'static str,
+    // the registers whose value changed, in register order with the integer registers first
+    pub written_registers: Vec<WrittenRegister>,
+    // the loads and stores the instruction made in the order it made them, fetches are left out
+    pub memory_accesses: Vec<MemoryAccess>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrittenRegister {
+    X { index: usize, value: i64 },
+    F { index: usize, value: f64 }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: usize,
+    // in bytes
+    pub size: usize,
+    // what was read or written, zero extended
+    pub value: u64
+}
+
+impl Cpu {
+    // Executes one instruction like tick and reports what it did. A trapping instruction reports
+    // nothing beyond the trap.
+    pub fn step(&mut self, memory: &mut dyn Memory) -> Result<StepInfo, Trap> {
+        let pc = self.pc;
+        let (x, f) = (self.x, self.f);
+        let accesses = RefCell::new(Vec::new());
+        let (word, mnemonic) = self.advance(memory, Some(&accesses))?;
+
+        Ok(StepInfo {
+            pc,
+            word,
+            mnemonic,
+            written_registers: self.written_registers(&x, &f),
+            memory_accesses: accesses.into_inner()
+        })
+    }
+
+    // the registers that no longer hold what they did in x and f
+    pub(crate) fn written_registers(&self, x: &[i64; 32], f: &[f64; 32]) -> Vec<WrittenRegister> {
+        let integer = (0..32).filter(|i| self.x[*i] != x[*i]).map(|index| WrittenRegister::X { index, value: self.x[index] });
+        let float = (0..32).filter(|i| self.f[*i].to_bits() != f[*i].to_bits()).map(|index| WrittenRegister::F { index, value: self.f[index] });
+        integer.chain(float).collect()
+    }
+}
+
+// Passes every access through to the memory underneath and notes it down. Only the execution
+// of the instruction sees it, the fetch goes straight to the memory underneath.
+pub(crate) struct Recording<'a> {
+    pub(crate) inner: &'a mut dyn Memory,
+    pub(crate) accesses: &'a RefCell<Vec<MemoryAccess>>
+}
+
+impl Recording<'_> {
+    fn note<T: Copy + Into<u64>>(&self, kind: AccessKind, address: usize, result: Result<T, Trap>) -> Result<T, Trap> {
+        if let Ok(value) = result {
+            self.accesses.borrow_mut().push(MemoryAccess { kind, address, size: size_of::<T>(), value: value.into() });
+        }
+        result
+    }
+}
+
+impl Memory for Recording<'_> {
+    fn read_i8(&self, address: usize) -> Result<i8, Trap> {
+        self.read_u8(address).map(|value| value as i8)
+    }
+
+    fn read_u8(&self, address: usize) -> Result<u8, Trap> {
+        self.note(AccessKind::Read, address, self.inner.read_u8(address))
+    }
+
+    fn read_i16(&self, address: usize) -> Result<i16, Trap> {
+        self.read_u16(address).map(|value| value as i16)
+    }
+
+    fn read_u16(&self, address: usize) -> Result<u16, Trap> {
+        self.note(AccessKind::Read, address, self.inner.read_u16(address))
+    }
+
+    fn read_i32(&self, address: usize) -> Result<i32, Trap> {
+        self.read_u32(address).map(|value| value as i32)
+    }
+
+    fn read_u32(&self, address: usize) -> Result<u32, Trap> {
+        self.note(AccessKind::Read, address, self.inner.read_u32(address))
+    }
+
+    fn read_i64(&self, address: usize) -> Result<i64, Trap> {
+        self.read_u64(address).map(|value| value as i64)
+    }
+
+    fn read_u64(&self, address: usize) -> Result<u64, Trap> {
+        self.note(AccessKind::Read, address, self.inner.read_u64(address))
+    }
+
+    fn write_u8(&mut self, address: usize, value: u8) -> Result<(), Trap> {
+        self.inner.write_u8(address, value)?;
+        self.note(AccessKind::Write, address, Ok(value)).map(|_| ())
+    }
+
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), Trap> {
+        self.inner.write_u16(address, value)?;
+        self.note(AccessKind::Write, address, Ok(value)).map(|_| ())
+    }
+
+    fn write_u32(&mut self, address: usize, value: u32) -> Result<(), Trap> {
+        self.inner.write_u32(address, value)?;
+        self.note(AccessKind::Write, address, Ok(value)).map(|_| ())
+    }
+
+    fn write_u64(&mut self, address: usize, value: u64) -> Result<(), Trap> {
+        self.inner.write_u64(address, value)?;
+        self.note(AccessKind::Write, address, Ok(value)).map(|_| ())
+    }
+
+    fn fence(&mut self) {
+        self.inner.fence();
+    }
+
+    // the memory underneath may make these atomic, so they have to reach it as they are
+    fn compare_exchange_u8(&mut self, address: usize, current: u8, new: u8) -> Result<Result<u8, u8>, Trap> {
+        let result = self.inner.compare_exchange_u8(address, current, new)?;
+        self.note(AccessKind::Read, address, Ok(result.unwrap_or_else(|old| old)))?;
+        if result.is_ok() {
+            self.note(AccessKind::Write, address, Ok(new))?;
+        }
+        Ok(result)
+    }
+
+    fn compare_exchange_u16(&mut self, address: usize, current: u16, new: u16) -> Result<Result<u16, u16>, Trap> {
+        let result = self.inner.compare_exchange_u16(address, current, new)?;
+        self.note(AccessKind::Read, address, Ok(result.unwrap_or_else(|old| old)))?;
+        if result.is_ok() {
+            self.note(AccessKind::Write, address, Ok(new))?;
+        }
+        Ok(result)
+    }
+
+    fn compare_exchange_u32(&mut self, address: usize, current: u32, new: u32) -> Result<Result<u32, u32>, Trap> {
+        let result = self.inner.compare_exchange_u32(address, current, new)?;
+        self.note(AccessKind::Read, address, Ok(result.unwrap_or_else(|old| old)))?;
+        if result.is_ok() {
+            self.note(AccessKind::Write, address, Ok(new))?;
+        }
+        Ok(result)
+    }
+
+    fn compare_exchange_u64(&mut self, address: usize, current: u64, new: u64) -> Result<Result<u64, u64>, Trap> {
+        let result = self.inner.compare_exchange_u64(address, current, new)?;
+        self.note(AccessKind::Read, address, Ok(result.unwrap_or_else(|old| old)))?;
+        if result.is_ok() {
+            self.note(AccessKind::Write, address, Ok(new))?;
+        }
+        Ok(result)
+    }
+
+    // noted as two doubleword accesses, low half first, as an access holds at most 64 bits
+    fn compare_exchange_u128(&mut self, address: usize, current: u128, new: u128) -> Result<Result<u128, u128>, Trap> {
+        let result = self.inner.compare_exchange_u128(address, current, new)?;
+        let old = result.unwrap_or_else(|old| old);
+        self.note(AccessKind::Read, address, Ok(old as u64))?;
+        self.note(AccessKind::Read, address + 8, Ok((old >> 64) as u64))?;
+        if result.is_ok() {
+            self.note(AccessKind::Write, address, Ok(new as u64))?;
+            self.note(AccessKind::Write, address + 8, Ok((new >> 64) as u64))?;
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(all(test, feature = "d"))]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn step_reports_registers_and_memory() {
+        // addi a0, zero, 0x40; sd a0, 8(a0); ld a1, 8(a0); fcvt.d.l fa0, a0
+        let words = [0x04000513u32, 0x00a53423, 0x00853583, 0xd2250553];
+        let mut memory = program(&words);
+        memory.resize(0x80, 0);
+        let mut cpu = Cpu::new();
+
+        let first = cpu.step(&mut memory).unwrap();
+        assert_eq!((0, 0x04000513, "ADDI"), (first.pc, first.word, first.mnemonic));
+        assert_eq!(vec![WrittenRegister::X { index: 10, value: 0x40 }], first.written_registers);
+        assert!(first.memory_accesses.is_empty());
+
+        let store = cpu.step(&mut memory).unwrap();
+        assert!(store.written_registers.is_empty());
+        assert_eq!(vec![MemoryAccess { kind: AccessKind::Write, address: 0x48, size: 8, value: 0x40 }], store.memory_accesses);
+
+        let load = cpu.step(&mut memory).unwrap();
+        assert_eq!(vec![WrittenRegister::X { index: 11, value: 0x40 }], load.written_registers);
+        assert_eq!(AccessKind::Read, load.memory_accesses[0].kind);
+
+        let convert = cpu.step(&mut memory).unwrap();
+        assert_eq!(vec![WrittenRegister::F { index: 10, value: 64.0 }], convert.written_registers);
+
+        // the zeroed word after the program is not an instruction
+        assert!(cpu.step(&mut memory).is_err());
+    }
+}
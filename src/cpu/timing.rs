@@ -0,0 +1,111 @@
+use crate::cpu::Cpu;
+use std::sync::Arc;
+
+// How many cycles an instruction takes, for the cycle counters. Given the instruction, whether
+// it moved the pc somewhere other than the next instruction and how many cache misses it caused
+// when there is a cache model, see set_cache_model.
+pub trait CostModel: Send + Sync {
+    fn cycles(&self, word: u32, taken: bool, misses: u64) -> u64;
+}
+
+// A cost for each class of instruction, loosely those of a simple in-order core. Change what
+// matters with e.g. ClassCosts { div: 40, ..ClassCosts::default() }
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClassCosts {
+    pub alu: u64,
+    pub mul: u64,
+    pub div: u64,
+    pub load: u64,
+    pub store: u64,
+    pub atomic: u64,
+    pub branch: u64,
+    // on top of branch when a branch is taken
+    pub taken_penalty: u64,
+    pub jump: u64,
+    pub fp: u64,
+    // division and square roots
+    pub fp_div: u64,
+    // csr instructions, fences and ecalls
+    pub system: u64,
+    pub miss_penalty: u64
+}
+
+impl Default for ClassCosts {
+    fn default() -> Self {
+        ClassCosts {
+            alu: 1,
+            mul: 3,
+            div: 20,
+            load: 2,
+            store: 1,
+            atomic: 4,
+            branch: 1,
+            taken_penalty: 2,
+            jump: 2,
+            fp: 4,
+            fp_div: 20,
+            system: 1,
+            miss_penalty: 20
+        }
+    }
+}
+
+impl CostModel for ClassCosts {
+    fn cycles(&self, word: u32, taken: bool, misses: u64) -> u64 {
+        let class = match word & 0x7f {
+            0b0110011 | 0b0111011 if word >> 25 == 1 => match (word >> 12) & 7 {
+                0..=3 => self.mul,
+                _ => self.div
+            },
+            0b0000011 | 0b0000111 => self.load,
+            0b0100011 | 0b0100111 => self.store,
+            0b0101111 => self.atomic,
+            0b1100011 => self.branch + if taken { self.taken_penalty } else { 0 },
+            0b1101111 | 0b1100111 => self.jump,
+            0b1010011 => match word >> 27 {
+                0b00011 | 0b01011 => self.fp_div,
+                _ => self.fp
+            },
+            0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => self.fp,
+            0b1110011 | 0b0001111 => self.system,
+            _ => self.alu
+        };
+        class + misses * self.miss_penalty
+    }
+}
+
+impl Cpu {
+    // Has mcycle, and cycle which reads it, count what model says each instruction costs rather
+    // than stay where the guest or host last set it. None stops the counting.
+    pub fn set_cost_model(&mut self, model: Option<Arc<dyn CostModel>>) {
+        self.cost_model = model;
+        self.update_observed();
+    }
+
+    pub fn cost_model(&self) -> Option<&Arc<dyn CostModel>> {
+        self.cost_model.as_ref()
+    }
+}
+
+#[cfg(all(test, feature = "m"))]
+mod test {
+    use super::*;
+    use crate::cpu::Register;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn cycles_follow_the_cost_of_each_instruction() {
+        // li a0, 3; mul a1, a0, a0; lw a2, 0x80(zero); rdcycle a3
+        let words = [0x00300513u32, 0x02a505b3, 0x08002603, 0xc00026f3];
+        let mut memory = [program(&words), vec![0; 0x80]].concat();
+        let mut cpu = Cpu::new();
+        cpu.set_cost_model(Some(Arc::new(ClassCosts { mul: 5, ..ClassCosts::default() })));
+
+        for _ in 0..4 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!(8, cpu.get_register(Register::A3));
+        assert_eq!(9, cpu.read_csr(0xc00));
+        assert_eq!(9, cpu.read_csr(0xb00));
+    }
+}
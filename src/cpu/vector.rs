@@ -0,0 +1,179 @@
+use crate::cpu::{Cpu, Trap, TrapType, Xlen};
+
+pub const DEFAULT_VLEN: usize = 128;
+
+pub(crate) const CSR_VSTART_ADDRESS: u16 = 0x008;
+pub(crate) const CSR_VXRM_ADDRESS: u16 = 0x00a;
+pub(crate) const CSR_VCSR_ADDRESS: u16 = 0x00f;
+pub(crate) const CSR_VL_ADDRESS: u16 = 0xc20;
+pub(crate) const CSR_VTYPE_ADDRESS: u16 = 0xc21;
+pub(crate) const CSR_VLENB_ADDRESS: u16 = 0xc22;
+
+// what vtype holds once vsetvl has checked it, the element width in bits and the register group
+// size as a power of two, negative for the fractional ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Vtype {
+    pub sew: usize,
+    pub lmul_log2: i32
+}
+
+impl Vtype {
+    // None for vill, reserved bits, element widths past 64 bits and fractional groups too small
+    // to hold a single element
+    pub fn parse(value: u64) -> Option<Vtype> {
+        let vsew = (value >> 3) & 7;
+        let lmul_log2 = match value & 7 {
+            vlmul @ 0..=3 => vlmul as i32,
+            4 => return None,
+            vlmul => vlmul as i32 - 8
+        };
+        match value >> 8 == 0 && vsew <= 3 && vsew as i32 <= lmul_log2 + 3 {
+            true => Some(Vtype { sew: 8 << vsew, lmul_log2 }),
+            false => None
+        }
+    }
+
+    pub fn vlmax(self, vlen: usize) -> usize {
+        match self.lmul_log2 >= 0 {
+            true => (vlen << self.lmul_log2) / self.sew,
+            false => (vlen >> -self.lmul_log2) / self.sew
+        }
+    }
+
+    // the group size for elements eew bits wide, keeping the ratio of width to group size
+    pub fn emul_log2(self, eew: usize) -> i32 {
+        self.lmul_log2 + eew.trailing_zeros() as i32 - self.sew.trailing_zeros() as i32
+    }
+}
+
+pub(crate) fn illegal(word: u32) -> Trap {
+    Trap { trap_type: TrapType::IllegalInstruction, value: word as u64 }
+}
+
+// a group has to be one of the sizes LMUL can take and, when it spans more than one register,
+// start on a multiple of its size
+pub(crate) fn check_group(vreg: usize, emul_log2: i32, word: u32) -> Result<(), Trap> {
+    match (-3..=3).contains(&emul_log2) && (emul_log2 <= 0 || vreg.is_multiple_of(1 << emul_log2)) {
+        true => Ok(()),
+        false => Err(illegal(word))
+    }
+}
+
+pub(crate) fn sign_extend(value: u64, bits: usize) -> i64 {
+    ((value << (64 - bits)) as i64) >> (64 - bits)
+}
+
+pub(crate) fn canonical_nan(width: usize) -> u64 {
+    match width {
+        32 => 0x7fc00000,
+        _ => 0x7ff8000000000000
+    }
+}
+
+pub(crate) fn truncate(value: u64, bits: usize) -> u64 {
+    match bits {
+        64 => value,
+        _ => value & ((1 << bits) - 1)
+    }
+}
+
+impl Cpu {
+    pub fn vlen(&self) -> usize {
+        self.v.len() / 4
+    }
+
+    // VLEN has to be a power of two between 64 and 65536 bits, changing it clears the registers
+    // and leaves vtype illegal until the next vsetvl
+    pub fn set_vlen(&mut self, vlen: usize) {
+        assert!(vlen.is_power_of_two() && (64..=65536).contains(&vlen), "VLEN must be a power of two from 64 to 65536");
+        self.v = vec![0; vlen * 4];
+        self.csr[CSR_VL_ADDRESS as usize] = 0;
+        self.csr[CSR_VTYPE_ADDRESS as usize] = self.vill();
+    }
+
+    // the 32 vector registers one after the other, each VLEN / 8 bytes
+    pub fn vector_registers(&self) -> &[u8] {
+        &self.v
+    }
+
+    pub fn vector_registers_mut(&mut self) -> &mut [u8] {
+        &mut self.v
+    }
+
+    pub(crate) fn vlenb(&self) -> usize {
+        self.v.len() / 32
+    }
+
+    pub(crate) fn vill(&self) -> u64 {
+        match self.xlen {
+            Xlen::Bit32 => 1 << 31,
+            Xlen::Bit64 => 1 << 63
+        }
+    }
+
+    // vsetvli, vsetivli and vsetvl all come here, with the requested vl when there is one
+    pub(crate) fn set_vector_config(&mut self, rd: usize, avl: Option<u64>, vtype: u64) {
+        let vl = match Vtype::parse(vtype) {
+            Some(parsed) => {
+                let vlmax = parsed.vlmax(self.vlen()) as u64;
+                self.csr[CSR_VTYPE_ADDRESS as usize] = vtype;
+                match avl {
+                    Some(avl) => avl.min(vlmax),
+                    None if rd != 0 => vlmax,
+                    // keeping vl, which only changing the ratio of SEW to LMUL could make too big
+                    None => self.csr[CSR_VL_ADDRESS as usize].min(vlmax)
+                }
+            },
+            None => {
+                self.csr[CSR_VTYPE_ADDRESS as usize] = self.vill();
+                0
+            }
+        };
+        self.csr[CSR_VL_ADDRESS as usize] = vl;
+        self.csr[CSR_VSTART_ADDRESS as usize] = 0;
+        if rd != 0 {
+            self.x[rd] = vl as i64;
+        }
+    }
+
+    // vtype, vl and vstart for an instruction working on elements, which is illegal under vill
+    pub(crate) fn vector_config(&self, word: u32) -> Result<(Vtype, usize, usize), Trap> {
+        match Vtype::parse(self.csr[CSR_VTYPE_ADDRESS as usize]) {
+            Some(vtype) => Ok((vtype, self.csr[CSR_VL_ADDRESS as usize] as usize, self.csr[CSR_VSTART_ADDRESS as usize] as usize)),
+            None => Err(illegal(word))
+        }
+    }
+
+    pub(crate) fn set_vstart(&mut self, vstart: usize) {
+        self.csr[CSR_VSTART_ADDRESS as usize] = vstart as u64;
+    }
+
+    // element index of the group starting at vreg, eew bits wide and zero extended
+    pub(crate) fn vector_element(&self, vreg: usize, index: usize, eew: usize) -> u64 {
+        let bytes = eew / 8;
+        let offset = vreg * self.vlenb() + index * bytes;
+        let mut value = [0; 8];
+        value[..bytes].copy_from_slice(&self.v[offset..offset + bytes]);
+        u64::from_le_bytes(value)
+    }
+
+    pub(crate) fn set_vector_element(&mut self, vreg: usize, index: usize, eew: usize, value: u64) {
+        let bytes = eew / 8;
+        let offset = vreg * self.vlenb() + index * bytes;
+        self.v[offset..offset + bytes].copy_from_slice(&value.to_le_bytes()[..bytes]);
+    }
+
+    pub(crate) fn mask_bit(&self, vreg: usize, index: usize) -> bool {
+        (self.v[vreg * self.vlenb() + index / 8] >> (index % 8)) & 1 == 1
+    }
+
+    pub(crate) fn set_mask_bit(&mut self, vreg: usize, index: usize, bit: bool) {
+        let offset = vreg * self.vlenb() + index / 8;
+        self.v[offset] = (self.v[offset] & !(1 << (index % 8))) | ((bit as u8) << (index % 8));
+    }
+
+    // whether an element takes part, which is all of them unless vm is clear and v0 says otherwise
+    pub(crate) fn element_active(&self, word: u32, index: usize) -> bool {
+        (word >> 25) & 1 == 1 || self.mask_bit(0, index)
+    }
+}
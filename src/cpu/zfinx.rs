@@ -0,0 +1,96 @@
+use crate::cpu::{Cpu, Memory, Trap, TrapType, Xlen};
+use crate::cpu::extensions::required;
+use crate::cpu::instruction::Instruction;
+
+// Zfinx and Zdinx: the F and D instructions take their operands from the x registers and there
+// is no f register file to move values in and out of. The instructions themselves are the same
+// ones as usual, run with the f registers standing in for the x ones and whatever they write
+// copied back. A single is sign extended from 32 bits rather than NaN boxed, and in RV32 a double
+// lives in an even/odd pair of registers, low half first, with x0 reading as a pair of zeros.
+impl Cpu {
+    pub(crate) fn execute_in_x(&mut self, instruction: &Instruction, memory: &mut dyn Memory, word: u32, address: usize) -> Result<(), Trap> {
+        let illegal = Trap { trap_type: TrapType::IllegalInstruction, value: word as u64 };
+        // there is nothing like Zfinx for quads
+        if required(word) == Some('Q') {
+            return Err(illegal);
+        }
+        let rd = ((word >> 7) & 0x1f) as usize;
+        // None when rd is an x register anyway, otherwise whether it takes a double
+        let destination = match word & 0x7f {
+            // the loads, stores and moves between register files have nothing left to do
+            0b0000111 | 0b0100111 => return Err(illegal),
+            0b1010011 => match word >> 25 {
+                0b1110000 | 0b1110001 | 0b1111000 | 0b1111001 | 0b1011001 if (word >> 12) & 7 == 0 => return Err(illegal),
+                // comparisons, fclass and conversions to integers
+                funct7 if matches!(funct7 >> 2, 0b10100 | 0b11000 | 0b11100) => None,
+                funct7 => Some(funct7 & 3 == 1)
+            },
+            _ => Some((word >> 25) & 3 == 1)
+        };
+        let pairs = self.xlen == Xlen::Bit32;
+        if pairs && destination == Some(true) && rd % 2 == 1 {
+            return Err(illegal);
+        }
+
+        let own = self.f;
+        for i in 0..32 {
+            let low = self.x[i] as u32 as u64;
+            let bits = match pairs && i % 2 == 0 {
+                true if i == 0 => 0,
+                true => (self.x[i + 1] as u64) << 32 | low,
+                false => self.x[i] as u64
+            };
+            self.f[i] = f64::from_bits(bits);
+        }
+        let result = (instruction.operation)(self, memory, word, address);
+        let written = self.f[rd].to_bits();
+        self.f = own;
+
+        match destination {
+            Some(true) if pairs && rd != 0 => {
+                self.x[rd] = written as i32 as i64;
+                self.x[rd + 1] = (written >> 32) as i32 as i64;
+            },
+            Some(true) if pairs => {},
+            Some(true) => self.x[rd] = written as i64,
+            Some(false) => self.x[rd] = written as i32 as i64,
+            None => {}
+        }
+        result
+    }
+}
+
+#[cfg(all(test, feature = "d"))]
+mod test {
+    use crate::cpu::test_cpu::program;
+    use crate::cpu::{Cpu, TrapType, Xlen};
+
+    #[test]
+    fn fp_instructions_use_the_x_registers() {
+        // fadd.s a0, a1, a2; fadd.d a3, a4, a5; flt.d a6, a4, a5; fcvt.d.s a7, a0; flw fa0, 0(zero)
+        let words = [0x00c5f553u32, 0x02f776d3, 0xa2f71853, 0x420508d3, 0x00002507];
+        let mut memory = program(&words);
+        let mut cpu = Cpu::builder().zfinx(true).build();
+        cpu.x[11..13].copy_from_slice(&[1.5f32.to_bits() as i64, (-4.0f32).to_bits() as i64]);
+        cpu.x[14..16].copy_from_slice(&[2.0f64.to_bits() as i64, 0.25f64.to_bits() as i64]);
+        for _ in 0..4 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        // the single is sign extended, not NaN boxed
+        assert_eq!((-2.5f32).to_bits() as i32 as i64, cpu.x[10]);
+        assert_eq!([2.25f64.to_bits() as i64, 0, (-2.5f64).to_bits() as i64], [cpu.x[13], cpu.x[16], cpu.x[17]]);
+        assert_eq!([0; 32], cpu.f.map(f64::to_bits));
+        assert_eq!(TrapType::IllegalInstruction, cpu.tick(&mut memory).unwrap_err().trap_type);
+
+        // doubles take a pair of registers in RV32, and the pair must start on an even one
+        // fadd.d a2, a4, a6; fadd.d a3, a4, a6
+        let words = [0x03077653u32, 0x030776d3];
+        let mut memory = program(&words);
+        let mut cpu = Cpu::builder().xlen(Xlen::Bit32).zfinx(true).build();
+        let (a, b) = (1.0f64.to_bits(), 0.5f64.to_bits());
+        cpu.x[14..18].copy_from_slice(&[a as u32 as i32 as i64, (a >> 32) as i32 as i64, b as u32 as i32 as i64, (b >> 32) as i32 as i64]);
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(1.5f64.to_bits(), (cpu.x[13] as u64) << 32 | cpu.x[12] as u32 as u64);
+        assert!(cpu.tick(&mut memory).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::cpu::{AccessKind, Cpu, CpuState, MemoryAccess, StepInfo, Trap};
+use crate::memory::Memory;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+// One side of a lockstep run, anything that executes a guest an instruction at a time. Besides
+// an Emulated hart this can be another simulator driven from outside, so long as it can report
+// its state in the same terms.
+pub trait Oracle {
+    fn state(&self) -> CpuState;
+    fn step(&mut self) -> Result<StepInfo, Trap>;
+}
+
+const CSR_FCSR_ADDRESS: u16 = 0x003;
+
+// A hart of this emulator along with its memory.
+pub struct Emulated<M: Memory> {
+    pub cpu: Cpu,
+    pub memory: M,
+    // The accrued flags are kept by the host, which both sides share, so each keeps its own
+    // between steps.
+    fcsr: u64
+}
+
+impl<M: Memory> Emulated<M> {
+    pub fn new(cpu: Cpu, memory: M) -> Self {
+        let fcsr = cpu.read_csr(CSR_FCSR_ADDRESS);
+        Emulated { cpu, memory, fcsr }
+    }
+}
+
+impl<M: Memory> Oracle for Emulated<M> {
+    fn state(&self) -> CpuState {
+        let mut state = self.cpu.save_state();
+        match self.fcsr {
+            0 => state.csr.remove(&CSR_FCSR_ADDRESS),
+            fcsr => state.csr.insert(CSR_FCSR_ADDRESS, fcsr)
+        };
+        state
+    }
+
+    fn step(&mut self) -> Result<StepInfo, Trap> {
+        self.cpu.write_csr(CSR_FCSR_ADDRESS, self.fcsr);
+        let result = self.cpu.step(&mut self.memory);
+        self.fcsr = self.cpu.read_csr(CSR_FCSR_ADDRESS);
+        result
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mismatch {
+    Pc(usize, usize),
+    // one side trapped and the other did not, or they trapped differently
+    Trap(Option<(String, u64)>, Option<(String, u64)>),
+    Register { index: usize, a: i64, b: i64 },
+    // the bits of the registers, so NaNs compare
+    FpRegister { index: usize, a: u64, b: u64 },
+    Csr { address: u16, a: u64, b: u64 },
+    // the stores the instruction made, in order
+    Writes(Vec<MemoryAccess>, Vec<MemoryAccess>)
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Pc(a, b) => write!(f, "pc differs: {:#x} vs {:#x}", a, b),
+            Mismatch::Trap(a, b) => write!(f, "trap differs: {:?} vs {:?}", a, b),
+            Mismatch::Register { index, a, b } => write!(f, "x{} differs: {:#x} vs {:#x}", index, a, b),
+            Mismatch::FpRegister { index, a, b } => write!(f, "f{} differs: {:#x} vs {:#x}", index, a, b),
+            Mismatch::Csr { address, a, b } => write!(f, "csr {:#x} differs: {:#x} vs {:#x}", address, a, b),
+            Mismatch::Writes(a, b) => write!(f, "writes differ: {:x?} vs {:x?}", a, b)
+        }
+    }
+}
+
+// Where two runs first went apart: the instruction, counted from zero, and the pc it was at on
+// the first side, along with everything that differed once it had executed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub step: u64,
+    pub pc: usize,
+    pub mismatches: Vec<Mismatch>
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "diverged at instruction {} (pc {:#x})", self.step, self.pc)?;
+        for mismatch in &self.mismatches {
+            writeln!(f, "  {}", mismatch)?;
+        }
+        Ok(())
+    }
+}
+
+// Runs two guests side by side, comparing them after every instruction, e.g.
+// Lockstep::new().ignore_csr(0xc01).run(&mut a, &mut b, 1_000_000)
+#[derive(Clone, Debug, Default)]
+pub struct Lockstep {
+    ignored_csrs: Vec<u16>
+}
+
+impl Lockstep {
+    pub fn new() -> Self {
+        Lockstep::default()
+    }
+
+    // for CSRs the two sides are not expected to agree on, such as a clock one of them keeps
+    pub fn ignore_csr(mut self, address: u16) -> Self {
+        self.ignored_csrs.push(address);
+        self
+    }
+
+    // Steps both until they diverge, the same trap stops them both or steps instructions have
+    // been executed. Returns how many they executed in agreement.
+    pub fn run(&self, a: &mut dyn Oracle, b: &mut dyn Oracle, steps: u64) -> Result<u64, Divergence> {
+        let (mut state_a, mut state_b) = (a.state(), b.state());
+        if state_a.pc != state_b.pc {
+            return Err(Divergence { step: 0, pc: state_a.pc, mismatches: vec![Mismatch::Pc(state_a.pc, state_b.pc)] });
+        }
+        for step in 0..steps {
+            let pc = state_a.pc;
+            let (result_a, result_b) = (a.step(), b.step());
+            (state_a, state_b) = (a.state(), b.state());
+
+            let mut mismatches = Vec::new();
+            let (trap_a, trap_b) = (result_a.as_ref().err().map(describe), result_b.as_ref().err().map(describe));
+            if trap_a != trap_b {
+                mismatches.push(Mismatch::Trap(trap_a.clone(), trap_b));
+            }
+            if let (Ok(step_a), Ok(step_b)) = (&result_a, &result_b) {
+                let (writes_a, writes_b) = (writes(step_a), writes(step_b));
+                if writes_a != writes_b {
+                    mismatches.push(Mismatch::Writes(writes_a, writes_b));
+                }
+            }
+            self.compare(&state_a, &state_b, &mut mismatches);
+
+            if !mismatches.is_empty() {
+                return Err(Divergence { step, pc, mismatches });
+            }
+            if trap_a.is_some() {
+                return Ok(step + 1);
+            }
+        }
+        Ok(steps)
+    }
+
+    fn compare(&self, a: &CpuState, b: &CpuState, mismatches: &mut Vec<Mismatch>) {
+        if a.pc != b.pc {
+            mismatches.push(Mismatch::Pc(a.pc, b.pc));
+        }
+        for index in (1..32).filter(|i| a.x[*i] != b.x[*i]) {
+            mismatches.push(Mismatch::Register { index, a: a.x[index], b: b.x[index] });
+        }
+        for index in (0..32).filter(|i| a.f[*i] != b.f[*i]) {
+            mismatches.push(Mismatch::FpRegister { index, a: a.f[index], b: b.f[index] });
+        }
+        // the states only list the CSRs that are not zero
+        let mut addresses: Vec<u16> = a.csr.keys().chain(b.csr.keys()).copied().filter(|address| !self.ignored_csrs.contains(address)).collect();
+        addresses.sort_unstable();
+        addresses.dedup();
+        for address in addresses {
+            let (value_a, value_b) = (a.csr.get(&address).copied().unwrap_or(0), b.csr.get(&address).copied().unwrap_or(0));
+            if value_a != value_b {
+                mismatches.push(Mismatch::Csr { address, a: value_a, b: value_b });
+            }
+        }
+    }
+}
+
+fn describe(trap: &Trap) -> (String, u64) {
+    (format!("{:?}", trap.trap_type), trap.value)
+}
+
+fn writes(step: &StepInfo) -> Vec<MemoryAccess> {
+    step.memory_accesses.iter().filter(|access| access.kind == AccessKind::Write).copied().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn the_first_divergence_is_reported() {
+        // addi a0, zero, 5; lw a1, 0x80(zero); sw a1, 0x84(zero)
+        let words = [0x00500513u32, 0x08002583, 0x08b02223];
+        let program = [program(&words), vec![0; 0x80]].concat();
+        let mut a = Emulated::new(Cpu::new(), program.clone());
+        let mut b = Emulated::new(Cpu::new(), program.clone());
+        assert_eq!(Ok(3), Lockstep::new().run(&mut a, &mut b, 3));
+
+        let mut a = Emulated::new(Cpu::new(), program.clone());
+        let mut b = Emulated::new(Cpu::new(), program);
+        b.memory[0x80] = 7;
+        let divergence = Lockstep::new().run(&mut a, &mut b, 3).unwrap_err();
+        assert_eq!(Divergence { step: 1, pc: 4, mismatches: vec![Mismatch::Register { index: 11, a: 0, b: 7 }] }, divergence);
+        assert_eq!("diverged at instruction 1 (pc 0x4)\n  x11 differs: 0x0 vs 0x7\n", divergence.to_string());
+    }
+
+    #[test]
+    #[cfg(feature = "f")]
+    fn each_side_accrues_its_own_flags() {
+        // csrwi fflags, 1 on one side and nop on the other
+        let a_program: Vec<u8> = [0x0010d073u32.to_le_bytes().to_vec(), vec![0; 0x80]].concat();
+        let b_program: Vec<u8> = [0x00000013u32.to_le_bytes().to_vec(), vec![0; 0x80]].concat();
+        let mut a = Emulated::new(Cpu::new(), a_program);
+        let mut b = Emulated::new(Cpu::new(), b_program);
+        let divergence = Lockstep::new().run(&mut a, &mut b, 1).unwrap_err();
+        assert_eq!(vec![Mismatch::Csr { address: CSR_FCSR_ADDRESS, a: 1, b: 0 }], divergence.mismatches);
+    }
+}
@@ -0,0 +1,700 @@
+use crate::cpu::{Cpu, Trap, TrapType, Xlen};
+use crate::cpu::instruction::{parse_format_b, parse_format_csr, parse_format_i, parse_format_j, parse_format_r, parse_format_r2, parse_format_s, parse_format_u};
+use crate::memory::Memory;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6"
+];
+
+const FP_REGISTER_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2", "fa3", "fa4", "fa5",
+    "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9", "fs10", "fs11", "ft8", "ft9", "ft10", "ft11"
+];
+
+const CSR_CYCLE: u16 = 0xc00;
+const CSR_TIME: u16 = 0xc01;
+const CSR_INSTRET: u16 = 0xc02;
+
+// bit manipulation ops with a single source, the encoding uses the other one to tell them apart
+const UNARY: [&str; 25] = ["clz", "clzw", "ctz", "ctzw", "cpop", "cpopw", "sext.b", "sext.h", "zext.h", "orc.b", "rev8", "brev8", "zip", "unzip",
+    "aes64im", "sha256sig0", "sha256sig1", "sha256sum0", "sha256sum1", "sha512sig0", "sha512sig1", "sha512sum0", "sha512sum1", "sm3p0", "sm3p1"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Register(usize),
+    FpRegister(usize),
+    Immediate(i64),
+    Memory { base: usize, offset: i64 },
+    // branch and jump targets are already resolved to an absolute address
+    Target { address: usize, symbol: Option<String> },
+    Csr(u16),
+    // one side of a FENCE, bits are i, o, r, w from high to low
+    Fence(u8),
+    // the index FLI takes, shown as the constant it loads
+    FpConstant(u8),
+    VectorRegister(usize),
+    // v0 as the mask of an instruction with vm clear
+    VectorMask,
+    // the vtype immediate of vsetvli and vsetivli
+    VectorType(u32)
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Register(r) => write!(f, "{}", REGISTER_NAMES[*r]),
+            Operand::FpRegister(r) => write!(f, "{}", FP_REGISTER_NAMES[*r]),
+            Operand::Immediate(value) => write!(f, "{}", value),
+            Operand::Memory { base, offset } => write!(f, "{}({})", offset, REGISTER_NAMES[*base]),
+            Operand::Target { address, symbol: None } => write!(f, "{:#x}", address),
+            Operand::Target { address, symbol: Some(symbol) } => write!(f, "{:#x} <{}>", address, symbol),
+            Operand::Csr(csr) => match csr_name(*csr) {
+                Some(name) => write!(f, "{}", name),
+                None => write!(f, "{:#x}", csr)
+            },
+            Operand::Fence(set) => {
+                for (bit, c) in ['i', 'o', 'r', 'w'].iter().enumerate() {
+                    if set & (8 >> bit) != 0 {
+                        write!(f, "{}", c)?;
+                    }
+                }
+                Ok(())
+            },
+            Operand::FpConstant(index) => write!(f, "{}", FLI_CONSTANTS[*index as usize & 31]),
+            Operand::VectorRegister(r) => write!(f, "v{}", r),
+            Operand::VectorMask => write!(f, "v0.t"),
+            Operand::VectorType(vtype) => {
+                let (vsew, vlmul) = ((vtype >> 3) & 7, vtype & 7);
+                if vtype >> 8 != 0 || vsew > 3 || vlmul == 4 {
+                    return write!(f, "{}", vtype);
+                }
+                let lmul = match vlmul {
+                    0..=3 => format!("m{}", 1 << vlmul),
+                    _ => format!("mf{}", 1 << (8 - vlmul))
+                };
+                let tail = if vtype & 0x40 != 0 { "ta" } else { "tu" };
+                let mask = if vtype & 0x80 != 0 { "ma" } else { "mu" };
+                write!(f, "e{}, {}, {}, {}", 8 << vsew, lmul, tail, mask)
+            }
+        }
+    }
+}
+
+const FLI_CONSTANTS: [&str; 32] = ["-1.0", "min", "1.52587890625e-05", "3.0517578125e-05", "0.00390625", "0.0078125", "0.0625",
+    "0.125", "0.25", "0.3125", "0.375", "0.4375", "0.5", "0.625", "0.75", "0.875", "1.0", "1.25", "1.5", "1.75", "2.0", "2.5",
+    "3.0", "4.0", "8.0", "16.0", "128.0", "256.0", "32768.0", "65536.0", "inf", "nan"];
+
+fn csr_name(csr: u16) -> Option<&'static str> {
+    match csr {
+        0x001 => Some("fflags"),
+        0x002 => Some("frm"),
+        0x003 => Some("fcsr"),
+        0x008 => Some("vstart"),
+        0x009 => Some("vxsat"),
+        0x00a => Some("vxrm"),
+        0x00f => Some("vcsr"),
+        0xc20 => Some("vl"),
+        0xc21 => Some("vtype"),
+        0xc22 => Some("vlenb"),
+        CSR_CYCLE => Some("cycle"),
+        CSR_TIME => Some("time"),
+        CSR_INSTRET => Some("instret"),
+        0xc80 => Some("cycleh"),
+        0xc81 => Some("timeh"),
+        0xc82 => Some("instreth"),
+        _ => None
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Disassembly {
+    pub address: usize,
+    // bytes covered, a folded pair such as `call` covers both instructions
+    pub length: usize,
+    pub mnemonic: String,
+    pub operands: Vec<Operand>,
+    // true when the mnemonic is an assembler pseudo-instruction rather than the encoded one
+    pub pseudo: bool
+}
+
+impl Display for Disassembly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Disassembler {
+    raw: bool,
+    xlen: Xlen,
+    symbols: BTreeMap<usize, String>
+}
+
+impl Disassembler {
+    pub fn new() -> Self {
+        Disassembler::default()
+    }
+
+    // in raw mode every instruction is shown exactly as encoded, no pseudo-ops and no folding
+    pub fn raw(mut self, raw: bool) -> Self {
+        self.raw = raw;
+        self
+    }
+
+    // which way compressed instructions are read, RV64 unless told otherwise
+    pub fn xlen(mut self, xlen: Xlen) -> Self {
+        self.xlen = xlen;
+        self
+    }
+
+    pub fn add_symbol(&mut self, address: usize, name: &str) {
+        self.symbols.insert(address, name.to_string());
+    }
+
+    // disassemble a single instruction word, compressed instructions are recognised from the low bits
+    pub fn decode(&self, word: u32, address: usize) -> Option<Disassembly> {
+        let decoded = match word & 3 {
+            3 => self.decode_raw(word, address, 4),
+            _ => self.decode_raw(Cpu::uncompress_for(word & 0xffff, self.xlen), address, 2)
+        }?;
+
+        Some(if self.raw { decoded } else { pseudo(decoded) })
+    }
+
+    pub fn disassemble(&self, memory: &dyn Memory, address: usize) -> Result<Disassembly, Trap> {
+        let first = self.decode_at(memory, address)?;
+        if self.raw {
+            return Ok(first);
+        }
+
+        if let Ok(second) = self.decode_at(memory, address + first.length) {
+            if let Some(folded) = self.fold(&first, &second) {
+                return Ok(folded);
+            }
+        }
+
+        Ok(pseudo(first))
+    }
+
+    // stops at the end of the range or the first word that does not decode
+    pub fn disassemble_range(&self, memory: &dyn Memory, range: Range<usize>) -> Vec<Disassembly> {
+        let mut result = Vec::new();
+        let mut address = range.start;
+        while address < range.end {
+            match self.disassemble(memory, address) {
+                Ok(disassembly) => {
+                    address += disassembly.length;
+                    result.push(disassembly);
+                },
+                Err(_) => break
+            }
+        }
+
+        result
+    }
+
+    fn decode_at(&self, memory: &dyn Memory, address: usize) -> Result<Disassembly, Trap> {
+        let low = memory.read_u16(address)? as u32;
+        let (word, length) = match low & 3 {
+            3 => (low | (memory.read_u16(address + 2)? as u32) << 16, 4),
+            _ => (Cpu::uncompress_for(low, self.xlen), 2)
+        };
+
+        self.decode_raw(word, address, length).ok_or(Trap {
+            trap_type: TrapType::IllegalInstruction,
+            value: word as u64
+        })
+    }
+
+    fn target(&self, address: usize) -> Operand {
+        let symbol = self.symbols.range(..=address).next_back().map(|(start, name)| {
+            match address - start {
+                0 => name.clone(),
+                offset => format!("{}+{:#x}", name, offset)
+            }
+        });
+
+        Operand::Target { address, symbol }
+    }
+
+    fn decode_raw(&self, word: u32, address: usize, length: usize) -> Option<Disassembly> {
+        use Operand::*;
+
+        let instruction = Cpu::decode_for(word, self.xlen)?;
+        let mut mnemonic = instruction.name.to_lowercase();
+        let mut operands = match word & 0x7f {
+            0b0110111 | 0b0010111 => {
+                let f = parse_format_u(word);
+                vec![Register(f.rd), Immediate(((f.imm >> 12) & 0xfffff) as i64)]
+            },
+            0b1101111 => {
+                let f = parse_format_j(word);
+                vec![Register(f.rd), self.target(address.wrapping_add(f.imm as usize))]
+            },
+            0b1100111 => {
+                let f = parse_format_i(word);
+                vec![Register(f.rd), Memory { base: f.rs1, offset: f.imm }]
+            },
+            0b1100011 => {
+                let f = parse_format_b(word);
+                vec![Register(f.rs1), Register(f.rs2), self.target(address.wrapping_add(f.imm as usize))]
+            },
+            // the loads and stores with a width that is not a float one are vector ones
+            0b0000111 | 0b0100111 if matches!((word >> 12) & 7, 0 | 5..=7) => vector_memory_operands(&mut mnemonic, word),
+            0b1010111 => vector_operands(&mut mnemonic, word),
+            0b0000011 | 0b0000111 => {
+                let f = parse_format_i(word);
+                let rd = if word & 0x7f == 0b0000111 { FpRegister(f.rd) } else { Register(f.rd) };
+                vec![rd, Memory { base: f.rs1, offset: f.imm }]
+            },
+            0b0100011 | 0b0100111 => {
+                let f = parse_format_s(word);
+                let rs2 = if word & 0x7f == 0b0100111 { FpRegister(f.rs2) } else { Register(f.rs2) };
+                vec![rs2, Memory { base: f.rs1, offset: f.imm }]
+            },
+            0b0010011 | 0b0011011 => {
+                let f = parse_format_i(word);
+                let imm = match (word >> 12) & 3 {
+                    // aes64ks1i has its round number where a shift amount would be
+                    1 if word & 0x7f == 0b0010011 && word >> 25 == 0b0011000 => ((word >> 20) & 0xf) as i64,
+                    // shifts only use the low bits of the immediate as the shift amount, slli.uw
+                    // is the one word sized shift with 6 of them
+                    1 => ((word >> 20) & if word & 0x7f == 0b0011011 && word >> 26 == 0 { 0x1f } else { 0x3f }) as i64,
+                    _ => f.imm
+                };
+                vec![Register(f.rd), Register(f.rs1), Immediate(imm)]
+            },
+            0b0110011 | 0b0111011 => {
+                let f = parse_format_r(word);
+                match mnemonic.as_str() {
+                    // the byte of rs2 the SM4 instructions take
+                    "sm4ed" | "sm4ks" => vec![Register(f.rd), Register(f.rs1), Register(f.rs2), Immediate((word >> 30) as i64)],
+                    _ => vec![Register(f.rd), Register(f.rs1), Register(f.rs2)]
+                }
+            },
+            0b1010011 => {
+                let f = parse_format_r(word);
+                match word >> 25 {
+                    0b1111000 if (word >> 12) & 7 == 1 => mnemonic = "fclass.s".to_string(),
+                    0b1110001 if (word >> 12) & 7 == 1 => mnemonic = "fclass.d".to_string(),
+                    _ => {}
+                }
+                match mnemonic.as_str() {
+                    "fli.s" | "fli.d" => vec![FpRegister(f.rd), FpConstant(f.rs1 as u8)],
+                    "fmvp.d.x" => vec![FpRegister(f.rd), Register(f.rs1), Register(f.rs2)],
+                    _ => fp_operands(&mnemonic, f.rd, f.rs1, f.rs2)
+                }
+            },
+            0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => {
+                let f = parse_format_r2(word);
+                vec![FpRegister(f.rd), FpRegister(f.rs1), FpRegister(f.rs2), FpRegister(f.rs3)]
+            },
+            0b0001111 => match (word >> 12) & 7 {
+                0b000 if mnemonic == "pause" => vec![],
+                0b000 => vec![Fence(((word >> 24) & 0xf) as u8), Fence(((word >> 20) & 0xf) as u8)],
+                _ => vec![]
+            },
+            0b0101111 => {
+                let f = parse_format_r(word);
+                match (word >> 25) & 3 {
+                    0b11 => mnemonic.push_str(".aqrl"),
+                    0b10 => mnemonic.push_str(".aq"),
+                    0b01 => mnemonic.push_str(".rl"),
+                    _ => {}
+                }
+                match word >> 27 {
+                    0b00010 => vec![Register(f.rd), Memory { base: f.rs1, offset: 0 }],
+                    _ => vec![Register(f.rd), Register(f.rs2), Memory { base: f.rs1, offset: 0 }]
+                }
+            },
+            0b1110011 => match (word >> 12) & 7 {
+                0b000 => vec![],
+                0b100 => {
+                    // the number of the may-be-operation is spread across the word
+                    let f = parse_format_r(word);
+                    let high = ((word >> 30) & 1) << 2 | (word >> 26) & 3;
+                    match mnemonic.as_str() {
+                        "mop.r" => {
+                            mnemonic = format!("mop.r.{}", high << 2 | (word >> 20) & 3);
+                            vec![Register(f.rd), Register(f.rs1)]
+                        },
+                        _ => {
+                            mnemonic = format!("mop.rr.{}", high);
+                            vec![Register(f.rd), Register(f.rs1), Register(f.rs2)]
+                        }
+                    }
+                },
+                funct3 => {
+                    let f = parse_format_csr(word);
+                    let source = if funct3 & 4 != 0 { Immediate(f.rs as i64) } else { Register(f.rs) };
+                    vec![Register(f.rd), Csr(f.csr), source]
+                }
+            },
+            // the immediate shifts of P have the amount where rs2 would be
+            0b1110111 => {
+                let f = parse_format_r(word);
+                match word >> 28 {
+                    0b0111 => vec![Register(f.rd), Register(f.rs1), Immediate((f.rs2 & if (word >> 27) & 1 == 1 { 7 } else { 15 }) as i64)],
+                    _ => vec![Register(f.rd), Register(f.rs1), Register(f.rs2)]
+                }
+            },
+            _ => vec![]
+        };
+        if UNARY.contains(&mnemonic.as_str()) {
+            operands.truncate(2);
+        }
+
+        Some(Disassembly { address, length, mnemonic, operands, pseudo: false })
+    }
+
+    fn fold(&self, first: &Disassembly, second: &Disassembly) -> Option<Disassembly> {
+        use Operand::*;
+
+        let (mnemonic, operands) = match (first.mnemonic.as_str(), first.operands.as_slice(), second.mnemonic.as_str(), second.operands.as_slice()) {
+            ("auipc", [Register(rd), Immediate(hi)], "jalr", [Register(link), Memory { base, offset }]) if base == rd => {
+                let target = self.target(first.address.wrapping_add(upper(*hi).wrapping_add(*offset) as usize));
+                match link {
+                    1 => ("call", vec![target]),
+                    0 => ("tail", vec![target]),
+                    _ => return None
+                }
+            },
+            ("auipc", [Register(rd), Immediate(hi)], "addi", [Register(rd2), Register(rs), Immediate(lo)]) if *rd != 0 && rd == rd2 && rd == rs => {
+                ("la", vec![Register(*rd), self.target(first.address.wrapping_add(upper(*hi).wrapping_add(*lo) as usize))])
+            },
+            ("lui", [Register(rd), Immediate(hi)], "addi", [Register(rd2), Register(rs), Immediate(lo)]) if *rd != 0 && rd == rd2 && rd == rs => {
+                ("li", vec![Register(*rd), Immediate(upper(*hi).wrapping_add(*lo))])
+            },
+            ("lui", [Register(rd), Immediate(hi)], "addiw", [Register(rd2), Register(rs), Immediate(lo)]) if *rd != 0 && rd == rd2 && rd == rs => {
+                ("li", vec![Register(*rd), Immediate(upper(*hi).wrapping_add(*lo) as i32 as i64)])
+            },
+            _ => return None
+        };
+
+        Some(Disassembly {
+            address: first.address,
+            length: first.length + second.length,
+            mnemonic: mnemonic.to_string(),
+            operands,
+            pseudo: true
+        })
+    }
+}
+
+// the value an LUI or AUIPC immediate contributes, sign extended the way rv64 does it
+fn upper(imm: i64) -> i64 {
+    ((imm << 12) as i32) as i64
+}
+
+fn fp_operands(mnemonic: &str, rd: usize, rs1: usize, rs2: usize) -> Vec<Operand> {
+    use Operand::*;
+
+    let integer_rd = ["feq", "flt", "fle", "fclass", "fmv.x", "fmvh.x", "fcvt.w", "fcvt.l", "fcvtmod"].iter().any(|p| mnemonic.starts_with(p));
+    let integer_rs1 = mnemonic.ends_with(".x") ||
+        (mnemonic.starts_with("fcvt.") && [".w", ".wu", ".l", ".lu"].iter().any(|s| mnemonic.ends_with(s)));
+    let unary = ["fsqrt", "fcvt", "fmv", "fclass", "fround"].iter().any(|p| mnemonic.starts_with(p));
+
+    let mut operands = vec![
+        if integer_rd { Register(rd) } else { FpRegister(rd) },
+        if integer_rs1 { Register(rs1) } else { FpRegister(rs1) }
+    ];
+    if !unary {
+        operands.push(FpRegister(rs2));
+    }
+
+    operands
+}
+
+// The OP-V instructions, most of which are named for what they do and get a suffix for their
+// operands: .vv, .vx, .vi or .vf, with w in place of the first v when vs2 is double width, and an m
+// when v0 holds carries or choices rather than a mask.
+// The number of fields of a segment load or store goes in its mnemonic, after the vl, vls, vlux
+// and so on, as does the number of registers of the whole register ones.
+fn vector_memory_operands(mnemonic: &mut String, word: u32) -> Vec<Operand> {
+    use Operand::*;
+
+    let f = parse_format_r(word);
+    let fields = (word >> 29) + 1;
+    let mut operands = vec![VectorRegister(f.rd), Memory { base: f.rs1, offset: 0 }];
+    match mnemonic.as_str() {
+        name if name.starts_with("vlre") || name == "vsr.v" => mnemonic.insert_str(2, &fields.to_string()),
+        _ if fields > 1 => {
+            let at = mnemonic.find('e').unwrap_or(mnemonic.len());
+            mnemonic.insert_str(at, &format!("seg{}", fields));
+        },
+        _ => {}
+    }
+    match (word >> 26) & 3 {
+        0b10 => operands.push(Register(f.rs2)),
+        0b01 | 0b11 => operands.push(VectorRegister(f.rs2)),
+        _ => {}
+    }
+    if (word >> 25) & 1 == 0 {
+        operands.push(VectorMask);
+    }
+    operands
+}
+
+fn vector_operands(mnemonic: &mut String, word: u32) -> Vec<Operand> {
+    use Operand::*;
+
+    let f = parse_format_r(word);
+    let funct3 = (word >> 12) & 7;
+    let masked = (word >> 25) & 1 == 0;
+    if funct3 == 0b111 {
+        return match mnemonic.as_str() {
+            "vsetvli" => vec![Register(f.rd), Register(f.rs1), VectorType((word >> 20) & 0x7ff)],
+            "vsetivli" => vec![Register(f.rd), Immediate(f.rs1 as i64), VectorType((word >> 20) & 0x3ff)],
+            _ => vec![Register(f.rd), Register(f.rs1), Register(f.rs2)]
+        };
+    }
+
+    let (kind, source) = match funct3 {
+        // shifts, slides and gathers take the immediate unsigned
+        0b011 => ('i', Immediate(match word >> 26 {
+            0b001100..=0b001111 | 0b100101.. => f.rs1 as i64,
+            _ => ((f.rs1 as i64) << 59) >> 59
+        })),
+        0b100 | 0b110 => ('x', Register(f.rs1)),
+        0b101 => ('f', FpRegister(f.rs1)),
+        _ => ('v', VectorRegister(f.rs1))
+    };
+    let (vd, vs2) = (VectorRegister(f.rd), VectorRegister(f.rs2));
+    let funct6 = word >> 26;
+    // the conversions, vfsqrt and vfclass use vs1 to say which they are
+    let unary = matches!(funct3, 0b001 | 0b010) && matches!(funct6, 0b010010..=0b010100);
+    let mask = if masked { vec![VectorMask] } else { vec![] };
+    match mnemonic.as_str() {
+        "vmv.x.s" => return vec![Register(f.rd), vs2],
+        "vcpop.m" | "vfirst.m" => return [vec![Register(f.rd), vs2], mask].concat(),
+        "vid.v" => return [vec![vd], mask].concat(),
+        "vmv.s.x" => return vec![vd, Register(f.rs1)],
+        "vfmv.f.s" => return vec![FpRegister(f.rd), vs2],
+        "vfmv.s.f" => return vec![vd, FpRegister(f.rs1)],
+        "vmvnr.v" => {
+            *mnemonic = format!("vmv{}r.v", f.rs1 + 1);
+            return vec![vd, vs2];
+        },
+        "vmerge" | "vfmerge" if !masked => {
+            *mnemonic = format!("{}.v.{}", mnemonic.replace("merge", "mv"), kind);
+            return vec![vd, source];
+        },
+        "vzext" | "vsext" => mnemonic.push_str(&format!(".vf{}", 1 << (4 - (f.rs1 >> 1)))),
+        _ if unary => {},
+        name if name.ends_with(".w") => mnemonic.push(kind),
+        // the reductions, which are all .vs
+        name if name.contains('.') => {},
+        _ => {
+            mnemonic.push_str(".v");
+            mnemonic.push(kind);
+        }
+    }
+
+    // the multiply-adds name the multiplier before vs2
+    let multiply_add = matches!(funct3, 0b001 | 0b010 | 0b101 | 0b110) && (funct6 >> 3 == 0b101 || funct6 >> 2 == 0b1111);
+    let mut operands = match mnemonic.split('.').next() {
+        Some("vzext" | "vsext") => vec![vd, vs2],
+        _ if unary => vec![vd, vs2],
+        _ if multiply_add => vec![vd, source, vs2],
+        _ => vec![vd, vs2, source]
+    };
+    let carrying = match mnemonic.split('.').next() {
+        Some("vadc" | "vsbc" | "vmerge" | "vfmerge") => true,
+        Some("vmadc" | "vmsbc") => masked,
+        _ => false
+    };
+    if carrying {
+        mnemonic.push('m');
+        operands.push(VectorRegister(0));
+    } else if masked {
+        operands.push(VectorMask);
+    }
+    operands
+}
+
+// rewrite a single instruction into its canonical pseudo-instruction where there is one
+fn pseudo(decoded: Disassembly) -> Disassembly {
+    use Operand::*;
+
+    if let [FpRegister(rd), FpRegister(rs1), FpRegister(rs2)] = decoded.operands.as_slice() {
+        if rs1 == rs2 {
+            let name = match decoded.mnemonic.as_str() {
+                "fsgnj.s" => Some("fmv.s"),
+                "fsgnj.d" => Some("fmv.d"),
+                "fsgnj.q" => Some("fmv.q"),
+                "fsgnjn.s" => Some("fneg.s"),
+                "fsgnjn.d" => Some("fneg.d"),
+                "fsgnjn.q" => Some("fneg.q"),
+                "fsgnjx.s" => Some("fabs.s"),
+                "fsgnjx.d" => Some("fabs.d"),
+                "fsgnjx.q" => Some("fabs.q"),
+                _ => None
+            };
+            if let Some(name) = name {
+                let operands = vec![FpRegister(*rd), FpRegister(*rs1)];
+                return Disassembly { mnemonic: name.to_string(), operands, pseudo: true, ..decoded };
+            }
+        }
+    }
+
+    let (mnemonic, operands) = match (decoded.mnemonic.as_str(), decoded.operands.as_slice()) {
+        ("addi", [Register(0), Register(0), Immediate(0)]) => ("nop", vec![]),
+        ("addi", [rd, Register(0), imm]) => ("li", vec![rd.clone(), imm.clone()]),
+        ("addi", [rd, rs, Immediate(0)]) => ("mv", vec![rd.clone(), rs.clone()]),
+        // c.mv expands to add
+        ("add", [rd, Register(0), rs]) => ("mv", vec![rd.clone(), rs.clone()]),
+        ("addiw", [rd, rs, Immediate(0)]) => ("sext.w", vec![rd.clone(), rs.clone()]),
+        ("xori", [rd, rs, Immediate(-1)]) => ("not", vec![rd.clone(), rs.clone()]),
+        ("sltiu", [rd, rs, Immediate(1)]) => ("seqz", vec![rd.clone(), rs.clone()]),
+        ("sub", [rd, Register(0), rs]) => ("neg", vec![rd.clone(), rs.clone()]),
+        ("subw", [rd, Register(0), rs]) => ("negw", vec![rd.clone(), rs.clone()]),
+        ("sltu", [rd, Register(0), rs]) => ("snez", vec![rd.clone(), rs.clone()]),
+        ("slt", [rd, rs, Register(0)]) => ("sltz", vec![rd.clone(), rs.clone()]),
+        ("slt", [rd, Register(0), rs]) => ("sgtz", vec![rd.clone(), rs.clone()]),
+        ("beq", [rs, Register(0), target]) => ("beqz", vec![rs.clone(), target.clone()]),
+        ("bne", [rs, Register(0), target]) => ("bnez", vec![rs.clone(), target.clone()]),
+        ("blt", [rs, Register(0), target]) => ("bltz", vec![rs.clone(), target.clone()]),
+        ("bge", [rs, Register(0), target]) => ("bgez", vec![rs.clone(), target.clone()]),
+        ("blt", [Register(0), rs, target]) => ("bgtz", vec![rs.clone(), target.clone()]),
+        ("bge", [Register(0), rs, target]) => ("blez", vec![rs.clone(), target.clone()]),
+        ("jal", [Register(0), target]) => ("j", vec![target.clone()]),
+        ("jal", [Register(1), target]) => ("jal", vec![target.clone()]),
+        ("jalr", [Register(0), Memory { base: 1, offset: 0 }]) => ("ret", vec![]),
+        ("jalr", [Register(0), Memory { base, offset: 0 }]) => ("jr", vec![Register(*base)]),
+        ("jalr", [Register(1), Memory { base, offset: 0 }]) => ("jalr", vec![Register(*base)]),
+        ("fence", [Fence(0xf), Fence(0xf)]) => ("fence", vec![]),
+        ("csrrs", [rd, Csr(CSR_CYCLE), Register(0)]) => ("rdcycle", vec![rd.clone()]),
+        ("csrrs", [rd, Csr(CSR_TIME), Register(0)]) => ("rdtime", vec![rd.clone()]),
+        ("csrrs", [rd, Csr(CSR_INSTRET), Register(0)]) => ("rdinstret", vec![rd.clone()]),
+        ("csrrs", [rd, csr, Register(0)]) => ("csrr", vec![rd.clone(), csr.clone()]),
+        ("csrrw", [Register(0), csr, rs]) => ("csrw", vec![csr.clone(), rs.clone()]),
+        ("csrrs", [Register(0), csr, rs]) => ("csrs", vec![csr.clone(), rs.clone()]),
+        ("csrrc", [Register(0), csr, rs]) => ("csrc", vec![csr.clone(), rs.clone()]),
+        ("csrrwi", [Register(0), csr, imm]) => ("csrwi", vec![csr.clone(), imm.clone()]),
+        ("csrrsi", [Register(0), csr, imm]) => ("csrsi", vec![csr.clone(), imm.clone()]),
+        ("csrrci", [Register(0), csr, imm]) => ("csrci", vec![csr.clone(), imm.clone()]),
+        _ => return decoded
+    };
+
+    Disassembly { mnemonic: mnemonic.to_string(), operands, pseudo: true, ..decoded }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+
+    #[test]
+    fn pseudo_instructions_can_be_turned_off() {
+        let disassembler = Disassembler::new();
+        assert_eq!("mv a0, a1", disassembler.decode(0x00058513, 0).unwrap().to_string());
+        assert_eq!("ret", disassembler.decode(0x00008067, 0).unwrap().to_string());
+        assert_eq!("li a0, -1", disassembler.decode(0xfff00513, 0).unwrap().to_string());
+
+        let raw = Disassembler::new().raw(true);
+        assert_eq!("addi a0, a1, 0", raw.decode(0x00058513, 0).unwrap().to_string());
+        assert_eq!("jalr zero, 0(ra)", raw.decode(0x00008067, 0).unwrap().to_string());
+    }
+
+    #[test]
+    fn folds_call_and_li_sequences() {
+        let mut disassembler = Disassembler::new();
+        disassembler.add_symbol(0x10, "puts");
+
+        // auipc ra, 0; jalr ra, 16(ra); lui a0, 0x12345; addiw a0, a0, 0x678
+        let memory = program(&[0x00000097, 0x010080e7, 0x12345537, 0x6785051b]);
+        let listing = disassembler.disassemble_range(&memory, 0..16);
+        assert_eq!(2, listing.len());
+        assert_eq!("call 0x10 <puts>", listing[0].to_string());
+        assert_eq!(vec![Operand::Register(10), Operand::Immediate(0x12345678)], listing[1].operands);
+        assert!(listing[1].pseudo);
+
+        let raw = Disassembler::new().raw(true).disassemble_range(&memory, 0..16);
+        assert_eq!(4, raw.len());
+        assert_eq!("auipc", raw[0].mnemonic);
+        assert!(!raw[0].pseudo);
+    }
+
+    #[test]
+    #[cfg(feature = "zbb")]
+    fn unary_bit_manipulation_has_one_source() {
+        let disassembler = Disassembler::new();
+        // clz a5, a0; rev8 s2, a0; andn a2, a0, a1
+        assert_eq!("clz a5, a0", disassembler.decode(0x60051793, 0).unwrap().to_string());
+        assert_eq!("rev8 s2, a0", disassembler.decode(0x6b855913, 0).unwrap().to_string());
+        assert_eq!("andn a2, a0, a1", disassembler.decode(0x40b57633, 0).unwrap().to_string());
+    }
+
+    #[test]
+    #[cfg(all(feature = "zkne", feature = "zknh"))]
+    fn scalar_crypto_shows_round_numbers() {
+        let disassembler = Disassembler::new();
+        assert_eq!("aes64ks1i a2, a1, 10", disassembler.decode(0x31a59613, 0).unwrap().to_string());
+        assert_eq!("sha256sig0 a4, a0", disassembler.decode(0x10251713, 0).unwrap().to_string());
+        assert_eq!("aes64esm a3, a0, a1", disassembler.decode(0x36b506b3, 0).unwrap().to_string());
+    }
+
+    #[test]
+    #[cfg(all(feature = "zksed", feature = "zksh"))]
+    fn sm4_shows_which_byte_it_takes() {
+        let disassembler = Disassembler::new();
+        assert_eq!("sm4ed a0, a0, a1, 2", disassembler.decode(0xb0b50533, 0).unwrap().to_string());
+        assert_eq!("sm3p1 a4, a1", disassembler.decode(0x10959713, 0).unwrap().to_string());
+    }
+
+    #[test]
+    #[cfg(all(feature = "zfa", feature = "d"))]
+    fn fli_shows_the_constant_it_loads() {
+        let disassembler = Disassembler::new();
+        assert_eq!("fli.s fa0, 2.0", disassembler.decode(0xf01a0553, 0).unwrap().to_string());
+        assert_eq!("fli.d fa1, min", disassembler.decode(0xf21085d3, 0).unwrap().to_string());
+        assert_eq!("fcvtmod.w.d a0, ft3", disassembler.decode(0xc2819553, 0).unwrap().to_string());
+    }
+
+    #[test]
+    #[cfg(feature = "zimop")]
+    fn may_be_operations_show_their_number() {
+        let disassembler = Disassembler::new();
+        assert_eq!("mop.r.0 a0, a1", disassembler.decode(0x81c5c573, 0).unwrap().to_string());
+        assert_eq!("mop.rr.7 a2, a0, a1", disassembler.decode(0xceb54673, 0).unwrap().to_string());
+    }
+
+    #[cfg(feature = "v")]
+    #[test]
+    fn vector_mnemonics_say_what_their_operands_are() {
+        let disassembler = Disassembler::new();
+        for (word, text) in [(0x0d0572d7, "vsetvli t0, a0, e32, m1, ta, ma"), (0xc1817057, "vsetivli zero, 2, e64, m1, tu, mu"),
+            (0x0205e087, "vle32.v v1, 0(a1)"), (0x0c1531d7, "vrsub.vi v3, v1, 10, v0.t"), (0x9616e257, "vmul.vx v4, v1, a3"),
+            (0xb2623457, "vnsrl.wi v8, v6, 4"), (0x4a1324d7, "vzext.vf2 v9, v1"), (0x424027d7, "vmv.x.s a5, v4"),
+            (0xb616e1d7, "vmacc.vx v3, a3, v1"), (0x9e40b157, "vmv2r.v v2, v4"), (0x5c21b0d7, "vmerge.vim v1, v2, 3, v0"),
+            (0x5e0640d7, "vmv.v.x v1, a2"), (0x442180d7, "vmadc.vvm v1, v2, v3, v0"), (0xb2155257, "vfmacc.vf v4, fa0, v1"),
+            (0x0e111457, "vfredosum.vs v8, v1, v2"), (0x02112857, "vredsum.vs v16, v1, v2"), (0x423015d7, "vfmv.f.s fa1, v3"),
+            (0x4a6b9557, "vfncvt.rtz.x.f.w v10, v6"), (0x481095d7, "vfcvt.x.f.v v11, v1, v0.t"), (0x5e055757, "vfmv.v.f v14, fa0"),
+            (0x5c1556d7, "vfmerge.vfm v13, v1, fa0, v0"), (0xd2655e57, "vfwadd.wf v28, v6, fa0"),
+            (0x03058087, "vle8ff.v v1, 0(a1)"), (0x4208a657, "vfirst.m a2, v0"), (0x0ae5e207, "vlse32.v v4, 0(a1), a4"),
+            (0x0c65d2a7, "vsoxei16.v v5, 0(a1), v6, v0.t"), (0x4ae5d427, "vssseg3e16.v v8, 0(a1), a4"),
+            (0x26658407, "vluxseg2ei8.v v8, 0(a1), v6"), (0x2285d507, "vl2re16.v v10, 0(a1)"), (0x62858627, "vs4r.v v12, 0(a1)"),
+            (0x02b58007, "vlm.v v0, 0(a1)"), (0x621121d7, "vmandn.mm v3, v1, v2"), (0x52082257, "viota.m v4, v0"),
+            (0x5008a2d7, "vid.v v5, v0.t")] {
+            assert_eq!(text, disassembler.decode(word, 0).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn compressed_instructions_report_their_length() {
+        // c.mv a0, a1
+        let memory = vec![0x2e, 0x85, 0, 0];
+        let decoded = Disassembler::new().disassemble(&memory, 0).expect("decode");
+        assert_eq!(2, decoded.length);
+        assert_eq!("mv a0, a1", decoded.to_string());
+    }
+}
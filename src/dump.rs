@@ -0,0 +1,134 @@
+use crate::memory::Memory;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+const ROW_SIZE: usize = 16;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Annotation {
+    pub address: usize,
+    pub label: String
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DumpRow {
+    pub address: usize,
+    // None where the memory faulted, so unmapped holes show up instead of ending the dump
+    pub bytes: Vec<Option<u8>>,
+    pub annotations: Vec<Annotation>
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryDump {
+    pub rows: Vec<DumpRow>
+}
+
+impl MemoryDump {
+    pub fn new(memory: &dyn Memory, range: Range<usize>) -> Self {
+        let mut rows = Vec::new();
+        let mut address = range.start;
+        while address < range.end {
+            let length = ROW_SIZE.min(range.end - address);
+            rows.push(DumpRow {
+                address,
+                bytes: (address..address + length).map(|a| memory.read_u8(a).ok()).collect(),
+                annotations: Vec::new()
+            });
+            address += length;
+        }
+
+        MemoryDump { rows }
+    }
+
+    // attach a label to the row containing the address, addresses outside the dump are ignored
+    pub fn annotate(&mut self, address: usize, label: &str) {
+        if let Some(row) = self.rows.iter_mut().find(|row| address >= row.address && address < row.address + row.bytes.len()) {
+            row.annotations.push(Annotation { address, label: label.to_string() });
+        }
+    }
+
+    pub fn annotate_symbols(&mut self, symbols: &BTreeMap<usize, String>) {
+        if let (Some(first), Some(last)) = (self.rows.first(), self.rows.last()) {
+            let range = first.address..last.address + last.bytes.len();
+            let visible: Vec<(usize, String)> = symbols.range(range).map(|(a, name)| (*a, name.clone())).collect();
+            for (address, name) in visible {
+                self.annotate(address, &name);
+            }
+        }
+    }
+
+    pub fn bytes(&self) -> impl Iterator<Item = Option<u8>> + '_ {
+        self.rows.iter().flat_map(|row| row.bytes.iter().copied())
+    }
+}
+
+impl Display for DumpRow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x} ", self.address)?;
+        for i in 0..ROW_SIZE {
+            if i == ROW_SIZE / 2 {
+                write!(f, " ")?;
+            }
+            match self.bytes.get(i) {
+                Some(Some(byte)) => write!(f, " {:02x}", byte)?,
+                Some(None) => write!(f, " ??")?,
+                None => write!(f, "   ")?
+            }
+        }
+
+        write!(f, "  |")?;
+        for byte in &self.bytes {
+            match byte {
+                Some(b) if b.is_ascii_graphic() || *b == b' ' => write!(f, "{}", *b as char)?,
+                _ => write!(f, ".")?
+            }
+        }
+        write!(f, "|")?;
+
+        for annotation in &self.annotations {
+            match annotation.address - self.address {
+                0 => write!(f, " <{}>", annotation.label)?,
+                offset => write!(f, " <{}+{:#x}>", annotation.label, offset)?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for MemoryDump {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for row in &self.rows {
+            writeln!(f, "{}", row)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dump_marks_unreadable_bytes_and_symbols() {
+        let mut memory = vec![0u8; 0x22];
+        memory[0x10..0x15].copy_from_slice(b"hello");
+
+        let mut symbols = BTreeMap::new();
+        symbols.insert(0x12, "greeting".to_string());
+        symbols.insert(0x40, "elsewhere".to_string());
+
+        let mut dump = MemoryDump::new(&memory, 0x10..0x24);
+        dump.annotate_symbols(&symbols);
+        assert_eq!(2, dump.rows.len());
+        assert_eq!(Some(b'h'), dump.rows[0].bytes[0]);
+        assert_eq!(vec![Some(0), Some(0), None, None], dump.rows[1].bytes);
+
+        let text = dump.to_string();
+        let first = text.lines().next().unwrap();
+        assert!(first.starts_with("0000000000000010  68 65 6c 6c 6f 00"));
+        assert!(first.ends_with("|hello...........| <greeting+0x2>"));
+        assert!(text.lines().nth(1).unwrap().contains("00 00 ?? ??"));
+    }
+}
@@ -1,175 +1,73 @@
+pub mod batch;
+pub mod checkpoint;
+pub mod clock;
+pub mod compare;
+pub mod coredump;
 pub mod cpu;
+pub mod diff;
+pub mod disasm;
+pub mod dump;
+pub mod loader;
 pub mod memory;
+pub mod minimize;
+pub mod prelude;
+pub mod symbolize;
+pub mod syscalls;
+pub mod trace;
 
 #[cfg(test)]
 mod test {
-    extern crate elfloader;
-
     use super::cpu::*;
     use super::cpu::instruction::Instruction;
-
-    use elfloader::*;
-    use std::io::Write;
+    use super::loader::elf::ElfLoader;
+    use super::trace::ConsoleTracer;
 
     const MAX_SIZE: usize = 1024 * 128;
     const STACK_SIZE: usize = 1024 * 16;
+    const FUEL: u64 = 1_000_000_000;
 
-    struct RVTestElfLoader<'a> {
-        pub img_base: u64,
-        target: &'a mut [u8]
-    }
-
-    impl <'a> RVTestElfLoader<'a> {
-        pub fn new(target: &'a mut [u8]) -> Self {
-            RVTestElfLoader {
-                img_base: u64::MAX,
-                target
-            }
-        }
-    }
-
-    impl <'a> ElfLoader for RVTestElfLoader<'a> {
-        fn allocate(&mut self, load_headers: LoadableHeaders) -> Result<(), ElfLoaderErr> {
-            for header in load_headers {
-                if header.virtual_addr() < self.img_base {
-                    self.img_base = header.virtual_addr();
-                }
-            }
-
-            Ok(())
-        }
-
-        fn load(&mut self, _flags: Flags, base: VAddr, region: &[u8]) -> Result<(), ElfLoaderErr> {
-            let start = base - self.img_base;
-            let end = start + region.len() as u64;
-
-            //println!("Loading region from {:#x} into {:?} with {:?} bytes", base, start, region.len());
-            if end < MAX_SIZE as u64 {
-                for i in 0..region.len() {
-                    self.target[start as usize + i] = region[i];
-                }
-
-                Ok(())
-            } else {
-                Err(ElfLoaderErr::OutOfMemory)
-            }
-        }
-
-        fn relocate(&mut self, _entry: RelocationEntry) -> Result<(), ElfLoaderErr> {
-            // let typ = TypeRela64::from(entry.get_type());
-            // let addr: *mut u64 = (self.vbase + entry.get_offset()) as *mut u64;
-
-            Err(ElfLoaderErr::UnsupportedRelocationEntry)
-
-        }
-
-        fn tls(
-            &mut self,
-            _tdata_start: VAddr,
-            _tdata_length: u64,
-            _total_size: u64,
-            _align: u64
-        ) -> Result<(), ElfLoaderErr> {
-            // let tls_end = tdata_start +  total_size;
-            // println!("Initial TLS region is at = {:#x} -- {:#x}", tdata_start, tls_end);
-            //Ok(())
-
-            Err(ElfLoaderErr::UnsupportedSectionData)
-        }
-
-    }
-
-    fn run_test(binary_blob: &[u8]) {
+    fn setup_test(binary_blob: &[u8]) -> Result<(Cpu, Vec<u8>), String> {
         let mut target: Vec<u8> = Vec::new();
         target.resize(MAX_SIZE + STACK_SIZE, 0);
 
-        let binary = ElfBinary::new(binary_blob).expect("Got proper ELF file");
-        let mut loader = RVTestElfLoader::new(&mut target);
-        binary.load(&mut loader).expect("Can't load the binary?");
-        let img_base = loader.img_base;
-
-        let entry_point_offset = binary.entry_point() - img_base;
-
         let mut cpu = Cpu::new();
-        cpu.set_ecall_handler(Some(Instruction{
+        cpu.set_call_tracking(true);
+        ElfLoader::new()
+            .base(0)
+            .stack_pointer(MAX_SIZE + STACK_SIZE - 1)
+            .load_into(binary_blob, &mut cpu, &mut target)
+            .map_err(|e| e.to_string())?;
+
+        cpu.set_ecall_fallback(Some(Instruction{
             name: "ECALL",
             operation: |cpu, _memory, _word, _address| {
                 match cpu.get_register(Register::A7) {
                     64 => Ok(()), // WRITE
-                    93 => Err(Trap { trap_type: TrapType::Stop, value: cpu.get_register(Register::A0) as u64 }),
+                    93 => Err(Trap::exit(cpu.get_register(Register::A0))),
                     num => Err(Trap { trap_type: TrapType::SupervisorSoftwareInterrupt, value: num as u64})
                 }
             }
         }));
 
-        cpu.update_pc(entry_point_offset as usize);
-        cpu.update_stack_pointer(MAX_SIZE + STACK_SIZE - 1);
-        let mut fuel = 1_000_000_000;
-
-        let dump_instructions = std::env::var("DUMP_INSTRUCTIONS").is_ok();
-        let mut old_x = cpu.x.clone();
-        let mut old_f = cpu.f.clone();
-
-        loop {
-            let pc = cpu.get_pc();
-
-            if dump_instructions {
-                let saved = cpu.pc;
-                let op = cpu.fetch(&target).expect("instruction fetch failed");
-                let inst = Cpu::decode(op);
-                cpu.pc = saved;
-
-                if let Some(inst) = inst {
-                    print!("pc = {:#x} - {:?}, Cpu - x: [", pc, inst.name);
-                    for i in 0..32 {
-                        if i > 0 {
-                            print!(", ");
-                        }
-                        if cpu.x[i] == old_x[i] {
-                            print!("{:?}", cpu.x[i]);
-                        } else {
-                            print!("\x1b[31m{:?}\x1b[0m", cpu.x[i]);
-                        }
-                    }
-                    print!("], f: [");
-                    for i in 0..32 {
-                        if i > 0 {
-                            print!(", ");
-                        }
-                        if cpu.f[i].to_bits() == old_f[i].to_bits() {
-                            print!("{:?}", cpu.f[i]);
-                        } else {
-                            print!("\x1b[31m{:?}\x1b[0m", cpu.f[i]);
-                        }
-                    }
-                    println!("]");
-                }
+        Ok((cpu, target))
+    }
 
-                old_x = cpu.x.clone();
-                old_f = cpu.f.clone();
-                std::io::stdout().flush().expect("flush");
-            }
+    fn run_test(binary_blob: &[u8]) {
+        let (mut cpu, mut target) = setup_test(binary_blob).expect("Can't load the binary?");
 
-            match cpu.tick(&mut target) {
-                Ok(_) => {
-                    fuel = fuel - 1;
-                    if fuel == 0 {
-                        panic!("out of fuel");
-                    }
-                },
-                Err(e) => {
-                    match e.trap_type {
-                        TrapType::Stop => {
-                            if e.value != 0 {
-                                panic!("CPU test {:?} failed a0={:#x} a1={:#x} a2={:#x} a3={:#x} a4={:#x} t2={:#x}", e.value >> 1, cpu.get_register(Register::A0), cpu.get_register(Register::A1), cpu.get_register(Register::A2), cpu.get_register(Register::A3), cpu.get_register(Register::A4), cpu.get_register(Register::T2));
-                            } else {
-                                break;
-                            }
-                        },
-                        _ => panic!("CPU failure: pc = {:#x} - {:?}", pc, e)
-                    }
-                }
-            }
+        if std::env::var("DUMP_INSTRUCTIONS").is_ok() {
+            cpu.set_tracer(Some(Box::new(ConsoleTracer::stdout())));
+        }
+        let stop = cpu.run_with_fuel(&mut target, FUEL).1;
+
+        match stop {
+            StopReason::Exited(0) => {},
+            StopReason::Exited(value) => {
+                panic!("CPU test {:?} failed a0={:#x} a1={:#x} a2={:#x} a3={:#x} a4={:#x} t2={:#x}", value >> 1, cpu.get_register(Register::A0), cpu.get_register(Register::A1), cpu.get_register(Register::A2), cpu.get_register(Register::A3), cpu.get_register(Register::A4), cpu.get_register(Register::T2));
+            },
+            StopReason::FuelExhausted => panic!("out of fuel"),
+            StopReason::Trap { pc, trap } => panic!("CPU failure: pc = {:#x} - {:?}, called from {:x?}", pc, trap, cpu.backtrace()),
+            stop => panic!("CPU failure: {:?}", stop)
         }
     }
 
@@ -430,6 +328,7 @@ mod test {
         }
     }
 
+    #[cfg(feature = "a")]
     mod rv64_ua_p {
         use super::*;
 
@@ -529,6 +428,7 @@ mod test {
         }
     }
 
+    #[cfg(feature = "m")]
     mod rv64_um_p {
         use super::*;
 
@@ -598,6 +498,7 @@ mod test {
         }
     }
 
+    #[cfg(feature = "c")]
     mod rv64_uc_p {
         use super::*;
 
@@ -607,6 +508,7 @@ mod test {
         }
     }
 
+    #[cfg(feature = "f")]
     mod rv64_uf_p {
         use super::*;
 
@@ -674,6 +576,7 @@ mod test {
         }
     }
 
+    #[cfg(feature = "d")]
     mod rv64_ud_p {
         use super::*;
 
@@ -734,6 +637,418 @@ mod test {
         }
     }
 
+    mod rv32_ui_p {
+        use super::*;
+
+        #[test]
+        fn rv32ui_p_add() {
+            rv_test!("../test/rv32ui-p-add");
+        }
+
+        #[test]
+        fn rv32ui_p_addi() {
+            rv_test!("../test/rv32ui-p-addi");
+        }
+
+        #[test]
+        fn rv32ui_p_and() {
+            rv_test!("../test/rv32ui-p-and");
+        }
+
+        #[test]
+        fn rv32ui_p_andi() {
+            rv_test!("../test/rv32ui-p-andi");
+        }
+
+        #[test]
+        fn rv32ui_p_auipc() {
+            rv_test!("../test/rv32ui-p-auipc");
+        }
+
+        #[test]
+        fn rv32ui_p_beq() {
+            rv_test!("../test/rv32ui-p-beq");
+        }
+
+        #[test]
+        fn rv32ui_p_bge() {
+            rv_test!("../test/rv32ui-p-bge");
+        }
+
+        #[test]
+        fn rv32ui_p_bgeu() {
+            rv_test!("../test/rv32ui-p-bgeu");
+        }
+
+        #[test]
+        fn rv32ui_p_blt() {
+            rv_test!("../test/rv32ui-p-blt");
+        }
+
+        #[test]
+        fn rv32ui_p_bltu() {
+            rv_test!("../test/rv32ui-p-bltu");
+        }
+
+        #[test]
+        fn rv32ui_p_bne() {
+            rv_test!("../test/rv32ui-p-bne");
+        }
+
+        #[test]
+        fn rv32ui_p_fence_i() {
+            rv_test!("../test/rv32ui-p-fence_i");
+        }
+
+        #[test]
+        fn rv32ui_p_jal() {
+            rv_test!("../test/rv32ui-p-jal");
+        }
+
+        #[test]
+        fn rv32ui_p_jalr() {
+            rv_test!("../test/rv32ui-p-jalr");
+        }
+
+        #[test]
+        fn rv32ui_p_lb() {
+            rv_test!("../test/rv32ui-p-lb");
+        }
+
+        #[test]
+        fn rv32ui_p_lbu() {
+            rv_test!("../test/rv32ui-p-lbu");
+        }
+
+        #[test]
+        fn rv32ui_p_lh() {
+            rv_test!("../test/rv32ui-p-lh");
+        }
+
+        #[test]
+        fn rv32ui_p_lhu() {
+            rv_test!("../test/rv32ui-p-lhu");
+        }
+
+        #[test]
+        fn rv32ui_p_lui() {
+            rv_test!("../test/rv32ui-p-lui");
+        }
+
+        #[test]
+        fn rv32ui_p_lw() {
+            rv_test!("../test/rv32ui-p-lw");
+        }
+
+        #[test]
+        fn rv32ui_p_or() {
+            rv_test!("../test/rv32ui-p-or");
+        }
+
+        #[test]
+        fn rv32ui_p_ori() {
+            rv_test!("../test/rv32ui-p-ori");
+        }
+
+        #[test]
+        fn rv32ui_p_sb() {
+            rv_test!("../test/rv32ui-p-sb");
+        }
+
+        #[test]
+        fn rv32ui_p_sh() {
+            rv_test!("../test/rv32ui-p-sh");
+        }
+
+        #[test]
+        fn rv32ui_p_simple() {
+            rv_test!("../test/rv32ui-p-simple");
+        }
+
+        #[test]
+        fn rv32ui_p_sll() {
+            rv_test!("../test/rv32ui-p-sll");
+        }
+
+        #[test]
+        fn rv32ui_p_slli() {
+            rv_test!("../test/rv32ui-p-slli");
+        }
+
+        #[test]
+        fn rv32ui_p_slt() {
+            rv_test!("../test/rv32ui-p-slt");
+        }
+
+        #[test]
+        fn rv32ui_p_slti() {
+            rv_test!("../test/rv32ui-p-slti");
+        }
+
+        #[test]
+        fn rv32ui_p_sltiu() {
+            rv_test!("../test/rv32ui-p-sltiu");
+        }
+
+        #[test]
+        fn rv32ui_p_sltu() {
+            rv_test!("../test/rv32ui-p-sltu");
+        }
+
+        #[test]
+        fn rv32ui_p_sra() {
+            rv_test!("../test/rv32ui-p-sra");
+        }
+
+        #[test]
+        fn rv32ui_p_srai() {
+            rv_test!("../test/rv32ui-p-srai");
+        }
+
+        #[test]
+        fn rv32ui_p_srl() {
+            rv_test!("../test/rv32ui-p-srl");
+        }
+
+        #[test]
+        fn rv32ui_p_srli() {
+            rv_test!("../test/rv32ui-p-srli");
+        }
+
+        #[test]
+        fn rv32ui_p_sub() {
+            rv_test!("../test/rv32ui-p-sub");
+        }
+
+        #[test]
+        fn rv32ui_p_sw() {
+            rv_test!("../test/rv32ui-p-sw");
+        }
+
+        #[test]
+        fn rv32ui_p_xor() {
+            rv_test!("../test/rv32ui-p-xor");
+        }
+
+        #[test]
+        fn rv32ui_p_xori() {
+            rv_test!("../test/rv32ui-p-xori");
+        }
+    }
+
+    #[cfg(feature = "m")]
+    mod rv32_um_p {
+        use super::*;
+
+        #[test]
+        fn rv32um_p_div() {
+            rv_test!("../test/rv32um-p-div");
+        }
+
+        #[test]
+        fn rv32um_p_divu() {
+            rv_test!("../test/rv32um-p-divu");
+        }
+
+        #[test]
+        fn rv32um_p_mul() {
+            rv_test!("../test/rv32um-p-mul");
+        }
+
+        #[test]
+        fn rv32um_p_mulh() {
+            rv_test!("../test/rv32um-p-mulh");
+        }
+
+        #[test]
+        fn rv32um_p_mulhsu() {
+            rv_test!("../test/rv32um-p-mulhsu");
+        }
+
+        #[test]
+        fn rv32um_p_mulhu() {
+            rv_test!("../test/rv32um-p-mulhu");
+        }
+
+        #[test]
+        fn rv32um_p_rem() {
+            rv_test!("../test/rv32um-p-rem");
+        }
+
+        #[test]
+        fn rv32um_p_remu() {
+            rv_test!("../test/rv32um-p-remu");
+        }
+    }
+
+    #[cfg(all(feature = "v", feature = "d"))]
+    mod rv64_uv_p {
+        use super::*;
+
+        #[test]
+        fn rv64uv_p_vfadd() {
+            rv_test!("../test/rv64uv-p-vfadd");
+        }
+
+        #[test]
+        fn rv64uv_p_vfcmp() {
+            rv_test!("../test/rv64uv-p-vfcmp");
+        }
+
+        #[test]
+        fn rv64uv_p_vfcvt() {
+            rv_test!("../test/rv64uv-p-vfcvt");
+        }
+
+        #[test]
+        fn rv64uv_p_vfdiv() {
+            rv_test!("../test/rv64uv-p-vfdiv");
+        }
+
+        #[test]
+        fn rv64uv_p_vfmadd() {
+            rv_test!("../test/rv64uv-p-vfmadd");
+        }
+
+        #[test]
+        fn rv64uv_p_vfmin() {
+            rv_test!("../test/rv64uv-p-vfmin");
+        }
+
+        #[test]
+        fn rv64uv_p_vfmv() {
+            rv_test!("../test/rv64uv-p-vfmv");
+        }
+
+        #[test]
+        fn rv64uv_p_vfredsum() {
+            rv_test!("../test/rv64uv-p-vfredsum");
+        }
+
+        #[test]
+        fn rv64uv_p_vfwadd() {
+            rv_test!("../test/rv64uv-p-vfwadd");
+        }
+
+        #[test]
+        fn rv64uv_p_vredsum() {
+            rv_test!("../test/rv64uv-p-vredsum");
+        }
+    }
+
+    #[cfg(feature = "zba")]
+    mod rv64_uzba_p {
+        use super::*;
+
+        #[test]
+        fn rv64uzba_p_add_uw() {
+            rv_test!("../test/rv64uzba-p-add_uw");
+        }
+
+        #[test]
+        fn rv64uzba_p_sh1add() {
+            rv_test!("../test/rv64uzba-p-sh1add");
+        }
+
+        #[test]
+        fn rv64uzba_p_sh1add_uw() {
+            rv_test!("../test/rv64uzba-p-sh1add_uw");
+        }
+
+        #[test]
+        fn rv64uzba_p_sh2add() {
+            rv_test!("../test/rv64uzba-p-sh2add");
+        }
+
+        #[test]
+        fn rv64uzba_p_sh2add_uw() {
+            rv_test!("../test/rv64uzba-p-sh2add_uw");
+        }
+
+        #[test]
+        fn rv64uzba_p_sh3add() {
+            rv_test!("../test/rv64uzba-p-sh3add");
+        }
+
+        #[test]
+        fn rv64uzba_p_sh3add_uw() {
+            rv_test!("../test/rv64uzba-p-sh3add_uw");
+        }
+
+        #[test]
+        fn rv64uzba_p_slli_uw() {
+            rv_test!("../test/rv64uzba-p-slli_uw");
+        }
+    }
+
+    #[cfg(feature = "zbc")]
+    mod rv64_uzbc_p {
+        use super::*;
+
+        #[test]
+        fn rv64uzbc_p_clmul() {
+            rv_test!("../test/rv64uzbc-p-clmul");
+        }
+
+        #[test]
+        fn rv64uzbc_p_clmulh() {
+            rv_test!("../test/rv64uzbc-p-clmulh");
+        }
+
+        #[test]
+        fn rv64uzbc_p_clmulr() {
+            rv_test!("../test/rv64uzbc-p-clmulr");
+        }
+    }
+
+    #[cfg(all(feature = "m", feature = "a"))]
+    mod batch {
+        use super::*;
+        use crate::batch::{run_files, BatchConfig, BatchStatus};
+        use std::path::PathBuf;
+
+        #[test]
+        fn runs_corpus_in_parallel() {
+            let paths: Vec<PathBuf> = ["rv64ui-p-add", "rv64ui-p-sub", "rv64um-p-mul", "rv64ua-p-lrsc"].iter()
+                .map(|name| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test").join(name))
+                .collect();
+            let config = BatchConfig { threads: 2, collect_coverage: true, ..BatchConfig::default() };
+
+            let report = run_files(&paths, &config, setup_test);
+
+            assert_eq!(4, report.passed());
+            assert_eq!(0, report.failed());
+            for result in &report.results {
+                assert!(matches!(result.status, BatchStatus::Exited(0)));
+                assert!(!result.coverage.is_empty());
+            }
+        }
+
+        #[test]
+        fn coverage_is_counted_once_across_binaries() {
+            let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test").join("rv64ui-p-add");
+            let config = BatchConfig { threads: 2, collect_coverage: true, ..BatchConfig::default() };
+
+            let report = run_files(&[path.clone(), path.clone()], &config, setup_test);
+            assert_eq!(2, report.passed());
+            assert_eq!(report.results[0].coverage.len(), report.total_coverage());
+
+            let report = run_files(&[path], &BatchConfig { fuel: 10, ..config }, setup_test);
+            assert!(matches!(report.results[0].status, BatchStatus::OutOfFuel));
+            assert_eq!((10, 10), (report.results[0].instructions, report.results[0].coverage.len()));
+        }
+
+        #[test]
+        fn reports_load_failures() {
+            let paths = vec![PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("README.md")];
+            let report = run_files(&paths, &BatchConfig::default(), setup_test);
+
+            assert_eq!(1, report.failed());
+            assert!(matches!(report.results[0].status, BatchStatus::LoadFailed(_)));
+        }
+    }
+
+    #[cfg(all(feature = "m", feature = "a", feature = "d", feature = "c"))]
     mod examples {
         use super::*;
 
@@ -0,0 +1,64 @@
+use crate::cpu::Trap;
+use crate::memory::Memory;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+pub mod elf;
+pub mod hex;
+pub mod raw;
+pub mod srec;
+pub mod stack;
+
+#[derive(Clone, Debug)]
+pub enum LoadError {
+    // the file ends before something its headers point at
+    Truncated,
+    BadMagic,
+    Unsupported(&'static str),
+    // a text format record that does not parse or fails its checksum, with its 1 based line
+    Malformed(usize),
+    // the memory backend refused a write while the image was being copied in
+    Memory(Trap)
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Truncated => write!(f, "file is truncated"),
+            LoadError::BadMagic => write!(f, "not an image this loader understands"),
+            LoadError::Unsupported(what) => write!(f, "unsupported: {}", what),
+            LoadError::Malformed(line) => write!(f, "malformed record on line {}", line),
+            LoadError::Memory(trap) => write!(f, "write to guest memory failed at {:#x}", trap.value)
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<Trap> for LoadError {
+    fn from(trap: Trap) -> Self {
+        LoadError::Memory(trap)
+    }
+}
+
+// the bytes spelled out by a string of hex digit pairs
+fn decode_hex(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) || !digits.is_ascii() {
+        return None;
+    }
+    (0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok()).collect()
+}
+
+// writes a record's data, growing the last segment when the record carries straight on from it
+fn write_record(memory: &mut dyn Memory, segments: &mut Vec<Range<usize>>, address: usize, data: &[u8]) -> Result<(), LoadError> {
+    for (i, byte) in data.iter().enumerate() {
+        memory.write_u8(address + i, *byte)?;
+    }
+    match segments.last_mut() {
+        Some(last) if last.end == address => last.end += data.len(),
+        _ => segments.push(address..address + data.len())
+    }
+    Ok(())
+}
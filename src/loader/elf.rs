@@ -0,0 +1,769 @@
+use crate::cpu::{Cpu, Engine, Register, Xlen};
+use crate::loader::LoadError;
+use crate::memory::Memory;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+const CLASS_32: u8 = 1;
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const MACHINE_RISCV: u16 = 243;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+const PT_TLS: u32 = 7;
+const SHT_SYMTAB: u32 = 2;
+
+const DT_NULL: u64 = 0;
+const DT_SYMTAB: u64 = 6;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_SYMENT: u64 = 11;
+
+const R_RISCV_NONE: u64 = 0;
+const R_RISCV_32: u64 = 1;
+const R_RISCV_64: u64 = 2;
+const R_RISCV_RELATIVE: u64 = 3;
+const R_RISCV_JUMP_SLOT: u64 = 5;
+const R_RISCV_TLS_TPREL32: u64 = 10;
+const R_RISCV_TLS_TPREL64: u64 = 11;
+
+const SHN_UNDEF: u16 = 0;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+const STB_WEAK: u8 = 2;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    // where the segment ended up in guest memory, not where it was linked
+    pub address: usize,
+    pub file_size: usize,
+    pub memory_size: usize,
+    // PF_R, PF_W and PF_X
+    pub flags: u32
+}
+
+impl Segment {
+    pub fn range(&self) -> Range<usize> {
+        self.address..self.address + self.memory_size
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.flags & PF_X != 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.flags & PF_W != 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Object,
+    // labels, absolute values and anything else the assembler did not give a type
+    Other
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: usize,
+    pub size: usize,
+    pub kind: SymbolKind
+}
+
+// The initial thread's static TLS block. RISC-V puts tp at the start of the block, with the
+// .tdata image first and the zeroed .tbss after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tls {
+    pub thread_pointer: usize,
+    pub size: usize,
+    pub align: usize
+}
+
+#[derive(Clone, Debug)]
+pub struct ElfImage {
+    pub entry: usize,
+    pub segments: Vec<Segment>,
+    // sorted by address, empty for stripped binaries
+    pub symbols: Vec<Symbol>,
+    // added to every linked address to get the guest address
+    pub bias: usize,
+    pub tls: Option<Tls>,
+    // where the program headers can be found in guest memory, for AT_PHDR
+    pub program_headers: Option<usize>,
+    pub program_header_count: usize,
+    // Bit32 for an ELFCLASS32 binary
+    pub xlen: Xlen
+}
+
+impl ElfImage {
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    // assembler labels have no size, so they are taken to run up to the next symbol
+    pub fn symbol_extent(&self, symbol: &Symbol) -> Range<usize> {
+        let end = match symbol.size {
+            0 => self.symbols.iter().map(|s| s.address).find(|a| *a > symbol.address)
+                .unwrap_or(self.image_range().end.max(symbol.address)),
+            size => symbol.address + size
+        };
+        symbol.address..end
+    }
+
+    pub fn global_pointer(&self) -> Option<usize> {
+        self.symbol("__global_pointer$").map(|s| s.address)
+    }
+
+    // lowest to highest address touched by any segment
+    pub fn image_range(&self) -> Range<usize> {
+        let start = self.segments.iter().map(|s| s.address).min().unwrap_or(0);
+        let end = self.segments.iter().map(|s| s.address + s.memory_size).max().unwrap_or(0);
+        start..end
+    }
+
+    // in the form the disassembler and memory dumps take
+    pub fn symbol_map(&self) -> BTreeMap<usize, String> {
+        self.symbols.iter().map(|s| (s.address, s.name.clone())).collect()
+    }
+}
+
+// Loads statically linked RV32 and RV64 executables, position independent ones included. By default every
+// segment goes to the address it was linked at, base moves the whole image so that its lowest
+// segment starts there instead and the dynamic relocations are applied to match. There is no
+// dynamic linker, so anything that asks for an interpreter is turned away.
+#[derive(Clone, Debug, Default)]
+pub struct ElfLoader {
+    base: Option<usize>,
+    stack_pointer: Option<usize>,
+    tls_base: Option<usize>,
+    predecode: bool
+}
+
+impl ElfLoader {
+    pub fn new() -> Self {
+        ElfLoader::default()
+    }
+
+    pub fn base(mut self, base: usize) -> Self {
+        self.base = Some(base);
+        self
+    }
+
+    // only used by load_into, which leaves sp alone without one
+    pub fn stack_pointer(mut self, stack_pointer: usize) -> Self {
+        self.stack_pointer = Some(stack_pointer);
+        self
+    }
+
+    // where the TLS block goes, by default it follows the highest segment
+    pub fn tls_base(mut self, tls_base: usize) -> Self {
+        self.tls_base = Some(tls_base);
+        self
+    }
+
+    // has load_into switch the hart to Engine::Cached and decode the executable segments up front,
+    // trading memory for not decoding anything while the program runs
+    pub fn predecode(mut self, predecode: bool) -> Self {
+        self.predecode = predecode;
+        self
+    }
+
+    pub fn load(&self, bytes: &[u8], memory: &mut dyn Memory) -> Result<ElfImage, LoadError> {
+        let file = ElfFile::parse(bytes)?;
+        if file.program_headers.iter().any(|p| p.kind == PT_INTERP) {
+            return Err(LoadError::Unsupported("dynamically linked executable"));
+        }
+
+        let loaded: Vec<&ProgramHeader> = file.program_headers.iter().filter(|p| p.kind == PT_LOAD).collect();
+        let lowest = loaded.iter().map(|p| p.address).min().unwrap_or(0);
+        let bias = match self.base {
+            Some(base) => base.wrapping_sub(lowest),
+            None => 0
+        };
+
+        let mut segments = Vec::new();
+        for header in loaded {
+            let data = bytes.get(span(header.offset, header.file_size)?).ok_or(LoadError::Truncated)?;
+            let address = header.address.wrapping_add(bias);
+            span(address, header.file_size.max(header.memory_size))?;
+            for (i, byte) in data.iter().enumerate() {
+                memory.write_u8(address + i, *byte)?;
+            }
+            // the rest of the segment is .bss
+            for i in header.file_size..header.memory_size {
+                memory.write_u8(address + i, 0)?;
+            }
+
+            segments.push(Segment {
+                address,
+                file_size: header.file_size,
+                memory_size: header.memory_size,
+                flags: header.flags
+            });
+        }
+
+        file.relocate(bytes, memory, bias)?;
+
+        let tls = match file.program_headers.iter().find(|p| p.kind == PT_TLS) {
+            Some(header) => {
+                let align = header.align.max(1);
+                let end = segments.iter().map(|s| s.address + s.memory_size).max().unwrap_or(0);
+                let thread_pointer = match self.tls_base {
+                    Some(base) => base,
+                    None => end.checked_next_multiple_of(align).ok_or(LoadError::Truncated)?
+                };
+                let template = bytes.get(span(header.offset, header.file_size)?).ok_or(LoadError::Truncated)?;
+                span(thread_pointer, header.file_size.max(header.memory_size))?;
+                for (i, byte) in template.iter().enumerate() {
+                    memory.write_u8(thread_pointer + i, *byte)?;
+                }
+                for i in header.file_size..header.memory_size {
+                    memory.write_u8(thread_pointer + i, 0)?;
+                }
+                Some(Tls { thread_pointer, size: header.memory_size, align })
+            },
+            None => None
+        };
+
+        let mut symbols = file.symbols(bytes)?;
+        for symbol in &mut symbols {
+            symbol.address = symbol.address.wrapping_add(bias);
+        }
+        symbols.sort_by_key(|s| s.address);
+
+        Ok(ElfImage {
+            entry: file.entry.wrapping_add(bias),
+            segments,
+            symbols,
+            bias,
+            tls,
+            program_headers: file.program_headers_address().map(|a| a.wrapping_add(bias)),
+            program_header_count: file.program_headers.len(),
+            xlen: file.xlen
+        })
+    }
+
+    // loads the image and points the hart at it, switching it to the width the binary was built
+    // for, gp is only set when the binary defines __global_pointer$ and tp only when it has TLS
+    pub fn load_into(&self, bytes: &[u8], cpu: &mut Cpu, memory: &mut dyn Memory) -> Result<ElfImage, LoadError> {
+        let image = self.load(bytes, memory)?;
+
+        cpu.set_xlen(image.xlen);
+        cpu.update_pc(image.entry);
+        if let Some(stack_pointer) = self.stack_pointer {
+            cpu.update_stack_pointer(stack_pointer);
+        }
+        if let Some(global_pointer) = image.global_pointer() {
+            cpu.set_register(Register::GP, global_pointer as i64);
+        }
+        if let Some(tls) = &image.tls {
+            cpu.set_register(Register::TP, tls.thread_pointer as i64);
+        }
+        if self.predecode {
+            cpu.set_engine(Engine::Cached);
+            for segment in image.segments.iter().filter(|s| s.is_executable()) {
+                cpu.predecode(memory, segment.range());
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: usize,
+    address: usize,
+    file_size: usize,
+    memory_size: usize,
+    align: usize
+}
+
+struct SectionHeader {
+    // where the name starts in the section name table
+    name: usize,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+    entry_size: usize
+}
+
+// Both classes are read into the same shape, addresses and sizes are 4 bytes wide in an RV32
+// binary and 8 in an RV64 one.
+struct ElfFile {
+    xlen: Xlen,
+    entry: usize,
+    program_offset: usize,
+    program_size: usize,
+    program_headers: Vec<ProgramHeader>,
+    section_headers: Vec<SectionHeader>,
+    // index of the section holding the section names
+    section_names: usize
+}
+
+impl ElfFile {
+    fn parse(bytes: &[u8]) -> Result<ElfFile, LoadError> {
+        if bytes.get(0..4) != Some(b"\x7fELF") {
+            return Err(LoadError::BadMagic);
+        }
+        let xlen = match read_u8(bytes, 4)? {
+            CLASS_32 => Xlen::Bit32,
+            CLASS_64 => Xlen::Bit64,
+            _ => return Err(LoadError::Unsupported("ELF class"))
+        };
+        if read_u8(bytes, 5)? != DATA_LITTLE_ENDIAN {
+            return Err(LoadError::Unsupported("big endian ELF"));
+        }
+        if read_u16(bytes, 18)? != MACHINE_RISCV {
+            return Err(LoadError::Unsupported("not a RISC-V binary"));
+        }
+
+        let word = |at: usize| read_word(bytes, at, xlen).map(|value| value as usize);
+        let width = word_size(xlen);
+        let entry = word(24)?;
+        let program_offset = word(24 + width)?;
+        let section_offset = word(24 + 2 * width)?;
+        // the flags and header size come between the offsets and the table sizes
+        let sizes = 30 + 3 * width;
+        let program_size = read_u16(bytes, sizes)? as usize;
+        let program_count = read_u16(bytes, sizes + 2)? as usize;
+        let section_size = read_u16(bytes, sizes + 4)? as usize;
+        let section_count = read_u16(bytes, sizes + 6)? as usize;
+        let section_names = read_u16(bytes, sizes + 8)? as usize;
+
+        let mut program_headers = Vec::with_capacity(program_count);
+        for i in 0..program_count {
+            let at = table_entry(bytes, program_offset, i, program_size)?;
+            // ELF64 moved the flags up next to the type to keep the wider fields aligned
+            program_headers.push(match xlen {
+                Xlen::Bit32 => ProgramHeader {
+                    kind: read_u32(bytes, at)?,
+                    flags: read_u32(bytes, at + 24)?,
+                    offset: word(at + 4)?,
+                    address: word(at + 8)?,
+                    file_size: word(at + 16)?,
+                    memory_size: word(at + 20)?,
+                    align: word(at + 28)?
+                },
+                Xlen::Bit64 => ProgramHeader {
+                    kind: read_u32(bytes, at)?,
+                    flags: read_u32(bytes, at + 4)?,
+                    offset: word(at + 8)?,
+                    address: word(at + 16)?,
+                    file_size: word(at + 32)?,
+                    memory_size: word(at + 40)?,
+                    align: word(at + 48)?
+                }
+            });
+        }
+
+        let mut section_headers = Vec::with_capacity(section_count);
+        for i in 0..section_count {
+            let at = table_entry(bytes, section_offset, i, section_size)?;
+            section_headers.push(SectionHeader {
+                name: read_u32(bytes, at)? as usize,
+                kind: read_u32(bytes, at + 4)?,
+                offset: word(at + 8 + 2 * width)?,
+                size: word(at + 8 + 3 * width)?,
+                link: read_u32(bytes, at + 8 + 4 * width)? as usize,
+                entry_size: word(at + 16 + 5 * width)?
+            });
+        }
+
+        Ok(ElfFile { xlen, entry, program_offset, program_size, program_headers, section_headers, section_names })
+    }
+
+    // where the fields of a symbol table entry are: the info byte, the section index, the value
+    // and the size
+    fn symbol_fields(&self) -> (usize, usize, usize, usize) {
+        match self.xlen {
+            Xlen::Bit32 => (12, 14, 4, 8),
+            Xlen::Bit64 => (4, 6, 8, 16)
+        }
+    }
+
+    // where a linked address lives in the file, only meaningful for addresses inside the file
+    // backed part of a loaded segment
+    fn file_offset(&self, address: usize) -> Result<usize, LoadError> {
+        self.program_headers.iter()
+            .find(|p| p.kind == PT_LOAD && address >= p.address && address - p.address < p.file_size)
+            .and_then(|p| p.offset.checked_add(address - p.address))
+            .ok_or(LoadError::Truncated)
+    }
+
+    // linked address of the program headers, if they are loaded at all
+    fn program_headers_address(&self) -> Option<usize> {
+        if let Some(header) = self.program_headers.iter().find(|p| p.kind == PT_PHDR) {
+            return Some(header.address);
+        }
+        let end = self.program_headers.len().checked_mul(self.program_size)?.checked_add(self.program_offset)?;
+        self.program_headers.iter()
+            .find(|p| p.kind == PT_LOAD && p.offset <= self.program_offset && p.offset.checked_add(p.file_size).is_some_and(|e| end <= e))
+            .and_then(|p| p.address.checked_add(self.program_offset - p.offset))
+    }
+
+    fn relocate(&self, bytes: &[u8], memory: &mut dyn Memory, bias: usize) -> Result<(), LoadError> {
+        let dynamic = match self.program_headers.iter().find(|p| p.kind == PT_DYNAMIC) {
+            Some(dynamic) => dynamic,
+            None => return Ok(())
+        };
+
+        let width = word_size(self.xlen);
+        let word = |at: usize| read_word(bytes, at, self.xlen);
+        let (info_at, section_at, value_at, _) = self.symbol_fields();
+        let (mut rela, mut rela_size, mut rela_entry) = (None, 0, 3 * width);
+        let (mut symbol_table, mut symbol_entry) = (None, 8 + 2 * width);
+        for at in span(dynamic.offset, dynamic.file_size)?.step_by(2 * width) {
+            // the tag first, an entry that starts past the end of the file is never added to
+            let tag = word(at)?;
+            let value = word(at + width)? as usize;
+            match tag {
+                DT_NULL => break,
+                DT_RELA => rela = Some(value),
+                DT_RELASZ => rela_size = value,
+                DT_RELAENT => rela_entry = value,
+                DT_SYMTAB => symbol_table = Some(value),
+                DT_SYMENT => symbol_entry = value,
+                _ => {}
+            }
+        }
+        let rela = match rela {
+            Some(rela) if rela_entry > 0 => self.file_offset(rela)?,
+            _ => return Ok(())
+        };
+
+        for at in span(rela, rela_size)?.step_by(rela_entry) {
+            let address = (word(at)? as usize).wrapping_add(bias);
+            let info = word(at + width)?;
+            let addend = word(at + 2 * width)?;
+            // ELF32 only has a byte for the type, leaving the rest of the word for the symbol
+            let (index, kind) = match self.xlen {
+                Xlen::Bit32 => (info >> 8, info & 0xff),
+                Xlen::Bit64 => (info >> 32, info & 0xffff_ffff)
+            };
+
+            // the linked value, or None for an unresolved weak reference
+            let symbol = || -> Result<Option<u64>, LoadError> {
+                let table = self.file_offset(symbol_table.ok_or(LoadError::Truncated)?)?;
+                let at = table_entry(bytes, table, index as usize, symbol_entry)?;
+                if read_u16(bytes, at + section_at)? != SHN_UNDEF {
+                    Ok(Some(word(at + value_at)?))
+                } else if read_u8(bytes, at + info_at)? >> 4 == STB_WEAK {
+                    Ok(None)
+                } else {
+                    Err(LoadError::Unsupported("relocation against an undefined symbol"))
+                }
+            };
+            // unresolved weak references are null
+            let address_of = || symbol().map(|value| value.map_or(0, |v| v.wrapping_add(bias as u64)));
+
+            match (kind, self.xlen) {
+                (R_RISCV_NONE, _) => {},
+                (R_RISCV_RELATIVE, _) => write_word(memory, address, (bias as u64).wrapping_add(addend), self.xlen)?,
+                (R_RISCV_JUMP_SLOT, _) => write_word(memory, address, address_of()?.wrapping_add(addend), self.xlen)?,
+                (R_RISCV_64, Xlen::Bit64) => memory.write_u64(address, address_of()?.wrapping_add(addend))?,
+                (R_RISCV_32, _) => memory.write_u32(address, address_of()?.wrapping_add(addend) as u32)?,
+                // TLS symbols hold their offset into the block, which is also their offset from tp
+                (R_RISCV_TLS_TPREL32, Xlen::Bit32) | (R_RISCV_TLS_TPREL64, Xlen::Bit64) =>
+                    write_word(memory, address, symbol()?.unwrap_or(0).wrapping_add(addend), self.xlen)?,
+                _ => return Err(LoadError::Unsupported("relocation type"))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn symbols(&self, bytes: &[u8]) -> Result<Vec<Symbol>, LoadError> {
+        let (info_at, section_at, value_at, size_at) = self.symbol_fields();
+        let mut symbols = Vec::new();
+        for table in self.section_headers.iter().filter(|s| s.kind == SHT_SYMTAB && s.entry_size > 0) {
+            let strings = self.section_headers.get(table.link).ok_or(LoadError::Truncated)?;
+            // entry 0 is always the null symbol
+            for i in 1..table.size / table.entry_size {
+                let at = table_entry(bytes, table.offset, i, table.entry_size)?;
+                let info = read_u8(bytes, at + info_at)?;
+                let section = read_u16(bytes, at + section_at)?;
+                if section == SHN_UNDEF || matches!(info & 0xf, STT_SECTION | STT_FILE) {
+                    continue;
+                }
+
+                let name = read_string(bytes, strings.offset.checked_add(read_u32(bytes, at)? as usize).ok_or(LoadError::Truncated)?)?;
+                if name.is_empty() {
+                    continue;
+                }
+                symbols.push(Symbol {
+                    name,
+                    address: read_word(bytes, at + value_at, self.xlen)? as usize,
+                    size: read_word(bytes, at + size_at, self.xlen)? as usize,
+                    kind: match info & 0xf {
+                        1 => SymbolKind::Object,
+                        2 => SymbolKind::Function,
+                        _ => SymbolKind::Other
+                    }
+                });
+            }
+        }
+
+        Ok(symbols)
+    }
+}
+
+// The contents of the section called name, for readers of what the loader itself has no use
+// for, such as debug information.
+pub fn section<'a>(bytes: &'a [u8], name: &str) -> Result<Option<&'a [u8]>, LoadError> {
+    let file = ElfFile::parse(bytes)?;
+    let Some(names) = file.section_headers.get(file.section_names) else {
+        return Ok(None);
+    };
+    for header in &file.section_headers {
+        if read_string(bytes, names.offset.checked_add(header.name).ok_or(LoadError::Truncated)?)? == name {
+            return bytes.get(span(header.offset, header.size)?).map(Some).ok_or(LoadError::Truncated);
+        }
+    }
+    Ok(None)
+}
+
+fn read_u8(bytes: &[u8], at: usize) -> Result<u8, LoadError> {
+    bytes.get(at).copied().ok_or(LoadError::Truncated)
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Result<u16, LoadError> {
+    Ok(u16::from_le_bytes(read_array(bytes, at)?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32, LoadError> {
+    Ok(u32::from_le_bytes(read_array(bytes, at)?))
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> Result<u64, LoadError> {
+    Ok(u64::from_le_bytes(read_array(bytes, at)?))
+}
+
+fn read_word(bytes: &[u8], at: usize, xlen: Xlen) -> Result<u64, LoadError> {
+    match xlen {
+        Xlen::Bit32 => read_u32(bytes, at).map(u64::from),
+        Xlen::Bit64 => read_u64(bytes, at)
+    }
+}
+
+fn word_size(xlen: Xlen) -> usize {
+    match xlen {
+        Xlen::Bit32 => 4,
+        Xlen::Bit64 => 8
+    }
+}
+
+fn write_word(memory: &mut dyn Memory, address: usize, value: u64, xlen: Xlen) -> Result<(), LoadError> {
+    match xlen {
+        Xlen::Bit32 => memory.write_u32(address, value as u32)?,
+        Xlen::Bit64 => memory.write_u64(address, value)?
+    }
+    Ok(())
+}
+
+// length bytes from at, as long as that does not run off the end of the address space
+fn span(at: usize, length: usize) -> Result<Range<usize>, LoadError> {
+    at.checked_add(length).map(|end| at..end).ok_or(LoadError::Truncated)
+}
+
+// where entry index of a table of size byte entries starts, which has to be inside the file so
+// that the offsets of the fields in the entry can be added to it without overflowing
+fn table_entry(bytes: &[u8], table: usize, index: usize, size: usize) -> Result<usize, LoadError> {
+    index.checked_mul(size)
+        .and_then(|offset| table.checked_add(offset))
+        .filter(|at| *at < bytes.len())
+        .ok_or(LoadError::Truncated)
+}
+
+fn read_array<const N: usize>(bytes: &[u8], at: usize) -> Result<[u8; N], LoadError> {
+    bytes.get(span(at, N)?).and_then(|b| b.try_into().ok()).ok_or(LoadError::Truncated)
+}
+
+fn read_string(bytes: &[u8], at: usize) -> Result<String, LoadError> {
+    let tail = bytes.get(at..).ok_or(LoadError::Truncated)?;
+    let length = tail.iter().position(|b| *b == 0).ok_or(LoadError::Truncated)?;
+    Ok(String::from_utf8_lossy(&tail[..length]).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ADD_TEST: &[u8] = include_bytes!("../../test/rv64ui-p-add");
+
+    #[test]
+    fn loads_segments_and_symbols_at_base() {
+        let mut memory = vec![0u8; 0x2000];
+        let mut cpu = Cpu::new();
+        let image = ElfLoader::new().base(0).stack_pointer(0x1ff0)
+            .load_into(ADD_TEST, &mut cpu, &mut memory)
+            .expect("load");
+
+        assert_eq!(0, image.entry);
+        assert_eq!(0x8000_0000usize.wrapping_neg(), image.bias);
+        assert_eq!(0..0x1048, image.image_range());
+        assert!(image.segments[0].is_executable() && !image.segments[1].is_executable());
+        assert_eq!(0x48, image.symbol("reset_vector").unwrap().address);
+        assert_eq!(0, cpu.get_pc());
+        assert_eq!(0x1ff0, cpu.get_register(Register::SP));
+        assert_eq!(&ADD_TEST[0x1000..0x1004], &memory[0..4]);
+    }
+
+    #[test]
+    fn predecoding_decodes_the_executable_segments() {
+        let mut memory = vec![0u8; 0x2000];
+        let mut cpu = Cpu::new();
+        ElfLoader::new().base(0).predecode(true).load_into(ADD_TEST, &mut cpu, &mut memory).expect("load");
+
+        assert_eq!(Engine::Cached, cpu.engine());
+        let decoded = cpu.cache_stats().decoded;
+        assert!(decoded.entries > 100);
+        assert_eq!(0, decoded.misses);
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!((1, 0), (cpu.cache_stats().decoded.hits, cpu.cache_stats().decoded.misses));
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_load() {
+        let mut memory = vec![0u8; 0x100];
+        assert!(matches!(ElfLoader::new().load(b"not an elf", &mut memory), Err(LoadError::BadMagic)));
+        assert!(matches!(ElfLoader::new().load(&ADD_TEST[..32], &mut memory), Err(LoadError::Truncated)));
+        // linked at 0x80000000, far outside this memory
+        assert!(matches!(ElfLoader::new().load(ADD_TEST, &mut memory), Err(LoadError::Memory(_))));
+    }
+
+    // a minimal static PIE linked at 0: one RWX segment holding everything, a dynamic table, a
+    // RELATIVE relocation at 0x200, an R_RISCV_64 against a defined symbol at 0x208, a TPREL64
+    // against a TLS symbol at 0x210 and 8 bytes of .tdata followed by 8 of .tbss
+    fn static_pie(relocation_type: u64) -> Vec<u8> {
+        fn put(bytes: &mut [u8], at: usize, values: &[u64]) {
+            for (i, value) in values.iter().enumerate() {
+                bytes[at + i * 8..at + i * 8 + 8].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        let mut bytes = vec![0u8; 0x460];
+        bytes[0..8].copy_from_slice(&[0x7f, b'E', b'L', b'F', CLASS_64, DATA_LITTLE_ENDIAN, 1, 0]);
+        bytes[16..20].copy_from_slice(&[3, 0, MACHINE_RISCV as u8, 0]);
+        put(&mut bytes, 24, &[0x100, 0x40, 0]);
+        bytes[54..58].copy_from_slice(&[56, 0, 3, 0]);
+
+        put(&mut bytes, 0x40, &[(7 << 32) | PT_LOAD as u64, 0, 0, 0, 0x460, 0x460, 0x1000]);
+        put(&mut bytes, 0x78, &[PT_DYNAMIC as u64, 0x300, 0x300, 0x300, 0x60, 0x60, 8]);
+        put(&mut bytes, 0xb0, &[PT_TLS as u64, 0x450, 0x450, 0x450, 8, 16, 16]);
+        put(&mut bytes, 0x300, &[DT_RELA, 0x380, DT_RELASZ, 72, DT_RELAENT, 24, DT_SYMTAB, 0x400, DT_SYMENT, 24, DT_NULL, 0]);
+        put(&mut bytes, 0x380, &[0x200, R_RISCV_RELATIVE, 0x100, 0x208, (1 << 32) | relocation_type, 8, 0x210, (2 << 32) | R_RISCV_TLS_TPREL64, 4]);
+        // symbol 1 is defined in section 1 at 0x180, symbol 2 is 8 bytes into the TLS block
+        put(&mut bytes, 0x418, &[1 << 48, 0x180, 0, 1 << 48, 8, 0]);
+        put(&mut bytes, 0x450, &[0x1122334455667788]);
+        bytes
+    }
+
+    #[test]
+    fn applies_dynamic_relocations_at_the_load_base() {
+        let mut memory = vec![0u8; 0x2000];
+        let image = ElfLoader::new().base(0x1000).load(&static_pie(R_RISCV_64), &mut memory).expect("load");
+
+        assert_eq!(0x1100, image.entry);
+        assert_eq!(0x1100, u64::from_le_bytes(memory[0x1200..0x1208].try_into().unwrap()));
+        assert_eq!(0x1188, u64::from_le_bytes(memory[0x1208..0x1210].try_into().unwrap()));
+        assert_eq!(12, u64::from_le_bytes(memory[0x1210..0x1218].try_into().unwrap()));
+    }
+
+    #[test]
+    fn tls_block_follows_the_image() {
+        let mut memory = vec![0xffu8; 0x2000];
+        let mut cpu = Cpu::new();
+        let image = ElfLoader::new().base(0x1000).load_into(&static_pie(R_RISCV_64), &mut cpu, &mut memory).expect("load");
+
+        assert_eq!(Some(Tls { thread_pointer: 0x1460, size: 16, align: 16 }), image.tls);
+        assert_eq!(0x1460, cpu.get_register(Register::TP));
+        assert_eq!(0x1122334455667788u64.to_le_bytes(), memory[0x1460..0x1468]);
+        assert_eq!([0u8; 8], memory[0x1468..0x1470]);
+    }
+
+    #[test]
+    fn loads_rv32_binaries() {
+        fn put(bytes: &mut [u8], at: usize, values: &[u32]) {
+            for (i, value) in values.iter().enumerate() {
+                bytes[at + i * 4..at + i * 4 + 4].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        // one RWX segment and a dynamic table holding a single RELATIVE relocation at 0x1f0
+        let mut bytes = vec![0u8; 0x200];
+        bytes[0..8].copy_from_slice(&[0x7f, b'E', b'L', b'F', CLASS_32, DATA_LITTLE_ENDIAN, 1, 0]);
+        bytes[16..20].copy_from_slice(&[3, 0, MACHINE_RISCV as u8, 0]);
+        put(&mut bytes, 24, &[0x100, 0x34, 0]);
+        bytes[42..46].copy_from_slice(&[32, 0, 2, 0]);
+        put(&mut bytes, 0x34, &[PT_LOAD, 0, 0, 0, 0x1f4, 0x1f4, 7, 0x1000]);
+        put(&mut bytes, 0x54, &[PT_DYNAMIC, 0x180, 0x180, 0x180, 0x20, 0x20, 6, 4]);
+        put(&mut bytes, 0x180, &[DT_RELA as u32, 0x1c0, DT_RELASZ as u32, 12, DT_RELAENT as u32, 12, DT_NULL as u32, 0]);
+        put(&mut bytes, 0x1c0, &[0x1f0, R_RISCV_RELATIVE as u32, 0x100]);
+
+        let mut memory = vec![0xffu8; 0x2000];
+        let mut cpu = Cpu::new();
+        let image = ElfLoader::new().base(0x1000).load_into(&bytes, &mut cpu, &mut memory).expect("load");
+
+        assert_eq!((Xlen::Bit32, Xlen::Bit32), (image.xlen, cpu.get_xlen()));
+        assert_eq!(0x1100, cpu.get_pc());
+        assert_eq!((Some(0x1034), 2), (image.program_headers, image.program_header_count));
+        // only the low 4 bytes belong to the relocation
+        assert_eq!([0x00, 0x11, 0, 0, 0xff], memory[0x11f0..0x11f5]);
+    }
+
+    #[test]
+    fn offsets_and_sizes_that_overflow_are_truncated() {
+        let mut memory = vec![0u8; 0x2000];
+        // the program header table, the loaded segment's offset and size, the TLS alignment
+        // and the relocation table's size
+        for (at, value) in [(32, u64::MAX), (0x48, u64::MAX), (0x68, u64::MAX - 0x10), (0xe0, u64::MAX), (0x318, u64::MAX - 0x300)] {
+            let mut bytes = static_pie(R_RISCV_64);
+            bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+            let result = ElfLoader::new().base(0x1000).load(&bytes, &mut memory);
+            assert!(matches!(result, Err(LoadError::Truncated)), "{at:#x}");
+        }
+    }
+
+    #[test]
+    fn sections_are_found_by_name_and_checked() {
+        // a section name table at 0x1c0 and a section called .x after it
+        let sections = |names: u64, offset: u64| {
+            let mut bytes = static_pie(R_RISCV_64);
+            bytes[40..48].copy_from_slice(&0x1d0u64.to_le_bytes());
+            bytes[58..64].copy_from_slice(&[64, 0, 2, 0, 0, 0]);
+            bytes[0x1c0..0x1c4].copy_from_slice(b"\0.x\0");
+            bytes[0x1e8..0x1f0].copy_from_slice(&names.to_le_bytes());
+            bytes[0x1f0..0x1f8].copy_from_slice(&4u64.to_le_bytes());
+            bytes[0x210..0x214].copy_from_slice(&1u32.to_le_bytes());
+            bytes[0x228..0x230].copy_from_slice(&offset.to_le_bytes());
+            bytes[0x230..0x238].copy_from_slice(&2u64.to_le_bytes());
+            bytes
+        };
+
+        assert_eq!(Some(&b".x"[..]), section(&sections(0x1c0, 0x1c1), ".x").unwrap());
+        assert_eq!(None, section(&sections(0x1c0, 0x1c1), ".y").unwrap());
+        assert!(matches!(section(&sections(u64::MAX, 0x1c1), ".x"), Err(LoadError::Truncated)));
+        assert!(matches!(section(&sections(0x1c0, u64::MAX), ".x"), Err(LoadError::Truncated)));
+    }
+
+    #[test]
+    fn unknown_relocations_are_rejected() {
+        let mut memory = vec![0u8; 0x2000];
+        // R_RISCV_TLS_DTPMOD64
+        let result = ElfLoader::new().load(&static_pie(6), &mut memory);
+        assert!(matches!(result, Err(LoadError::Unsupported("relocation type"))));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::loader::raw::RawImage;
+use crate::loader::{decode_hex, write_record, LoadError};
+use crate::memory::Memory;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+// Loads an Intel HEX file. The entry point comes from a start address record, or is the lowest
+// address written if there is none. Blank lines are skipped and anything after the end of file
+// record is ignored.
+pub fn load(text: &str, memory: &mut dyn Memory) -> Result<RawImage, LoadError> {
+    let mut segments = Vec::new();
+    let mut entry = None;
+    // added to every data record's 16 bit address
+    let mut base = 0usize;
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let malformed = LoadError::Malformed(index + 1);
+        let bytes = line.strip_prefix(':').and_then(decode_hex).ok_or(malformed.clone())?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 || bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(malformed);
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+        let data = &bytes[4..bytes.len() - 1];
+        let value = || data.iter().fold(0usize, |value, b| value << 8 | *b as usize);
+
+        match bytes[3] {
+            DATA => write_record(memory, &mut segments, base + address, data)?,
+            END_OF_FILE => break,
+            EXTENDED_SEGMENT_ADDRESS if data.len() == 2 => base = value() << 4,
+            EXTENDED_LINEAR_ADDRESS if data.len() == 2 => base = value() << 16,
+            // CS:IP
+            START_SEGMENT_ADDRESS if data.len() == 4 => entry = Some((value() >> 16 << 4) + (value() & 0xffff)),
+            START_LINEAR_ADDRESS if data.len() == 4 => entry = Some(value()),
+            _ => return Err(malformed)
+        }
+    }
+
+    Ok(RawImage {
+        entry: entry.unwrap_or_else(|| segments.iter().map(|s| s.start).min().unwrap_or(0)),
+        segments
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn loads_data_above_64k_with_a_start_address() {
+        let text = "\
+:020000040001F9
+:0400000013051500CF
+:04000400B30505003B
+:040000058000000077
+:00000001FF
+";
+        let mut memory = vec![0u8; 0x20000];
+        let image = load(text, &mut memory).expect("load");
+
+        assert_eq!(vec![0x10000..0x10008], image.segments);
+        assert_eq!(0x80000000, image.entry);
+        assert_eq!([0x13, 0x05, 0x15, 0x00, 0xb3, 0x05, 0x05, 0x00], memory[0x10000..0x10008]);
+    }
+
+    #[test]
+    fn bad_checksums_name_the_line() {
+        let mut memory = vec![0u8; 0x100];
+        assert!(matches!(load("\n:0400000013051500C8\n", &mut memory), Err(LoadError::Malformed(2))));
+        assert!(matches!(load("0400000013051500C7", &mut memory), Err(LoadError::Malformed(1))));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::cpu::Cpu;
+use crate::loader::LoadError;
+use crate::memory::Memory;
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawImage {
+    pub entry: usize,
+    // guest addresses written, in the order the segments were given
+    pub segments: Vec<Range<usize>>
+}
+
+// Loads images that carry no headers of their own, bare metal blobs and bootloader payloads.
+// Segment offsets are relative to the base and the entry point defaults to the base itself.
+//
+//     RawLoader::new(0x8000_0000).segment(0, &blob).load_into(&mut cpu, &mut memory)
+#[derive(Clone, Debug)]
+pub struct RawLoader<'a> {
+    base: usize,
+    entry: Option<usize>,
+    stack_pointer: Option<usize>,
+    segments: Vec<(usize, &'a [u8])>
+}
+
+impl<'a> RawLoader<'a> {
+    pub fn new(base: usize) -> Self {
+        RawLoader {
+            base,
+            entry: None,
+            stack_pointer: None,
+            segments: Vec::new()
+        }
+    }
+
+    pub fn segment(mut self, offset: usize, data: &'a [u8]) -> Self {
+        self.segments.push((offset, data));
+        self
+    }
+
+    // an absolute guest address, not an offset from the base
+    pub fn entry(mut self, entry: usize) -> Self {
+        self.entry = Some(entry);
+        self
+    }
+
+    // only used by load_into, which leaves sp alone without one
+    pub fn stack_pointer(mut self, stack_pointer: usize) -> Self {
+        self.stack_pointer = Some(stack_pointer);
+        self
+    }
+
+    // overlapping segments are refused rather than quietly letting the later one win
+    pub fn load(&self, memory: &mut dyn Memory) -> Result<RawImage, LoadError> {
+        let mut segments: Vec<Range<usize>> = Vec::new();
+        for (offset, data) in &self.segments {
+            let start = self.base.checked_add(*offset).ok_or(LoadError::Unsupported("segment beyond the address space"))?;
+            let range = start..start.checked_add(data.len()).ok_or(LoadError::Unsupported("segment beyond the address space"))?;
+            if segments.iter().any(|s| s.start < range.end && range.start < s.end) {
+                return Err(LoadError::Unsupported("overlapping segments"));
+            }
+
+            for (i, byte) in data.iter().enumerate() {
+                memory.write_u8(range.start + i, *byte)?;
+            }
+            segments.push(range);
+        }
+
+        Ok(RawImage {
+            entry: self.entry.unwrap_or(self.base),
+            segments
+        })
+    }
+
+    pub fn load_into(&self, cpu: &mut Cpu, memory: &mut dyn Memory) -> Result<RawImage, LoadError> {
+        let image = self.load(memory)?;
+        cpu.update_pc(image.entry);
+        if let Some(stack_pointer) = self.stack_pointer {
+            cpu.update_stack_pointer(stack_pointer);
+        }
+
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Register;
+
+    #[test]
+    fn places_segments_relative_to_base() {
+        // addi a0, a0, 1
+        let code = 0x00150513u32.to_le_bytes();
+        let data = [0xaa, 0xbb];
+        let mut memory = vec![0u8; 0x400];
+        let mut cpu = Cpu::new();
+
+        let image = RawLoader::new(0x100).segment(0, &code).segment(0x200, &data).entry(0x100)
+            .load_into(&mut cpu, &mut memory)
+            .expect("load");
+        cpu.tick(&mut memory).unwrap();
+
+        assert_eq!(vec![0x100..0x104, 0x300..0x302], image.segments);
+        assert_eq!([0xaa, 0xbb], memory[0x300..0x302]);
+        assert_eq!(1, cpu.get_register(Register::A0));
+    }
+
+    #[test]
+    fn refuses_overlaps_and_unmapped_targets() {
+        let mut memory = vec![0u8; 0x100];
+        let overlapping = RawLoader::new(0).segment(0, &[1, 2, 3, 4]).segment(2, &[5]).load(&mut memory);
+        assert!(matches!(overlapping, Err(LoadError::Unsupported("overlapping segments"))));
+        assert!(matches!(RawLoader::new(0xff).segment(0, &[1, 2]).load(&mut memory), Err(LoadError::Memory(_))));
+    }
+}
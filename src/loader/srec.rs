@@ -0,0 +1,81 @@
+use crate::loader::raw::RawImage;
+use crate::loader::{decode_hex, write_record, LoadError};
+use crate::memory::Memory;
+
+// Loads a Motorola S-record file. S1, S2 and S3 records carry data with 16, 24 and 32 bit
+// addresses, S7, S8 and S9 give the entry point. Headers and record counts are checked but
+// otherwise ignored. Without a termination record the entry is the lowest address written.
+pub fn load(text: &str, memory: &mut dyn Memory) -> Result<RawImage, LoadError> {
+    let mut segments = Vec::new();
+    let mut entry = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let malformed = LoadError::Malformed(index + 1);
+        let kind = line.strip_prefix('S').and_then(|l| l.chars().next()).and_then(|c| c.to_digit(10)).ok_or(malformed.clone())?;
+        let bytes = decode_hex(&line[2..]).ok_or(malformed.clone())?;
+        // the count covers the address, data and checksum, the checksum makes everything sum to 0xff
+        if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 || bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xff {
+            return Err(malformed);
+        }
+
+        let address_length = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(malformed)
+        };
+        let body = &bytes[1..bytes.len() - 1];
+        if body.len() < address_length {
+            return Err(malformed);
+        }
+        let address = body[..address_length].iter().fold(0usize, |value, b| value << 8 | *b as usize);
+        let data = &body[address_length..];
+
+        match kind {
+            1..=3 => write_record(memory, &mut segments, address, data)?,
+            7..=9 => entry = Some(address),
+            _ => {}
+        }
+    }
+
+    Ok(RawImage {
+        entry: entry.unwrap_or_else(|| segments.iter().map(|s| s.start).min().unwrap_or(0)),
+        segments
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn loads_mixed_address_sizes() {
+        let text = "\
+S008000068656C6C6FE3
+S3090001000013051500C8
+S30900010004B305050034
+S1050100AABB94
+S5030003F9
+S70500010000F9
+";
+        let mut memory = vec![0u8; 0x20000];
+        let image = load(text, &mut memory).expect("load");
+
+        assert_eq!(vec![0x10000..0x10008, 0x100..0x102], image.segments);
+        assert_eq!(0x10000, image.entry);
+        assert_eq!([0x13, 0x05, 0x15, 0x00], memory[0x10000..0x10004]);
+        assert_eq!([0xaa, 0xbb], memory[0x100..0x102]);
+    }
+
+    #[test]
+    fn bad_records_name_the_line() {
+        let mut memory = vec![0u8; 0x200];
+        assert!(matches!(load("S1050100AABB95", &mut memory), Err(LoadError::Malformed(1))));
+        assert!(matches!(load("S1050100AABB94\nX0", &mut memory), Err(LoadError::Malformed(2))));
+    }
+}
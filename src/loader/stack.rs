@@ -0,0 +1,189 @@
+use crate::cpu::{Cpu, Trap};
+use crate::loader::elf::ElfImage;
+use crate::memory::Memory;
+
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_BASE: u64 = 7;
+pub const AT_FLAGS: u64 = 8;
+pub const AT_ENTRY: u64 = 9;
+pub const AT_UID: u64 = 11;
+pub const AT_EUID: u64 = 12;
+pub const AT_GID: u64 = 13;
+pub const AT_EGID: u64 = 14;
+pub const AT_HWCAP: u64 = 16;
+pub const AT_CLKTCK: u64 = 17;
+pub const AT_SECURE: u64 = 23;
+pub const AT_RANDOM: u64 = 25;
+pub const AT_EXECFN: u64 = 31;
+
+const PAGE_SIZE: u64 = 4096;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+// bit n is set for the nth letter of the alphabet, here IMAFDC
+const HWCAP: u64 = (1 << 8) | (1 << 12) | 1 | (1 << 5) | (1 << 3) | (1 << 2);
+
+// The stack a Linux kernel hands a new RISC-V process. From sp upwards: argc, the argv pointers
+// and a null, the envp pointers and a null, then the auxiliary vector ending in AT_NULL. The
+// strings and the AT_RANDOM bytes sit above all of that, just below the top of the stack.
+#[derive(Clone, Debug)]
+pub struct LinuxStack {
+    args: Vec<String>,
+    env: Vec<String>,
+    // the bytes AT_RANDOM points at, fixed by default so that runs are reproducible
+    random: [u8; 16],
+    aux: Vec<(u64, u64)>
+}
+
+impl Default for LinuxStack {
+    fn default() -> Self {
+        LinuxStack {
+            args: Vec::new(),
+            env: Vec::new(),
+            random: *b"user-mode-riscv!",
+            aux: Vec::new()
+        }
+    }
+}
+
+impl LinuxStack {
+    pub fn new() -> Self {
+        LinuxStack::default()
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub fn args<I: IntoIterator<Item = S>, S: AsRef<str>>(mut self, args: I) -> Self {
+        self.args.extend(args.into_iter().map(|a| a.as_ref().to_string()));
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push(format!("{}={}", key, value));
+        self
+    }
+
+    pub fn random(mut self, random: [u8; 16]) -> Self {
+        self.random = random;
+        self
+    }
+
+    // extra auxiliary vector entries, placed after the standard ones
+    pub fn aux(mut self, key: u64, value: u64) -> Self {
+        self.aux.push((key, value));
+        self
+    }
+
+    // writes the stack below top and returns the 16 byte aligned sp the program should start with
+    pub fn build(&self, memory: &mut dyn Memory, top: usize, image: &ElfImage) -> Result<usize, Trap> {
+        let mut cursor = top;
+        let mut push_bytes = |memory: &mut dyn Memory, bytes: &[u8]| -> Result<usize, Trap> {
+            cursor -= bytes.len();
+            for (i, byte) in bytes.iter().enumerate() {
+                memory.write_u8(cursor + i, *byte)?;
+            }
+            Ok(cursor)
+        };
+
+        let random = push_bytes(memory, &self.random)?;
+        let mut strings = |memory: &mut dyn Memory, values: &[String]| -> Result<Vec<usize>, Trap> {
+            values.iter().map(|v| push_bytes(memory, &[v.as_bytes(), &[0]].concat())).collect()
+        };
+        let env = strings(memory, &self.env)?;
+        let args = strings(memory, &self.args)?;
+
+        let mut aux = vec![
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_PHENT, PROGRAM_HEADER_SIZE),
+            (AT_PHNUM, image.program_header_count as u64),
+            (AT_BASE, 0),
+            (AT_FLAGS, 0),
+            (AT_ENTRY, image.entry as u64),
+            (AT_UID, 0),
+            (AT_EUID, 0),
+            (AT_GID, 0),
+            (AT_EGID, 0),
+            (AT_HWCAP, HWCAP),
+            (AT_CLKTCK, 100),
+            (AT_SECURE, 0),
+            (AT_RANDOM, random as u64)
+        ];
+        if let Some(program_headers) = image.program_headers {
+            aux.push((AT_PHDR, program_headers as u64));
+        }
+        if let Some(name) = args.first() {
+            aux.push((AT_EXECFN, *name as u64));
+        }
+        aux.extend_from_slice(&self.aux);
+        aux.push((AT_NULL, 0));
+
+        let mut words = vec![args.len() as u64];
+        words.extend(args.iter().map(|a| *a as u64));
+        words.push(0);
+        words.extend(env.iter().map(|e| *e as u64));
+        words.push(0);
+        words.extend(aux.iter().flat_map(|(key, value)| [*key, *value]));
+
+        let lowest = args.iter().chain(&env).copied().fold(random, usize::min);
+        let sp = (lowest - words.len() * 8) & !0xf;
+        for (i, word) in words.iter().enumerate() {
+            memory.write_u64(sp + i * 8, *word)?;
+        }
+        Ok(sp)
+    }
+
+    // builds the stack and points sp at it
+    pub fn install(&self, cpu: &mut Cpu, memory: &mut dyn Memory, top: usize, image: &ElfImage) -> Result<(), Trap> {
+        let sp = self.build(memory, top, image)?;
+        cpu.update_stack_pointer(sp);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Register;
+    use crate::loader::elf::ElfLoader;
+
+    const MANDELBROT: &[u8] = include_bytes!("../../test/mandelbrot");
+
+    fn read_string(memory: &[u8], address: u64) -> String {
+        let bytes = &memory[address as usize..];
+        String::from_utf8(bytes[..bytes.iter().position(|b| *b == 0).unwrap()].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn lays_out_argv_envp_and_auxv() {
+        let mut memory = vec![0u8; 0x10000];
+        let top = memory.len();
+        let mut cpu = Cpu::new();
+        let image = ElfLoader::new().base(0).load_into(MANDELBROT, &mut cpu, &mut memory).unwrap();
+        LinuxStack::new().args(["mandelbrot", "-v"]).env("HOME", "/")
+            .install(&mut cpu, &mut memory, top, &image)
+            .unwrap();
+
+        let sp = cpu.get_register(Register::SP) as usize;
+        assert_eq!(0, sp % 16);
+        let words: Vec<u64> = memory[sp..].chunks(8).take(40).map(|c| u64::from_le_bytes(c.try_into().unwrap())).collect();
+        assert_eq!(2, words[0]);
+        assert_eq!("mandelbrot", read_string(&memory, words[1]));
+        assert_eq!("-v", read_string(&memory, words[2]));
+        assert_eq!(0, words[3]);
+        assert_eq!("HOME=/", read_string(&memory, words[4]));
+        assert_eq!(0, words[5]);
+
+        let aux: Vec<(u64, u64)> = words[6..].chunks(2).map(|p| (p[0], p[1])).take_while(|p| p.0 != AT_NULL).collect();
+        let lookup = |key| aux.iter().find(|p| p.0 == key).map(|p| p.1);
+        assert_eq!(Some(4096), lookup(AT_PAGESZ));
+        assert_eq!(Some(64), lookup(AT_PHDR));
+        assert_eq!(Some(image.entry as u64), lookup(AT_ENTRY));
+        let random = lookup(AT_RANDOM).unwrap() as usize;
+        assert_eq!(b"user-mode-riscv!", &memory[random..random + 16]);
+    }
+}
@@ -1,5 +1,28 @@
 use crate::cpu::{Trap, TrapType};
+use std::collections::VecDeque;
 use std::convert::TryInto;
+use std::ops::Range;
+
+mod cow;
+mod guard;
+pub mod marshal;
+#[cfg(any(unix, windows))]
+mod mmap;
+mod reorder;
+mod shared;
+mod space;
+
+pub use cow::CowMemory;
+pub(crate) use cow::{Page, PAGE_SIZE};
+pub use guard::GuardedMemory;
+pub use reorder::{ReorderConfig, ReorderingMemory};
+pub use shared::SharedMemory;
+pub use space::{AddressSpace, MapError};
+
+#[cfg(any(unix, windows))]
+pub use mmap::MmapMemory;
+
+pub type GuestAddr = usize;
 
 pub trait Memory {
     fn read_i8(&self, address: usize) -> Result<i8, Trap>;
@@ -15,6 +38,148 @@ pub trait Memory {
     fn write_u16(&mut self, address: usize, value: u16) -> Result<(), Trap>;
     fn write_u32(&mut self, address: usize, value: u32) -> Result<(), Trap>;
     fn write_u64(&mut self, address: usize, value: u64) -> Result<(), Trap>;
+
+    // called for FENCE, memories that buffer stores must make them visible before returning
+    fn fence(&mut self) {}
+
+    // Store new if the value at address is current, returning the value that was there either way.
+    // AMOs and SC are built on these, memories that are shared between harts must override them
+    // with a real atomic operation.
+    fn compare_exchange_u8(&mut self, address: usize, current: u8, new: u8) -> Result<Result<u8, u8>, Trap> {
+        let old = self.read_u8(address)?;
+        if old != current {
+            return Ok(Err(old));
+        }
+        self.write_u8(address, new)?;
+        Ok(Ok(old))
+    }
+
+    fn compare_exchange_u16(&mut self, address: usize, current: u16, new: u16) -> Result<Result<u16, u16>, Trap> {
+        let old = self.read_u16(address)?;
+        if old != current {
+            return Ok(Err(old));
+        }
+        self.write_u16(address, new)?;
+        Ok(Ok(old))
+    }
+
+    fn compare_exchange_u32(&mut self, address: usize, current: u32, new: u32) -> Result<Result<u32, u32>, Trap> {
+        let old = self.read_u32(address)?;
+        if old != current {
+            return Ok(Err(old));
+        }
+        self.write_u32(address, new)?;
+        Ok(Ok(old))
+    }
+
+    fn compare_exchange_u64(&mut self, address: usize, current: u64, new: u64) -> Result<Result<u64, u64>, Trap> {
+        let old = self.read_u64(address)?;
+        if old != current {
+            return Ok(Err(old));
+        }
+        self.write_u64(address, new)?;
+        Ok(Ok(old))
+    }
+
+    // the double width AMOCAS of an RV64 hart, the value being held with its low doubleword first
+    fn compare_exchange_u128(&mut self, address: usize, current: u128, new: u128) -> Result<Result<u128, u128>, Trap> {
+        let old = self.read_u64(address)? as u128 | (self.read_u64(address + 8)? as u128) << 64;
+        if old != current {
+            return Ok(Err(old));
+        }
+        self.write_u64(address, new as u64)?;
+        self.write_u64(address + 8, (new >> 64) as u64)?;
+        Ok(Ok(old))
+    }
+
+    // Stores value in every byte of range, faulting on the first address that cannot be written.
+    // Memories that can do it in bulk should override this and copy.
+    fn fill(&mut self, range: Range<GuestAddr>, value: u8) -> Result<(), Trap> {
+        range.into_iter().try_for_each(|a| self.write_u8(a, value))
+    }
+
+    // copies length bytes from source to destination, the two ranges must not overlap
+    fn copy(&mut self, source: GuestAddr, destination: GuestAddr, length: usize) -> Result<(), Trap> {
+        (0..length).try_for_each(|i| {
+            let byte = self.read_u8(source + i)?;
+            self.write_u8(destination + i, byte)
+        })
+    }
+
+    // every address in range where pattern starts, overlapping matches included
+    fn find(&self, pattern: &[u8], range: Range<GuestAddr>) -> Vec<GuestAddr> {
+        scan(self, pattern, None, range)
+    }
+
+    // like find, but only the bits set in mask have to match
+    fn find_masked(&self, pattern: &[u8], mask: &[u8], range: Range<GuestAddr>) -> Vec<GuestAddr> {
+        scan(self, pattern, Some(mask), range)
+    }
+}
+
+fn scan<M: Memory + ?Sized>(memory: &M, pattern: &[u8], mask: Option<&[u8]>, range: Range<GuestAddr>) -> Vec<GuestAddr> {
+    let mut found = Vec::new();
+    if pattern.is_empty() {
+        return found;
+    }
+
+    // each byte is read once, bytes that fault never match
+    let mut window: VecDeque<Option<u8>> = VecDeque::with_capacity(pattern.len());
+    for address in range {
+        if window.len() == pattern.len() {
+            window.pop_front();
+        }
+        window.push_back(memory.read_u8(address).ok());
+        if window.len() < pattern.len() {
+            continue;
+        }
+
+        let matched = window.iter().zip(pattern.iter()).enumerate().all(|(i, (byte, expected))| {
+            let mask = mask.and_then(|m| m.get(i).copied()).unwrap_or(0xff);
+            matches!(byte, Some(b) if b & mask == expected & mask)
+        });
+        if matched {
+            found.push(address + 1 - pattern.len());
+        }
+    }
+
+    found
+}
+
+// The form every backend is saved in, so a guest saved from one can be restored into another.
+// Pages are cow::PAGE_SIZE bytes and only those that are not all zero are kept.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Image {
+    size: usize,
+    pages: Vec<(usize, Vec<u8>)>
+}
+
+// the first size bytes of memory, only looking at the pages present says might not be all zero
+#[cfg(feature = "serde")]
+fn serialize_image<S: serde::Serializer>(serializer: S, memory: &dyn Memory, size: usize, present: impl Fn(usize) -> bool) -> Result<S::Ok, S::Error> {
+    use serde::Serialize;
+    let pages = (0..size.div_ceil(cow::PAGE_SIZE)).filter(|page| present(*page)).filter_map(|page| {
+        let start = page * cow::PAGE_SIZE;
+        let bytes = marshal::read_bytes(memory, start, cow::PAGE_SIZE.min(size - start)).ok()?;
+        bytes.iter().any(|byte| *byte != 0).then_some((page, bytes))
+    }).collect();
+    Image { size, pages }.serialize(serializer)
+}
+
+// fills in the memory new makes for the saved size
+#[cfg(feature = "serde")]
+fn deserialize_image<'de, D, M, F>(deserializer: D, new: F) -> Result<M, D::Error>
+    where D: serde::Deserializer<'de>, M: Memory, F: FnOnce(usize) -> Result<M, String> {
+    use serde::de::Error;
+    use serde::Deserialize;
+    let image = Image::deserialize(deserializer)?;
+    let mut memory = new(image.size).map_err(D::Error::custom)?;
+    for (page, bytes) in &image.pages {
+        marshal::write_bytes(&mut memory, page * cow::PAGE_SIZE, bytes)
+            .map_err(|trap| D::Error::custom(format!("page at {:#x} is outside the memory", trap.value)))?;
+    }
+    Ok(memory)
 }
 
 impl Memory for Vec<u8> {
@@ -153,4 +318,67 @@ impl Memory for Vec<u8> {
             })
         }
     }
-}
\ No newline at end of file
+
+    fn fill(&mut self, range: Range<GuestAddr>, value: u8) -> Result<(), Trap> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        let len = self.len();
+        self[range.start.min(len)..range.end.min(len)].fill(value);
+        if range.end > len {
+            return Err(Trap { trap_type: TrapType::StoreAccessFault, value: range.start.max(len) as u64 });
+        }
+        Ok(())
+    }
+
+    fn copy(&mut self, source: GuestAddr, destination: GuestAddr, length: usize) -> Result<(), Trap> {
+        let fits = |address: usize| address.checked_add(length).is_some_and(|end| end <= self.len());
+        if !fits(source) {
+            return Err(Trap { trap_type: TrapType::LoadAccessFault, value: source.max(self.len()) as u64 });
+        }
+        if !fits(destination) {
+            return Err(Trap { trap_type: TrapType::StoreAccessFault, value: destination.max(self.len()) as u64 });
+        }
+        self.copy_within(source..source + length, destination);
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn find_reports_overlapping_matches_in_range() {
+        let mut memory = vec![0u8; 32];
+        memory[4..9].copy_from_slice(&[0xaa, 0xaa, 0xaa, 0x12, 0x34]);
+        memory[30..32].copy_from_slice(&[0x12, 0x34]);
+
+        assert_eq!(vec![4, 5], memory.find(&[0xaa, 0xaa], 0..32));
+        assert_eq!(vec![7], memory.find(&[0x12, 0x34], 0..31));
+        // the match has to fit inside memory, reading past the end never matches
+        assert!(memory.find(&[0x34, 0x00], 28..40).is_empty());
+    }
+
+    #[test]
+    fn bulk_fill_and_copy_fault_past_the_end() {
+        let mut memory = vec![0u8; 16];
+        memory.fill(4..8, 0xaa).unwrap();
+        assert_eq!([0, 0xaa, 0xaa, 0xaa, 0xaa, 0], memory[3..9]);
+        assert!(matches!(memory.fill(12..20, 0xbb), Err(Trap { trap_type: TrapType::StoreAccessFault, value: 16 })));
+        assert_eq!(0xbb, memory[15]);
+
+        memory.copy(4, 9, 4).unwrap();
+        assert_eq!([0xaa; 4], memory[9..13]);
+        assert!(matches!(memory.copy(14, 0, 4), Err(Trap { trap_type: TrapType::LoadAccessFault, value: 16 })));
+        assert!(matches!(memory.copy(0, usize::MAX, 4), Err(Trap { trap_type: TrapType::StoreAccessFault, value: u64::MAX })));
+    }
+
+    #[test]
+    fn masked_scan_ignores_cleared_bits() {
+        let mut memory = vec![0u8; 16];
+        memory.write_u32(8, 0xcafe1234).unwrap();
+
+        assert_eq!(vec![8], memory.find_masked(&[0x00, 0x00, 0xfe, 0xca], &[0x00, 0x00, 0xff, 0xff], 0..16));
+        assert_eq!(vec![8], memory.find_masked(&[0x30, 0x12], &[0xf0, 0xff], 0..16));
+    }
+}
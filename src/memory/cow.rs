@@ -0,0 +1,268 @@
+use crate::cpu::{Trap, TrapType};
+use crate::memory::Memory;
+use std::ops::Range;
+use std::sync::Arc;
+
+pub const PAGE_SIZE: usize = 4096;
+
+pub(crate) type Page = [u8; PAGE_SIZE];
+
+// Paged guest memory where pages are shared between clones until one of them writes to it.
+// Pages that have never been written are not allocated at all and read as zero.
+#[derive(Clone)]
+pub struct CowMemory {
+    pages: Vec<Option<Arc<Page>>>,
+    size: usize
+}
+
+impl CowMemory {
+    pub fn new(size: usize) -> Self {
+        CowMemory {
+            pages: vec![None; size.div_ceil(PAGE_SIZE)],
+            size
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut memory = CowMemory::new(bytes.len());
+        for (index, chunk) in bytes.chunks(PAGE_SIZE).enumerate() {
+            if chunk.iter().any(|b| *b != 0) {
+                let mut page = [0u8; PAGE_SIZE];
+                page[..chunk.len()].copy_from_slice(chunk);
+                memory.pages[index] = Some(Arc::new(page));
+            }
+        }
+
+        memory
+    }
+
+    // cheap duplicate, only the page table is copied
+    pub fn clone_cow(&self) -> Self {
+        self.clone()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // number of pages this instance holds that are not shared with any other clone
+    pub fn private_pages(&self) -> usize {
+        self.pages.iter().flatten().filter(|page| Arc::strong_count(page) == 1).count()
+    }
+
+    // Every page that differs from the one at the same index in base, None for a page that
+    // reads as zero. Pages still shared with base are the same without looking at them.
+    pub(crate) fn delta(&self, base: &CowMemory) -> Vec<(usize, Option<Arc<Page>>)> {
+        self.pages.iter().enumerate().filter(|(index, page)| match (page, base.pages.get(*index).and_then(Option::as_ref)) {
+            (Some(page), Some(other)) => !Arc::ptr_eq(page, other) && page != other,
+            (None, None) => false,
+            _ => true
+        }).map(|(index, page)| (index, page.clone())).collect()
+    }
+
+    // base resized to size with the pages in delta put in place of its own
+    pub(crate) fn with_delta(base: &CowMemory, size: usize, delta: &[(usize, Option<Arc<Page>>)]) -> CowMemory {
+        let mut memory = CowMemory { pages: base.pages.clone(), size };
+        memory.pages.resize(size.div_ceil(PAGE_SIZE), None);
+        let count = memory.pages.len();
+        for (index, page) in delta.iter().filter(|(index, _)| *index < count) {
+            memory.pages[*index] = page.clone();
+        }
+        memory
+    }
+
+    fn in_bounds(&self, address: usize, length: usize) -> bool {
+        match address.checked_add(length) {
+            Some(end) => end <= self.size,
+            None => false
+        }
+    }
+
+    fn check(&self, address: usize, length: usize, trap_type: TrapType) -> Result<(), Trap> {
+        if self.in_bounds(address, length) {
+            Ok(())
+        } else {
+            Err(Trap { trap_type, value: address.max(self.size) as u64 })
+        }
+    }
+
+    fn load<const N: usize>(&self, address: usize) -> Result<[u8; N], Trap> {
+        if !self.in_bounds(address, N) {
+            return Err(Trap {
+                trap_type: TrapType::LoadAccessFault,
+                value: address as u64
+            });
+        }
+
+        let mut result = [0u8; N];
+        for (i, byte) in result.iter_mut().enumerate() {
+            let offset = address + i;
+            if let Some(page) = &self.pages[offset / PAGE_SIZE] {
+                *byte = page[offset % PAGE_SIZE];
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn store<const N: usize>(&mut self, address: usize, bytes: [u8; N]) -> Result<(), Trap> {
+        if !self.in_bounds(address, N) {
+            return Err(Trap {
+                trap_type: TrapType::StoreAccessFault,
+                value: address as u64
+            });
+        }
+
+        for (i, byte) in bytes.iter().enumerate() {
+            let offset = address + i;
+            let page = self.pages[offset / PAGE_SIZE].get_or_insert_with(|| Arc::new([0u8; PAGE_SIZE]));
+            Arc::make_mut(page)[offset % PAGE_SIZE] = *byte;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for CowMemory {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_image(serializer, self, self.size, |page| self.pages[page].is_some())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for CowMemory {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        super::deserialize_image(deserializer, |size| Ok(CowMemory::new(size)))
+    }
+}
+
+impl Memory for CowMemory {
+    fn read_i8(&self, address: usize) -> Result<i8, Trap> {
+        Ok(i8::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u8(&self, address: usize) -> Result<u8, Trap> {
+        Ok(u8::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_i16(&self, address: usize) -> Result<i16, Trap> {
+        Ok(i16::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u16(&self, address: usize) -> Result<u16, Trap> {
+        Ok(u16::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_i32(&self, address: usize) -> Result<i32, Trap> {
+        Ok(i32::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u32(&self, address: usize) -> Result<u32, Trap> {
+        Ok(u32::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_i64(&self, address: usize) -> Result<i64, Trap> {
+        Ok(i64::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u64(&self, address: usize) -> Result<u64, Trap> {
+        Ok(u64::from_le_bytes(self.load(address)?))
+    }
+
+    fn write_u8(&mut self, address: usize, value: u8) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, address: usize, value: u32) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, address: usize, value: u64) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn fill(&mut self, range: Range<usize>, value: u8) -> Result<(), Trap> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        self.check(range.start, range.end - range.start, TrapType::StoreAccessFault)?;
+        for index in range.start / PAGE_SIZE..range.end.div_ceil(PAGE_SIZE) {
+            let start = range.start.max(index * PAGE_SIZE) - index * PAGE_SIZE;
+            let end = range.end.min((index + 1) * PAGE_SIZE) - index * PAGE_SIZE;
+            // zeroing a whole page, or any of one that was never written, needs no page at all
+            if value == 0 && (end - start == PAGE_SIZE || self.pages[index].is_none()) {
+                self.pages[index] = None;
+                continue;
+            }
+            let page = self.pages[index].get_or_insert_with(|| Arc::new([0u8; PAGE_SIZE]));
+            Arc::make_mut(page)[start..end].fill(value);
+        }
+        Ok(())
+    }
+
+    fn copy(&mut self, source: usize, destination: usize, length: usize) -> Result<(), Trap> {
+        self.check(source, length, TrapType::LoadAccessFault)?;
+        self.check(destination, length, TrapType::StoreAccessFault)?;
+        let mut offset = 0;
+        while offset < length {
+            // a piece at a time, none spanning a page boundary on either side
+            let from = source + offset;
+            let to = destination + offset;
+            let piece = (length - offset).min(PAGE_SIZE - from % PAGE_SIZE).min(PAGE_SIZE - to % PAGE_SIZE);
+            match self.pages[from / PAGE_SIZE].clone() {
+                // whole pages are shared rather than copied
+                Some(page) if piece == PAGE_SIZE => self.pages[to / PAGE_SIZE] = Some(page),
+                Some(page) => {
+                    let target = self.pages[to / PAGE_SIZE].get_or_insert_with(|| Arc::new([0u8; PAGE_SIZE]));
+                    Arc::make_mut(target)[to % PAGE_SIZE..to % PAGE_SIZE + piece].copy_from_slice(&page[from % PAGE_SIZE..from % PAGE_SIZE + piece]);
+                }
+                None => self.fill(to..to + piece, 0)?
+            }
+            offset += piece;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clones_share_pages_until_written() {
+        let mut original = CowMemory::from_bytes(&[1u8; PAGE_SIZE * 4]);
+        let mut copy = original.clone_cow();
+        assert_eq!(0, copy.private_pages());
+
+        copy.write_u32(PAGE_SIZE + 4, 0xdeadbeef).expect("write");
+        assert_eq!(0xdeadbeef, copy.read_u32(PAGE_SIZE + 4).expect("read"));
+        assert_eq!(0x01010101, original.read_u32(PAGE_SIZE + 4).expect("read"));
+        assert_eq!(1, copy.private_pages());
+
+        original.write_u16(PAGE_SIZE - 1, 0xabcd).expect("write across pages");
+        assert_eq!(0xabcd, original.read_u16(PAGE_SIZE - 1).expect("read"));
+        assert_eq!(0x0101, copy.read_u16(PAGE_SIZE - 1).expect("read"));
+    }
+
+    #[test]
+    fn zeroing_whole_pages_drops_them() {
+        let mut memory = CowMemory::from_bytes(&[1u8; PAGE_SIZE * 4]);
+        memory.fill(PAGE_SIZE - 2..PAGE_SIZE * 3, 0).expect("fill");
+        assert_eq!(0x0101, memory.read_u16(PAGE_SIZE - 4).expect("read"));
+        assert_eq!(0, memory.read_u16(PAGE_SIZE - 2).expect("read"));
+        assert_eq!(2, memory.pages.iter().flatten().count());
+
+        memory.copy(0, 2 * PAGE_SIZE, PAGE_SIZE + 8).expect("copy");
+        assert_eq!(0x0101, memory.read_u16(2 * PAGE_SIZE).expect("read"));
+        assert_eq!(0, memory.read_u16(3 * PAGE_SIZE - 2).expect("read"));
+        assert_eq!(0, memory.read_u64(3 * PAGE_SIZE).expect("read"));
+        assert_eq!(0x0101, memory.read_u16(3 * PAGE_SIZE + 8).expect("read"));
+        assert!(memory.fill(PAGE_SIZE * 4 - 1..PAGE_SIZE * 4 + 1, 0).is_err());
+        assert!(memory.copy(0, PAGE_SIZE * 4, 1).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::cpu::{Trap, TrapType};
+use crate::memory::Memory;
+use std::ops::Range;
+
+const NULL_PAGE: Range<usize> = 0..4096;
+
+// Wraps a memory so that any access touching the guard region faults with the offending address,
+// which turns guest null pointer dereferences into a trap instead of a read of the first page.
+pub struct GuardedMemory<M: Memory> {
+    inner: M,
+    guard: Range<usize>
+}
+
+impl<M: Memory> GuardedMemory<M> {
+    // guards page 0
+    pub fn new(inner: M) -> Self {
+        GuardedMemory::with_guard(inner, NULL_PAGE)
+    }
+
+    pub fn with_guard(inner: M, guard: Range<usize>) -> Self {
+        GuardedMemory { inner, guard }
+    }
+
+    pub fn guard(&self) -> &Range<usize> {
+        &self.guard
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    // bypasses the guard, for loaders that need to place data before the guest starts
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    fn check(&self, address: usize, size: usize, trap_type: TrapType) -> Result<(), Trap> {
+        if address < self.guard.end && address.saturating_add(size) > self.guard.start {
+            Err(Trap {
+                trap_type,
+                value: address as u64
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<M: Memory> Memory for GuardedMemory<M> {
+    fn read_i8(&self, address: usize) -> Result<i8, Trap> {
+        self.check(address, 1, TrapType::LoadAccessFault)?;
+        self.inner.read_i8(address)
+    }
+
+    fn read_u8(&self, address: usize) -> Result<u8, Trap> {
+        self.check(address, 1, TrapType::LoadAccessFault)?;
+        self.inner.read_u8(address)
+    }
+
+    fn read_i16(&self, address: usize) -> Result<i16, Trap> {
+        self.check(address, 2, TrapType::LoadAccessFault)?;
+        self.inner.read_i16(address)
+    }
+
+    fn read_u16(&self, address: usize) -> Result<u16, Trap> {
+        self.check(address, 2, TrapType::LoadAccessFault)?;
+        self.inner.read_u16(address)
+    }
+
+    fn read_i32(&self, address: usize) -> Result<i32, Trap> {
+        self.check(address, 4, TrapType::LoadAccessFault)?;
+        self.inner.read_i32(address)
+    }
+
+    fn read_u32(&self, address: usize) -> Result<u32, Trap> {
+        self.check(address, 4, TrapType::LoadAccessFault)?;
+        self.inner.read_u32(address)
+    }
+
+    fn read_i64(&self, address: usize) -> Result<i64, Trap> {
+        self.check(address, 8, TrapType::LoadAccessFault)?;
+        self.inner.read_i64(address)
+    }
+
+    fn read_u64(&self, address: usize) -> Result<u64, Trap> {
+        self.check(address, 8, TrapType::LoadAccessFault)?;
+        self.inner.read_u64(address)
+    }
+
+    fn write_u8(&mut self, address: usize, value: u8) -> Result<(), Trap> {
+        self.check(address, 1, TrapType::StoreAccessFault)?;
+        self.inner.write_u8(address, value)
+    }
+
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), Trap> {
+        self.check(address, 2, TrapType::StoreAccessFault)?;
+        self.inner.write_u16(address, value)
+    }
+
+    fn write_u32(&mut self, address: usize, value: u32) -> Result<(), Trap> {
+        self.check(address, 4, TrapType::StoreAccessFault)?;
+        self.inner.write_u32(address, value)
+    }
+
+    fn write_u64(&mut self, address: usize, value: u64) -> Result<(), Trap> {
+        self.check(address, 8, TrapType::StoreAccessFault)?;
+        self.inner.write_u64(address, value)
+    }
+
+    fn fence(&mut self) {
+        self.inner.fence();
+    }
+
+    fn compare_exchange_u8(&mut self, address: usize, current: u8, new: u8) -> Result<Result<u8, u8>, Trap> {
+        self.check(address, 1, TrapType::StoreAccessFault)?;
+        self.inner.compare_exchange_u8(address, current, new)
+    }
+
+    fn compare_exchange_u16(&mut self, address: usize, current: u16, new: u16) -> Result<Result<u16, u16>, Trap> {
+        self.check(address, 2, TrapType::StoreAccessFault)?;
+        self.inner.compare_exchange_u16(address, current, new)
+    }
+
+    fn compare_exchange_u32(&mut self, address: usize, current: u32, new: u32) -> Result<Result<u32, u32>, Trap> {
+        self.check(address, 4, TrapType::StoreAccessFault)?;
+        self.inner.compare_exchange_u32(address, current, new)
+    }
+
+    fn compare_exchange_u64(&mut self, address: usize, current: u64, new: u64) -> Result<Result<u64, u64>, Trap> {
+        self.check(address, 8, TrapType::StoreAccessFault)?;
+        self.inner.compare_exchange_u64(address, current, new)
+    }
+
+    fn compare_exchange_u128(&mut self, address: usize, current: u128, new: u128) -> Result<Result<u128, u128>, Trap> {
+        self.check(address, 16, TrapType::StoreAccessFault)?;
+        self.inner.compare_exchange_u128(address, current, new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn null_dereference_traps_with_address() {
+        let mut memory = GuardedMemory::new(vec![0u8; 0x3000]);
+        // ld a0, 8(zero)
+        memory.inner_mut().write_u32(0x2000, 0x00803503).unwrap();
+        let mut cpu = Cpu::builder().pc(0x2000).build();
+
+        let trap = cpu.tick(&mut memory).expect_err("null load");
+        assert_eq!(TrapType::LoadAccessFault, trap.trap_type);
+        assert_eq!(8, trap.value);
+
+        assert!(memory.write_u16(0xfff, 1).is_err());
+        assert!(memory.write_u16(0x1000, 1).is_ok());
+    }
+
+    #[test]
+    fn jump_to_null_is_an_instruction_fault() {
+        let mut memory = GuardedMemory::with_guard(vec![0u8; 0x3000], 0..0x2000);
+        let mut cpu = Cpu::builder().pc(0x100).build();
+
+        let trap = cpu.tick(&mut memory).expect_err("fetch from guard");
+        assert_eq!(TrapType::InstructionAccessFault, trap.trap_type);
+        assert_eq!(0x100, trap.value);
+    }
+}
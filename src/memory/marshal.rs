@@ -0,0 +1,128 @@
+use crate::cpu::{Trap, TrapType};
+use crate::memory::{GuestAddr, Memory};
+use std::mem::{size_of, MaybeUninit};
+
+// Moving host values in and out of guest memory, for ecall handlers and host functions. Every
+// helper checks the whole range it touches, an access that would run past the end of the
+// address space or onto a byte that faults comes back as the trap for it.
+
+// A type every bit pattern is a valid value of and that has no padding, so it can be copied
+// to and from guest memory byte for byte. A #[repr(C)] struct made only of Plain fields and
+// laid out without gaps can implement it too. Values are copied in the host's byte order,
+// which is the guest's on every little endian host. Implementing it for a type with padding,
+// references, or invalid bit patterns such as bool is undefined behaviour.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe trait Plain: Copy + 'static {}
+
+unsafe impl Plain for u8 {}
+unsafe impl Plain for u16 {}
+unsafe impl Plain for u32 {}
+unsafe impl Plain for u64 {}
+unsafe impl Plain for i8 {}
+unsafe impl Plain for i16 {}
+unsafe impl Plain for i32 {}
+unsafe impl Plain for i64 {}
+unsafe impl Plain for f32 {}
+unsafe impl Plain for f64 {}
+unsafe impl<T: Plain, const N: usize> Plain for [T; N] {}
+
+fn end(address: GuestAddr, length: usize, trap_type: TrapType) -> Result<GuestAddr, Trap> {
+    address.checked_add(length).ok_or(Trap { trap_type, value: address as u64 })
+}
+
+pub fn read_bytes(memory: &dyn Memory, address: GuestAddr, length: usize) -> Result<Vec<u8>, Trap> {
+    (address..end(address, length, TrapType::LoadAccessFault)?).map(|a| memory.read_u8(a)).collect()
+}
+
+pub fn write_bytes(memory: &mut dyn Memory, address: GuestAddr, bytes: &[u8]) -> Result<(), Trap> {
+    end(address, bytes.len(), TrapType::StoreAccessFault)?;
+    bytes.iter().enumerate().try_for_each(|(i, byte)| memory.write_u8(address + i, *byte))
+}
+
+// The bytes of the NUL terminated string at address, without the NUL. A string that has no NUL
+// within its first limit bytes faults on the byte where the NUL should have been.
+pub fn read_c_string(memory: &dyn Memory, address: GuestAddr, limit: usize) -> Result<Vec<u8>, Trap> {
+    let mut bytes = Vec::new();
+    loop {
+        let at = end(address, bytes.len(), TrapType::LoadAccessFault)?;
+        match memory.read_u8(at)? {
+            0 => return Ok(bytes),
+            _ if bytes.len() == limit => return Err(Trap { trap_type: TrapType::LoadAccessFault, value: at as u64 }),
+            byte => bytes.push(byte)
+        }
+    }
+}
+
+// the bytes followed by a NUL
+pub fn write_c_string(memory: &mut dyn Memory, address: GuestAddr, string: &[u8]) -> Result<(), Trap> {
+    write_bytes(memory, address, &[string, &[0]].concat())
+}
+
+// A buffer stored as its length, a u64, followed by that many bytes. One longer than limit
+// faults on its first byte past the limit.
+pub fn read_buffer(memory: &dyn Memory, address: GuestAddr, limit: usize) -> Result<Vec<u8>, Trap> {
+    let length = memory.read_u64(address)? as usize;
+    let start = end(address, size_of::<u64>(), TrapType::LoadAccessFault)?;
+    if length > limit {
+        return Err(Trap { trap_type: TrapType::LoadAccessFault, value: (start + limit) as u64 });
+    }
+    read_bytes(memory, start, length)
+}
+
+pub fn write_buffer(memory: &mut dyn Memory, address: GuestAddr, bytes: &[u8]) -> Result<(), Trap> {
+    write_bytes(memory, address, &[&(bytes.len() as u64).to_le_bytes(), bytes].concat())
+}
+
+pub fn read_struct<T: Plain>(memory: &dyn Memory, address: GuestAddr) -> Result<T, Trap> {
+    let bytes = read_bytes(memory, address, size_of::<T>())?;
+    let mut value = MaybeUninit::<T>::uninit();
+    // Plain types are valid for any bytes and T is exactly as big as what was read
+    unsafe {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), value.as_mut_ptr() as *mut u8, bytes.len());
+        Ok(value.assume_init())
+    }
+}
+
+pub fn write_struct<T: Plain>(memory: &mut dyn Memory, address: GuestAddr, value: &T) -> Result<(), Trap> {
+    // Plain types have no padding, so every byte of one is initialized
+    let bytes = unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    write_bytes(memory, address, bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Timespec {
+        seconds: i64,
+        nanoseconds: u32,
+        flags: [u8; 4]
+    }
+
+    unsafe impl Plain for Timespec {}
+
+    #[test]
+    fn values_round_trip_with_bounds_checks() {
+        let mut memory = vec![0xffu8; 0x40];
+
+        write_c_string(&mut memory, 0x00, b"hello").unwrap();
+        assert_eq!(b"hello".to_vec(), read_c_string(&memory, 0x00, 5).unwrap());
+        let trap = read_c_string(&memory, 0x00, 4).unwrap_err();
+        assert_eq!((TrapType::LoadAccessFault, 0x04), (trap.trap_type, trap.value));
+        // no NUL before the end of memory
+        assert!(read_c_string(&memory, 0x38, 64).is_err());
+
+        write_buffer(&mut memory, 0x08, b"abc").unwrap();
+        assert_eq!(b"abc".to_vec(), read_buffer(&memory, 0x08, 3).unwrap());
+        assert!(read_buffer(&memory, 0x08, 2).is_err());
+
+        let time = Timespec { seconds: -2, nanoseconds: 500, flags: [1, 2, 3, 4] };
+        write_struct(&mut memory, 0x20, &time).unwrap();
+        assert_eq!(time, read_struct(&memory, 0x20).unwrap());
+        assert_eq!(500, memory.read_u32(0x28).unwrap());
+        assert!(write_struct(&mut memory, 0x38, &time).is_err());
+        assert!(read_bytes(&memory, usize::MAX, 2).is_err());
+    }
+}
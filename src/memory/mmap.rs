@@ -0,0 +1,352 @@
+use crate::cpu::{Trap, TrapType};
+use crate::memory::Memory;
+use std::ffi::c_void;
+use std::io;
+use std::ops::Range;
+
+// pages are tracked at this granularity so we can report how much of the reservation the guest
+// has actually touched, and so the Windows backend knows which pages still need committing
+const PAGE_SIZE: usize = 4096;
+
+pub struct MmapMemory {
+    base: *mut u8,
+    size: usize,
+    touched: Vec<u64>
+}
+
+// the mapping is exclusively owned by this value
+unsafe impl Send for MmapMemory {}
+
+impl MmapMemory {
+    // reserves `size` bytes of guest address space, nothing is committed until it is written
+    pub fn new(size: usize) -> io::Result<Self> {
+        let base = os::reserve(size)?;
+        let pages = size.div_ceil(PAGE_SIZE);
+
+        Ok(MmapMemory {
+            base,
+            size,
+            touched: vec![0; pages.div_ceil(64)]
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn committed_bytes(&self) -> usize {
+        self.touched.iter().map(|bits| bits.count_ones() as usize).sum::<usize>() * PAGE_SIZE
+    }
+
+    fn is_touched(&self, page: usize) -> bool {
+        self.touched[page / 64] & (1 << (page % 64)) != 0
+    }
+
+    fn in_bounds(&self, address: usize, length: usize) -> bool {
+        match address.checked_add(length) {
+            Some(end) => end <= self.size,
+            None => false
+        }
+    }
+
+    fn load<const N: usize>(&self, address: usize) -> Result<[u8; N], Trap> {
+        if !self.in_bounds(address, N) {
+            return Err(Trap {
+                trap_type: TrapType::LoadAccessFault,
+                value: address as u64
+            });
+        }
+
+        let mut result = [0u8; N];
+        for (i, byte) in result.iter_mut().enumerate() {
+            let offset = address + i;
+            // untouched pages read as zero without being committed
+            if !os::READS_UNCOMMITTED && !self.is_touched(offset / PAGE_SIZE) {
+                continue;
+            }
+            *byte = unsafe { *self.base.add(offset) };
+        }
+
+        Ok(result)
+    }
+
+    fn store<const N: usize>(&mut self, address: usize, bytes: [u8; N]) -> Result<(), Trap> {
+        if !self.in_bounds(address, N) {
+            return Err(Trap {
+                trap_type: TrapType::StoreAccessFault,
+                value: address as u64
+            });
+        }
+
+        self.commit(address, N)?;
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.base.add(address), N); }
+        Ok(())
+    }
+
+    // makes the pages under length bytes from address, which must be in bounds, writable
+    fn commit(&mut self, address: usize, length: usize) -> Result<(), Trap> {
+        for page in address / PAGE_SIZE..(address + length).div_ceil(PAGE_SIZE) {
+            if !self.is_touched(page) {
+                let start = page * PAGE_SIZE;
+                let length = PAGE_SIZE.min(self.size - start);
+                if os::commit(unsafe { self.base.add(start) }, length).is_err() {
+                    return Err(Trap {
+                        trap_type: TrapType::StoreAccessFault,
+                        value: address as u64
+                    });
+                }
+                self.touched[page / 64] |= 1 << (page % 64);
+            }
+        }
+        Ok(())
+    }
+
+    fn check(&self, address: usize, length: usize, trap_type: TrapType) -> Result<(), Trap> {
+        if self.in_bounds(address, length) {
+            Ok(())
+        } else {
+            Err(Trap { trap_type, value: address.max(self.size) as u64 })
+        }
+    }
+}
+
+impl Drop for MmapMemory {
+    fn drop(&mut self) {
+        os::release(self.base, self.size);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for MmapMemory {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_image(serializer, self, self.size, |page| self.is_touched(page))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for MmapMemory {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        super::deserialize_image(deserializer, |size| MmapMemory::new(size).map_err(|e| e.to_string()))
+    }
+}
+
+impl Memory for MmapMemory {
+    fn read_i8(&self, address: usize) -> Result<i8, Trap> {
+        Ok(i8::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u8(&self, address: usize) -> Result<u8, Trap> {
+        Ok(u8::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_i16(&self, address: usize) -> Result<i16, Trap> {
+        Ok(i16::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u16(&self, address: usize) -> Result<u16, Trap> {
+        Ok(u16::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_i32(&self, address: usize) -> Result<i32, Trap> {
+        Ok(i32::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u32(&self, address: usize) -> Result<u32, Trap> {
+        Ok(u32::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_i64(&self, address: usize) -> Result<i64, Trap> {
+        Ok(i64::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u64(&self, address: usize) -> Result<u64, Trap> {
+        Ok(u64::from_le_bytes(self.load(address)?))
+    }
+
+    fn write_u8(&mut self, address: usize, value: u8) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, address: usize, value: u32) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, address: usize, value: u64) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn fill(&mut self, range: Range<usize>, value: u8) -> Result<(), Trap> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        self.check(range.start, range.end - range.start, TrapType::StoreAccessFault)?;
+        for page in range.start / PAGE_SIZE..range.end.div_ceil(PAGE_SIZE) {
+            // untouched pages already read as zero, so zeroing them would only commit them
+            if value == 0 && !self.is_touched(page) {
+                continue;
+            }
+            let start = range.start.max(page * PAGE_SIZE);
+            let end = range.end.min((page + 1) * PAGE_SIZE);
+            self.commit(start, end - start)?;
+            unsafe { std::ptr::write_bytes(self.base.add(start), value, end - start); }
+        }
+        Ok(())
+    }
+
+    fn copy(&mut self, source: usize, destination: usize, length: usize) -> Result<(), Trap> {
+        self.check(source, length, TrapType::LoadAccessFault)?;
+        self.check(destination, length, TrapType::StoreAccessFault)?;
+        let mut offset = 0;
+        while offset < length {
+            // a piece at a time, none spanning a page boundary on either side, and untouched
+            // source pages are known to read as zero
+            let from = source + offset;
+            let to = destination + offset;
+            let piece = (length - offset).min(PAGE_SIZE - from % PAGE_SIZE).min(PAGE_SIZE - to % PAGE_SIZE);
+            if self.is_touched(from / PAGE_SIZE) {
+                self.commit(to, piece)?;
+                unsafe { std::ptr::copy(self.base.add(from), self.base.add(to), piece); }
+            } else {
+                self.fill(to..to + piece, 0)?;
+            }
+            offset += piece;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+mod os {
+    use super::c_void;
+    use std::io;
+
+    const PROT_READ: i32 = 1;
+    const PROT_WRITE: i32 = 2;
+    const MAP_PRIVATE: i32 = 2;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const MAP_ANONYMOUS: i32 = 0x20;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const MAP_ANONYMOUS: i32 = 0x1000;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const MAP_NORESERVE: i32 = 0x4000;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const MAP_NORESERVE: i32 = 0;
+
+    // anonymous private mappings are backed by the shared zero page until written, so the kernel
+    // does the lazy commit for us
+    pub const READS_UNCOMMITTED: bool = true;
+
+    extern "C" {
+        fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, offset: isize) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> i32;
+    }
+
+    pub fn reserve(size: usize) -> io::Result<*mut u8> {
+        let result = unsafe {
+            mmap(std::ptr::null_mut(), size.max(1), PROT_READ | PROT_WRITE,
+                 MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, -1, 0)
+        };
+        if result as isize == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result as *mut u8)
+        }
+    }
+
+    pub fn commit(_address: *mut u8, _length: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn release(address: *mut u8, size: usize) {
+        unsafe { munmap(address as *mut c_void, size.max(1)); }
+    }
+}
+
+#[cfg(windows)]
+mod os {
+    use super::c_void;
+    use std::io;
+
+    const MEM_COMMIT: u32 = 0x1000;
+    const MEM_RESERVE: u32 = 0x2000;
+    const MEM_RELEASE: u32 = 0x8000;
+    const PAGE_NOACCESS: u32 = 0x01;
+    const PAGE_READWRITE: u32 = 0x04;
+
+    // reserved but uncommitted pages fault on access, so reads must skip them
+    pub const READS_UNCOMMITTED: bool = false;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn VirtualAlloc(address: *mut c_void, size: usize, allocation_type: u32, protect: u32) -> *mut c_void;
+        fn VirtualFree(address: *mut c_void, size: usize, free_type: u32) -> i32;
+    }
+
+    pub fn reserve(size: usize) -> io::Result<*mut u8> {
+        let result = unsafe { VirtualAlloc(std::ptr::null_mut(), size.max(1), MEM_RESERVE, PAGE_NOACCESS) };
+        if result.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result as *mut u8)
+        }
+    }
+
+    pub fn commit(address: *mut u8, length: usize) -> io::Result<()> {
+        let result = unsafe { VirtualAlloc(address as *mut c_void, length, MEM_COMMIT, PAGE_READWRITE) };
+        if result.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn release(address: *mut u8, _size: usize) {
+        unsafe { VirtualFree(address as *mut c_void, 0, MEM_RELEASE); }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn large_reservation_commits_lazily() {
+        let mut memory = MmapMemory::new(1 << 32).expect("reserve 4GiB");
+        assert_eq!(0, memory.committed_bytes());
+
+        memory.write_u64(0xffff_fff0, 0x1122334455667788).expect("write");
+        assert_eq!(0x1122334455667788, memory.read_u64(0xffff_fff0).expect("read"));
+        assert_eq!(0, memory.read_u32(0x1000_0000).expect("read untouched"));
+        assert_eq!(PAGE_SIZE, memory.committed_bytes());
+    }
+
+    #[test]
+    fn zeroing_leaves_untouched_pages_uncommitted() {
+        let mut memory = MmapMemory::new(4 * PAGE_SIZE).expect("reserve");
+        memory.write_u32(PAGE_SIZE + 8, 0xcafe).expect("write");
+        memory.fill(0..4 * PAGE_SIZE, 0).expect("fill");
+        assert_eq!(0, memory.read_u32(PAGE_SIZE + 8).expect("read"));
+        assert_eq!(PAGE_SIZE, memory.committed_bytes());
+
+        memory.fill(PAGE_SIZE - 2..PAGE_SIZE + 2, 0x11).expect("fill");
+        memory.copy(PAGE_SIZE - 2, 3 * PAGE_SIZE + 100, 4).expect("copy");
+        assert_eq!(0x11111111, memory.read_u32(3 * PAGE_SIZE + 100).expect("read"));
+        memory.copy(2 * PAGE_SIZE, 3 * PAGE_SIZE + 100, 4).expect("copy");
+        assert_eq!(0, memory.read_u32(3 * PAGE_SIZE + 100).expect("read"));
+        assert!(memory.fill(4 * PAGE_SIZE - 1..4 * PAGE_SIZE + 1, 0).is_err());
+        assert!(memory.copy(usize::MAX, 0, 2).is_err());
+    }
+
+    #[test]
+    fn out_of_range_access_faults() {
+        let mut memory = MmapMemory::new(PAGE_SIZE).expect("reserve");
+        assert!(memory.read_u32(PAGE_SIZE - 2).is_err());
+        assert!(memory.write_u8(PAGE_SIZE, 1).is_err());
+        assert!(memory.write_u16(usize::MAX, 1).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use crate::cpu::Trap;
+use crate::memory::Memory;
+use std::ops::Range;
+
+#[derive(Clone, Debug)]
+pub struct ReorderConfig {
+    pub seed: u64,
+    // strict mode writes straight through, which is the same as not using the adapter at all
+    pub strict: bool,
+    // the most device stores that may be in flight before the oldest is forced out
+    pub max_pending: usize,
+    // chance (out of 256) that a store is retired after each device access
+    pub drain_chance: u8
+}
+
+impl Default for ReorderConfig {
+    fn default() -> Self {
+        ReorderConfig {
+            seed: 0x2545f4914f6cdd1d,
+            strict: false,
+            max_pending: 8,
+            drain_chance: 64
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PendingStore {
+    address: usize,
+    size: usize,
+    value: u64
+}
+
+// Wraps a memory and lets stores to device regions linger in a store buffer until a FENCE, so
+// guest drivers that forget a barrier see their device accesses happen out of program order.
+// Loads always observe the hart's own earlier stores, as RVWMO requires.
+pub struct ReorderingMemory<M: Memory> {
+    inner: M,
+    devices: Vec<Range<usize>>,
+    config: ReorderConfig,
+    rng: u64,
+    pending: Vec<PendingStore>
+}
+
+impl<M: Memory> ReorderingMemory<M> {
+    pub fn new(inner: M, config: ReorderConfig) -> Self {
+        ReorderingMemory {
+            inner,
+            devices: Vec::new(),
+            rng: config.seed.max(1),
+            config,
+            pending: Vec::new()
+        }
+    }
+
+    pub fn add_device_region(&mut self, region: Range<usize>) {
+        self.devices.push(region);
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn into_inner(mut self) -> M {
+        self.flush();
+        self.inner
+    }
+
+    pub fn pending_stores(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn flush(&mut self) {
+        while !self.pending.is_empty() {
+            self.retire(0);
+        }
+    }
+
+    // whether all size bytes at address lie inside one device region
+    fn is_device(&self, address: usize, size: usize) -> bool {
+        self.devices.iter().any(|r| r.start <= address && address.checked_add(size).is_some_and(|end| end <= r.end))
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift64, good enough to shuffle stores and fully determined by the seed
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn retire(&mut self, index: usize) {
+        let store = self.pending.remove(index);
+        // the original store already passed the bounds check when it was issued, a fault here
+        // can only come from the device itself and there is nobody left to report it to
+        let _ = match store.size {
+            1 => self.inner.write_u8(store.address, store.value as u8),
+            2 => self.inner.write_u16(store.address, store.value as u16),
+            4 => self.inner.write_u32(store.address, store.value as u32),
+            _ => self.inner.write_u64(store.address, store.value)
+        };
+    }
+
+    fn settle(&mut self) {
+        while self.pending.len() > self.config.max_pending {
+            self.retire(0);
+        }
+        if !self.pending.is_empty() && (self.next_random() & 0xff) < self.config.drain_chance as u64 {
+            // stores to different addresses may become visible in any order
+            let index = (self.next_random() % self.pending.len() as u64) as usize;
+            let PendingStore { address, size, .. } = self.pending[index];
+            // but stores to overlapping bytes must stay in program order
+            let first = self.pending.iter().position(|p| p.address < address + size && address < p.address + p.size).unwrap_or(index);
+            self.retire(first);
+        }
+    }
+
+    // Only a store that lies wholly inside a device region is held back, and a device region is
+    // taken to be there to store to. Anything else goes straight through, so the memory
+    // underneath reports its faults at the instruction that made them.
+    fn store(&mut self, address: usize, size: usize, value: u64) -> Result<(), Trap> {
+        if self.config.strict || !self.is_device(address, size) {
+            return match size {
+                1 => self.inner.write_u8(address, value as u8),
+                2 => self.inner.write_u16(address, value as u16),
+                4 => self.inner.write_u32(address, value as u32),
+                _ => self.inner.write_u64(address, value)
+            };
+        }
+
+        let last = self.pending.len().checked_sub(1);
+        match last.map(|i| self.pending[i]) {
+            // back to back writes of the same register coalesce into one
+            Some(p) if p.address == address && p.size == size => self.pending[last.unwrap()].value = value,
+            _ => self.pending.push(PendingStore { address, size, value })
+        }
+        self.settle();
+        Ok(())
+    }
+
+    // overlay any buffered stores on top of what the device currently holds
+    fn forward(&self, address: usize, size: usize, value: u64) -> u64 {
+        let mut bytes = value.to_le_bytes();
+        for store in &self.pending {
+            let store_bytes = store.value.to_le_bytes();
+            for (i, byte) in store_bytes.iter().take(store.size).enumerate() {
+                let a = store.address + i;
+                if a >= address && a < address + size {
+                    bytes[a - address] = *byte;
+                }
+            }
+        }
+        u64::from_le_bytes(bytes)
+    }
+}
+
+impl<M: Memory> Memory for ReorderingMemory<M> {
+    fn read_i8(&self, address: usize) -> Result<i8, Trap> {
+        Ok(self.read_u8(address)? as i8)
+    }
+
+    fn read_u8(&self, address: usize) -> Result<u8, Trap> {
+        let value = self.inner.read_u8(address)?;
+        Ok(self.forward(address, 1, value as u64) as u8)
+    }
+
+    fn read_i16(&self, address: usize) -> Result<i16, Trap> {
+        Ok(self.read_u16(address)? as i16)
+    }
+
+    fn read_u16(&self, address: usize) -> Result<u16, Trap> {
+        let value = self.inner.read_u16(address)?;
+        Ok(self.forward(address, 2, value as u64) as u16)
+    }
+
+    fn read_i32(&self, address: usize) -> Result<i32, Trap> {
+        Ok(self.read_u32(address)? as i32)
+    }
+
+    fn read_u32(&self, address: usize) -> Result<u32, Trap> {
+        let value = self.inner.read_u32(address)?;
+        Ok(self.forward(address, 4, value as u64) as u32)
+    }
+
+    fn read_i64(&self, address: usize) -> Result<i64, Trap> {
+        Ok(self.read_u64(address)? as i64)
+    }
+
+    fn read_u64(&self, address: usize) -> Result<u64, Trap> {
+        let value = self.inner.read_u64(address)?;
+        Ok(self.forward(address, 8, value))
+    }
+
+    fn write_u8(&mut self, address: usize, value: u8) -> Result<(), Trap> {
+        self.store(address, 1, value as u64)
+    }
+
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), Trap> {
+        self.store(address, 2, value as u64)
+    }
+
+    fn write_u32(&mut self, address: usize, value: u32) -> Result<(), Trap> {
+        self.store(address, 4, value as u64)
+    }
+
+    fn write_u64(&mut self, address: usize, value: u64) -> Result<(), Trap> {
+        self.store(address, 8, value)
+    }
+
+    fn fence(&mut self) {
+        self.flush();
+        self.inner.fence();
+    }
+
+    // atomics are never reordered, anything buffered goes out first
+    fn compare_exchange_u8(&mut self, address: usize, current: u8, new: u8) -> Result<Result<u8, u8>, Trap> {
+        self.flush();
+        self.inner.compare_exchange_u8(address, current, new)
+    }
+
+    fn compare_exchange_u16(&mut self, address: usize, current: u16, new: u16) -> Result<Result<u16, u16>, Trap> {
+        self.flush();
+        self.inner.compare_exchange_u16(address, current, new)
+    }
+
+    fn compare_exchange_u32(&mut self, address: usize, current: u32, new: u32) -> Result<Result<u32, u32>, Trap> {
+        self.flush();
+        self.inner.compare_exchange_u32(address, current, new)
+    }
+
+    fn compare_exchange_u64(&mut self, address: usize, current: u64, new: u64) -> Result<Result<u64, u64>, Trap> {
+        self.flush();
+        self.inner.compare_exchange_u64(address, current, new)
+    }
+
+    fn compare_exchange_u128(&mut self, address: usize, current: u128, new: u128) -> Result<Result<u128, u128>, Trap> {
+        self.flush();
+        self.inner.compare_exchange_u128(address, current, new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn never_drains() -> ReorderConfig {
+        ReorderConfig { drain_chance: 0, max_pending: 16, ..ReorderConfig::default() }
+    }
+
+    #[test]
+    fn device_stores_wait_for_fence() {
+        let mut memory = ReorderingMemory::new(vec![0u8; 0x200], never_drains());
+        memory.add_device_region(0x100..0x200);
+
+        memory.write_u32(0x100, 0x12345678).expect("write");
+        memory.write_u32(0x10, 0xcafe).expect("write");
+        assert_eq!(0, memory.inner().read_u32(0x100).unwrap());
+        assert_eq!(0xcafe, memory.inner().read_u32(0x10).unwrap());
+        assert_eq!(0x5678, memory.read_u16(0x100).expect("forwarded"));
+
+        memory.fence();
+        assert_eq!(0x12345678, memory.inner().read_u32(0x100).unwrap());
+    }
+
+    #[test]
+    fn repeated_register_writes_coalesce() {
+        let mut memory = ReorderingMemory::new(vec![0u8; 0x200], never_drains());
+        memory.add_device_region(0x100..0x200);
+
+        memory.write_u32(0x104, 1).expect("write");
+        memory.write_u32(0x104, 2).expect("write");
+        assert_eq!(1, memory.pending_stores());
+        assert_eq!(2, memory.into_inner().read_u32(0x104).unwrap());
+    }
+
+    #[test]
+    fn stores_leaving_a_device_region_write_through() {
+        let mut memory = ReorderingMemory::new(vec![0u8; 0x200], never_drains());
+        memory.add_device_region(0x100..0x1fe);
+
+        memory.write_u32(0x1fc, 0x12345678).expect("write");
+        assert_eq!(0, memory.pending_stores());
+        assert_eq!(0x12345678, memory.inner().read_u32(0x1fc).unwrap());
+        assert!(memory.write_u64(0x1fc, 0).is_err());
+        assert_eq!(0, memory.pending_stores());
+    }
+
+    #[test]
+    fn strict_mode_writes_through() {
+        let config = ReorderConfig { strict: true, ..never_drains() };
+        let mut memory = ReorderingMemory::new(vec![0u8; 0x200], config);
+        memory.add_device_region(0x100..0x200);
+
+        memory.write_u8(0x100, 7).expect("write");
+        assert_eq!(0, memory.pending_stores());
+        assert_eq!(7, memory.inner().read_u8(0x100).unwrap());
+    }
+}
@@ -0,0 +1,308 @@
+use crate::cpu::{Trap, TrapType};
+use crate::memory::Memory;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Guest memory that several harts can use at once, each clone is another handle onto the same
+// bytes. Every aligned access is a single atomic operation on the 64 bit word that contains it,
+// so AMOs and LR/SC behave across threads the way they would on real hardware. Accesses that
+// straddle two words are done a byte at a time, which is as much as the spec promises for them.
+#[derive(Clone)]
+pub struct SharedMemory {
+    words: Arc<[AtomicU64]>,
+    // held across a double width compare and swap, there being no 128 bit atomics to use instead
+    wide: Arc<Mutex<()>>,
+    size: usize
+}
+
+impl SharedMemory {
+    pub fn new(size: usize) -> Self {
+        SharedMemory {
+            words: (0..size.div_ceil(8)).map(|_| AtomicU64::new(0)).collect(),
+            wide: Arc::new(Mutex::new(())),
+            size
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let memory = SharedMemory::new(bytes.len());
+        for (word, chunk) in memory.words.iter().zip(bytes.chunks(8)) {
+            let mut value = [0u8; 8];
+            value[..chunk.len()].copy_from_slice(chunk);
+            word.store(u64::from_le_bytes(value), Ordering::Relaxed);
+        }
+
+        memory
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // how many handles, and so at most how many harts, share this memory
+    pub fn handles(&self) -> usize {
+        Arc::strong_count(&self.words)
+    }
+
+    // a snapshot of the contents, other harts may be changing it while this runs
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.words.iter().flat_map(|w| w.load(Ordering::SeqCst).to_le_bytes()).collect();
+        bytes.truncate(self.size);
+        bytes
+    }
+
+    fn check(&self, address: usize, length: usize, trap_type: TrapType) -> Result<(), Trap> {
+        match address.checked_add(length) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(Trap {
+                trap_type,
+                value: address as u64
+            })
+        }
+    }
+
+    fn load(&self, address: usize, length: usize) -> Result<u64, Trap> {
+        self.check(address, length, TrapType::LoadAccessFault)?;
+
+        let shift = (address % 8) * 8;
+        if address % 8 + length <= 8 {
+            let word = self.words[address / 8].load(Ordering::SeqCst);
+            return Ok((word >> shift) & mask(length));
+        }
+
+        let mut value = 0;
+        for i in (0..length).rev() {
+            value = value << 8 | self.load(address + i, 1)?;
+        }
+        Ok(value)
+    }
+
+    fn store(&self, address: usize, length: usize, value: u64) -> Result<(), Trap> {
+        self.check(address, length, TrapType::StoreAccessFault)?;
+
+        let shift = (address % 8) * 8;
+        if length == 8 && shift == 0 {
+            self.words[address / 8].store(value, Ordering::SeqCst);
+        } else if address % 8 + length <= 8 {
+            let bits = mask(length) << shift;
+            let _ = self.words[address / 8].fetch_update(Ordering::SeqCst, Ordering::SeqCst, |word| {
+                Some((word & !bits) | ((value << shift) & bits))
+            });
+        } else {
+            for i in 0..length {
+                self.store(address + i, 1, value >> (i * 8))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn compare_exchange(&self, address: usize, length: usize, current: u64, new: u64) -> Result<Result<u64, u64>, Trap> {
+        self.check(address, length, TrapType::StoreAccessFault)?;
+        if !address.is_multiple_of(length) {
+            return Err(Trap {
+                trap_type: TrapType::StoreAddressMisaligned,
+                value: address as u64
+            });
+        }
+
+        let shift = (address % 8) * 8;
+        let bits = mask(length) << shift;
+        let word = &self.words[address / 8];
+        let mut observed = word.load(Ordering::SeqCst);
+        loop {
+            let old = (observed & bits) >> shift;
+            if old != current {
+                return Ok(Err(old));
+            }
+            // the rest of the word may be changed by other harts without affecting this exchange
+            match word.compare_exchange_weak(observed, (observed & !bits) | (new << shift), Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return Ok(Ok(old)),
+                Err(actual) => observed = actual
+            }
+        }
+    }
+}
+
+fn mask(length: usize) -> u64 {
+    match length {
+        8 => u64::MAX,
+        _ => (1 << (length * 8)) - 1
+    }
+}
+
+// saved as what it holds at the time, later handles onto a restored one share that copy
+#[cfg(feature = "serde")]
+impl serde::Serialize for SharedMemory {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_image(serializer, self, self.size, |_| true)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SharedMemory {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        super::deserialize_image(deserializer, |size| Ok(SharedMemory::new(size)))
+    }
+}
+
+impl Memory for SharedMemory {
+    fn read_i8(&self, address: usize) -> Result<i8, Trap> {
+        Ok(self.load(address, 1)? as i8)
+    }
+
+    fn read_u8(&self, address: usize) -> Result<u8, Trap> {
+        Ok(self.load(address, 1)? as u8)
+    }
+
+    fn read_i16(&self, address: usize) -> Result<i16, Trap> {
+        Ok(self.load(address, 2)? as i16)
+    }
+
+    fn read_u16(&self, address: usize) -> Result<u16, Trap> {
+        Ok(self.load(address, 2)? as u16)
+    }
+
+    fn read_i32(&self, address: usize) -> Result<i32, Trap> {
+        Ok(self.load(address, 4)? as i32)
+    }
+
+    fn read_u32(&self, address: usize) -> Result<u32, Trap> {
+        Ok(self.load(address, 4)? as u32)
+    }
+
+    fn read_i64(&self, address: usize) -> Result<i64, Trap> {
+        Ok(self.load(address, 8)? as i64)
+    }
+
+    fn read_u64(&self, address: usize) -> Result<u64, Trap> {
+        self.load(address, 8)
+    }
+
+    fn write_u8(&mut self, address: usize, value: u8) -> Result<(), Trap> {
+        self.store(address, 1, value as u64)
+    }
+
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), Trap> {
+        self.store(address, 2, value as u64)
+    }
+
+    fn write_u32(&mut self, address: usize, value: u32) -> Result<(), Trap> {
+        self.store(address, 4, value as u64)
+    }
+
+    fn write_u64(&mut self, address: usize, value: u64) -> Result<(), Trap> {
+        self.store(address, 8, value)
+    }
+
+    fn compare_exchange_u8(&mut self, address: usize, current: u8, new: u8) -> Result<Result<u8, u8>, Trap> {
+        Ok(self.compare_exchange(address, 1, current as u64, new as u64)?
+            .map(|old| old as u8)
+            .map_err(|old| old as u8))
+    }
+
+    fn compare_exchange_u16(&mut self, address: usize, current: u16, new: u16) -> Result<Result<u16, u16>, Trap> {
+        Ok(self.compare_exchange(address, 2, current as u64, new as u64)?
+            .map(|old| old as u16)
+            .map_err(|old| old as u16))
+    }
+
+    fn compare_exchange_u32(&mut self, address: usize, current: u32, new: u32) -> Result<Result<u32, u32>, Trap> {
+        Ok(self.compare_exchange(address, 4, current as u64, new as u64)?
+            .map(|old| old as u32)
+            .map_err(|old| old as u32))
+    }
+
+    fn compare_exchange_u64(&mut self, address: usize, current: u64, new: u64) -> Result<Result<u64, u64>, Trap> {
+        self.compare_exchange(address, 8, current, new)
+    }
+
+    // Atomic with respect to the other double width exchanges, which all take the same lock. A
+    // plain store or narrower AMO from another hart can still land between the two halves.
+    fn compare_exchange_u128(&mut self, address: usize, current: u128, new: u128) -> Result<Result<u128, u128>, Trap> {
+        self.check(address, 16, TrapType::StoreAccessFault)?;
+        if !address.is_multiple_of(16) {
+            return Err(Trap {
+                trap_type: TrapType::StoreAddressMisaligned,
+                value: address as u64
+            });
+        }
+
+        let _wide = self.wide.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (low, high) = (&self.words[address / 8], &self.words[address / 8 + 1]);
+        let old = low.load(Ordering::SeqCst) as u128 | (high.load(Ordering::SeqCst) as u128) << 64;
+        if old != current {
+            return Ok(Err(old));
+        }
+        low.store(new as u64, Ordering::SeqCst);
+        high.store((new >> 64) as u64, Ordering::SeqCst);
+        Ok(Ok(old))
+    }
+}
+
+#[cfg(all(test, feature = "a"))]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+    use crate::cpu::{Cpu, Register};
+    use std::thread;
+
+    // li t0, 1000; loop: amoadd.w zero, a1, (a0); addi t0, t0, -1; bnez t0, loop
+    const COUNTER_LOOP: [u32; 4] = [0x3e800293, 0x00b5202f, 0xfff28293, 0xfe029ce3];
+    const COUNTER: usize = 0x100;
+
+    #[test]
+    fn amos_are_atomic_across_harts() {
+        let code = program(&COUNTER_LOOP);
+        let mut image = vec![0u8; 0x200];
+        image[..code.len()].copy_from_slice(&code);
+        let memory = SharedMemory::from_bytes(&image);
+        let end = code.len();
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                let mut memory = memory.clone();
+                scope.spawn(move || {
+                    let mut cpu = Cpu::new();
+                    cpu.set_register(Register::A0, COUNTER as i64);
+                    cpu.set_register(Register::A1, 1);
+                    while cpu.get_pc() != end {
+                        cpu.tick(&mut memory).unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(4000, memory.read_u32(COUNTER).unwrap());
+        assert_eq!(1, memory.handles());
+    }
+
+    #[test]
+    fn sc_fails_after_another_hart_writes() {
+        // lr.w a2, (a0); sc.w a3, a1, (a0)
+        let mut image = vec![0u8; 0x200];
+        image[0..4].copy_from_slice(&0x1005262fu32.to_le_bytes());
+        image[4..8].copy_from_slice(&0x18b526afu32.to_le_bytes());
+        let mut memory = SharedMemory::from_bytes(&image);
+        let mut other = memory.clone();
+
+        let mut cpu = Cpu::new();
+        cpu.set_register(Register::A0, COUNTER as i64);
+        cpu.set_register(Register::A1, 7);
+        cpu.tick(&mut memory).unwrap();
+        other.write_u32(COUNTER, 5).unwrap();
+        cpu.tick(&mut memory).unwrap();
+
+        assert_eq!(1, cpu.get_register(Register::A3));
+        assert_eq!(5, memory.read_u32(COUNTER).unwrap());
+    }
+
+    #[test]
+    fn unaligned_accesses_span_words() {
+        let mut memory = SharedMemory::new(16);
+        memory.write_u32(6, 0xaabbccdd).unwrap();
+        assert_eq!(0xaabbccdd, memory.read_u32(6).unwrap());
+        assert_eq!(0xccdd, memory.read_u16(6).unwrap());
+        assert_eq!(vec![0, 0, 0, 0, 0, 0, 0xdd, 0xcc, 0xbb, 0xaa, 0, 0, 0, 0, 0, 0], memory.to_vec());
+        assert!(memory.write_u8(16, 0).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+use crate::cpu::{Trap, TrapType};
+use crate::memory::Memory;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MapError {
+    Empty,
+    // the new region would overlap the region already mapped at this base
+    Overlap(usize)
+}
+
+impl Display for MapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Empty => write!(f, "cannot map an empty region"),
+            MapError::Overlap(base) => write!(f, "overlaps the region mapped at {:#x}", base)
+        }
+    }
+}
+
+impl Error for MapError {}
+
+enum Backing<'a> {
+    Host(&'a mut [u8]),
+    HostReadOnly(&'a [u8]),
+    // addresses handed to the inner memory are relative to the region base
+    Memory(Box<dyn Memory + 'a>)
+}
+
+struct Region<'a> {
+    base: usize,
+    size: usize,
+    backing: Backing<'a>
+}
+
+// A guest address space stitched together from regions. Host slices are borrowed rather than
+// copied, so the borrow checker keeps them alive for as long as the guest can reach them and
+// hands them back, with whatever the guest wrote, once the address space is dropped.
+#[derive(Default)]
+pub struct AddressSpace<'a> {
+    regions: Vec<Region<'a>>,
+    // guests tend to hammer the same region, so remember the last one that matched
+    last: Cell<usize>
+}
+
+impl<'a> AddressSpace<'a> {
+    pub fn new() -> Self {
+        AddressSpace::default()
+    }
+
+    pub fn map_slice(&mut self, base: usize, slice: &'a mut [u8]) -> Result<(), MapError> {
+        self.insert(base, slice.len(), Backing::Host(slice))
+    }
+
+    // guest stores to a read only slice fault
+    pub fn map_slice_readonly(&mut self, base: usize, slice: &'a [u8]) -> Result<(), MapError> {
+        self.insert(base, slice.len(), Backing::HostReadOnly(slice))
+    }
+
+    pub fn map_memory(&mut self, base: usize, size: usize, memory: Box<dyn Memory + 'a>) -> Result<(), MapError> {
+        self.insert(base, size, Backing::Memory(memory))
+    }
+
+    // returns false if nothing was mapped at base
+    pub fn unmap(&mut self, base: usize) -> bool {
+        match self.regions.binary_search_by_key(&base, |r| r.base) {
+            Ok(index) => {
+                self.regions.remove(index);
+                self.last.set(0);
+                true
+            },
+            Err(_) => false
+        }
+    }
+
+    // (base, size) of every mapped region in address order
+    pub fn regions(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.regions.iter().map(|r| (r.base, r.size))
+    }
+
+    fn insert(&mut self, base: usize, size: usize, backing: Backing<'a>) -> Result<(), MapError> {
+        if size == 0 {
+            return Err(MapError::Empty);
+        }
+        let end = base.checked_add(size).ok_or(MapError::Overlap(base))?;
+        let index = self.regions.partition_point(|r| r.base < base);
+        if let Some(previous) = index.checked_sub(1).map(|i| &self.regions[i]) {
+            if previous.base + previous.size > base {
+                return Err(MapError::Overlap(previous.base));
+            }
+        }
+        if let Some(next) = self.regions.get(index) {
+            if next.base < end {
+                return Err(MapError::Overlap(next.base));
+            }
+        }
+
+        self.regions.insert(index, Region { base, size, backing });
+        self.last.set(0);
+        Ok(())
+    }
+
+    fn find(&self, address: usize, length: usize) -> Option<usize> {
+        let fits = |r: &Region| address >= r.base && address - r.base + length <= r.size;
+
+        let last = self.last.get();
+        if self.regions.get(last).is_some_and(fits) {
+            return Some(last);
+        }
+
+        let index = self.regions.partition_point(|r| r.base <= address).checked_sub(1)?;
+        if fits(&self.regions[index]) {
+            self.last.set(index);
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    // the inner memory and offset when address falls in a region backed by another Memory, host
+    // slices are only ever touched by this address space so plain reads and writes are enough
+    fn shared_region(&mut self, address: usize, length: usize) -> Result<Option<(&mut (dyn Memory + 'a), usize)>, Trap> {
+        let index = self.find(address, length).ok_or(store_fault(address))?;
+        let region = &mut self.regions[index];
+        match &mut region.backing {
+            Backing::Memory(memory) => Ok(Some((memory.as_mut(), address - region.base))),
+            _ => Ok(None)
+        }
+    }
+
+    fn load<const N: usize>(&self, address: usize) -> Result<[u8; N], Trap> {
+        let fault = Trap {
+            trap_type: TrapType::LoadAccessFault,
+            value: address as u64
+        };
+        let region = &self.regions[self.find(address, N).ok_or(fault.clone())?];
+        let offset = address - region.base;
+
+        let mut result = [0u8; N];
+        match &region.backing {
+            Backing::Host(slice) => result.copy_from_slice(&slice[offset..offset + N]),
+            Backing::HostReadOnly(slice) => result.copy_from_slice(&slice[offset..offset + N]),
+            Backing::Memory(memory) => {
+                let value = match N {
+                    1 => memory.read_u8(offset).map(|v| v as u64),
+                    2 => memory.read_u16(offset).map(|v| v as u64),
+                    4 => memory.read_u32(offset).map(|v| v as u64),
+                    _ => memory.read_u64(offset)
+                }.map_err(|_| fault)?;
+                result.copy_from_slice(&value.to_le_bytes()[..N]);
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn store<const N: usize>(&mut self, address: usize, bytes: [u8; N]) -> Result<(), Trap> {
+        let fault = store_fault(address);
+        let index = self.find(address, N).ok_or(fault.clone())?;
+        let region = &mut self.regions[index];
+        let offset = address - region.base;
+
+        match &mut region.backing {
+            Backing::Host(slice) => slice[offset..offset + N].copy_from_slice(&bytes),
+            Backing::HostReadOnly(_) => return Err(fault),
+            Backing::Memory(memory) => {
+                let mut value = [0u8; 8];
+                value[..N].copy_from_slice(&bytes);
+                let value = u64::from_le_bytes(value);
+                match N {
+                    1 => memory.write_u8(offset, value as u8),
+                    2 => memory.write_u16(offset, value as u16),
+                    4 => memory.write_u32(offset, value as u32),
+                    _ => memory.write_u64(offset, value)
+                }.map_err(|_| fault)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn store_fault(address: usize) -> Trap {
+    Trap {
+        trap_type: TrapType::StoreAccessFault,
+        value: address as u64
+    }
+}
+
+impl Memory for AddressSpace<'_> {
+    fn read_i8(&self, address: usize) -> Result<i8, Trap> {
+        Ok(i8::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u8(&self, address: usize) -> Result<u8, Trap> {
+        Ok(u8::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_i16(&self, address: usize) -> Result<i16, Trap> {
+        Ok(i16::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u16(&self, address: usize) -> Result<u16, Trap> {
+        Ok(u16::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_i32(&self, address: usize) -> Result<i32, Trap> {
+        Ok(i32::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u32(&self, address: usize) -> Result<u32, Trap> {
+        Ok(u32::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_i64(&self, address: usize) -> Result<i64, Trap> {
+        Ok(i64::from_le_bytes(self.load(address)?))
+    }
+
+    fn read_u64(&self, address: usize) -> Result<u64, Trap> {
+        Ok(u64::from_le_bytes(self.load(address)?))
+    }
+
+    fn write_u8(&mut self, address: usize, value: u8) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, address: usize, value: u32) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, address: usize, value: u64) -> Result<(), Trap> {
+        self.store(address, value.to_le_bytes())
+    }
+
+    fn compare_exchange_u8(&mut self, address: usize, current: u8, new: u8) -> Result<Result<u8, u8>, Trap> {
+        match self.shared_region(address, 1)? {
+            Some((memory, offset)) => memory.compare_exchange_u8(offset, current, new).map_err(|_| store_fault(address)),
+            None => {
+                let old = self.read_u8(address)?;
+                if old != current {
+                    return Ok(Err(old));
+                }
+                self.write_u8(address, new)?;
+                Ok(Ok(old))
+            }
+        }
+    }
+
+    fn compare_exchange_u16(&mut self, address: usize, current: u16, new: u16) -> Result<Result<u16, u16>, Trap> {
+        match self.shared_region(address, 2)? {
+            Some((memory, offset)) => memory.compare_exchange_u16(offset, current, new).map_err(|_| store_fault(address)),
+            None => {
+                let old = self.read_u16(address)?;
+                if old != current {
+                    return Ok(Err(old));
+                }
+                self.write_u16(address, new)?;
+                Ok(Ok(old))
+            }
+        }
+    }
+
+    fn compare_exchange_u32(&mut self, address: usize, current: u32, new: u32) -> Result<Result<u32, u32>, Trap> {
+        match self.shared_region(address, 4)? {
+            Some((memory, offset)) => memory.compare_exchange_u32(offset, current, new).map_err(|_| store_fault(address)),
+            None => {
+                let old = self.read_u32(address)?;
+                if old != current {
+                    return Ok(Err(old));
+                }
+                self.write_u32(address, new)?;
+                Ok(Ok(old))
+            }
+        }
+    }
+
+    fn compare_exchange_u64(&mut self, address: usize, current: u64, new: u64) -> Result<Result<u64, u64>, Trap> {
+        match self.shared_region(address, 8)? {
+            Some((memory, offset)) => memory.compare_exchange_u64(offset, current, new).map_err(|_| store_fault(address)),
+            None => {
+                let old = self.read_u64(address)?;
+                if old != current {
+                    return Ok(Err(old));
+                }
+                self.write_u64(address, new)?;
+                Ok(Ok(old))
+            }
+        }
+    }
+
+    fn compare_exchange_u128(&mut self, address: usize, current: u128, new: u128) -> Result<Result<u128, u128>, Trap> {
+        match self.shared_region(address, 16)? {
+            Some((memory, offset)) => memory.compare_exchange_u128(offset, current, new).map_err(|_| store_fault(address)),
+            None => {
+                let old = self.read_u64(address)? as u128 | (self.read_u64(address + 8)? as u128) << 64;
+                if old != current {
+                    return Ok(Err(old));
+                }
+                self.write_u64(address, new as u64)?;
+                self.write_u64(address + 8, (new >> 64) as u64)?;
+                Ok(Ok(old))
+            }
+        }
+    }
+
+    fn fence(&mut self) {
+        for region in &mut self.regions {
+            if let Backing::Memory(memory) = &mut region.backing {
+                memory.fence();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn guest_writes_land_in_host_slice() {
+        let mut framebuffer = vec![0u8; 64];
+        let input = [1u8, 2, 3, 4];
+        {
+            let mut space = AddressSpace::new();
+            // sw a1, 0(a0)
+            space.map_memory(0, 4, Box::new(vec![0x23, 0x20, 0xb5, 0x00])).unwrap();
+            space.map_slice(0x1000_0000, &mut framebuffer).unwrap();
+            space.map_slice_readonly(0x2000_0000, &input).unwrap();
+
+            let mut cpu = Cpu::new();
+            cpu.set_register(crate::cpu::Register::A0, 0x1000_0010);
+            cpu.set_register(crate::cpu::Register::A1, 0x11223344);
+            cpu.tick(&mut space).expect("store to framebuffer");
+
+            assert_eq!(0x04030201, space.read_u32(0x2000_0000).unwrap());
+            assert!(space.write_u8(0x2000_0000, 0).is_err());
+            // accesses straddling the end of a region fault rather than spilling over
+            assert!(space.read_u16(0x1000_003f).is_err());
+        }
+
+        assert_eq!([0x44, 0x33, 0x22, 0x11], framebuffer[0x10..0x14]);
+    }
+
+    #[test]
+    fn overlapping_maps_are_rejected() {
+        let mut a = [0u8; 16];
+        let mut b = [0u8; 16];
+        let mut c = [0u8; 16];
+        let mut space = AddressSpace::new();
+        space.map_slice(0x100, &mut a).unwrap();
+        assert_eq!(Err(MapError::Overlap(0x100)), space.map_slice(0x108, &mut b));
+        assert_eq!(Err(MapError::Empty), space.map_slice(0x200, &mut []));
+
+        assert!(space.unmap(0x100));
+        space.map_slice(0x108, &mut c).unwrap();
+        assert_eq!(vec![(0x108, 16)], space.regions().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::cpu::{Cpu, Trap, TrapType};
+use crate::memory::CowMemory;
+
+#[derive(Clone, Debug)]
+pub struct MinimizeConfig {
+    // instructions each run may retire before it is counted as not crashing
+    pub fuel: u64,
+    // total runs, including the one that confirms the original input crashes
+    pub max_runs: usize
+}
+
+impl Default for MinimizeConfig {
+    fn default() -> Self {
+        MinimizeConfig {
+            fuel: 10_000_000,
+            max_runs: 10_000
+        }
+    }
+}
+
+// Two runs are taken to be the same bug when they raise the same kind of trap at the same pc.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crash {
+    pub pc: usize,
+    pub trap_type: TrapType
+}
+
+#[derive(Clone, Debug)]
+pub struct Minimized {
+    pub input: Vec<u8>,
+    pub crash: Crash,
+    pub runs: usize
+}
+
+// Runs the input once from the snapshot. inject is the harness entry point, it puts the input
+// into the forked guest, usually by writing it to a buffer and setting up the argument registers.
+// A failing inject or a TrapType::Stop exit count as not crashing.
+pub fn reproduce<F>(cpu: &Cpu, memory: &CowMemory, input: &[u8], fuel: u64, inject: &F) -> Option<Crash>
+    where F: Fn(&mut Cpu, &mut CowMemory, &[u8]) -> Result<(), Trap> {
+    let (mut cpu, mut memory) = cpu.fork(memory);
+    inject(&mut cpu, &mut memory, input).ok()?;
+
+    for _ in 0..fuel {
+        let pc = cpu.get_pc();
+        match cpu.tick(&mut memory) {
+            Ok(_) => {},
+            Err(trap) if trap.trap_type == TrapType::Stop => return None,
+            Err(trap) => return Some(Crash { pc, trap_type: trap.trap_type })
+        }
+    }
+
+    None
+}
+
+// Shrinks a crashing input to the smallest one found that still crashes the same way. Every run
+// starts from a copy-on-write fork of the snapshot, so cpu and memory should be captured at the
+// harness entry point. Chunks of the input are removed, halving the chunk size whenever none of
+// them can go, then the surviving bytes are zeroed one at a time where that keeps the crash.
+// Returns None if the original input does not crash.
+pub fn minimize<F>(cpu: &Cpu, memory: &CowMemory, input: &[u8], config: &MinimizeConfig, inject: F) -> Option<Minimized>
+    where F: Fn(&mut Cpu, &mut CowMemory, &[u8]) -> Result<(), Trap> {
+    let crash = reproduce(cpu, memory, input, config.fuel, &inject)?;
+    let mut runs = 1;
+    let still_crashes = |candidate: &[u8], runs: &mut usize| {
+        *runs += 1;
+        reproduce(cpu, memory, candidate, config.fuel, &inject) == Some(crash)
+    };
+
+    let mut best = input.to_vec();
+    let mut chunk = (best.len() / 2).max(1);
+    while !best.is_empty() && runs < config.max_runs {
+        let mut removed = false;
+        let mut start = 0;
+        while start < best.len() && runs < config.max_runs {
+            let end = (start + chunk).min(best.len());
+            let candidate = [&best[..start], &best[end..]].concat();
+            if still_crashes(&candidate, &mut runs) {
+                best = candidate;
+                removed = true;
+            } else {
+                start = end;
+            }
+        }
+
+        if !removed {
+            if chunk == 1 {
+                break;
+            }
+            chunk /= 2;
+        }
+    }
+
+    for i in 0..best.len() {
+        if runs >= config.max_runs {
+            break;
+        }
+        if best[i] != 0 {
+            let mut candidate = best.clone();
+            candidate[i] = 0;
+            if still_crashes(&candidate, &mut runs) {
+                best = candidate;
+            }
+        }
+    }
+
+    Some(Minimized { input: best, crash, runs })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Register;
+    use crate::cpu::instruction::Instruction;
+    use crate::memory::Memory;
+
+    // scans a1 bytes at a0 and executes an illegal instruction at 0x20 on finding an 'X'
+    //     li t0, 'X'
+    // loop:
+    //     beqz a1, done
+    //     lbu t1, 0(a0)
+    //     beq t1, t0, crash
+    //     addi a0, a0, 1
+    //     addi a1, a1, -1
+    //     j loop
+    // done:
+    //     ecall
+    // crash:
+    //     .word 0
+    const PARSER: [u32; 9] = [0x05800293, 0x00058c63, 0x00054303, 0x00530a63, 0x00150513, 0xfff58593, 0xfedff06f, 0x00000073, 0x00000000];
+    const INPUT: usize = 0x100;
+
+    fn snapshot() -> (Cpu, CowMemory) {
+        let mut image = vec![0u8; 0x1000];
+        for (i, word) in PARSER.iter().enumerate() {
+            image[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+
+        let mut cpu = Cpu::new();
+        cpu.set_ecall_fallback(Some(Instruction {
+            name: "ECALL",
+            operation: |_cpu, _memory, _word, _address| Err(Trap::exit(0))
+        }));
+        (cpu, CowMemory::from_bytes(&image))
+    }
+
+    fn inject(cpu: &mut Cpu, memory: &mut CowMemory, input: &[u8]) -> Result<(), Trap> {
+        for (i, byte) in input.iter().enumerate() {
+            memory.write_u8(INPUT + i, *byte)?;
+        }
+        cpu.set_register(Register::A0, INPUT as i64);
+        cpu.set_register(Register::A1, input.len() as i64);
+        Ok(())
+    }
+
+    #[test]
+    fn shrinks_to_the_byte_that_matters() {
+        let (cpu, memory) = snapshot();
+        let minimized = minimize(&cpu, &memory, b"some input with an X in it", &MinimizeConfig::default(), inject)
+            .expect("input crashes");
+
+        assert_eq!(b"X".to_vec(), minimized.input);
+        assert_eq!(Crash { pc: 0x20, trap_type: TrapType::IllegalInstruction }, minimized.crash);
+        // the snapshot is never touched
+        assert_eq!(0, memory.read_u8(INPUT).unwrap());
+    }
+
+    #[test]
+    fn inputs_that_exit_cleanly_are_not_crashes() {
+        let (cpu, memory) = snapshot();
+        assert!(minimize(&cpu, &memory, b"nothing to see", &MinimizeConfig::default(), inject).is_none());
+    }
+}
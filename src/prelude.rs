@@ -0,0 +1,11 @@
+// The supported public surface of the crate. Anything not re-exported here is an implementation
+// detail that may change between releases.
+pub use crate::cpu::{BranchStats, CacheConfig, CacheModel, CacheStats, Caches, ClassCosts, CostModel, Coverage, Cpu, CpuBuilder, CpuState, CsrHandler, CsrHooks, CustomDecoder, CustomDecoders, EcallHandlers, Engine, Extensions, FpRegister, GasTable, PcRecord, PointerMasking, Predictor, Profile, Register, ReplayLog, ReplayStatus, RunConfig, StepInfo, StopHandle, StopReason, Trap, TrapType, WakeHandle, Xlen};
+pub use crate::cpu::instruction::Instruction;
+pub use crate::disasm::{Disassembler, Disassembly, Operand};
+pub use crate::dump::MemoryDump;
+pub use crate::loader::LoadError;
+pub use crate::loader::elf::{ElfImage, ElfLoader};
+pub use crate::memory::{GuestAddr, Memory};
+pub use crate::symbolize::{Location, Symbolizer};
+pub use crate::trace::{ConsoleTracer, JsonTracer, SpikeTracer, TraceEvent, TraceFilter, Tracer};
@@ -0,0 +1,180 @@
+#[cfg(feature = "dwarf")]
+use crate::loader::LoadError;
+use crate::loader::elf::{ElfImage, SymbolKind};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+// An address in terms of the program it belongs to, displayed as function+offset (file:line)
+// with whichever parts are known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub address: usize,
+    // the symbol the address falls in and how far into it
+    pub function: Option<(String, usize)>,
+    pub file: Option<String>,
+    pub line: Option<u32>
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some((name, 0)) => write!(f, "{}", name)?,
+            Some((name, offset)) => write!(f, "{}+{:#x}", name, offset)?,
+            None => write!(f, "{:#x}", self.address)?
+        }
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, " ({}:{})", file, line),
+            (Some(file), None) => write!(f, " ({})", file),
+            _ => Ok(())
+        }
+    }
+}
+
+// Turns guest addresses into locations and back, from the symbol table of a loaded ELF and,
+// with the dwarf feature, its line tables. Everything is kept in guest addresses, so a moved
+// image symbolizes the same as one loaded where it was linked.
+#[derive(Clone, Debug, Default)]
+pub struct Symbolizer {
+    // code and labels, by where they start, data objects are left out
+    functions: Vec<(Range<usize>, String)>,
+    // where each row of a line table starts, None where a sequence ends
+    lines: BTreeMap<usize, Option<(usize, u32)>>,
+    files: Vec<String>
+}
+
+impl Symbolizer {
+    pub fn new(image: &ElfImage) -> Self {
+        let functions = image.symbols.iter()
+            .filter(|symbol| symbol.kind != SymbolKind::Object)
+            .map(|symbol| (image.symbol_extent(symbol), symbol.name.clone()))
+            .collect();
+        Symbolizer { functions, lines: BTreeMap::new(), files: Vec::new() }
+    }
+
+    // Adds the file and line of every address the DWARF line tables in elf cover, elf being the
+    // file image was loaded from. An ELF without debug information adds nothing.
+    #[cfg(feature = "dwarf")]
+    pub fn with_debug_info(mut self, image: &ElfImage, elf: &[u8]) -> Result<Self, LoadError> {
+        dwarf::read_lines(&mut self, elf, image.bias).map_err(|_| LoadError::Unsupported("malformed DWARF"))?;
+        Ok(self)
+    }
+
+    pub fn symbolize(&self, address: usize) -> Location {
+        let function = self.functions[..self.functions.partition_point(|(range, _)| range.start <= address)].iter().rev()
+            .find(|(range, _)| range.contains(&address))
+            .map(|(range, name)| (name.clone(), address - range.start));
+        let line = self.lines.range(..=address).next_back().and_then(|(_, row)| *row);
+        Location {
+            address,
+            function,
+            file: line.map(|(file, _)| self.files[file].clone()),
+            line: line.map(|(_, line)| line).filter(|line| *line != 0)
+        }
+    }
+
+    // The address of name, name+offset or file:line, for setting breakpoints by where they are
+    // in the source. A file matches by the end of its path and a line by its lowest address.
+    pub fn resolve(&self, location: &str) -> Option<usize> {
+        if let Some((file, line)) = location.rsplit_once(':').and_then(|(file, line)| Some((file, line.parse::<u32>().ok()?))) {
+            return self.lines.iter()
+                .filter_map(|(address, row)| row.map(|(index, at)| (*address, index, at)))
+                .filter(|(_, index, at)| *at == line && self.files[*index].ends_with(file))
+                .map(|(address, _, _)| address)
+                .min();
+        }
+        let (name, offset) = match location.split_once('+') {
+            Some((name, offset)) => (name, parse_offset(offset)?),
+            None => (location, 0)
+        };
+        self.functions.iter().find(|(_, function)| function == name).map(|(range, _)| range.start + offset)
+    }
+}
+
+fn parse_offset(offset: &str) -> Option<usize> {
+    match offset.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => offset.parse().ok()
+    }
+}
+
+#[cfg(feature = "dwarf")]
+mod dwarf {
+    use super::Symbolizer;
+    use crate::loader::elf;
+    use gimli::{Dwarf, EndianSlice, LittleEndian};
+
+    pub(super) fn read_lines(symbolizer: &mut Symbolizer, bytes: &[u8], bias: usize) -> gimli::Result<()> {
+        let dwarf = Dwarf::load(|id| -> gimli::Result<_> {
+            let section = elf::section(bytes, id.name()).ok().flatten().unwrap_or(&[]);
+            Ok(EndianSlice::new(section, LittleEndian))
+        })?;
+
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                let address = (row.address() as usize).wrapping_add(bias);
+                let file = match (row.end_sequence(), row.file(header)) {
+                    (false, Some(file)) => file,
+                    _ => {
+                        symbolizer.lines.entry(address).or_insert(None);
+                        continue;
+                    }
+                };
+                let name = dwarf.attr_string(&unit, file.path_name())?.to_string_lossy().into_owned();
+                let mut path = match file.directory(header) {
+                    Some(directory) if !name.starts_with('/') => {
+                        format!("{}/{}", dwarf.attr_string(&unit, directory)?.to_string_lossy(), name)
+                    },
+                    _ => name
+                };
+                // relative to where the unit was compiled
+                if let Some(directory) = unit.comp_dir.filter(|_| !path.starts_with('/')) {
+                    path = format!("{}/{}", directory.to_string_lossy(), path);
+                }
+                let index = match symbolizer.files.iter().position(|known| *known == path) {
+                    Some(index) => index,
+                    None => {
+                        symbolizer.files.push(path);
+                        symbolizer.files.len() - 1
+                    }
+                };
+                symbolizer.lines.insert(address, Some((index, row.line().map_or(0, |line| line.get() as u32))));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::loader::elf::ElfLoader;
+
+    const MANDELBROT_DEBUG: &[u8] = include_bytes!("../test/mandelbrot-debug");
+
+    #[test]
+    fn addresses_resolve_both_ways() {
+        let mut memory = vec![0u8; 0x200000];
+        let image = ElfLoader::new().load(MANDELBROT_DEBUG, &mut memory).unwrap();
+        let symbolizer = Symbolizer::new(&image);
+
+        let start = symbolizer.symbolize(0x10480);
+        assert_eq!(Some(("_start".to_string(), 6)), start.function);
+        assert_eq!(Some(0x10480), symbolizer.resolve("_start+0x6"));
+        assert_eq!("0x8", symbolizer.symbolize(8).to_string());
+
+        #[cfg(feature = "dwarf")]
+        {
+            let symbolizer = symbolizer.with_debug_info(&image, MANDELBROT_DEBUG).unwrap();
+            assert_eq!("_start+0x6 (/work/src/main.rs:64)", symbolizer.symbolize(0x10480).to_string());
+            assert_eq!(Some(0x1047a), symbolizer.resolve("main.rs:63"));
+        }
+    }
+}
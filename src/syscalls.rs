@@ -0,0 +1,17 @@
+use crate::cpu::{Trap, TrapType};
+use crate::cpu::instruction::Instruction;
+
+pub mod heap;
+pub mod linux;
+pub mod policy;
+pub mod semihosting;
+pub mod strace;
+pub mod vfs;
+
+// An ecall handler that hands every call back to whoever is calling tick. The hart has already
+// moved past the ecall when the trap comes out, so serving the call and carrying on ticking is
+// all the host needs to do.
+pub const TRAPPING_ECALL: Instruction = Instruction {
+    name: "ECALL",
+    operation: |_cpu, _memory, _word, _address| Err(Trap { trap_type: TrapType::EnvironmentCallFromUMode, value: 0 })
+};
@@ -0,0 +1,213 @@
+use crate::cpu::{Trap, TrapType};
+use crate::memory::Memory;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+pub const PAGE_SIZE: usize = 4096;
+
+// The program break and the anonymous mappings of a process. The break grows up from the end of
+// the image, mappings are placed top down below mmap_top in the highest gap that fits, and
+// neither is allowed to run into the other. Everything handed out is zeroed first, so the guest
+// never sees what an earlier mapping left behind.
+#[derive(Clone, Debug)]
+pub struct Heap {
+    break_start: usize,
+    program_break: usize,
+    mmap_top: usize,
+    // start to end of every live mapping, always page aligned and never overlapping
+    mappings: BTreeMap<usize, usize>
+}
+
+impl Heap {
+    pub fn new(program_break: usize, mmap_top: usize) -> Self {
+        let program_break = page_align(program_break).unwrap_or(program_break & !(PAGE_SIZE - 1));
+        Heap {
+            break_start: program_break,
+            program_break,
+            mmap_top: mmap_top & !(PAGE_SIZE - 1),
+            mappings: BTreeMap::new()
+        }
+    }
+
+    pub fn program_break(&self) -> usize {
+        self.program_break
+    }
+
+    pub fn mappings(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.mappings.iter().map(|(start, end)| *start..*end)
+    }
+
+    // Moves the break and returns where it ended up. Like the kernel, a request that cannot be
+    // met leaves the break where it was, which is also how brk(0) asks for the current value.
+    pub fn brk(&mut self, memory: &mut dyn Memory, requested: usize) -> usize {
+        let grows = requested > self.program_break;
+        let fits = requested >= self.break_start && requested <= self.mmap_top && !(grows && self.overlaps(self.program_break..requested));
+        if fits && (!grows || memory.fill(self.program_break..requested, 0).is_ok()) {
+            self.program_break = requested;
+        }
+        self.program_break
+    }
+
+    // None when there is no gap big enough left between the break and mmap_top
+    pub fn map(&mut self, memory: &mut dyn Memory, length: usize) -> Option<usize> {
+        let length = page_align(length)?;
+        let start = self.find_gap(length)?;
+        memory.fill(start..start + length, 0).ok()?;
+        self.mappings.insert(start, start + length);
+        Some(start)
+    }
+
+    // MAP_FIXED, anything already mapped in the way is replaced
+    pub fn map_fixed(&mut self, memory: &mut dyn Memory, address: usize, length: usize) -> Result<(), Trap> {
+        let end = extent(address, length).ok_or(Trap { trap_type: TrapType::StoreAccessFault, value: address as u64 })?;
+        memory.fill(address..end, 0)?;
+        self.unmap(address, end - address);
+        self.mappings.insert(address, end);
+        Ok(())
+    }
+
+    // unmapping a range that is only partly mapped, or not mapped at all, is fine, as is one that
+    // runs off the end of the address space
+    pub fn unmap(&mut self, address: usize, length: usize) {
+        let end = extent(address, length).unwrap_or(usize::MAX);
+        let overlapping: Vec<(usize, usize)> = self.mappings.range(..end)
+            .filter(|(_, stop)| **stop > address)
+            .map(|(start, stop)| (*start, *stop))
+            .collect();
+
+        for (start, stop) in overlapping {
+            self.mappings.remove(&start);
+            if start < address {
+                self.mappings.insert(start, address);
+            }
+            if stop > end {
+                self.mappings.insert(end, stop);
+            }
+        }
+    }
+
+    // Resizes the mapping at address, in place when the pages after it are free and otherwise,
+    // if allowed to, by moving it and its contents somewhere that fits. Returns the new start.
+    pub fn remap(&mut self, memory: &mut dyn Memory, address: usize, old_length: usize, new_length: usize, may_move: bool) -> Option<usize> {
+        let old_end = extent(address, old_length)?;
+        let new_end = extent(address, new_length)?;
+        if self.mappings.get(&address) != Some(&old_end) || new_length == 0 {
+            return None;
+        }
+
+        if new_end <= old_end {
+            self.unmap(new_end, old_end - new_end);
+            return Some(address);
+        }
+        if new_end <= self.mmap_top && !self.overlaps(old_end..new_end) {
+            memory.fill(old_end..new_end, 0).ok()?;
+            self.mappings.insert(address, new_end);
+            return Some(address);
+        }
+        if !may_move {
+            return None;
+        }
+
+        let start = self.map(memory, new_length)?;
+        memory.copy(address, start, old_end - address).ok()?;
+        self.unmap(address, old_end - address);
+        Some(start)
+    }
+
+    fn overlaps(&self, range: Range<usize>) -> bool {
+        self.mappings.range(..range.end).next_back().is_some_and(|(_, stop)| *stop > range.start)
+    }
+
+    fn find_gap(&self, length: usize) -> Option<usize> {
+        let mut end = self.mmap_top;
+        for (start, stop) in self.mappings.range(..self.mmap_top).rev() {
+            if end >= *stop && end - stop >= length {
+                break;
+            }
+            end = end.min(*start);
+        }
+        end.checked_sub(length).filter(|start| *start >= self.program_break)
+    }
+}
+
+// None when rounding up runs off the end of the address space
+pub fn page_align(address: usize) -> Option<usize> {
+    address.checked_next_multiple_of(PAGE_SIZE)
+}
+
+// the end of length bytes from address rounded up to whole pages, if it is addressable
+pub fn extent(address: usize, length: usize) -> Option<usize> {
+    page_align(length).and_then(|length| address.checked_add(length))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unmapped_pages_are_reused_and_zeroed() {
+        let mut memory = vec![0u8; 0x10000];
+        let mut heap = Heap::new(0x1000, 0x8000);
+        assert_eq!(Some(0x7000), heap.map(&mut memory, 0x1000));
+        assert_eq!(Some(0x5000), heap.map(&mut memory, 0x2000));
+        assert_eq!(Some(0x4000), heap.map(&mut memory, 1));
+
+        memory[0x5800] = 0xaa;
+        heap.unmap(0x5000, 0x2000);
+        assert_eq!(vec![0x4000..0x5000, 0x7000..0x8000], heap.mappings().collect::<Vec<_>>());
+        assert_eq!(Some(0x6000), heap.map(&mut memory, 0x1000));
+        assert_eq!(Some(0x5000), heap.map(&mut memory, 0x1000));
+        assert_eq!(0, memory[0x5800]);
+
+        // the break cannot grow into the lowest mapping, and mappings cannot go below the break
+        assert_eq!(0x3000, heap.brk(&mut memory, 0x3000));
+        assert_eq!(0x3000, heap.brk(&mut memory, 0x4001));
+        assert_eq!(None, heap.map(&mut memory, 0x2000));
+    }
+
+    #[test]
+    fn the_break_cannot_grow_into_a_fixed_mapping_that_starts_below_it() {
+        let mut memory = vec![0u8; 0x10000];
+        let mut heap = Heap::new(0x1000, 0x8000);
+        assert_eq!(0x3000, heap.brk(&mut memory, 0x3000));
+        heap.map_fixed(&mut memory, 0x2000, 0x2000).unwrap();
+
+        assert_eq!(0x3000, heap.brk(&mut memory, 0x5000));
+        assert_eq!(0x2000, heap.brk(&mut memory, 0x2000));
+        assert_eq!(0x2000, heap.brk(&mut memory, 0x3000));
+        heap.unmap(0x2000, 0x2000);
+        assert_eq!(0x5000, heap.brk(&mut memory, 0x5000));
+    }
+
+    #[test]
+    fn remapping_grows_in_place_or_moves() {
+        let mut memory = vec![0u8; 0x10000];
+        let mut heap = Heap::new(0x1000, 0x8000);
+        let first = heap.map(&mut memory, 0x1000).unwrap();
+        let second = heap.map(&mut memory, 0x1000).unwrap();
+        memory[second] = 0x55;
+
+        // the mapping above second is in the way
+        assert_eq!(None, heap.remap(&mut memory, second, 0x1000, 0x2000, false));
+        let moved = heap.remap(&mut memory, second, 0x1000, 0x2000, true).unwrap();
+        assert_eq!(0x55, memory[moved]);
+        assert_eq!(vec![moved..moved + 0x2000, first..first + 0x1000], heap.mappings().collect::<Vec<_>>());
+
+        heap.unmap(first, 0x1000);
+        assert_eq!(Some(moved), heap.remap(&mut memory, moved, 0x2000, 0x3000, false));
+        assert_eq!(Some(moved), heap.remap(&mut memory, moved, 0x3000, 0x1000, false));
+        assert_eq!(vec![moved..moved + 0x1000], heap.mappings().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn lengths_past_the_end_of_the_address_space_are_refused() {
+        let mut memory = vec![0u8; 0x10000];
+        let mut heap = Heap::new(0x1000, 0x8000);
+        let mapped = heap.map(&mut memory, 0x1000).unwrap();
+        assert_eq!(None, heap.map(&mut memory, usize::MAX));
+        assert!(heap.map_fixed(&mut memory, usize::MAX & !(PAGE_SIZE - 1), 0x2000).is_err());
+        assert_eq!(None, heap.remap(&mut memory, mapped, 0x1000, usize::MAX, true));
+        heap.unmap(mapped, usize::MAX);
+        assert_eq!(0, heap.mappings().count());
+    }
+}
@@ -0,0 +1,1351 @@
+use crate::clock::{Clock, HostClock};
+use crate::coredump;
+use crate::cpu::{Cpu, Register, Trap, TrapType};
+use crate::loader::elf::ElfImage;
+use crate::memory::Memory;
+use crate::memory::marshal;
+use crate::syscalls::TRAPPING_ECALL;
+use crate::syscalls::heap::{self, Heap, PAGE_SIZE};
+use crate::syscalls::policy::{Policy, Verdict};
+use crate::syscalls::strace::SyscallTrace;
+use crate::syscalls::vfs::{normalize, File, FileType, MemoryFs, Metadata, OpenOptions, Vfs};
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::io::{Read, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(feature = "net")]
+pub mod net;
+pub mod signal;
+
+use signal::{divides_by_zero, SigAction, CSR_FCSR_ADDRESS, SIGABRT, SIGFPE, SIGSEGV};
+
+pub const SYS_GETCWD: i64 = 17;
+pub const SYS_IOCTL: i64 = 29;
+pub const SYS_FACCESSAT: i64 = 48;
+pub const SYS_CHDIR: i64 = 49;
+pub const SYS_OPENAT: i64 = 56;
+pub const SYS_CLOSE: i64 = 57;
+pub const SYS_GETDENTS64: i64 = 61;
+pub const SYS_LSEEK: i64 = 62;
+pub const SYS_READ: i64 = 63;
+pub const SYS_WRITE: i64 = 64;
+pub const SYS_READV: i64 = 65;
+pub const SYS_WRITEV: i64 = 66;
+pub const SYS_READLINKAT: i64 = 78;
+pub const SYS_NEWFSTATAT: i64 = 79;
+pub const SYS_FSTAT: i64 = 80;
+pub const SYS_EXIT: i64 = 93;
+pub const SYS_EXIT_GROUP: i64 = 94;
+pub const SYS_SET_TID_ADDRESS: i64 = 96;
+pub const SYS_FUTEX: i64 = 98;
+pub const SYS_SET_ROBUST_LIST: i64 = 99;
+pub const SYS_CLOCK_GETTIME: i64 = 113;
+pub const SYS_SCHED_YIELD: i64 = 124;
+pub const SYS_KILL: i64 = 129;
+pub const SYS_TKILL: i64 = 130;
+pub const SYS_TGKILL: i64 = 131;
+pub const SYS_SIGALTSTACK: i64 = 132;
+pub const SYS_RT_SIGACTION: i64 = 134;
+pub const SYS_RT_SIGPROCMASK: i64 = 135;
+pub const SYS_RT_SIGRETURN: i64 = 139;
+pub const SYS_UNAME: i64 = 160;
+pub const SYS_GETTIMEOFDAY: i64 = 169;
+pub const SYS_GETPID: i64 = 172;
+pub const SYS_GETPPID: i64 = 173;
+pub const SYS_GETUID: i64 = 174;
+pub const SYS_GETEUID: i64 = 175;
+pub const SYS_GETGID: i64 = 176;
+pub const SYS_GETEGID: i64 = 177;
+pub const SYS_GETTID: i64 = 178;
+pub const SYS_BRK: i64 = 214;
+pub const SYS_SOCKET: i64 = 198;
+pub const SYS_BIND: i64 = 200;
+pub const SYS_LISTEN: i64 = 201;
+pub const SYS_ACCEPT: i64 = 202;
+pub const SYS_CONNECT: i64 = 203;
+pub const SYS_GETSOCKNAME: i64 = 204;
+pub const SYS_GETPEERNAME: i64 = 205;
+pub const SYS_SENDTO: i64 = 206;
+pub const SYS_RECVFROM: i64 = 207;
+pub const SYS_SETSOCKOPT: i64 = 208;
+pub const SYS_GETSOCKOPT: i64 = 209;
+pub const SYS_SHUTDOWN: i64 = 210;
+pub const SYS_MUNMAP: i64 = 215;
+pub const SYS_MREMAP: i64 = 216;
+pub const SYS_CLONE: i64 = 220;
+pub const SYS_MMAP: i64 = 222;
+pub const SYS_MPROTECT: i64 = 226;
+pub const SYS_MADVISE: i64 = 233;
+pub const SYS_ACCEPT4: i64 = 242;
+pub const SYS_GETRANDOM: i64 = 278;
+
+pub const EPERM: i64 = 1;
+pub const ENOENT: i64 = 2;
+pub const ESRCH: i64 = 3;
+pub const EINTR: i64 = 4;
+pub const EIO: i64 = 5;
+pub const EBADF: i64 = 9;
+pub const EAGAIN: i64 = 11;
+pub const ENOMEM: i64 = 12;
+pub const EACCES: i64 = 13;
+pub const EFAULT: i64 = 14;
+pub const EEXIST: i64 = 17;
+pub const ENODEV: i64 = 19;
+pub const ENOTDIR: i64 = 20;
+pub const EISDIR: i64 = 21;
+pub const EINVAL: i64 = 22;
+pub const EMFILE: i64 = 24;
+pub const ENOTTY: i64 = 25;
+pub const EFBIG: i64 = 27;
+pub const ESPIPE: i64 = 29;
+pub const ERANGE: i64 = 34;
+pub const ENAMETOOLONG: i64 = 36;
+pub const ENOSYS: i64 = 38;
+pub const ETIMEDOUT: i64 = 110;
+
+const MAP_FIXED: i64 = 0x10;
+const MAP_ANONYMOUS: i64 = 0x20;
+const MREMAP_MAYMOVE: i64 = 1;
+const CLONE_VM: i64 = 0x100;
+const CLONE_SETTLS: i64 = 0x80000;
+const CLONE_THREAD: i64 = 0x10000;
+const CLONE_PARENT_SETTID: i64 = 0x100000;
+const CLONE_CHILD_CLEARTID: i64 = 0x200000;
+const CLONE_CHILD_SETTID: i64 = 0x1000000;
+const FUTEX_WAIT: i64 = 0;
+const FUTEX_WAKE: i64 = 1;
+const FUTEX_WAIT_BITSET: i64 = 9;
+const FUTEX_WAKE_BITSET: i64 = 10;
+// ignored, every futex is private to the one process there is and timeouts only ever end a deadlock
+const FUTEX_PRIVATE_FLAG: i64 = 128;
+const FUTEX_CLOCK_REALTIME: i64 = 256;
+// GRND_NONBLOCK, GRND_RANDOM and GRND_INSECURE, none of which change anything here
+const GRND_FLAGS: i64 = 0x7;
+const AT_FDCWD: i64 = -100;
+const AT_EMPTY_PATH: i64 = 0x1000;
+const O_ACCMODE: i64 = 0o3;
+const O_WRONLY: i64 = 0o1;
+const O_RDWR: i64 = 0o2;
+const O_CREAT: i64 = 0o100;
+const O_EXCL: i64 = 0o200;
+const O_TRUNC: i64 = 0o1000;
+const O_APPEND: i64 = 0o2000;
+const O_DIRECTORY: i64 = 0o200000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const PATH_MAX: usize = 4096;
+const MAX_FILES: i64 = 1024;
+// the most a single read or write moves, anything longer comes back short
+const MAX_TRANSFER: usize = 1 << 20;
+// the most iovecs readv and writev take at once
+const IOV_MAX: usize = 1024;
+const STAT_SIZE: usize = 128;
+const UTSNAME_FIELD_SIZE: usize = 65;
+// also the tid of the thread the process starts with
+const PID: i64 = 1;
+// how many instructions a thread runs before the next runnable one gets a turn
+const QUANTUM: usize = 10_000;
+
+// The system calls a statically linked rv64 Linux program makes on its way to main and back,
+// served from the host. stdin, stdout and stderr are the host's own unless redirected, and
+// files come from a Vfs, an empty one unless another is given. The program break starts just
+// past the image and anonymous mappings are made below mmap_top. Threads made by clone get a
+// hart of their own and take turns with the others on the same memory. Faults go to the guest's
+// own signal handlers when it has installed them, and otherwise end the run.
+pub struct Linux {
+    heap: Heap,
+    vfs: Box<dyn Vfs>,
+    files: BTreeMap<i64, OpenFile>,
+    cwd: String,
+    clock: Arc<dyn Clock>,
+    rng: Rng,
+    // the thread the process started with is always first, exited threads are kept so an index
+    // always names the same thread
+    threads: Vec<Thread>,
+    // the thread whose system calls are being served
+    current: usize,
+    next_tid: i64,
+    // set by sched_yield to end the current thread's turn early
+    yielded: bool,
+    tracer: Option<SyscallTracer>,
+    policy: Option<Box<dyn Policy>>,
+    // shared by every thread, what each signal that is not left at SIG_DFL does
+    actions: BTreeMap<i64, SigAction>,
+    trap_division: bool
+}
+
+type SyscallTracer = Box<dyn FnMut(&SyscallTrace)>;
+
+// read or write, for readv and writev to share
+type Transfer = fn(&mut Linux, &mut dyn Memory, i64, usize, usize) -> Result<i64, i64>;
+
+struct OpenFile {
+    file: Box<dyn File>,
+    // what openat resolved, relative paths are looked up from here when this is a directory
+    path: String,
+    // how many directory entries getdents64 has handed out so far
+    listed: usize
+}
+
+struct Thread {
+    tid: i64,
+    // None for the first thread, whose hart belongs to whoever called run, and for any thread
+    // while it is taking its turn
+    cpu: Option<Cpu>,
+    // zeroed and woken when the thread exits, for pthread_join
+    clear_child_tid: usize,
+    state: ThreadState,
+    // blocked and pending signals, bit n - 1 for signal n
+    mask: u64,
+    pending: u64,
+    // base and size of the sigaltstack
+    alt_stack: Option<(usize, usize)>,
+    // fcsr as the thread left it at the end of its last turn, the accrued flags being kept by the
+    // host's floating point unit while a thread runs, None until then
+    fcsr: Option<u64>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ThreadState {
+    Runnable,
+    // blocked in FUTEX_WAIT on address until woken by a FUTEX_WAKE with a matching bit
+    Waiting { address: usize, bitset: u32, timeout: bool },
+    Exited
+}
+
+impl Linux {
+    pub fn new(program_break: usize, mmap_top: usize) -> Self {
+        Linux {
+            heap: Heap::new(program_break, mmap_top),
+            vfs: Box::new(MemoryFs::new()),
+            files: BTreeMap::new(),
+            cwd: "/".to_string(),
+            clock: Arc::new(HostClock::new()),
+            rng: Rng(RandomState::new().build_hasher().finish()),
+            threads: vec![Thread { tid: PID, cpu: None, clear_child_tid: 0, state: ThreadState::Runnable, mask: 0, pending: 0, alt_stack: None, fcsr: None }],
+            current: 0,
+            next_tid: PID + 1,
+            yielded: false,
+            tracer: None,
+            policy: None,
+            actions: BTreeMap::new(),
+            trap_division: false
+        }
+        .stdin(io::stdin())
+        .stdout(io::stdout())
+        .stderr(io::stderr())
+    }
+
+    // the break goes after everything the loader placed, including the TLS block
+    pub fn for_image(image: &ElfImage, mmap_top: usize) -> Self {
+        let end = image.tls.as_ref().map_or(0, |tls| tls.thread_pointer + tls.size).max(image.image_range().end);
+        Linux::new(end, mmap_top)
+    }
+
+    pub fn vfs<V: Vfs + 'static>(mut self, vfs: V) -> Self {
+        self.vfs = Box::new(vfs);
+        self
+    }
+
+    pub fn stdin<R: Read + 'static>(self, input: R) -> Self {
+        self.stream(0, Box::new(Input(Box::new(input))))
+    }
+
+    // flushed after every write, so output shows up as soon as the guest produces it
+    pub fn stdout<W: Write + 'static>(self, output: W) -> Self {
+        self.stream(1, Box::new(Output(Box::new(output))))
+    }
+
+    pub fn stderr<W: Write + 'static>(self, output: W) -> Self {
+        self.stream(2, Box::new(Output(Box::new(output))))
+    }
+
+    fn stream(mut self, fd: i64, file: Box<dyn File>) -> Self {
+        self.files.insert(fd, OpenFile { file, path: String::new(), listed: 0 });
+        self
+    }
+
+    // relative paths are resolved from here, it must be absolute
+    pub fn cwd(mut self, cwd: &str) -> Self {
+        self.cwd = normalize("/", cwd);
+        self
+    }
+
+    // the host's clock unless another is given, the hart's time CSR is driven by it too
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    // getrandom is seeded from the host unless given a seed, with one every run sees the same bytes
+    pub fn random_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng(seed);
+        self
+    }
+
+    // Hands every system call to tracer once it has been served, for strace style logging, e.g.
+    // .trace(|call| eprintln!("{}", call))
+    pub fn trace<F: FnMut(&SyscallTrace) + 'static>(mut self, tracer: F) -> Self {
+        self.tracer = Some(Box::new(tracer));
+        self
+    }
+
+    // consulted before every system call is served, everything is allowed without one
+    pub fn policy<P: Policy + 'static>(mut self, policy: P) -> Self {
+        self.policy = Some(Box::new(policy));
+        self
+    }
+
+    // raises SIGFPE for integer division by zero, which RISC-V itself answers without trapping
+    pub fn trap_division_by_zero(mut self, enabled: bool) -> Self {
+        self.trap_division = enabled;
+        self
+    }
+
+    // makes ecalls come back out of tick for syscall to serve and shares the clock with the hart
+    pub fn install(&self, cpu: &mut Cpu) {
+        cpu.set_ecall_fallback(Some(TRAPPING_ECALL));
+        cpu.set_clock(Some(self.clock.clone()));
+    }
+
+    pub fn program_break(&self) -> usize {
+        self.heap.program_break()
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    // Runs the guest until it exits and returns its exit status, cpu being the hart of the thread
+    // it starts with. Any trap that is not a system call ends the run and is handed back with pc
+    // still pointing at the instruction responsible. A call the policy kills the guest for, or a
+    // futex wait that nothing is left to wake, comes back out as the ecall trap it started as.
+    // A stop requested through the hart's StopHandle comes back as a TrapType::StopRequested
+    // trap before the next instruction of any thread, and running again carries on from there.
+    pub fn run(&mut self, cpu: &mut Cpu, memory: &mut dyn Memory) -> Result<i64, Trap> {
+        self.install(cpu);
+        cpu.resume();
+        for thread in self.threads.iter_mut() {
+            if let Some(hart) = thread.cpu.as_mut() {
+                hart.resume();
+            }
+        }
+        loop {
+            let index = self.current;
+            let mut own = self.threads[index].cpu.take();
+            let hart = own.as_mut().unwrap_or(&mut *cpu);
+            // every thread's flags are accrued in the one place, so each takes its own along
+            if let Some(fcsr) = self.threads[index].fcsr {
+                hart.write_csr(CSR_FCSR_ADDRESS, fcsr);
+            }
+            let turn = self.take_turn(hart, memory);
+            self.threads[index].fcsr = Some(hart.read_csr(CSR_FCSR_ADDRESS));
+            if self.threads[index].state != ThreadState::Exited {
+                self.threads[index].cpu = own;
+            }
+
+            if let Some(status) = turn? {
+                return Ok(status);
+            }
+            self.schedule(cpu)?;
+        }
+    }
+
+    // Runs the current thread until its quantum is up or it blocks, exits or yields. Signals
+    // only become pending through system calls, so those are the only places to deliver them.
+    fn take_turn(&mut self, cpu: &mut Cpu, memory: &mut dyn Memory) -> Result<Option<i64>, Trap> {
+        if let Some(status) = self.deliver_pending(cpu, memory) {
+            return Ok(Some(status));
+        }
+        for _ in 0..QUANTUM {
+            if cpu.poll_stop() {
+                return Err(Trap { trap_type: TrapType::StopRequested, value: 0 });
+            }
+            let pc = cpu.get_pc();
+            if self.trap_division && divides_by_zero(cpu, memory) && !self.fault(cpu, memory, SIGFPE, pc, pc as u64) {
+                return Ok(Some(128 + SIGFPE));
+            }
+            match cpu.tick(memory) {
+                Ok(_) => {},
+                Err(trap) if trap.trap_type == TrapType::EnvironmentCallFromUMode => {
+                    if let Some(status) = self.syscall(cpu, memory)? {
+                        return Ok(Some(status));
+                    }
+                    if let Some(status) = self.deliver_pending(cpu, memory) {
+                        return Ok(Some(status));
+                    }
+                    if self.threads[self.current].state != ThreadState::Runnable || std::mem::take(&mut self.yielded) {
+                        break;
+                    }
+                },
+                Err(trap) if trap.trap_type == TrapType::Stop => return Ok(Some(trap.value as i64)),
+                Err(trap) => {
+                    let signal = coredump::signal(&trap) as i64;
+                    let address = match trap.trap_type {
+                        TrapType::IllegalInstruction | TrapType::Breakpoint => pc as u64,
+                        _ => trap.value
+                    };
+                    if signal == SIGFPE || signal == SIGABRT || !self.fault(cpu, memory, signal, pc, address) {
+                        cpu.update_pc(pc);
+                        return Err(trap);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // Moves on to the next runnable thread round-robin. With none left, waits that were given a
+    // timeout are the only ones that can end, so they end now.
+    fn schedule(&mut self, main: &mut Cpu) -> Result<(), Trap> {
+        // a signal interrupts a futex wait, which the guest sees as EINTR
+        for thread in self.threads.iter_mut().filter(|t| matches!(t.state, ThreadState::Waiting { .. }) && signal::deliverable(t) != 0) {
+            thread.state = ThreadState::Runnable;
+            thread.cpu.as_mut().unwrap_or(&mut *main).set_register(Register::A0, -EINTR);
+        }
+
+        let count = self.threads.len();
+        if let Some(index) = (1..=count).map(|step| (self.current + step) % count).find(|i| self.threads[*i].state == ThreadState::Runnable) {
+            self.current = index;
+            return Ok(());
+        }
+
+        let mut woken = None;
+        for (index, thread) in self.threads.iter_mut().enumerate() {
+            if let ThreadState::Waiting { timeout: true, .. } = thread.state {
+                thread.state = ThreadState::Runnable;
+                thread.cpu.as_mut().unwrap_or(&mut *main).set_register(Register::A0, -ETIMEDOUT);
+                woken = woken.or(Some(index));
+            }
+        }
+        self.current = woken.ok_or(Trap { trap_type: TrapType::EnvironmentCallFromUMode, value: SYS_FUTEX as u64 })?;
+        Ok(())
+    }
+
+    // Serves the call numbered in a7 with its arguments in a0 to a5 and leaves the result, or a
+    // negated errno, in a0. Some(status) when the guest asked to exit, and the ecall trap when
+    // the policy killed it instead.
+    pub fn syscall(&mut self, cpu: &mut Cpu, memory: &mut dyn Memory) -> Result<Option<i64>, Trap> {
+        let number = cpu.get_register(Register::A7);
+        let args = [Register::A0, Register::A1, Register::A2, Register::A3, Register::A4, Register::A5]
+            .map(|r| cpu.get_register(r));
+        let tid = self.threads[self.current].tid;
+        let mut trace = self.tracer.is_some().then(|| SyscallTrace::new(memory, tid, number, &args));
+
+        let verdict = self.policy.as_mut().map_or(Verdict::Allow, |policy| policy.check(tid, number, &args));
+        // anything the call writes, a read into a buffer or a mapping being zeroed, might be where
+        // the guest runs code from next
+        let status = cpu.host_writes(memory, |cpu, memory| match verdict {
+            // the guest's own threads, memory and signals follow from what it does, what comes
+            // from the host is recorded and replayed like any other input
+            Verdict::Allow if from_host(number) => {
+                let _ = cpu.host_ecall(memory, |cpu, memory| {
+                    self.serve(cpu, memory, number, args);
+                    Ok(())
+                });
+                None
+            },
+            Verdict::Allow => self.serve(cpu, memory, number, args),
+            Verdict::Deny(errno) => {
+                cpu.set_register(Register::A0, -errno);
+                None
+            },
+            Verdict::Kill => None
+        });
+
+        if let (Some(tracer), Some(trace)) = (&mut self.tracer, &mut trace) {
+            if !matches!(number, SYS_EXIT | SYS_EXIT_GROUP) && verdict != Verdict::Kill {
+                trace.result = Some(cpu.get_register(Register::A0));
+            }
+            tracer(trace);
+        }
+        match verdict {
+            Verdict::Kill => Err(Trap { trap_type: TrapType::EnvironmentCallFromUMode, value: number as u64 }),
+            _ => Ok(status)
+        }
+    }
+
+    fn serve(&mut self, cpu: &mut Cpu, memory: &mut dyn Memory, number: i64, args: [i64; 6]) -> Option<i64> {
+        let [a0, a1, a2, a3, _, a5] = args;
+        let result = match number {
+            SYS_EXIT => return self.exit_thread(memory, a0),
+            SYS_EXIT_GROUP => return Some(a0),
+            SYS_READ => self.read(memory, a0, a1 as usize, a2 as usize),
+            SYS_WRITE => self.write(memory, a0, a1 as usize, a2 as usize),
+            SYS_READV => self.vectored(memory, a0, a1 as usize, a2 as usize, Linux::read),
+            SYS_WRITEV => self.vectored(memory, a0, a1 as usize, a2 as usize, Linux::write),
+            SYS_OPENAT => self.openat(memory, a0, a1 as usize, a2),
+            SYS_CLOSE => self.files.remove(&a0).map(|_| 0).ok_or(EBADF),
+            SYS_LSEEK => self.lseek(a0, a1, a2),
+            SYS_IOCTL => self.file(a0).and(Err(ENOTTY)),
+            SYS_GETDENTS64 => self.getdents64(memory, a0, a1 as usize, a2 as usize),
+            SYS_FSTAT => self.file(a0).and_then(|f| f.file.metadata().map_err(errno)).and_then(|m| write_stat(memory, a1 as usize, &m)),
+            SYS_NEWFSTATAT if a3 & AT_EMPTY_PATH != 0 && matches!(memory.read_u8(a1 as usize), Ok(0)) => {
+                self.file(a0).and_then(|f| f.file.metadata().map_err(errno)).and_then(|m| write_stat(memory, a2 as usize, &m))
+            },
+            SYS_NEWFSTATAT => self.path_metadata(memory, a0, a1 as usize).and_then(|m| write_stat(memory, a2 as usize, &m)),
+            SYS_FACCESSAT => self.path_metadata(memory, a0, a1 as usize).map(|_| 0),
+            SYS_CHDIR => self.chdir(memory, a0 as usize),
+            SYS_READLINKAT => Err(EINVAL),
+            SYS_GETCWD => self.getcwd(memory, a0 as usize, a1 as usize),
+            SYS_BRK => Ok(self.heap.brk(memory, a0 as usize) as i64),
+            SYS_MMAP => self.mmap(memory, a0 as usize, a1 as usize, a3),
+            SYS_MUNMAP => self.munmap(cpu, a0 as usize, a1 as usize),
+            SYS_MREMAP if heap::extent(a0 as usize, a1 as usize).is_none() || heap::extent(a0 as usize, a2 as usize).is_none() => Err(EINVAL),
+            SYS_MREMAP => self.heap.remap(memory, a0 as usize, a1 as usize, a2 as usize, a3 & MREMAP_MAYMOVE != 0)
+                .map(|start| start as i64)
+                .ok_or(ENOMEM),
+            // all guest memory is readable, writable and executable whatever it asks for
+            SYS_MPROTECT | SYS_MADVISE => Ok(0),
+            SYS_CLOCK_GETTIME => self.clock_gettime(memory, cpu.ticks(), a0, a1 as usize),
+            SYS_GETTIMEOFDAY => self.gettimeofday(memory, cpu.ticks(), a0 as usize),
+            SYS_UNAME => uname(memory, a0 as usize),
+            SYS_GETRANDOM => self.getrandom(memory, a0 as usize, a1 as usize, a2),
+            #[cfg(feature = "net")]
+            SYS_SOCKET | SYS_BIND | SYS_LISTEN | SYS_ACCEPT | SYS_ACCEPT4 | SYS_CONNECT | SYS_GETSOCKNAME | SYS_GETPEERNAME |
+            SYS_SENDTO | SYS_RECVFROM | SYS_SETSOCKOPT | SYS_GETSOCKOPT | SYS_SHUTDOWN => self.socketcall(memory, number, args),
+            SYS_CLONE => self.clone_thread(cpu, memory),
+            SYS_FUTEX => self.futex(memory, a0 as usize, a1, a2, a3 != 0, a5),
+            SYS_SET_TID_ADDRESS => {
+                self.threads[self.current].clear_child_tid = a0 as usize;
+                Ok(self.threads[self.current].tid)
+            },
+            SYS_GETTID => Ok(self.threads[self.current].tid),
+            SYS_GETPID => Ok(PID),
+            SYS_GETPPID | SYS_GETUID | SYS_GETEUID | SYS_GETGID | SYS_GETEGID => Ok(0),
+            SYS_SCHED_YIELD => {
+                self.yielded = true;
+                Ok(0)
+            },
+            SYS_RT_SIGACTION => self.sigaction(memory, a0, a1 as usize, a2 as usize),
+            SYS_RT_SIGPROCMASK => self.sigprocmask(memory, a0, a1 as usize, a2 as usize),
+            SYS_SIGALTSTACK => self.sigaltstack(memory, a0 as usize, a1 as usize),
+            SYS_RT_SIGRETURN => return self.sigreturn(cpu, memory).err().map(|_| 128 + SIGSEGV),
+            SYS_KILL if matches!(a0, PID | 0 | -1) => self.kill(None, a1),
+            SYS_KILL => Err(ESRCH),
+            SYS_TKILL => self.kill(Some(a0), a1),
+            SYS_TGKILL if a0 == PID => self.kill(Some(a1), a2),
+            SYS_TGKILL => Err(ESRCH),
+            // threads only ever end by exiting, never while holding a robust futex
+            SYS_SET_ROBUST_LIST => Ok(0),
+            _ => Err(ENOSYS)
+        };
+
+        cpu.set_register(Register::A0, result.unwrap_or_else(|errno| -errno));
+        None
+    }
+
+    // Only threads are supported, a clone that does not share the address space would need a
+    // copy of memory to run in. The new thread starts at the same pc with a0 zeroed and its own
+    // stack and thread pointer.
+    fn clone_thread(&mut self, cpu: &Cpu, memory: &mut dyn Memory) -> Result<i64, i64> {
+        let [flags, stack, parent_tid, tls, child_tid] = [Register::A0, Register::A1, Register::A2, Register::A3, Register::A4]
+            .map(|r| cpu.get_register(r));
+        let (parent_tid, child_tid) = (parent_tid as usize, child_tid as usize);
+        if flags & CLONE_VM == 0 || flags & CLONE_THREAD == 0 {
+            return Err(ENOSYS);
+        }
+
+        let tid = self.next_tid;
+        if flags & CLONE_PARENT_SETTID != 0 {
+            memory.write_u32(parent_tid, tid as u32).map_err(|_| EFAULT)?;
+        }
+        if flags & CLONE_CHILD_SETTID != 0 {
+            memory.write_u32(child_tid, tid as u32).map_err(|_| EFAULT)?;
+        }
+
+        let mut child = cpu.clone();
+        child.set_register(Register::A0, 0);
+        if stack != 0 {
+            child.set_register(Register::SP, stack);
+        }
+        if flags & CLONE_SETTLS != 0 {
+            child.set_register(Register::TP, tls);
+        }
+        self.next_tid += 1;
+        self.threads.push(Thread {
+            tid,
+            cpu: Some(child),
+            clear_child_tid: if flags & CLONE_CHILD_CLEARTID != 0 { child_tid } else { 0 },
+            state: ThreadState::Runnable,
+            mask: self.threads[self.current].mask,
+            pending: 0,
+            alt_stack: None,
+            fcsr: Some(cpu.read_csr(CSR_FCSR_ADDRESS))
+        });
+        Ok(tid)
+    }
+
+    // the process only exits along with its last thread
+    fn exit_thread(&mut self, memory: &mut dyn Memory, status: i64) -> Option<i64> {
+        let thread = &mut self.threads[self.current];
+        thread.state = ThreadState::Exited;
+        let address = thread.clear_child_tid;
+        if address != 0 && memory.write_u32(address, 0).is_ok() {
+            self.wake(address, 1, u32::MAX);
+        }
+        self.threads.iter().all(|t| t.state == ThreadState::Exited).then_some(status)
+    }
+
+    fn futex(&mut self, memory: &dyn Memory, address: usize, op: i64, value: i64, timeout: bool, bitset: i64) -> Result<i64, i64> {
+        match op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME) {
+            FUTEX_WAIT => self.wait(memory, address, value, u32::MAX, timeout),
+            FUTEX_WAIT_BITSET => self.wait(memory, address, value, bitset as u32, timeout),
+            FUTEX_WAKE => Ok(self.wake(address, value, u32::MAX)),
+            FUTEX_WAKE_BITSET => Ok(self.wake(address, value, bitset as u32)),
+            _ => Err(ENOSYS)
+        }
+    }
+
+    // blocks the current thread when the word still holds the value, what it sees once woken
+    fn wait(&mut self, memory: &dyn Memory, address: usize, value: i64, bitset: u32, timeout: bool) -> Result<i64, i64> {
+        if !address.is_multiple_of(4) || bitset == 0 {
+            return Err(EINVAL);
+        }
+        if memory.read_u32(address).map_err(|_| EFAULT)? != value as u32 {
+            return Err(EAGAIN);
+        }
+        self.threads[self.current].state = ThreadState::Waiting { address, bitset, timeout };
+        Ok(0)
+    }
+
+    // wakes up to count waiters on address, starting from the thread after the current one
+    fn wake(&mut self, address: usize, count: i64, bitset: u32) -> i64 {
+        let threads = self.threads.len();
+        let mut woken = 0;
+        for step in 1..=threads {
+            let thread = &mut self.threads[(self.current + step) % threads];
+            match thread.state {
+                _ if woken >= count as i32 as i64 => break,
+                ThreadState::Waiting { address: waiting, bitset: bits, .. } if waiting == address && bits & bitset != 0 => {
+                    thread.state = ThreadState::Runnable;
+                    woken += 1;
+                },
+                _ => {}
+            }
+        }
+        woken
+    }
+
+    fn file(&mut self, fd: i64) -> Result<&mut OpenFile, i64> {
+        self.files.get_mut(&fd).ok_or(EBADF)
+    }
+
+    // the absolute path a dirfd and path name, as passed to the *at calls
+    fn resolve(&self, memory: &dyn Memory, dirfd: i64, address: usize) -> Result<String, i64> {
+        let path = read_string(memory, address)?;
+        let base = match dirfd {
+            _ if path.starts_with('/') => "/",
+            AT_FDCWD => &self.cwd,
+            fd => &self.files.get(&fd).ok_or(EBADF)?.path
+        };
+        Ok(normalize(base, &path))
+    }
+
+    fn path_metadata(&mut self, memory: &dyn Memory, dirfd: i64, address: usize) -> Result<Metadata, i64> {
+        let path = self.resolve(memory, dirfd, address)?;
+        self.vfs.metadata(&path).map_err(errno)
+    }
+
+    fn openat(&mut self, memory: &dyn Memory, dirfd: i64, address: usize, flags: i64) -> Result<i64, i64> {
+        let path = self.resolve(memory, dirfd, address)?;
+        let options = OpenOptions {
+            read: flags & O_ACCMODE != O_WRONLY,
+            write: matches!(flags & O_ACCMODE, O_WRONLY | O_RDWR),
+            create: flags & O_CREAT != 0,
+            exclusive: flags & O_EXCL != 0,
+            truncate: flags & O_TRUNC != 0,
+            append: flags & O_APPEND != 0
+        };
+        let file = self.vfs.open(&path, &options).map_err(errno)?;
+        if flags & O_DIRECTORY != 0 && file.metadata().map_err(errno)?.file_type != FileType::Directory {
+            return Err(ENOTDIR);
+        }
+
+        self.insert_file(OpenFile { file, path, listed: 0 })
+    }
+
+    // at the lowest free descriptor, as POSIX requires
+    fn insert_file(&mut self, file: OpenFile) -> Result<i64, i64> {
+        let fd = (0..MAX_FILES).find(|fd| !self.files.contains_key(fd)).ok_or(EMFILE)?;
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    fn read(&mut self, memory: &mut dyn Memory, fd: i64, address: usize, length: usize) -> Result<i64, i64> {
+        let mut buffer = vec![0; length.min(MAX_TRANSFER)];
+        let count = self.file(fd)?.file.read(&mut buffer).map_err(errno)?;
+        write_bytes(memory, address, &buffer[..count])?;
+        Ok(count as i64)
+    }
+
+    fn write(&mut self, memory: &mut dyn Memory, fd: i64, address: usize, length: usize) -> Result<i64, i64> {
+        let bytes = read_bytes(memory, address, length.min(MAX_TRANSFER))?;
+        self.file(fd)?.file.write(&bytes).map(|count| count as i64).map_err(errno)
+    }
+
+    // readv and writev, one iovec at a time until one comes up short
+    fn vectored(&mut self, memory: &mut dyn Memory, fd: i64, iov: usize, count: usize, transfer: Transfer) -> Result<i64, i64> {
+        if count > IOV_MAX {
+            return Err(EINVAL);
+        }
+        let mut total = 0;
+        for i in 0..count {
+            let entry = i.checked_mul(16).and_then(|offset| iov.checked_add(offset)).ok_or(EFAULT)?;
+            let base = memory.read_u64(entry).map_err(|_| EFAULT)? as usize;
+            let length = memory.read_u64(entry.checked_add(8).ok_or(EFAULT)?).map_err(|_| EFAULT)? as usize;
+            let done = transfer(self, memory, fd, base, length)?;
+            total += done;
+            if (done as usize) < length {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    fn lseek(&mut self, fd: i64, offset: i64, whence: i64) -> Result<i64, i64> {
+        let position = match whence {
+            0 if offset < 0 => return Err(EINVAL),
+            0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(EINVAL)
+        };
+        self.file(fd)?.file.seek(position).map(|p| p as i64).map_err(errno)
+    }
+
+    // linux_dirent64 records for as many entries as fit, 0 once the directory has been read
+    fn getdents64(&mut self, memory: &mut dyn Memory, fd: i64, address: usize, count: usize) -> Result<i64, i64> {
+        let open = self.file(fd)?;
+        let entries = open.file.read_dir().map_err(errno)?;
+        let mut records = Vec::new();
+        for entry in entries.iter().skip(open.listed) {
+            let length = (19 + entry.name.len() + 1).next_multiple_of(8);
+            if records.len() + length > count {
+                if records.is_empty() {
+                    return Err(EINVAL);
+                }
+                break;
+            }
+
+            open.listed += 1;
+            let start = records.len();
+            records.extend_from_slice(&entry.inode.to_le_bytes());
+            records.extend_from_slice(&(open.listed as u64).to_le_bytes());
+            records.extend_from_slice(&(length as u16).to_le_bytes());
+            records.push(match entry.file_type {
+                FileType::CharacterDevice => 2,
+                FileType::Socket => 12,
+                FileType::Directory => 4,
+                FileType::File => 8
+            });
+            records.extend_from_slice(entry.name.as_bytes());
+            records.resize(start + length, 0);
+        }
+
+        write_bytes(memory, address, &records)?;
+        Ok(records.len() as i64)
+    }
+
+    fn chdir(&mut self, memory: &dyn Memory, address: usize) -> Result<i64, i64> {
+        let path = self.resolve(memory, AT_FDCWD, address)?;
+        match self.vfs.metadata(&path).map_err(errno)?.file_type {
+            FileType::Directory => {
+                self.cwd = path;
+                Ok(0)
+            },
+            _ => Err(ENOTDIR)
+        }
+    }
+
+    fn getcwd(&self, memory: &mut dyn Memory, address: usize, size: usize) -> Result<i64, i64> {
+        let cwd = [self.cwd.as_bytes(), &[0]].concat();
+        if size < cwd.len() {
+            return Err(ERANGE);
+        }
+        write_bytes(memory, address, &cwd).map(|_| cwd.len() as i64)
+    }
+
+    fn mmap(&mut self, memory: &mut dyn Memory, address: usize, length: usize, flags: i64) -> Result<i64, i64> {
+        if flags & MAP_ANONYMOUS == 0 {
+            return Err(ENODEV);
+        }
+        if length == 0 || (flags & MAP_FIXED != 0 && !address.is_multiple_of(PAGE_SIZE)) {
+            return Err(EINVAL);
+        }
+        if heap::page_align(length).is_none() || (flags & MAP_FIXED != 0 && heap::extent(address, length).is_none()) {
+            return Err(ENOMEM);
+        }
+
+        match flags & MAP_FIXED {
+            0 => self.heap.map(memory, length).ok_or(ENOMEM),
+            _ => self.heap.map_fixed(memory, address, length).map(|_| address).map_err(|_| ENOMEM)
+        }.map(|start| start as i64)
+    }
+
+    fn munmap(&mut self, cpu: &mut Cpu, address: usize, length: usize) -> Result<i64, i64> {
+        let end = match heap::extent(address, length) {
+            Some(end) if length != 0 && address.is_multiple_of(PAGE_SIZE) => end,
+            _ => return Err(EINVAL)
+        };
+        self.heap.unmap(address, length);
+        // nothing decoded from there is to run should something else be mapped in its place
+        cpu.invalidate_code(address..end);
+        Ok(0)
+    }
+
+    fn clock_gettime(&self, memory: &mut dyn Memory, ticks: u64, clock: i64, address: usize) -> Result<i64, i64> {
+        let time = match clock {
+            // CLOCK_REALTIME and CLOCK_TAI
+            0 | 11 => self.clock.realtime(ticks),
+            // the monotonic, boot time and cpu time clocks all count from when the process started
+            1..=7 => self.clock.monotonic(ticks),
+            _ => return Err(EINVAL)
+        };
+        write_time(memory, address, time, time.subsec_nanos())
+    }
+
+    fn getrandom(&mut self, memory: &mut dyn Memory, address: usize, length: usize, flags: i64) -> Result<i64, i64> {
+        if flags & !GRND_FLAGS != 0 {
+            return Err(EINVAL);
+        }
+        let mut bytes = vec![0; length.min(MAX_TRANSFER)];
+        self.rng.fill(&mut bytes);
+        write_bytes(memory, address, &bytes).map(|_| bytes.len() as i64)
+    }
+
+    fn gettimeofday(&self, memory: &mut dyn Memory, ticks: u64, address: usize) -> Result<i64, i64> {
+        if address == 0 {
+            return Ok(0);
+        }
+        let time = self.clock.realtime(ticks);
+        write_time(memory, address, time, time.subsec_micros())
+    }
+}
+
+pub(super) fn read_bytes(memory: &dyn Memory, address: usize, length: usize) -> Result<Vec<u8>, i64> {
+    marshal::read_bytes(memory, address, length).map_err(|_| EFAULT)
+}
+
+pub(super) fn write_bytes(memory: &mut dyn Memory, address: usize, bytes: &[u8]) -> Result<(), i64> {
+    marshal::write_bytes(memory, address, bytes).map_err(|_| EFAULT)
+}
+
+fn read_string(memory: &dyn Memory, address: usize) -> Result<String, i64> {
+    // a fault past PATH_MAX is a name with no end to it rather than a bad pointer
+    let bytes = marshal::read_c_string(memory, address, PATH_MAX).map_err(|trap| match trap.value as usize {
+        end if end == address + PATH_MAX => ENAMETOOLONG,
+        _ => EFAULT
+    })?;
+    String::from_utf8(bytes).map_err(|_| EINVAL)
+}
+
+// the errno the guest sees for a failed file operation
+pub(super) fn errno(error: io::Error) -> i64 {
+    match error.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        io::ErrorKind::NotADirectory => ENOTDIR,
+        io::ErrorKind::IsADirectory => EISDIR,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => error.raw_os_error().map_or(EIO, |code| code as i64)
+    }
+}
+
+fn write_stat(memory: &mut dyn Memory, address: usize, metadata: &Metadata) -> Result<i64, i64> {
+    let file_type = match metadata.file_type {
+        FileType::File => S_IFREG,
+        FileType::Directory => S_IFDIR,
+        FileType::CharacterDevice => S_IFCHR,
+        FileType::Socket => S_IFSOCK
+    };
+    let mut stat = [0u8; STAT_SIZE];
+    stat[8..16].copy_from_slice(&metadata.inode.to_le_bytes());
+    stat[16..20].copy_from_slice(&(file_type | metadata.mode).to_le_bytes());
+    stat[20..24].copy_from_slice(&1u32.to_le_bytes());
+    stat[48..56].copy_from_slice(&metadata.size.to_le_bytes());
+    stat[56..60].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+    stat[64..72].copy_from_slice(&metadata.size.div_ceil(512).to_le_bytes());
+    write_bytes(memory, address, &stat).map(|_| 0)
+}
+
+// SplitMix64, which is plenty for guest entropy that needs to be reproducible rather than secure
+#[derive(Clone, Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(8) {
+            let value = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+    }
+}
+
+// Collects everything written to it, for capturing guest output. Clones share the same buffer,
+// so one can be given to Linux::stdout and another kept to read the output back.
+#[derive(Clone, Debug, Default)]
+pub struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Capture {
+    pub fn new() -> Self {
+        Capture::default()
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// the standard streams all look like character devices to the guest
+fn stream_metadata() -> io::Result<Metadata> {
+    Ok(Metadata { file_type: FileType::CharacterDevice, size: 0, mode: 0o620, inode: 0 })
+}
+
+pub(super) struct Input(pub(super) Box<dyn Read>);
+
+impl File for Input {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buffer)
+    }
+
+    fn write(&mut self, _buffer: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(EBADF as i32))
+    }
+
+    fn metadata(&self) -> io::Result<Metadata> {
+        stream_metadata()
+    }
+}
+
+pub(super) struct Output(pub(super) Box<dyn Write>);
+
+impl File for Output {
+    fn read(&mut self, _buffer: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(EBADF as i32))
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buffer)?;
+        self.0.flush()?;
+        Ok(buffer.len())
+    }
+
+    fn metadata(&self) -> io::Result<Metadata> {
+        stream_metadata()
+    }
+}
+
+fn write_time(memory: &mut dyn Memory, address: usize, time: Duration, fraction: u32) -> Result<i64, i64> {
+    let mut value = time.as_secs().to_le_bytes().to_vec();
+    value.extend_from_slice(&(fraction as u64).to_le_bytes());
+    write_bytes(memory, address, &value).map(|_| 0)
+}
+
+// the calls whose outcome depends on the host's files, sockets, clock or randomness
+fn from_host(number: i64) -> bool {
+    #[cfg(feature = "net")]
+    if matches!(number, SYS_SOCKET | SYS_BIND | SYS_LISTEN | SYS_ACCEPT | SYS_ACCEPT4 | SYS_CONNECT | SYS_GETSOCKNAME |
+        SYS_GETPEERNAME | SYS_SENDTO | SYS_RECVFROM | SYS_SETSOCKOPT | SYS_GETSOCKOPT | SYS_SHUTDOWN) {
+        return true;
+    }
+    matches!(number, SYS_READ | SYS_WRITE | SYS_READV | SYS_WRITEV | SYS_OPENAT | SYS_CLOSE | SYS_LSEEK | SYS_IOCTL |
+        SYS_GETDENTS64 | SYS_FSTAT | SYS_NEWFSTATAT | SYS_FACCESSAT | SYS_CHDIR | SYS_GETCWD | SYS_CLOCK_GETTIME |
+        SYS_GETTIMEOFDAY | SYS_GETRANDOM)
+}
+
+fn uname(memory: &mut dyn Memory, address: usize) -> Result<i64, i64> {
+    let fields = ["Linux", "riscv", "6.1.0", "#1", "riscv64", ""];
+    let mut name = vec![0u8; fields.len() * UTSNAME_FIELD_SIZE];
+    for (i, field) in fields.iter().enumerate() {
+        name[i * UTSNAME_FIELD_SIZE..i * UTSNAME_FIELD_SIZE + field.len()].copy_from_slice(field.as_bytes());
+    }
+    write_bytes(memory, address, &name).map(|_| 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::InstructionClock;
+    use crate::cpu::test_cpu::program;
+    use crate::cpu::{Engine, ReplayStatus};
+    use crate::syscalls::policy::Rules;
+
+    pub(super) fn call(linux: &mut Linux, cpu: &mut Cpu, memory: &mut Vec<u8>, number: i64, args: &[i64]) -> i64 {
+        cpu.set_register(Register::A7, number);
+        for (i, arg) in args.iter().enumerate() {
+            cpu.set_register([Register::A0, Register::A1, Register::A2, Register::A3, Register::A4, Register::A5][i], *arg);
+        }
+        assert_eq!(None, linux.syscall(cpu, memory).unwrap());
+        cpu.get_register(Register::A0)
+    }
+
+    #[test]
+    fn break_and_mappings_stay_apart() {
+        let mut memory = vec![0xffu8; 0x10000];
+        let mut cpu = Cpu::new();
+        let mut linux = Linux::new(0xf00, 0x8000);
+
+        assert_eq!(0x1000, call(&mut linux, &mut cpu, &mut memory, SYS_BRK, &[0]));
+        assert_eq!(0x1800, call(&mut linux, &mut cpu, &mut memory, SYS_BRK, &[0x1800]));
+        assert!(memory[0x1000..0x1800].iter().all(|b| *b == 0));
+
+        assert_eq!(0x7000, call(&mut linux, &mut cpu, &mut memory, SYS_MMAP, &[0, 0x100, 3, MAP_ANONYMOUS]));
+        assert_eq!(0x5000, call(&mut linux, &mut cpu, &mut memory, SYS_MMAP, &[0, 0x2000, 3, MAP_ANONYMOUS]));
+        assert!(memory[0x5000..0x8000].iter().all(|b| *b == 0));
+        assert_eq!(0x1800, call(&mut linux, &mut cpu, &mut memory, SYS_BRK, &[0x5800]));
+        assert_eq!(-ENOMEM, call(&mut linux, &mut cpu, &mut memory, SYS_MMAP, &[0, 0x4000, 3, MAP_ANONYMOUS]));
+        assert_eq!(-ENODEV, call(&mut linux, &mut cpu, &mut memory, SYS_MMAP, &[0, 0x100, 3, 0]));
+
+        // lengths that run off the end of the address space
+        assert_eq!(-ENOMEM, call(&mut linux, &mut cpu, &mut memory, SYS_MMAP, &[0, -1, 3, MAP_ANONYMOUS]));
+        assert_eq!(-ENOMEM, call(&mut linux, &mut cpu, &mut memory, SYS_MMAP, &[-0x1000, 0x2000, 3, MAP_ANONYMOUS | MAP_FIXED]));
+        assert_eq!(-EINVAL, call(&mut linux, &mut cpu, &mut memory, SYS_MUNMAP, &[0x5000, -1]));
+        assert_eq!(-EINVAL, call(&mut linux, &mut cpu, &mut memory, SYS_MREMAP, &[0x5000, 0x2000, -1, 1]));
+    }
+
+    #[test]
+    fn replays_reproduce_what_the_host_handed_the_guest() {
+        let program = || {
+            let mut memory = vec![0u8; 0x1000];
+            memory[0x100..0x107].copy_from_slice(b"/input\0");
+            memory
+        };
+        let run = |linux: &mut Linux, cpu: &mut Cpu, memory: &mut Vec<u8>| {
+            let input = call(linux, cpu, memory, SYS_OPENAT, &[AT_FDCWD, 0x100, 0]);
+            call(linux, cpu, memory, SYS_READ, &[input, 0x200, 8]);
+            call(linux, cpu, memory, SYS_GETRANDOM, &[0x300, 16, 0]);
+            call(linux, cpu, memory, SYS_CLOCK_GETTIME, &[1, 0x400]);
+            call(linux, cpu, memory, SYS_MMAP, &[0, 0x100, 3, MAP_ANONYMOUS])
+        };
+        let mut vfs = MemoryFs::new();
+        vfs.add_file("/input", b"abc");
+        let mut memory = program();
+        let mut cpu = Cpu::new();
+        cpu.start_recording();
+        let mapped = run(&mut Linux::new(0x800, 0x1000).vfs(vfs), &mut cpu, &mut memory);
+        let log = cpu.stop_recording().unwrap();
+
+        // no file and a different random seed, yet the same results
+        let mut replayed = program();
+        let mut replay = Cpu::new();
+        replay.start_replay(log);
+        assert_eq!(mapped, run(&mut Linux::new(0x800, 0x1000), &mut replay, &mut replayed));
+        assert_eq!(b"abc", &replayed[0x200..0x203]);
+        assert_eq!(memory, replayed);
+        assert_eq!(cpu.x, replay.x);
+        assert_eq!(ReplayStatus::Replaying { remaining: 0 }, replay.replay_status());
+    }
+
+    #[test]
+    fn code_read_over_what_was_decoded_is_what_runs() {
+        let mut vfs = MemoryFs::new();
+        // addi a0, zero, 2
+        vfs.add_file("/code", &0x0020_0513u32.to_le_bytes());
+        let mut memory = vec![0u8; 0x1000];
+        memory[0x100..0x106].copy_from_slice(b"/code\0");
+        // addi a0, zero, 1
+        memory.write_u32(0, 0x0010_0513).unwrap();
+        let mut cpu = Cpu::new();
+        cpu.set_engine(Engine::Cached);
+        let mut linux = Linux::new(0x800, 0x1000).vfs(vfs);
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(1, cpu.get_register(Register::A0));
+
+        let code = call(&mut linux, &mut cpu, &mut memory, SYS_OPENAT, &[AT_FDCWD, 0x100, 0]);
+        assert_eq!(4, call(&mut linux, &mut cpu, &mut memory, SYS_READ, &[code, 0, 4]));
+        cpu.update_pc(0);
+        cpu.tick(&mut memory).unwrap();
+        assert_eq!(2, cpu.get_register(Register::A0));
+
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_MUNMAP, &[0, 0x1000]));
+        assert_eq!(0, cpu.cache_stats().decoded.entries);
+    }
+
+    #[test]
+    fn files_come_from_the_vfs() {
+        let mut vfs = MemoryFs::new();
+        vfs.add_file("/data/input.txt", b"abc");
+        let mut memory = vec![0u8; 0x1000];
+        memory[0x100..0x106].copy_from_slice(b"/data\0");
+        memory[0x110..0x11a].copy_from_slice(b"input.txt\0");
+        memory[0x120..0x128].copy_from_slice(b"out.txt\0");
+        let mut cpu = Cpu::new();
+        let mut linux = Linux::new(0x800, 0x1000).vfs(vfs.clone()).cwd("/data");
+
+        let directory = call(&mut linux, &mut cpu, &mut memory, SYS_OPENAT, &[AT_FDCWD, 0x100, O_DIRECTORY]);
+        assert_eq!(3, directory);
+        let input = call(&mut linux, &mut cpu, &mut memory, SYS_OPENAT, &[directory, 0x110, 0]);
+        assert_eq!(3, call(&mut linux, &mut cpu, &mut memory, SYS_READ, &[input, 0x200, 8]));
+        assert_eq!(b"abc", &memory[0x200..0x203]);
+        assert_eq!(1, call(&mut linux, &mut cpu, &mut memory, SYS_LSEEK, &[input, 1, 0]));
+        assert_eq!(2, call(&mut linux, &mut cpu, &mut memory, SYS_READ, &[input, 0x200, 8]));
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_NEWFSTATAT, &[AT_FDCWD, 0x110, 0x300, 0]));
+        assert_eq!(3, memory.read_u64(0x300 + 48).unwrap());
+
+        let output = call(&mut linux, &mut cpu, &mut memory, SYS_OPENAT, &[AT_FDCWD, 0x120, O_WRONLY | O_CREAT]);
+        assert_eq!(9, call(&mut linux, &mut cpu, &mut memory, SYS_WRITE, &[output, 0x110, 9]));
+        assert_eq!(-EINVAL, call(&mut linux, &mut cpu, &mut memory, SYS_LSEEK, &[output, -2, 0]));
+        assert_eq!(i64::MAX, call(&mut linux, &mut cpu, &mut memory, SYS_LSEEK, &[output, i64::MAX, 0]));
+        assert_eq!(-EFBIG, call(&mut linux, &mut cpu, &mut memory, SYS_WRITE, &[output, 0x110, 9]));
+        assert_eq!(9, call(&mut linux, &mut cpu, &mut memory, SYS_LSEEK, &[output, 9, 0]));
+        assert_eq!(Some(b"input.txt".to_vec()), vfs.contents("/data/out.txt"));
+
+        // the listing was taken when the directory was opened
+        let length = call(&mut linux, &mut cpu, &mut memory, SYS_GETDENTS64, &[directory, 0x400, 0x200]) as usize;
+        let mut names = Vec::new();
+        let mut offset = 0x400;
+        while offset < 0x400 + length {
+            let name = &memory[offset + 19..];
+            names.push(String::from_utf8(name[..name.iter().position(|b| *b == 0).unwrap()].to_vec()).unwrap());
+            offset += memory.read_u16(offset + 16).unwrap() as usize;
+        }
+        assert_eq!(vec![".", "..", "input.txt"], names);
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_GETDENTS64, &[directory, 0x400, 0x200]));
+
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_CLOSE, &[input]));
+        assert_eq!(-EBADF, call(&mut linux, &mut cpu, &mut memory, SYS_READ, &[input, 0x200, 8]));
+        assert_eq!(-ENOENT, call(&mut linux, &mut cpu, &mut memory, SYS_OPENAT, &[AT_FDCWD, 0x100 + 1, 0]));
+    }
+
+    #[test]
+    fn reports_a_riscv64_kernel() {
+        let mut memory = vec![0u8; 0x1000];
+        let mut cpu = Cpu::new();
+        let mut linux = Linux::new(0x800, 0x1000);
+
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_UNAME, &[0x100]));
+        assert_eq!(b"riscv64\0", &memory[0x100 + 4 * 65..0x100 + 4 * 65 + 8]);
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_CLOCK_GETTIME, &[0, 0x400]));
+        // some time after 2020
+        assert!(memory.read_u64(0x400).unwrap() > 1_577_836_800);
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_FSTAT, &[1, 0x400]));
+        assert_eq!(S_IFCHR, memory.read_u32(0x410).unwrap() & S_IFCHR);
+        assert_eq!(-ENOSYS, call(&mut linux, &mut cpu, &mut memory, 1234, &[]));
+    }
+
+    #[test]
+    fn standard_streams_can_be_redirected() {
+        let mut memory = vec![0u8; 0x1000];
+        let mut cpu = Cpu::new();
+        let stdout = Capture::new();
+        let mut linux = Linux::new(0x800, 0x1000).stdin(io::Cursor::new(b"fixture".to_vec())).stdout(stdout.clone());
+
+        assert_eq!(7, call(&mut linux, &mut cpu, &mut memory, SYS_READ, &[0, 0x100, 16]));
+        assert_eq!(4, call(&mut linux, &mut cpu, &mut memory, SYS_WRITE, &[1, 0x100, 4]));
+        assert_eq!(-EBADF, call(&mut linux, &mut cpu, &mut memory, SYS_READ, &[1, 0x100, 4]));
+        assert_eq!(b"fixt".to_vec(), stdout.contents());
+    }
+
+    #[test]
+    fn vectors_are_bounded_like_the_kernel_bounds_them() {
+        let mut memory = vec![0u8; 0x1000];
+        let mut cpu = Cpu::new();
+        let stdout = Capture::new();
+        let mut linux = Linux::new(0x800, 0x1000).stdout(stdout.clone());
+        memory.write_u64(0x200, 0x100).unwrap();
+        memory.write_u64(0x208, 2).unwrap();
+        memory.write_u64(0x210, 0x104).unwrap();
+        memory.write_u64(0x218, 3).unwrap();
+        memory[0x100..0x107].copy_from_slice(b"abcdefg");
+
+        assert_eq!(5, call(&mut linux, &mut cpu, &mut memory, SYS_WRITEV, &[1, 0x200, 2]));
+        assert_eq!(b"abefg".to_vec(), stdout.contents());
+        assert_eq!(-EINVAL, call(&mut linux, &mut cpu, &mut memory, SYS_WRITEV, &[1, 0x200, 1025]));
+        assert_eq!(-EINVAL, call(&mut linux, &mut cpu, &mut memory, SYS_READV, &[0, 0x200, -1]));
+        // an array that wraps past the top of the address space
+        assert_eq!(-EFAULT, call(&mut linux, &mut cpu, &mut memory, SYS_WRITEV, &[1, -16, 2]));
+        assert_eq!(-EFAULT, call(&mut linux, &mut cpu, &mut memory, SYS_WRITEV, &[1, -8, 1]));
+    }
+
+    #[test]
+    #[cfg(all(feature = "m", feature = "a", feature = "d", feature = "c"))]
+    fn mandelbrot_output_is_captured() {
+        let mut memory = vec![0u8; 1 << 20];
+        let mut cpu = Cpu::new();
+        let image = crate::loader::elf::ElfLoader::new().base(0).stack_pointer(memory.len() - 16)
+            .load_into(include_bytes!("../../test/mandelbrot"), &mut cpu, &mut memory)
+            .unwrap();
+        let stdout = Capture::new();
+
+        let status = Linux::for_image(&image, 1 << 19).stdout(stdout.clone()).run(&mut cpu, &mut memory);
+        assert_eq!(0, status.unwrap());
+        assert!(stdout.contents().ends_with(b"%%%%\n"));
+    }
+
+    #[test]
+    fn instruction_clock_makes_time_reproducible() {
+        // rdtime a0; li a7, 113; li a0, 1; li a1, 0x100; ecall; li a7, 93; ecall
+        // reading the time CSR must not disturb the tick count the clock works from
+        let words = [0xc0102573u32, 0x07100893, 0x00100513, 0x10000593, 0x00000073, 0x05d00893, 0x00000073];
+        let run = || {
+            let mut memory = program(&words);
+            memory.resize(0x200, 0);
+            let mut cpu = Cpu::new();
+            let clock = InstructionClock::new().nanoseconds_per_tick(1000);
+            Linux::new(0x200, 0x200).clock(clock).run(&mut cpu, &mut memory).unwrap();
+            memory[0x100..0x110].to_vec()
+        };
+
+        let time = run();
+        assert_eq!(time, run());
+        // five instructions in by the time clock_gettime runs
+        assert_eq!(0, u64::from_le_bytes(time[0..8].try_into().unwrap()));
+        assert_eq!(5000, u64::from_le_bytes(time[8..16].try_into().unwrap()));
+    }
+
+    #[test]
+    fn seeded_random_bytes_repeat() {
+        let mut cpu = Cpu::new();
+        let mut random = |seed| {
+            let mut memory = vec![0u8; 0x100];
+            let mut linux = Linux::new(0x100, 0x100).random_seed(seed);
+            assert_eq!(20, call(&mut linux, &mut cpu, &mut memory, SYS_GETRANDOM, &[0x10, 20, 0]));
+            assert_eq!(-EINVAL, call(&mut linux, &mut cpu, &mut memory, SYS_GETRANDOM, &[0x10, 20, 0x8]));
+            memory[0x10..0x24].to_vec()
+        };
+
+        assert_eq!(random(7), random(7));
+        assert_ne!(random(7), random(8));
+    }
+
+    #[test]
+    fn threads_take_turns_and_join() {
+        // the parent clones a thread with CLONE_PARENT_SETTID and CLONE_CHILD_CLEARTID on 0x100
+        // and futex waits there until the child has stored 42 at 0x108 and exited, then exits
+        // with what the child stored
+        let words = [
+            0x00311537u32, 0x90050513, 0x40000593, 0x10000613, 0x00000693, 0x10000713, 0x0dc00893, 0x00000073,
+            0x02050663, 0x10002603, 0x00060c63, 0x10000513, 0x08000593, 0x06200893, 0x00000073, 0xfe9ff06f,
+            0x10802503, 0x05e00893, 0x00000073,
+            0x02a00293, 0x10502423, 0x00000513, 0x05d00893, 0x00000073
+        ];
+        let mut memory = program(&words);
+        memory.resize(0x1000, 0);
+        let mut cpu = Cpu::new();
+        let mut linux = Linux::new(0x1000, 0x1000);
+        assert_eq!(42, linux.run(&mut cpu, &mut memory).unwrap());
+        assert_eq!(0, memory.read_u32(0x100).unwrap());
+
+        // li a0, 0x100; li a1, 0; li a2, 0; li a7, 98; ecall waits with nothing left to wake it
+        let mut memory = program(&[0x10000513u32, 0x00000593, 0x00000613, 0x06200893, 0x00000073]);
+        memory.resize(0x200, 0);
+        let mut cpu = Cpu::new();
+        let trap = Linux::new(0x200, 0x200).run(&mut cpu, &mut memory).unwrap_err();
+        assert_eq!(TrapType::EnvironmentCallFromUMode, trap.trap_type);
+        assert_eq!(SYS_FUTEX as u64, trap.value);
+    }
+
+    #[test]
+    #[cfg(feature = "f")]
+    fn threads_keep_their_own_fflags() {
+        // the parent clears fflags, clones a thread and yields to it, then exits with 7 plus
+        // fflags; the child sets NX and exits
+        let words = [
+            0x00105073u32, 0x00010537, 0x10050513, 0x00000593, 0x0dc00893, 0x00000073, 0x02050063,
+            0x07c00893, 0x00000073, 0x00102573, 0x00750513, 0x05e00893, 0x00000073, 0x00000013,
+            0x0010d073, 0x05d00893, 0x00000513, 0x00000073
+        ];
+        let mut memory = program(&words);
+        memory.resize(0x200, 0);
+        let mut cpu = Cpu::new();
+        assert_eq!(7, Linux::new(0x200, 0x200).run(&mut cpu, &mut memory).unwrap());
+    }
+
+    #[test]
+    fn futex_wait_checks_the_word() {
+        let mut memory = vec![0u8; 0x200];
+        memory[0x100] = 5;
+        let mut cpu = Cpu::new();
+        let mut linux = Linux::new(0x200, 0x200);
+        assert_eq!(-EAGAIN, call(&mut linux, &mut cpu, &mut memory, SYS_FUTEX, &[0x100, FUTEX_WAIT, 4]));
+        assert_eq!(-EINVAL, call(&mut linux, &mut cpu, &mut memory, SYS_FUTEX, &[0x102, FUTEX_WAIT, 5]));
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_FUTEX, &[0x100, FUTEX_WAKE, 1]));
+        assert_eq!(PID, call(&mut linux, &mut cpu, &mut memory, SYS_SET_TID_ADDRESS, &[0x180]));
+        assert_eq!(-ENOSYS, call(&mut linux, &mut cpu, &mut memory, SYS_CLONE, &[17, 0, 0, 0]));
+    }
+
+    #[test]
+    fn calls_can_be_traced() {
+        let mut memory = vec![0u8; 0x200];
+        memory[0x100..0x104].copy_from_slice(b"/no\0");
+        let mut cpu = Cpu::new();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let log = calls.clone();
+        let mut linux = Linux::new(0x200, 0x200).trace(move |call| log.lock().unwrap().push(call.to_string()));
+
+        call(&mut linux, &mut cpu, &mut memory, SYS_OPENAT, &[AT_FDCWD, 0x100, O_RDWR]);
+        call(&mut linux, &mut cpu, &mut memory, SYS_GETTID, &[]);
+        assert_eq!(vec!["openat(AT_FDCWD, \"/no\", O_RDWR) = -1 ENOENT", "gettid() = 1"], *calls.lock().unwrap());
+    }
+
+    #[test]
+    fn policy_can_deny_or_kill() {
+        // li a7, 172; ecall; li a7, 93; ecall
+        let mut memory = program(&[0x0ac00893u32, 0x00000073, 0x05d00893, 0x00000073]);
+        memory.resize(0x100, 0);
+        let rules = Rules::new(Verdict::Allow).deny(&[SYS_GETPID], EPERM).kill(&[SYS_EXIT]);
+        let mut cpu = Cpu::new();
+        let trap = Linux::new(0x100, 0x100).policy(rules).run(&mut cpu, &mut memory).unwrap_err();
+        assert_eq!(SYS_EXIT as u64, trap.value);
+        assert_eq!(-EPERM, cpu.get_register(Register::A0));
+
+        let mut cpu = Cpu::new();
+        let only_exit = |_tid, number, _args: &[i64; 6]| if number == SYS_EXIT { Verdict::Allow } else { Verdict::Deny(ENOSYS) };
+        assert_eq!(-ENOSYS, Linux::new(0x100, 0x100).policy(only_exit).run(&mut cpu, &mut memory).unwrap());
+    }
+
+    #[test]
+    fn runs_until_exit() {
+        // li a0, 3; li a7, 93; ecall
+        let mut memory = program(&[0x00300513u32, 0x05d00893, 0x00000073]);
+        let mut cpu = Cpu::new();
+        assert_eq!(3, Linux::new(0x100, 0x1000).run(&mut cpu, &mut memory).unwrap());
+    }
+
+    #[test]
+    fn a_stop_request_interrupts_the_guest() {
+        // spin: lw a0, 0x100(zero); beqz a0, spin; li a7, 93; ecall
+        let mut memory = program(&[0x10002503u32, 0xfe050ee3, 0x05d00893, 0x00000073]);
+        memory.resize(0x200, 0);
+        let mut cpu = Cpu::new();
+        let mut linux = Linux::new(0x200, 0x200);
+        let handle = cpu.stop_handle();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            handle.stop();
+        });
+
+        assert_eq!(TrapType::StopRequested, linux.run(&mut cpu, &mut memory).unwrap_err().trap_type);
+        stopper.join().unwrap();
+        // running again withdraws the request and carries on from where the guest was
+        memory.write_u32(0x100, 5).unwrap();
+        assert_eq!(5, linux.run(&mut cpu, &mut memory).unwrap());
+    }
+}
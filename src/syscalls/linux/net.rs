@@ -0,0 +1,301 @@
+use super::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+
+const AF_INET: i64 = 2;
+const AF_INET6: i64 = 10;
+const SOCK_STREAM: i64 = 1;
+const SOCK_DGRAM: i64 = 2;
+const SOCK_NONBLOCK: i64 = 0o4000;
+const SOCKADDR_IN_SIZE: usize = 16;
+const SOCKADDR_IN6_SIZE: usize = 28;
+const EAFNOSUPPORT: i64 = 97;
+const EPROTONOSUPPORT: i64 = 93;
+const EOPNOTSUPP: i64 = 95;
+const ENOTCONN: i64 = 107;
+const ENOTSOCK: i64 = 88;
+
+// A guest socket backed by one from std::net. std only makes sockets once it knows what they
+// are for, so a stream socket stays Unbound, remembering any address it was bound to, until
+// listen or connect decides which kind of host socket it becomes.
+pub enum Socket {
+    Unbound { kind: i64, ipv6: bool, nonblocking: bool, address: Option<SocketAddr> },
+    Listener(TcpListener),
+    Stream(TcpStream),
+    Datagram(UdpSocket)
+}
+
+impl File for Socket {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Stream(stream) => stream.read(buffer),
+            Socket::Datagram(socket) => socket.recv(buffer),
+            _ => Err(io::Error::from_raw_os_error(ENOTCONN as i32))
+        }
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Stream(stream) => stream.write(buffer),
+            Socket::Datagram(socket) => socket.send(buffer),
+            _ => Err(io::Error::from_raw_os_error(ENOTCONN as i32))
+        }
+    }
+
+    fn metadata(&self) -> io::Result<Metadata> {
+        Ok(Metadata { file_type: FileType::Socket, size: 0, mode: 0o777, inode: 0 })
+    }
+
+    fn socket(&mut self) -> Option<&mut Socket> {
+        Some(self)
+    }
+}
+
+impl Linux {
+    // The socket calls, passed through to the host's network stack. Calls that wait, like
+    // accept and recvfrom on a blocking socket, hold up every guest thread until they return.
+    pub(super) fn socketcall(&mut self, memory: &mut dyn Memory, number: i64, [a0, a1, a2, a3, a4, a5]: [i64; 6]) -> Result<i64, i64> {
+        match number {
+            SYS_SOCKET => self.socket(a0, a1, a2),
+            SYS_BIND => self.bind(memory, a0, a1 as usize, a2 as usize),
+            SYS_LISTEN => self.listen(a0),
+            SYS_ACCEPT => self.accept(memory, a0, a1 as usize, a2 as usize, 0),
+            SYS_ACCEPT4 => self.accept(memory, a0, a1 as usize, a2 as usize, a3),
+            SYS_CONNECT => self.connect(memory, a0, a1 as usize, a2 as usize),
+            SYS_SENDTO => self.sendto(memory, a0, a1 as usize, a2 as usize, a4 as usize, a5 as usize),
+            SYS_RECVFROM => self.recvfrom(memory, a0, a1 as usize, a2 as usize, a4 as usize, a5 as usize),
+            SYS_SHUTDOWN => self.shutdown(a0, a1),
+            SYS_GETSOCKNAME | SYS_GETPEERNAME => {
+                let address = match self.socket_file(a0)? {
+                    Socket::Listener(listener) if number == SYS_GETSOCKNAME => listener.local_addr(),
+                    Socket::Stream(stream) if number == SYS_GETSOCKNAME => stream.local_addr(),
+                    Socket::Stream(stream) => stream.peer_addr(),
+                    Socket::Datagram(socket) if number == SYS_GETSOCKNAME => socket.local_addr(),
+                    Socket::Datagram(socket) => socket.peer_addr(),
+                    Socket::Unbound { address: Some(address), .. } if number == SYS_GETSOCKNAME => Ok(*address),
+                    _ => return Err(ENOTCONN)
+                }.map_err(errno)?;
+                write_socket_address(memory, a1 as usize, a2 as usize, address).map(|_| 0)
+            },
+            // options are the host's business, and SO_ERROR and friends reading back as 0 is right
+            SYS_SETSOCKOPT => self.socket_file(a0).map(|_| 0),
+            SYS_GETSOCKOPT => {
+                self.socket_file(a0)?;
+                write_bytes(memory, a3 as usize, &0i32.to_le_bytes())?;
+                write_bytes(memory, a4 as usize, &4u32.to_le_bytes()).map(|_| 0)
+            },
+            _ => Err(ENOSYS)
+        }
+    }
+
+    fn socket_file(&mut self, fd: i64) -> Result<&mut Socket, i64> {
+        self.file(fd)?.file.socket().ok_or(ENOTSOCK)
+    }
+
+    fn socket(&mut self, domain: i64, kind: i64, _protocol: i64) -> Result<i64, i64> {
+        if domain != AF_INET && domain != AF_INET6 {
+            return Err(EAFNOSUPPORT);
+        }
+        if !matches!(kind & 0xf, SOCK_STREAM | SOCK_DGRAM) {
+            return Err(EPROTONOSUPPORT);
+        }
+        let socket = Socket::Unbound { kind: kind & 0xf, ipv6: domain == AF_INET6, nonblocking: kind & SOCK_NONBLOCK != 0, address: None };
+        self.insert_file(OpenFile { file: Box::new(socket), path: String::new(), listed: 0 })
+    }
+
+    fn bind(&mut self, memory: &dyn Memory, fd: i64, address: usize, length: usize) -> Result<i64, i64> {
+        let address = read_socket_address(memory, address, length)?;
+        let socket = self.socket_file(fd)?;
+        match socket {
+            Socket::Unbound { kind: SOCK_DGRAM, nonblocking, .. } => {
+                let bound = UdpSocket::bind(address).map_err(errno)?;
+                bound.set_nonblocking(*nonblocking).map_err(errno)?;
+                *socket = Socket::Datagram(bound);
+            },
+            Socket::Unbound { address: bound @ None, .. } => *bound = Some(address),
+            _ => return Err(EINVAL)
+        }
+        Ok(0)
+    }
+
+    fn listen(&mut self, fd: i64) -> Result<i64, i64> {
+        let socket = self.socket_file(fd)?;
+        match socket {
+            Socket::Listener(_) => {},
+            Socket::Unbound { kind: SOCK_STREAM, ipv6, nonblocking, address } => {
+                let listener = TcpListener::bind(address.unwrap_or_else(|| unspecified(*ipv6))).map_err(errno)?;
+                listener.set_nonblocking(*nonblocking).map_err(errno)?;
+                *socket = Socket::Listener(listener);
+            },
+            _ => return Err(EOPNOTSUPP)
+        }
+        Ok(0)
+    }
+
+    fn accept(&mut self, memory: &mut dyn Memory, fd: i64, address: usize, length: usize, flags: i64) -> Result<i64, i64> {
+        let (stream, peer) = match self.socket_file(fd)? {
+            Socket::Listener(listener) => listener.accept().map_err(errno)?,
+            _ => return Err(EINVAL)
+        };
+        stream.set_nonblocking(flags & SOCK_NONBLOCK != 0).map_err(errno)?;
+        if address != 0 {
+            write_socket_address(memory, address, length, peer)?;
+        }
+        self.insert_file(OpenFile { file: Box::new(Socket::Stream(stream)), path: String::new(), listed: 0 })
+    }
+
+    fn connect(&mut self, memory: &dyn Memory, fd: i64, address: usize, length: usize) -> Result<i64, i64> {
+        let address = read_socket_address(memory, address, length)?;
+        let socket = self.socket_file(fd)?;
+        match socket {
+            Socket::Unbound { kind: SOCK_STREAM, nonblocking, .. } => {
+                let stream = TcpStream::connect(address).map_err(errno)?;
+                stream.set_nonblocking(*nonblocking).map_err(errno)?;
+                *socket = Socket::Stream(stream);
+            },
+            Socket::Unbound { .. } | Socket::Datagram(_) => datagram(socket)?.connect(address).map_err(errno)?,
+            _ => return Err(EINVAL)
+        }
+        Ok(0)
+    }
+
+    fn sendto(&mut self, memory: &dyn Memory, fd: i64, buffer: usize, length: usize, address: usize, address_length: usize) -> Result<i64, i64> {
+        let bytes = read_bytes(memory, buffer, length.min(MAX_TRANSFER))?;
+        let to = match address {
+            0 => None,
+            _ => Some(read_socket_address(memory, address, address_length)?)
+        };
+        let socket = self.socket_file(fd)?;
+        let sent = match (socket, to) {
+            (Socket::Stream(stream), _) => stream.write(&bytes),
+            (socket, Some(to)) => datagram(socket)?.send_to(&bytes, to),
+            (socket, None) => datagram(socket)?.send(&bytes)
+        };
+        sent.map(|count| count as i64).map_err(errno)
+    }
+
+    fn recvfrom(&mut self, memory: &mut dyn Memory, fd: i64, buffer: usize, length: usize, address: usize, address_length: usize) -> Result<i64, i64> {
+        let mut bytes = vec![0; length.min(MAX_TRANSFER)];
+        let (count, from) = match self.socket_file(fd)? {
+            Socket::Stream(stream) => (stream.read(&mut bytes).map_err(errno)?, None),
+            Socket::Datagram(socket) => socket.recv_from(&mut bytes).map(|(count, from)| (count, Some(from))).map_err(errno)?,
+            _ => return Err(ENOTCONN)
+        };
+        write_bytes(memory, buffer, &bytes[..count])?;
+        if let (Some(from), true) = (from, address != 0) {
+            write_socket_address(memory, address, address_length, from)?;
+        }
+        Ok(count as i64)
+    }
+
+    fn shutdown(&mut self, fd: i64, how: i64) -> Result<i64, i64> {
+        let how = match how {
+            0 => Shutdown::Read,
+            1 => Shutdown::Write,
+            2 => Shutdown::Both,
+            _ => return Err(EINVAL)
+        };
+        match self.socket_file(fd)? {
+            Socket::Stream(stream) => stream.shutdown(how).map(|_| 0).map_err(errno),
+            _ => Err(ENOTCONN)
+        }
+    }
+}
+
+// a datagram socket that sends before binding is bound to any port, as the kernel would
+fn datagram(socket: &mut Socket) -> Result<&mut UdpSocket, i64> {
+    if let Socket::Unbound { kind: SOCK_DGRAM, ipv6, nonblocking, .. } = socket {
+        let bound = UdpSocket::bind(unspecified(*ipv6)).map_err(errno)?;
+        bound.set_nonblocking(*nonblocking).map_err(errno)?;
+        *socket = Socket::Datagram(bound);
+    }
+    match socket {
+        Socket::Datagram(socket) => Ok(socket),
+        _ => Err(EOPNOTSUPP)
+    }
+}
+
+fn unspecified(ipv6: bool) -> SocketAddr {
+    match ipv6 {
+        true => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        false => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)
+    }
+}
+
+// sockaddr_in and sockaddr_in6, where only the port and address are kept in network order
+fn read_socket_address(memory: &dyn Memory, address: usize, length: usize) -> Result<SocketAddr, i64> {
+    let bytes = read_bytes(memory, address, length.min(SOCKADDR_IN6_SIZE))?;
+    let family = bytes.get(0..2).map(|f| u16::from_le_bytes([f[0], f[1]]) as i64);
+    match family {
+        Some(AF_INET) if length >= SOCKADDR_IN_SIZE => {
+            let ip = Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]);
+            Ok(SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes([bytes[2], bytes[3]])))
+        },
+        Some(AF_INET6) if length >= SOCKADDR_IN6_SIZE => {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[8..24]).unwrap());
+            Ok(SocketAddr::new(IpAddr::V6(ip), u16::from_be_bytes([bytes[2], bytes[3]])))
+        },
+        Some(AF_INET | AF_INET6) => Err(EINVAL),
+        _ => Err(EAFNOSUPPORT)
+    }
+}
+
+// writes as much of the address as fits in the buffer and its full size to length_address
+fn write_socket_address(memory: &mut dyn Memory, address: usize, length_address: usize, socket_address: SocketAddr) -> Result<(), i64> {
+    let mut bytes = Vec::with_capacity(SOCKADDR_IN6_SIZE);
+    match socket_address {
+        SocketAddr::V4(v4) => {
+            bytes.extend_from_slice(&(AF_INET as u16).to_le_bytes());
+            bytes.extend_from_slice(&v4.port().to_be_bytes());
+            bytes.extend_from_slice(&v4.ip().octets());
+            bytes.resize(SOCKADDR_IN_SIZE, 0);
+        },
+        SocketAddr::V6(v6) => {
+            bytes.extend_from_slice(&(AF_INET6 as u16).to_le_bytes());
+            bytes.extend_from_slice(&v6.port().to_be_bytes());
+            bytes.extend_from_slice(&v6.flowinfo().to_be_bytes());
+            bytes.extend_from_slice(&v6.ip().octets());
+            bytes.extend_from_slice(&v6.scope_id().to_le_bytes());
+        }
+    }
+    let room = memory.read_u32(length_address).map_err(|_| EFAULT)? as usize;
+    write_bytes(memory, address, &bytes[..room.min(bytes.len())])?;
+    memory.write_u32(length_address, bytes.len() as u32).map_err(|_| EFAULT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syscalls::linux::test::call;
+
+    #[test]
+    fn tcp_and_udp_reach_the_host() {
+        let mut memory = vec![0u8; 0x1000];
+        let mut cpu = Cpu::new();
+        let mut linux = Linux::new(0x1000, 0x1000);
+        memory[0x200..0x204].copy_from_slice(b"ping");
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        memory.write_u32(0x1f0, SOCKADDR_IN_SIZE as u32).unwrap();
+        write_socket_address(&mut memory, 0x100, 0x1f0, listener.local_addr().unwrap()).unwrap();
+
+        let stream = call(&mut linux, &mut cpu, &mut memory, SYS_SOCKET, &[AF_INET, SOCK_STREAM, 0]);
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_CONNECT, &[stream, 0x100, SOCKADDR_IN_SIZE as i64]));
+        assert_eq!(4, call(&mut linux, &mut cpu, &mut memory, SYS_SENDTO, &[stream, 0x200, 4, 0, 0, 0]));
+        let mut received = [0u8; 4];
+        listener.accept().unwrap().0.read_exact(&mut received).unwrap();
+        assert_eq!(b"ping", &received);
+
+        let host = UdpSocket::bind("127.0.0.1:0").unwrap();
+        write_socket_address(&mut memory, 0x100, 0x1f0, host.local_addr().unwrap()).unwrap();
+        let datagram = call(&mut linux, &mut cpu, &mut memory, SYS_SOCKET, &[AF_INET, SOCK_DGRAM, 0]);
+        assert_eq!(4, call(&mut linux, &mut cpu, &mut memory, SYS_SENDTO, &[datagram, 0x200, 4, 0, 0x100, SOCKADDR_IN_SIZE as i64]));
+        let (count, guest) = host.recv_from(&mut received).unwrap();
+        host.send_to(b"pong", guest).unwrap();
+        assert_eq!(4, count);
+
+        assert_eq!(4, call(&mut linux, &mut cpu, &mut memory, SYS_RECVFROM, &[datagram, 0x300, 16, 0, 0x180, 0x1f0]));
+        assert_eq!(b"pong", &memory[0x300..0x304]);
+        assert_eq!(host.local_addr().unwrap(), read_socket_address(&memory, 0x180, SOCKADDR_IN_SIZE).unwrap());
+        assert_eq!(-ENOTSOCK, call(&mut linux, &mut cpu, &mut memory, SYS_LISTEN, &[1, 0]));
+    }
+}
@@ -0,0 +1,315 @@
+use super::*;
+
+pub const SIGILL: i64 = 4;
+pub const SIGTRAP: i64 = 5;
+pub const SIGABRT: i64 = 6;
+pub const SIGBUS: i64 = 7;
+pub const SIGFPE: i64 = 8;
+pub const SIGKILL: i64 = 9;
+pub const SIGSEGV: i64 = 11;
+pub const SIGTERM: i64 = 15;
+pub const SIGCHLD: i64 = 17;
+pub const SIGCONT: i64 = 18;
+pub const SIGSTOP: i64 = 19;
+pub const SIGURG: i64 = 23;
+pub const SIGWINCH: i64 = 28;
+const SIGNALS: i64 = 64;
+
+const SIG_DFL: u64 = 0;
+const SIG_IGN: u64 = 1;
+const SA_ONSTACK: u64 = 0x08000000;
+const SA_NODEFER: u64 = 0x40000000;
+const SA_RESETHAND: u64 = 0x80000000;
+const SIG_BLOCK: i64 = 0;
+const SIG_UNBLOCK: i64 = 1;
+const SIG_SETMASK: i64 = 2;
+const SS_ONSTACK: i32 = 1;
+const SS_DISABLE: i32 = 2;
+const MINSIGSTKSZ: u64 = 2048;
+// si_code for a signal sent by kill, and for the one reason each fault signal is raised here
+const SI_USER: i32 = 0;
+const FAULT_CODE: i32 = 1;
+
+// struct rt_sigframe on riscv64, siginfo followed by a ucontext with the registers in the same
+// order as a core dump and the D extension's floating point state after them
+const SIGINFO_SIZE: usize = 128;
+const UC_STACK: usize = 16;
+const UC_SIGMASK: usize = 40;
+const UC_MCONTEXT: usize = 176;
+const UC_FP: usize = UC_MCONTEXT + 256;
+const UC_FCSR: usize = UC_FP + 256;
+const UCONTEXT_SIZE: usize = 960;
+const FRAME_SIZE: usize = SIGINFO_SIZE + UCONTEXT_SIZE;
+// li a7, 139; ecall, the kernel's vdso would hold this but there is no vdso here, so it goes on
+// the stack just past the frame for the handler to return through
+const SIGRETURN_TRAMPOLINE: [u32; 2] = [0x08b00893, 0x00000073];
+pub(super) const CSR_FCSR_ADDRESS: u16 = 0x003;
+
+pub(super) fn deliverable(thread: &Thread) -> u64 {
+    thread.pending & !thread.mask
+}
+
+// the parts of a siginfo that are filled in, si_value is the fault address or the sender's pid
+struct SigInfo {
+    signal: i64,
+    code: i32,
+    value: u64
+}
+
+// what rt_sigaction installed for a signal
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(super) struct SigAction {
+    handler: u64,
+    flags: u64,
+    mask: u64
+}
+
+fn bit(signal: i64) -> u64 {
+    1 << (signal - 1)
+}
+
+// the signals whose default action is to do nothing at all
+fn ignored_by_default(signal: i64) -> bool {
+    matches!(signal, SIGCHLD | SIGCONT | SIGURG | SIGWINCH)
+}
+
+impl Linux {
+    pub(super) fn sigaction(&mut self, memory: &mut dyn Memory, signal: i64, action: usize, old_action: usize) -> Result<i64, i64> {
+        if !(1..=SIGNALS).contains(&signal) || (action != 0 && matches!(signal, SIGKILL | SIGSTOP)) {
+            return Err(EINVAL);
+        }
+        if old_action != 0 {
+            let old = self.actions.get(&signal).copied().unwrap_or_default();
+            let bytes = [old.handler, old.flags, old.mask].map(u64::to_le_bytes).concat();
+            write_bytes(memory, old_action, &bytes)?;
+        }
+        if action != 0 {
+            let [handler, flags, mask] = [0, 8, 16].map(|offset| memory.read_u64(action + offset).map_err(|_| EFAULT));
+            self.actions.insert(signal, SigAction { handler: handler?, flags: flags?, mask: mask? });
+        }
+        Ok(0)
+    }
+
+    pub(super) fn sigprocmask(&mut self, memory: &mut dyn Memory, how: i64, set: usize, old_set: usize) -> Result<i64, i64> {
+        let thread = &mut self.threads[self.current];
+        if old_set != 0 {
+            write_bytes(memory, old_set, &thread.mask.to_le_bytes())?;
+        }
+        if set != 0 {
+            let set = memory.read_u64(set).map_err(|_| EFAULT)?;
+            thread.mask = match how {
+                SIG_BLOCK => thread.mask | set,
+                SIG_UNBLOCK => thread.mask & !set,
+                SIG_SETMASK => set,
+                _ => return Err(EINVAL)
+            } & !(bit(SIGKILL) | bit(SIGSTOP));
+        }
+        Ok(0)
+    }
+
+    // stack_t is the stack's base, its flags and its size
+    pub(super) fn sigaltstack(&mut self, memory: &mut dyn Memory, stack: usize, old_stack: usize) -> Result<i64, i64> {
+        let thread = &mut self.threads[self.current];
+        if old_stack != 0 {
+            let (base, size, flags) = thread.alt_stack.map_or((0, 0, SS_DISABLE), |(base, size)| (base, size, 0));
+            write_bytes(memory, old_stack, &[(base as u64).to_le_bytes(), (flags as u64).to_le_bytes(), (size as u64).to_le_bytes()].concat())?;
+        }
+        if stack != 0 {
+            let [base, flags, size] = [0, 8, 16].map(|offset| memory.read_u64(stack + offset).map_err(|_| EFAULT));
+            let (base, flags, size) = (base?, flags?, size?);
+            thread.alt_stack = match flags as i32 {
+                SS_DISABLE => None,
+                0 | SS_ONSTACK if size < MINSIGSTKSZ => return Err(ENOMEM),
+                0 | SS_ONSTACK => Some((base as usize, size as usize)),
+                _ => return Err(EINVAL)
+            };
+        }
+        Ok(0)
+    }
+
+    // Queues signal for tid, or for whichever thread is not blocking it when sent to the whole
+    // process. Signal 0 only checks that the target exists.
+    pub(super) fn kill(&mut self, tid: Option<i64>, signal: i64) -> Result<i64, i64> {
+        if !(0..=SIGNALS).contains(&signal) {
+            return Err(EINVAL);
+        }
+        let alive = |thread: &Thread| thread.state != ThreadState::Exited;
+        let target = match tid {
+            Some(tid) => self.threads.iter().position(|t| t.tid == tid && alive(t)),
+            None => [self.current].into_iter().chain(0..self.threads.len())
+                .find(|i| alive(&self.threads[*i]) && (signal == 0 || self.threads[*i].mask & bit(signal) == 0))
+                .or(Some(self.current))
+        };
+        let thread = &mut self.threads[target.ok_or(ESRCH)?];
+        if signal != 0 {
+            thread.pending |= bit(signal);
+        }
+        Ok(0)
+    }
+
+    // Runs the handler for the lowest pending signal the current thread is not blocking. Some
+    // status when the signal's default action ends the process, as 128 plus the signal the way
+    // a shell reports it.
+    pub(super) fn deliver_pending(&mut self, cpu: &mut Cpu, memory: &mut dyn Memory) -> Option<i64> {
+        loop {
+            let thread = &mut self.threads[self.current];
+            let signal = match deliverable(thread) {
+                0 => return None,
+                signals => signals.trailing_zeros() as i64 + 1
+            };
+            thread.pending &= !bit(signal);
+
+            let action = self.actions.get(&signal).copied().unwrap_or_default();
+            match action.handler {
+                SIG_IGN => {},
+                SIG_DFL if ignored_by_default(signal) => {},
+                SIG_DFL => return Some(128 + signal),
+                _ => if self.push_frame(cpu, memory, SigInfo { signal, code: SI_USER, value: PID as u64 }, cpu.get_pc(), action).is_err() {
+                    return Some(128 + SIGSEGV);
+                }
+            }
+        }
+    }
+
+    // Hands a fault to the guest's handler for signal with the faulting instruction's pc, false
+    // when there is no handler, it is blocked, or the frame will not fit on the stack.
+    pub(super) fn fault(&mut self, cpu: &mut Cpu, memory: &mut dyn Memory, signal: i64, pc: usize, address: u64) -> bool {
+        let action = self.actions.get(&signal).copied().unwrap_or_default();
+        action.handler > SIG_IGN
+            && self.threads[self.current].mask & bit(signal) == 0
+            && self.push_frame(cpu, memory, SigInfo { signal, code: FAULT_CODE, value: address }, pc, action).is_ok()
+    }
+
+    // builds the rt_sigframe below the stack pointer, or on the alternate stack, and enters the
+    // handler with the signal, the siginfo and the ucontext as its arguments
+    fn push_frame(&mut self, cpu: &mut Cpu, memory: &mut dyn Memory, info: SigInfo, pc: usize, action: SigAction) -> Result<(), i64> {
+        let signal = info.signal;
+        let thread = &mut self.threads[self.current];
+        let mut sp = cpu.get_register(Register::SP) as usize;
+        if let (true, Some((base, size))) = (action.flags & SA_ONSTACK != 0, thread.alt_stack) {
+            if !(base..base + size).contains(&sp) {
+                sp = base + size;
+            }
+        }
+        let frame = sp.checked_sub(FRAME_SIZE + 8).ok_or(EFAULT)? & !15;
+
+        let mut bytes = vec![0u8; FRAME_SIZE + 8];
+        bytes[0..4].copy_from_slice(&(signal as i32).to_le_bytes());
+        bytes[8..12].copy_from_slice(&info.code.to_le_bytes());
+        bytes[16..24].copy_from_slice(&info.value.to_le_bytes());
+        let uc = SIGINFO_SIZE;
+        let (base, size, flags) = thread.alt_stack.map_or((0, 0, SS_DISABLE), |(base, size)| (base, size, 0));
+        bytes[uc + UC_STACK..uc + UC_STACK + 8].copy_from_slice(&(base as u64).to_le_bytes());
+        bytes[uc + UC_STACK + 8..uc + UC_STACK + 12].copy_from_slice(&flags.to_le_bytes());
+        bytes[uc + UC_STACK + 16..uc + UC_STACK + 24].copy_from_slice(&(size as u64).to_le_bytes());
+        bytes[uc + UC_SIGMASK..uc + UC_SIGMASK + 8].copy_from_slice(&thread.mask.to_le_bytes());
+        let registers = std::iter::once(pc as u64).chain(cpu.registers()[1..].iter().map(|x| *x as u64));
+        let floats = cpu.fp_registers().iter().map(|f| f.to_bits());
+        for (i, value) in registers.chain(floats).enumerate() {
+            bytes[uc + UC_MCONTEXT + i * 8..uc + UC_MCONTEXT + i * 8 + 8].copy_from_slice(&value.to_le_bytes());
+        }
+        bytes[uc + UC_FCSR..uc + UC_FCSR + 4].copy_from_slice(&(cpu.read_csr(CSR_FCSR_ADDRESS) as u32).to_le_bytes());
+        for (i, word) in SIGRETURN_TRAMPOLINE.iter().enumerate() {
+            bytes[FRAME_SIZE + i * 4..FRAME_SIZE + i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        write_bytes(memory, frame, &bytes)?;
+
+        thread.mask |= action.mask | if action.flags & SA_NODEFER != 0 { 0 } else { bit(signal) };
+        thread.mask &= !(bit(SIGKILL) | bit(SIGSTOP));
+        if action.flags & SA_RESETHAND != 0 {
+            self.actions.remove(&signal);
+        }
+        cpu.update_pc(action.handler as usize);
+        cpu.set_register(Register::A0, signal);
+        cpu.set_register(Register::A1, frame as i64);
+        cpu.set_register(Register::A2, (frame + SIGINFO_SIZE) as i64);
+        cpu.set_register(Register::RA, (frame + FRAME_SIZE) as i64);
+        cpu.set_register(Register::SP, frame as i64);
+        // a compiled trampoline might still be cached from an earlier frame at the same place
+        cpu.invalidate_code_cache();
+        Ok(())
+    }
+
+    // puts back everything push_frame saved, including any changes the handler made to it
+    pub(super) fn sigreturn(&mut self, cpu: &mut Cpu, memory: &dyn Memory) -> Result<(), i64> {
+        let uc = cpu.get_register(Register::SP) as usize + SIGINFO_SIZE;
+        let read = |offset: usize| memory.read_u64(uc + offset).map_err(|_| EFAULT);
+        let mut registers = [0u64; 64];
+        for (i, register) in registers.iter_mut().enumerate() {
+            *register = read(UC_MCONTEXT + i * 8)?;
+        }
+        let fcsr = memory.read_u32(uc + UC_FCSR).map_err(|_| EFAULT)?;
+        self.threads[self.current].mask = read(UC_SIGMASK)? & !(bit(SIGKILL) | bit(SIGSTOP));
+
+        cpu.update_pc(registers[0] as usize);
+        cpu.x[1..].iter_mut().zip(&registers[1..32]).for_each(|(x, value)| *x = *value as i64);
+        cpu.f.iter_mut().zip(&registers[32..]).for_each(|(f, value)| *f = f64::from_bits(*value));
+        cpu.write_csr(CSR_FCSR_ADDRESS, fcsr as u64);
+        Ok(())
+    }
+}
+
+// div, divu, rem and remu and their word forms with a zero divisor, which RISC-V answers without
+// trapping but other architectures raise SIGFPE for
+pub(super) fn divides_by_zero(cpu: &Cpu, memory: &dyn Memory) -> bool {
+    let Ok(word) = memory.read_u32(cpu.get_pc()) else {
+        return false;
+    };
+    let divisor = cpu.registers()[(word >> 20) as usize & 0x1f];
+    match (word & 0x7f, word >> 25, (word >> 12) & 0x7) {
+        (0x33, 1, 4..=7) => divisor == 0,
+        (0x3b, 1, 4..=7) => divisor as i32 == 0,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+    use crate::syscalls::linux::test::call;
+
+    #[test]
+    fn faults_reach_the_guest_handler() {
+        // main installs the handler at 0x100 for SIGSEGV, loads from 0x10000 which is past the
+        // end of memory and then exits with s1, which only the handler sets
+        let mut memory = program(&[
+            0x00b00513, 0x20000593, 0x00000613, 0x00800693, 0x08600893, 0x00000073, 0x000102b7, 0x0002b303,
+            0x00048513, 0x05d00893, 0x00000073
+        ]);
+        memory.resize(0x100, 0);
+        // the handler steps the saved pc past the load, sets the saved s1 to 7 and returns
+        memory.extend(program(&[0x0b063283, 0x00428293, 0x0a563823, 0x00700293, 0x0e563c23, 0x00008067]));
+        memory.resize(0x1000, 0);
+        memory.write_u64(0x200, 0x100).unwrap();
+        let mut cpu = Cpu::new();
+        cpu.set_register(Register::SP, 0x1000);
+        assert_eq!(7, Linux::new(0x1000, 0x1000).run(&mut cpu, &mut memory).unwrap());
+
+        // li a0, 1; div a0, a0, zero; exit dies of SIGFPE once division by zero traps
+        let mut memory = program(&[0x00100513, 0x02054533, 0x05d00893, 0x00000073]);
+        memory.resize(0x200, 0);
+        let mut cpu = Cpu::new();
+        assert_eq!(128 + SIGFPE, Linux::new(0x200, 0x200).trap_division_by_zero(true).run(&mut cpu, &mut memory).unwrap());
+    }
+
+    #[test]
+    fn signals_can_be_blocked_and_sent() {
+        let mut memory = vec![0u8; 0x200];
+        let mut cpu = Cpu::new();
+        let mut linux = Linux::new(0x200, 0x200);
+        assert_eq!(-EINVAL, call(&mut linux, &mut cpu, &mut memory, SYS_RT_SIGACTION, &[SIGKILL, 0x100, 0, 8]));
+
+        memory.write_u64(0x100, bit(SIGTERM) | bit(SIGKILL)).unwrap();
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_RT_SIGPROCMASK, &[SIG_BLOCK, 0x100, 0x108, 8]));
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_RT_SIGPROCMASK, &[SIG_BLOCK, 0, 0x108, 8]));
+        assert_eq!(bit(SIGTERM), memory.read_u64(0x108).unwrap());
+
+        // blocked, SIGTERM waits until it is unblocked and then ends the process
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_KILL, &[PID, SIGTERM]));
+        assert_eq!(None, linux.deliver_pending(&mut cpu, &mut memory));
+        assert_eq!(0, call(&mut linux, &mut cpu, &mut memory, SYS_RT_SIGPROCMASK, &[SIG_UNBLOCK, 0x100, 0, 8]));
+        assert_eq!(Some(128 + SIGTERM), linux.deliver_pending(&mut cpu, &mut memory));
+        assert_eq!(-ESRCH, call(&mut linux, &mut cpu, &mut memory, SYS_TGKILL, &[PID, PID + 1, SIGTERM]));
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    // fails the call with this errno without serving it
+    Deny(i64),
+    // ends the run, the call comes back out of Linux::run as the ecall trap it started as
+    Kill
+}
+
+// Decides what happens to each system call before it is served, the way a seccomp filter does.
+// Closures taking the calling thread, the call number and its six arguments are policies too.
+pub trait Policy {
+    fn check(&mut self, tid: i64, number: i64, args: &[i64; 6]) -> Verdict;
+}
+
+impl<F: FnMut(i64, i64, &[i64; 6]) -> Verdict> Policy for F {
+    fn check(&mut self, tid: i64, number: i64, args: &[i64; 6]) -> Verdict {
+        self(tid, number, args)
+    }
+}
+
+// A verdict for each call number and one for everything else, which is all most filters need.
+#[derive(Clone, Debug)]
+pub struct Rules {
+    otherwise: Verdict,
+    calls: BTreeMap<i64, Verdict>
+}
+
+impl Rules {
+    pub fn new(otherwise: Verdict) -> Self {
+        Rules { otherwise, calls: BTreeMap::new() }
+    }
+
+    pub fn allow(self, numbers: &[i64]) -> Self {
+        self.verdict(numbers, Verdict::Allow)
+    }
+
+    pub fn deny(self, numbers: &[i64], errno: i64) -> Self {
+        self.verdict(numbers, Verdict::Deny(errno))
+    }
+
+    pub fn kill(self, numbers: &[i64]) -> Self {
+        self.verdict(numbers, Verdict::Kill)
+    }
+
+    fn verdict(mut self, numbers: &[i64], verdict: Verdict) -> Self {
+        self.calls.extend(numbers.iter().map(|n| (*n, verdict)));
+        self
+    }
+}
+
+impl Policy for Rules {
+    fn check(&mut self, _tid: i64, number: i64, _args: &[i64; 6]) -> Verdict {
+        self.calls.get(&number).copied().unwrap_or(self.otherwise)
+    }
+}
@@ -0,0 +1,379 @@
+use crate::clock::{Clock, HostClock, TIMEBASE_FREQUENCY};
+use crate::cpu::{Cpu, Register, Trap, TrapType, Xlen};
+use crate::memory::Memory;
+use crate::memory::marshal;
+use crate::syscalls::linux::{errno, read_bytes, write_bytes, Input, Output, EBADF, EFAULT, EINVAL, EMFILE, ENOSYS};
+use crate::syscalls::vfs::{normalize, File, MemoryFs, OpenOptions, Vfs};
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{Read, SeekFrom, Write};
+use std::sync::Arc;
+
+pub const SYS_OPEN: i64 = 0x01;
+pub const SYS_CLOSE: i64 = 0x02;
+pub const SYS_WRITEC: i64 = 0x03;
+pub const SYS_WRITE0: i64 = 0x04;
+pub const SYS_WRITE: i64 = 0x05;
+pub const SYS_READ: i64 = 0x06;
+pub const SYS_READC: i64 = 0x07;
+pub const SYS_ISERROR: i64 = 0x08;
+pub const SYS_ISTTY: i64 = 0x09;
+pub const SYS_SEEK: i64 = 0x0a;
+pub const SYS_FLEN: i64 = 0x0c;
+pub const SYS_CLOCK: i64 = 0x10;
+pub const SYS_TIME: i64 = 0x11;
+pub const SYS_ERRNO: i64 = 0x13;
+pub const SYS_GET_CMDLINE: i64 = 0x15;
+pub const SYS_HEAPINFO: i64 = 0x16;
+pub const SYS_EXIT: i64 = 0x18;
+pub const SYS_EXIT_EXTENDED: i64 = 0x20;
+pub const SYS_ELAPSED: i64 = 0x30;
+pub const SYS_TICKFREQ: i64 = 0x31;
+
+// slli zero, zero, 0x1f; ebreak; srai zero, zero, 7
+const SEQUENCE: [u32; 3] = [0x01f01013, 0x00100073, 0x40705013];
+// the reason SYS_EXIT is given for a program returning from main, any other is a failure
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+// the special file name SYS_OPEN gives the console, read for stdin, written for stdout and
+// appended to for stderr
+const CONSOLE: &str = ":tt";
+const MAX_HANDLES: i64 = 1024;
+// the most a single read or write moves
+const MAX_TRANSFER: usize = 1 << 20;
+
+// Whether the ebreak at address is the middle of the sequence that makes a semihosting call.
+pub fn is_call(memory: &dyn Memory, address: usize) -> bool {
+    let Some(start) = address.checked_sub(4) else {
+        return false;
+    };
+    SEQUENCE.iter().enumerate().all(|(i, word)| memory.read_u32(start + i * 4).is_ok_and(|value| value == *word))
+}
+
+// The Arm semihosting calls RISC-V adopted, which embedded toolchains use in place of an
+// operating system to print, read files and exit. The calls are served from the host: the
+// console is the host's standard streams unless redirected and files come from a Vfs, an empty
+// one unless another is given. Failed calls leave their errno for SYS_ERRNO.
+pub struct Semihosting {
+    vfs: Box<dyn Vfs>,
+    // stdin, stdout and stderr
+    streams: [Box<dyn File>; 3],
+    handles: BTreeMap<i64, Handle>,
+    command_line: String,
+    // heap base and limit then stack base and limit, as SYS_HEAPINFO reports them
+    heap_info: [u64; 4],
+    clock: Arc<dyn Clock>,
+    errno: i64
+}
+
+enum Handle {
+    Console(usize),
+    File(Box<dyn File>)
+}
+
+impl Semihosting {
+    pub fn new() -> Self {
+        Semihosting {
+            vfs: Box::new(MemoryFs::new()),
+            streams: [Box::new(Input(Box::new(io::stdin()))), Box::new(Output(Box::new(io::stdout()))), Box::new(Output(Box::new(io::stderr())))],
+            handles: BTreeMap::new(),
+            command_line: String::new(),
+            heap_info: [0; 4],
+            clock: Arc::new(HostClock::new()),
+            errno: 0
+        }
+    }
+
+    pub fn vfs<V: Vfs + 'static>(mut self, vfs: V) -> Self {
+        self.vfs = Box::new(vfs);
+        self
+    }
+
+    pub fn stdin<R: Read + 'static>(mut self, input: R) -> Self {
+        self.streams[0] = Box::new(Input(Box::new(input)));
+        self
+    }
+
+    pub fn stdout<W: Write + 'static>(mut self, output: W) -> Self {
+        self.streams[1] = Box::new(Output(Box::new(output)));
+        self
+    }
+
+    pub fn stderr<W: Write + 'static>(mut self, output: W) -> Self {
+        self.streams[2] = Box::new(Output(Box::new(output)));
+        self
+    }
+
+    // what SYS_GET_CMDLINE hands back, the program name and its arguments separated by spaces
+    pub fn command_line(mut self, command_line: &str) -> Self {
+        self.command_line = command_line.to_string();
+        self
+    }
+
+    // where the C runtime should put its heap and stack, zero leaves it to work that out itself
+    pub fn heap_info(mut self, heap_base: u64, heap_limit: u64, stack_base: u64, stack_limit: u64) -> Self {
+        self.heap_info = [heap_base, heap_limit, stack_base, stack_limit];
+        self
+    }
+
+    // the host's clock unless another is given, the hart's time CSR is driven by it too
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    // Runs the guest until it exits and returns its exit status. Any other trap ends the run and
+    // is handed back with pc still pointing at the instruction responsible.
+    pub fn run(&mut self, cpu: &mut Cpu, memory: &mut dyn Memory) -> Result<i64, Trap> {
+        cpu.set_clock(Some(self.clock.clone()));
+        loop {
+            let pc = cpu.get_pc();
+            match cpu.tick(memory) {
+                Ok(_) => {},
+                Err(trap) if trap.trap_type == TrapType::Breakpoint && is_call(memory, trap.value as usize) => {
+                    if let Some(status) = self.call(cpu, memory) {
+                        return Ok(status);
+                    }
+                },
+                Err(trap) if trap.trap_type == TrapType::Stop => return Ok(trap.value as i64),
+                Err(trap) => {
+                    cpu.update_pc(pc);
+                    return Err(trap);
+                }
+            }
+        }
+    }
+
+    // Serves the call numbered in a0 with its parameter block at a1, leaving the result in a0.
+    // Some exit status once the guest has asked to stop.
+    pub fn call(&mut self, cpu: &mut Cpu, memory: &mut dyn Memory) -> Option<i64> {
+        let operation = cpu.get_register(Register::A0);
+        let block = cpu.unsigned_data(cpu.get_register(Register::A1)) as usize;
+        let xlen = cpu.get_xlen();
+        let ticks = cpu.ticks();
+
+        let result = match operation {
+            SYS_OPEN => fields(memory, xlen, block).and_then(|[name, mode, length]| self.open(memory, name as usize, mode, length as usize)),
+            SYS_CLOSE => fields(memory, xlen, block).and_then(|[handle]| self.handles.remove(&(handle as i64)).map(|_| 0).ok_or(EBADF)),
+            SYS_WRITEC => read_bytes(memory, block, 1).and_then(|byte| self.streams[1].write(&byte).map(|_| 0).map_err(errno)),
+            SYS_WRITE0 => self.write0(memory, block),
+            SYS_WRITE => fields(memory, xlen, block).and_then(|[handle, address, length]| self.write(memory, handle as i64, address as usize, length as usize)),
+            SYS_READ => fields(memory, xlen, block).and_then(|[handle, address, length]| self.read(memory, handle as i64, address as usize, length as usize)),
+            SYS_READC => {
+                let mut byte = [0];
+                match self.streams[0].read(&mut byte).map_err(errno) {
+                    Ok(1) => Ok(byte[0] as i64),
+                    Ok(_) => Ok(-1),
+                    Err(error) => Err(error)
+                }
+            },
+            SYS_ISERROR => fields(memory, xlen, block).map(|[status]| ((status as i64) < 0) as i64),
+            SYS_ISTTY => fields(memory, xlen, block).and_then(|[handle]| match self.handles.get(&(handle as i64)) {
+                Some(Handle::Console(_)) => Ok(1),
+                Some(Handle::File(_)) => Ok(0),
+                None => Err(EBADF)
+            }),
+            SYS_SEEK => fields(memory, xlen, block).and_then(|[handle, position]| {
+                self.file(handle as i64)?.seek(SeekFrom::Start(position)).map(|_| 0).map_err(errno)
+            }),
+            SYS_FLEN => fields(memory, xlen, block).and_then(|[handle]| {
+                self.file(handle as i64)?.metadata().map(|metadata| metadata.size as i64).map_err(errno)
+            }),
+            SYS_CLOCK => Ok(self.clock.monotonic(ticks).as_millis() as i64 / 10),
+            SYS_TIME => Ok(self.clock.realtime(ticks).as_secs() as i64),
+            SYS_ERRNO => Ok(self.errno),
+            SYS_GET_CMDLINE => self.get_cmdline(memory, xlen, block),
+            SYS_HEAPINFO => fields(memory, xlen, block).and_then(|[address]| {
+                self.heap_info.iter().enumerate().try_for_each(|(i, value)| write_field(memory, xlen, address as usize, i, *value)).map(|_| 0)
+            }),
+            // on RV32 the reason is passed in a1 itself and there is no exit code to go with it
+            SYS_EXIT => {
+                let [reason, code] = match xlen {
+                    Xlen::Bit32 => [block as u64, 0],
+                    Xlen::Bit64 => fields(memory, xlen, block).unwrap_or([0, 1])
+                };
+                return Some(if reason == ADP_STOPPED_APPLICATION_EXIT { code as i64 } else { 1 });
+            },
+            SYS_EXIT_EXTENDED => {
+                let [reason, code] = fields(memory, xlen, block).unwrap_or([0, 1]);
+                return Some(if reason == ADP_STOPPED_APPLICATION_EXIT { code as i64 } else { 1 });
+            },
+            // in ticks of the time CSR, as a 64 bit value even on RV32
+            SYS_ELAPSED => {
+                let elapsed = self.clock.monotonic(ticks).as_nanos() as u64 * TIMEBASE_FREQUENCY / 1_000_000_000;
+                write_bytes(memory, block, &elapsed.to_le_bytes()).map(|_| 0)
+            },
+            SYS_TICKFREQ => Ok(TIMEBASE_FREQUENCY as i64),
+            // the Vfs cannot remove or rename files and there is no host shell to run commands
+            _ => Err(ENOSYS)
+        };
+
+        let value = result.unwrap_or_else(|error| {
+            self.errno = error;
+            -1
+        });
+        cpu.set_register(Register::A0, value);
+        None
+    }
+
+    // mode is the index of an fopen mode in r, rb, r+, r+b, w, wb, w+, w+b, a, ab, a+, a+b
+    fn open(&mut self, memory: &dyn Memory, name: usize, mode: u64, length: usize) -> Result<i64, i64> {
+        if mode > 11 {
+            return Err(EINVAL);
+        }
+        let name = String::from_utf8(read_bytes(memory, name, length)?).map_err(|_| EINVAL)?;
+        let handle = match name.as_str() {
+            CONSOLE => Handle::Console(mode as usize / 4),
+            _ => {
+                let (kind, update) = (mode / 4, mode & 2 != 0);
+                let options = OpenOptions {
+                    read: kind == 0 || update,
+                    write: kind != 0 || update,
+                    create: kind != 0,
+                    exclusive: false,
+                    truncate: kind == 1,
+                    append: kind == 2
+                };
+                Handle::File(self.vfs.open(&normalize("/", &name), &options).map_err(errno)?)
+            }
+        };
+
+        // handles start at 1, some C libraries take 0 to mean that nothing is open
+        let number = (1..MAX_HANDLES).find(|number| !self.handles.contains_key(number)).ok_or(EMFILE)?;
+        self.handles.insert(number, handle);
+        Ok(number)
+    }
+
+    fn file(&mut self, handle: i64) -> Result<&mut Box<dyn File>, i64> {
+        match self.handles.get_mut(&handle) {
+            Some(Handle::Console(stream)) => Ok(&mut self.streams[*stream]),
+            Some(Handle::File(file)) => Ok(file),
+            None => Err(EBADF)
+        }
+    }
+
+    // the NUL terminated string at address, to the console
+    fn write0(&mut self, memory: &dyn Memory, address: usize) -> Result<i64, i64> {
+        let bytes = marshal::read_c_string(memory, address, MAX_TRANSFER).map_err(|_| EFAULT)?;
+        self.streams[1].write(&bytes).map(|_| 0).map_err(errno)
+    }
+
+    // SYS_WRITE and SYS_READ both report how many bytes were not transferred
+    fn write(&mut self, memory: &dyn Memory, handle: i64, address: usize, length: usize) -> Result<i64, i64> {
+        let bytes = read_bytes(memory, address, length.min(MAX_TRANSFER))?;
+        let count = self.file(handle)?.write(&bytes).map_err(errno)?;
+        Ok((length - count) as i64)
+    }
+
+    fn read(&mut self, memory: &mut dyn Memory, handle: i64, address: usize, length: usize) -> Result<i64, i64> {
+        let mut buffer = vec![0; length.min(MAX_TRANSFER)];
+        let count = self.file(handle)?.read(&mut buffer).map_err(errno)?;
+        write_bytes(memory, address, &buffer[..count])?;
+        Ok((length - count) as i64)
+    }
+
+    // the block holds the buffer and its size, which is updated to the length of the command line
+    fn get_cmdline(&self, memory: &mut dyn Memory, xlen: Xlen, block: usize) -> Result<i64, i64> {
+        let [address, size] = fields(memory, xlen, block)?;
+        if self.command_line.len() >= size as usize {
+            return Err(EINVAL);
+        }
+        write_bytes(memory, address as usize, &[self.command_line.as_bytes(), &[0]].concat())?;
+        write_field(memory, xlen, block, 1, self.command_line.len() as u64).map(|_| 0)
+    }
+}
+
+impl Default for Semihosting {
+    fn default() -> Self {
+        Semihosting::new()
+    }
+}
+
+// the first N fields of a parameter block, each as wide as a register
+fn fields<const N: usize>(memory: &dyn Memory, xlen: Xlen, block: usize) -> Result<[u64; N], i64> {
+    let mut fields = [0; N];
+    for (i, field) in fields.iter_mut().enumerate() {
+        let read = match xlen {
+            Xlen::Bit32 => memory.read_u32(block + i * 4).map(|value| value as u64),
+            Xlen::Bit64 => memory.read_u64(block + i * 8)
+        };
+        *field = read.map_err(|_| EFAULT)?;
+    }
+    Ok(fields)
+}
+
+fn write_field(memory: &mut dyn Memory, xlen: Xlen, block: usize, index: usize, value: u64) -> Result<(), i64> {
+    match xlen {
+        Xlen::Bit32 => write_bytes(memory, block + index * 4, &(value as u32).to_le_bytes()),
+        Xlen::Bit64 => write_bytes(memory, block + index * 8, &value.to_le_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::test_cpu::program;
+    use crate::syscalls::linux::Capture;
+
+    fn call(semihosting: &mut Semihosting, cpu: &mut Cpu, memory: &mut Vec<u8>, operation: i64, block: usize) -> i64 {
+        cpu.set_register(Register::A0, operation);
+        cpu.set_register(Register::A1, block as i64);
+        assert_eq!(None, semihosting.call(cpu, memory));
+        cpu.get_register(Register::A0)
+    }
+
+    #[test]
+    fn programs_print_and_exit() {
+        // a bare ebreak, then SYS_WRITE0 of the string at 0x100 and SYS_EXIT with the block at 0x110
+        let words = [
+            0x00100073u32, 0x00400513, 0x10000593, 0x01f01013, 0x00100073, 0x40705013,
+            0x01800513, 0x11000593, 0x01f01013, 0x00100073, 0x40705013
+        ];
+        let mut memory = program(&words);
+        memory.resize(0x100, 0);
+        memory.extend_from_slice(b"hello\n\0");
+        memory.resize(0x110, 0);
+        memory.extend_from_slice(&ADP_STOPPED_APPLICATION_EXIT.to_le_bytes());
+        memory.extend_from_slice(&3u64.to_le_bytes());
+
+        let output = Capture::new();
+        let mut cpu = Cpu::new();
+        assert_eq!(3, Semihosting::new().stdout(output.clone()).run(&mut cpu, &mut memory).unwrap());
+        assert_eq!(b"hello\n".to_vec(), output.contents());
+    }
+
+    #[test]
+    fn files_come_from_the_vfs() {
+        let mut vfs = MemoryFs::new();
+        vfs.add_file("/data.txt", b"semihosted");
+        let mut semihosting = Semihosting::new().vfs(vfs).command_line("test -v");
+        let mut cpu = Cpu::new();
+        let mut memory = vec![0u8; 0x200];
+        memory[0x100..0x108].copy_from_slice(b"data.txt");
+
+        // open "data.txt" with mode rb
+        memory.write_u64(0x10, 0x100).unwrap();
+        memory.write_u64(0x18, 1).unwrap();
+        memory.write_u64(0x20, 8).unwrap();
+        let handle = call(&mut semihosting, &mut cpu, &mut memory, SYS_OPEN, 0x10);
+        assert_eq!(1, handle);
+        memory.write_u64(0x30, handle as u64).unwrap();
+        assert_eq!(10, call(&mut semihosting, &mut cpu, &mut memory, SYS_FLEN, 0x30));
+        assert_eq!(0, call(&mut semihosting, &mut cpu, &mut memory, SYS_ISTTY, 0x30));
+
+        // read 16 bytes to 0x180, 6 of which are left over
+        memory.write_u64(0x38, 0x180).unwrap();
+        memory.write_u64(0x40, 16).unwrap();
+        assert_eq!(6, call(&mut semihosting, &mut cpu, &mut memory, SYS_READ, 0x30));
+        assert_eq!(b"semihosted", &memory[0x180..0x18a]);
+
+        assert_eq!(0, call(&mut semihosting, &mut cpu, &mut memory, SYS_CLOSE, 0x30));
+        assert_eq!(-1, call(&mut semihosting, &mut cpu, &mut memory, SYS_CLOSE, 0x30));
+        assert_eq!(EBADF, call(&mut semihosting, &mut cpu, &mut memory, SYS_ERRNO, 0));
+
+        memory.write_u64(0x50, 0x1c0).unwrap();
+        memory.write_u64(0x58, 0x20).unwrap();
+        assert_eq!(0, call(&mut semihosting, &mut cpu, &mut memory, SYS_GET_CMDLINE, 0x50));
+        assert_eq!(b"test -v\0", &memory[0x1c0..0x1c8]);
+        assert_eq!(7, memory.read_u64(0x58).unwrap());
+    }
+}
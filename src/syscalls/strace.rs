@@ -0,0 +1,276 @@
+use crate::memory::Memory;
+use crate::syscalls::linux::*;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+// how many bytes of a buffer or string to show before cutting it short with ...
+const MAX_SHOWN: usize = 32;
+
+// One system call as strace would print it: the name, the arguments decoded as far as the name
+// allows, with paths and written data read out of guest memory and flags spelled out, and what
+// the call returned. result is None for the calls that never return, like exit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyscallTrace {
+    pub tid: i64,
+    pub number: i64,
+    pub name: Option<&'static str>,
+    pub arguments: Vec<String>,
+    pub result: Option<i64>
+}
+
+impl SyscallTrace {
+    // decodes the arguments, which needs to happen before the call has a chance to change memory
+    pub fn new(memory: &dyn Memory, tid: i64, number: i64, args: &[i64; 6]) -> Self {
+        SyscallTrace { tid, number, name: name(number), arguments: arguments(memory, number, args), result: None }
+    }
+}
+
+impl Display for SyscallTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "{}({})", name, self.arguments.join(", "))?,
+            None => write!(f, "syscall_{:#x}({})", self.number, self.arguments.join(", "))?
+        }
+        match self.result {
+            None => write!(f, " = ?"),
+            Some(result) if (-4095..0).contains(&result) => match errno_name(-result) {
+                Some(errno) => write!(f, " = -1 {}", errno),
+                None => write!(f, " = -1 errno {}", -result)
+            },
+            Some(result) if matches!(self.number, SYS_BRK | SYS_MMAP | SYS_MREMAP) => write!(f, " = {:#x}", result),
+            Some(result) => write!(f, " = {}", result)
+        }
+    }
+}
+
+const NAMES: &[(i64, &str, usize)] = &[
+    (SYS_GETCWD, "getcwd", 2),
+    (SYS_IOCTL, "ioctl", 3),
+    (SYS_FACCESSAT, "faccessat", 3),
+    (SYS_CHDIR, "chdir", 1),
+    (SYS_OPENAT, "openat", 4),
+    (SYS_CLOSE, "close", 1),
+    (SYS_GETDENTS64, "getdents64", 3),
+    (SYS_LSEEK, "lseek", 3),
+    (SYS_READ, "read", 3),
+    (SYS_WRITE, "write", 3),
+    (SYS_READV, "readv", 3),
+    (SYS_WRITEV, "writev", 3),
+    (SYS_READLINKAT, "readlinkat", 4),
+    (SYS_NEWFSTATAT, "newfstatat", 4),
+    (SYS_FSTAT, "fstat", 2),
+    (SYS_EXIT, "exit", 1),
+    (SYS_EXIT_GROUP, "exit_group", 1),
+    (SYS_SET_TID_ADDRESS, "set_tid_address", 1),
+    (SYS_FUTEX, "futex", 6),
+    (SYS_SET_ROBUST_LIST, "set_robust_list", 2),
+    (SYS_CLOCK_GETTIME, "clock_gettime", 2),
+    (SYS_SCHED_YIELD, "sched_yield", 0),
+    (SYS_KILL, "kill", 2),
+    (SYS_TKILL, "tkill", 2),
+    (SYS_TGKILL, "tgkill", 3),
+    (SYS_SIGALTSTACK, "sigaltstack", 2),
+    (SYS_RT_SIGACTION, "rt_sigaction", 4),
+    (SYS_RT_SIGPROCMASK, "rt_sigprocmask", 4),
+    (SYS_RT_SIGRETURN, "rt_sigreturn", 0),
+    (SYS_UNAME, "uname", 1),
+    (SYS_GETTIMEOFDAY, "gettimeofday", 2),
+    (SYS_GETPID, "getpid", 0),
+    (SYS_GETPPID, "getppid", 0),
+    (SYS_GETUID, "getuid", 0),
+    (SYS_GETEUID, "geteuid", 0),
+    (SYS_GETGID, "getgid", 0),
+    (SYS_GETEGID, "getegid", 0),
+    (SYS_GETTID, "gettid", 0),
+    (SYS_BRK, "brk", 1),
+    (SYS_SOCKET, "socket", 3),
+    (SYS_BIND, "bind", 3),
+    (SYS_LISTEN, "listen", 2),
+    (SYS_ACCEPT, "accept", 3),
+    (SYS_CONNECT, "connect", 3),
+    (SYS_GETSOCKNAME, "getsockname", 3),
+    (SYS_GETPEERNAME, "getpeername", 3),
+    (SYS_SENDTO, "sendto", 6),
+    (SYS_RECVFROM, "recvfrom", 6),
+    (SYS_SETSOCKOPT, "setsockopt", 5),
+    (SYS_GETSOCKOPT, "getsockopt", 5),
+    (SYS_SHUTDOWN, "shutdown", 2),
+    (SYS_MUNMAP, "munmap", 2),
+    (SYS_MREMAP, "mremap", 5),
+    (SYS_CLONE, "clone", 5),
+    (SYS_MMAP, "mmap", 6),
+    (SYS_MPROTECT, "mprotect", 3),
+    (SYS_MADVISE, "madvise", 3),
+    (SYS_ACCEPT4, "accept4", 4),
+    (SYS_GETRANDOM, "getrandom", 3)
+];
+
+const OPEN_FLAGS: &[(i64, &str)] = &[
+    (0o100, "O_CREAT"), (0o200, "O_EXCL"), (0o400, "O_NOCTTY"), (0o1000, "O_TRUNC"), (0o2000, "O_APPEND"),
+    (0o4000, "O_NONBLOCK"), (0o200000, "O_DIRECTORY"), (0o400000, "O_NOFOLLOW"), (0o2000000, "O_CLOEXEC")
+];
+
+const PROT_FLAGS: &[(i64, &str)] = &[(0x1, "PROT_READ"), (0x2, "PROT_WRITE"), (0x4, "PROT_EXEC")];
+
+const MAP_FLAGS: &[(i64, &str)] = &[
+    (0x1, "MAP_SHARED"), (0x2, "MAP_PRIVATE"), (0x10, "MAP_FIXED"), (0x20, "MAP_ANONYMOUS"),
+    (0x100, "MAP_GROWSDOWN"), (0x4000, "MAP_NORESERVE"), (0x8000, "MAP_POPULATE"), (0x20000, "MAP_STACK")
+];
+
+const CLONE_FLAGS: &[(i64, &str)] = &[
+    (0x100, "CLONE_VM"), (0x200, "CLONE_FS"), (0x400, "CLONE_FILES"), (0x800, "CLONE_SIGHAND"),
+    (0x4000, "CLONE_VFORK"), (0x10000, "CLONE_THREAD"), (0x40000, "CLONE_SYSVSEM"), (0x80000, "CLONE_SETTLS"),
+    (0x100000, "CLONE_PARENT_SETTID"), (0x200000, "CLONE_CHILD_CLEARTID"), (0x1000000, "CLONE_CHILD_SETTID")
+];
+
+const AT_FLAGS: &[(i64, &str)] = &[(0x100, "AT_SYMLINK_NOFOLLOW"), (0x200, "AT_EACCESS"), (0x1000, "AT_EMPTY_PATH")];
+
+const FUTEX_OPS: &[&str] = &[
+    "FUTEX_WAIT", "FUTEX_WAKE", "FUTEX_FD", "FUTEX_REQUEUE", "FUTEX_CMP_REQUEUE", "FUTEX_WAKE_OP", "FUTEX_LOCK_PI",
+    "FUTEX_UNLOCK_PI", "FUTEX_TRYLOCK_PI", "FUTEX_WAIT_BITSET", "FUTEX_WAKE_BITSET"
+];
+
+pub fn name(number: i64) -> Option<&'static str> {
+    NAMES.iter().find(|(n, _, _)| *n == number).map(|(_, name, _)| *name)
+}
+
+pub fn errno_name(errno: i64) -> Option<&'static str> {
+    Some(match errno {
+        EPERM => "EPERM",
+        ENOENT => "ENOENT",
+        ESRCH => "ESRCH",
+        EINTR => "EINTR",
+        EIO => "EIO",
+        EBADF => "EBADF",
+        EAGAIN => "EAGAIN",
+        ENOMEM => "ENOMEM",
+        EACCES => "EACCES",
+        EFAULT => "EFAULT",
+        EEXIST => "EEXIST",
+        ENODEV => "ENODEV",
+        ENOTDIR => "ENOTDIR",
+        EISDIR => "EISDIR",
+        EINVAL => "EINVAL",
+        EMFILE => "EMFILE",
+        ENOTTY => "ENOTTY",
+        ESPIPE => "ESPIPE",
+        ERANGE => "ERANGE",
+        ENAMETOOLONG => "ENAMETOOLONG",
+        ENOSYS => "ENOSYS",
+        ETIMEDOUT => "ETIMEDOUT",
+        _ => return None
+    })
+}
+
+fn arguments(memory: &dyn Memory, number: i64, args: &[i64; 6]) -> Vec<String> {
+    let [a0, a1, a2, a3, a4, a5] = *args;
+    match number {
+        SYS_OPENAT if a2 & 0o100 != 0 => vec![dirfd(a0), path(memory, a1), open_flags(a2), format!("{:#o}", a3)],
+        SYS_OPENAT => vec![dirfd(a0), path(memory, a1), open_flags(a2)],
+        SYS_NEWFSTATAT => vec![dirfd(a0), path(memory, a1), hex(a2), flags(a3, AT_FLAGS)],
+        SYS_FACCESSAT => vec![dirfd(a0), path(memory, a1), format!("{:#o}", a2)],
+        SYS_READLINKAT => vec![dirfd(a0), path(memory, a1), hex(a2), a3.to_string()],
+        SYS_CHDIR => vec![path(memory, a0)],
+        SYS_WRITE => vec![a0.to_string(), buffer(memory, a1, a2), a2.to_string()],
+        SYS_READ | SYS_GETDENTS64 | SYS_READV | SYS_WRITEV => vec![a0.to_string(), hex(a1), a2.to_string()],
+        SYS_LSEEK => vec![a0.to_string(), a1.to_string(), ["SEEK_SET", "SEEK_CUR", "SEEK_END"].get(a2 as usize).map_or(a2.to_string(), |w| w.to_string())],
+        SYS_MMAP => vec![hex(a0), a1.to_string(), prot(a2), flags(a3, MAP_FLAGS), a4.to_string(), hex(a5)],
+        SYS_MPROTECT => vec![hex(a0), a1.to_string(), prot(a2)],
+        SYS_CLONE => vec![flags(a0, CLONE_FLAGS), hex(a1), hex(a2), hex(a3), hex(a4)],
+        SYS_FUTEX => {
+            let op = FUTEX_OPS.get((a1 & 0x7f) as usize).map_or((a1 & 0x7f).to_string(), |op| op.to_string());
+            let op = [op.as_str(), if a1 & 128 != 0 { "|FUTEX_PRIVATE_FLAG" } else { "" }, if a1 & 256 != 0 { "|FUTEX_CLOCK_REALTIME" } else { "" }].concat();
+            vec![hex(a0), op, a2.to_string(), hex(a3)]
+        },
+        SYS_EXIT | SYS_EXIT_GROUP | SYS_CLOSE => vec![a0.to_string()],
+        _ => {
+            let count = NAMES.iter().find(|(n, _, _)| *n == number).map_or(6, |(_, _, count)| *count);
+            args[..count].iter().map(|a| hex(*a)).collect()
+        }
+    }
+}
+
+fn hex(value: i64) -> String {
+    format!("{:#x}", value)
+}
+
+fn dirfd(fd: i64) -> String {
+    match fd {
+        -100 => "AT_FDCWD".to_string(),
+        fd => fd.to_string()
+    }
+}
+
+// flags joined with | and whatever bits have no name left over in hex, 0x0 when none are set
+fn flags(value: i64, names: &[(i64, &str)]) -> String {
+    let mut parts: Vec<String> = names.iter().filter(|(bit, _)| value & bit != 0).map(|(_, name)| name.to_string()).collect();
+    let rest = names.iter().fold(value, |rest, (bit, _)| rest & !bit);
+    if rest != 0 || parts.is_empty() {
+        parts.push(hex(rest));
+    }
+    parts.join("|")
+}
+
+fn prot(value: i64) -> String {
+    match value {
+        0 => "PROT_NONE".to_string(),
+        _ => flags(value, PROT_FLAGS)
+    }
+}
+
+fn open_flags(value: i64) -> String {
+    let mode = ["O_RDONLY", "O_WRONLY", "O_RDWR", "O_ACCMODE"][(value & 0o3) as usize];
+    match flags(value & !0o3, OPEN_FLAGS).as_str() {
+        "0x0" => mode.to_string(),
+        rest => format!("{}|{}", mode, rest)
+    }
+}
+
+fn path(memory: &dyn Memory, address: i64) -> String {
+    let mut bytes = Vec::new();
+    while let Ok(byte) = memory.read_u8(address as usize + bytes.len()) {
+        if byte == 0 || bytes.len() > MAX_SHOWN * 8 {
+            break;
+        }
+        bytes.push(byte);
+    }
+    quote(&bytes, false)
+}
+
+fn buffer(memory: &dyn Memory, address: i64, length: i64) -> String {
+    let shown = (length.max(0) as usize).min(MAX_SHOWN);
+    let bytes: Vec<u8> = (0..shown).map_while(|i| memory.read_u8(address as usize + i).ok()).collect();
+    quote(&bytes, (length as usize) > shown)
+}
+
+fn quote(bytes: &[u8], truncated: bool) -> String {
+    let text: String = bytes.iter().flat_map(|b| std::ascii::escape_default(*b)).map(char::from).collect();
+    format!("\"{}\"{}", text, if truncated { "..." } else { "" })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decodes_like_strace() {
+        let mut memory = vec![0u8; 0x100];
+        memory[0x10..0x1b].copy_from_slice(b"/etc/passwd");
+        memory[0x20..0x23].copy_from_slice(b"hi\n");
+
+        let mut open = SyscallTrace::new(&memory, 1, SYS_OPENAT, &[-100, 0x10, 0o2000000, 0, 0, 0]);
+        open.result = Some(-ENOENT);
+        assert_eq!("openat(AT_FDCWD, \"/etc/passwd\", O_RDONLY|O_CLOEXEC) = -1 ENOENT", open.to_string());
+
+        let mut write = SyscallTrace::new(&memory, 1, SYS_WRITE, &[1, 0x20, 3, 0, 0, 0]);
+        write.result = Some(3);
+        assert_eq!("write(1, \"hi\\n\", 3) = 3", write.to_string());
+
+        let mut mmap = SyscallTrace::new(&memory, 1, SYS_MMAP, &[0, 0x1000, 3, 0x22, -1, 0]);
+        mmap.result = Some(0x7000);
+        assert_eq!("mmap(0x0, 4096, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0x0) = 0x7000", mmap.to_string());
+
+        assert_eq!("exit_group(0) = ?", SyscallTrace::new(&memory, 1, SYS_EXIT_GROUP, &[0; 6]).to_string());
+        assert_eq!("syscall_0x4d2(0x0, 0x0, 0x0, 0x0, 0x0, 0x0) = ?", SyscallTrace::new(&memory, 1, 1234, &[0; 6]).to_string());
+    }
+}
@@ -0,0 +1,573 @@
+use crate::syscalls::linux::{EACCES, EEXIST, EFBIG, EINVAL, EISDIR, ENOENT, ENOTDIR, ESPIPE};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "net")]
+use crate::syscalls::linux::net::Socket;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    CharacterDevice,
+    Socket
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub file_type: FileType,
+    pub size: u64,
+    // the permission bits, without the file type
+    pub mode: u32,
+    pub inode: u64
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub file_type: FileType,
+    pub inode: u64
+}
+
+// what openat was asked for, stripped of everything the file systems do not care about
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpenOptions {
+    pub read: bool,
+    pub write: bool,
+    pub create: bool,
+    pub exclusive: bool,
+    pub truncate: bool,
+    pub append: bool
+}
+
+// An open file. Errors carry the errno the guest should see, either through their kind or as
+// a raw os error.
+pub trait File {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize>;
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize>;
+    fn metadata(&self) -> io::Result<Metadata>;
+
+    fn seek(&mut self, _position: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::from_raw_os_error(ESPIPE as i32))
+    }
+
+    // every entry, . and .. included, for an open directory
+    fn read_dir(&mut self) -> io::Result<Vec<DirEntry>> {
+        Err(io::Error::from_raw_os_error(ENOTDIR as i32))
+    }
+
+    #[cfg(feature = "net")]
+    fn socket(&mut self) -> Option<&mut Socket> {
+        None
+    }
+}
+
+// The files a guest can see. Paths are always absolute and normalized, with no . or ..
+// components left in them, by the time they get here.
+pub trait Vfs {
+    fn open(&mut self, path: &str, options: &OpenOptions) -> io::Result<Box<dyn File>>;
+    fn metadata(&mut self, path: &str) -> io::Result<Metadata>;
+}
+
+// Resolves path against the absolute directory base. .. never climbs above the root, so the
+// result can be joined onto a host directory without escaping it.
+pub fn normalize(base: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    let full = if path.starts_with('/') { path.to_string() } else { format!("{}/{}", base, path) };
+    for component in full.split('/') {
+        match component {
+            "" | "." => {},
+            ".." => { components.pop(); },
+            name => components.push(name)
+        }
+    }
+    format!("/{}", components.join("/"))
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(index) => &path[..index]
+    }
+}
+
+#[derive(Clone)]
+enum Node {
+    File(Arc<Mutex<Vec<u8>>>),
+    Directory
+}
+
+// A file system that lives entirely in host memory, for handing a guest exactly the files it
+// needs. Clones share the same files, so keeping one back is how the host reads what the guest
+// wrote once the other has been handed over.
+#[derive(Clone)]
+pub struct MemoryFs {
+    // path to node and inode number
+    nodes: Arc<Mutex<BTreeMap<String, (Node, u64)>>>
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert("/".to_string(), (Node::Directory, 1));
+        MemoryFs { nodes: Arc::new(Mutex::new(nodes)) }
+    }
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        MemoryFs::default()
+    }
+
+    // creates any missing parent directories along the way
+    pub fn add_file(&mut self, path: &str, contents: &[u8]) {
+        let path = normalize("/", path);
+        self.add_directory(parent(&path));
+        self.insert(path, Node::File(Arc::new(Mutex::new(contents.to_vec()))));
+    }
+
+    pub fn add_directory(&mut self, path: &str) {
+        let path = normalize("/", path);
+        if !self.nodes.lock().unwrap().contains_key(&path) {
+            self.add_directory(parent(&path));
+            self.insert(path, Node::Directory);
+        }
+    }
+
+    pub fn contents(&self, path: &str) -> Option<Vec<u8>> {
+        match self.nodes.lock().unwrap().get(&normalize("/", path)) {
+            Some((Node::File(data), _)) => Some(data.lock().unwrap().clone()),
+            _ => None
+        }
+    }
+
+    fn insert(&mut self, path: String, node: Node) {
+        let mut nodes = self.nodes.lock().unwrap();
+        // replacing a node keeps its inode number
+        let inode = nodes.get(&path).map_or(nodes.len() as u64 + 1, |(_, inode)| *inode);
+        nodes.insert(path, (node, inode));
+    }
+
+    fn entries(&self, path: &str, inode: u64) -> Vec<DirEntry> {
+        let nodes = self.nodes.lock().unwrap();
+        let parent_inode = nodes.get(parent(path)).map_or(inode, |(_, inode)| *inode);
+        let mut entries = vec![
+            DirEntry { name: ".".to_string(), file_type: FileType::Directory, inode },
+            DirEntry { name: "..".to_string(), file_type: FileType::Directory, inode: parent_inode }
+        ];
+        for (child, (node, inode)) in nodes.iter() {
+            if child != path && parent(child) == path {
+                entries.push(DirEntry { name: child[child.rfind('/').unwrap() + 1..].to_string(), file_type: node.file_type(), inode: *inode });
+            }
+        }
+        entries
+    }
+}
+
+impl Node {
+    fn file_type(&self) -> FileType {
+        match self {
+            Node::File(_) => FileType::File,
+            Node::Directory => FileType::Directory
+        }
+    }
+
+    fn metadata(&self, inode: u64) -> Metadata {
+        match self {
+            Node::File(data) => Metadata { file_type: FileType::File, size: data.lock().unwrap().len() as u64, mode: 0o644, inode },
+            Node::Directory => Metadata { file_type: FileType::Directory, size: 0, mode: 0o755, inode }
+        }
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn open(&mut self, path: &str, options: &OpenOptions) -> io::Result<Box<dyn File>> {
+        let existing = self.nodes.lock().unwrap().get(path).cloned();
+        if existing.is_none() {
+            if !options.create {
+                return Err(io::Error::from_raw_os_error(ENOENT as i32));
+            }
+            if !matches!(self.nodes.lock().unwrap().get(parent(path)), Some((Node::Directory, _))) {
+                return Err(io::Error::from_raw_os_error(ENOENT as i32));
+            }
+            self.insert(path.to_string(), Node::File(Arc::new(Mutex::new(Vec::new()))));
+        } else if options.create && options.exclusive {
+            return Err(io::Error::from_raw_os_error(EEXIST as i32));
+        }
+
+        let (node, inode) = self.nodes.lock().unwrap()[path].clone();
+        match node {
+            Node::File(data) => {
+                if options.truncate && options.write {
+                    data.lock().unwrap().clear();
+                }
+                Ok(Box::new(MemoryFile { data, position: 0, inode, append: options.append }))
+            },
+            Node::Directory if options.write => Err(io::Error::from_raw_os_error(EISDIR as i32)),
+            Node::Directory => Ok(Box::new(MemoryDirectory { entries: self.entries(path, inode), inode }))
+        }
+    }
+
+    fn metadata(&mut self, path: &str) -> io::Result<Metadata> {
+        match self.nodes.lock().unwrap().get(path) {
+            Some((node, inode)) => Ok(node.metadata(*inode)),
+            None => Err(io::Error::from_raw_os_error(ENOENT as i32))
+        }
+    }
+}
+
+// how big a guest can make a file held in host memory
+const MAX_MEMORY_FILE: u64 = 1 << 32;
+
+struct MemoryFile {
+    data: Arc<Mutex<Vec<u8>>>,
+    position: u64,
+    inode: u64,
+    append: bool
+}
+
+impl File for MemoryFile {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.lock().unwrap();
+        let start = (self.position as usize).min(data.len());
+        let count = buffer.len().min(data.len() - start);
+        buffer[..count].copy_from_slice(&data[start..start + count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let mut data = self.data.lock().unwrap();
+        if self.append {
+            self.position = data.len() as u64;
+        }
+        let end = self.position.checked_add(buffer.len() as u64).filter(|end| *end <= MAX_MEMORY_FILE)
+            .ok_or(io::Error::from_raw_os_error(EFBIG as i32))?;
+        let (start, end) = (self.position as usize, end as usize);
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buffer);
+        self.position += buffer.len() as u64;
+        Ok(buffer.len())
+    }
+
+    fn metadata(&self) -> io::Result<Metadata> {
+        Ok(Node::File(self.data.clone()).metadata(self.inode))
+    }
+
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => (self.data.lock().unwrap().len() as u64).checked_add_signed(offset)
+        };
+        // the position has to come back to the guest as an off_t
+        self.position = position.filter(|p| *p <= i64::MAX as u64).ok_or(io::Error::from_raw_os_error(EINVAL as i32))?;
+        Ok(self.position)
+    }
+}
+
+struct MemoryDirectory {
+    entries: Vec<DirEntry>,
+    inode: u64
+}
+
+impl File for MemoryDirectory {
+    fn read(&mut self, _buffer: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(EISDIR as i32))
+    }
+
+    fn write(&mut self, _buffer: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(EISDIR as i32))
+    }
+
+    fn metadata(&self) -> io::Result<Metadata> {
+        Ok(Node::Directory.metadata(self.inode))
+    }
+
+    fn read_dir(&mut self) -> io::Result<Vec<DirEntry>> {
+        Ok(self.entries.clone())
+    }
+}
+
+// Passes file access through to a directory on the host, which the guest sees as its root.
+// Parts of the guest's tree can be mounted from elsewhere on the host, and allow and deny lists
+// of guest paths decide what it may touch at all. Paths are normalized before they are joined
+// on and symbolic links are resolved and checked, so nothing outside the root or the mounts can
+// be reached either way.
+#[derive(Clone, Debug)]
+pub struct HostFs {
+    root: PathBuf,
+    // guest path to the host file or directory standing in for it
+    mounts: Vec<(String, PathBuf)>,
+    allowed: Vec<String>,
+    denied: Vec<String>
+}
+
+impl HostFs {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        HostFs { root: root.into(), mounts: Vec::new(), allowed: Vec::new(), denied: Vec::new() }
+    }
+
+    // serves guest_path and everything below it from host_path, the longest matching mount wins
+    pub fn mount<P: Into<PathBuf>>(mut self, guest_path: &str, host_path: P) -> Self {
+        self.mounts.push((normalize("/", guest_path), host_path.into()));
+        self
+    }
+
+    // Once anything is allowed, only paths at or below an allowed one can be opened. Denying
+    // wins over allowing, and everything refused fails with EACCES.
+    pub fn allow(mut self, guest_path: &str) -> Self {
+        self.allowed.push(normalize("/", guest_path));
+        self
+    }
+
+    pub fn deny(mut self, guest_path: &str) -> Self {
+        self.denied.push(normalize("/", guest_path));
+        self
+    }
+
+    fn permitted(&self, path: &str) -> bool {
+        !self.denied.iter().any(|p| within(path, p)) && (self.allowed.is_empty() || self.allowed.iter().any(|p| within(path, p)))
+    }
+
+    // where path lives on the host, with any links resolved so that it can be checked to still
+    // be inside the root or mount it was found through
+    fn host_path(&self, path: &str) -> io::Result<PathBuf> {
+        if !self.permitted(path) {
+            return Err(io::Error::from_raw_os_error(EACCES as i32));
+        }
+        let (base, rest) = self.mounts.iter()
+            .filter(|(guest, _)| within(path, guest))
+            .max_by_key(|(guest, _)| guest.len())
+            .map_or((&self.root, path), |(guest, host)| (host, &path[guest.len()..]));
+
+        let joined = match rest.trim_start_matches('/') {
+            "" => base.clone(),
+            rest => base.join(rest)
+        };
+        let resolved = match fs::canonicalize(&joined) {
+            Ok(resolved) => resolved,
+            // a link to nowhere would be followed out of the root by anything that creates it
+            Err(_) if fs::symlink_metadata(&joined).is_ok_and(|m| m.file_type().is_symlink()) => {
+                return Err(io::Error::from_raw_os_error(EACCES as i32));
+            },
+            // something about to be created, so only the directory it goes in has to exist
+            Err(e) if e.kind() == io::ErrorKind::NotFound => match (joined.parent(), joined.file_name()) {
+                (Some(directory), Some(name)) => fs::canonicalize(directory)?.join(name),
+                _ => return Err(e)
+            },
+            Err(e) => return Err(e)
+        };
+        // a link can lead to something inside that the guest would be refused by its own name
+        match resolved.starts_with(fs::canonicalize(base)?) && self.guest_paths(&resolved).iter().all(|guest| self.permitted(guest)) {
+            true => Ok(resolved),
+            false => Err(io::Error::from_raw_os_error(EACCES as i32))
+        }
+    }
+
+    // every guest path that leads to the resolved host path, through the root or a mount
+    fn guest_paths(&self, resolved: &Path) -> Vec<String> {
+        std::iter::once(("/", &self.root))
+            .chain(self.mounts.iter().map(|(guest, host)| (guest.as_str(), host)))
+            .filter_map(|(guest, host)| {
+                let rest = resolved.strip_prefix(fs::canonicalize(host).ok()?).ok()?;
+                Some(normalize(guest, &rest.to_string_lossy()))
+            })
+            .collect()
+    }
+}
+
+// path is prefix or somewhere below it
+fn within(path: &str, prefix: &str) -> bool {
+    prefix == "/" || path == prefix || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+}
+
+impl Vfs for HostFs {
+    fn open(&mut self, path: &str, options: &OpenOptions) -> io::Result<Box<dyn File>> {
+        let host_path = self.host_path(path)?;
+        if host_path.is_dir() {
+            if options.write {
+                return Err(io::Error::from_raw_os_error(EISDIR as i32));
+            }
+            let inode = host_metadata(&fs::metadata(&host_path)?).inode;
+            let mut entries = vec![
+                DirEntry { name: ".".to_string(), file_type: FileType::Directory, inode },
+                DirEntry { name: "..".to_string(), file_type: FileType::Directory, inode: self.metadata(parent(path)).map_or(inode, |m| m.inode) }
+            ];
+            for entry in fs::read_dir(&host_path)? {
+                let entry = entry?;
+                // what cannot be opened is not listed either
+                if !self.permitted(&normalize(path, &entry.file_name().to_string_lossy())) {
+                    continue;
+                }
+                let metadata = host_metadata(&entry.metadata()?);
+                entries.push(DirEntry { name: entry.file_name().to_string_lossy().into_owned(), file_type: metadata.file_type, inode: metadata.inode });
+            }
+            return Ok(Box::new(MemoryDirectory { entries, inode }));
+        }
+
+        let file = fs::OpenOptions::new()
+            .read(options.read)
+            .write(options.write)
+            .append(options.append)
+            .truncate(options.truncate && options.write)
+            .create(options.create && !options.exclusive)
+            .create_new(options.create && options.exclusive)
+            .open(host_path)?;
+        Ok(Box::new(HostFile(file)))
+    }
+
+    fn metadata(&mut self, path: &str) -> io::Result<Metadata> {
+        fs::metadata(self.host_path(path)?).map(|m| host_metadata(&m))
+    }
+}
+
+fn host_metadata(metadata: &fs::Metadata) -> Metadata {
+    #[cfg(unix)]
+    let (mode, inode) = {
+        use std::os::unix::fs::MetadataExt;
+        (metadata.mode() & 0o7777, metadata.ino())
+    };
+    #[cfg(not(unix))]
+    let (mode, inode) = (if metadata.is_dir() { 0o755 } else { 0o644 }, 0);
+
+    Metadata {
+        file_type: if metadata.is_dir() { FileType::Directory } else { FileType::File },
+        size: metadata.len(),
+        mode,
+        inode
+    }
+}
+
+struct HostFile(fs::File);
+
+impl File for HostFile {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buffer)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.write(buffer)
+    }
+
+    fn metadata(&self) -> io::Result<Metadata> {
+        self.0.metadata().map(|m| host_metadata(&m))
+    }
+
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.0.seek(position)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalizing_never_leaves_the_root() {
+        assert_eq!("/etc/passwd", normalize("/home", "../../etc/./passwd"));
+        assert_eq!("/home/guest/data", normalize("/home/guest", "data/"));
+        assert_eq!("/", normalize("/", ".."));
+    }
+
+    #[test]
+    fn memory_files_can_be_written_and_listed() {
+        let mut vfs = MemoryFs::new();
+        vfs.add_file("/etc/motd", b"hello");
+
+        let read = OpenOptions { read: true, ..OpenOptions::default() };
+        let mut buffer = [0u8; 8];
+        let mut motd = vfs.open("/etc/motd", &read).unwrap();
+        assert_eq!(5, motd.read(&mut buffer).unwrap());
+        assert_eq!(b"hello", &buffer[..5]);
+
+        let create = OpenOptions { write: true, create: true, ..OpenOptions::default() };
+        vfs.open("/etc/out", &create).unwrap().write(b"written").unwrap();
+        assert_eq!(Some(b"written".to_vec()), vfs.contents("/etc/out"));
+        assert_eq!(Some(EEXIST), vfs.open("/etc/out", &OpenOptions { exclusive: true, ..create }).err().and_then(|e| e.raw_os_error()).map(|e| e as i64));
+        assert_eq!(Some(ENOENT), vfs.open("/missing/file", &create).err().and_then(|e| e.raw_os_error()).map(|e| e as i64));
+
+        let names: Vec<String> = vfs.open("/etc", &read).unwrap().read_dir().unwrap().into_iter().map(|e| e.name).collect();
+        assert_eq!(vec![".", "..", "motd", "out"], names);
+    }
+
+    #[test]
+    fn host_files_are_confined_to_the_root() {
+        let root = std::env::temp_dir().join(format!("user-mode-riscv-vfs-{}", std::process::id()));
+        fs::create_dir_all(root.join("inner")).unwrap();
+        fs::write(root.join("inner/file"), b"host").unwrap();
+
+        let mut vfs = HostFs::new(root.join("inner"));
+        let path = normalize("/", "../../file");
+        let mut buffer = [0u8; 4];
+        assert_eq!(4, vfs.open(&path, &OpenOptions { read: true, ..OpenOptions::default() }).unwrap().read(&mut buffer).unwrap());
+        assert_eq!(b"host", &buffer);
+        assert_eq!(FileType::Directory, vfs.metadata("/").unwrap().file_type);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn host_paths_can_be_remapped_and_refused() {
+        let root = std::env::temp_dir().join(format!("user-mode-riscv-sandbox-{}", std::process::id()));
+        fs::create_dir_all(root.join("guest/etc")).unwrap();
+        fs::create_dir_all(root.join("guest/tmp")).unwrap();
+        fs::write(root.join("guest/etc/shadow"), b"secret").unwrap();
+        fs::write(root.join("passwd"), b"guest:x:1000").unwrap();
+        fs::write(root.join("outside"), b"host").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("outside"), root.join("guest/tmp/link")).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("escaped"), root.join("guest/tmp/dangling")).unwrap();
+
+        let mut vfs = HostFs::new(root.join("guest")).mount("/etc/passwd", root.join("passwd")).deny("/etc/shadow");
+        let read = OpenOptions { read: true, ..OpenOptions::default() };
+        let mut buffer = [0u8; 5];
+        assert_eq!(5, vfs.open("/etc/passwd", &read).unwrap().read(&mut buffer).unwrap());
+        assert_eq!(b"guest", &buffer);
+        let refused = |result: io::Result<Metadata>| result.err().and_then(|e| e.raw_os_error()).map(|e| e as i64);
+        assert_eq!(Some(EACCES), refused(vfs.metadata("/etc/shadow")));
+        #[cfg(unix)]
+        assert_eq!(Some(EACCES), refused(vfs.metadata("/tmp/link")));
+
+        let names: Vec<String> = vfs.open("/etc", &read).unwrap().read_dir().unwrap().into_iter().map(|e| e.name).collect();
+        assert_eq!(vec![".", ".."], names);
+
+        let mut vfs = vfs.allow("/tmp");
+        assert_eq!(Some(EACCES), refused(vfs.metadata("/etc/passwd")));
+        let create = OpenOptions { write: true, create: true, ..OpenOptions::default() };
+        vfs.open("/tmp/new", &create).unwrap().write(b"ok").unwrap();
+        assert_eq!(b"ok".to_vec(), fs::read(root.join("guest/tmp/new")).unwrap());
+        #[cfg(unix)]
+        {
+            assert_eq!(Some(EACCES as i32), vfs.open("/tmp/dangling", &create).err().and_then(|e| e.raw_os_error()));
+            assert!(!root.join("escaped").exists());
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn links_inside_the_root_cannot_reach_what_is_refused() {
+        let root = std::env::temp_dir().join(format!("user-mode-riscv-links-{}", std::process::id()));
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::create_dir_all(root.join("tmp")).unwrap();
+        fs::write(root.join("etc/shadow"), b"secret").unwrap();
+        fs::write(root.join("etc/motd"), b"hello").unwrap();
+        std::os::unix::fs::symlink(root.join("etc/shadow"), root.join("tmp/shadow")).unwrap();
+        std::os::unix::fs::symlink("../etc/motd", root.join("tmp/motd")).unwrap();
+
+        let refused = |result: io::Result<Metadata>| result.err().and_then(|e| e.raw_os_error()).map(|e| e as i64);
+        let mut vfs = HostFs::new(&root).deny("/etc/shadow");
+        assert_eq!(Some(EACCES), refused(vfs.metadata("/tmp/shadow")));
+        assert!(vfs.metadata("/tmp/motd").is_ok());
+
+        let mut vfs = HostFs::new(&root).allow("/tmp");
+        assert_eq!(Some(EACCES), refused(vfs.metadata("/tmp/motd")));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,53 @@
+use crate::cpu::{MemoryAccess, Trap, WrittenRegister};
+
+mod console;
+mod filter;
+mod json;
+mod ring;
+mod spike;
+
+pub use console::ConsoleTracer;
+pub use filter::TraceFilter;
+pub use json::JsonTracer;
+pub use ring::{ring_buffer, RingConsumer, RingProducer};
+pub use spike::SpikeTracer;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub pc: usize,
+    // always the 32 bit form, compressed instructions are expanded before they get here
+    pub word: u32,
+    // as it was encoded, the low half only for a compressed instruction
+    pub encoding: u32,
+    pub name: &'static str
+}
+
+// Receives every instruction the hart retires. Tracers run inline with execution so anything
+// expensive should be handed off, see ring_buffer.
+//
+// The registers an instruction wrote and the loads and stores it made follow its retire, in that
+// order, but only reach tracers that ask for them with wants_effects as working them out slows
+// every instruction down. A trapping instruction does not retire, the trap is all that is heard.
+pub trait Tracer: Send {
+    fn on_retire(&mut self, event: &TraceEvent);
+
+    fn wants_effects(&self) -> bool {
+        false
+    }
+
+    fn on_register_write(&mut self, _pc: usize, _register: &WrittenRegister) {}
+
+    fn on_memory_access(&mut self, _pc: usize, _access: &MemoryAccess) {}
+
+    fn on_trap(&mut self, _pc: usize, _trap: &Trap) {}
+}
+
+// A tracer is a sink that belongs to one hart, so a cloned or forked Cpu starts out untraced.
+#[derive(Default)]
+pub(crate) struct TracerSlot(pub(crate) Option<Box<dyn Tracer>>);
+
+impl Clone for TracerSlot {
+    fn clone(&self) -> Self {
+        TracerSlot(None)
+    }
+}
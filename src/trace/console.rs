@@ -0,0 +1,98 @@
+use crate::cpu::{AccessKind, MemoryAccess, Trap, WrittenRegister};
+use crate::trace::{TraceEvent, Tracer};
+use std::io;
+use std::io::Write;
+
+// Prints each instruction as it retires followed by a line for every register it wrote and every
+// load and store it made, for following a guest by eye. Write errors are ignored rather than
+// getting in the way of the guest.
+pub struct ConsoleTracer {
+    out: Box<dyn Write + Send>
+}
+
+impl ConsoleTracer {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        ConsoleTracer { out }
+    }
+
+    pub fn stdout() -> Self {
+        ConsoleTracer::new(Box::new(io::stdout()))
+    }
+}
+
+impl Tracer for ConsoleTracer {
+    fn on_retire(&mut self, event: &TraceEvent) {
+        let _ = writeln!(self.out, "pc = {:#x} - {} ({:08x})", event.pc, event.name, event.word);
+    }
+
+    fn wants_effects(&self) -> bool {
+        true
+    }
+
+    fn on_register_write(&mut self, _pc: usize, register: &WrittenRegister) {
+        let _ = match register {
+            WrittenRegister::X { index, value } => writeln!(self.out, "    x{} = {:#x}", index, value),
+            WrittenRegister::F { index, value } => writeln!(self.out, "    f{} = {} ({:#x})", index, value, value.to_bits())
+        };
+    }
+
+    fn on_memory_access(&mut self, _pc: usize, access: &MemoryAccess) {
+        let kind = match access.kind {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write"
+        };
+        let _ = writeln!(self.out, "    {} {:#x} = {:#x} ({} bytes)", kind, access.address, access.value, access.size);
+    }
+
+    // the guest may not get any further, so whatever is buffered is written out now
+    fn on_trap(&mut self, pc: usize, trap: &Trap) {
+        let _ = writeln!(self.out, "pc = {:#x} - trap {:?}", pc, trap);
+        let _ = self.out.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::cpu::test_cpu::program;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn instructions_are_printed_with_what_they_did() {
+        // addi a0, zero, 5; sw a0, 0x80(zero); then an illegal instruction
+        let words = [0x00500513u32, 0x08a02023, 0];
+        let mut memory = [program(&words), vec![0; 0x80]].concat();
+        let out = Shared::default();
+        let mut cpu = Cpu::new();
+        cpu.set_tracer(Some(Box::new(ConsoleTracer::new(Box::new(out.clone())))));
+
+        cpu.tick(&mut memory).unwrap();
+        cpu.tick(&mut memory).unwrap();
+        assert!(cpu.tick(&mut memory).is_err());
+        let printed = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = printed.lines().collect();
+        assert_eq!(vec![
+            "pc = 0x0 - ADDI (00500513)",
+            "    x10 = 0x5",
+            "pc = 0x4 - SW (08a02023)",
+            "    write 0x80 = 0x5 (4 bytes)"
+        ], lines[..4]);
+        assert!(lines[4].starts_with("pc = 0x8 - trap Trap { trap_type: IllegalInstruction"));
+        assert_eq!(5, lines.len());
+    }
+}
@@ -0,0 +1,111 @@
+use crate::loader::elf::ElfImage;
+use std::ops::Range;
+
+// Decides which pcs reach the tracer. With no include rules everything is traced, otherwise only
+// pcs inside an included range are. Exclude rules always win, so a libc range can be carved out
+// of a larger included region. Rules are merged into sorted disjoint ranges as they are added,
+// which keeps the per instruction check down to a couple of binary searches.
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    include: Vec<Range<usize>>,
+    exclude: Vec<Range<usize>>
+}
+
+impl TraceFilter {
+    pub fn new() -> Self {
+        TraceFilter::default()
+    }
+
+    pub fn include(mut self, range: Range<usize>) -> Self {
+        add(&mut self.include, range);
+        self
+    }
+
+    pub fn exclude(mut self, range: Range<usize>) -> Self {
+        add(&mut self.exclude, range);
+        self
+    }
+
+    // every symbol in the image whose name passes the predicate, e.g. |name| name == "main"
+    pub fn include_symbols<F: Fn(&str) -> bool>(mut self, image: &ElfImage, predicate: F) -> Self {
+        for symbol in image.symbols.iter().filter(|s| predicate(&s.name)) {
+            add(&mut self.include, image.symbol_extent(symbol));
+        }
+        self
+    }
+
+    pub fn exclude_symbols<F: Fn(&str) -> bool>(mut self, image: &ElfImage, predicate: F) -> Self {
+        for symbol in image.symbols.iter().filter(|s| predicate(&s.name)) {
+            add(&mut self.exclude, image.symbol_extent(symbol));
+        }
+        self
+    }
+
+    pub fn matches(&self, pc: usize) -> bool {
+        if contains(&self.exclude, pc) {
+            return false;
+        }
+        self.include.is_empty() || contains(&self.include, pc)
+    }
+}
+
+fn add(ranges: &mut Vec<Range<usize>>, range: Range<usize>) {
+    if range.is_empty() {
+        return;
+    }
+
+    ranges.push(range);
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges.drain(..) {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range)
+        }
+    }
+    *ranges = merged;
+}
+
+fn contains(ranges: &[Range<usize>], pc: usize) -> bool {
+    let index = ranges.partition_point(|r| r.end <= pc);
+    ranges.get(index).is_some_and(|r| r.start <= pc)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::cpu::test_cpu::program;
+    use crate::trace::{ring_buffer, TraceEvent};
+
+    #[test]
+    fn excludes_win_over_includes() {
+        let filter = TraceFilter::new()
+            .include(0x100..0x200)
+            .include(0x180..0x300)
+            .exclude(0x140..0x150);
+
+        assert!(!filter.matches(0xff));
+        assert!(filter.matches(0x100));
+        assert!(!filter.matches(0x148));
+        assert!(filter.matches(0x2ff));
+        assert!(!filter.matches(0x300));
+        assert!(TraceFilter::new().exclude(0..4).matches(4));
+    }
+
+    #[test]
+    fn only_matching_pcs_reach_the_tracer() {
+        // addi a0, a0, 1 four times
+        let mut memory = program(&[0x00150513u32; 4]);
+        let (producer, mut consumer) = ring_buffer(16);
+        let mut cpu = Cpu::new();
+        cpu.set_tracer(Some(Box::new(producer)));
+        cpu.set_trace_filter(Some(TraceFilter::new().include(4..12)));
+
+        for _ in 0..4 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        assert_eq!(vec![4, 8], consumer.drain().map(|e: TraceEvent| e.pc).collect::<Vec<_>>());
+        assert_eq!(4, cpu.get_register(crate::cpu::Register::A0));
+    }
+}
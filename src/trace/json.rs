@@ -0,0 +1,155 @@
+use crate::cpu::{AccessKind, MemoryAccess, Trap, WrittenRegister};
+use crate::disasm::Disassembler;
+use crate::trace::{TraceEvent, Tracer};
+use std::fmt::Write as _;
+use std::io::Write;
+
+// Writes one JSON object per line for every instruction retired and every trap, for analysis
+// scripts and for diffing two runs line by line. An instruction looks like
+//
+//   {"pc":4,"word":"0x08a02023","mnemonic":"sw","operands":["a0","128(zero)"],"registers":{"x10":"0x5"},"memory":[{"kind":"write","address":128,"size":4,"value":"0x5"}]}
+//
+// and a trap like {"pc":8,"trap":"IllegalInstruction","value":"0x0"}. Register and memory values
+// are hex strings as they do not all fit the doubles most JSON readers use for numbers, float
+// registers are given as their bits. Operands are the encoded ones, pseudo-instructions are not
+// folded in.
+pub struct JsonTracer {
+    out: Box<dyn Write + Send>,
+    disassembler: Disassembler,
+    // the instruction whose registers and accesses are still coming in
+    pending: Option<Pending>
+}
+
+struct Pending {
+    head: String,
+    registers: Vec<String>,
+    memory: Vec<String>
+}
+
+impl JsonTracer {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        JsonTracer { out, disassembler: Disassembler::new().raw(true), pending: None }
+    }
+
+    fn finish(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            let _ = writeln!(self.out, "{},\"registers\":{{{}}},\"memory\":[{}]}}", pending.head, pending.registers.join(","), pending.memory.join(","));
+        }
+    }
+}
+
+impl Tracer for JsonTracer {
+    fn on_retire(&mut self, event: &TraceEvent) {
+        self.finish();
+        let mut head = format!("{{\"pc\":{},\"word\":\"{:#010x}\"", event.pc, event.word);
+        match self.disassembler.decode(event.word, event.pc) {
+            Some(disassembly) => {
+                let operands: Vec<String> = disassembly.operands.iter().map(|operand| string(&operand.to_string())).collect();
+                let _ = write!(head, ",\"mnemonic\":{},\"operands\":[{}]", string(&disassembly.mnemonic), operands.join(","));
+            },
+            None => {
+                let _ = write!(head, ",\"mnemonic\":{},\"operands\":[]", string(&event.name.to_lowercase()));
+            }
+        }
+        self.pending = Some(Pending { head, registers: Vec::new(), memory: Vec::new() });
+    }
+
+    fn wants_effects(&self) -> bool {
+        true
+    }
+
+    fn on_register_write(&mut self, _pc: usize, register: &WrittenRegister) {
+        if let Some(pending) = &mut self.pending {
+            pending.registers.push(match register {
+                WrittenRegister::X { index, value } => format!("\"x{}\":\"{:#x}\"", index, value),
+                WrittenRegister::F { index, value } => format!("\"f{}\":\"{:#x}\"", index, value.to_bits())
+            });
+        }
+    }
+
+    fn on_memory_access(&mut self, _pc: usize, access: &MemoryAccess) {
+        let kind = match access.kind {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write"
+        };
+        if let Some(pending) = &mut self.pending {
+            pending.memory.push(format!("{{\"kind\":\"{}\",\"address\":{},\"size\":{},\"value\":\"{:#x}\"}}", kind, access.address, access.size, access.value));
+        }
+    }
+
+    fn on_trap(&mut self, pc: usize, trap: &Trap) {
+        self.finish();
+        let _ = writeln!(self.out, "{{\"pc\":{},\"trap\":{},\"value\":\"{:#x}\"}}", pc, string(&format!("{:?}", trap.trap_type)), trap.value);
+        let _ = self.out.flush();
+    }
+}
+
+// the last instruction only gets written once nothing more can come in for it
+impl Drop for JsonTracer {
+    fn drop(&mut self) {
+        self.finish();
+        let _ = self.out.flush();
+    }
+}
+
+fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            },
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::cpu::test_cpu::program;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn every_instruction_is_a_line_of_json() {
+        // addi a0, zero, 5; sw a0, 0x80(zero); lw a1, 0x80(zero)
+        let words = [0x00500513u32, 0x08a02023, 0x08002583];
+        let mut memory = [program(&words), vec![0; 0x80]].concat();
+        let out = Shared::default();
+        let mut cpu = Cpu::new();
+        cpu.set_tracer(Some(Box::new(JsonTracer::new(Box::new(out.clone())))));
+
+        for _ in 0..3 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        cpu.set_tracer(None);
+        let printed = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(vec![
+            r#"{"pc":0,"word":"0x00500513","mnemonic":"addi","operands":["a0","zero","5"],"registers":{"x10":"0x5"},"memory":[]}"#,
+            r#"{"pc":4,"word":"0x08a02023","mnemonic":"sw","operands":["a0","128(zero)"],"registers":{},"memory":[{"kind":"write","address":128,"size":4,"value":"0x5"}]}"#,
+            r#"{"pc":8,"word":"0x08002583","mnemonic":"lw","operands":["a1","128(zero)"],"registers":{"x11":"0x5"},"memory":[{"kind":"read","address":128,"size":4,"value":"0x5"}]}"#
+        ], printed.lines().collect::<Vec<_>>());
+        assert!(serde_json::from_str::<serde_json::Value>(printed.lines().next().unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::trace::{TraceEvent, Tracer};
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+// head and tail only ever increase, the slot is the index masked by the power of two capacity
+struct Ring {
+    slots: Box<[UnsafeCell<TraceEvent>]>,
+    mask: usize,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicU64,
+    closed: AtomicBool
+}
+
+// the producer only writes slots between tail and head + capacity, the consumer only reads slots
+// between tail and head, and the atomics hand each slot over between the two
+unsafe impl Sync for Ring {}
+
+pub struct RingProducer {
+    ring: Arc<Ring>
+}
+
+pub struct RingConsumer {
+    ring: Arc<Ring>
+}
+
+// Single producer, single consumer trace buffer. When the consumer falls behind new events are
+// dropped and counted rather than stalling the hart.
+pub fn ring_buffer(capacity: usize) -> (RingProducer, RingConsumer) {
+    let capacity = capacity.max(2).next_power_of_two();
+    let ring = Arc::new(Ring {
+        slots: (0..capacity).map(|_| UnsafeCell::new(TraceEvent::default())).collect(),
+        mask: capacity - 1,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        dropped: AtomicU64::new(0),
+        closed: AtomicBool::new(false)
+    });
+
+    (RingProducer { ring: ring.clone() }, RingConsumer { ring })
+}
+
+impl RingProducer {
+    // returns false if the buffer was full and the event was dropped
+    pub fn push(&mut self, event: TraceEvent) -> bool {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        if head.wrapping_sub(tail) > self.ring.mask {
+            self.ring.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        unsafe { *self.ring.slots[head & self.ring.mask].get() = event; }
+        self.ring.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.ring.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for RingProducer {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+    }
+}
+
+impl Tracer for RingProducer {
+    fn on_retire(&mut self, event: &TraceEvent) {
+        self.push(*event);
+    }
+}
+
+impl RingConsumer {
+    pub fn pop(&mut self) -> Option<TraceEvent> {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        if tail == self.ring.head.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let event = unsafe { *self.ring.slots[tail & self.ring.mask].get() };
+        self.ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(event)
+    }
+
+    // everything currently buffered, without waiting for more
+    pub fn drain(&mut self) -> impl Iterator<Item = TraceEvent> + '_ {
+        std::iter::from_fn(move || self.pop())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.head.load(Ordering::Acquire).wrapping_sub(self.ring.tail.load(Ordering::Relaxed))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.ring.dropped.load(Ordering::Relaxed)
+    }
+
+    // true once the producer has been dropped, anything still buffered can be drained
+    pub fn is_disconnected(&self) -> bool {
+        self.ring.closed.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+    use std::thread;
+
+    #[test]
+    fn full_buffer_drops_and_counts() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        for pc in 0..6 {
+            producer.push(TraceEvent { pc, ..TraceEvent::default() });
+        }
+
+        assert_eq!(2, consumer.dropped());
+        assert_eq!(vec![0, 1, 2, 3], consumer.drain().map(|e| e.pc).collect::<Vec<_>>());
+        assert!(producer.push(TraceEvent::default()));
+    }
+
+    #[test]
+    fn consumer_thread_sees_events_in_order() {
+        let (mut producer, mut consumer) = ring_buffer(64);
+        let total = 100_000;
+
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            loop {
+                let disconnected = consumer.is_disconnected();
+                received.extend(consumer.drain().map(|e| e.pc));
+                if disconnected {
+                    return (received, consumer.dropped());
+                }
+                thread::yield_now();
+            }
+        });
+
+        for pc in 0..total {
+            producer.push(TraceEvent { pc, ..TraceEvent::default() });
+        }
+        drop(producer);
+
+        let (received, dropped) = reader.join().unwrap();
+        assert_eq!(total as u64, received.len() as u64 + dropped);
+        assert!(received.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn cpu_feeds_the_ring() {
+        // addi a0, a0, 1 twice
+        let mut memory = vec![0x13, 0x05, 0x15, 0x00, 0x13, 0x05, 0x15, 0x00];
+        let (producer, mut consumer) = ring_buffer(16);
+        let mut cpu = Cpu::new();
+        cpu.set_tracer(Some(Box::new(producer)));
+
+        cpu.tick(&mut memory).unwrap();
+        cpu.tick(&mut memory).unwrap();
+        let events: Vec<TraceEvent> = consumer.drain().collect();
+        assert_eq!(vec![0, 4], events.iter().map(|e| e.pc).collect::<Vec<_>>());
+        assert_eq!("ADDI", events[0].name);
+        assert!(cpu.clone().take_tracer().is_none());
+    }
+}
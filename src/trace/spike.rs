@@ -0,0 +1,207 @@
+use crate::cpu::{AccessKind, Cpu, MemoryAccess, Trap, WrittenRegister, Xlen};
+use crate::trace::{TraceEvent, Tracer};
+use std::io::Write;
+
+// Writes the log spike gives with --log-commits, so the two can be run on the same program and
+// diffed line by line:
+//
+//   core   0: 0 0x0000000000010078 (0x00a7a023) mem 0x0000000000011000 0x00000005
+//
+// Spike shows the destination of every instruction that has one, even when the value did not
+// change, so the tracer keeps its own copy of the registers to fill those in. It has to start
+// from the hart's registers for that, see starting_from, and registers set from outside the guest
+// while it is being traced go unseen. Writes to CSRs, fflags among them, are not shown as the hart
+// does not report them, and neither are traps, which spike only shows with -l.
+pub struct SpikeTracer {
+    out: Box<dyn Write + Send>,
+    privilege: u8,
+    xlen: Xlen,
+    x: [u64; 32],
+    f: [u64; 32],
+    // the instruction whose registers and accesses are still coming in
+    pending: Option<Pending>
+}
+
+struct Pending {
+    pc: usize,
+    word: u32,
+    encoding: u32,
+    written: Vec<(char, usize)>,
+    accesses: Vec<MemoryAccess>
+}
+
+impl SpikeTracer {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        SpikeTracer { out, privilege: 0, xlen: Xlen::Bit64, x: [0; 32], f: [0; 32], pending: None }
+    }
+
+    // the registers and xlen of cpu as they are before it is traced
+    pub fn starting_from(mut self, cpu: &Cpu) -> Self {
+        self.xlen = cpu.get_xlen();
+        self.x = cpu.registers().map(|x| x as u64);
+        self.f = cpu.fp_registers().map(f64::to_bits);
+        self
+    }
+
+    // the privilege level shown for every instruction, 0 for user mode and 3 to compare against
+    // bare metal programs spike runs in machine mode
+    pub fn privilege(mut self, privilege: u8) -> Self {
+        self.privilege = privilege;
+        self
+    }
+
+    fn finish(&mut self) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        let xlen = match self.xlen {
+            Xlen::Bit32 => 32,
+            Xlen::Bit64 => 64
+        };
+        let mut line = format!("core   0: {} {} ({})", self.privilege, hex(xlen, pending.pc as u64), match pending.encoding & 3 {
+            3 => hex(32, pending.encoding as u64),
+            _ => hex(16, pending.encoding as u64)
+        });
+
+        let destination = destination(pending.word).filter(|register| *register != ('x', 0));
+        for (file, index) in destination.into_iter().chain(pending.written.into_iter().filter(|register| Some(*register) != destination)) {
+            match file {
+                'x' => line += &format!(" x{:<2} {}", index, hex(xlen, self.x[index])),
+                _ => line += &format!(" f{:<2} {}", index, hex(64, self.f[index]))
+            }
+        }
+        let loads = pending.accesses.iter().filter(|access| access.kind == AccessKind::Read);
+        let stores = pending.accesses.iter().filter(|access| access.kind == AccessKind::Write);
+        for load in loads {
+            line += &format!(" mem {}", hex(xlen, load.address as u64));
+        }
+        for store in stores {
+            line += &format!(" mem {} {}", hex(xlen, store.address as u64), hex(store.size * 8, store.value));
+        }
+        let _ = writeln!(self.out, "{}", line);
+    }
+}
+
+impl Tracer for SpikeTracer {
+    fn on_retire(&mut self, event: &TraceEvent) {
+        self.finish();
+        self.pending = Some(Pending { pc: event.pc, word: event.word, encoding: event.encoding, written: Vec::new(), accesses: Vec::new() });
+    }
+
+    fn wants_effects(&self) -> bool {
+        true
+    }
+
+    fn on_register_write(&mut self, _pc: usize, register: &WrittenRegister) {
+        let written = match *register {
+            WrittenRegister::X { index, value } => {
+                self.x[index] = value as u64;
+                ('x', index)
+            },
+            WrittenRegister::F { index, value } => {
+                self.f[index] = value.to_bits();
+                ('f', index)
+            }
+        };
+        if let Some(pending) = &mut self.pending {
+            pending.written.push(written);
+        }
+    }
+
+    fn on_memory_access(&mut self, _pc: usize, access: &MemoryAccess) {
+        if let Some(pending) = &mut self.pending {
+            pending.accesses.push(*access);
+        }
+    }
+
+    fn on_trap(&mut self, _pc: usize, _trap: &Trap) {
+        self.finish();
+        let _ = self.out.flush();
+    }
+}
+
+// the last instruction only gets written once nothing more can come in for it
+impl Drop for SpikeTracer {
+    fn drop(&mut self) {
+        self.finish();
+        let _ = self.out.flush();
+    }
+}
+
+fn hex(bits: usize, value: u64) -> String {
+    let value = match bits {
+        64 => value,
+        _ => value & ((1 << bits) - 1)
+    };
+    format!("0x{:01$x}", value, bits / 4)
+}
+
+// the register an instruction writes, whether or not that changes it
+fn destination(word: u32) -> Option<(char, usize)> {
+    let rd = ((word >> 7) & 0x1f) as usize;
+    match word & 0x7f {
+        // lui, auipc, jal, jalr, loads, op-imm, op, their w forms and atomics
+        0b0110111 | 0b0010111 | 0b1101111 | 0b1100111 | 0b0000011 | 0b0010011 | 0b0011011 | 0b0110011 | 0b0111011 | 0b0101111 => Some(('x', rd)),
+        // csr instructions, ecall and ebreak have no destination
+        0b1110011 if (word >> 12) & 7 != 0 => Some(('x', rd)),
+        // vector loads, the ones with a width that is not a float one, write no x or f register
+        0b0000111 if matches!((word >> 12) & 7, 0 | 5..=7) => None,
+        // floating point loads and fused multiply adds
+        0b0000111 | 0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => Some(('f', rd)),
+        // the vsetvls and vmv.x.s are the vector instructions with an x destination
+        0b1010111 if (word >> 12) & 7 == 0b111 || ((word >> 12) & 7 == 0b010 && word >> 26 == 0b010000) => Some(('x', rd)),
+        // and vfmv.f.s the one with an f destination
+        0b1010111 if (word >> 12) & 7 == 0b001 && word >> 26 == 0b010000 => Some(('f', rd)),
+        // comparisons, fclass, fmv.x and conversions to integers land in x
+        0b1010011 => match word >> 27 {
+            0b10100 | 0b11100 | 0b11000 => Some(('x', rd)),
+            _ => Some(('f', rd))
+        },
+        _ => None
+    }
+}
+
+#[cfg(all(test, feature = "c"))]
+mod test {
+    use super::*;
+    use crate::cpu::Register;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn commits_are_logged_the_way_spike_logs_them() {
+        // addi a0, zero, 5; sw a0, 0x80(zero); c.li a1, 3; addi a2, a2, 0
+        let mut memory: Vec<u8> = [0x00500513u32.to_le_bytes().to_vec(), 0x08a02023u32.to_le_bytes().to_vec(), 0x458du16.to_le_bytes().to_vec(),
+            0x00060613u32.to_le_bytes().to_vec(), vec![0; 0x80]].concat();
+        let out = Shared::default();
+        let mut cpu = Cpu::new();
+        cpu.set_register(Register::A2, -2);
+        cpu.set_tracer(Some(Box::new(SpikeTracer::new(Box::new(out.clone())).starting_from(&cpu))));
+
+        for _ in 0..4 {
+            cpu.tick(&mut memory).unwrap();
+        }
+        cpu.set_tracer(None);
+        let printed = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(vec![
+            "core   0: 0 0x0000000000000000 (0x00500513) x10 0x0000000000000005",
+            "core   0: 0 0x0000000000000004 (0x08a02023) mem 0x0000000000000080 0x00000005",
+            "core   0: 0 0x0000000000000008 (0x458d) x11 0x0000000000000003",
+            "core   0: 0 0x000000000000000a (0x00060613) x12 0xfffffffffffffffe"
+        ], printed.lines().collect::<Vec<_>>());
+    }
+}
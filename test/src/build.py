@@ -0,0 +1,228 @@
+#!/usr/bin/env python3
+# Builds the tests under test/src into test/<suite>-p-<name> along with a .dump of each, e.g.
+#   python3 test/src/build.py rv32ui rv32um
+# builds two suites and no arguments builds them all. The sources are preprocessed with cpp and
+# assembled with llvm-mc, then linked here: a test is a .text and a .data section, the code at
+# 0x80000000 and the data on the page after it, and the only relocations llvm-mc leaves are the
+# pc relative ones that la and the loads and stores of a symbol make.
+
+import os
+import struct
+import subprocess
+import sys
+
+SRC = os.path.dirname(os.path.abspath(__file__))
+OUT = os.path.dirname(SRC)
+BASE = 0x80000000
+PAGE = 0x1000
+
+# xlen and the extensions each suite is assembled for
+SUITES = {
+    "rv32ui": (32, []),
+    "rv32um": (32, ["+m"]),
+    "rv64uzba": (64, ["+zba"]),
+    "rv64uzbc": (64, ["+zbc"]),
+    "rv64uv": (64, ["+v", "+f", "+d"]),
+}
+
+LLVM_MC = os.environ.get("LLVM_MC", "llvm-mc")
+LLVM_OBJDUMP = os.environ.get("LLVM_OBJDUMP", "llvm-objdump")
+
+R_RISCV_PCREL_HI20 = 23
+R_RISCV_PCREL_LO12_I = 24
+R_RISCV_PCREL_LO12_S = 25
+
+SHT_SYMTAB = 2
+SHT_RELA = 4
+STT_SECTION = 3
+
+
+def run(command, **kwargs):
+    return subprocess.run(command, check=True, stdout=subprocess.PIPE, **kwargs).stdout
+
+
+class Object:
+    def __init__(self, data):
+        self.data = data
+        self.is64 = data[4] == 2
+        if self.is64:
+            shoff, = struct.unpack_from("<Q", data, 0x28)
+            shentsize, shnum, shstrndx = struct.unpack_from("<HHH", data, 0x3a)
+        else:
+            shoff, = struct.unpack_from("<I", data, 0x20)
+            shentsize, shnum, shstrndx = struct.unpack_from("<HHH", data, 0x2e)
+        self.sections = []
+        for i in range(shnum):
+            layout = "<IIQQQQIIQQ" if self.is64 else "<IIIIIIIIII"
+            name, kind, _, _, offset, size, link, info, _, entsize = struct.unpack_from(layout, data, shoff + i * shentsize)
+            self.sections.append({"name": name, "kind": kind, "offset": offset, "size": size, "link": link,
+                                  "info": info, "entsize": entsize})
+        names = self.sections[shstrndx]["offset"]
+        for section in self.sections:
+            section["name"] = self.string(names + section["name"])
+
+    def string(self, at):
+        return self.data[at:self.data.index(b"\0", at)].decode()
+
+    def index(self, name):
+        return next((i for i, s in enumerate(self.sections) if s["name"] == name), None)
+
+    def contents(self, index):
+        section = self.sections[index]
+        return self.data[section["offset"]:section["offset"] + section["size"]]
+
+    # (name, type, section index, value) of every symbol
+    def symbols(self):
+        symtab = next(s for s in self.sections if s["kind"] == SHT_SYMTAB)
+        strtab = self.sections[symtab["link"]]["offset"]
+        for at in range(symtab["offset"], symtab["offset"] + symtab["size"], symtab["entsize"]):
+            if self.is64:
+                name, info, _, index, value, _ = struct.unpack_from("<IBBHQQ", self.data, at)
+            else:
+                name, value, _, info, _, index = struct.unpack_from("<IIIBBH", self.data, at)
+            yield self.string(strtab + name), info & 0xf, index, value
+
+    # (offset, symbol, type, addend) of every relocation applied to the section at index
+    def relocations(self, index):
+        for section in self.sections:
+            if section["kind"] == SHT_RELA and section["info"] == index:
+                for at in range(section["offset"], section["offset"] + section["size"], section["entsize"]):
+                    if self.is64:
+                        offset, info, addend = struct.unpack_from("<QQq", self.data, at)
+                        yield offset, info >> 32, info & 0xffffffff, addend
+                    else:
+                        offset, info, addend = struct.unpack_from("<IIi", self.data, at)
+                        yield offset, info >> 8, info & 0xff, addend
+
+
+# Returns the linked code and data, where the data starts and the address of every label.
+def link(obj):
+    text_index, data_index = obj.index(".text"), obj.index(".data")
+    text = bytearray(obj.contents(text_index))
+    data = bytearray(obj.contents(data_index) if data_index is not None else b"")
+    data_base = BASE + (len(text) + PAGE - 1) // PAGE * PAGE
+    bases = {text_index: BASE}
+    if data_index is not None:
+        bases[data_index] = data_base
+
+    symbols = list(obj.symbols())
+
+    def address(symbol):
+        _, _, index, value = symbols[symbol]
+        return bases[index] + value
+
+    for index, contents in ((text_index, text), (data_index, data)):
+        if index is None:
+            continue
+        relocations = list(obj.relocations(index))
+        # the low half of a pc relative pair names the auipc that has the high half
+        high = {}
+        for offset, symbol, kind, addend in relocations:
+            if kind == R_RISCV_PCREL_HI20:
+                high[bases[index] + offset] = address(symbol) + addend - (bases[index] + offset)
+        for offset, symbol, kind, addend in relocations:
+            word, = struct.unpack_from("<I", contents, offset)
+            if kind == R_RISCV_PCREL_HI20:
+                value = high[bases[index] + offset]
+                word = (word & 0xfff) | ((value + 0x800) & 0xfffff000)
+            elif kind == R_RISCV_PCREL_LO12_I:
+                value = high[address(symbol)] & 0xfff
+                word = (word & 0xfffff) | (value << 20)
+            elif kind == R_RISCV_PCREL_LO12_S:
+                value = high[address(symbol)] & 0xfff
+                word = (word & 0x1fff07f) | ((value >> 5) << 25) | ((value & 0x1f) << 7)
+            else:
+                raise SystemExit("relocation of type %d is not handled" % kind)
+            struct.pack_into("<I", contents, offset, word & 0xffffffff)
+
+    # the local labels llvm-mc makes up for 1: and the like start with .L
+    labels = [(name, bases[index] + value) for name, kind, index, value in symbols
+              if index in bases and name and not name.startswith(".L") and kind != STT_SECTION]
+    return bytes(text), bytes(data), data_base, labels
+
+
+def executable(xlen, text, data, data_base, labels):
+    is64 = xlen == 64
+    ehsize, phentsize, shentsize = (64, 56, 64) if is64 else (52, 32, 40)
+
+    strtab = b"\0"
+    symtab = bytes(24 if is64 else 16)
+    for name, value in labels:
+        # all global, so that none have to be sorted ahead of the others
+        section = 1 if value < data_base else 2
+        if is64:
+            symtab += struct.pack("<IBBHQQ", len(strtab), 0x10, 0, section, value, 0)
+        else:
+            symtab += struct.pack("<IIIBBH", len(strtab), value, 0, 0x10, 0, section)
+        strtab += name.encode() + b"\0"
+    shstrtab = b"\0.text\0.data\0.symtab\0.strtab\0.shstrtab\0"
+
+    # the file is laid out as memory is, from its first page on
+    image_size = data_base - BASE + len(data)
+    symtab_offset = PAGE + image_size
+    symtab_offset += -symtab_offset % 8
+    strtab_offset = symtab_offset + len(symtab)
+    shstrtab_offset = strtab_offset + len(strtab)
+    shoff = shstrtab_offset + len(shstrtab)
+    shoff += -shoff % 8
+
+    ident = b"\x7fELF" + bytes([2 if is64 else 1, 1, 1, 0]) + bytes(8)
+    # ET_EXEC, EM_RISCV, with the one segment readable, writable and executable
+    if is64:
+        header = ident + struct.pack("<HHIQQQIHHHHHH", 2, 243, 1, BASE, ehsize, shoff, 0, ehsize, phentsize, 1, shentsize, 6, 5)
+        program = struct.pack("<IIQQQQQQ", 1, 7, PAGE, BASE, BASE, image_size, image_size, PAGE)
+    else:
+        header = ident + struct.pack("<HHIIIIIHHHHHH", 2, 243, 1, BASE, ehsize, shoff, 0, ehsize, phentsize, 1, shentsize, 6, 5)
+        program = struct.pack("<IIIIIIII", 1, PAGE, BASE, BASE, image_size, image_size, 7, PAGE)
+
+    def section(name, kind, flags, address, offset, size, link, info, align, entsize):
+        layout = "<IIQQQQIIQQ" if is64 else "<IIIIIIIIII"
+        return struct.pack(layout, name, kind, flags, address, offset, size, link, info, align, entsize)
+
+    table = section(0, 0, 0, 0, 0, 0, 0, 0, 0, 0)
+    table += section(1, 1, 6, BASE, PAGE, len(text), 0, 0, 64, 0)
+    table += section(7, 1, 3, data_base, PAGE + data_base - BASE, len(data), 0, 0, 64, 0)
+    table += section(13, 2, 0, 0, symtab_offset, len(symtab), 4, 1, 8, 24 if is64 else 16)
+    table += section(21, 3, 0, 0, strtab_offset, len(strtab), 0, 0, 1, 0)
+    table += section(29, 3, 0, 0, shstrtab_offset, len(shstrtab), 0, 0, 1, 0)
+
+    image = bytearray(header + program)
+    image += bytes(PAGE - len(image))
+    image += text
+    image += bytes(PAGE + data_base - BASE - len(image))
+    image += data
+    image += bytes(symtab_offset - len(image))
+    image += symtab + strtab + shstrtab
+    image += bytes(shoff - len(image))
+    image += table
+    return bytes(image)
+
+
+def build(suite, name):
+    xlen, extensions = SUITES[suite]
+    source = os.path.join(SRC, suite, name + ".S")
+    output = os.path.join(OUT, "%s-p-%s" % (suite, name))
+
+    assembly = run(["cpp", "-x", "assembler-with-cpp", "-P", "-nostdinc", "-undef", "-D__riscv_xlen=%d" % xlen,
+                    "-I", os.path.join(SRC, "env"), "-I", os.path.join(SRC, suite), source])
+    # without relaxation llvm-mc resolves the references within a section itself
+    obj = run([LLVM_MC, "-triple=riscv%d" % xlen, "-mattr=%s" % ",".join(extensions + ["-relax"]), "-filetype=obj", "-o", "-"],
+              input=assembly)
+    with open(output, "wb") as f:
+        f.write(executable(xlen, *link(Object(obj))))
+
+    mattr = ["--mattr=%s" % ",".join(extensions)] if extensions else []
+    dump = run([LLVM_OBJDUMP, "-D"] + mattr + [os.path.basename(output)], cwd=OUT)
+    with open(output + ".dump", "wb") as f:
+        f.write(dump)
+
+
+def main():
+    for suite in sys.argv[1:] or sorted(SUITES):
+        for source in sorted(os.listdir(os.path.join(SRC, suite))):
+            if source.endswith(".S"):
+                build(suite, source[:-2])
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,220 @@
+// Test macros for version 1.0 of the vector extension, which riscv-tests has none of yet. They
+// go with the scalar ones: a case leaves its number in TESTNUM and branches to fail. The operands
+// are whole registers loaded from the data, with vl set to VLMAX, so a case covers every element
+// of a register; the tests are written for a VLEN of 128, 16 bytes of data to a register.
+
+#ifndef __TEST_MACROS_VECTOR_H
+#define __TEST_MACROS_VECTOR_H
+
+#-----------------------------------------------------------------------
+# Helper macros
+#-----------------------------------------------------------------------
+
+// the result register groups are v24 on, stored here to be checked
+#define TEST_VECTOR_DATA \
+        .align 4; \
+vresult: .zero 64;
+
+#define TEST_V_SETUP( testnum, sew, lmul ) \
+test_ ## testnum: \
+    li  TESTNUM, testnum; \
+    vsetvli x6, x0, e ## sew, lmul, ta, ma;
+
+// compares the doublewords that store puts in vresult with those at correct
+#define TEST_V_CHECK( store, dwords, correct ) \
+    la  x1, vresult; \
+    store v24, (x1); \
+    la  x2, correct; \
+    li  x13, dwords; \
+1:  ld  x4, 0(x1); \
+    ld  x5, 0(x2); \
+    bne x4, x5, fail; \
+    addi x1, x1, 8; \
+    addi x2, x2, 8; \
+    addi x13, x13, -1; \
+    bnez x13, 1b;
+
+#define TEST_V_FFLAGS( flags ) \
+    frflags x4; \
+    li  x5, flags; \
+    bne x4, x5, fail;
+
+#-----------------------------------------------------------------------
+# Tests of elementwise instructions
+#-----------------------------------------------------------------------
+
+#define TEST_V_OP( testnum, inst, sew, flags, correct, val1 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl1re8.v v8, (x1); \
+    fsflags x0; \
+    inst v24, v8; \
+    TEST_V_FFLAGS( flags ) \
+    TEST_V_CHECK( vs1r.v, 2, correct )
+
+#define TEST_VV_OP( testnum, inst, sew, flags, correct, val1, val2 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl1re8.v v8, (x1); \
+    la  x1, val2; \
+    vl1re8.v v16, (x1); \
+    fsflags x0; \
+    inst v24, v8, v16; \
+    TEST_V_FFLAGS( flags ) \
+    TEST_V_CHECK( vs1r.v, 2, correct )
+
+// val2 is the scalar, loaded with fload
+#define TEST_VF_OP( testnum, inst, sew, fload, flags, correct, val1, val2 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl1re8.v v8, (x1); \
+    la  x1, val2; \
+    fload fa0, 0(x1); \
+    fsflags x0; \
+    inst v24, v8, fa0; \
+    TEST_V_FFLAGS( flags ) \
+    TEST_V_CHECK( vs1r.v, 2, correct )
+
+// the multiply adds, with val3 what the destination holds going in
+#define TEST_VVV_OP( testnum, inst, sew, flags, correct, val1, val2, val3 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl1re8.v v8, (x1); \
+    la  x1, val2; \
+    vl1re8.v v16, (x1); \
+    la  x1, val3; \
+    vl1re8.v v24, (x1); \
+    fsflags x0; \
+    inst v24, v8, v16; \
+    TEST_V_FFLAGS( flags ) \
+    TEST_V_CHECK( vs1r.v, 2, correct )
+
+// the compares, whose result is a mask of vl bits
+#define TEST_VV_CMP( testnum, inst, sew, flags, correct, val1, val2 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl1re8.v v8, (x1); \
+    la  x1, val2; \
+    vl1re8.v v16, (x1); \
+    fsflags x0; \
+    inst v24, v8, v16; \
+    TEST_V_FFLAGS( flags ) \
+    vmv.x.s x14, v24; \
+    li  x5, 1; \
+    sll x5, x5, x6; \
+    addi x5, x5, -1; \
+    and x14, x14, x5; \
+    li  x7, correct; \
+    bne x14, x7, fail;
+
+#define TEST_VF_CMP( testnum, inst, sew, fload, flags, correct, val1, val2 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl1re8.v v8, (x1); \
+    la  x1, val2; \
+    fload fa0, 0(x1); \
+    fsflags x0; \
+    inst v24, v8, fa0; \
+    TEST_V_FFLAGS( flags ) \
+    vmv.x.s x14, v24; \
+    li  x5, 1; \
+    sll x5, x5, x6; \
+    addi x5, x5, -1; \
+    and x14, x14, x5; \
+    li  x7, correct; \
+    bne x14, x7, fail;
+
+#-----------------------------------------------------------------------
+# Tests of widening and narrowing instructions
+#-----------------------------------------------------------------------
+
+// sew is the width of the narrow operands, the result is a group of two registers
+#define TEST_V_WIDEN_OP( testnum, inst, sew, flags, correct, val1 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl1re8.v v8, (x1); \
+    fsflags x0; \
+    inst v24, v8; \
+    TEST_V_FFLAGS( flags ) \
+    TEST_V_CHECK( vs2r.v, 4, correct )
+
+#define TEST_VV_WIDEN_OP( testnum, inst, sew, flags, correct, val1, val2 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl1re8.v v8, (x1); \
+    la  x1, val2; \
+    vl1re8.v v16, (x1); \
+    fsflags x0; \
+    inst v24, v8, v16; \
+    TEST_V_FFLAGS( flags ) \
+    TEST_V_CHECK( vs2r.v, 4, correct )
+
+// the .wv forms, where val1 is already wide
+#define TEST_WV_WIDEN_OP( testnum, inst, sew, flags, correct, val1, val2 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl2re8.v v8, (x1); \
+    la  x1, val2; \
+    vl1re8.v v16, (x1); \
+    fsflags x0; \
+    inst v24, v8, v16; \
+    TEST_V_FFLAGS( flags ) \
+    TEST_V_CHECK( vs2r.v, 4, correct )
+
+#define TEST_VVV_WIDEN_OP( testnum, inst, sew, flags, correct, val1, val2, val3 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl1re8.v v8, (x1); \
+    la  x1, val2; \
+    vl1re8.v v16, (x1); \
+    la  x1, val3; \
+    vl2re8.v v24, (x1); \
+    fsflags x0; \
+    inst v24, v8, v16; \
+    TEST_V_FFLAGS( flags ) \
+    TEST_V_CHECK( vs2r.v, 4, correct )
+
+// sew is the width of the narrow result, val1 is a group of two registers
+#define TEST_W_NARROW_OP( testnum, inst, sew, flags, correct, val1 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl2re8.v v8, (x1); \
+    fsflags x0; \
+    inst v24, v8; \
+    TEST_V_FFLAGS( flags ) \
+    TEST_V_CHECK( vs1r.v, 2, correct )
+
+#-----------------------------------------------------------------------
+# Tests of reductions
+#-----------------------------------------------------------------------
+
+// val2 gives the starting element, the result is element 0 as vmv.x.s sign extends it
+#define TEST_VRED_OP( testnum, inst, sew, flags, correct, val1, val2 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl1re8.v v8, (x1); \
+    la  x1, val2; \
+    vl1re8.v v16, (x1); \
+    fsflags x0; \
+    inst v24, v8, v16; \
+    TEST_V_FFLAGS( flags ) \
+    vmv.x.s x14, v24; \
+    li  x7, correct; \
+    bne x14, x7, fail;
+
+// the starting element and the result are twice sew wide
+#define TEST_VRED_WIDEN_OP( testnum, inst, sew, wsew, flags, correct, val1, val2 ) \
+    TEST_V_SETUP( testnum, sew, m1 ) \
+    la  x1, val1; \
+    vl1re8.v v8, (x1); \
+    la  x1, val2; \
+    vl1re8.v v16, (x1); \
+    fsflags x0; \
+    inst v24, v8, v16; \
+    TEST_V_FFLAGS( flags ) \
+    vsetvli x6, x0, e ## wsew, m1, ta, ma; \
+    vmv.x.s x14, v24; \
+    li  x7, correct; \
+    bne x14, x7, fail;
+
+#endif